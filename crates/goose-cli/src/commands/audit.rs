@@ -0,0 +1,33 @@
+use anyhow::{Context, Result};
+use clap::Subcommand;
+use goose::agents::audit::{verify, AuditLog};
+use std::path::PathBuf;
+
+#[derive(Subcommand)]
+pub enum AuditCommand {
+    /// Check that no entry of the audit log was changed, removed or reordered
+    Verify {
+        /// The log to check (default: GOOSE_AUDIT_LOG)
+        file: Option<PathBuf>,
+    },
+}
+
+impl AuditCommand {
+    pub fn run(self) -> Result<()> {
+        match self {
+            AuditCommand::Verify { file } => {
+                let path = file
+                    .or_else(AuditLog::configured_path)
+                    .context("No audit log, set GOOSE_AUDIT_LOG or name the file")?;
+                let (entries, head) = verify(&path)?;
+                println!(
+                    "{} is intact, {} entries, the last hash is {}",
+                    path.display(),
+                    entries,
+                    head
+                );
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,372 @@
+use anyhow::{Context, Result};
+use clap::Subcommand;
+use console::style;
+use goose::message::{Message, MessageContent};
+use goose::providers::base::{ProviderUsage, Usage};
+use goose::providers::pricing;
+use mcp_core::role::Role;
+use std::fs::File;
+use std::path::Path;
+
+use crate::commands::session::find_session;
+use crate::session::{
+    deserialize_messages, ensure_session_dir, fork_session, forked_sessions, load_state,
+    SessionState,
+};
+
+#[derive(Subcommand)]
+pub enum BranchCommand {
+    /// Start a new session from the first messages of a session
+    Fork {
+        /// The session to fork
+        session: String,
+
+        /// How many messages the fork keeps, as numbered by 'goose branch log'
+        #[arg(long, value_name = "N")]
+        at: usize,
+
+        /// Name of the new session (default: <session>-fork-<n>)
+        #[arg(short, long, value_name = "NAME")]
+        name: Option<String>,
+    },
+
+    /// Show the messages of a session, numbered
+    Log {
+        /// The session to show (default: the most recent one)
+        session: Option<String>,
+    },
+
+    /// List the forks of a session, or of every session
+    List {
+        /// The session whose forks to list
+        session: Option<String>,
+    },
+
+    /// Compare two sessions from where they diverge
+    Compare { first: String, second: String },
+}
+
+impl BranchCommand {
+    pub fn run(self) -> Result<()> {
+        let session_dir = ensure_session_dir()?;
+        match self {
+            BranchCommand::Fork { session, at, name } => {
+                let name = name.unwrap_or_else(|| fork_name(&session_dir, &session));
+                fork_session(&session_dir, &session, at, &name)?;
+                println!(
+                    "Forked '{}' after message {} as '{}', resume it with 'goose session --resume --name {}'",
+                    session, at, name, name
+                );
+            }
+            BranchCommand::Log { session } => {
+                let messages = load_messages(&session_dir, session.as_deref())?;
+                for (index, message) in messages.iter().enumerate() {
+                    println!("{:>4}  {}", index + 1, summary(message));
+                }
+            }
+            BranchCommand::List { session } => {
+                let forks = forked_sessions(&session_dir)?;
+                let forks: Vec<_> = forks
+                    .into_iter()
+                    .filter(|(_, fork_point)| {
+                        session.as_ref().is_none_or(|s| *s == fork_point.session)
+                    })
+                    .collect();
+                if forks.is_empty() {
+                    println!("No forks, start one with 'goose branch fork'");
+                }
+                for (name, fork_point) in forks {
+                    println!(
+                        "{}: forked from '{}' after message {}",
+                        name, fork_point.session, fork_point.messages
+                    );
+                }
+            }
+            BranchCommand::Compare { first, second } => {
+                let sides = [first, second]
+                    .into_iter()
+                    .map(|name| {
+                        let messages = load_messages(&session_dir, Some(&name))?;
+                        let file = session_dir.join(format!("{}.jsonl", name));
+                        let state = load_state(&file)?.unwrap_or_default();
+                        Ok((name, messages, state))
+                    })
+                    .collect::<Result<Vec<_>>>()?;
+                let shared = shared_prefix(&sides[0].1, &sides[1].1);
+                println!("Both sessions share the first {} messages", shared);
+                for (index, (name, messages, state)) in sides.iter().enumerate() {
+                    let usage = branch_usage(name, state, &sides[1 - index].2);
+                    print_branch(name, &messages[shared..], shared, usage);
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+fn load_messages(session_dir: &Path, name: Option<&str>) -> Result<Vec<Message>> {
+    let session_file = find_session(session_dir, name).with_context(|| match name {
+        Some(name) => format!("No session named '{}'", name),
+        None => "No session found".to_string(),
+    })?;
+    deserialize_messages(File::open(session_file)?)
+}
+
+/// The first name of the form `<session>-fork-<n>` that is not taken
+fn fork_name(session_dir: &Path, session: &str) -> String {
+    (1..)
+        .map(|n| format!("{}-fork-{}", session, n))
+        .find(|name| !session_dir.join(format!("{}.jsonl", name)).exists())
+        .expect("there is a free name")
+}
+
+/// How many messages `a` and `b` start with in common
+fn shared_prefix(a: &[Message], b: &[Message]) -> usize {
+    a.iter().zip(b).take_while(|(a, b)| a == b).count()
+}
+
+/// A message on one line
+fn summary(message: &Message) -> String {
+    let author = match message.role {
+        Role::User if message.is_tool_response() => "tools",
+        Role::User => "user",
+        Role::Assistant => "goose",
+    };
+    let mut parts = Vec::new();
+    let text = message.as_concat_text();
+    if let Some(line) = text.lines().find(|line| !line.trim().is_empty()) {
+        let mut line: String = line.trim().chars().take(80).collect();
+        if line.len() < text.trim().len() {
+            line.push('…');
+        }
+        parts.push(line);
+    }
+    for content in &message.content {
+        match content {
+            MessageContent::ToolRequest(request) => match &request.tool_call {
+                Ok(call) => parts.push(format!("calls {}", call.name)),
+                Err(_) => parts.push("makes an invalid tool call".to_string()),
+            },
+            MessageContent::ToolResponse(response) if response.tool_result.is_err() => {
+                parts.push("a tool failed".to_string())
+            }
+            _ => {}
+        }
+    }
+    if parts.is_empty() && message.is_tool_response() {
+        parts.push("results".to_string());
+    }
+    format!("{}: {}", author, parts.join(", "))
+}
+
+/// The usage of a session, as far as it can be told apart from before a fork
+enum BranchUsage {
+    SinceFork(Vec<ProviderUsage>),
+    Total(Vec<ProviderUsage>),
+}
+
+/// The usage of session `name` to compare with the session in `other`
+///
+/// A fork starts with no usage, so all of its usage came after the fork. When
+/// `other` is a fork of `name`, the usage `name` had when the fork was made
+/// is left out. Otherwise only the whole usage is known.
+fn branch_usage(name: &str, state: &SessionState, other: &SessionState) -> BranchUsage {
+    if state.forked_from.is_some() {
+        return BranchUsage::SinceFork(state.usage.clone());
+    }
+    match &other.forked_from {
+        Some(fork_point) if fork_point.session == name => {
+            BranchUsage::SinceFork(usage_since(&fork_point.usage, &state.usage))
+        }
+        _ => BranchUsage::Total(state.usage.clone()),
+    }
+}
+
+/// The usage in `after` that is not in `before`, by model
+fn usage_since(before: &[ProviderUsage], after: &[ProviderUsage]) -> Vec<ProviderUsage> {
+    let minus = |a: Option<i32>, b: Option<i32>| a.map(|a| a - b.unwrap_or(0));
+    after
+        .iter()
+        .map(
+            |usage| match before.iter().find(|u| u.model == usage.model) {
+                Some(earlier) => ProviderUsage::new(
+                    usage.model.clone(),
+                    Usage::new(
+                        minus(usage.usage.input_tokens, earlier.usage.input_tokens),
+                        minus(usage.usage.output_tokens, earlier.usage.output_tokens),
+                        minus(usage.usage.total_tokens, earlier.usage.total_tokens),
+                    ),
+                ),
+                None => usage.clone(),
+            },
+        )
+        .collect()
+}
+
+/// Tokens and cost of `usage`, the cost leaves out and names the models without a price
+fn usage_summary(usage: &[ProviderUsage]) -> String {
+    let tokens: i32 = usage.iter().filter_map(|u| u.usage.total_tokens).sum();
+    let mut cost = 0.0;
+    let mut unpriced = Vec::new();
+    for usage in usage {
+        match pricing::request_cost(&usage.model, &usage.usage) {
+            Some(request_cost) => cost += request_cost,
+            None => unpriced.push(usage.model.as_str()),
+        }
+    }
+    let mut summary = format!("{} tokens, ${:.4}", tokens, cost);
+    if !unpriced.is_empty() {
+        summary.push_str(&format!(
+            " without {}, which have no price",
+            unpriced.join(", ")
+        ));
+    }
+    summary
+}
+
+fn print_branch(name: &str, messages: &[Message], offset: usize, usage: BranchUsage) {
+    let tool_calls = messages
+        .iter()
+        .flat_map(|m| &m.content)
+        .filter(|c| c.as_tool_request().is_some())
+        .count();
+    let usage = match usage {
+        BranchUsage::SinceFork(usage) => format!("{} since the fork", usage_summary(&usage)),
+        BranchUsage::Total(usage) => format!("{} in total", usage_summary(&usage)),
+    };
+    println!(
+        "\n{} {} more messages, {} tool calls, {}",
+        style(name).cyan().bold(),
+        messages.len(),
+        tool_calls,
+        usage
+    );
+    for (index, message) in messages.iter().enumerate() {
+        println!("{:>4}  {}", offset + index + 1, summary(message));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::session::{persist_messages, persist_state, ForkPoint};
+    use mcp_core::tool::ToolCall;
+    use serde_json::json;
+    use tempfile::TempDir;
+
+    fn conversation() -> Vec<Message> {
+        vec![
+            Message::user().with_text("List the files"),
+            Message::assistant().with_tool_request(
+                "1",
+                Ok(ToolCall::new("developer__shell", json!({"command": "ls"}))),
+            ),
+            Message::user().with_tool_response("1", Ok(vec![])),
+            Message::assistant().with_text("There are none"),
+        ]
+    }
+
+    #[test]
+    fn test_fork_session() -> Result<()> {
+        let dir = TempDir::new()?;
+        let source = dir.path().join("work.jsonl");
+        persist_messages(&source, &conversation())?;
+        persist_state(
+            &source,
+            &SessionState {
+                provider: "openai".to_string(),
+                model: "gpt-4o".to_string(),
+                usage: vec![ProviderUsage::new(
+                    "gpt-4o".to_string(),
+                    Usage::new(Some(10), Some(5), Some(15)),
+                )],
+                ..Default::default()
+            },
+        )?;
+
+        let fork = fork_session(dir.path(), "work", 3, "retry")?;
+        let messages = deserialize_messages(File::open(&fork)?)?;
+        assert_eq!(messages, conversation()[..3]);
+        let state = load_state(&fork)?.unwrap();
+        assert_eq!(state.model, "gpt-4o");
+        assert!(state.usage.is_empty());
+        let fork_point = state.forked_from.as_ref().unwrap();
+        assert_eq!(fork_point.session, "work");
+        assert_eq!(fork_point.usage[0].usage.total_tokens, Some(15));
+
+        // The tool call at message 2 has no results before it
+        assert!(fork_session(dir.path(), "work", 2, "broken").is_err());
+        assert!(fork_session(dir.path(), "work", 9, "long").is_err());
+        assert!(fork_session(dir.path(), "work", 1, "retry").is_err());
+        // Names stay inside the session directory
+        for name in ["../escape", "nested/fork", "nested\\fork", ".."] {
+            assert!(fork_session(dir.path(), "work", 1, name).is_err());
+        }
+        assert!(fork_session(dir.path(), "../work", 1, "outside").is_err());
+        assert!(!dir.path().join("../escape.jsonl").exists());
+
+        let forks = forked_sessions(dir.path())?;
+        assert_eq!(forks.len(), 1);
+        assert_eq!(forks[0].0, "retry");
+        assert_eq!(forks[0].1.messages, 3);
+        assert_eq!(fork_name(dir.path(), "work"), "work-fork-1");
+        Ok(())
+    }
+
+    #[test]
+    fn test_compare() {
+        let mut other = conversation()[..3].to_vec();
+        other.push(Message::assistant().with_text("The directory is empty"));
+        assert_eq!(shared_prefix(&conversation(), &other), 3);
+        assert_eq!(summary(&conversation()[1]), "goose: calls developer__shell");
+        assert_eq!(summary(&conversation()[2]), "tools: results");
+        assert_eq!(summary(&other[3]), "goose: The directory is empty");
+    }
+
+    #[test]
+    fn test_branch_usage() {
+        let usage = |total| {
+            vec![ProviderUsage::new(
+                "gpt-4o".to_string(),
+                Usage::new(Some(total), Some(0), Some(total)),
+            )]
+        };
+        let source = SessionState {
+            usage: usage(150),
+            ..Default::default()
+        };
+        let fork = SessionState {
+            usage: usage(30),
+            forked_from: Some(ForkPoint {
+                session: "work".to_string(),
+                messages: 3,
+                usage: usage(100),
+            }),
+            ..Default::default()
+        };
+
+        let total = |usage: &[ProviderUsage]| usage[0].usage.total_tokens;
+        // The source spent 50 tokens since the fork, the fork all of its 30
+        assert!(matches!(
+            branch_usage("work", &source, &fork),
+            BranchUsage::SinceFork(usage) if total(&usage) == Some(50)
+        ));
+        assert!(matches!(
+            branch_usage("retry", &fork, &source),
+            BranchUsage::SinceFork(usage) if total(&usage) == Some(30)
+        ));
+        assert!(matches!(
+            branch_usage("other", &source, &fork),
+            BranchUsage::Total(usage) if total(&usage) == Some(150)
+        ));
+
+        let unpriced = vec![ProviderUsage::new(
+            "branch-test-unpriced".to_string(),
+            Usage::new(Some(10), Some(0), Some(10)),
+        )];
+        assert_eq!(
+            usage_summary(&[usage(1_000_000), unpriced].concat()),
+            "1000010 tokens, $2.5000 without branch-test-unpriced, which have no price"
+        );
+    }
+}
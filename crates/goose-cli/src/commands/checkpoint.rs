@@ -0,0 +1,62 @@
+use anyhow::Result;
+use chrono::Local;
+use clap::Subcommand;
+use goose_mcp::CheckpointStore;
+
+#[derive(Subcommand)]
+pub enum CheckpointCommand {
+    /// List the checkpoints of files under the current directory
+    List {
+        /// List the checkpoints of every file
+        #[arg(long)]
+        all: bool,
+    },
+
+    /// Put a file back as it was when a checkpoint was taken
+    Revert {
+        /// The checkpoint, as shown by 'goose checkpoint list'
+        id: u64,
+    },
+}
+
+impl CheckpointCommand {
+    pub fn run(self) -> Result<()> {
+        let store = CheckpointStore::from_env();
+        match self {
+            CheckpointCommand::List { all } => {
+                let dir = std::env::current_dir()?;
+                let checkpoints = store.list((!all).then_some(dir.as_path()))?;
+                if checkpoints.is_empty() {
+                    println!("No checkpoints, they are taken when goose writes or edits a file");
+                }
+                for checkpoint in checkpoints {
+                    println!(
+                        "{:>5}  {}  {:<12} {}{}",
+                        checkpoint.id,
+                        checkpoint
+                            .created
+                            .with_timezone(&Local)
+                            .format("%Y-%m-%d %H:%M:%S"),
+                        checkpoint.action,
+                        checkpoint.path.display(),
+                        if checkpoint.existed {
+                            ""
+                        } else {
+                            " (new file)"
+                        }
+                    );
+                }
+            }
+            CheckpointCommand::Revert { id } => {
+                let current = store.revert(id)?;
+                println!(
+                    "Reverted {} to checkpoint {}, revert checkpoint {} to undo this",
+                    current.path.display(),
+                    id,
+                    current.id
+                );
+            }
+        }
+        Ok(())
+    }
+}
@@ -1,9 +1,13 @@
 use cliclack::spinner;
 use console::style;
-use goose::agents::{extension::Envs, ExtensionConfig};
+use goose::agents::{
+    extension::{Envs, HttpAuthConfig, Sandbox},
+    ExtensionConfig,
+};
 use goose::config::{Config, ConfigError, ExtensionEntry, ExtensionManager};
 use goose::message::Message;
-use goose::providers::{create, providers};
+use goose::providers::{create, providers, supported_models};
+use mcp_client::oauth::OAuthConfig;
 use mcp_core::Tool;
 use serde_json::{json, Value};
 use std::collections::HashMap;
@@ -255,9 +259,11 @@ pub async fn configure_provider_dialog() -> Result<bool, Box<dyn Error>> {
 
     // Select model, defaulting to the provider's recommended model UNLESS there is an env override
     let default_model = std::env::var("GOOSE_MODEL").unwrap_or(provider_meta.default_model.clone());
-    let model: String = cliclack::input("Enter a model from that provider:")
-        .default_input(&default_model)
-        .interact()?;
+    let spin = spinner();
+    spin.start("Fetching available models...");
+    let models = supported_models(provider_name).await;
+    spin.clear();
+    let model: String = select_model(&models, &default_model)?;
 
     // Update config with new values
     config.set("GOOSE_PROVIDER", Value::String(provider_name.to_string()))?;
@@ -306,6 +312,37 @@ pub async fn configure_provider_dialog() -> Result<bool, Box<dyn Error>> {
     }
 }
 
+/// Pick one of the provider's models, or type in a name the listing does not have
+fn select_model(models: &[String], default_model: &str) -> Result<String, Box<dyn Error>> {
+    if models.is_empty() {
+        return Ok(cliclack::input("Enter a model from that provider:")
+            .default_input(default_model)
+            .interact()?);
+    }
+
+    const OTHER: &str = "";
+    let mut items: Vec<(&str, &str, &str)> = models
+        .iter()
+        .map(|model| (model.as_str(), model.as_str(), ""))
+        .collect();
+    items.push((OTHER, "Other", "Enter a model name"));
+    let initial = if models.iter().any(|m| m == default_model) {
+        default_model
+    } else {
+        models[0].as_str()
+    };
+    let choice = cliclack::select("Which model should we use?")
+        .initial_value(initial)
+        .items(&items)
+        .interact()?;
+    if choice != OTHER {
+        return Ok(choice.to_string());
+    }
+    Ok(cliclack::input("Enter a model from that provider:")
+        .default_input(default_model)
+        .interact()?)
+}
+
 /// Configure extensions that can be used with goose
 /// Dialog for toggling which extensions are enabled/disabled
 pub fn toggle_extensions_dialog() -> Result<(), Box<dyn Error>> {
@@ -371,6 +408,11 @@ pub fn configure_extensions_dialog() -> Result<(), Box<dyn Error>> {
             "Remote Extension",
             "Connect to a remote extension via SSE",
         )
+        .item(
+            "streamable_http",
+            "Hosted Extension",
+            "Connect to an MCP server over Streamable HTTP",
+        )
         .interact()?;
 
     match extension_type {
@@ -387,6 +429,11 @@ pub fn configure_extensions_dialog() -> Result<(), Box<dyn Error>> {
                     "Computer Controller",
                     "controls for webscraping, file caching, and automations",
                 )
+                .item(
+                    "computeruse",
+                    "Computer Use",
+                    "See the screen and use the mouse and keyboard - needs xdotool or cliclick",
+                )
                 .item(
                     "google_drive",
                     "Google Drive",
@@ -463,6 +510,38 @@ pub fn configure_extensions_dialog() -> Result<(), Box<dyn Error>> {
                 }
             }
 
+            let mut sandbox = Sandbox::default();
+            if cliclack::confirm("Would you like to limit what the extension can see?")
+                .initial_value(false)
+                .interact()?
+            {
+                let working_dir: String =
+                    cliclack::input("Directory to run in (leave empty for the current one):")
+                        .placeholder("~/projects/demo")
+                        .required(false)
+                        .interact()?;
+                if !working_dir.is_empty() {
+                    sandbox.working_dir = Some(working_dir.into());
+                }
+                let allowlist: String = cliclack::input(
+                    "Environment variables to pass on, besides PATH, HOME and the like:",
+                )
+                .placeholder("GITHUB_TOKEN, NODE_OPTIONS")
+                .required(false)
+                .interact()?;
+                sandbox.env_allowlist = Some(
+                    allowlist
+                        .split(',')
+                        .map(str::trim)
+                        .filter(|name| !name.is_empty())
+                        .map(String::from)
+                        .collect(),
+                );
+                sandbox.no_network = !cliclack::confirm("Allow network access?")
+                    .initial_value(true)
+                    .interact()?;
+            }
+
             ExtensionManager::set(ExtensionEntry {
                 enabled: true,
                 config: ExtensionConfig::Stdio {
@@ -470,6 +549,7 @@ pub fn configure_extensions_dialog() -> Result<(), Box<dyn Error>> {
                     cmd,
                     args,
                     envs: Envs::new(envs),
+                    sandbox,
                 },
             })?;
 
@@ -536,6 +616,76 @@ pub fn configure_extensions_dialog() -> Result<(), Box<dyn Error>> {
 
             cliclack::outro(format!("Added {} extension", style(name).green()))?;
         }
+        "streamable_http" => {
+            let extensions = ExtensionManager::get_all_names()?;
+            let name: String = cliclack::input("What would you like to call this extension?")
+                .placeholder("my-hosted-extension")
+                .validate(move |input: &String| {
+                    if input.is_empty() {
+                        Err("Please enter a name")
+                    } else if extensions.contains(input) {
+                        Err("An extension with this name already exists")
+                    } else {
+                        Ok(())
+                    }
+                })
+                .interact()?;
+
+            let uri: String = cliclack::input("What is the MCP endpoint URI?")
+                .placeholder("https://example.com/mcp")
+                .validate(|input: &String| {
+                    if input.is_empty() {
+                        Err("Please enter a URI")
+                    } else if !input.starts_with("http") {
+                        Err("URI should start with http:// or https://")
+                    } else {
+                        Ok(())
+                    }
+                })
+                .interact()?;
+
+            let auth = match cliclack::select("How does the server authorize goose?")
+                .item("none", "No authorization", "")
+                .item("bearer", "Bearer token", "an API key or access token")
+                .item("oauth", "OAuth", "sign in with a code in your browser")
+                .interact()?
+            {
+                "bearer" => {
+                    let token: String = cliclack::password("Token:").mask('▪').interact()?;
+                    let token_key = format!("{}_token", name);
+                    Config::global().set_secret(&token_key, serde_json::Value::String(token))?;
+                    HttpAuthConfig::Bearer { token_key }
+                }
+                "oauth" => HttpAuthConfig::OAuth(OAuthConfig {
+                    client_id: cliclack::input("OAuth client ID:").interact()?,
+                    device_authorization_url: cliclack::input("Device authorization URL:")
+                        .placeholder("https://example.com/oauth/device/code")
+                        .interact()?,
+                    token_url: cliclack::input("Token URL:")
+                        .placeholder("https://example.com/oauth/token")
+                        .interact()?,
+                    scopes: {
+                        let scopes: String = cliclack::input("Scopes, separated by spaces:")
+                            .required(false)
+                            .interact()?;
+                        scopes.split_whitespace().map(String::from).collect()
+                    },
+                }),
+                _ => HttpAuthConfig::None,
+            };
+
+            ExtensionManager::set(ExtensionEntry {
+                enabled: true,
+                config: ExtensionConfig::StreamableHttp {
+                    name: name.clone(),
+                    uri,
+                    headers: HashMap::new(),
+                    auth,
+                },
+            })?;
+
+            cliclack::outro(format!("Added {} extension", style(name).green()))?;
+        }
         _ => unreachable!(),
     };
 
@@ -0,0 +1,88 @@
+use anyhow::{Context, Result};
+use console::style;
+use goose::config::Config;
+use goose::providers::health::{AuthStatus, HealthCheck};
+use goose::providers::{health_check, status_live};
+
+/// Check that the configured provider answers, or with `all` every provider that has its keys set
+///
+/// Sends a completion capped at one token to each provider checked. With
+/// `json` the results are printed as JSON, for scripts and monitoring.
+/// Fails when a checked provider is unhealthy.
+pub async fn run(all: bool, json: bool) -> Result<()> {
+    let checks: Vec<(String, HealthCheck)> = if all {
+        status_live()
+            .await
+            .into_iter()
+            .filter_map(|status| Some((status.name, status.health?)))
+            .collect()
+    } else {
+        let config = Config::global();
+        let provider: String = config
+            .get("GOOSE_PROVIDER")
+            .context("No provider configured. Run 'goose configure' first")?;
+        let model: String = config
+            .get("GOOSE_MODEL")
+            .context("No model configured. Run 'goose configure' first")?;
+        let health = health_check(&provider, &model).await;
+        vec![(provider, health)]
+    };
+
+    if json {
+        let report: serde_json::Map<String, serde_json::Value> = checks
+            .iter()
+            .map(|(name, health)| Ok((name.clone(), serde_json::to_value(health)?)))
+            .collect::<Result<_>>()?;
+        println!("{}", serde_json::to_string_pretty(&report)?);
+    } else if checks.is_empty() {
+        println!("No provider has its keys set, run 'goose configure' to set one up");
+    } else {
+        for (name, health) in &checks {
+            print_check(name, health);
+        }
+    }
+
+    let unhealthy = checks.iter().filter(|(_, h)| !h.is_healthy()).count();
+    if unhealthy > 0 {
+        anyhow::bail!(
+            "{} of {} providers failed the check",
+            unhealthy,
+            checks.len()
+        );
+    }
+    Ok(())
+}
+
+fn print_check(name: &str, health: &HealthCheck) {
+    let mark = if health.is_healthy() {
+        style("ok").green()
+    } else {
+        style("failed").red()
+    };
+    let latency = health
+        .latency_ms
+        .map(|ms| format!(" in {}ms", ms))
+        .unwrap_or_default();
+    println!(
+        "{} {}/{}{}",
+        mark,
+        style(name).bold(),
+        health.model,
+        latency
+    );
+    let auth = match health.auth {
+        AuthStatus::Valid => "valid",
+        AuthStatus::Invalid => "rejected, check the API key",
+        AuthStatus::Unknown => "not checked",
+    };
+    println!("    credentials: {}", auth);
+    let model = match health.model_available {
+        Some(true) => "available",
+        Some(false) => "not found, pick another with 'goose configure'",
+        None => "not checked",
+    };
+    println!("    model: {}", model);
+    if let Some(error) = &health.error {
+        println!("    error: {}", error);
+    }
+}
@@ -0,0 +1,48 @@
+use anyhow::{Context, Result};
+use goose::export::{export, ExportFormat};
+use std::fs::File;
+use std::path::PathBuf;
+
+use crate::commands::session::find_session;
+use crate::session::{deserialize_messages, ensure_session_dir};
+
+/// Write the transcript of a session, the most recent one unless `name` is given
+///
+/// Without a format it follows the extension of `output`, or is Markdown.
+/// Without `output` the transcript goes to stdout.
+pub fn run(
+    name: Option<String>,
+    format: Option<ExportFormat>,
+    output: Option<PathBuf>,
+) -> Result<()> {
+    let session_dir = ensure_session_dir()?;
+    let session_file =
+        find_session(&session_dir, name.as_deref()).with_context(|| match &name {
+            Some(name) => format!("No session named '{}'", name),
+            None => "No session found".to_string(),
+        })?;
+    let messages = deserialize_messages(File::open(&session_file)?)?;
+
+    let format = format
+        .or_else(|| {
+            output
+                .as_ref()
+                .and_then(|path| path.extension())
+                .and_then(|ext| ext.to_str()?.parse().ok())
+        })
+        .unwrap_or_default();
+    let title = session_file
+        .file_stem()
+        .and_then(|stem| stem.to_str())
+        .unwrap_or("session");
+    let transcript = export(&messages, format, title);
+
+    match output {
+        Some(path) => std::fs::write(&path, transcript)
+            .with_context(|| format!("Failed to write {}", path.display())),
+        None => {
+            print!("{}", transcript);
+            Ok(())
+        }
+    }
+}
@@ -0,0 +1,52 @@
+use anyhow::Result;
+use chrono::Local;
+use clap::Subcommand;
+use goose::memory::MemoryStore;
+
+#[derive(Subcommand)]
+pub enum MemoryCommand {
+    /// List the facts remembered for the current directory
+    List {
+        /// List the facts of every project
+        #[arg(long)]
+        all: bool,
+    },
+
+    /// Forget a fact
+    Forget {
+        /// The fact, as shown by 'goose memory list'
+        id: u64,
+    },
+}
+
+impl MemoryCommand {
+    pub fn run(self) -> Result<()> {
+        let store = MemoryStore::from_config();
+        match self {
+            MemoryCommand::List { all } => {
+                let dir = std::env::current_dir()?;
+                let memories = store.list((!all).then_some(dir.as_path()))?;
+                if memories.is_empty() {
+                    println!("Nothing remembered, facts are kept at the end of sessions when GOOSE_MEMORY is true");
+                }
+                for memory in memories {
+                    println!(
+                        "{:>5}  {}  {}  {}",
+                        memory.id,
+                        memory.created.with_timezone(&Local).format("%Y-%m-%d"),
+                        memory.project.display(),
+                        memory.text
+                    );
+                }
+            }
+            MemoryCommand::Forget { id } => {
+                if store.remove(id)? {
+                    println!("Forgot fact {}", id);
+                } else {
+                    anyhow::bail!("No fact {}, see 'goose memory list --all'", id);
+                }
+            }
+        }
+        Ok(())
+    }
+}
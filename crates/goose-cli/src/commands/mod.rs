@@ -1,5 +1,13 @@
 pub mod agent_version;
+pub mod audit;
+pub mod branch;
+pub mod checkpoint;
 pub mod configure;
+pub mod doctor;
+pub mod export;
 pub mod mcp;
+pub mod memory;
+pub mod recipe;
+pub mod schedule;
 pub mod session;
 pub mod version;
@@ -0,0 +1,40 @@
+use goose::recipe::Recipe;
+use std::collections::HashMap;
+use std::process;
+
+/// Parse a `KEY=VALUE` recipe parameter
+pub fn parse_param(param: &str) -> Result<(String, String), String> {
+    param
+        .split_once('=')
+        .map(|(key, value)| (key.trim().to_string(), value.to_string()))
+        .filter(|(key, _)| !key.is_empty())
+        .ok_or_else(|| format!("'{}' is not of the form KEY=VALUE", param))
+}
+
+/// Load the recipe at `path` with the given parameters, exiting if it can't be used
+pub fn load_recipe(path: &str, params: Vec<(String, String)>) -> Recipe {
+    let values: HashMap<String, String> = params.into_iter().collect();
+    Recipe::load(path, &values).unwrap_or_else(|e| {
+        eprintln!("{}", e);
+        process::exit(1);
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_param() {
+        assert_eq!(
+            parse_param("query=is:open label:bug"),
+            Ok(("query".to_string(), "is:open label:bug".to_string()))
+        );
+        assert_eq!(
+            parse_param("empty="),
+            Ok(("empty".to_string(), String::new()))
+        );
+        assert!(parse_param("no-value").is_err());
+        assert!(parse_param("=value").is_err());
+    }
+}
@@ -0,0 +1,362 @@
+use anyhow::{anyhow, Result};
+use chrono::{DateTime, Local, Timelike};
+use clap::Subcommand;
+use goose::config::{ScheduleEntry, ScheduleManager};
+use goose::recipe::Recipe;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::fs::{self, File, OpenOptions};
+use std::io::Write;
+use std::path::PathBuf;
+use std::process::Stdio;
+use tokio::task::JoinHandle;
+
+use crate::commands::recipe::parse_param;
+use crate::session::ensure_session_dir;
+
+#[derive(Subcommand)]
+pub enum ScheduleCommand {
+    /// Add or replace a schedule
+    Add {
+        /// Name of the schedule, runs are named after it
+        ///
+        /// Letters, digits, '-' and '_' only, since it is part of file names.
+        name: String,
+
+        /// When to run, e.g. '0 2 * * *' for every night at 2am
+        #[arg(long, value_name = "CRON")]
+        cron: String,
+
+        /// Path to an instruction file for the run
+        #[arg(
+            short,
+            long,
+            value_name = "FILE",
+            conflicts_with = "input_text",
+            required_unless_present_any = ["input_text", "recipe"]
+        )]
+        instructions: Option<String>,
+
+        /// Instructions for the run
+        #[arg(short = 't', long = "text", value_name = "TEXT")]
+        input_text: Option<String>,
+
+        /// Start the run from a recipe, its prompt is used without instructions
+        #[arg(long, value_name = "FILE")]
+        recipe: Option<String>,
+
+        /// Values for the parameters of the recipe
+        #[arg(
+            long = "params",
+            value_name = "KEY=VALUE",
+            value_parser = parse_param,
+            requires = "recipe"
+        )]
+        params: Vec<(String, String)>,
+
+        /// Add a builtin extension to the run
+        #[arg(long = "with-builtin", value_name = "NAME")]
+        builtin: Option<String>,
+
+        /// Add a stdio extension to the run
+        #[arg(long = "with-extension", value_name = "COMMAND")]
+        extension: Option<String>,
+    },
+
+    /// List the schedules with their next and last runs
+    List {},
+
+    /// Remove a schedule
+    Remove { name: String },
+
+    /// Run a schedule now, exiting with the status of the run
+    Run { name: String },
+
+    /// Keep running and start each schedule when it is due
+    Daemon {},
+}
+
+/// The outcome of a scheduled run, appended to the run history
+#[derive(Debug, Serialize, Deserialize)]
+pub struct RunRecord {
+    pub schedule: String,
+    pub session: String,
+    pub started: DateTime<Local>,
+    pub finished: DateTime<Local>,
+    /// The exit code of the run, -1 when it was killed by a signal
+    pub exit_code: i32,
+    /// The conversation of the run
+    pub transcript: PathBuf,
+    /// What the run printed
+    pub log: PathBuf,
+}
+
+impl RunRecord {
+    pub fn succeeded(&self) -> bool {
+        self.exit_code == 0
+    }
+}
+
+fn ensure_schedule_dir() -> Result<PathBuf> {
+    let home_dir = dirs::home_dir().ok_or(anyhow!("Could not determine home directory"))?;
+    let schedule_dir = home_dir.join(".config").join("goose").join("schedules");
+    fs::create_dir_all(&schedule_dir)?;
+    Ok(schedule_dir)
+}
+
+fn history_file() -> Result<PathBuf> {
+    Ok(ensure_schedule_dir()?.join("runs.jsonl"))
+}
+
+fn append_history(record: &RunRecord) -> Result<()> {
+    let mut file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(history_file()?)?;
+    writeln!(file, "{}", serde_json::to_string(record)?)?;
+    Ok(())
+}
+
+/// The most recent run of each schedule
+fn last_runs() -> HashMap<String, RunRecord> {
+    let Ok(history) = history_file().and_then(|file| Ok(fs::read_to_string(file)?)) else {
+        return HashMap::new();
+    };
+    history
+        .lines()
+        .filter_map(|line| serde_json::from_str::<RunRecord>(line).ok())
+        .map(|record| (record.schedule.clone(), record))
+        .collect()
+}
+
+/// Run the schedule as a headless `goose run` and record the outcome
+///
+/// The run is a separate process, so one that fails or hangs does not take
+/// the daemon down with it. Its output goes to a log next to the history.
+async fn run_schedule(entry: &ScheduleEntry) -> Result<RunRecord> {
+    // The config can be edited by hand, so the name is checked again before it is used in paths
+    entry.validate()?;
+    let started = Local::now();
+    let session = format!("{}-{}", entry.name, started.format("%Y%m%d-%H%M%S"));
+    let log_dir = ensure_schedule_dir()?.join(&entry.name);
+    fs::create_dir_all(&log_dir)?;
+    let log = log_dir.join(format!("{}.log", session));
+    let output = File::create(&log)?;
+
+    let mut command = tokio::process::Command::new(std::env::current_exe()?);
+    command.args(["run", "--name", &session]);
+    if let Some(instructions) = &entry.instructions {
+        command.args(["--text", instructions]);
+    }
+    if let Some(recipe) = &entry.recipe {
+        command.args(["--recipe", recipe]);
+        for (key, value) in &entry.params {
+            command.args(["--params", &format!("{}={}", key, value)]);
+        }
+    }
+    if let Some(builtin) = &entry.builtin {
+        command.args(["--with-builtin", builtin]);
+    }
+    if let Some(extension) = &entry.extension {
+        command.args(["--with-extension", extension]);
+    }
+    let status = command
+        .stdin(Stdio::null())
+        .stdout(output.try_clone()?)
+        .stderr(output)
+        .status()
+        .await?;
+
+    let record = RunRecord {
+        schedule: entry.name.clone(),
+        transcript: ensure_session_dir()?.join(format!("{}.jsonl", session)),
+        session,
+        started,
+        finished: Local::now(),
+        exit_code: status.code().unwrap_or(-1),
+        log,
+    };
+    append_history(&record)?;
+    Ok(record)
+}
+
+/// Check that the recipe at `path` loads with `params`, returning its absolute path
+///
+/// The daemon can run in any directory, so the path is stored absolute. A
+/// recipe without a prompt needs instructions to start the run with.
+fn check_recipe(
+    path: &str,
+    params: &BTreeMap<String, String>,
+    has_instructions: bool,
+) -> Result<String> {
+    let values: HashMap<String, String> = params.clone().into_iter().collect();
+    let recipe = Recipe::load(path, &values)?;
+    if recipe.prompt.is_none() && !has_instructions {
+        return Err(anyhow!(
+            "The recipe {} has no prompt, give the run --instructions or --text",
+            path
+        ));
+    }
+    let path = fs::canonicalize(path)?;
+    Ok(path.to_string_lossy().into_owned())
+}
+
+fn report(record: &RunRecord) {
+    let outcome = if record.succeeded() {
+        "succeeded".to_string()
+    } else {
+        format!("failed with exit code {}", record.exit_code)
+    };
+    println!(
+        "{} {} {} in {}s, transcript {}, log {}",
+        record.finished.format("%Y-%m-%d %H:%M"),
+        record.schedule,
+        outcome,
+        (record.finished - record.started).num_seconds(),
+        record.transcript.display(),
+        record.log.display()
+    );
+}
+
+/// Start the schedules that are due, checking once a minute
+///
+/// Schedules are read from the config on every check, so changes apply
+/// without a restart. A schedule whose previous run is still going is not
+/// started again.
+async fn daemon() -> Result<()> {
+    println!("Running schedules, press Ctrl+C to stop");
+    let mut running: HashMap<String, JoinHandle<()>> = HashMap::new();
+    let mut checked = None;
+    loop {
+        let now = Local::now().naive_local();
+        // Waking up early must not start the same minute twice
+        let minute = (now.date(), now.hour(), now.minute());
+        let due = if checked == Some(minute) {
+            Vec::new()
+        } else {
+            ScheduleManager::get_all()
+        };
+        checked = Some(minute);
+        running.retain(|_, run| !run.is_finished());
+        for entry in due {
+            if !entry.enabled || running.contains_key(&entry.name) {
+                continue;
+            }
+            let cron = match entry.cron() {
+                Ok(cron) => cron,
+                Err(e) => {
+                    eprintln!("Skipping schedule '{}': {}", entry.name, e);
+                    continue;
+                }
+            };
+            if !cron.matches(now) {
+                continue;
+            }
+            let name = entry.name.clone();
+            let run = tokio::spawn(async move {
+                match run_schedule(&entry).await {
+                    Ok(record) => report(&record),
+                    Err(e) => eprintln!("Could not run schedule '{}': {}", entry.name, e),
+                }
+            });
+            running.insert(name, run);
+        }
+
+        let wait = 60 - u64::from(Local::now().second()).min(59);
+        tokio::select! {
+            _ = tokio::time::sleep(std::time::Duration::from_secs(wait)) => {}
+            _ = tokio::signal::ctrl_c() => return Ok(()),
+        }
+    }
+}
+
+fn list() {
+    let schedules = ScheduleManager::get_all();
+    if schedules.is_empty() {
+        println!("No schedules, add one with 'goose schedule add'");
+        return;
+    }
+    let last_runs = last_runs();
+    let now = Local::now().naive_local();
+    for entry in schedules {
+        let next = match entry.cron() {
+            Ok(_) if !entry.enabled => "disabled".to_string(),
+            Ok(cron) => cron.next_after(now).map_or("never".to_string(), |next| {
+                format!("next {}", next.format("%Y-%m-%d %H:%M"))
+            }),
+            Err(e) => e.to_string(),
+        };
+        let last = match last_runs.get(&entry.name) {
+            Some(record) if record.succeeded() => {
+                format!("last succeeded {}", record.started.format("%Y-%m-%d %H:%M"))
+            }
+            Some(record) => format!(
+                "last failed {} (exit code {})",
+                record.started.format("%Y-%m-%d %H:%M"),
+                record.exit_code
+            ),
+            None => "not run yet".to_string(),
+        };
+        println!("{} '{}': {}, {}", entry.name, entry.cron, next, last);
+    }
+}
+
+impl ScheduleCommand {
+    /// Run the command, returning the exit code for the process
+    pub async fn run(self) -> Result<i32> {
+        match self {
+            ScheduleCommand::Add {
+                name,
+                cron,
+                instructions,
+                input_text,
+                recipe,
+                params,
+                builtin,
+                extension,
+            } => {
+                let instructions = match (instructions, input_text) {
+                    (Some(file), _) => Some(
+                        fs::read_to_string(&file)
+                            .map_err(|e| anyhow!("Failed to read {}: {}", file, e))?,
+                    ),
+                    (None, text) => text,
+                };
+                let params: BTreeMap<String, String> = params.into_iter().collect();
+                let recipe = match recipe {
+                    Some(path) => Some(check_recipe(&path, &params, instructions.is_some())?),
+                    None => None,
+                };
+                ScheduleManager::set(ScheduleEntry {
+                    name: name.clone(),
+                    cron,
+                    instructions,
+                    recipe,
+                    params,
+                    builtin,
+                    extension,
+                    enabled: true,
+                })?;
+                println!("Added schedule '{}'", name);
+            }
+            ScheduleCommand::List {} => list(),
+            ScheduleCommand::Remove { name } => {
+                ScheduleManager::remove(&name)?;
+                println!("Removed schedule '{}'", name);
+            }
+            ScheduleCommand::Run { name } => {
+                let entry = ScheduleManager::get(&name)
+                    .ok_or_else(|| anyhow!("No schedule named '{}'", name))?;
+                let record = run_schedule(&entry).await?;
+                report(&record);
+                return Ok(if record.succeeded() {
+                    0
+                } else {
+                    record.exit_code.max(1)
+                });
+            }
+            ScheduleCommand::Daemon {} => daemon().await?,
+        }
+        Ok(0)
+    }
+}
@@ -1,14 +1,19 @@
 use rand::{distributions::Alphanumeric, Rng};
 use std::process;
+use std::sync::Arc;
 
 use crate::prompt::rustyline::RustylinePrompt;
-use crate::session::{ensure_session_dir, get_most_recent_session, Session};
+use crate::session::{
+    ensure_session_dir, get_most_recent_session, load_state, restore_envs, terminal_device_code,
+    Session, SessionState,
+};
 use console::style;
-use goose::agents::extension::{Envs, ExtensionError};
-use goose::agents::AgentFactory;
+use goose::agents::extension::{Envs, ExtensionError, Sandbox};
+use goose::agents::{AgentFactory, SecretRedactor};
 use goose::config::{Config, ExtensionConfig, ExtensionManager};
 use goose::providers::create;
-use std::path::Path;
+use goose::recipe::{Recipe, RecipeSettings};
+use std::path::{Path, PathBuf};
 
 use mcp_client::transport::Error as McpClientError;
 
@@ -17,19 +22,35 @@ pub async fn build_session(
     resume: bool,
     extension: Option<String>,
     builtin: Option<String>,
+    recipe: Option<&Recipe>,
 ) -> Session<'static> {
     // Load config and get provider/model
     let config = Config::global();
-
-    let provider_name: String = config
-        .get("GOOSE_PROVIDER")
-        .expect("No provider configured. Run 'goose configure' first");
     let session_dir = ensure_session_dir().expect("Failed to create session directory");
 
-    let model: String = config
-        .get("GOOSE_MODEL")
-        .expect("No model configured. Run 'goose configure' first");
+    // A resumed session carries on with the provider, model and extensions it had
+    let resumed = if resume {
+        find_session(&session_dir, name.as_deref())
+    } else {
+        None
+    };
+    let state = resumed.as_deref().and_then(|session_file| {
+        load_state(session_file).unwrap_or_else(|e| {
+            eprintln!(
+                "Failed to load session state, using the current config.\n{}",
+                e
+            );
+            None
+        })
+    });
+
+    let settings = recipe.map(|recipe| &recipe.settings);
+    let (provider_name, model) = provider_and_model(state.as_ref(), settings, config);
     let model_config = goose::model::ModelConfig::new(model.clone());
+    let model_config = match settings {
+        Some(settings) => settings.apply(model_config),
+        None => model_config,
+    };
     let provider = create(&provider_name, model_config).expect("Failed to create provider");
 
     // Create the agent
@@ -40,10 +61,19 @@ pub async fn build_session(
     }
     .expect("Failed to create agent");
 
+    // Keep credentials that tools print out of the requests to the model
+    if config.get::<bool>("GOOSE_REDACT_SECRETS").unwrap_or(false) {
+        agent.add_moderator(Arc::new(SecretRedactor::new())).await;
+    }
+
+    agent.set_device_code_prompt(terminal_device_code()).await;
+
     // Setup extensions for the agent
+    let mut extensions: Vec<ExtensionConfig> = Vec::new();
     for extension in ExtensionManager::get_all().expect("should load extensions") {
         if extension.enabled {
             let config = extension.config.clone();
+            extensions.push(config.clone());
             agent
                 .add_extension(config.clone())
                 .await
@@ -96,8 +126,10 @@ pub async fn build_session(
             cmd,
             args: parts.iter().map(|s| s.to_string()).collect(),
             envs: Envs::new(envs),
+            sandbox: Sandbox::default(),
         };
 
+        extensions.push(config.clone());
         agent.add_extension(config).await.unwrap_or_else(|e| {
             eprintln!("Failed to start extension: {}", e);
             process::exit(1);
@@ -107,53 +139,155 @@ pub async fn build_session(
     // Add builtin extension if provided
     if let Some(name) = builtin {
         let config = ExtensionConfig::Builtin { name };
+        extensions.push(config.clone());
         agent.add_extension(config).await.unwrap_or_else(|e| {
             eprintln!("Failed to start builtin extension: {}", e);
             process::exit(1);
         });
     }
 
-    // If resuming, try to find the session
-    if resume {
-        if let Some(ref session_name) = name {
-            // Try to resume specific session
-            let session_file = session_dir.join(format!("{}.jsonl", session_name));
-            if session_file.exists() {
-                let prompt = Box::new(RustylinePrompt::new());
-                return Session::new(agent, prompt, session_file);
-            } else {
-                eprintln!("Session '{}' not found, starting new session", session_name);
-            }
-        } else {
-            // Try to resume most recent session
-            if let Ok(session_file) = get_most_recent_session() {
-                let prompt = Box::new(RustylinePrompt::new());
-                return Session::new(agent, prompt, session_file);
-            } else {
-                eprintln!("No previous sessions found, starting new session");
+    // Add the extensions and instructions of the recipe
+    if let Some(recipe) = recipe {
+        for config in &recipe.extensions {
+            if extensions.iter().any(|e| e.name() == config.name()) {
+                continue;
             }
+            extensions.push(config.clone());
+            agent
+                .add_extension(config.clone())
+                .await
+                .unwrap_or_else(|e| {
+                    eprintln!(
+                        "Failed to start extension {} of the recipe: {}",
+                        config.name(),
+                        e
+                    );
+                    process::exit(1);
+                });
+        }
+        if let Some(instructions) = &recipe.instructions {
+            agent.extend_system_prompt(instructions.clone()).await;
         }
     }
 
-    // Generate session name if not provided
-    let name = name.unwrap_or_else(|| {
-        rand::thread_rng()
-            .sample_iter(&Alphanumeric)
-            .take(8)
-            .map(char::from)
-            .collect()
-    });
+    // Restart the extensions the session had that the config doesn't enable
+    let (earlier_usage, forked_from) = match state {
+        Some(state) => {
+            let lookup = |name: &str| {
+                std::env::var(name)
+                    .ok()
+                    .or_else(|| config.get_secret(name).ok())
+            };
+            for config in extensions_to_restart(state.extensions, &extensions, lookup) {
+                match agent.add_extension(config.clone()).await {
+                    Ok(()) => extensions.push(config),
+                    Err(e) => eprintln!(
+                        "Failed to restart extension {} of the session: {}",
+                        config.name(),
+                        e
+                    ),
+                }
+            }
+            (state.usage, state.forked_from)
+        }
+        None => (Vec::new(), None),
+    };
 
-    let session_file = session_dir.join(format!("{}.jsonl", name));
-    if session_file.exists() {
-        eprintln!("Session '{}' already exists", name);
-        process::exit(1);
-    }
+    let session_file = match resumed {
+        Some(session_file) => session_file,
+        None => {
+            if resume {
+                match &name {
+                    Some(name) => {
+                        eprintln!("Session '{}' not found, starting new session", name)
+                    }
+                    None => eprintln!("No previous sessions found, starting new session"),
+                }
+            }
+
+            // Generate session name if not provided
+            let name = name.unwrap_or_else(|| {
+                rand::thread_rng()
+                    .sample_iter(&Alphanumeric)
+                    .take(8)
+                    .map(char::from)
+                    .collect()
+            });
+
+            let session_file = session_dir.join(format!("{}.jsonl", name));
+            if session_file.exists() {
+                eprintln!("Session '{}' already exists", name);
+                process::exit(1);
+            }
+            session_file
+        }
+    };
 
     let prompt = Box::new(RustylinePrompt::new());
 
     display_session_info(resume, &provider_name, &model, &session_file);
-    Session::new(agent, prompt, session_file)
+    let state = SessionState {
+        provider: provider_name,
+        model,
+        extensions,
+        usage: earlier_usage,
+        forked_from,
+    };
+    Session::new(agent, prompt, session_file, state)
+}
+
+/// The provider and model of a session: those it had when resumed, else the recipe's, else the configured ones
+fn provider_and_model(
+    state: Option<&SessionState>,
+    settings: Option<&RecipeSettings>,
+    config: &Config,
+) -> (String, String) {
+    let provider = match (state, settings.and_then(|s| s.provider.clone())) {
+        (Some(state), _) => state.provider.clone(),
+        (None, Some(provider)) => provider,
+        (None, None) => config
+            .get("GOOSE_PROVIDER")
+            .expect("No provider configured. Run 'goose configure' first"),
+    };
+    let model = match (state, settings.and_then(|s| s.model.clone())) {
+        (Some(state), _) => state.model.clone(),
+        (None, Some(model)) => model,
+        (None, None) => config
+            .get("GOOSE_MODEL")
+            .expect("No model configured. Run 'goose configure' first"),
+    };
+    (provider, model)
+}
+
+/// The extensions of a resumed session that are not running yet, with their envs from `lookup`
+fn extensions_to_restart(
+    saved: Vec<ExtensionConfig>,
+    running: &[ExtensionConfig],
+    lookup: impl Fn(&str) -> Option<String>,
+) -> Vec<ExtensionConfig> {
+    saved
+        .into_iter()
+        .filter(|config| !running.iter().any(|e| e.name() == config.name()))
+        .map(|mut config| {
+            let missing = restore_envs(&mut config, &lookup);
+            if !missing.is_empty() {
+                eprintln!(
+                    "Restarting extension {} without {}, set them in the environment to pass them",
+                    config.name(),
+                    missing.join(", ")
+                );
+            }
+            config
+        })
+        .collect()
+}
+
+/// The session file to resume, the named session or else the most recent one
+pub fn find_session(session_dir: &Path, name: Option<&str>) -> Option<PathBuf> {
+    match name {
+        Some(name) => Some(session_dir.join(format!("{}.jsonl", name))).filter(|f| f.exists()),
+        None => get_most_recent_session().ok(),
+    }
 }
 
 fn display_session_info(resume: bool, provider: &str, model: &str, session_file: &Path) {
@@ -176,3 +310,84 @@ fn display_session_info(resume: bool, provider: &str, model: &str, session_file:
         style(session_file.display()).dim().cyan(),
     );
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    fn resumed_state() -> SessionState {
+        SessionState {
+            provider: "anthropic".to_string(),
+            model: "claude-3-5-sonnet-latest".to_string(),
+            extensions: vec![
+                ExtensionConfig::Builtin {
+                    name: "developer".to_string(),
+                },
+                ExtensionConfig::Stdio {
+                    name: "tracker".to_string(),
+                    cmd: "tracker-mcp".to_string(),
+                    args: vec![],
+                    envs: Envs::new(HashMap::from([(
+                        "TRACKER_TOKEN".to_string(),
+                        String::new(),
+                    )])),
+                    sandbox: Sandbox::default(),
+                },
+            ],
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_provider_and_model() {
+        let file = tempfile::NamedTempFile::new().unwrap();
+        let config = Config::new(file.path(), "goose-session-test").unwrap();
+        config
+            .set("GOOSE_PROVIDER", serde_json::json!("openai"))
+            .unwrap();
+        config
+            .set("GOOSE_MODEL", serde_json::json!("gpt-4o"))
+            .unwrap();
+        let settings = RecipeSettings {
+            model: Some("gpt-4o-mini".to_string()),
+            ..Default::default()
+        };
+
+        // A resumed session keeps what it had, over the recipe and the config
+        let state = resumed_state();
+        assert_eq!(
+            provider_and_model(Some(&state), Some(&settings), &config),
+            (
+                "anthropic".to_string(),
+                "claude-3-5-sonnet-latest".to_string()
+            )
+        );
+        assert_eq!(
+            provider_and_model(None, Some(&settings), &config),
+            ("openai".to_string(), "gpt-4o-mini".to_string())
+        );
+        assert_eq!(
+            provider_and_model(None, None, &config),
+            ("openai".to_string(), "gpt-4o".to_string())
+        );
+    }
+
+    #[test]
+    fn test_extensions_to_restart() {
+        let running = vec![ExtensionConfig::Builtin {
+            name: "developer".to_string(),
+        }];
+        let restarted = extensions_to_restart(resumed_state().extensions, &running, |name| {
+            (name == "TRACKER_TOKEN").then(|| "s3cret".to_string())
+        });
+
+        // Only the one that is not running yet, with its env filled in
+        assert_eq!(restarted.len(), 1);
+        let ExtensionConfig::Stdio { name, envs, .. } = &restarted[0] else {
+            panic!("expected the stdio extension");
+        };
+        assert_eq!(name, "tracker");
+        assert_eq!(envs.get_env()["TRACKER_TOKEN"], "s3cret");
+    }
+}
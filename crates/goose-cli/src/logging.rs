@@ -89,17 +89,21 @@ pub fn setup_logging(name: Option<&str>) -> Result<()> {
         .with(file_layer.with_filter(env_filter)) // Gets all logs
         .with(console_layer.with_filter(LevelFilter::WARN)); // Controls log levels
 
-    // Initialize with Langfuse if available
-    if let Some(langfuse) = langfuse_layer::create_langfuse_observer() {
-        subscriber
-            .with(langfuse.with_filter(LevelFilter::DEBUG))
-            .try_init()
-            .context("Failed to set global subscriber")?;
-    } else {
-        subscriber
-            .try_init()
-            .context("Failed to set global subscriber")?;
-    }
+    // Add Langfuse if available
+    let subscriber = subscriber.with(
+        langfuse_layer::create_langfuse_observer()
+            .map(|langfuse| langfuse.with_filter(LevelFilter::DEBUG)),
+    );
+
+    // Export spans over OTLP if an endpoint is configured
+    #[cfg(feature = "otel")]
+    let subscriber = subscriber.with(
+        goose::tracing::otlp::create_otlp_layer().map(|otlp| otlp.with_filter(LevelFilter::INFO)),
+    );
+
+    subscriber
+        .try_init()
+        .context("Failed to set global subscriber")?;
 
     Ok(())
 }
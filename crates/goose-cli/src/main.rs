@@ -8,14 +8,22 @@ mod prompt;
 mod session;
 
 use commands::agent_version::AgentCommand;
+use commands::audit::AuditCommand;
+use commands::branch::BranchCommand;
+use commands::checkpoint::CheckpointCommand;
 use commands::configure::handle_configure;
 use commands::mcp::run_server;
+use commands::memory::MemoryCommand;
+use commands::recipe::{load_recipe, parse_param};
+use commands::schedule::ScheduleCommand;
 use commands::session::build_session;
 use commands::version::print_version;
 use console::style;
 use goose::config::Config;
+use goose::export::ExportFormat;
 use logging::setup_logging;
 use std::io::{self, Read};
+use std::path::PathBuf;
 
 #[cfg(test)]
 mod test_helpers;
@@ -79,6 +87,25 @@ enum Command {
             long_help = "Add a builtin extension that is bundled with goose by specifying its name"
         )]
         builtin: Option<String>,
+
+        /// Start from a recipe file
+        #[arg(
+            long,
+            value_name = "FILE",
+            help = "Start from a recipe file (e.g., 'triage.yaml')",
+            long_help = "Start from a YAML or JSON recipe with instructions, extensions, model settings and a first prompt. Give values for its parameters with --params."
+        )]
+        recipe: Option<String>,
+
+        /// Values for the parameters of the recipe
+        #[arg(
+            long = "params",
+            value_name = "KEY=VALUE",
+            value_parser = parse_param,
+            requires = "recipe",
+            help = "Value for a recipe parameter, can be repeated (e.g., 'repo=block/goose')"
+        )]
+        params: Vec<(String, String)>,
     },
 
     /// Execute commands from an instruction file
@@ -142,10 +169,129 @@ enum Command {
             long_help = "Add a builtin extension that is compiled into goose by specifying its name"
         )]
         builtin: Option<String>,
+
+        /// Start from a recipe file
+        #[arg(
+            long,
+            value_name = "FILE",
+            help = "Start from a recipe file (e.g., 'triage.yaml')",
+            long_help = "Start from a YAML or JSON recipe with instructions, extensions, model settings and a first prompt. Give values for its parameters with --params."
+        )]
+        recipe: Option<String>,
+
+        /// Values for the parameters of the recipe
+        #[arg(
+            long = "params",
+            value_name = "KEY=VALUE",
+            value_parser = parse_param,
+            requires = "recipe",
+            help = "Value for a recipe parameter, can be repeated (e.g., 'repo=block/goose')"
+        )]
+        params: Vec<(String, String)>,
+    },
+
+    /// Export a session as a transcript
+    #[command(
+        about = "Export a session as a Markdown or HTML transcript",
+        long_about = "Write a readable transcript of a session, with its tool calls, their results and images. Exports the most recent session unless --name is given."
+    )]
+    Export {
+        /// Name of the session to export
+        #[arg(
+            short,
+            long,
+            value_name = "NAME",
+            help = "Name of the session to export (default: the most recent one)"
+        )]
+        name: Option<String>,
+
+        /// Format of the transcript
+        #[arg(
+            short,
+            long,
+            value_name = "FORMAT",
+            help = "Format of the transcript, markdown or html",
+            long_help = "Format of the transcript, markdown or html. Defaults to the extension of --output, or markdown."
+        )]
+        format: Option<ExportFormat>,
+
+        /// File to write the transcript to
+        #[arg(
+            short,
+            long,
+            value_name = "FILE",
+            help = "File to write the transcript to (default: stdout)"
+        )]
+        output: Option<PathBuf>,
     },
 
     /// List available agent versions
     Agents(AgentCommand),
+
+    /// Check the audit log of tool calls
+    #[command(
+        about = "Check the audit log of tool calls",
+        long_about = "With GOOSE_AUDIT_LOG set to a file, every tool call is appended to it with its arguments, a digest of its output, how it was approved and the model message that made it. A relative path is kept in each workspace. Each entry carries the hash of the one before it, so verifying the log shows whether any entry was changed, removed or reordered. Keep the last hash elsewhere to also notice entries cut off the end."
+    )]
+    Audit {
+        #[command(subcommand)]
+        command: AuditCommand,
+    },
+
+    /// Fork sessions and compare the branches
+    #[command(
+        about = "Fork sessions and compare the branches",
+        long_about = "Start a new session from the first messages of an existing one, to try a different instruction without losing the original. The fork keeps the provider, model and extensions of the session it came from."
+    )]
+    Branch {
+        #[command(subcommand)]
+        command: BranchCommand,
+    },
+
+    /// List and revert the checkpoints of files changed by goose
+    #[command(
+        about = "List and revert the checkpoints of files changed by goose",
+        long_about = "Every file goose writes or edits is copied to ~/.config/goose/checkpoints first, or GOOSE_CHECKPOINT_DIR when set. Reverting a checkpoint puts the file back as it was, or removes it if goose created it."
+    )]
+    Checkpoint {
+        #[command(subcommand)]
+        command: CheckpointCommand,
+    },
+
+    /// List and forget the facts goose remembers about projects
+    #[command(
+        about = "List and forget the facts goose remembers about projects",
+        long_about = "With GOOSE_MEMORY set to true, goose distills facts about the project at the end of every session, such as its conventions and how to test it, and adds the ones relevant to each request to the system prompt of later sessions in the same directory. The facts are embedded with the provider's embeddings, or those of GOOSE_MEMORY_EMBEDDING_PROVIDER. Only the truncate agent, the default, remembers."
+    )]
+    Memory {
+        #[command(subcommand)]
+        command: MemoryCommand,
+    },
+
+    /// Check that the configured provider answers
+    #[command(
+        about = "Check that the configured provider answers",
+        long_about = "Send a completion capped at one token to the configured provider and report how long it took, whether the credentials were accepted and whether the model exists. Exits with an error when the check fails."
+    )]
+    Doctor {
+        /// Check every provider that has its keys set
+        #[arg(long, help = "Check every provider that has its keys set")]
+        all: bool,
+
+        /// Print the results as JSON
+        #[arg(long, help = "Print the results as JSON")]
+        json: bool,
+    },
+
+    /// Run instructions headless on a timer
+    #[command(
+        about = "Run instructions headless on a timer",
+        long_about = "Manage schedules that start headless runs from cron expressions. Each run is recorded as a session, its outcome is appended to ~/.config/goose/schedules/runs.jsonl. Tools set to always ask are declined, as nobody is there to approve them."
+    )]
+    Schedule {
+        #[command(subcommand)]
+        command: ScheduleCommand,
+    },
 }
 
 #[derive(clap::ValueEnum, Clone, Debug)]
@@ -177,11 +323,19 @@ async fn main() -> Result<()> {
             resume,
             extension,
             builtin,
+            recipe,
+            params,
         }) => {
-            let mut session = build_session(name, resume, extension, builtin).await;
+            let recipe = recipe.map(|path| load_recipe(&path, params));
+            let mut session =
+                build_session(name, resume, extension, builtin, recipe.as_ref()).await;
             setup_logging(session.session_file().file_stem().and_then(|s| s.to_str()))?;
 
-            let _ = session.start().await;
+            // A resumed session has had its first prompt already
+            let initial_message = recipe.and_then(|recipe| recipe.prompt).filter(|_| !resume);
+            let _ = session.start(initial_message).await;
+            #[cfg(feature = "otel")]
+            goose::tracing::otlp::shutdown();
             return Ok(());
         }
         Some(Command::Run {
@@ -191,10 +345,17 @@ async fn main() -> Result<()> {
             resume,
             extension,
             builtin,
+            recipe,
+            params,
         }) => {
+            let recipe = recipe.map(|path| load_recipe(&path, params));
+            let recipe_prompt = recipe.as_ref().and_then(|recipe| recipe.prompt.clone());
+
             // Validate that we have some input source
-            if instructions.is_none() && input_text.is_none() {
-                eprintln!("Error: Must provide either --instructions or --text");
+            if instructions.is_none() && input_text.is_none() && recipe_prompt.is_none() {
+                eprintln!(
+                    "Error: Must provide either --instructions, --text or a recipe with a prompt"
+                );
                 std::process::exit(1);
             }
 
@@ -203,6 +364,8 @@ async fn main() -> Result<()> {
                 std::fs::read_to_string(file_path).expect("Failed to read the instruction file")
             } else if let Some(input_text) = input_text {
                 input_text
+            } else if let Some(recipe_prompt) = recipe_prompt {
+                recipe_prompt
             } else {
                 let mut stdin = String::new();
                 io::stdin()
@@ -210,14 +373,53 @@ async fn main() -> Result<()> {
                     .expect("Failed to read from stdin");
                 stdin
             };
-            let mut session = build_session(name, resume, extension, builtin).await;
-            let _ = session.headless_start(contents.clone()).await;
+            let mut session =
+                build_session(name, resume, extension, builtin, recipe.as_ref()).await;
+            let result = session.headless_start(contents.clone()).await;
+            #[cfg(feature = "otel")]
+            goose::tracing::otlp::shutdown();
+            if let Err(e) = result {
+                eprintln!("Error: {}", e);
+                std::process::exit(1);
+            }
+            return Ok(());
+        }
+        Some(Command::Export {
+            name,
+            format,
+            output,
+        }) => {
+            commands::export::run(name, format, output)?;
             return Ok(());
         }
         Some(Command::Agents(cmd)) => {
             cmd.run()?;
             return Ok(());
         }
+        Some(Command::Audit { command }) => {
+            command.run()?;
+            return Ok(());
+        }
+        Some(Command::Branch { command }) => {
+            command.run()?;
+            return Ok(());
+        }
+        Some(Command::Checkpoint { command }) => {
+            command.run()?;
+            return Ok(());
+        }
+        Some(Command::Memory { command }) => {
+            command.run()?;
+            return Ok(());
+        }
+        Some(Command::Doctor { all, json }) => {
+            commands::doctor::run(all, json).await?;
+            return Ok(());
+        }
+        Some(Command::Schedule { command }) => {
+            let code = command.run().await?;
+            std::process::exit(code);
+        }
         None => {
             Cli::command().print_help()?;
             println!();
@@ -30,6 +30,7 @@ pub enum InputType {
     AskAgain, // Ask the user for input again. Control flow command.
     Message,  // User sent a message
     Exit,     // User wants to exit the session
+    Plan,     // User wants a plan for the request in `content` to review before it runs
 }
 
 pub enum Theme {
@@ -259,6 +259,15 @@ pub fn render(message: &Message, theme: &Theme, renderers: HashMap<String, Box<d
             MessageContent::Image(image) => {
                 println!("Image: [data: {}, type: {}]", image.data, image.mime_type);
             }
+            MessageContent::Audio(audio) => {
+                println!("Audio: [format: {}]", audio.format);
+            }
+            MessageContent::Refusal(refusal) => {
+                println!("Refused: {}", refusal.refusal);
+            }
+            MessageContent::Thinking(thinking) => {
+                println!("Thinking: {}", thinking.thinking);
+            }
         }
     }
 
@@ -127,12 +127,24 @@ impl Prompt for RustylinePrompt {
                 input_type: InputType::AskAgain,
                 content: None,
             });
+        } else if let Some(request) = message_text
+            .strip_prefix("/plan ")
+            .map(str::trim)
+            .filter(|request| !request.is_empty())
+        {
+            return Ok(Input {
+                input_type: InputType::Plan,
+                content: Some(request.to_string()),
+            });
         } else if message_text.eq_ignore_ascii_case("/?")
             || message_text.eq_ignore_ascii_case("/help")
         {
             println!("Commands:");
             println!("/exit - Exit the session");
             println!("/t - Toggle Light/Dark theme");
+            println!(
+                "/plan <request> - Review and edit the steps for a request before any of them runs"
+            );
             println!("/? | /help - Display this help message");
             println!("Ctrl+C - Interrupt goose (resets the interaction to before the interrupted user request)");
             println!("Ctrl+j - Adds a newline");
@@ -1,14 +1,20 @@
 use anyhow::Result;
 use core::panic;
 use futures::StreamExt;
+use serde::{Deserialize, Serialize};
 use std::fs::{self, File};
 use std::io::{self, BufRead, Write};
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
 
 use crate::log_usage::log_usage;
 use crate::prompt::{InputType, Prompt};
-use goose::agents::Agent;
+use goose::agents::extension::{DeviceCodePrompt, Envs};
+use goose::agents::{Agent, SafeguardDecision, SafeguardPause, ToolApproval, ToolPolicies};
+use goose::config::{ExtensionConfig, ToolCategory};
 use goose::message::{Message, MessageContent};
+use goose::plan::{Plan, StepStatus};
+use goose::providers::base::ProviderUsage;
 use mcp_core::handler::ToolError;
 use mcp_core::role::Role;
 
@@ -91,12 +97,236 @@ pub fn deserialize_messages(file: File) -> Result<Vec<Message>> {
     Ok(messages)
 }
 
+/// What a resumed session needs besides its messages, saved next to them after every turn
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct SessionState {
+    pub provider: String,
+    pub model: String,
+    /// The extensions the session ran with, including one-off ones from the command line
+    ///
+    /// Saved without the values of their envs and headers, which often hold
+    /// credentials, see `restore_envs`.
+    #[serde(default)]
+    pub extensions: Vec<ExtensionConfig>,
+    /// Provider usage of every run of the session, one entry per model and run
+    #[serde(default)]
+    pub usage: Vec<ProviderUsage>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub forked_from: Option<ForkPoint>,
+}
+
+/// Where a forked session branched off
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ForkPoint {
+    /// The name of the session it was forked from
+    pub session: String,
+    /// How many messages of that session it started with
+    pub messages: usize,
+    /// The usage of that session when the fork was made
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub usage: Vec<ProviderUsage>,
+}
+
+/// The state file of a session, `name.state.json` next to `name.jsonl`
+pub fn state_file(session_file: &Path) -> PathBuf {
+    session_file.with_extension("state.json")
+}
+
+/// Load the state of a session, sessions from before state was kept have none
+pub fn load_state(session_file: &Path) -> Result<Option<SessionState>> {
+    match fs::read_to_string(state_file(session_file)) {
+        Ok(contents) => Ok(Some(serde_json::from_str(&contents)?)),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Save the state of a session, replacing the file only once the new one is written
+///
+/// The envs and headers of the extensions are saved by name only, and the
+/// file is only readable by the user.
+pub fn persist_state(session_file: &Path, state: &SessionState) -> Result<()> {
+    let mut state = state.clone();
+    for extension in &mut state.extensions {
+        if let ExtensionConfig::StreamableHttp { headers, .. } = extension {
+            headers.values_mut().for_each(String::clear);
+        }
+        if let Some(envs) = envs_mut(extension) {
+            *envs = Envs::new(
+                envs.get_env()
+                    .into_keys()
+                    .map(|name| (name, String::new()))
+                    .collect(),
+            );
+        }
+    }
+
+    let path = state_file(session_file);
+    let partial = path.with_extension("json.tmp");
+    let mut file = File::create(&partial)?;
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        file.set_permissions(fs::Permissions::from_mode(0o600))?;
+    }
+    file.write_all(serde_json::to_string_pretty(&state)?.as_bytes())?;
+    file.sync_all()?;
+    fs::rename(partial, path)?;
+    Ok(())
+}
+
+/// The env or secret the value of `header` of `extension` is restored from, e.g. `TRACKER_X_API_KEY`
+pub fn header_key(extension: &str, header: &str) -> String {
+    format!("{}_{}", extension, header)
+        .to_uppercase()
+        .replace(|c: char| !c.is_ascii_alphanumeric(), "_")
+}
+
+/// Fill in the envs and headers of an extension from a saved state with `lookup`
+///
+/// Envs are looked up by their name and headers by their `header_key`.
+/// Returns the names `lookup` has no value for, the extension starts without them.
+pub fn restore_envs(
+    extension: &mut ExtensionConfig,
+    lookup: impl Fn(&str) -> Option<String>,
+) -> Vec<String> {
+    let mut missing = Vec::new();
+    if let ExtensionConfig::StreamableHttp { name, headers, .. } = extension {
+        headers.retain(|header, value| {
+            let key = header_key(name, header);
+            match lookup(&key) {
+                Some(found) => {
+                    *value = found;
+                    true
+                }
+                None => {
+                    missing.push(key);
+                    false
+                }
+            }
+        });
+    }
+    let Some(envs) = envs_mut(extension) else {
+        missing.sort();
+        return missing;
+    };
+    let mut restored = std::collections::HashMap::new();
+    for name in envs.get_env().into_keys() {
+        match lookup(&name) {
+            Some(value) => {
+                restored.insert(name, value);
+            }
+            None => missing.push(name),
+        }
+    }
+    *envs = Envs::new(restored);
+    missing.sort();
+    missing
+}
+
+fn envs_mut(extension: &mut ExtensionConfig) -> Option<&mut Envs> {
+    match extension {
+        ExtensionConfig::Sse { envs, .. } | ExtensionConfig::Stdio { envs, .. } => Some(envs),
+        ExtensionConfig::StreamableHttp { .. } | ExtensionConfig::Builtin { .. } => None,
+    }
+}
+
+/// Reject a session name that would point outside the session directory
+fn check_session_name(name: &str) -> Result<()> {
+    if name.is_empty() || name.contains(['/', '\\']) || name.contains("..") {
+        return Err(anyhow::anyhow!(
+            "'{}' can not name a session, use a name without path separators or '..'",
+            name
+        ));
+    }
+    Ok(())
+}
+
+/// Start session `name` with the first `keep` messages of session `source`
+///
+/// The fork resumes with the provider, model and extensions of the source,
+/// its usage starts from nothing so the branches can be compared, the usage of
+/// the source at that point is kept in the fork point. The source is left as
+/// it is.
+pub fn fork_session(session_dir: &Path, source: &str, keep: usize, name: &str) -> Result<PathBuf> {
+    check_session_name(source)?;
+    check_session_name(name)?;
+    let source_file = session_dir.join(format!("{}.jsonl", source));
+    if !source_file.exists() {
+        return Err(anyhow::anyhow!("No session named '{}'", source));
+    }
+    let fork_file = session_dir.join(format!("{}.jsonl", name));
+    if fork_file.exists() {
+        return Err(anyhow::anyhow!("Session '{}' already exists", name));
+    }
+
+    let mut messages = deserialize_messages(File::open(&source_file)?)?;
+    if keep > messages.len() {
+        return Err(anyhow::anyhow!(
+            "Session '{}' has only {} messages",
+            source,
+            messages.len()
+        ));
+    }
+    messages.truncate(keep);
+    // A tool request without its results can't be answered
+    if messages.last().is_some_and(|message| {
+        message
+            .content
+            .iter()
+            .any(|content| content.as_tool_request().is_some())
+    }) {
+        return Err(anyhow::anyhow!(
+            "Message {} calls tools, fork after their results",
+            keep
+        ));
+    }
+
+    let source_state = load_state(&source_file)?.unwrap_or_default();
+    let state = SessionState {
+        usage: Vec::new(),
+        forked_from: Some(ForkPoint {
+            session: source.to_string(),
+            messages: keep,
+            usage: source_state.usage.clone(),
+        }),
+        ..source_state
+    };
+    persist_messages(&fork_file, &messages)?;
+    persist_state(&fork_file, &state)?;
+    Ok(fork_file)
+}
+
+/// Every forked session in `session_dir`, by name, with where it branched off
+pub fn forked_sessions(session_dir: &Path) -> Result<Vec<(String, ForkPoint)>> {
+    let mut forks = Vec::new();
+    for entry in fs::read_dir(session_dir)? {
+        let path = entry?.path();
+        if path.extension().is_none_or(|ext| ext != "jsonl") {
+            continue;
+        }
+        let Some(name) = path.file_stem().and_then(|stem| stem.to_str()) else {
+            continue;
+        };
+        if let Ok(Some(SessionState {
+            forked_from: Some(fork_point),
+            ..
+        })) = load_state(&path)
+        {
+            forks.push((name.to_string(), fork_point));
+        }
+    }
+    forks.sort_by(|a, b| a.0.cmp(&b.0));
+    Ok(forks)
+}
+
 // Session management
 pub struct Session<'a> {
     agent: Box<dyn Agent>,
     prompt: Box<dyn Prompt + 'a>,
     session_file: PathBuf,
     messages: Vec<Message>,
+    state: SessionState,
 }
 
 #[allow(dead_code)]
@@ -105,6 +335,7 @@ impl<'a> Session<'a> {
         agent: Box<dyn Agent>,
         mut prompt: Box<dyn Prompt + 'a>,
         session_file: PathBuf,
+        state: SessionState,
     ) -> Self {
         let messages = match readable_session_file(&session_file) {
             Ok(file) => deserialize_messages(file).unwrap_or_else(|e| {
@@ -127,12 +358,29 @@ impl<'a> Session<'a> {
             prompt,
             session_file,
             messages,
+            state,
         }
     }
 
-    pub async fn start(&mut self) -> Result<(), Box<dyn std::error::Error>> {
+    /// Run the interactive session, answering `initial_message` first if there is one
+    pub async fn start(
+        &mut self,
+        initial_message: Option<String>,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        self.agent.set_tool_approval(terminal_approval()).await;
+        self.agent.set_safeguard_pause(terminal_pause()).await;
         self.prompt.goose_ready();
 
+        if let Some(message) = initial_message {
+            let message = Message::user().with_text(message);
+            self.prompt.render(Box::new(message.clone()));
+            self.messages.push(message);
+            persist_messages(&self.session_file, &self.messages)?;
+            self.prompt.show_busy();
+            self.agent_process_messages().await;
+            self.prompt.hide_busy();
+        }
+
         loop {
             let input = self.prompt.get_input().unwrap();
             match input.input_type {
@@ -144,6 +392,12 @@ impl<'a> Session<'a> {
                 }
                 InputType::Exit => break,
                 InputType::AskAgain => continue,
+                InputType::Plan => {
+                    if let Some(request) = &input.content {
+                        self.plan_and_run(request).await?;
+                    }
+                    continue;
+                }
             }
 
             self.prompt.show_busy();
@@ -162,18 +416,103 @@ impl<'a> Session<'a> {
             .push(Message::user().with_text(initial_message.as_str()));
         persist_messages(&self.session_file, &self.messages)?;
 
-        self.agent_process_messages().await;
+        let completed = self.agent_process_messages().await;
 
         self.close_session().await;
+        if !completed {
+            return Err("The run did not complete".into());
+        }
         Ok(())
     }
 
-    async fn agent_process_messages(&mut self) {
+    /// Save the state with the usage of this run added, so a crash loses at most one turn
+    async fn persist_state(&self) {
+        let mut state = self.state.clone();
+        state.usage.extend(self.agent.usage().await);
+        persist_state(&self.session_file, &state)
+            .unwrap_or_else(|e| eprintln!("Failed to persist session state: {}", e));
+    }
+
+    /// Propose a plan for `request`, let the user edit or approve it, then run it step by step
+    ///
+    /// No tool runs before the plan is approved. After every step the user can
+    /// continue, run the remaining steps without asking, or stop.
+    async fn plan_and_run(&mut self, request: &str) -> Result<()> {
+        self.messages.push(Message::user().with_text(request));
+        self.prompt.show_busy();
+        let plan = self.agent.plan(&self.messages).await;
+        self.prompt.hide_busy();
+        let mut plan = match plan {
+            Ok(plan) => plan,
+            Err(e) => {
+                eprintln!("Could not make a plan: {}", e);
+                self.messages.pop();
+                return Ok(());
+            }
+        };
+        if !review_plan(&mut plan)? {
+            self.messages.pop();
+            self.prompt
+                .render(raw_message("Cancelled the plan, nothing was run."));
+            return Ok(());
+        }
+        self.messages.push(
+            Message::assistant().with_text(format!("The approved plan:\n{}", plan.to_editable())),
+        );
+
+        let mut checkpoints = true;
+        while let Some(index) = plan.next_step() {
+            self.messages.push(plan.step_message(index));
+            persist_messages(&self.session_file, &self.messages)?;
+            let sent = self.messages.len();
+            self.prompt.show_busy();
+            self.agent_process_messages().await;
+            self.prompt.hide_busy();
+
+            // A failed or interrupted step is rewound, the rest of the plan is not run
+            let stopped = self.messages.len() < sent;
+            plan.steps[index].status = if stopped {
+                StepStatus::Skipped
+            } else {
+                StepStatus::Done
+            };
+            let stop = stopped
+                || (checkpoints
+                    && plan.next_step().is_some()
+                    && match checkpoint(&plan)? {
+                        Checkpoint::Continue => false,
+                        Checkpoint::RunRest => {
+                            checkpoints = false;
+                            false
+                        }
+                        Checkpoint::Stop => true,
+                    });
+            if stop {
+                plan.steps
+                    .iter_mut()
+                    .filter(|step| step.status == StepStatus::Pending)
+                    .for_each(|step| step.status = StepStatus::Skipped);
+            }
+        }
+        println!("\n{}", plan);
+        Ok(())
+    }
+
+    /// Run the agent on the messages, false if the reply failed or was interrupted
+    async fn agent_process_messages(&mut self) -> bool {
+        let completed = self.process_reply().await;
+        self.persist_state().await;
+        completed
+    }
+
+    async fn process_reply(&mut self) -> bool {
+        // Taken before the reply, which holds on to the agent until it ends
+        let policies = self.agent.tool_policies().await;
         let mut stream = match self.agent.reply(&self.messages).await {
             Ok(stream) => stream,
             Err(e) => {
                 eprintln!("Error starting reply stream: {}", e);
-                return;
+                return false;
             }
         };
         loop {
@@ -185,7 +524,10 @@ impl<'a> Session<'a> {
                             persist_messages(&self.session_file, &self.messages).unwrap_or_else(|e| eprintln!("Failed to persist messages: {}", e));
                             self.prompt.hide_busy();
                             self.prompt.render(Box::new(message.clone()));
-                            self.prompt.show_busy();
+                            // Keep the spinner away from the approval question
+                            if !needs_approval(&policies, &message) {
+                                self.prompt.show_busy();
+                            }
                         }
                         Some(Err(e)) => {
                             eprintln!("Error: {}", e);
@@ -196,9 +538,9 @@ The error above was an exception we were not able to handle.\n\n
 These errors are often related to connection or authentication\n
 We've removed the conversation up to the most recent user message
  - depending on the error you may be able to continue"#));
-                            break;
+                            return false;
                         }
-                        None => break,
+                        None => return true,
                     }
                 }
                 _ = tokio::signal::ctrl_c() => {
@@ -207,7 +549,7 @@ We've removed the conversation up to the most recent user message
                     // goose::process_store::kill_processes();
                     drop(stream);
                     self.handle_interrupted_messages();
-                    break;
+                    return false;
                 }
             }
         }
@@ -309,6 +651,18 @@ We've removed the conversation up to the most recent user message
     }
 
     async fn close_session(&mut self) {
+        if !self.messages.is_empty() {
+            match self.agent.remember(&self.messages).await {
+                Ok(0) => {}
+                Ok(added) => {
+                    self.prompt.render(raw_message(
+                        format!("Remembered {} facts about this project\n", added).as_str(),
+                    ));
+                    self.persist_state().await;
+                }
+                Err(e) => eprintln!("Could not remember this session: {}", e),
+            }
+        }
         self.prompt.render(raw_message(
             format!(
                 "Closing session. Recorded to {}\n",
@@ -326,6 +680,285 @@ We've removed the conversation up to the most recent user message
     }
 }
 
+/// Ask in the terminal before running tools whose permission policy is to always ask
+fn terminal_approval() -> ToolApproval {
+    Arc::new(|tool_call, category: ToolCategory| {
+        Box::pin(async move {
+            tokio::task::spawn_blocking(move || {
+                cliclack::confirm(format!(
+                    "Allow goose to run {} ({} tool)?",
+                    tool_call.name,
+                    category.label()
+                ))
+                .initial_value(false)
+                .interact()
+                .unwrap_or(false)
+            })
+            .await
+            .unwrap_or(false)
+        })
+    })
+}
+
+/// Show in the terminal where to enter the code that connects an extension with OAuth
+pub fn terminal_device_code() -> DeviceCodePrompt {
+    Arc::new(|extension, authorization| {
+        eprintln!(
+            "\nTo connect {}, open {} and enter the code {}\n",
+            extension,
+            authorization
+                .verification_uri_complete
+                .as_ref()
+                .unwrap_or(&authorization.verification_uri),
+            authorization.user_code
+        );
+    })
+}
+
+/// Ask in the terminal whether to go on when goose keeps calling tools
+fn terminal_pause() -> SafeguardPause {
+    Arc::new(|trip| {
+        Box::pin(async move {
+            tokio::task::spawn_blocking(move || {
+                let choice = cliclack::select(format!("Paused, {}", trip))
+                    .item("continue", "Continue", "run the tool calls and keep going")
+                    .item(
+                        "stop",
+                        "Stop",
+                        "run no more tools, then tell goose what to do",
+                    )
+                    .interact()
+                    .unwrap_or("stop");
+                match choice {
+                    "continue" => SafeguardDecision::Continue,
+                    _ => SafeguardDecision::Stop,
+                }
+            })
+            .await
+            .unwrap_or(SafeguardDecision::Stop)
+        })
+    })
+}
+
+/// Show the plan until the user approves or cancels it, editing it in between
+fn review_plan(plan: &mut Plan) -> Result<bool> {
+    loop {
+        println!("\n{}", plan);
+        let choice = cliclack::select("Run this plan?")
+            .item("approve", "Approve", "run the steps one at a time")
+            .item("edit", "Edit", "change the steps in your editor")
+            .item("cancel", "Cancel", "run nothing")
+            .interact()?;
+        match choice {
+            "approve" => return Ok(true),
+            "edit" => match edit_in_editor(&plan.to_editable()) {
+                Ok(text) => {
+                    let edited = Plan::from_editable(&text);
+                    if edited.steps.is_empty() {
+                        return Ok(false);
+                    }
+                    *plan = edited;
+                }
+                Err(e) => eprintln!("{}", e),
+            },
+            _ => return Ok(false),
+        }
+    }
+}
+
+/// Let the user change `text` in $VISUAL or $EDITOR, falling back to vi
+fn edit_in_editor(text: &str) -> Result<String> {
+    let path = std::env::temp_dir().join(format!("goose-plan-{}.md", std::process::id()));
+    fs::write(
+        &path,
+        format!(
+            "# One step per line, remove a line to drop the step\n{}",
+            text
+        ),
+    )?;
+    let editor = std::env::var("VISUAL")
+        .or_else(|_| std::env::var("EDITOR"))
+        .unwrap_or_else(|_| "vi".to_string());
+    // The editor may come with arguments, e.g. "code --wait"
+    let mut command = editor.split_whitespace();
+    let status = std::process::Command::new(command.next().unwrap_or("vi"))
+        .args(command)
+        .arg(&path)
+        .status();
+    let edited = fs::read_to_string(&path);
+    let _ = fs::remove_file(&path);
+    match status {
+        Ok(status) if status.success() => Ok(edited?),
+        Ok(status) => Err(anyhow::anyhow!("The editor exited with {}", status)),
+        Err(e) => Err(anyhow::anyhow!(
+            "Could not start the editor {}: {}",
+            editor,
+            e
+        )),
+    }
+}
+
+/// What to do after a step of the plan finished
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Checkpoint {
+    Continue,
+    RunRest,
+    Stop,
+}
+
+fn checkpoint(plan: &Plan) -> Result<Checkpoint> {
+    println!("\n{}", plan);
+    Ok(cliclack::select("Continue with the next step?")
+        .item(Checkpoint::Continue, "Continue", "")
+        .item(
+            Checkpoint::RunRest,
+            "Run the remaining steps",
+            "without stopping after each one",
+        )
+        .item(Checkpoint::Stop, "Stop", "skip the remaining steps")
+        .interact()?)
+}
+
+fn needs_approval(policies: &ToolPolicies, message: &Message) -> bool {
+    message.content.iter().any(|content| {
+        content
+            .as_tool_request()
+            .and_then(|request| request.tool_call.as_ref().ok())
+            .is_some_and(|tool_call| policies.asks(&tool_call.name))
+    })
+}
+
 fn raw_message(content: &str) -> Box<Message> {
     Box::new(Message::assistant().with_text(content))
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use goose::agents::extension::Sandbox;
+    use goose::providers::base::Usage;
+    use std::collections::HashMap;
+    use tempfile::TempDir;
+
+    fn one_off_extension() -> ExtensionConfig {
+        ExtensionConfig::Stdio {
+            name: "tracker".to_string(),
+            cmd: "tracker-mcp".to_string(),
+            args: vec!["--readonly".to_string()],
+            envs: Envs::new(HashMap::from([
+                ("TRACKER_TOKEN".to_string(), "s3cret".to_string()),
+                ("TRACKER_HOST".to_string(), "tracker.internal".to_string()),
+            ])),
+            sandbox: Sandbox::default(),
+        }
+    }
+
+    #[test]
+    fn test_persist_and_load_state() -> Result<()> {
+        let dir = TempDir::new()?;
+        let session_file = dir.path().join("work.jsonl");
+        assert!(load_state(&session_file)?.is_none());
+
+        let state = SessionState {
+            provider: "openai".to_string(),
+            model: "gpt-4o".to_string(),
+            extensions: vec![
+                ExtensionConfig::Builtin {
+                    name: "developer".to_string(),
+                },
+                one_off_extension(),
+            ],
+            usage: vec![ProviderUsage::new(
+                "gpt-4o".to_string(),
+                Usage::new(Some(10), Some(5), Some(15)),
+            )],
+            forked_from: None,
+        };
+        persist_state(&session_file, &state)?;
+
+        let path = state_file(&session_file);
+        assert!(!fs::read_to_string(&path)?.contains("s3cret"));
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            assert_eq!(fs::metadata(&path)?.permissions().mode() & 0o777, 0o600);
+        }
+
+        let loaded = load_state(&session_file)?.unwrap();
+        assert_eq!(loaded.provider, "openai");
+        assert_eq!(loaded.model, "gpt-4o");
+        assert_eq!(loaded.usage.len(), 1);
+        assert_eq!(loaded.extensions.len(), 2);
+        let ExtensionConfig::Stdio { args, envs, .. } = &loaded.extensions[1] else {
+            unreachable!("expected the stdio extension");
+        };
+        assert_eq!(args, &vec!["--readonly".to_string()]);
+        assert!(envs.get_env().values().all(String::is_empty));
+        assert_eq!(envs.get_env().len(), 2);
+        Ok(())
+    }
+
+    #[test]
+    fn test_restore_envs() {
+        let dir = TempDir::new().unwrap();
+        let session_file = dir.path().join("work.jsonl");
+        let state = SessionState {
+            extensions: vec![one_off_extension()],
+            ..Default::default()
+        };
+        persist_state(&session_file, &state).unwrap();
+        let mut extension = load_state(&session_file).unwrap().unwrap().extensions[0].clone();
+
+        let missing = restore_envs(&mut extension, |name| {
+            (name == "TRACKER_TOKEN").then(|| "fresh".to_string())
+        });
+        assert_eq!(missing, vec!["TRACKER_HOST".to_string()]);
+        let ExtensionConfig::Stdio { envs, .. } = extension else {
+            unreachable!("expected the stdio extension");
+        };
+        assert_eq!(
+            envs.get_env(),
+            HashMap::from([("TRACKER_TOKEN".to_string(), "fresh".to_string())])
+        );
+
+        let mut builtin = ExtensionConfig::Builtin {
+            name: "developer".to_string(),
+        };
+        assert!(restore_envs(&mut builtin, |_| None).is_empty());
+    }
+
+    #[test]
+    fn test_remote_headers_are_not_persisted() -> Result<()> {
+        let dir = TempDir::new()?;
+        let session_file = dir.path().join("work.jsonl");
+        let state = SessionState {
+            extensions: vec![ExtensionConfig::StreamableHttp {
+                name: "tracker".to_string(),
+                uri: "https://tracker.internal/mcp".to_string(),
+                headers: HashMap::from([
+                    ("X-Api-Key".to_string(), "k3y".to_string()),
+                    ("X-Team".to_string(), "core".to_string()),
+                ]),
+                auth: Default::default(),
+            }],
+            ..Default::default()
+        };
+        persist_state(&session_file, &state)?;
+        let contents = fs::read_to_string(state_file(&session_file))?;
+        assert!(!contents.contains("k3y") && !contents.contains("core"));
+
+        let mut extension = load_state(&session_file)?.unwrap().extensions[0].clone();
+        let missing = restore_envs(&mut extension, |name| {
+            (name == "TRACKER_X_API_KEY").then(|| "fresh".to_string())
+        });
+        assert_eq!(missing, vec!["TRACKER_X_TEAM".to_string()]);
+        let ExtensionConfig::StreamableHttp { headers, .. } = extension else {
+            unreachable!("expected the remote extension");
+        };
+        assert_eq!(
+            headers,
+            HashMap::from([("X-Api-Key".to_string(), "fresh".to_string())])
+        );
+        Ok(())
+    }
+}
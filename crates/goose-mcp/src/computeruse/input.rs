@@ -0,0 +1,583 @@
+//! Mouse and keyboard input through the tool of the platform
+//!
+//! Linux uses xdotool and macOS cliclick, both have to be installed. cliclick
+//! can not turn the mouse wheel, so macOS scrolls with a Quartz event posted
+//! through osascript. The actions are those of Anthropic's computer use tool,
+//! so the inputs of the native tool can be run as they are.
+use serde_json::Value;
+use tokio::process::Command;
+
+use mcp_core::handler::ToolError;
+
+use super::Display;
+
+/// A point on the screen, in screen pixels
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct Point {
+    pub x: i32,
+    pub y: i32,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Button {
+    Left,
+    Right,
+    Middle,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Direction {
+    Up,
+    Down,
+    Left,
+    Right,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum Action {
+    Screenshot,
+    CursorPosition,
+    MouseMove(Point),
+    /// Clicks `count` times at `at`, or where the mouse is
+    Click {
+        button: Button,
+        count: u8,
+        at: Option<Point>,
+    },
+    Drag {
+        from: Option<Point>,
+        to: Point,
+    },
+    MouseDown,
+    MouseUp,
+    Key(String),
+    HoldKey {
+        keys: String,
+        seconds: f64,
+    },
+    Type(String),
+    Scroll {
+        at: Option<Point>,
+        direction: Direction,
+        amount: u32,
+    },
+    Wait(f64),
+}
+
+fn invalid(message: impl Into<String>) -> ToolError {
+    ToolError::InvalidParameters(message.into())
+}
+
+impl Action {
+    /// The action the tool was called with, coordinates are those of screenshots of `display`
+    pub fn parse(params: &Value, display: &Display) -> Result<Self, ToolError> {
+        let action = params
+            .get("action")
+            .and_then(|v| v.as_str())
+            .ok_or_else(|| invalid("Missing 'action' parameter"))?;
+
+        let point = |name: &str| -> Result<Option<Point>, ToolError> {
+            let Some(value) = params.get(name).filter(|v| !v.is_null()) else {
+                return Ok(None);
+            };
+            let coordinate: Vec<u64> = value
+                .as_array()
+                .filter(|c| c.len() == 2)
+                .and_then(|c| c.iter().map(|v| v.as_u64()).collect())
+                .ok_or_else(|| invalid(format!("'{}' must be [x, y]", name)))?;
+            let (x, y) = (coordinate[0] as u32, coordinate[1] as u32);
+            if x >= display.screenshot_width() || y >= display.screenshot_height() {
+                return Err(invalid(format!(
+                    "[{}, {}] is outside of the {}x{} screen",
+                    x,
+                    y,
+                    display.screenshot_width(),
+                    display.screenshot_height()
+                )));
+            }
+            Ok(Some(display.to_screen(x, y)))
+        };
+        let required_point = |name: &str| {
+            point(name)?.ok_or_else(|| invalid(format!("{} needs '{}'", action, name)))
+        };
+        let text = || {
+            params
+                .get("text")
+                .and_then(|v| v.as_str())
+                .filter(|t| !t.is_empty())
+                .map(str::to_string)
+                .ok_or_else(|| invalid(format!("{} needs 'text'", action)))
+        };
+        let duration = || {
+            params
+                .get("duration")
+                .and_then(|v| v.as_f64())
+                .filter(|d| (0.0..=100.0).contains(d))
+                .ok_or_else(|| invalid(format!("{} needs a 'duration' of 0 to 100", action)))
+        };
+        let click = |button, count| -> Result<Action, ToolError> {
+            Ok(Action::Click {
+                button,
+                count,
+                at: point("coordinate")?,
+            })
+        };
+
+        match action {
+            "screenshot" => Ok(Action::Screenshot),
+            "cursor_position" => Ok(Action::CursorPosition),
+            "mouse_move" => Ok(Action::MouseMove(required_point("coordinate")?)),
+            "left_click" => click(Button::Left, 1),
+            "right_click" => click(Button::Right, 1),
+            "middle_click" => click(Button::Middle, 1),
+            "double_click" => click(Button::Left, 2),
+            "triple_click" => click(Button::Left, 3),
+            "left_click_drag" => Ok(Action::Drag {
+                from: point("start_coordinate")?,
+                to: required_point("coordinate")?,
+            }),
+            "left_mouse_down" => Ok(Action::MouseDown),
+            "left_mouse_up" => Ok(Action::MouseUp),
+            "key" => Ok(Action::Key(text()?)),
+            "hold_key" => Ok(Action::HoldKey {
+                keys: text()?,
+                seconds: duration()?,
+            }),
+            "type" => Ok(Action::Type(text()?)),
+            "scroll" => {
+                let direction = match params.get("scroll_direction").and_then(|v| v.as_str()) {
+                    Some("up") => Direction::Up,
+                    Some("down") => Direction::Down,
+                    Some("left") => Direction::Left,
+                    Some("right") => Direction::Right,
+                    _ => return Err(invalid("scroll needs a 'scroll_direction'")),
+                };
+                let amount = params
+                    .get("scroll_amount")
+                    .and_then(|v| v.as_u64())
+                    .unwrap_or(3)
+                    .clamp(1, 100) as u32;
+                Ok(Action::Scroll {
+                    at: point("coordinate")?,
+                    direction,
+                    amount,
+                })
+            }
+            "wait" => Ok(Action::Wait(duration()?)),
+            _ => Err(invalid(format!("Unknown action '{}'", action))),
+        }
+    }
+}
+
+fn coordinates(point: Point) -> [String; 2] {
+    [point.x.to_string(), point.y.to_string()]
+}
+
+/// The arguments of one xdotool call that performs `action`
+fn xdotool_args(action: &Action) -> Vec<String> {
+    let mut args: Vec<String> = Vec::new();
+    let move_to = |args: &mut Vec<String>, at: &Option<Point>| {
+        if let Some(point) = at {
+            args.push("mousemove".into());
+            args.push("--sync".into());
+            args.extend(coordinates(*point));
+        }
+    };
+    match action {
+        Action::MouseMove(point) => move_to(&mut args, &Some(*point)),
+        Action::Click { button, count, at } => {
+            move_to(&mut args, at);
+            let button = match button {
+                Button::Left => "1",
+                Button::Middle => "2",
+                Button::Right => "3",
+            };
+            args.extend(["click", "--repeat", &count.to_string(), button].map(String::from));
+        }
+        Action::Drag { from, to } => {
+            move_to(&mut args, from);
+            args.extend(["mousedown", "1"].map(String::from));
+            move_to(&mut args, &Some(*to));
+            args.extend(["mouseup", "1"].map(String::from));
+        }
+        Action::MouseDown => args.extend(["mousedown", "1"].map(String::from)),
+        Action::MouseUp => args.extend(["mouseup", "1"].map(String::from)),
+        // Combinations are separated by spaces, e.g. "ctrl+a Delete"
+        Action::Key(keys) => {
+            args.push("key".into());
+            args.push("--".into());
+            args.extend(keys.split_whitespace().map(String::from));
+        }
+        Action::HoldKey { keys, seconds } => {
+            args.push("keydown".into());
+            args.extend(keys.split_whitespace().map(String::from));
+            args.extend([
+                "sleep".to_string(),
+                seconds.to_string(),
+                "keyup".to_string(),
+            ]);
+            args.extend(keys.split_whitespace().map(String::from));
+        }
+        Action::Type(text) => args.extend(["type", "--delay", "12", "--", text].map(String::from)),
+        Action::Scroll {
+            at,
+            direction,
+            amount,
+        } => {
+            move_to(&mut args, at);
+            let button = match direction {
+                Direction::Up => "4",
+                Direction::Down => "5",
+                Direction::Left => "6",
+                Direction::Right => "7",
+            };
+            args.extend(["click", "--repeat", &amount.to_string(), button].map(String::from));
+        }
+        Action::Screenshot | Action::CursorPosition | Action::Wait(_) => {}
+    }
+    args
+}
+
+/// The name cliclick knows a key by, None for keys that are typed as text
+fn cliclick_key(key: &str) -> Option<&'static str> {
+    let name = match key.to_ascii_lowercase().as_str() {
+        "return" | "enter" | "kp_enter" => "return",
+        "escape" | "esc" => "esc",
+        "tab" => "tab",
+        "backspace" => "delete",
+        "delete" => "fwd-delete",
+        "space" => "space",
+        "up" => "arrow-up",
+        "down" => "arrow-down",
+        "left" => "arrow-left",
+        "right" => "arrow-right",
+        "home" => "home",
+        "end" => "end",
+        "page_up" | "prior" => "page-up",
+        "page_down" | "next" => "page-down",
+        "f1" => "f1",
+        "f2" => "f2",
+        "f3" => "f3",
+        "f4" => "f4",
+        "f5" => "f5",
+        "f6" => "f6",
+        "f7" => "f7",
+        "f8" => "f8",
+        "f9" => "f9",
+        "f10" => "f10",
+        "f11" => "f11",
+        "f12" => "f12",
+        _ => return None,
+    };
+    Some(name)
+}
+
+/// The name cliclick knows a modifier by, also for the left and right keys such as "Control_L"
+fn cliclick_modifier(key: &str) -> Option<&'static str> {
+    let key = key.to_ascii_lowercase();
+    let key = key
+        .strip_suffix("_l")
+        .or_else(|| key.strip_suffix("_r"))
+        .unwrap_or(&key);
+    match key {
+        "ctrl" | "control" => Some("ctrl"),
+        "alt" | "option" => Some("alt"),
+        "shift" => Some("shift"),
+        "super" | "cmd" | "command" | "meta" => Some("cmd"),
+        "fn" => Some("fn"),
+        _ => None,
+    }
+}
+
+/// The keys of a chord such as "ctrl+s", "cmd-shift-t" or "ctrl++"
+fn chord_keys(chord: &str) -> Vec<&str> {
+    let separator = match chord.split_once('-') {
+        Some((first, _)) if !chord.contains('+') && cliclick_modifier(first).is_some() => '-',
+        _ => '+',
+    };
+    let mut keys: Vec<&str> = chord.split(separator).collect();
+    // A trailing separator is the key itself, as in "ctrl++"
+    if keys.len() > 1 && keys.ends_with(&["", ""]) {
+        keys.truncate(keys.len() - 2);
+        keys.push(&chord[chord.len() - 1..]);
+    }
+    keys
+}
+
+/// The cliclick commands that press xdotool key combinations, e.g. "ctrl+s" or "ctrl+a Delete"
+///
+/// Chords separated by whitespace are pressed one after the other, a hold
+/// keeps the last one pressed.
+fn cliclick_keys(keys: &str, hold: Option<f64>) -> Result<Vec<String>, ToolError> {
+    let chords: Vec<&str> = keys.split_whitespace().collect();
+    let mut commands = Vec::new();
+    for (i, chord) in chords.iter().enumerate() {
+        let hold = hold.filter(|_| i + 1 == chords.len());
+        commands.extend(cliclick_chord(chord, hold)?);
+    }
+    if commands.is_empty() {
+        return Err(invalid("No keys to press"));
+    }
+    Ok(commands)
+}
+
+/// The cliclick commands that press the keys of one chord
+fn cliclick_chord(chord: &str, hold: Option<f64>) -> Result<Vec<String>, ToolError> {
+    let parts = chord_keys(chord);
+    let (key, modifiers) = parts.split_last().unwrap_or((&"", &[]));
+    let mut modifiers: Vec<&str> = modifiers
+        .iter()
+        .map(|m| cliclick_modifier(m).ok_or_else(|| invalid(format!("Unknown modifier '{}'", m))))
+        .collect::<Result<_, _>>()?;
+
+    let mut press = None;
+    match (cliclick_modifier(key), cliclick_key(key)) {
+        (Some(modifier), _) => modifiers.push(modifier),
+        (None, Some(name)) => press = Some(format!("kp:{}", name)),
+        (None, None) if key.chars().count() == 1 => press = Some(format!("t:{}", key)),
+        _ => return Err(invalid(format!("Unknown key '{}'", key))),
+    }
+
+    let mut commands = Vec::new();
+    if !modifiers.is_empty() {
+        commands.push(format!("kd:{}", modifiers.join(",")));
+    }
+    commands.extend(press);
+    if let Some(seconds) = hold {
+        commands.push(format!("w:{}", (seconds * 1000.0).round() as u64));
+    }
+    if !modifiers.is_empty() {
+        commands.push(format!("ku:{}", modifiers.join(",")));
+    }
+    Ok(commands)
+}
+
+/// The arguments of one cliclick call that performs `action`
+fn cliclick_args(action: &Action) -> Result<Vec<String>, ToolError> {
+    let at = |point: &Option<Point>| match point {
+        Some(point) => format!("{},{}", point.x, point.y),
+        None => ".".to_string(),
+    };
+    let commands = match action {
+        Action::MouseMove(point) => vec![format!("m:{}", at(&Some(*point)))],
+        Action::Click {
+            button,
+            count,
+            at: point,
+        } => {
+            let command = match (button, count) {
+                (Button::Left, 1) => "c",
+                (Button::Left, 2) => "dc",
+                (Button::Left, _) => "tc",
+                (Button::Right, _) => "rc",
+                (Button::Middle, _) => {
+                    return Err(ToolError::ExecutionError(
+                        "Middle clicks are not supported on macOS".into(),
+                    ))
+                }
+            };
+            vec![format!("{}:{}", command, at(point))]
+        }
+        Action::Drag { from, to } => vec![
+            format!("dd:{}", at(from)),
+            format!("dm:{}", at(&Some(*to))),
+            format!("du:{}", at(&Some(*to))),
+        ],
+        Action::MouseDown => vec!["dd:.".to_string()],
+        Action::MouseUp => vec!["du:.".to_string()],
+        Action::Key(keys) => cliclick_keys(keys, None)?,
+        Action::HoldKey { keys, seconds } => cliclick_keys(keys, Some(*seconds))?,
+        Action::Type(text) => vec![format!("t:{}", text)],
+        // Only moves the mouse, `quartz_scroll_args` turns the wheel
+        Action::Scroll {
+            at: Some(point), ..
+        } => vec![format!("m:{}", at(&Some(*point)))],
+        Action::Scroll { at: None, .. } => Vec::new(),
+        Action::Screenshot | Action::CursorPosition | Action::Wait(_) => Vec::new(),
+    };
+    Ok(commands)
+}
+
+/// The osascript arguments that turn the mouse wheel by `amount` clicks where the mouse is
+///
+/// Posts a Quartz scroll event counted in lines, positive values scroll up
+/// and left like a wheel turned away from the user.
+fn quartz_scroll_args(direction: Direction, amount: u32) -> Vec<String> {
+    let amount = amount as i64;
+    let (vertical, horizontal) = match direction {
+        Direction::Up => (amount, 0),
+        Direction::Down => (-amount, 0),
+        Direction::Left => (0, amount),
+        Direction::Right => (0, -amount),
+    };
+    let script = format!(
+        "ObjC.import('CoreGraphics'); \
+         $.CGEventPost(0, $.CGEventCreateScrollWheelEvent2(null, 1, 2, {}, {}, 0));",
+        vertical, horizontal
+    );
+    ["-l", "JavaScript", "-e", &script]
+        .map(String::from)
+        .to_vec()
+}
+
+/// The input tool of this platform
+fn input_tool() -> Result<&'static str, ToolError> {
+    match std::env::consts::OS {
+        "linux" => Ok("xdotool"),
+        "macos" => Ok("cliclick"),
+        os => Err(ToolError::ExecutionError(format!(
+            "Mouse and keyboard input is not supported on {}",
+            os
+        ))),
+    }
+}
+
+async fn run(tool: &str, args: &[String]) -> Result<String, ToolError> {
+    let output = Command::new(tool).args(args).output().await.map_err(|e| {
+        ToolError::ExecutionError(format!("Failed to run {}, is it installed? {}", tool, e))
+    })?;
+    if !output.status.success() {
+        return Err(ToolError::ExecutionError(format!(
+            "{} failed: {}",
+            tool,
+            String::from_utf8_lossy(&output.stderr).trim()
+        )));
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+/// Perform `action` with the mouse or keyboard
+pub(crate) async fn perform(action: &Action) -> Result<(), ToolError> {
+    let tool = input_tool()?;
+    let args = match tool {
+        "xdotool" => xdotool_args(action),
+        _ => cliclick_args(action)?,
+    };
+    if !args.is_empty() {
+        run(tool, &args).await?;
+    }
+    if let (
+        "cliclick",
+        Action::Scroll {
+            direction, amount, ..
+        },
+    ) = (tool, action)
+    {
+        run("osascript", &quartz_scroll_args(*direction, *amount)).await?;
+    }
+    Ok(())
+}
+
+/// Where the mouse is on the screen
+pub(crate) async fn cursor_position() -> Result<Point, ToolError> {
+    let tool = input_tool()?;
+    let parse_error = |output: &str| {
+        ToolError::ExecutionError(format!("Unexpected output of {}: {}", tool, output))
+    };
+    let (x, y) = match tool {
+        // X=100\nY=200\nSCREEN=0\nWINDOW=123
+        "xdotool" => {
+            let output = run(tool, &["getmouselocation".into(), "--shell".into()]).await?;
+            let value = |name: &str| {
+                output
+                    .lines()
+                    .find_map(|line| line.strip_prefix(name)?.parse::<i32>().ok())
+            };
+            (value("X=").zip(value("Y="))).ok_or_else(|| parse_error(&output))?
+        }
+        // 100,200
+        _ => {
+            let output = run(tool, &["p:.".into()]).await?;
+            output
+                .trim()
+                .split_once(',')
+                .and_then(|(x, y)| Some((x.parse().ok()?, y.parse().ok()?)))
+                .ok_or_else(|| parse_error(&output))?
+        }
+    };
+    Ok(Point { x, y })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_xdotool_args() {
+        let args = xdotool_args(&Action::Click {
+            button: Button::Right,
+            count: 1,
+            at: Some(Point { x: 10, y: 20 }),
+        });
+        assert_eq!(
+            args,
+            [
+                "mousemove",
+                "--sync",
+                "10",
+                "20",
+                "click",
+                "--repeat",
+                "1",
+                "3"
+            ]
+        );
+        let args = xdotool_args(&Action::Type("-rf /".to_string()));
+        assert_eq!(args, ["type", "--delay", "12", "--", "-rf /"]);
+    }
+
+    #[test]
+    fn test_cliclick_keys() {
+        assert_eq!(
+            cliclick_keys("ctrl+shift+t", None).unwrap(),
+            ["kd:ctrl,shift", "t:t", "ku:ctrl,shift"]
+        );
+        assert_eq!(cliclick_keys("Return", None).unwrap(), ["kp:return"]);
+        assert_eq!(
+            cliclick_keys("shift", Some(1.5)).unwrap(),
+            ["kd:shift", "w:1500", "ku:shift"]
+        );
+        assert!(cliclick_keys("hyper+x", None).is_err());
+        assert!(cliclick_keys("Launch", None).is_err());
+    }
+
+    #[test]
+    fn test_cliclick_chord_forms() {
+        assert_eq!(
+            cliclick_keys("cmd-shift-t", None).unwrap(),
+            ["kd:cmd,shift", "t:t", "ku:cmd,shift"]
+        );
+        assert_eq!(
+            cliclick_keys("Control_L+s", None).unwrap(),
+            ["kd:ctrl", "t:s", "ku:ctrl"]
+        );
+        assert_eq!(
+            cliclick_keys("ctrl++", None).unwrap(),
+            ["kd:ctrl", "t:+", "ku:ctrl"]
+        );
+        assert_eq!(cliclick_keys("-", None).unwrap(), ["t:-"]);
+        assert_eq!(
+            cliclick_keys("ctrl+a Delete", None).unwrap(),
+            ["kd:ctrl", "t:a", "ku:ctrl", "kp:fwd-delete"]
+        );
+        assert_eq!(
+            cliclick_keys("ctrl+a shift", Some(0.5)).unwrap(),
+            ["kd:ctrl", "t:a", "ku:ctrl", "kd:shift", "w:500", "ku:shift"]
+        );
+        assert!(cliclick_keys(" ", None).is_err());
+    }
+
+    #[test]
+    fn test_cliclick_scroll() {
+        let scroll = Action::Scroll {
+            at: Some(Point { x: 10, y: 20 }),
+            direction: Direction::Down,
+            amount: 3,
+        };
+        assert_eq!(cliclick_args(&scroll).unwrap(), ["m:10,20"]);
+        let args = quartz_scroll_args(Direction::Down, 3);
+        assert_eq!(args[..3], ["-l", "JavaScript", "-e"]);
+        assert!(args[3].contains("CGEventCreateScrollWheelEvent2(null, 1, 2, -3, 0, 0)"));
+        assert!(quartz_scroll_args(Direction::Left, 2)[3].contains("(null, 1, 2, 0, 2, 0)"));
+    }
+}
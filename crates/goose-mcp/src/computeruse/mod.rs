@@ -0,0 +1,385 @@
+mod input;
+
+use base64::Engine;
+use indoc::formatdoc;
+use serde_json::{json, Value};
+use std::{future::Future, io::Cursor, pin::Pin, time::Duration};
+use xcap::Monitor;
+
+use mcp_core::{
+    handler::{ResourceError, ToolError},
+    protocol::ServerCapabilities,
+    resource::Resource,
+    tool::Tool,
+    Content,
+};
+use mcp_server::router::CapabilitiesBuilder;
+use mcp_server::Router;
+
+use input::{Action, Point};
+
+/// Largest screenshot sent to the model, larger screens are scaled down to fit
+const MAX_SCREENSHOT_WIDTH: u32 = 1280;
+const MAX_SCREENSHOT_HEIGHT: u32 = 800;
+
+/// How long to let the screen settle after an action before the screenshot
+const SETTLE_DELAY: Duration = Duration::from_millis(500);
+
+/// The screen the actions go to and how screenshots of it are scaled
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) struct Display {
+    x: i32,
+    y: i32,
+    width: u32,
+    height: u32,
+    /// Screenshot pixels per screen pixel
+    scale: f64,
+}
+
+impl Display {
+    fn new(x: i32, y: i32, width: u32, height: u32) -> Self {
+        let scale = (MAX_SCREENSHOT_WIDTH as f64 / width.max(1) as f64)
+            .min(MAX_SCREENSHOT_HEIGHT as f64 / height.max(1) as f64)
+            .min(1.0);
+        Self {
+            x,
+            y,
+            width,
+            height,
+            scale,
+        }
+    }
+
+    fn of(monitor: &Monitor) -> Self {
+        Self::new(monitor.x(), monitor.y(), monitor.width(), monitor.height())
+    }
+
+    fn screenshot_width(&self) -> u32 {
+        (self.width as f64 * self.scale).round() as u32
+    }
+
+    fn screenshot_height(&self) -> u32 {
+        (self.height as f64 * self.scale).round() as u32
+    }
+
+    /// The point on the screen at `x`, `y` in the screenshot
+    fn to_screen(self, x: u32, y: u32) -> Point {
+        Point {
+            x: self.x + (x as f64 / self.scale).round() as i32,
+            y: self.y + (y as f64 / self.scale).round() as i32,
+        }
+    }
+
+    /// The point in the screenshot at `point` on the screen
+    fn to_screenshot(self, point: Point) -> (i64, i64) {
+        (
+            ((point.x - self.x) as f64 * self.scale).round() as i64,
+            ((point.y - self.y) as f64 * self.scale).round() as i64,
+        )
+    }
+}
+
+/// The primary monitor, or the first one when none is marked primary
+fn primary_monitor() -> Result<Monitor, ToolError> {
+    let monitors = Monitor::all()
+        .map_err(|_| ToolError::ExecutionError("Failed to access monitors".into()))?;
+    let primary = monitors.iter().position(|m| m.is_primary()).unwrap_or(0);
+    monitors
+        .into_iter()
+        .nth(primary)
+        .ok_or_else(|| ToolError::ExecutionError("No monitor found".into()))
+}
+
+/// An extension that sees the screen through screenshots and uses the mouse and keyboard
+///
+/// Every action is answered with a new screenshot, so the model sees what it
+/// did. Coordinates are those of the screenshots, the `coordinate` parameter
+/// is bounded by their size, which is also how providers with a native
+/// computer use tool learn the display size.
+#[derive(Clone)]
+pub struct ComputerUseRouter {
+    tools: Vec<Tool>,
+    display: Display,
+    instructions: String,
+}
+
+impl Default for ComputerUseRouter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ComputerUseRouter {
+    pub fn new() -> Self {
+        // Without a monitor, e.g. over ssh, the tool is still listed and reports the error when used
+        let display = primary_monitor()
+            .map(|monitor| Display::of(&monitor))
+            .unwrap_or_else(|_| Display::new(0, 0, MAX_SCREENSHOT_WIDTH, MAX_SCREENSHOT_HEIGHT));
+
+        let computer_tool = Tool::new(
+            "computer",
+            formatdoc! {r#"
+                Use a mouse and keyboard to interact with the computer, and take screenshots.
+
+                The screen is shown as {width}x{height} screenshots, coordinates are pixels
+                in them from the top left. Every action returns a new screenshot. Take a
+                screenshot first to see where things are, and check the one after each
+                action before going on. Click in the middle of elements.
+
+                Actions:
+                - screenshot: capture the screen
+                - cursor_position: where the mouse is
+                - mouse_move: move the mouse to `coordinate`
+                - left_click, right_click, middle_click, double_click, triple_click: click at `coordinate`, or where the mouse is
+                - left_click_drag: press at `start_coordinate`, or where the mouse is, and release at `coordinate`
+                - left_mouse_down, left_mouse_up: press or release the left button
+                - key: press a key or combination in `text`, e.g. "Return" or "ctrl+s"
+                - hold_key: hold the keys in `text` for `duration` seconds
+                - type: type `text`
+                - scroll: scroll `scroll_amount` clicks in `scroll_direction` at `coordinate`, or where the mouse is
+                - wait: wait `duration` seconds
+                "#,
+                width = display.screenshot_width(),
+                height = display.screenshot_height(),
+            },
+            tool_schema(&display),
+        );
+
+        let instructions = formatdoc! {r#"
+            The computeruse extension controls the graphical desktop of this computer
+            with the computer tool. Prefer shell and file tools where they can do the
+            task, and use the computer tool for applications that can only be used
+            through their interface.
+            "#};
+
+        Self {
+            tools: vec![computer_tool],
+            display,
+            instructions,
+        }
+    }
+
+    async fn computer(&self, params: Value) -> Result<Vec<Content>, ToolError> {
+        let action = Action::parse(&params, &self.display)?;
+        match &action {
+            Action::Screenshot => return self.screenshot().await,
+            Action::CursorPosition => {
+                let (x, y) = self.display.to_screenshot(input::cursor_position().await?);
+                return Ok(vec![Content::text(format!("X={}, Y={}", x, y))]);
+            }
+            Action::Wait(seconds) => {
+                tokio::time::sleep(Duration::from_secs_f64(*seconds)).await;
+            }
+            _ => {
+                input::perform(&action).await?;
+                tokio::time::sleep(SETTLE_DELAY).await;
+            }
+        }
+        let mut contents = vec![Content::text(format!(
+            "Did {}",
+            params["action"].as_str().unwrap_or_default()
+        ))];
+        contents.extend(self.screenshot().await?);
+        Ok(contents)
+    }
+
+    async fn screenshot(&self) -> Result<Vec<Content>, ToolError> {
+        let display = self.display;
+        let data = tokio::task::spawn_blocking(move || {
+            let monitor = primary_monitor()?;
+            let image = monitor.capture_image().map_err(|e| {
+                ToolError::ExecutionError(format!("Failed to capture the screen: {}", e))
+            })?;
+            // Screens with a scale factor capture more pixels than they have points
+            let image = xcap::image::imageops::resize(
+                &image,
+                display.screenshot_width(),
+                display.screenshot_height(),
+                xcap::image::imageops::FilterType::Triangle,
+            );
+            let mut bytes: Vec<u8> = Vec::new();
+            image
+                .write_to(&mut Cursor::new(&mut bytes), xcap::image::ImageFormat::Png)
+                .map_err(|e| {
+                    ToolError::ExecutionError(format!("Failed to write image buffer {}", e))
+                })?;
+            Ok::<_, ToolError>(base64::prelude::BASE64_STANDARD.encode(bytes))
+        })
+        .await
+        .map_err(|e| ToolError::ExecutionError(e.to_string()))??;
+
+        Ok(vec![Content::image(data, "image/png")])
+    }
+}
+
+fn tool_schema(display: &Display) -> Value {
+    let coordinate = |description: &str| {
+        json!({
+            "type": "array",
+            "description": description,
+            "items": {"type": "integer", "minimum": 0},
+            "prefixItems": [
+                {"type": "integer", "minimum": 0, "maximum": display.screenshot_width() - 1},
+                {"type": "integer", "minimum": 0, "maximum": display.screenshot_height() - 1}
+            ],
+            "minItems": 2,
+            "maxItems": 2
+        })
+    };
+    json!({
+        "type": "object",
+        "required": ["action"],
+        "properties": {
+            "action": {
+                "type": "string",
+                "enum": [
+                    "screenshot", "cursor_position", "mouse_move", "left_click",
+                    "right_click", "middle_click", "double_click", "triple_click",
+                    "left_click_drag", "left_mouse_down", "left_mouse_up", "key",
+                    "hold_key", "type", "scroll", "wait"
+                ]
+            },
+            "coordinate": coordinate("[x, y] in the screenshot"),
+            "start_coordinate": coordinate("[x, y] in the screenshot where a drag starts"),
+            "text": {
+                "type": "string",
+                "description": "The text to type, or the keys to press, e.g. \"ctrl+s\""
+            },
+            "scroll_direction": {
+                "type": "string",
+                "enum": ["up", "down", "left", "right"]
+            },
+            "scroll_amount": {
+                "type": "integer",
+                "minimum": 1,
+                "description": "How many clicks of the wheel to scroll"
+            },
+            "duration": {
+                "type": "number",
+                "minimum": 0,
+                "maximum": 100,
+                "description": "Seconds to wait or to hold the keys"
+            }
+        }
+    })
+}
+
+impl Router for ComputerUseRouter {
+    fn name(&self) -> String {
+        "computeruse".to_string()
+    }
+
+    fn instructions(&self) -> String {
+        self.instructions.clone()
+    }
+
+    fn capabilities(&self) -> ServerCapabilities {
+        CapabilitiesBuilder::new().with_tools(false).build()
+    }
+
+    fn list_tools(&self) -> Vec<Tool> {
+        self.tools.clone()
+    }
+
+    fn call_tool(
+        &self,
+        tool_name: &str,
+        arguments: Value,
+    ) -> Pin<Box<dyn Future<Output = Result<Vec<Content>, ToolError>> + Send + 'static>> {
+        let this = self.clone();
+        let tool_name = tool_name.to_string();
+        Box::pin(async move {
+            match tool_name.as_str() {
+                "computer" => this.computer(arguments).await,
+                _ => Err(ToolError::NotFound(format!("Tool {} not found", tool_name))),
+            }
+        })
+    }
+
+    fn list_resources(&self) -> Vec<Resource> {
+        Vec::new()
+    }
+
+    fn read_resource(
+        &self,
+        _uri: &str,
+    ) -> Pin<Box<dyn Future<Output = Result<String, ResourceError>> + Send + 'static>> {
+        Box::pin(async move { Ok("".to_string()) })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::input::{Button, Direction};
+    use super::*;
+
+    #[test]
+    fn test_display_scaling() {
+        // A 4k screen is scaled to fit 1280x800
+        let display = Display::new(0, 0, 3840, 2160);
+        assert_eq!(display.screenshot_width(), 1280);
+        assert_eq!(display.screenshot_height(), 720);
+        assert_eq!(display.to_screen(640, 360), Point { x: 1920, y: 1080 });
+        assert_eq!(
+            display.to_screenshot(Point { x: 1920, y: 1080 }),
+            (640, 360)
+        );
+
+        // Small screens are not scaled up, a second monitor is offset
+        let display = Display::new(1920, 0, 1024, 768);
+        assert_eq!(display.screenshot_width(), 1024);
+        assert_eq!(display.to_screen(10, 20), Point { x: 1930, y: 20 });
+    }
+
+    #[test]
+    fn test_schema_bounds_coordinates() {
+        let schema = tool_schema(&Display::new(0, 0, 2560, 1600));
+        let bounds = &schema["properties"]["coordinate"]["prefixItems"];
+        assert_eq!(bounds[0]["maximum"], 1279);
+        assert_eq!(bounds[1]["maximum"], 799);
+    }
+
+    #[test]
+    fn test_parse_action() {
+        let display = Display::new(0, 0, 2560, 1600);
+        let click = Action::parse(
+            &json!({"action": "double_click", "coordinate": [100, 50]}),
+            &display,
+        )
+        .unwrap();
+        assert_eq!(
+            click,
+            Action::Click {
+                button: Button::Left,
+                count: 2,
+                at: Some(Point { x: 200, y: 100 }),
+            }
+        );
+
+        let scroll = Action::parse(
+            &json!({"action": "scroll", "scroll_direction": "down", "scroll_amount": 3}),
+            &display,
+        )
+        .unwrap();
+        assert_eq!(
+            scroll,
+            Action::Scroll {
+                at: None,
+                direction: Direction::Down,
+                amount: 3,
+            }
+        );
+
+        for invalid in [
+            json!({"action": "fly"}),
+            json!({"action": "mouse_move"}),
+            json!({"action": "type"}),
+            json!({"action": "left_click", "coordinate": [1280, 0]}),
+        ] {
+            assert!(matches!(
+                Action::parse(&invalid, &display),
+                Err(ToolError::InvalidParameters(_))
+            ));
+        }
+    }
+}
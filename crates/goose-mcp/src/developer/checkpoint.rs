@@ -0,0 +1,254 @@
+//! Copies of files taken before the developer tools change them
+//!
+//! Every write or edit first stores the file as it was, or notes that it
+//! didn't exist, so any change of the agent can be reverted later, also from
+//! another process such as `goose checkpoint revert`. The store keeps the
+//! most recent `MAX_CHECKPOINTS` checkpoints.
+use anyhow::{anyhow, Context, Result};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::fs::{self, File, OpenOptions};
+use std::path::{Path, PathBuf};
+
+const MAX_CHECKPOINTS: usize = 500;
+const INDEX_FILE: &str = "index.json";
+const LOCK_FILE: &str = "index.lock";
+
+/// A file as it was before a change
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Checkpoint {
+    pub id: u64,
+    pub path: PathBuf,
+    pub created: DateTime<Utc>,
+    /// What was about to change the file, e.g. "write" or "str_replace"
+    pub action: String,
+    /// Whether the file existed, reverting a checkpoint of a new file removes it
+    pub existed: bool,
+}
+
+/// Checkpoints in a directory, an index with the copies of the files next to it
+pub struct CheckpointStore {
+    root: PathBuf,
+}
+
+impl CheckpointStore {
+    pub fn new<P: AsRef<Path>>(root: P) -> Self {
+        Self {
+            root: root.as_ref().to_path_buf(),
+        }
+    }
+
+    /// The store in GOOSE_CHECKPOINT_DIR, ~/.config/goose/checkpoints by default
+    pub fn from_env() -> Self {
+        let root = std::env::var("GOOSE_CHECKPOINT_DIR")
+            .map(PathBuf::from)
+            .unwrap_or_else(|_| {
+                dirs::home_dir()
+                    .map(|home| home.join(".config/goose/checkpoints"))
+                    .unwrap_or_else(|| PathBuf::from(".config/goose/checkpoints"))
+            });
+        Self::new(root)
+    }
+
+    fn blob(&self, id: u64) -> PathBuf {
+        self.root.join(id.to_string())
+    }
+
+    /// Take the lock on the index, held until the file is dropped
+    ///
+    /// It is an advisory lock on a file of its own, so the changes of the
+    /// index are serialized across processes as well as threads.
+    fn lock(&self) -> Result<File> {
+        fs::create_dir_all(&self.root)
+            .with_context(|| format!("Failed to create {}", self.root.display()))?;
+        let path = self.root.join(LOCK_FILE);
+        let file = OpenOptions::new()
+            .create(true)
+            .truncate(false)
+            .write(true)
+            .open(&path)
+            .with_context(|| format!("Failed to open {}", path.display()))?;
+        file.lock()
+            .with_context(|| format!("Failed to lock {}", path.display()))?;
+        Ok(file)
+    }
+
+    fn load(&self) -> Result<Vec<Checkpoint>> {
+        let index = self.root.join(INDEX_FILE);
+        if !index.exists() {
+            return Ok(Vec::new());
+        }
+        let content = fs::read_to_string(&index)
+            .with_context(|| format!("Failed to read {}", index.display()))?;
+        Ok(serde_json::from_str(&content)?)
+    }
+
+    fn save(&self, checkpoints: &[Checkpoint]) -> Result<()> {
+        // Written next to the index and renamed, so readers never see half of it
+        let partial = self.root.join(format!("{}.partial", INDEX_FILE));
+        fs::write(&partial, serde_json::to_string_pretty(checkpoints)?)?;
+        fs::rename(&partial, self.root.join(INDEX_FILE))?;
+        Ok(())
+    }
+
+    /// Store `path` as it is now, before `action` changes it
+    pub fn snapshot(&self, path: &Path, action: &str) -> Result<Checkpoint> {
+        let _lock = self.lock()?;
+        let mut checkpoints = self.load()?;
+        let checkpoint = Checkpoint {
+            id: checkpoints.last().map_or(1, |c| c.id + 1),
+            path: path.to_path_buf(),
+            created: Utc::now(),
+            action: action.to_string(),
+            existed: path.exists(),
+        };
+        if checkpoint.existed {
+            fs::copy(path, self.blob(checkpoint.id))
+                .with_context(|| format!("Failed to copy {}", path.display()))?;
+        }
+        checkpoints.push(checkpoint.clone());
+
+        let excess = checkpoints.len().saturating_sub(MAX_CHECKPOINTS);
+        for old in checkpoints.drain(..excess) {
+            let _ = fs::remove_file(self.blob(old.id));
+        }
+        self.save(&checkpoints)?;
+        Ok(checkpoint)
+    }
+
+    /// The checkpoints from oldest to newest, only those of files under `dir` when it is given
+    pub fn list(&self, dir: Option<&Path>) -> Result<Vec<Checkpoint>> {
+        // The index is replaced whole, so reading it needs no lock
+        let mut checkpoints = self.load()?;
+        if let Some(dir) = dir {
+            checkpoints.retain(|c| c.path.starts_with(dir));
+        }
+        Ok(checkpoints)
+    }
+
+    /// Put the file of checkpoint `id` back as it was when the checkpoint was taken
+    ///
+    /// The file as it is now is checkpointed first, so a revert can be
+    /// reverted too. Returns that new checkpoint.
+    pub fn revert(&self, id: u64) -> Result<Checkpoint> {
+        let checkpoint = self
+            .list(None)?
+            .into_iter()
+            .find(|c| c.id == id)
+            .ok_or_else(|| anyhow!("There is no checkpoint {}", id))?;
+        // Read before snapshotting, which may prune this very checkpoint in a full store
+        let content = checkpoint
+            .existed
+            .then(|| fs::read(self.blob(id)))
+            .transpose()
+            .with_context(|| format!("Failed to read checkpoint {}", id))?;
+        let current = self.snapshot(&checkpoint.path, &format!("revert {}", id))?;
+
+        if let Some(content) = content {
+            if let Some(parent) = checkpoint.path.parent() {
+                fs::create_dir_all(parent)?;
+            }
+            fs::write(&checkpoint.path, content)
+                .with_context(|| format!("Failed to restore {}", checkpoint.path.display()))?;
+        } else if checkpoint.path.exists() {
+            fs::remove_file(&checkpoint.path)
+                .with_context(|| format!("Failed to remove {}", checkpoint.path.display()))?;
+        }
+        Ok(current)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_snapshot_and_revert() -> Result<()> {
+        let dir = TempDir::new()?;
+        let store = CheckpointStore::new(dir.path().join("checkpoints"));
+        let file = dir.path().join("work").join("notes.txt");
+
+        let created = store.snapshot(&file, "write")?;
+        assert!(!created.existed);
+        fs::create_dir_all(file.parent().unwrap())?;
+        fs::write(&file, "first")?;
+
+        let edited = store.snapshot(&file, "str_replace")?;
+        fs::write(&file, "trashed")?;
+
+        // Back to the content before the edit
+        let undo = store.revert(edited.id)?;
+        assert_eq!(fs::read_to_string(&file)?, "first");
+
+        // The revert is a checkpoint of its own
+        store.revert(undo.id)?;
+        assert_eq!(fs::read_to_string(&file)?, "trashed");
+
+        // Before the file was written it didn't exist
+        store.revert(created.id)?;
+        assert!(!file.exists());
+
+        let ids: Vec<u64> = store.list(None)?.iter().map(|c| c.id).collect();
+        assert_eq!(ids, vec![1, 2, 3, 4, 5]);
+        assert_eq!(store.list(Some(&dir.path().join("work")))?.len(), 5);
+        assert!(store.list(Some(Path::new("/elsewhere")))?.is_empty());
+        assert!(store.revert(42).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_revert_oldest_in_full_store() -> Result<()> {
+        let dir = TempDir::new()?;
+        let store = CheckpointStore::new(dir.path().join("checkpoints"));
+        let file = dir.path().join("notes.txt");
+        fs::write(&file, "oldest")?;
+        let oldest = store.snapshot(&file, "write")?;
+        fs::write(&file, "newer")?;
+        for _ in 1..MAX_CHECKPOINTS {
+            store.snapshot(&file, "write")?;
+        }
+
+        // Taking the revert's checkpoint prunes the one being reverted
+        store.revert(oldest.id)?;
+        assert_eq!(fs::read_to_string(&file)?, "oldest");
+        assert_eq!(store.list(None)?.len(), MAX_CHECKPOINTS);
+        assert!(store.list(None)?.iter().all(|c| c.id != oldest.id));
+        Ok(())
+    }
+
+    #[test]
+    fn test_concurrent_snapshots() -> Result<()> {
+        let dir = TempDir::new()?;
+        let root = dir.path().join("checkpoints");
+        let file = dir.path().join("notes.txt");
+        fs::write(&file, "notes")?;
+
+        // Separate stores share nothing but the directory, like separate processes
+        let handles: Vec<_> = (0..8)
+            .map(|_| {
+                let (root, file) = (root.clone(), file.clone());
+                std::thread::spawn(move || {
+                    let store = CheckpointStore::new(root);
+                    (0..5)
+                        .map(|_| store.snapshot(&file, "write").map(|c| c.id))
+                        .collect::<Result<Vec<u64>>>()
+                })
+            })
+            .collect();
+        let mut taken: Vec<u64> = Vec::new();
+        for handle in handles {
+            taken.extend(handle.join().unwrap()?);
+        }
+        taken.sort();
+
+        let listed: Vec<u64> = CheckpointStore::new(&root)
+            .list(None)?
+            .iter()
+            .map(|c| c.id)
+            .collect();
+        assert_eq!(taken, (1..=40).collect::<Vec<u64>>());
+        assert_eq!(listed, taken);
+        Ok(())
+    }
+}
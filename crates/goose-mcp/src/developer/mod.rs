@@ -1,5 +1,8 @@
+mod checkpoint;
 mod lang;
 
+pub use checkpoint::{Checkpoint, CheckpointStore};
+
 use anyhow::Result;
 use base64::Engine;
 use indoc::formatdoc;
@@ -34,6 +37,7 @@ use xcap::{Monitor, Window};
 pub struct DeveloperRouter {
     tools: Vec<Tool>,
     file_history: Arc<Mutex<HashMap<PathBuf, Vec<String>>>>,
+    checkpoints: Arc<CheckpointStore>,
     instructions: String,
 }
 
@@ -195,6 +199,7 @@ impl DeveloperRouter {
                 screen_capture_tool,
             ],
             file_history: Arc::new(Mutex::new(HashMap::new())),
+            checkpoints: Arc::new(CheckpointStore::from_env()),
             instructions,
         }
     }
@@ -393,6 +398,8 @@ impl DeveloperRouter {
         path: &PathBuf,
         file_text: &str,
     ) -> Result<Vec<Content>, ToolError> {
+        self.checkpoint(path, "write")?;
+
         // Write to the file
         std::fs::write(path, file_text)
             .map_err(|e| ToolError::ExecutionError(format!("Failed to write file: {}", e)))?;
@@ -453,6 +460,7 @@ impl DeveloperRouter {
 
         // Save history for undo
         self.save_file_history(path)?;
+        self.checkpoint(path, "str_replace")?;
 
         // Replace and write back
         let new_content = content.replace(old_str, new_str);
@@ -546,6 +554,18 @@ impl DeveloperRouter {
         Ok(())
     }
 
+    // Keep the file as it is, so the change can be reverted after the session
+    fn checkpoint(&self, path: &Path, action: &str) -> Result<(), ToolError> {
+        self.checkpoints.snapshot(path, action).map_err(|e| {
+            ToolError::ExecutionError(format!(
+                "Failed to checkpoint {} before changing it: {}",
+                path.display(),
+                e
+            ))
+        })?;
+        Ok(())
+    }
+
     async fn list_windows(&self, _params: Value) -> Result<Vec<Content>, ToolError> {
         let windows = Window::all()
             .map_err(|_| ToolError::ExecutionError("Failed to list windows".into()))?;
@@ -688,6 +708,7 @@ impl Clone for DeveloperRouter {
         Self {
             tools: self.tools.clone(),
             file_history: Arc::clone(&self.file_history),
+            checkpoints: Arc::clone(&self.checkpoints),
             instructions: self.instructions.clone(),
         }
     }
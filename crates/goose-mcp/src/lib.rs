@@ -1,11 +1,13 @@
 mod computercontroller;
+mod computeruse;
 mod developer;
 mod google_drive;
 mod jetbrains;
 mod memory;
 
 pub use computercontroller::ComputerControllerRouter;
-pub use developer::DeveloperRouter;
+pub use computeruse::ComputerUseRouter;
+pub use developer::{Checkpoint, CheckpointStore, DeveloperRouter};
 pub use google_drive::GoogleDriveRouter;
 pub use jetbrains::JetBrainsRouter;
 pub use memory::MemoryRouter;
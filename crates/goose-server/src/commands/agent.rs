@@ -24,11 +24,25 @@ pub async fn run() -> Result<()> {
         .allow_methods(Any)
         .allow_headers(Any);
 
-    let app = crate::routes::configure(state).layer(cors);
+    let app = crate::routes::configure(state.clone());
+    #[cfg(feature = "otel")]
+    let app = if settings.metrics {
+        app.merge(crate::routes::metrics::routes(state))
+    } else {
+        app
+    };
+    #[cfg(not(feature = "otel"))]
+    if settings.metrics {
+        tracing::warn!("GOOSE_METRICS is set, but /metrics needs a build with the otel feature");
+    }
+    let app = app.layer(cors);
 
     // Run server
     let listener = tokio::net::TcpListener::bind(settings.socket_addr()).await?;
     info!("listening on {}", listener.local_addr()?);
-    axum::serve(listener, app).await?;
+    let result = axum::serve(listener, app).await;
+    #[cfg(feature = "otel")]
+    goose::tracing::otlp::shutdown();
+    result?;
     Ok(())
 }
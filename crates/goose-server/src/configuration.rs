@@ -9,6 +9,9 @@ pub struct Settings {
     pub host: String,
     #[serde(default = "default_port")]
     pub port: u16,
+    /// Serve usage metrics on /metrics behind the secret key, set with GOOSE_METRICS
+    #[serde(default)]
+    pub metrics: bool,
 }
 
 impl Settings {
@@ -83,6 +86,7 @@ mod tests {
         let server_settings = Settings {
             host: "127.0.0.1".to_string(),
             port: 3000,
+            metrics: false,
         };
         let addr = server_settings.socket_addr();
         assert_eq!(addr.to_string(), "127.0.0.1:3000");
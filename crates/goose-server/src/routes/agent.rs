@@ -97,7 +97,10 @@ async fn create_agent(
         .version
         .unwrap_or_else(|| AgentFactory::default_version().to_string());
 
-    let new_agent = AgentFactory::create(&version, provider).expect("Failed to create agent");
+    let mut new_agent = AgentFactory::create(&version, provider).expect("Failed to create agent");
+    new_agent
+        .set_device_code_prompt(state.device_code_prompt())
+        .await;
 
     let mut agent = state.agent.lock().await;
     *agent = Some(new_agent);
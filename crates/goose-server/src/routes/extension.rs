@@ -1,9 +1,16 @@
 use std::collections::HashMap;
 
-use crate::state::AppState;
-use axum::{extract::State, routing::post, Json, Router};
+use crate::state::{AppState, DeviceCode};
+use axum::{
+    extract::State,
+    routing::{get, post},
+    Json, Router,
+};
 use goose::{
-    agents::{extension::Envs, ExtensionConfig},
+    agents::{
+        extension::{Envs, HttpAuthConfig, Sandbox},
+        ExtensionConfig,
+    },
     config::Config,
 };
 use http::{HeaderMap, StatusCode};
@@ -37,6 +44,23 @@ enum ExtensionConfigRequest {
         /// List of environment variable keys. The server will fetch their values from the keyring.
         #[serde(default)]
         env_keys: Vec<String>,
+        /// Working directory, environment allow-list and network restriction of the process.
+        #[serde(default)]
+        sandbox: Sandbox,
+    },
+    /// Remote extension reached over Streamable HTTP.
+    #[serde(rename = "streamable_http")]
+    StreamableHttp {
+        /// The name to identify this extension
+        name: String,
+        /// The URI of the MCP endpoint.
+        uri: String,
+        /// Extra headers sent with every request.
+        #[serde(default)]
+        headers: HashMap<String, String>,
+        /// How the server is authorized, tokens are kept in the keyring.
+        #[serde(default)]
+        auth: HttpAuthConfig,
     },
     /// Built-in extension that is part of the goose binary.
     #[serde(rename = "builtin")]
@@ -118,6 +142,7 @@ async fn add_extension(
             cmd,
             args,
             env_keys,
+            sandbox,
         } => {
             let mut env_map = HashMap::new();
             for key in env_keys {
@@ -146,15 +171,30 @@ async fn add_extension(
                 cmd,
                 args,
                 envs: Envs::new(env_map),
+                sandbox,
             }
         }
+        ExtensionConfigRequest::StreamableHttp {
+            name,
+            uri,
+            headers,
+            auth,
+        } => ExtensionConfig::StreamableHttp {
+            name,
+            uri,
+            headers,
+            auth,
+        },
         ExtensionConfigRequest::Builtin { name } => ExtensionConfig::Builtin { name },
     };
 
     // Acquire a lock on the agent and attempt to add the extension.
+    let name = extension_config.name().to_string();
     let mut agent = state.agent.lock().await;
     let agent = agent.as_mut().ok_or(StatusCode::PRECONDITION_REQUIRED)?;
     let response = agent.add_extension(extension_config).await;
+    // The code is of no use once the extension connected or gave up
+    state.device_codes.lock().unwrap().remove(&name);
 
     // Respond with the result.
     match response {
@@ -202,10 +242,91 @@ async fn remove_extension(
     }))
 }
 
+/// Handler for the codes of the extensions waiting to be connected
+///
+/// An OAuth extension connects once the user enters its code, so the client
+/// polls this while adding one.
+async fn device_codes(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+) -> Result<Json<Vec<DeviceCode>>, StatusCode> {
+    // Verify the presence and validity of the secret key
+    let secret_key = headers
+        .get("X-Secret-Key")
+        .and_then(|value| value.to_str().ok())
+        .ok_or(StatusCode::UNAUTHORIZED)?;
+
+    if secret_key != state.secret_key {
+        return Err(StatusCode::UNAUTHORIZED);
+    }
+
+    let mut codes: Vec<DeviceCode> = state
+        .device_codes
+        .lock()
+        .unwrap()
+        .values()
+        .cloned()
+        .collect();
+    codes.sort_by(|a, b| a.extension.cmp(&b.extension));
+    Ok(Json(codes))
+}
+
 /// Registers the extension management routes with the Axum router.
 pub fn routes(state: AppState) -> Router {
     Router::new()
         .route("/extensions/add", post(add_extension))
         .route("/extensions/remove", post(remove_extension))
+        .route("/extensions/device_codes", get(device_codes))
         .with_state(state)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::body::Body;
+    use axum::http::Request;
+    use tower::ServiceExt;
+
+    async fn get_device_codes(state: AppState, secret_key: &str) -> (StatusCode, String) {
+        let request = Request::get("/extensions/device_codes")
+            .header("X-Secret-Key", secret_key)
+            .body(Body::empty())
+            .unwrap();
+        let response = routes(state).oneshot(request).await.unwrap();
+        let status = response.status();
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        (status, String::from_utf8(body.to_vec()).unwrap())
+    }
+
+    #[tokio::test]
+    async fn test_device_codes() {
+        let state = AppState::new("test-secret".to_string()).await.unwrap();
+        let authorization = serde_json::from_value(serde_json::json!({
+            "device_code": "device",
+            "user_code": "ABCD-1234",
+            "verification_uri": "https://example.com/device",
+            "expires_in": 600
+        }))
+        .unwrap();
+        let prompt = state.device_code_prompt();
+        prompt("tracker", &authorization);
+
+        let (status, body) = get_device_codes(state.clone(), "test-secret").await;
+        assert_eq!(status, StatusCode::OK);
+        let codes: serde_json::Value = serde_json::from_str(&body).unwrap();
+        assert_eq!(
+            codes,
+            serde_json::json!([{
+                "extension": "tracker",
+                "user_code": "ABCD-1234",
+                "verification_uri": "https://example.com/device",
+                "expires_in": 600
+            }])
+        );
+
+        let (status, _) = get_device_codes(state, "wrong").await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+    }
+}
@@ -0,0 +1,84 @@
+use crate::state::AppState;
+use axum::{
+    extract::State,
+    http::{header, HeaderMap, StatusCode},
+    response::IntoResponse,
+    routing::get,
+    Router,
+};
+
+/// Usage metrics in the Prometheus text format, see `goose::tracing::metrics`
+async fn metrics(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+) -> Result<impl IntoResponse, StatusCode> {
+    // Verify secret key
+    let secret_key = headers
+        .get("X-Secret-Key")
+        .and_then(|value| value.to_str().ok())
+        .ok_or(StatusCode::UNAUTHORIZED)?;
+
+    if secret_key != state.secret_key {
+        return Err(StatusCode::UNAUTHORIZED);
+    }
+
+    Ok((
+        [(header::CONTENT_TYPE, "text/plain; version=0.0.4")],
+        goose::tracing::metrics::render(),
+    ))
+}
+
+/// Configure the metrics route, enabled with GOOSE_METRICS
+pub fn routes(state: AppState) -> Router {
+    Router::new()
+        .route("/metrics", get(metrics))
+        .with_state(state)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::body::Body;
+    use axum::http::Request;
+    use std::sync::Arc;
+    use tokio::sync::Mutex;
+    use tower::ServiceExt;
+
+    async fn get_metrics(secret_key: Option<&str>) -> axum::response::Response {
+        let app = routes(AppState {
+            agent: Arc::new(Mutex::new(None)),
+            secret_key: "test-secret".to_string(),
+            device_codes: Default::default(),
+        });
+        let mut request = Request::get("/metrics");
+        if let Some(secret_key) = secret_key {
+            request = request.header("X-Secret-Key", secret_key);
+        }
+        app.oneshot(request.body(Body::empty()).unwrap())
+            .await
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_metrics_route() {
+        let response = get_metrics(Some("test-secret")).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(
+            response.headers()[header::CONTENT_TYPE],
+            "text/plain; version=0.0.4"
+        );
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let text = String::from_utf8(body.to_vec()).unwrap();
+        assert!(text.contains("# TYPE goose_completions_total counter"));
+    }
+
+    #[tokio::test]
+    async fn test_metrics_need_the_secret_key() {
+        for secret_key in [None, Some("wrong")] {
+            let response = get_metrics(secret_key).await;
+            assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+        }
+    }
+}
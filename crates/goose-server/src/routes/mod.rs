@@ -2,6 +2,9 @@
 pub mod agent;
 pub mod extension;
 pub mod health;
+#[cfg(feature = "otel")]
+pub mod metrics;
+pub mod openai;
 pub mod reply;
 pub mod secrets;
 
@@ -14,5 +17,6 @@ pub fn configure(state: crate::state::AppState) -> Router {
         .merge(reply::routes(state.clone()))
         .merge(agent::routes(state.clone()))
         .merge(extension::routes(state.clone()))
+        .merge(openai::routes(state.clone()))
         .merge(secrets::routes(state))
 }
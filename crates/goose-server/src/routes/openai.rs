@@ -0,0 +1,484 @@
+use crate::state::AppState;
+use axum::{
+    extract::State,
+    http::{HeaderMap, StatusCode},
+    response::{
+        sse::{Event, Sse},
+        IntoResponse, Response,
+    },
+    routing::{get, post},
+    Json, Router,
+};
+use futures::stream::StreamExt;
+use goose::agents::Agent;
+use goose::message::{Message, MessageContent};
+use goose::providers::base::ProviderUsage;
+use mcp_core::role::Role;
+use serde::Deserialize;
+use serde_json::{json, Value};
+use std::convert::Infallible;
+use std::sync::{Arc, Mutex};
+use tokio::sync::mpsc;
+use tokio_stream::wrappers::UnboundedReceiverStream;
+
+/// The model name reported when the client doesn't send one
+const DEFAULT_MODEL: &str = "goose";
+
+// Types matching the OpenAI chat completions request
+#[derive(Debug, Deserialize)]
+struct ChatCompletionRequest {
+    #[serde(default)]
+    model: Option<String>,
+    messages: Vec<ChatMessage>,
+    #[serde(default)]
+    stream: bool,
+}
+
+#[derive(Debug, Deserialize)]
+struct ChatMessage {
+    role: String,
+    #[serde(default)]
+    content: Value,
+}
+
+/// An error in the shape OpenAI clients know how to display
+fn error(status: StatusCode, message: &str, kind: &str) -> Response {
+    let body = json!({"error": {"message": message, "type": kind, "code": Value::Null}});
+    (status, Json(body)).into_response()
+}
+
+/// Accept the secret as `X-Secret-Key` or, as OpenAI clients send their API key, as a bearer token
+fn authorized(headers: &HeaderMap, secret_key: &str) -> bool {
+    let header = |name: &str| headers.get(name).and_then(|value| value.to_str().ok());
+    header("X-Secret-Key") == Some(secret_key)
+        || header("Authorization").and_then(|value| value.strip_prefix("Bearer "))
+            == Some(secret_key)
+}
+
+/// The text of a message's content, a string or an array of content parts
+fn content_text(content: &Value) -> String {
+    match content {
+        Value::String(text) => text.clone(),
+        Value::Array(parts) => parts
+            .iter()
+            .filter_map(|part| part.get("text").and_then(Value::as_str))
+            .collect::<Vec<_>>()
+            .join("\n"),
+        _ => String::new(),
+    }
+}
+
+/// Convert the conversation to goose messages
+///
+/// goose runs its own tools, so tool calls and tool results from the client
+/// are left out. System messages are passed on as user text, since the agent
+/// has its own system prompt.
+fn convert_messages(incoming: Vec<ChatMessage>) -> Vec<Message> {
+    let mut messages = Vec::new();
+    for message in incoming {
+        let text = content_text(&message.content);
+        if text.is_empty() {
+            continue;
+        }
+        match message.role.as_str() {
+            "user" | "system" | "developer" => messages.push(Message::user().with_text(text)),
+            "assistant" => messages.push(Message::assistant().with_text(text)),
+            _ => tracing::warn!("Skipping a message with role: {}", message.role),
+        }
+    }
+    messages
+}
+
+/// The text an assistant message adds to the completion
+fn assistant_text(message: &Message) -> Option<String> {
+    if message.role != Role::Assistant {
+        return None;
+    }
+    let text: Vec<&str> = message
+        .content
+        .iter()
+        .filter_map(|content| match content {
+            MessageContent::Text(text) => Some(text.text.as_str()),
+            MessageContent::Refusal(refusal) => Some(refusal.refusal.as_str()),
+            _ => None,
+        })
+        .collect();
+    (!text.is_empty()).then(|| text.join("\n"))
+}
+
+/// Total input and output tokens of the agent so far
+async fn token_totals(agent: &dyn Agent) -> (i64, i64) {
+    let usage: Vec<ProviderUsage> = agent.usage().await;
+    usage.iter().fold((0, 0), |(input, output), usage| {
+        (
+            input + i64::from(usage.usage.input_tokens.unwrap_or(0)),
+            output + i64::from(usage.usage.output_tokens.unwrap_or(0)),
+        )
+    })
+}
+
+fn usage_json(before: (i64, i64), after: (i64, i64)) -> Value {
+    let (prompt, completion) = (after.0 - before.0, after.1 - before.1);
+    json!({
+        "prompt_tokens": prompt,
+        "completion_tokens": completion,
+        "total_tokens": prompt + completion,
+    })
+}
+
+fn completion_id() -> String {
+    format!(
+        "chatcmpl-{}",
+        chrono::Utc::now().timestamp_nanos_opt().unwrap_or_default()
+    )
+}
+
+fn chunk(id: &str, created: i64, model: &str, delta: Value, finish_reason: Value) -> Event {
+    let chunk = json!({
+        "id": id,
+        "object": "chat.completion.chunk",
+        "created": created,
+        "model": model,
+        "choices": [{"index": 0, "delta": delta, "finish_reason": finish_reason}],
+    });
+    Event::default().data(chunk.to_string())
+}
+
+/// Answer a chat completion request with a full goose agent, tools included
+async fn chat_completions(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Json(request): Json<ChatCompletionRequest>,
+) -> Response {
+    if !authorized(&headers, &state.secret_key) {
+        return error(
+            StatusCode::UNAUTHORIZED,
+            "Invalid secret key",
+            "invalid_request_error",
+        );
+    }
+
+    let messages = convert_messages(request.messages);
+    if messages.is_empty() {
+        return error(
+            StatusCode::BAD_REQUEST,
+            "The request has no messages with text",
+            "invalid_request_error",
+        );
+    }
+    let model = request.model.unwrap_or_else(|| DEFAULT_MODEL.to_string());
+    let id = completion_id();
+    let created = chrono::Utc::now().timestamp();
+
+    if request.stream {
+        return stream_completion(state, messages, id, created, model).into_response();
+    }
+
+    let agent = state.agent.lock().await;
+    let Some(agent) = agent.as_ref() else {
+        return error(
+            StatusCode::SERVICE_UNAVAILABLE,
+            "No agent configured",
+            "server_error",
+        );
+    };
+    let before = token_totals(agent.as_ref()).await;
+
+    let mut stream = match agent.reply(&messages).await {
+        Ok(stream) => stream,
+        Err(e) => {
+            tracing::error!("Failed to start reply stream: {}", e);
+            return error(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                &e.to_string(),
+                "server_error",
+            );
+        }
+    };
+    let mut content = Vec::new();
+    while let Some(response) = stream.next().await {
+        match response {
+            Ok(message) => content.extend(assistant_text(&message)),
+            Err(e) => {
+                tracing::error!("Error processing message: {}", e);
+                return error(
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    &e.to_string(),
+                    "server_error",
+                );
+            }
+        }
+    }
+    drop(stream);
+    let after = token_totals(agent.as_ref()).await;
+
+    Json(json!({
+        "id": id,
+        "object": "chat.completion",
+        "created": created,
+        "model": model,
+        "choices": [{
+            "index": 0,
+            "message": {"role": "assistant", "content": content.join("\n")},
+            "finish_reason": "stop",
+        }],
+        "usage": usage_json(before, after),
+    }))
+    .into_response()
+}
+
+/// The error event of a stream, in the shape OpenAI clients raise as an error
+fn error_event(message: &str) -> Event {
+    let body = json!({"error": {"message": message, "type": "server_error", "code": Value::Null}});
+    Event::default().data(body.to_string())
+}
+
+/// What the client was sent of a streamed completion so far
+#[derive(Default)]
+struct Sent {
+    /// Whether any text was sent
+    text: bool,
+    /// Whether the text of the response being generated was sent as it arrived
+    streamed: bool,
+}
+
+/// Stream the completion as server-sent chunks, with the text of each response as it arrives
+///
+/// Responses the agent can't stream, such as those its moderators rewrite,
+/// are sent whole once they are complete. An error ends the stream with an
+/// error event instead of a finish chunk.
+fn stream_completion(
+    state: AppState,
+    messages: Vec<Message>,
+    id: String,
+    created: i64,
+    model: String,
+) -> Sse<impl futures::Stream<Item = Result<Event, Infallible>>> {
+    let (tx, rx) = mpsc::unbounded_channel();
+
+    tokio::spawn(async move {
+        let mut agent = state.agent.lock().await;
+        let send = |event: Event| tx.send(Ok(event)).is_ok();
+
+        let Some(agent) = agent.as_mut() else {
+            send(error_event("No agent configured"));
+            send(Event::default().data("[DONE]"));
+            return;
+        };
+
+        send(chunk(
+            &id,
+            created,
+            &model,
+            json!({"role": "assistant", "content": ""}),
+            Value::Null,
+        ));
+
+        let sent = Arc::new(Mutex::new(Sent::default()));
+        // Separate the texts of consecutive messages like the full response does
+        let text_chunk = {
+            let (id, model) = (id.clone(), model.clone());
+            move |sent: &mut Sent, text: &str| {
+                let text = if sent.text && !sent.streamed {
+                    format!("\n{}", text)
+                } else {
+                    text.to_string()
+                };
+                sent.text = true;
+                chunk(&id, created, &model, json!({"content": text}), Value::Null)
+            }
+        };
+        agent
+            .set_text_deltas(Some({
+                let (tx, sent, text_chunk) = (tx.clone(), sent.clone(), text_chunk.clone());
+                Arc::new(move |text: &str| {
+                    if text.is_empty() {
+                        return;
+                    }
+                    let mut sent = sent.lock().unwrap();
+                    let event = text_chunk(&mut sent, text);
+                    sent.streamed = true;
+                    let _ = tx.send(Ok(event));
+                })
+            }))
+            .await;
+
+        let outcome = match agent.reply(&messages).await {
+            Ok(mut stream) => loop {
+                match stream.next().await {
+                    Some(Ok(message)) => {
+                        let mut sent = sent.lock().unwrap();
+                        if std::mem::take(&mut sent.streamed) {
+                            continue;
+                        }
+                        if let Some(text) = assistant_text(&message) {
+                            send(text_chunk(&mut sent, &text));
+                        }
+                        if tx.is_closed() {
+                            // The client went away, stop the agent
+                            break Ok(());
+                        }
+                    }
+                    Some(Err(e)) => {
+                        tracing::error!("Error processing message: {}", e);
+                        break Err(e.to_string());
+                    }
+                    None => break Ok(()),
+                }
+            },
+            Err(e) => {
+                tracing::error!("Failed to start reply stream: {}", e);
+                Err(e.to_string())
+            }
+        };
+        agent.set_text_deltas(None).await;
+
+        match outcome {
+            Ok(()) => send(chunk(&id, created, &model, json!({}), json!("stop"))),
+            Err(e) => send(error_event(&e)),
+        };
+        send(Event::default().data("[DONE]"));
+    });
+
+    Sse::new(UnboundedReceiverStream::new(rx))
+}
+
+/// List goose as the only model, for clients that check the model list first
+async fn models(State(state): State<AppState>, headers: HeaderMap) -> Response {
+    if !authorized(&headers, &state.secret_key) {
+        return error(
+            StatusCode::UNAUTHORIZED,
+            "Invalid secret key",
+            "invalid_request_error",
+        );
+    }
+    Json(json!({
+        "object": "list",
+        "data": [{"id": DEFAULT_MODEL, "object": "model", "created": 0, "owned_by": "goose"}],
+    }))
+    .into_response()
+}
+
+// Configure routes for this module
+pub fn routes(state: AppState) -> Router {
+    Router::new()
+        .route("/v1/chat/completions", post(chat_completions))
+        .route("/v1/models", get(models))
+        .with_state(state)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{body::Body, http::Request};
+    use goose::{
+        agents::AgentFactory,
+        providers::{base::Usage, errors::ProviderError, testing::TestProvider},
+    };
+    use std::sync::Arc;
+    use tokio::sync::Mutex;
+    use tower::ServiceExt;
+
+    fn app() -> Router {
+        let provider = TestProvider::new("test-model")
+            .with_usage("mock", Usage::new(Some(7), Some(3), Some(10)))
+            .with_reply(|request| {
+                let text = format!("{} messages", request.messages.len());
+                Ok(Message::assistant().with_text(text))
+            });
+        app_with(provider)
+    }
+
+    fn app_with(provider: TestProvider) -> Router {
+        let agent = AgentFactory::create("reference", Box::new(provider)).unwrap();
+        routes(AppState {
+            agent: Arc::new(Mutex::new(Some(agent))),
+            secret_key: "test-secret".to_string(),
+            device_codes: Default::default(),
+        })
+    }
+
+    async fn post(app: Router, auth: &str, body: Value) -> (StatusCode, String) {
+        let request = Request::builder()
+            .uri("/v1/chat/completions")
+            .method("POST")
+            .header("content-type", "application/json")
+            .header("authorization", auth)
+            .body(Body::from(body.to_string()))
+            .unwrap();
+        let response = app.oneshot(request).await.unwrap();
+        let status = response.status();
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        (status, String::from_utf8(body.to_vec()).unwrap())
+    }
+
+    fn conversation(stream: bool) -> Value {
+        json!({
+            "model": "goose",
+            "stream": stream,
+            "messages": [
+                {"role": "system", "content": "Be brief"},
+                {"role": "user", "content": [{"type": "text", "text": "Hello"}]},
+            ],
+        })
+    }
+
+    #[tokio::test]
+    async fn test_chat_completion() {
+        let (status, body) = post(app(), "Bearer test-secret", conversation(false)).await;
+        assert_eq!(status, StatusCode::OK);
+        let body: Value = serde_json::from_str(&body).unwrap();
+        assert_eq!(body["object"], "chat.completion");
+        assert_eq!(body["choices"][0]["message"]["content"], "2 messages");
+        assert_eq!(body["usage"]["total_tokens"], 10);
+
+        let (status, body) = post(app(), "Bearer wrong", conversation(false)).await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+        assert!(body.contains("invalid_request_error"));
+    }
+
+    /// The JSON events of a streamed completion, checking it ends with [DONE]
+    fn stream_chunks(body: &str) -> Vec<Value> {
+        let events: Vec<&str> = body
+            .lines()
+            .filter_map(|line| line.strip_prefix("data: "))
+            .collect();
+        assert_eq!(events.last(), Some(&"[DONE]"));
+        events[..events.len() - 1]
+            .iter()
+            .map(|event| serde_json::from_str(event).unwrap())
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn test_streamed_chat_completion() {
+        let (status, body) = post(app(), "Bearer test-secret", conversation(true)).await;
+        assert_eq!(status, StatusCode::OK);
+        let chunks = stream_chunks(&body);
+        assert_eq!(chunks.len(), 3);
+        assert_eq!(chunks[0]["choices"][0]["delta"]["role"], "assistant");
+        assert_eq!(chunks[1]["choices"][0]["delta"]["content"], "2 messages");
+        assert_eq!(chunks[2]["choices"][0]["finish_reason"], "stop");
+    }
+
+    #[tokio::test]
+    async fn test_streamed_chat_completion_error() {
+        let provider = TestProvider::new("test-model")
+            .with_error(|| ProviderError::ServerError("overloaded".to_string()));
+        let (status, body) =
+            post(app_with(provider), "Bearer test-secret", conversation(true)).await;
+        assert_eq!(status, StatusCode::OK);
+        let chunks = stream_chunks(&body);
+        let error = chunks.last().unwrap();
+        assert!(error["error"]["message"]
+            .as_str()
+            .unwrap()
+            .contains("overloaded"));
+        assert_eq!(error["error"]["type"], "server_error");
+        assert!(chunks
+            .iter()
+            .all(|chunk| chunk["choices"][0]["finish_reason"].is_null()));
+    }
+}
@@ -8,9 +8,12 @@ use axum::{
 };
 use bytes::Bytes;
 use futures::{stream::StreamExt, Stream};
-use goose::message::{Message, MessageContent};
+use goose::message::{Message, MessageContent, RefusalContent};
 
-use mcp_core::{content::Content, role::Role};
+use mcp_core::{
+    content::{Content, TextContent},
+    role::Role,
+};
 use serde::Deserialize;
 use serde_json::{json, Value};
 use std::{
@@ -240,14 +243,17 @@ async fn stream_message(
                             }
                         }
                     }
-                    MessageContent::Text(text) => {
-                        for line in text.text.lines() {
+                    MessageContent::Text(TextContent { text, .. })
+                    | MessageContent::Refusal(RefusalContent { refusal: text }) => {
+                        for line in text.lines() {
                             let modified_line = format!("{}\n", line);
                             tx.send(ProtocolFormatter::format_text(&modified_line))
                                 .await?;
                         }
                     }
-                    MessageContent::Image(_) => {
+                    MessageContent::Image(_)
+                    | MessageContent::Audio(_)
+                    | MessageContent::Thinking(_) => {
                         // TODO
                         continue;
                     }
@@ -438,44 +444,7 @@ pub fn routes(state: AppState) -> Router {
 #[cfg(test)]
 mod tests {
     use super::*;
-    use goose::{
-        agents::AgentFactory,
-        model::ModelConfig,
-        providers::{
-            base::{Provider, ProviderUsage, Usage},
-            errors::ProviderError,
-        },
-    };
-    use mcp_core::tool::Tool;
-
-    // Mock Provider implementation for testing
-    #[derive(Clone)]
-    struct MockProvider {
-        model_config: ModelConfig,
-    }
-
-    #[async_trait::async_trait]
-    impl Provider for MockProvider {
-        fn metadata() -> goose::providers::base::ProviderMetadata {
-            goose::providers::base::ProviderMetadata::empty()
-        }
-
-        fn get_model_config(&self) -> ModelConfig {
-            self.model_config.clone()
-        }
-
-        async fn complete(
-            &self,
-            _system: &str,
-            _messages: &[Message],
-            _tools: &[Tool],
-        ) -> anyhow::Result<(Message, ProviderUsage), ProviderError> {
-            Ok((
-                Message::assistant().with_text("Mock response"),
-                ProviderUsage::new("mock".to_string(), Usage::default()),
-            ))
-        }
-    }
+    use goose::{agents::AgentFactory, providers::testing::TestProvider};
 
     #[test]
     fn test_convert_messages_user_only() {
@@ -567,14 +536,13 @@ mod tests {
         #[tokio::test]
         async fn test_ask_endpoint() {
             // Create a mock app state with mock provider
-            let mock_model_config = ModelConfig::new("test-model".to_string());
-            let mock_provider = Box::new(MockProvider {
-                model_config: mock_model_config,
-            });
+            let mock_provider =
+                Box::new(TestProvider::new("test-model").with_text("Mock response"));
             let agent = AgentFactory::create("reference", mock_provider).unwrap();
             let state = AppState {
                 agent: Arc::new(Mutex::new(Some(agent))),
                 secret_key: "test-secret".to_string(),
+                device_codes: Default::default(),
             };
 
             // Build router
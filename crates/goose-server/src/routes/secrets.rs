@@ -36,8 +36,9 @@ async fn store_secret(
     }
 
     let config = Config::global();
+    // Rotated so that running sessions pick up a new provider key
     let result = if request.is_secret {
-        config.set_secret(&request.key, Value::String(request.value))
+        config.rotate_secret(&request.key, Value::String(request.value))
     } else {
         config.set(&request.key, Value::String(request.value))
     };
@@ -1,14 +1,31 @@
 use anyhow::Result;
+use goose::agents::extension::DeviceCodePrompt;
 use goose::agents::Agent;
+use serde::Serialize;
+use std::collections::HashMap;
 use std::sync::Arc;
 use tokio::sync::Mutex;
 
+/// A code the user enters to connect an extension over OAuth
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct DeviceCode {
+    pub extension: String,
+    pub user_code: String,
+    /// The page to enter the code on, with the code filled in when the server offers it
+    pub verification_uri: String,
+    pub expires_in: u64,
+}
+
 /// Shared application state
 #[allow(dead_code)]
 #[derive(Clone)]
 pub struct AppState {
     pub agent: Arc<Mutex<Option<Box<dyn Agent>>>>,
     pub secret_key: String,
+    /// The codes of the extensions waiting to be connected, by extension name
+    ///
+    /// Kept apart from the agent, which stays locked while an extension connects.
+    pub device_codes: Arc<std::sync::Mutex<HashMap<String, DeviceCode>>>,
 }
 
 impl AppState {
@@ -16,6 +33,27 @@ impl AppState {
         Ok(Self {
             agent: Arc::new(Mutex::new(None)),
             secret_key,
+            device_codes: Arc::default(),
+        })
+    }
+
+    /// Keeps the codes of device flows for the client to show, there is no terminal to prompt in
+    pub fn device_code_prompt(&self) -> DeviceCodePrompt {
+        let device_codes = self.device_codes.clone();
+        Arc::new(move |extension, authorization| {
+            let code = DeviceCode {
+                extension: extension.to_string(),
+                user_code: authorization.user_code.clone(),
+                verification_uri: authorization
+                    .verification_uri_complete
+                    .clone()
+                    .unwrap_or_else(|| authorization.verification_uri.clone()),
+                expires_in: authorization.expires_in,
+            };
+            device_codes
+                .lock()
+                .unwrap()
+                .insert(extension.to_string(), code);
         })
     }
 }
@@ -2,10 +2,17 @@ use anyhow::Result;
 use async_trait::async_trait;
 use futures::stream::BoxStream;
 use serde_json::Value;
+use std::sync::Arc;
 
-use super::extension::{ExtensionConfig, ExtensionResult};
+use super::capabilities::{TextDeltas, ToolApproval, ToolPolicies};
+use super::extension::{DeviceCodePrompt, ExtensionConfig, ExtensionResult};
+use super::moderation::Moderator;
+use super::run::{self, RunLimits, RunResult};
+use super::safeguard::{SafeguardPause, SafeguardTrip};
 use crate::message::Message;
+use crate::plan::Plan;
 use crate::providers::base::ProviderUsage;
+use crate::recipe::Recipe;
 
 /// Core trait defining the behavior of an Agent
 #[async_trait]
@@ -13,6 +20,9 @@ pub trait Agent: Send + Sync {
     /// Create a stream that yields each message as it's generated by the agent
     async fn reply(&self, messages: &[Message]) -> Result<BoxStream<'_, Result<Message>>>;
 
+    /// Propose the steps for the latest request in `messages`, without running any tool
+    async fn plan(&self, messages: &[Message]) -> Result<Plan>;
+
     /// Add a new MCP client to the agent
     async fn add_extension(&mut self, config: ExtensionConfig) -> ExtensionResult<()>;
 
@@ -26,6 +36,53 @@ pub trait Agent: Send + Sync {
     /// Pass through a JSON-RPC request to a specific extension
     async fn passthrough(&self, extension: &str, request: Value) -> ExtensionResult<Value>;
 
+    /// Show the codes that connect extensions with OAuth with `prompt`, they are logged either way
+    async fn set_device_code_prompt(&mut self, prompt: DeviceCodePrompt);
+
+    /// Ask `approval` before running tools whose permission policy is to always ask
+    async fn set_tool_approval(&mut self, approval: ToolApproval);
+
+    /// Send the text of the model's responses to `deltas` as it arrives, or stop with None
+    ///
+    /// Replies still yield the complete messages. The text is not streamed
+    /// while a moderator runs, since it may rewrite the response.
+    async fn set_text_deltas(&mut self, deltas: Option<TextDeltas>);
+
+    /// The tool permissions of the running extensions, as the agent applies them
+    async fn tool_policies(&self) -> ToolPolicies;
+
+    /// Ask `pause` how to go on when the model calls tools too often for one request
+    async fn set_safeguard_pause(&mut self, pause: SafeguardPause);
+
+    /// The safeguard the last reply stopped at, None when it didn't stop at one
+    async fn safeguard_trip(&self) -> Option<SafeguardTrip>;
+
+    /// Count toward the safeguards across replies while `held`, instead of from 0 in each
+    async fn hold_safeguards(&self, held: bool);
+
+    /// Add instructions to the end of the system prompt, such as those of a recipe
+    async fn extend_system_prompt(&mut self, instructions: String);
+
+    /// Check and rewrite every request to the model and its responses with `moderator`
+    async fn add_moderator(&mut self, moderator: Arc<dyn Moderator>);
+
     /// Get the total usage of the agent
     async fn usage(&self) -> Vec<ProviderUsage>;
+
+    /// Keep what later sessions in this project should know from `messages`
+    ///
+    /// Returns how many new facts were kept, agents without memory keep none.
+    async fn remember(&self, _messages: &[Message]) -> Result<usize> {
+        Ok(0)
+    }
+
+    /// Reply to `messages` until the model answers without calling a tool or a limit is reached
+    async fn run_to_completion(&self, messages: &[Message], limits: RunLimits) -> RunResult {
+        run::run_to_completion(self, messages, limits).await
+    }
+
+    /// Start the extensions of `recipe`, add its instructions and run its prompt to completion
+    async fn run_recipe(&mut self, recipe: &Recipe, limits: RunLimits) -> Result<RunResult> {
+        run::run_recipe(self, recipe, limits).await
+    }
 }
@@ -0,0 +1,643 @@
+use anyhow::{Context, Result};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use sha2::{Digest, Sha256};
+use std::fs::{self, File, OpenOptions};
+use std::io::{BufRead, BufReader, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+
+use crate::config::Config;
+use crate::message::{Message, ToolRequest};
+use crate::providers::redact::{credential_rules, RedactionRule};
+use mcp_core::{Content, ToolResult};
+
+/// The `prev_hash` of the first entry of a log
+pub const GENESIS_HASH: &str = "0000000000000000000000000000000000000000000000000000000000000000";
+
+/// Config key of the audit log file, relative paths are in the workspace
+const AUDIT_LOG_KEY: &str = "GOOSE_AUDIT_LOG";
+
+/// How a tool call came to run, or not
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ApprovalDecision {
+    /// The tool's permission policy lets it run without asking
+    Auto,
+    /// The user was asked and allowed it
+    Approved,
+    /// The user was asked and refused it
+    Declined,
+    /// The tool's permission policy forbids it
+    Denied,
+    /// The policy is to ask, but nobody could be asked
+    Unattended,
+    /// The model's tool call could not be parsed, so nothing could run
+    Invalid,
+}
+
+/// When an entry was written for a tool call
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AuditStage {
+    /// Once it was decided whether the call runs, before it does
+    Decision,
+    /// Once the result was there
+    Outcome,
+    /// Not a tool call, the line before it is an entry a crash cut off
+    ///
+    /// Its `output_digest` is the SHA-256 of that line, so it can't be changed
+    /// without breaking the chain either.
+    Recovered,
+}
+
+/// One line of the audit log
+///
+/// `hash` is the SHA-256 of the entry serialized with an empty `hash`, and
+/// `prev_hash` the `hash` of the entry before it, so changing, removing or
+/// reordering an entry breaks the chain from there on. Credentials in the
+/// arguments, the message text and the error are redacted before hashing.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct AuditEntry {
+    /// The position in the log, from 1
+    pub seq: u64,
+    pub timestamp: DateTime<Utc>,
+    pub stage: AuditStage,
+    pub tool_call_id: String,
+    /// The tool and its arguments, empty when the call could not be parsed
+    pub tool: String,
+    pub arguments: Value,
+    /// SHA-256 of the arguments as the tool got them, before redaction
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub arguments_digest: String,
+    /// How the call came to run, None for a `Recovered` marker
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub approval: Option<ApprovalDecision>,
+    /// Why the call failed or did not run
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    /// SHA-256 of the result as the model got it, empty for a decision
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub output_digest: String,
+    /// SHA-256 of the model message that made the call
+    pub message_digest: String,
+    /// The text of that message, what the model said it was doing
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub message_text: String,
+    pub prev_hash: String,
+    pub hash: String,
+}
+
+impl AuditEntry {
+    fn compute_hash(&self) -> String {
+        let mut unhashed = self.clone();
+        unhashed.hash = String::new();
+        let json = serde_json::to_string(&unhashed).expect("audit entries serialize");
+        sha256(json.as_bytes())
+    }
+}
+
+fn sha256(bytes: &[u8]) -> String {
+    format!("{:x}", Sha256::digest(bytes))
+}
+
+/// What the log records of a tool call, see `AuditLog::record`
+pub struct AuditRecord<'a> {
+    pub request: &'a ToolRequest,
+    pub approval: ApprovalDecision,
+    /// The result of the call, None for the decision before it runs
+    pub result: Option<&'a ToolResult<Vec<Content>>>,
+    /// The model message that made the call, when known
+    pub message: Option<&'a Message>,
+}
+
+/// An append-only record of every tool call, in JSON lines chained by hash
+///
+/// Each call gets an entry when it is decided whether it runs and another
+/// with its result. Only ever appended to, each entry is written and synced
+/// under an exclusive lock on the file, so several processes can share a log.
+/// An entry a crash cut off is left as it is and a `Recovered` marker follows.
+/// `verify` checks the chain. Cutting entries off the end keeps the chain
+/// intact, so keep the latest hash somewhere else when that matters.
+pub struct AuditLog {
+    path: PathBuf,
+    rules: Vec<RedactionRule>,
+}
+
+impl AuditLog {
+    /// Open the log at `path` to append to, creating it when missing
+    pub fn open(path: impl Into<PathBuf>) -> Result<Self> {
+        let path = path.into();
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        if path.exists() {
+            let mut file = File::open(&path)?;
+            file.lock_shared()?;
+            read_tail(&mut file)
+                .with_context(|| format!("The last entry of {} is corrupt", path.display()))?;
+        }
+        Ok(Self {
+            path,
+            rules: credential_rules(),
+        })
+    }
+
+    /// The file in GOOSE_AUDIT_LOG, None when it is not set
+    ///
+    /// A relative path is taken from the current directory, so each workspace
+    /// can keep its own log, e.g. `.goose/audit.jsonl`.
+    pub fn configured_path() -> Option<PathBuf> {
+        let path: String = Config::global().get(AUDIT_LOG_KEY).ok()?;
+        let path = PathBuf::from(path);
+        Some(match std::env::current_dir() {
+            Ok(dir) if path.is_relative() => dir.join(path),
+            _ => path,
+        })
+    }
+
+    /// The log at `configured_path`, None when it is not set
+    pub fn from_config() -> Option<Result<Self>> {
+        Self::configured_path().map(Self::open)
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Append an entry for the decision on a tool call, or for its result
+    ///
+    /// Waiting for the lock and syncing the file happen on a blocking thread.
+    pub async fn record(&self, record: AuditRecord<'_>) -> Result<AuditEntry> {
+        let message_digest = record
+            .message
+            .map(|m| sha256(serde_json::to_string(m).unwrap_or_default().as_bytes()))
+            .unwrap_or_default();
+        let (tool, arguments, arguments_digest, invalid) = match &record.request.tool_call {
+            Ok(call) => (
+                call.name.clone(),
+                self.redact_value(&call.arguments),
+                sha256(serde_json::to_string(&call.arguments)?.as_bytes()),
+                None,
+            ),
+            Err(e) => (
+                String::new(),
+                Value::Null,
+                String::new(),
+                Some(e.to_string()),
+            ),
+        };
+        let (stage, error, output_digest) = match record.result {
+            None => (AuditStage::Decision, invalid, String::new()),
+            Some(result) => (
+                AuditStage::Outcome,
+                result.as_ref().err().map(|e| e.to_string()),
+                sha256(serde_json::to_string(result)?.as_bytes()),
+            ),
+        };
+
+        let entry = AuditEntry {
+            seq: 0,
+            timestamp: Utc::now(),
+            stage,
+            tool_call_id: record.request.id.clone(),
+            tool,
+            arguments,
+            arguments_digest,
+            approval: Some(record.approval),
+            error: error.map(|e| self.redact(&e)),
+            output_digest,
+            message_digest,
+            message_text: record
+                .message
+                .map(|m| self.redact(&m.as_concat_text()))
+                .unwrap_or_default(),
+            prev_hash: String::new(),
+            hash: String::new(),
+        };
+        let path = self.path.clone();
+        tokio::task::spawn_blocking(move || append(&path, entry)).await?
+    }
+
+    fn redact(&self, text: &str) -> String {
+        self.rules
+            .iter()
+            .fold(text.to_string(), |text, rule| rule.redact(&text))
+    }
+
+    /// `value` with every string in it redacted
+    fn redact_value(&self, value: &Value) -> Value {
+        match value {
+            Value::String(text) => Value::String(self.redact(text)),
+            Value::Array(values) => {
+                Value::Array(values.iter().map(|v| self.redact_value(v)).collect())
+            }
+            Value::Object(map) => Value::Object(
+                map.iter()
+                    .map(|(key, v)| (key.clone(), self.redact_value(v)))
+                    .collect(),
+            ),
+            other => other.clone(),
+        }
+    }
+}
+
+/// Chain `entry` to the end of the log at `path` and write it, under the lock
+fn append(path: &Path, mut entry: AuditEntry) -> Result<AuditEntry> {
+    // Other processes append to the log too, the entry follows whatever came last
+    let mut file = OpenOptions::new()
+        .create(true)
+        .read(true)
+        .append(true)
+        .open(path)?;
+    file.lock()?;
+    let (mut last, torn) = read_tail(&mut file)?;
+    let mut lines = String::new();
+    let mut marker = None;
+    if let Some(torn) = torn {
+        // End the cut off line, so what follows starts a line of its own
+        lines.push('\n');
+        match serde_json::from_slice::<AuditEntry>(&torn) {
+            // Only the newline was lost
+            Ok(entry) => last = Some(entry),
+            Err(_) => marker = Some(torn),
+        }
+    }
+    let (mut seq, mut prev_hash) = match last {
+        Some(last) => (last.seq, last.hash),
+        None => (0, GENESIS_HASH.to_string()),
+    };
+
+    if let Some(torn) = marker {
+        tracing::warn!(
+            "The last entry of {} was cut off, marking it as recovered",
+            path.display()
+        );
+        let mut marker = AuditEntry {
+            seq: seq + 1,
+            timestamp: Utc::now(),
+            stage: AuditStage::Recovered,
+            tool_call_id: String::new(),
+            tool: String::new(),
+            arguments: Value::Null,
+            arguments_digest: String::new(),
+            approval: None,
+            error: Some(format!("{} bytes of an entry were cut off", torn.len())),
+            output_digest: sha256(&torn),
+            message_digest: String::new(),
+            message_text: String::new(),
+            prev_hash,
+            hash: String::new(),
+        };
+        marker.hash = marker.compute_hash();
+        lines.push_str(&serde_json::to_string(&marker)?);
+        lines.push('\n');
+        (seq, prev_hash) = (marker.seq, marker.hash);
+    }
+
+    entry.seq = seq + 1;
+    entry.prev_hash = prev_hash;
+    entry.hash = entry.compute_hash();
+    lines.push_str(&serde_json::to_string(&entry)?);
+    lines.push('\n');
+    file.write_all(lines.as_bytes())?;
+    file.sync_data()?;
+    Ok(entry)
+}
+
+/// The last complete entry of the log in `file`, and what follows it when a write was cut off
+///
+/// Every entry ends with a newline, so bytes after the last one are what is
+/// left of an entry that was being written.
+fn read_tail(file: &mut File) -> Result<(Option<AuditEntry>, Option<Vec<u8>>)> {
+    const CHUNK: u64 = 4096;
+    let len = file.seek(SeekFrom::End(0))?;
+    let mut tail = Vec::new();
+    let mut start = len;
+    // Until the tail holds the newlines on both sides of the last complete line
+    while start > 0 && tail.iter().filter(|b| **b == b'\n').count() < 2 {
+        let from = start.saturating_sub(CHUNK);
+        let mut chunk = vec![0; (start - from) as usize];
+        file.seek(SeekFrom::Start(from))?;
+        file.read_exact(&mut chunk)?;
+        chunk.extend(tail);
+        tail = chunk;
+        start = from;
+    }
+
+    let complete = match tail.iter().rposition(|b| *b == b'\n') {
+        Some(end) => end + 1,
+        None => 0,
+    };
+    let torn = (complete < tail.len()).then(|| tail[complete..].to_vec());
+    let text = String::from_utf8_lossy(&tail[..complete]);
+    let last = match text.lines().rfind(|line| !line.trim().is_empty()) {
+        Some(line) => Some(serde_json::from_str(line)?),
+        None => None,
+    };
+    Ok((last, torn))
+}
+
+/// Check the hash chain of the log at `path`, returning the number of entries and the last hash
+///
+/// A line that is not an entry passes only when a crash cut it off, that is
+/// when it is the unfinished last line or a `Recovered` marker follows it.
+pub fn verify(path: &Path) -> Result<(u64, String)> {
+    let file = File::open(path).with_context(|| format!("Could not open {}", path.display()))?;
+    let mut reader = BufReader::new(file);
+    let mut head = (0, GENESIS_HASH.to_string());
+    let mut torn: Option<(usize, Vec<u8>)> = None;
+    let mut line = Vec::new();
+    let mut number = 0;
+    let mut finished = true;
+    loop {
+        line.clear();
+        if reader.read_until(b'\n', &mut line)? == 0 {
+            break;
+        }
+        number += 1;
+        finished = line.ends_with(b"\n");
+        let text = line.strip_suffix(b"\n").unwrap_or(&line);
+        let (torn_number, entry) = match serde_json::from_slice::<AuditEntry>(text) {
+            Ok(entry) => (torn.take(), entry),
+            Err(_) if torn.is_none() => {
+                torn = Some((number, text.to_vec()));
+                continue;
+            }
+            Err(e) => {
+                return Err(e).with_context(|| format!("Line {} is not an audit entry", number))
+            }
+        };
+        if let Some((torn_number, bytes)) = torn_number {
+            anyhow::ensure!(
+                entry.stage == AuditStage::Recovered && entry.output_digest == sha256(&bytes),
+                "Line {} is not an audit entry",
+                torn_number
+            );
+        }
+        anyhow::ensure!(
+            entry.seq == head.0 + 1,
+            "Line {} has entry {} where {} was expected, entries were removed or reordered",
+            number,
+            entry.seq,
+            head.0 + 1
+        );
+        anyhow::ensure!(
+            entry.prev_hash == head.1,
+            "Line {} does not follow from the entry before it, entries were removed or changed",
+            number
+        );
+        anyhow::ensure!(
+            entry.hash == entry.compute_hash(),
+            "Line {} was changed after it was written",
+            number
+        );
+        head = (entry.seq, entry.hash);
+    }
+    // An unfinished last line is an entry that was being written when the process died
+    if let Some((torn_number, _)) = torn {
+        anyhow::ensure!(!finished, "Line {} is not an audit entry", torn_number);
+    }
+    Ok(head)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use mcp_core::{ToolCall, ToolError};
+    use serde_json::json;
+    use tempfile::TempDir;
+
+    async fn record_calls(log: &AuditLog, count: usize) -> Result<()> {
+        let message = Message::assistant().with_text("Listing the files");
+        for i in 0..count {
+            let request = ToolRequest {
+                id: format!("call-{}", i),
+                tool_call: Ok(ToolCall::new(
+                    "developer__shell",
+                    json!({"command": "ls", "i": i}),
+                )),
+            };
+            let result = if i % 2 == 0 {
+                Ok(vec![Content::text("README.md")])
+            } else {
+                Err(ToolError::ExecutionError("The user declined".to_string()))
+            };
+            log.record(AuditRecord {
+                request: &request,
+                approval: if i % 2 == 0 {
+                    ApprovalDecision::Auto
+                } else {
+                    ApprovalDecision::Declined
+                },
+                result: Some(&result),
+                message: Some(&message),
+            })
+            .await?;
+        }
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_chain() -> Result<()> {
+        let dir = TempDir::new()?;
+        let path = dir.path().join("audit.jsonl");
+        record_calls(&AuditLog::open(&path)?, 2).await?;
+        // Reopening carries on the chain
+        record_calls(&AuditLog::open(&path)?, 1).await?;
+
+        let (count, head) = verify(&path)?;
+        assert_eq!(count, 3);
+
+        let lines: Vec<AuditEntry> = fs::read_to_string(&path)?
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(lines[0].prev_hash, GENESIS_HASH);
+        assert_eq!(lines[1].prev_hash, lines[0].hash);
+        assert_eq!(lines[2].hash, head);
+        assert_eq!(lines[1].approval, Some(ApprovalDecision::Declined));
+        assert!(lines[1].error.as_ref().unwrap().contains("declined"));
+        assert_eq!(lines[0].message_text, "Listing the files");
+        assert_eq!(lines[0].message_digest, lines[1].message_digest);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_decisions_and_invalid_calls() -> Result<()> {
+        let dir = TempDir::new()?;
+        let path = dir.path().join("audit.jsonl");
+        let log = AuditLog::open(&path)?;
+        let request = ToolRequest {
+            id: "call".to_string(),
+            tool_call: Err(ToolError::InvalidParameters("not json".to_string())),
+        };
+        let decision = log
+            .record(AuditRecord {
+                request: &request,
+                approval: ApprovalDecision::Invalid,
+                result: None,
+                message: None,
+            })
+            .await?;
+        assert_eq!(decision.stage, AuditStage::Decision);
+        assert!(decision.tool.is_empty());
+        assert!(decision.error.unwrap().contains("not json"));
+        assert!(decision.output_digest.is_empty());
+
+        let result = Err(ToolError::InvalidParameters("not json".to_string()));
+        let outcome = log
+            .record(AuditRecord {
+                request: &request,
+                approval: ApprovalDecision::Invalid,
+                result: Some(&result),
+                message: None,
+            })
+            .await?;
+        assert_eq!(outcome.stage, AuditStage::Outcome);
+        assert!(!outcome.output_digest.is_empty());
+        assert_eq!(verify(&path)?.0, 2);
+        Ok(())
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn test_shared_log() -> Result<()> {
+        let dir = TempDir::new()?;
+        let path = dir.path().join("audit.jsonl");
+        // Logs opened before the others wrote, like those of other processes
+        let logs: Vec<AuditLog> = (0..4)
+            .map(|_| AuditLog::open(&path))
+            .collect::<Result<_>>()?;
+        futures::future::try_join_all(logs.iter().map(|log| record_calls(log, 25))).await?;
+        assert_eq!(verify(&path)?.0, 100);
+
+        // Long lines are read back across chunks too
+        let long = "x".repeat(10_000);
+        let message = Message::assistant().with_text(&long);
+        let request = ToolRequest {
+            id: "long".to_string(),
+            tool_call: Ok(ToolCall::new("developer__shell", json!({}))),
+        };
+        for _ in 0..2 {
+            logs[0]
+                .record(AuditRecord {
+                    request: &request,
+                    approval: ApprovalDecision::Auto,
+                    result: None,
+                    message: Some(&message),
+                })
+                .await?;
+        }
+        assert_eq!(verify(&path)?.0, 102);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_tampering_is_detected() -> Result<()> {
+        let dir = TempDir::new()?;
+        let path = dir.path().join("audit.jsonl");
+        record_calls(&AuditLog::open(&path)?, 3).await?;
+        let original = fs::read_to_string(&path)?;
+        let lines: Vec<&str> = original.lines().collect();
+
+        // A changed argument
+        fs::write(&path, original.replacen("\"ls\"", "\"rm\"", 1))?;
+        assert!(verify(&path).unwrap_err().to_string().contains("Line 1"));
+
+        // A removed entry
+        fs::write(&path, format!("{}\n{}\n", lines[0], lines[2]))?;
+        assert!(verify(&path).unwrap_err().to_string().contains("Line 2"));
+
+        // A rewritten entry with a fresh hash no longer matches the next one
+        let mut entry: AuditEntry = serde_json::from_str(lines[1])?;
+        entry.approval = Some(ApprovalDecision::Auto);
+        entry.hash = entry.compute_hash();
+        let forged = serde_json::to_string(&entry)?;
+        fs::write(&path, format!("{}\n{}\n{}\n", lines[0], forged, lines[2]))?;
+        assert!(verify(&path).unwrap_err().to_string().contains("Line 3"));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_cut_off_entry_is_recovered() -> Result<()> {
+        let dir = TempDir::new()?;
+        let path = dir.path().join("audit.jsonl");
+        record_calls(&AuditLog::open(&path)?, 2).await?;
+        let whole = fs::read_to_string(&path)?;
+        let line = whole.lines().next().unwrap();
+
+        // A crash halfway through a write
+        let torn = &line[..line.len() / 2];
+        fs::write(&path, format!("{}{}", whole, torn))?;
+        assert_eq!(verify(&path)?.0, 2);
+
+        let log = AuditLog::open(&path)?;
+        record_calls(&log, 1).await?;
+        let (count, _) = verify(&path)?;
+        assert_eq!(count, 4);
+        let text = fs::read_to_string(&path)?;
+        let mut lines: Vec<&str> = text.lines().collect();
+        assert_eq!(lines[2], torn);
+        let marker: AuditEntry = serde_json::from_str(lines[3])?;
+        assert_eq!(marker.stage, AuditStage::Recovered);
+        assert_eq!(marker.seq, 3);
+        assert_eq!(marker.approval, None);
+        assert_eq!(marker.output_digest, sha256(torn.as_bytes()));
+
+        // Changing the cut off bytes breaks the chain
+        let changed = torn.replacen("call", "lost", 1);
+        lines[2] = &changed;
+        fs::write(&path, lines.join("\n") + "\n")?;
+        assert!(verify(&path).unwrap_err().to_string().contains("Line 3"));
+
+        // Losing only the newline keeps the entry
+        fs::write(&path, whole.trim_end())?;
+        record_calls(&log, 1).await?;
+        assert_eq!(verify(&path)?.0, 3);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_credentials_are_redacted() -> Result<()> {
+        let dir = TempDir::new()?;
+        let path = dir.path().join("audit.jsonl");
+        let log = AuditLog::open(&path)?;
+        let token = format!("ghp_{}", "a".repeat(36));
+        let arguments =
+            json!({"command": format!("gh auth login --with-token {}", token), "env": [token]});
+        let request = ToolRequest {
+            id: "call".to_string(),
+            tool_call: Ok(ToolCall::new("developer__shell", arguments.clone())),
+        };
+        let message = Message::assistant().with_text(format!("Logging in with {}", token));
+        let result = Err(ToolError::ExecutionError(format!("{} is expired", token)));
+        let entry = log
+            .record(AuditRecord {
+                request: &request,
+                approval: ApprovalDecision::Auto,
+                result: Some(&result),
+                message: Some(&message),
+            })
+            .await?;
+
+        assert!(!fs::read_to_string(&path)?.contains(&token));
+        assert_eq!(
+            entry.arguments,
+            json!({"command": "gh auth login --with-token [REDACTED_GITHUB_TOKEN]", "env": ["[REDACTED_GITHUB_TOKEN]"]})
+        );
+        assert_eq!(
+            entry.arguments_digest,
+            sha256(serde_json::to_string(&arguments)?.as_bytes())
+        );
+        assert_eq!(
+            entry.message_text,
+            "Logging in with [REDACTED_GITHUB_TOKEN]"
+        );
+        assert!(entry
+            .error
+            .unwrap()
+            .contains("[REDACTED_GITHUB_TOKEN] is expired"));
+        assert_eq!(verify(&path)?.0, 1);
+        Ok(())
+    }
+}
@@ -1,18 +1,38 @@
 use chrono::{DateTime, TimeZone, Utc};
+use futures::future::BoxFuture;
 use futures::stream::{FuturesUnordered, StreamExt};
 use mcp_client::McpService;
-use std::collections::{HashMap, HashSet};
+use serde::Serialize;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::future::Future;
 use std::sync::Arc;
 use std::sync::LazyLock;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 use tokio::sync::Mutex;
-use tracing::{debug, instrument};
-
-use super::extension::{ExtensionConfig, ExtensionError, ExtensionInfo, ExtensionResult};
-use crate::prompt_template::load_prompt_file;
-use crate::providers::base::{Provider, ProviderUsage};
+use tokio::time::error::Elapsed;
+use tracing::field::Empty;
+use tracing::{debug, error, info_span, instrument, warn, Instrument};
+
+use super::audit::{ApprovalDecision, AuditLog, AuditRecord};
+use super::extension::{
+    DeviceCodePrompt, ExtensionConfig, ExtensionError, ExtensionInfo, ExtensionResult,
+};
+use super::moderation::{self, ModerationError, Moderator};
+use super::router::{ModelRouter, Phase};
+use super::safeguard::{
+    SafeguardDecision, SafeguardPause, SafeguardTracker, SafeguardTrip, Safeguards,
+};
+use super::tool_output::{ToolOutputLimit, READ_TOOL_OUTPUT};
+use crate::config::{Config, ExtensionPermissions, PermissionManager, ToolCategory, ToolPolicy};
+use crate::message::{Message, MessageContent, ToolRequest};
+use crate::plan::{create_plan, Plan};
+use crate::prompt_template::{load_prompt_extending, load_prompt_file};
+use crate::providers::base::{MessageDelta, Provider, ProviderUsage};
+use crate::providers::errors::ProviderError;
+use crate::providers::streaming::StreamCollector;
+use crate::providers::utils::count_images;
 use mcp_client::client::{ClientCapabilities, ClientInfo, McpClient, McpClientTrait};
-use mcp_client::transport::{SseTransport, StdioTransport, Transport};
+use mcp_client::transport::{SseTransport, StdioTransport, StreamableHttpTransport, Transport};
 use mcp_core::{Content, Tool, ToolCall, ToolError, ToolResult};
 use serde_json::Value;
 
@@ -23,6 +43,89 @@ static DEFAULT_TIMESTAMP: LazyLock<DateTime<Utc>> =
 
 type McpClientBox = Arc<Mutex<Box<dyn McpClientTrait>>>;
 
+/// How many tool calls from one response run at once, unless GOOSE_TOOL_PARALLELISM is set
+const DEFAULT_TOOL_PARALLELISM: usize = 4;
+
+/// How long a tool call may run, unless GOOSE_TOOL_TIMEOUT is set in seconds
+const DEFAULT_TOOL_TIMEOUT: Duration = Duration::from_secs(300);
+
+/// Asks the user whether a tool call may run, resolving to true when it may
+pub type ToolApproval =
+    Arc<dyn Fn(ToolCall, ToolCategory) -> BoxFuture<'static, bool> + Send + Sync>;
+
+/// Receives the text of a model response as it arrives
+pub type TextDeltas = Arc<dyn Fn(&str) + Send + Sync>;
+
+/// The tool permissions of the running extensions, as `Capabilities` applies them
+///
+/// A snapshot for callers that can not reach the agent while it replies, such
+/// as a UI deciding whether a tool request will be followed by a question.
+#[derive(Debug, Clone, Default)]
+pub struct ToolPolicies {
+    /// The permissions of each running extension, by normalized name
+    extensions: HashMap<String, ExtensionPermissions>,
+}
+
+impl ToolPolicies {
+    /// The category and policy for a tool, named with its extension prefix
+    pub fn get(&self, prefixed_name: &str) -> (ToolCategory, ToolPolicy) {
+        let extension = self
+            .extensions
+            .iter()
+            .find(|(name, _)| prefixed_name.starts_with(name.as_str()));
+        match extension {
+            Some((name, permissions)) => resolve_policy(name, Some(permissions), prefixed_name),
+            None => (ToolCategory::ReadOnly, ToolPolicy::AutoApprove),
+        }
+    }
+
+    /// Whether the user will be asked before the tool runs
+    pub fn asks(&self, prefixed_name: &str) -> bool {
+        self.get(prefixed_name).1 == ToolPolicy::AlwaysAsk
+    }
+}
+
+/// The category and policy for a tool of `client_name`, named with its extension prefix
+fn resolve_policy(
+    client_name: &str,
+    permissions: Option<&ExtensionPermissions>,
+    prefixed_name: &str,
+) -> (ToolCategory, ToolPolicy) {
+    let tool_name = prefixed_name
+        .strip_prefix(client_name)
+        .and_then(|s| s.strip_prefix("__"))
+        .unwrap_or(prefixed_name);
+    let policy = permissions
+        .map(|permissions| permissions.policy(tool_name))
+        .unwrap_or_default();
+    (ToolCategory::classify(tool_name), policy)
+}
+
+/// Config key of a file with a template for the system prompt
+const SYSTEM_PROMPT_TEMPLATE_KEY: &str = "GOOSE_SYSTEM_PROMPT_TEMPLATE";
+
+/// What templates for the system prompt can use
+#[derive(Serialize)]
+struct SystemPromptContext {
+    extensions: Vec<ExtensionInfo>,
+    /// The instructions of each extension, by name
+    sections: BTreeMap<String, String>,
+    tools: Vec<ToolInfo>,
+    /// Added with `extend_system_prompt`, such as the instructions of a recipe
+    additional_instructions: Vec<String>,
+    os: &'static str,
+    arch: &'static str,
+    cwd: String,
+    date: String,
+    datetime: String,
+}
+
+#[derive(Serialize)]
+struct ToolInfo {
+    name: String,
+    description: String,
+}
+
 /// Manages MCP clients and their interactions
 pub struct Capabilities {
     clients: HashMap<String, McpClientBox>,
@@ -30,6 +133,25 @@ pub struct Capabilities {
     resource_capable_extensions: HashSet<String>,
     provider: Box<dyn Provider>,
     provider_usage: Mutex<Vec<ProviderUsage>>,
+    tool_parallelism: usize,
+    tool_timeout: Duration,
+    permissions: HashMap<String, ExtensionPermissions>,
+    tool_approval: Option<ToolApproval>,
+    device_code_prompt: Option<DeviceCodePrompt>,
+    text_deltas: Option<TextDeltas>,
+    safeguards: Safeguards,
+    safeguard_pause: Option<SafeguardPause>,
+    /// Counts the tool calls of the current reply, or of the run holding it
+    safeguard_tracker: SafeguardTracker,
+    safeguards_held: bool,
+    /// The safeguard the last reply stopped at
+    safeguard_trip: Option<SafeguardTrip>,
+    /// The log of GOOSE_AUDIT_LOG, or why it could not be opened, when it is set
+    audit_log: Option<Result<Arc<AuditLog>, String>>,
+    moderators: Vec<Arc<dyn Moderator>>,
+    system_prompt_extensions: Vec<String>,
+    tool_output_limit: Option<ToolOutputLimit>,
+    router: ModelRouter,
 }
 
 /// A flattened representation of a resource used by the agent to prepare inference
@@ -82,12 +204,163 @@ fn normalize(input: String) -> String {
 impl Capabilities {
     /// Create a new Capabilities with the specified provider
     pub fn new(provider: Box<dyn Provider>) -> Self {
+        let config = Config::global();
+        let tool_output_limit = ToolOutputLimit::from_config(provider.as_ref());
+        let router = ModelRouter::from_config(provider.as_ref());
+        let safeguards = Safeguards::from_config();
         Self {
             clients: HashMap::new(),
             instructions: HashMap::new(),
             resource_capable_extensions: HashSet::new(),
             provider,
             provider_usage: Mutex::new(Vec::new()),
+            tool_parallelism: config
+                .get("GOOSE_TOOL_PARALLELISM")
+                .unwrap_or(DEFAULT_TOOL_PARALLELISM),
+            tool_timeout: config
+                .get("GOOSE_TOOL_TIMEOUT")
+                .map(Duration::from_secs)
+                .unwrap_or(DEFAULT_TOOL_TIMEOUT),
+            permissions: PermissionManager::get_all()
+                .unwrap_or_else(|e| {
+                    warn!(
+                        "Could not read the tool permissions, every tool is allowed: {}",
+                        e
+                    );
+                    HashMap::new()
+                })
+                .into_iter()
+                .map(|(extension, permissions)| (normalize(extension), permissions))
+                .collect(),
+            tool_approval: None,
+            device_code_prompt: None,
+            text_deltas: None,
+            safeguards,
+            safeguard_pause: None,
+            safeguard_tracker: SafeguardTracker::new(safeguards),
+            safeguards_held: false,
+            safeguard_trip: None,
+            audit_log: AuditLog::from_config().map(|log| match log {
+                Ok(log) => Ok(Arc::new(log)),
+                Err(e) => {
+                    error!("Could not open the audit log, no tool will run: {}", e);
+                    Err(e.to_string())
+                }
+            }),
+            moderators: Vec::new(),
+            system_prompt_extensions: Vec::new(),
+            tool_output_limit,
+            router,
+        }
+    }
+
+    /// Set how many tool calls run at once and how long each one may take
+    pub fn set_tool_limits(&mut self, parallelism: usize, timeout: Duration) {
+        self.tool_parallelism = parallelism;
+        self.tool_timeout = timeout;
+    }
+
+    /// Make tool results fit `limit`, or pass them on whole with None
+    pub fn set_tool_output_limit(&mut self, limit: Option<ToolOutputLimit>) {
+        self.tool_output_limit = limit;
+    }
+
+    /// The tools goose offers itself, next to the ones of the extensions
+    pub fn platform_tools(&self) -> Vec<Tool> {
+        self.tool_output_limit
+            .as_ref()
+            .and_then(|limit| limit.read_tool())
+            .into_iter()
+            .collect()
+    }
+
+    /// Replace the tool permissions of an extension
+    pub fn set_permissions(&mut self, extension: &str, permissions: ExtensionPermissions) {
+        self.permissions
+            .insert(normalize(extension.to_string()), permissions);
+    }
+
+    /// Ask `approval` before running a tool whose policy is to always ask
+    ///
+    /// Without one those tools are refused, so that an unattended session
+    /// never runs them.
+    pub fn set_tool_approval(&mut self, approval: ToolApproval) {
+        self.tool_approval = Some(approval);
+    }
+
+    /// Show the codes that connect extensions with OAuth with `prompt`, besides logging them
+    pub fn set_device_code_prompt(&mut self, prompt: DeviceCodePrompt) {
+        self.device_code_prompt = Some(prompt);
+    }
+
+    /// Send the text of model responses to `deltas` as it arrives, or stop with None
+    ///
+    /// Only while no moderator runs, since moderators may rewrite a response
+    /// before it is shown.
+    pub fn set_text_deltas(&mut self, deltas: Option<TextDeltas>) {
+        self.text_deltas = deltas;
+    }
+
+    /// Record every tool call in `log`, or none with None
+    pub fn set_audit_log(&mut self, log: Option<AuditLog>) {
+        self.audit_log = log.map(|log| Ok(Arc::new(log)));
+    }
+
+    /// Replace the limits on the tool calls of one request
+    pub fn set_safeguards(&mut self, safeguards: Safeguards) {
+        self.safeguards = safeguards;
+        self.safeguard_tracker = SafeguardTracker::new(safeguards);
+    }
+
+    /// Ask `pause` how to go on when a safeguard is reached
+    ///
+    /// Without one the agent stops there, so that an unattended session
+    /// can't spend its budget on a model that is stuck.
+    pub fn set_safeguard_pause(&mut self, pause: SafeguardPause) {
+        self.safeguard_pause = Some(pause);
+    }
+
+    /// Start counting the tool calls of a new reply, unless a run holds the count
+    pub fn start_safeguards(&mut self) {
+        self.safeguard_trip = None;
+        if !self.safeguards_held {
+            self.safeguard_tracker = SafeguardTracker::new(self.safeguards);
+        }
+    }
+
+    /// Count across replies while `held`, from 0 when it starts
+    ///
+    /// For a run that replies several times to one task, such as
+    /// `run_to_completion` checking its budget between tool turns.
+    pub fn hold_safeguards(&mut self, held: bool) {
+        if held && !self.safeguards_held {
+            self.safeguard_tracker = SafeguardTracker::new(self.safeguards);
+        }
+        self.safeguards_held = held;
+    }
+
+    /// The safeguard the last reply stopped at, None when it didn't stop at one
+    pub fn safeguard_trip(&self) -> Option<SafeguardTrip> {
+        self.safeguard_trip.clone()
+    }
+
+    /// Count `requests`, resolving to the safeguard they reach if the user stops there
+    pub async fn check_safeguards(&mut self, requests: &[&ToolRequest]) -> Option<SafeguardTrip> {
+        let trip = self.safeguard_tracker.record(requests)?;
+        warn!("Pausing: {}", trip);
+        let decision = match &self.safeguard_pause {
+            Some(pause) => pause(trip.clone()).await,
+            None => SafeguardDecision::Stop,
+        };
+        match decision {
+            SafeguardDecision::Continue => {
+                self.safeguard_tracker.reset();
+                None
+            }
+            SafeguardDecision::Stop => {
+                self.safeguard_trip = Some(trip.clone());
+                Some(trip)
+            }
         }
     }
 
@@ -106,9 +379,26 @@ impl Capabilities {
                 Box::new(McpClient::new(service))
             }
             ExtensionConfig::Stdio {
-                cmd, args, envs, ..
+                cmd,
+                args,
+                envs,
+                sandbox,
+                ..
+            } => {
+                let transport =
+                    sandbox.apply(StdioTransport::new(cmd, args.to_vec(), envs.get_env()));
+                let handle = transport.start().await?;
+                let service = McpService::with_timeout(handle, Duration::from_secs(300));
+                Box::new(McpClient::new(service))
+            }
+            ExtensionConfig::StreamableHttp {
+                name,
+                uri,
+                headers,
+                auth,
             } => {
-                let transport = StdioTransport::new(cmd, args.to_vec(), envs.get_env());
+                let auth = auth.provider(name, self.device_code_prompt.clone())?;
+                let transport = StreamableHttpTransport::with_auth(uri, headers.clone(), auth);
                 let handle = transport.start().await?;
                 let service = McpService::with_timeout(handle, Duration::from_secs(300));
                 Box::new(McpClient::new(service))
@@ -169,6 +459,132 @@ impl Capabilities {
         &*self.provider
     }
 
+    /// The provider that runs `phase`, the agent's unless the phase is routed elsewhere
+    pub fn provider_for(&self, phase: Phase) -> &dyn Provider {
+        self.router.route(phase).unwrap_or(self.provider())
+    }
+
+    /// Send the phases of a reply to the providers of `router`
+    pub fn set_router(&mut self, router: ModelRouter) {
+        self.router = router;
+    }
+
+    /// Complete the request with the provider routed for its phase
+    ///
+    /// When the tool calls model answers without calling a tool and the final
+    /// answer has a route, the answer is asked for again from that model. The
+    /// usage of the discarded answer is recorded here. An answer that may be
+    /// discarded is not sent to the text deltas.
+    pub async fn complete(
+        &self,
+        system: &str,
+        messages: &[Message],
+        tools: &[Tool],
+    ) -> Result<(Message, ProviderUsage), ProviderError> {
+        let phase = Phase::of(messages);
+        let final_answer = self
+            .router
+            .route(Phase::FinalAnswer)
+            .filter(|_| phase == Phase::ToolCalls);
+        let provider = self.provider_for(phase);
+        let (response, usage) = match final_answer {
+            Some(_) => provider.complete(system, messages, tools).await?,
+            None => {
+                self.complete_with(provider, system, messages, tools)
+                    .await?
+            }
+        };
+        let ends_tool_calls = !response
+            .content
+            .iter()
+            .any(|content| content.as_tool_request().is_some());
+        match final_answer {
+            Some(final_answer) if ends_tool_calls => {
+                self.record_usage(usage).await;
+                self.complete_with(final_answer, system, messages, tools)
+                    .await
+            }
+            _ => Ok((response, usage)),
+        }
+    }
+
+    /// Complete with `provider`, streaming the response to the text deltas when there are any
+    async fn complete_with(
+        &self,
+        provider: &dyn Provider,
+        system: &str,
+        messages: &[Message],
+        tools: &[Tool],
+    ) -> Result<(Message, ProviderUsage), ProviderError> {
+        let deltas = match &self.text_deltas {
+            Some(deltas) if self.moderators.is_empty() => deltas,
+            _ => return provider.complete(system, messages, tools).await,
+        };
+        let stream = provider.stream(system, messages, tools).await?;
+        StreamCollector::new(stream)
+            .run(|delta| {
+                if let MessageDelta::Content(MessageContent::Text(text)) = delta {
+                    deltas(&text.text);
+                }
+            })
+            .await
+    }
+
+    /// Add `instructions` to the end of the system prompt
+    pub fn extend_system_prompt(&mut self, instructions: String) {
+        self.system_prompt_extensions.push(instructions);
+    }
+
+    /// Run `moderator` on every request and response, after the ones added before it
+    pub fn add_moderator(&mut self, moderator: Arc<dyn Moderator>) {
+        self.moderators.push(moderator);
+    }
+
+    /// The moderators, in the order they run
+    pub fn moderators(&self) -> &[Arc<dyn Moderator>] {
+        &self.moderators
+    }
+
+    /// The system prompt and messages to send, as the moderators rewrote them
+    pub async fn moderate_request(
+        &self,
+        system: &str,
+        messages: &[Message],
+    ) -> Result<(String, Vec<Message>), ModerationError> {
+        moderation::moderate_request(&self.moderators, system, messages).await
+    }
+
+    /// Propose a plan for the latest request without running any tool
+    pub async fn plan(&mut self, messages: &[Message]) -> anyhow::Result<Plan> {
+        let tools = self.get_prefixed_tools().await?;
+        let (_, messages) = self.moderate_request("", messages).await?;
+        let (plan, usage) =
+            create_plan(self.provider_for(Phase::Planning), &messages, &tools).await?;
+        self.record_usage(usage).await;
+        Ok(plan)
+    }
+
+    /// The response as the moderators rewrote it
+    pub async fn moderate_response(
+        &self,
+        mut response: Message,
+    ) -> Result<Message, ModerationError> {
+        for moderator in &self.moderators {
+            moderator.after_response(&mut response).await?;
+        }
+        Ok(response)
+    }
+
+    /// Warn when the newest message carries images the selected model can not see
+    pub fn warn_on_unsupported_images(&self, messages: &[Message]) {
+        let has_images = messages
+            .last()
+            .is_some_and(|message| count_images(std::slice::from_ref(message)) > 0);
+        if has_images && !self.provider.instance_metadata().image_input {
+            warn!("The selected model does not accept images, choose a vision model to send them");
+        }
+    }
+
     /// Record provider usage
     // TODO consider moving this off to the provider or as a form of logging
     pub async fn record_usage(&self, usage: ProviderUsage) {
@@ -285,9 +701,11 @@ impl Capabilities {
     }
 
     /// Get the extension prompt including client instructions
-    pub async fn get_system_prompt(&self) -> String {
-        let mut context: HashMap<&str, Vec<ExtensionInfo>> = HashMap::new();
-        let extensions_info: Vec<ExtensionInfo> = self
+    ///
+    /// The prompt is rendered from the template in GOOSE_SYSTEM_PROMPT_TEMPLATE
+    /// when one is configured, falling back to the built-in one if it fails.
+    pub async fn get_system_prompt(&self, tools: &[Tool]) -> String {
+        let extensions: Vec<ExtensionInfo> = self
             .clients
             .keys()
             .map(|name| {
@@ -296,8 +714,41 @@ impl Capabilities {
                 ExtensionInfo::new(name, &instructions, has_resources)
             })
             .collect();
+        let now = chrono::Local::now();
+        let context = SystemPromptContext {
+            extensions,
+            sections: self.instructions.clone().into_iter().collect(),
+            tools: tools
+                .iter()
+                .map(|tool| ToolInfo {
+                    name: tool.name.clone(),
+                    description: tool.description.clone(),
+                })
+                .collect(),
+            additional_instructions: self.system_prompt_extensions.clone(),
+            os: std::env::consts::OS,
+            arch: std::env::consts::ARCH,
+            cwd: std::env::current_dir()
+                .map(|dir| dir.display().to_string())
+                .unwrap_or_default(),
+            date: now.format("%Y-%m-%d").to_string(),
+            datetime: now.to_rfc3339(),
+        };
 
-        context.insert("extensions", extensions_info);
+        if let Ok(path) = Config::global().get::<String>(SYSTEM_PROMPT_TEMPLATE_KEY) {
+            let rendered = std::fs::read_to_string(&path)
+                .map_err(|e| e.to_string())
+                .and_then(|template| {
+                    load_prompt_extending(&template, &context).map_err(|e| format!("{:?}", e))
+                });
+            match rendered {
+                Ok(system_prompt) => return system_prompt,
+                Err(e) => warn!(
+                    "Failed to render the system prompt template {}, using the built-in one: {}",
+                    path, e
+                ),
+            }
+        }
         load_prompt_file("system.md", &context).expect("Prompt should render")
     }
 
@@ -475,31 +926,55 @@ impl Capabilities {
     /// Dispatch a single tool call to the appropriate client
     #[instrument(skip(self, tool_call), fields(input, output))]
     pub async fn dispatch_tool_call(&self, tool_call: ToolCall) -> ToolResult<Vec<Content>> {
+        match self.dispatch_tool_call_within(tool_call, None).await {
+            Ok(result) => result,
+            Err(_) => unreachable!("calls without a timeout do not time out"),
+        }
+    }
+
+    /// Like `dispatch_tool_call`, giving up once the tool has run for `timeout`
+    ///
+    /// A call to an extension waits for the calls to it that came first, the
+    /// timeout only starts once it holds the extension.
+    async fn dispatch_tool_call_within(
+        &self,
+        tool_call: ToolCall,
+        timeout: Option<Duration>,
+    ) -> Result<ToolResult<Vec<Content>>, Elapsed> {
         let result = if tool_call.name == "platform__read_resource" {
             // Check if the tool is read_resource and handle it separately
-            self.read_resource(tool_call.arguments.clone()).await
+            within(timeout, self.read_resource(tool_call.arguments.clone())).await?
         } else if tool_call.name == "platform__list_resources" {
-            self.list_resources(tool_call.arguments.clone()).await
+            within(timeout, self.list_resources(tool_call.arguments.clone())).await?
+        } else if tool_call.name == READ_TOOL_OUTPUT {
+            match &self.tool_output_limit {
+                Some(limit) => limit.read_page(tool_call.arguments.clone()),
+                None => Err(ToolError::NotFound(tool_call.name.clone())),
+            }
         } else {
             // Else, dispatch tool call based on the prefix naming convention
-            let (client_name, client) = self
-                .get_client_for_tool(&tool_call.name)
-                .ok_or_else(|| ToolError::NotFound(tool_call.name.clone()))?;
+            let Some((client_name, client)) = self.get_client_for_tool(&tool_call.name) else {
+                return Ok(Err(ToolError::NotFound(tool_call.name.clone())));
+            };
 
             // rsplit returns the iterator in reverse, tool_name is then at 0
-            let tool_name = tool_call
+            let Some(tool_name) = tool_call
                 .name
                 .strip_prefix(client_name)
                 .and_then(|s| s.strip_prefix("__"))
-                .ok_or_else(|| ToolError::NotFound(tool_call.name.clone()))?;
+            else {
+                return Ok(Err(ToolError::NotFound(tool_call.name.clone())));
+            };
 
             let client_guard = client.lock().await;
 
-            client_guard
-                .call_tool(tool_name, tool_call.clone().arguments)
-                .await
-                .map(|result| result.content)
-                .map_err(|e| ToolError::ExecutionError(e.to_string()))
+            within(
+                timeout,
+                client_guard.call_tool(tool_name, tool_call.clone().arguments),
+            )
+            .await?
+            .map(|result| result.content)
+            .map_err(|e| ToolError::ExecutionError(e.to_string()))
         };
 
         debug!(
@@ -507,17 +982,252 @@ impl Capabilities {
             "output" = serde_json::to_string(&result).unwrap(),
         );
 
-        result
+        Ok(result)
+    }
+
+    /// Dispatch the tool requests of one response concurrently
+    ///
+    /// The requests are first checked against the tool permissions one at a
+    /// time, so the user is asked about them in order. Then at most
+    /// `tool_parallelism` calls run at once and each one fails with an
+    /// `ExecutionError` when it takes longer than `tool_timeout`. Calls to the
+    /// same extension still run one after another. The results are in the
+    /// order of the requests, a request whose tool call could not be parsed
+    /// gets its parse error.
+    pub async fn dispatch_tool_requests(
+        &self,
+        requests: &[&ToolRequest],
+    ) -> Vec<ToolResult<Vec<Content>>> {
+        self.dispatch_tool_requests_of(None, requests).await
+    }
+
+    /// Like `dispatch_tool_requests`, for the requests of `response`
+    ///
+    /// The audit log, when there is one, records the decision on each call
+    /// before any of them runs and its result once it's there, with the model
+    /// message that made it. A call whose decision could not be recorded does
+    /// not run.
+    pub async fn dispatch_tool_requests_of(
+        &self,
+        response: Option<&Message>,
+        requests: &[&ToolRequest],
+    ) -> Vec<ToolResult<Vec<Content>>> {
+        let mut approved = Vec::with_capacity(requests.len());
+        for request in requests {
+            let (decision, mut tool_call) = self.approve_tool_request(request).await;
+            let recorded = self.audit(request, decision, None, response).await;
+            if let (Err(e), Ok(call)) = (recorded, &tool_call) {
+                tool_call = Err(ToolError::ExecutionError(format!(
+                    "{} was not run, the audit log could not record it: {}",
+                    call.name, e
+                )));
+            }
+            approved.push((request, decision, tool_call));
+        }
+        let calls: Vec<_> = approved
+            .into_iter()
+            .map(|(request, decision, tool_call)| async move {
+                let result = self.dispatch_approved_tool_call(tool_call).await;
+                let _ = self.audit(request, decision, Some(&result), response).await;
+                result
+            })
+            .collect();
+        futures::stream::iter(calls)
+            .buffered(self.tool_parallelism.max(1))
+            .collect()
+            .await
+    }
+
+    /// Record the decision on `request`, or its `result`, in the audit log when there is one
+    ///
+    /// Fails when the log is set but could not be opened or written to.
+    async fn audit(
+        &self,
+        request: &ToolRequest,
+        approval: ApprovalDecision,
+        result: Option<&ToolResult<Vec<Content>>>,
+        message: Option<&Message>,
+    ) -> Result<(), String> {
+        let log = match &self.audit_log {
+            None => return Ok(()),
+            Some(Err(e)) => return Err(e.clone()),
+            Some(Ok(log)) => log,
+        };
+        let record = AuditRecord {
+            request,
+            approval,
+            result,
+            message,
+        };
+        log.record(record).await.map(|_| ()).map_err(|e| {
+            error!(
+                "Could not record tool call {} in the audit log {}: {}",
+                request.id,
+                log.path().display(),
+                e
+            );
+            e.to_string()
+        })
+    }
+
+    /// The policy for a tool, named with its extension prefix
+    fn tool_policy(&self, prefixed_name: &str) -> (ToolCategory, ToolPolicy) {
+        let Some((client_name, _)) = self.get_client_for_tool(prefixed_name) else {
+            return (ToolCategory::ReadOnly, ToolPolicy::AutoApprove);
+        };
+        resolve_policy(
+            client_name,
+            self.permissions.get(client_name),
+            prefixed_name,
+        )
+    }
+
+    /// The tool permissions of the running extensions
+    pub fn tool_policies(&self) -> ToolPolicies {
+        let extensions = self
+            .clients
+            .keys()
+            .map(|name| {
+                let permissions = self.permissions.get(name).cloned().unwrap_or_default();
+                (name.clone(), permissions)
+            })
+            .collect();
+        ToolPolicies { extensions }
+    }
+
+    /// Whether the request may run and how that was decided
+    async fn approve_tool_request(
+        &self,
+        request: &ToolRequest,
+    ) -> (ApprovalDecision, ToolResult<ToolCall>) {
+        let tool_call = match request.tool_call.clone() {
+            Ok(tool_call) => tool_call,
+            Err(e) => return (ApprovalDecision::Invalid, Err(e)),
+        };
+        let (category, policy) = self.tool_policy(&tool_call.name);
+        let (decision, result) = match policy {
+            ToolPolicy::AutoApprove => (ApprovalDecision::Auto, Ok(tool_call)),
+            ToolPolicy::Deny => (
+                ApprovalDecision::Denied,
+                Err(ToolError::ExecutionError(format!(
+                    "{} is not allowed to run by the user's tool permissions",
+                    tool_call.name
+                ))),
+            ),
+            ToolPolicy::AlwaysAsk => match &self.tool_approval {
+                None => (
+                    ApprovalDecision::Unattended,
+                    Err(ToolError::ExecutionError(format!(
+                        "{} needs the user's approval, which can't be asked for in this session",
+                        tool_call.name
+                    ))),
+                ),
+                Some(approval) if approval(tool_call.clone(), category).await => {
+                    (ApprovalDecision::Approved, Ok(tool_call))
+                }
+                Some(_) => (
+                    ApprovalDecision::Declined,
+                    Err(ToolError::ExecutionError(format!(
+                        "The user declined to run {}",
+                        tool_call.name
+                    ))),
+                ),
+            },
+        };
+        (decision, result)
+    }
+
+    /// Run an approved tool call, within its span following the OpenTelemetry GenAI conventions
+    async fn dispatch_approved_tool_call(
+        &self,
+        tool_call: ToolResult<ToolCall>,
+    ) -> ToolResult<Vec<Content>> {
+        let tool_call = tool_call?;
+        let name = tool_call.name.clone();
+        let span = info_span!(
+            "execute_tool",
+            "otel.name" = format!("execute_tool {}", name),
+            "otel.kind" = "internal",
+            "otel.status_code" = Empty,
+            "gen_ai.operation.name" = "execute_tool",
+            "gen_ai.tool.name" = name.as_str(),
+            "error.type" = Empty,
+        );
+        let started = Instant::now();
+        let (result, error_type) = match self
+            .dispatch_tool_call_within(tool_call, Some(self.tool_timeout))
+            .instrument(span.clone())
+            .await
+        {
+            Ok(Ok(contents)) => (Ok(contents), None),
+            Ok(Err(error)) => {
+                let error_type = tool_error_type(&error);
+                (Err(error), Some(error_type))
+            }
+            Err(_) => (
+                Err(ToolError::ExecutionError(format!(
+                    "{} did not finish within {} seconds",
+                    name,
+                    self.tool_timeout.as_secs_f32()
+                ))),
+                Some("timeout"),
+            ),
+        };
+        if let Some(error_type) = error_type {
+            span.record("otel.status_code", "ERROR");
+            span.record("error.type", error_type);
+        }
+        #[cfg(feature = "otel")]
+        crate::tracing::metrics::record_tool_call(&name, started.elapsed(), error_type);
+        #[cfg(not(feature = "otel"))]
+        let _ = started;
+        let mut contents = result?;
+        if let Some(limit) = &self.tool_output_limit {
+            for usage in limit
+                .apply(
+                    &name,
+                    &mut contents,
+                    self.provider_for(Phase::Summarization),
+                    &self.moderators,
+                )
+                .await
+            {
+                self.record_usage(usage).await;
+            }
+        }
+        Ok(contents)
+    }
+}
+
+/// Run `future`, failing once it has taken longer than `timeout` when there is one
+async fn within<T>(
+    timeout: Option<Duration>,
+    future: impl Future<Output = T>,
+) -> Result<T, Elapsed> {
+    match timeout {
+        Some(timeout) => tokio::time::timeout(timeout, future).await,
+        None => Ok(future.await),
+    }
+}
+
+/// A short stable name for the error, as `error.type` expects
+fn tool_error_type(error: &ToolError) -> &'static str {
+    match error {
+        ToolError::InvalidParameters(_) => "invalid_parameters",
+        ToolError::SchemaError(_) => "schema_error",
+        ToolError::NotFound(_) => "not_found",
+        _ => "execution_error",
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::agents::audit::{AuditEntry, AuditStage};
     use crate::message::Message;
     use crate::model::ModelConfig;
-    use crate::providers::base::{Provider, ProviderMetadata, ProviderUsage, Usage};
-    use crate::providers::errors::ProviderError;
+    use crate::providers::base::ProviderUsage;
+    use crate::providers::testing::TestProvider;
     use mcp_client::client::Error;
     use mcp_client::client::McpClientTrait;
     use mcp_core::protocol::{
@@ -525,35 +1235,6 @@ mod tests {
     };
     use serde_json::json;
 
-    // Mock Provider implementation for testing
-    #[derive(Clone)]
-    struct MockProvider {
-        model_config: ModelConfig,
-    }
-
-    #[async_trait::async_trait]
-    impl Provider for MockProvider {
-        fn metadata() -> ProviderMetadata {
-            ProviderMetadata::empty()
-        }
-
-        fn get_model_config(&self) -> ModelConfig {
-            self.model_config.clone()
-        }
-
-        async fn complete(
-            &self,
-            _system: &str,
-            _messages: &[Message],
-            _tools: &[Tool],
-        ) -> anyhow::Result<(Message, ProviderUsage), ProviderError> {
-            Ok((
-                Message::assistant().with_text("Mock response"),
-                ProviderUsage::new("mock".to_string(), Usage::default()),
-            ))
-        }
-    }
-
     struct MockClient {}
 
     #[async_trait::async_trait]
@@ -587,6 +1268,20 @@ mod tests {
                     content: vec![],
                     is_error: None,
                 }),
+                "brief" => {
+                    tokio::time::sleep(Duration::from_millis(300)).await;
+                    Ok(CallToolResult {
+                        content: vec![],
+                        is_error: None,
+                    })
+                }
+                "slow" => {
+                    tokio::time::sleep(Duration::from_secs(60)).await;
+                    Ok(CallToolResult {
+                        content: vec![],
+                        is_error: None,
+                    })
+                }
                 _ => Err(Error::NotInitialized),
             }
         }
@@ -597,9 +1292,9 @@ mod tests {
         let mock_model_config =
             ModelConfig::new("test-model".to_string()).with_context_limit(200_000.into());
 
-        let mut capabilities = Capabilities::new(Box::new(MockProvider {
-            model_config: mock_model_config,
-        }));
+        let mut capabilities = Capabilities::new(Box::new(
+            TestProvider::new("mock").with_model_config(mock_model_config),
+        ));
 
         // Add some mock clients
         capabilities.clients.insert(
@@ -646,9 +1341,9 @@ mod tests {
         let mock_model_config =
             ModelConfig::new("test-model".to_string()).with_context_limit(200_000.into());
 
-        let mut capabilities = Capabilities::new(Box::new(MockProvider {
-            model_config: mock_model_config,
-        }));
+        let mut capabilities = Capabilities::new(Box::new(
+            TestProvider::new("mock").with_model_config(mock_model_config),
+        ));
 
         // Add some mock clients
         capabilities.clients.insert(
@@ -731,4 +1426,319 @@ mod tests {
         let result = capabilities.dispatch_tool_call(invalid_tool_call).await;
         assert!(matches!(result.err().unwrap(), ToolError::NotFound(_)));
     }
+
+    #[tokio::test]
+    async fn test_dispatch_tool_requests() {
+        let mut capabilities = Capabilities::new(Box::new(
+            TestProvider::new("mock").with_model_config(ModelConfig::new("test-model".to_string())),
+        ));
+        capabilities.set_tool_limits(2, Duration::from_millis(50));
+        capabilities.clients.insert(
+            "test_client".to_string(),
+            Arc::new(Mutex::new(Box::new(MockClient {}))),
+        );
+        capabilities.clients.insert(
+            "other_client".to_string(),
+            Arc::new(Mutex::new(Box::new(MockClient {}))),
+        );
+
+        let request = |id: &str, tool_call| ToolRequest {
+            id: id.to_string(),
+            tool_call,
+        };
+        let call = |name: &str| {
+            Ok(ToolCall {
+                name: name.to_string(),
+                arguments: json!({}),
+            })
+        };
+        let requests = [
+            request("1", call("other_client__slow")),
+            request(
+                "2",
+                Err(ToolError::InvalidParameters("not json".to_string())),
+            ),
+            request("3", call("test_client__tool")),
+            request("4", call("missing__tool")),
+        ];
+        let requests: Vec<&ToolRequest> = requests.iter().collect();
+
+        let started = std::time::Instant::now();
+        let results = capabilities.dispatch_tool_requests(&requests).await;
+        assert!(started.elapsed() < Duration::from_secs(5));
+
+        // Results keep the order of the requests, including the failed ones
+        assert!(
+            matches!(&results[0], Err(ToolError::ExecutionError(e)) if e.contains("did not finish"))
+        );
+        assert!(matches!(results[1], Err(ToolError::InvalidParameters(_))));
+        assert!(results[2].is_ok());
+        assert!(matches!(results[3], Err(ToolError::NotFound(_))));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_timeout_starts_once_the_extension_is_free() {
+        let mut capabilities = Capabilities::new(Box::new(
+            TestProvider::new("mock").with_model_config(ModelConfig::new("test-model".to_string())),
+        ));
+        capabilities.set_tool_limits(3, Duration::from_millis(500));
+        capabilities.clients.insert(
+            "test_client".to_string(),
+            Arc::new(Mutex::new(Box::new(MockClient {}))),
+        );
+
+        // The calls queue for the one extension, together they take longer than the timeout
+        let requests: Vec<ToolRequest> = (0..3)
+            .map(|i| ToolRequest {
+                id: i.to_string(),
+                tool_call: Ok(ToolCall::new("test_client__brief", json!({}))),
+            })
+            .collect();
+        let requests: Vec<&ToolRequest> = requests.iter().collect();
+        let results = capabilities.dispatch_tool_requests(&requests).await;
+        assert!(results.iter().all(|result| result.is_ok()));
+    }
+
+    #[tokio::test]
+    async fn test_tool_permissions() {
+        let mut capabilities = Capabilities::new(Box::new(
+            TestProvider::new("mock").with_model_config(ModelConfig::new("test-model".to_string())),
+        ));
+        capabilities.clients.insert(
+            "developer".to_string(),
+            Arc::new(Mutex::new(Box::new(MockClient {}))),
+        );
+        capabilities.set_permissions(
+            "developer",
+            ExtensionPermissions {
+                default: ToolPolicy::AlwaysAsk,
+                deny: vec!["shell".to_string()],
+                allow: vec!["tool".to_string()],
+                ..Default::default()
+            },
+        );
+
+        // The snapshot for UIs agrees with what dispatching does
+        let policies = capabilities.tool_policies();
+        for name in [
+            "developer__tool",
+            "developer__shell",
+            "developer__edit",
+            "other__x",
+        ] {
+            assert_eq!(policies.get(name), capabilities.tool_policy(name));
+        }
+        assert!(policies.asks("developer__edit"));
+        assert!(!policies.asks("developer__tool"));
+
+        let requests: Vec<ToolRequest> = ["developer__tool", "developer__shell", "developer__edit"]
+            .iter()
+            .map(|name| ToolRequest {
+                id: name.to_string(),
+                tool_call: Ok(ToolCall {
+                    name: name.to_string(),
+                    arguments: json!({}),
+                }),
+            })
+            .collect();
+        let requests: Vec<&ToolRequest> = requests.iter().collect();
+
+        // Nobody to ask, so only the allowed tool runs
+        let results = capabilities.dispatch_tool_requests(&requests).await;
+        assert!(results[0].is_ok());
+        assert!(
+            matches!(&results[1], Err(ToolError::ExecutionError(e)) if e.contains("not allowed"))
+        );
+        assert!(matches!(&results[2], Err(ToolError::ExecutionError(e)) if e.contains("approval")));
+
+        let asked = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let log = asked.clone();
+        capabilities.set_tool_approval(Arc::new(move |tool_call, category| {
+            log.lock().unwrap().push((tool_call.name, category));
+            Box::pin(async { false })
+        }));
+        let results = capabilities.dispatch_tool_requests(&requests).await;
+        assert!(matches!(&results[2], Err(ToolError::ExecutionError(e)) if e.contains("declined")));
+        assert_eq!(
+            *asked.lock().unwrap(),
+            vec![("developer__edit".to_string(), ToolCategory::Write)]
+        );
+
+        // Every decision ends up in the audit log before anything runs, then every result
+        let dir = tempfile::TempDir::new().unwrap();
+        let path = dir.path().join("audit.jsonl");
+        capabilities.set_audit_log(Some(AuditLog::open(&path).unwrap()));
+        let response = Message::assistant().with_text("Editing");
+        let invalid = ToolRequest {
+            id: "invalid".to_string(),
+            tool_call: Err(ToolError::InvalidParameters("not json".to_string())),
+        };
+        let mut requests = requests;
+        requests.push(&invalid);
+        capabilities
+            .dispatch_tool_requests_of(Some(&response), &requests)
+            .await;
+        let entries: Vec<(AuditStage, ApprovalDecision)> = std::fs::read_to_string(&path)
+            .unwrap()
+            .lines()
+            .map(|line| serde_json::from_str::<AuditEntry>(line).unwrap())
+            .map(|entry| (entry.stage, entry.approval.unwrap()))
+            .collect();
+        let approvals = [
+            ApprovalDecision::Auto,
+            ApprovalDecision::Denied,
+            ApprovalDecision::Declined,
+            ApprovalDecision::Invalid,
+        ];
+        let (decisions, outcomes) = entries.split_at(approvals.len());
+        assert_eq!(
+            decisions,
+            approvals.map(|approval| (AuditStage::Decision, approval))
+        );
+        // The results are recorded as the calls finish, in no particular order
+        assert_eq!(outcomes.len(), approvals.len());
+        for approval in approvals {
+            assert!(outcomes.contains(&(AuditStage::Outcome, approval)));
+        }
+        assert_eq!(crate::agents::audit::verify(&path).unwrap().0, 8);
+
+        // A log that can't be written to stops the calls it can't record
+        capabilities.audit_log = Some(Err("The log is corrupt".to_string()));
+        let results = capabilities.dispatch_tool_requests(&requests[..1]).await;
+        assert!(
+            matches!(&results[0], Err(ToolError::ExecutionError(e)) if e.contains("audit log"))
+        );
+    }
+
+    #[tokio::test]
+    async fn test_check_safeguards() {
+        let mut capabilities = Capabilities::new(Box::new(
+            TestProvider::new("mock").with_model_config(ModelConfig::new("test-model".to_string())),
+        ));
+        capabilities.set_safeguards(Safeguards {
+            max_turns: Some(1),
+            max_repeated_calls: None,
+        });
+        let request = ToolRequest {
+            id: "1".to_string(),
+            tool_call: Ok(ToolCall::new("developer__shell", json!({}))),
+        };
+
+        // Nobody to ask, so the agent stops
+        capabilities.start_safeguards();
+        assert_eq!(capabilities.check_safeguards(&[&request]).await, None);
+        assert_eq!(
+            capabilities.check_safeguards(&[&request]).await,
+            Some(SafeguardTrip::MaxTurns(1))
+        );
+        assert_eq!(
+            capabilities.safeguard_trip(),
+            Some(SafeguardTrip::MaxTurns(1))
+        );
+
+        // A run holding the count keeps it across replies
+        capabilities.hold_safeguards(true);
+        capabilities.start_safeguards();
+        assert_eq!(capabilities.check_safeguards(&[&request]).await, None);
+        capabilities.start_safeguards();
+        assert_eq!(capabilities.safeguard_trip(), None);
+        assert_eq!(
+            capabilities.check_safeguards(&[&request]).await,
+            Some(SafeguardTrip::MaxTurns(1))
+        );
+        capabilities.hold_safeguards(false);
+
+        capabilities.set_safeguard_pause(Arc::new(|_| {
+            Box::pin(async { SafeguardDecision::Continue })
+        }));
+        capabilities.start_safeguards();
+        assert_eq!(capabilities.safeguard_trip(), None);
+        for _ in 0..3 {
+            assert_eq!(capabilities.check_safeguards(&[&request]).await, None);
+        }
+    }
+
+    /// Answers with its model name, calling a tool when `calls_tools` is set
+    fn routed(model: &'static str, calls_tools: bool) -> Box<TestProvider> {
+        Box::new(TestProvider::new(model).with_reply(move |_| {
+            let mut response = Message::assistant().with_text(model);
+            if calls_tools {
+                response = response
+                    .with_tool_request("2", Ok(ToolCall::new("developer__shell", json!({}))));
+            }
+            Ok(response)
+        }))
+    }
+
+    #[tokio::test]
+    async fn test_router_picks_provider_per_phase() {
+        let mut capabilities = Capabilities::new(routed("strong", false));
+        let question = Message::user().with_text("what is in src?");
+        let call = Message::assistant()
+            .with_tool_request("1", Ok(ToolCall::new("developer__shell", json!({}))));
+        let result = Message::user().with_tool_response("1", Ok(vec![Content::text("main.rs")]));
+        let first = [question.clone()];
+        let after_tools = [question, call, result];
+
+        capabilities
+            .set_router(ModelRouter::new().with_route(Phase::ToolCalls, routed("cheap", true)));
+        let model = |(response, _): (Message, ProviderUsage)| response.as_concat_text();
+        let (planning, tool_calls) = (
+            capabilities.complete("", &first, &[]).await.unwrap(),
+            capabilities.complete("", &after_tools, &[]).await.unwrap(),
+        );
+        assert_eq!(model(planning), "strong");
+        assert_eq!(model(tool_calls), "cheap");
+
+        // The cheap model ending the run hands the answer to the final answer model
+        capabilities.set_router(
+            ModelRouter::new()
+                .with_route(Phase::ToolCalls, routed("cheap", false))
+                .with_route(Phase::FinalAnswer, routed("final", false)),
+        );
+        let (response, usage) = capabilities.complete("", &after_tools, &[]).await.unwrap();
+        assert_eq!(response.as_concat_text(), "final");
+        assert_eq!(usage.model, "final");
+        let recorded: Vec<_> = capabilities
+            .get_usage()
+            .await
+            .into_iter()
+            .map(|usage| usage.model)
+            .collect();
+        assert_eq!(recorded, ["cheap"]);
+    }
+
+    #[tokio::test]
+    async fn test_text_deltas() {
+        let mut capabilities = Capabilities::new(routed("strong", false));
+        let received = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let sink = received.clone();
+        capabilities.set_text_deltas(Some(Arc::new(move |text: &str| {
+            sink.lock().unwrap().push(text.to_string())
+        })));
+        let question = [Message::user().with_text("hi")];
+
+        let (response, _) = capabilities.complete("", &question, &[]).await.unwrap();
+        assert_eq!(response.as_concat_text(), "strong");
+        assert_eq!(*received.lock().unwrap(), ["strong"]);
+
+        // A discarded answer is not streamed, the final answer is
+        let call = Message::assistant()
+            .with_tool_request("1", Ok(ToolCall::new("developer__shell", json!({}))));
+        let result = Message::user().with_tool_response("1", Ok(vec![Content::text("main.rs")]));
+        let after_tools = [question[0].clone(), call, result];
+        capabilities.set_router(
+            ModelRouter::new()
+                .with_route(Phase::ToolCalls, routed("cheap", false))
+                .with_route(Phase::FinalAnswer, routed("final", false)),
+        );
+        capabilities.complete("", &after_tools, &[]).await.unwrap();
+        assert_eq!(*received.lock().unwrap(), ["strong", "final"]);
+
+        // Moderators may rewrite the response, so nothing is streamed past them
+        capabilities.set_router(ModelRouter::new());
+        capabilities.add_moderator(Arc::new(moderation::SecretRedactor::new()));
+        capabilities.complete("", &question, &[]).await.unwrap();
+        assert_eq!(received.lock().unwrap().len(), 2);
+    }
 }
@@ -1,9 +1,15 @@
 use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::Arc;
 
 use mcp_client::client::Error as ClientError;
+use mcp_client::oauth::{DeviceAuthorization, DeviceFlowAuth, OAuthConfig, OAuthToken};
+use mcp_client::transport::{BearerToken, Error as TransportError, HttpAuth, StdioTransport};
 use serde::{Deserialize, Serialize};
 use thiserror::Error;
 
+use crate::config::Config;
+
 /// Errors from Extension operation
 #[derive(Error, Debug)]
 pub enum ExtensionError {
@@ -40,6 +46,152 @@ impl Envs {
     }
 }
 
+/// What the process of a stdio extension gets to see
+///
+/// By default it runs in goose's working directory with all of goose's
+/// environment, which includes credentials it has no use for.
+#[derive(Debug, Clone, Default, PartialEq, Deserialize, Serialize)]
+pub struct Sandbox {
+    /// The directory the server runs in
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub working_dir: Option<PathBuf>,
+    /// The variables of goose's environment the server gets, on top of the
+    /// basics such as PATH and HOME. All of them when not set. The `envs` of
+    /// the extension are always passed.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub env_allowlist: Option<Vec<String>>,
+    /// Run the server without network access
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub no_network: bool,
+}
+
+/// Variables a server needs to find its command and a place to write,
+/// passed even with an allow-list
+const BASE_ENV: [&str; 9] = [
+    "PATH",
+    "HOME",
+    "USER",
+    "LOGNAME",
+    "SHELL",
+    "LANG",
+    "TERM",
+    "TMPDIR",
+    "SYSTEMROOT",
+];
+
+impl Sandbox {
+    pub fn is_unrestricted(&self) -> bool {
+        self == &Self::default()
+    }
+
+    /// Apply the restrictions to the transport that launches the server
+    pub fn apply(&self, mut transport: StdioTransport) -> StdioTransport {
+        if let Some(dir) = &self.working_dir {
+            let dir = match (dir.strip_prefix("~"), dirs::home_dir()) {
+                (Ok(rest), Some(home)) => home.join(rest),
+                _ => dir.clone(),
+            };
+            transport = transport.with_working_dir(dir);
+        }
+        if let Some(allowlist) = &self.env_allowlist {
+            let names = BASE_ENV
+                .iter()
+                .map(|name| name.to_string())
+                .chain(allowlist.iter().cloned())
+                .collect();
+            transport = transport.with_inherited_env(names);
+        }
+        if self.no_network {
+            transport = transport.without_network();
+        }
+        transport
+    }
+}
+
+/// Shows the user where to enter the code that connects an extension, given its name
+pub type DeviceCodePrompt = Arc<dyn Fn(&str, &DeviceAuthorization) + Send + Sync>;
+
+/// How goose authorizes itself to an MCP server reached over HTTP
+#[derive(Debug, Clone, Default, PartialEq, Deserialize, Serialize)]
+#[serde(tag = "type")]
+pub enum HttpAuthConfig {
+    #[default]
+    #[serde(rename = "none")]
+    None,
+    /// A bearer token, kept as the secret `token_key`
+    #[serde(rename = "bearer")]
+    Bearer { token_key: String },
+    /// A token from the OAuth 2.0 device flow, kept as a secret once granted
+    #[serde(rename = "oauth")]
+    OAuth(OAuthConfig),
+}
+
+impl HttpAuthConfig {
+    pub fn is_none(&self) -> bool {
+        self == &Self::None
+    }
+
+    /// The secret the OAuth token of `extension` is kept as
+    fn token_secret(extension: &str) -> String {
+        format!("oauth_token_{}", extension)
+    }
+
+    /// What authorizes the requests to the server of `extension`
+    ///
+    /// The code of an OAuth device flow is logged, and shown with `prompt` when given.
+    pub fn provider(
+        &self,
+        extension: &str,
+        prompt: Option<DeviceCodePrompt>,
+    ) -> Result<Option<Arc<dyn HttpAuth>>, TransportError> {
+        let config = Config::global();
+        match self {
+            Self::None => Ok(None),
+            Self::Bearer { token_key } => {
+                let token: String = config.get_secret(token_key).map_err(|_| {
+                    TransportError::Authorization(format!(
+                        "No secret {} with the token for {}, add it with goose configure",
+                        token_key, extension
+                    ))
+                })?;
+                Ok(Some(Arc::new(BearerToken::new(token))))
+            }
+            Self::OAuth(oauth) => {
+                let secret = Self::token_secret(extension);
+                let name = extension.to_string();
+                let auth = DeviceFlowAuth::new(oauth.clone(), move |authorization| {
+                    tracing::info!(
+                        "To connect {}, open {} and enter the code {}",
+                        name,
+                        authorization
+                            .verification_uri_complete
+                            .as_ref()
+                            .unwrap_or(&authorization.verification_uri),
+                        authorization.user_code
+                    );
+                    if let Some(prompt) = &prompt {
+                        prompt(&name, authorization);
+                    }
+                })
+                .with_token(config.get_secret::<OAuthToken>(&secret).ok())
+                .on_token(move |token| {
+                    if let Err(e) = serde_json::to_value(token)
+                        .map_err(|e| e.to_string())
+                        .and_then(|token| {
+                            Config::global()
+                                .set_secret(&secret, token)
+                                .map_err(|e| e.to_string())
+                        })
+                    {
+                        tracing::warn!("Failed to keep the OAuth token: {}", e);
+                    }
+                });
+                Ok(Some(Arc::new(auth)))
+            }
+        }
+    }
+}
+
 /// Represents the different types of MCP extensions that can be added to the manager
 #[derive(Debug, Clone, Deserialize, Serialize)]
 #[serde(tag = "type")]
@@ -62,6 +214,20 @@ pub enum ExtensionConfig {
         args: Vec<String>,
         #[serde(default)]
         envs: Envs,
+        #[serde(default, skip_serializing_if = "Sandbox::is_unrestricted")]
+        sandbox: Sandbox,
+    },
+    /// MCP server reached over HTTP with the Streamable HTTP transport
+    #[serde(rename = "streamable_http")]
+    StreamableHttp {
+        /// The name used to identify this extension
+        name: String,
+        uri: String,
+        /// Headers sent with every request
+        #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+        headers: HashMap<String, String>,
+        #[serde(default, skip_serializing_if = "HttpAuthConfig::is_none")]
+        auth: HttpAuthConfig,
     },
     /// Built-in extension that is part of the goose binary
     #[serde(rename = "builtin")]
@@ -88,12 +254,22 @@ impl ExtensionConfig {
         }
     }
 
+    pub fn streamable_http<S: Into<String>>(name: S, uri: S) -> Self {
+        Self::StreamableHttp {
+            name: name.into(),
+            uri: uri.into(),
+            headers: HashMap::new(),
+            auth: HttpAuthConfig::None,
+        }
+    }
+
     pub fn stdio<S: Into<String>>(name: S, cmd: S) -> Self {
         Self::Stdio {
             name: name.into(),
             cmd: cmd.into(),
             args: vec![],
             envs: Envs::default(),
+            sandbox: Sandbox::default(),
         }
     }
 
@@ -104,22 +280,48 @@ impl ExtensionConfig {
     {
         match self {
             Self::Stdio {
-                name, cmd, envs, ..
+                name,
+                cmd,
+                envs,
+                sandbox,
+                ..
             } => Self::Stdio {
                 name,
                 cmd,
                 envs,
+                sandbox,
                 args: args.into_iter().map(Into::into).collect(),
             },
             other => other,
         }
     }
 
+    /// Restrict what the process of a stdio extension can see
+    pub fn with_sandbox(self, sandbox: Sandbox) -> Self {
+        match self {
+            Self::Stdio {
+                name,
+                cmd,
+                args,
+                envs,
+                ..
+            } => Self::Stdio {
+                name,
+                cmd,
+                args,
+                envs,
+                sandbox,
+            },
+            other => other,
+        }
+    }
+
     /// Get the extension name regardless of variant
     pub fn name(&self) -> &str {
         match self {
             Self::Sse { name, .. } => name,
             Self::Stdio { name, .. } => name,
+            Self::StreamableHttp { name, .. } => name,
             Self::Builtin { name } => name,
         }
     }
@@ -134,6 +336,9 @@ impl std::fmt::Display for ExtensionConfig {
             } => {
                 write!(f, "Stdio({}: {} {})", name, cmd, args.join(" "))
             }
+            ExtensionConfig::StreamableHttp { name, uri, .. } => {
+                write!(f, "StreamableHttp({}: {})", name, uri)
+            }
             ExtensionConfig::Builtin { name } => write!(f, "Builtin({})", name),
         }
     }
@@ -156,3 +361,119 @@ impl ExtensionInfo {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sandbox_config() {
+        let config: ExtensionConfig = serde_yaml::from_str(
+            "type: stdio\n\
+             name: github\n\
+             cmd: npx\n\
+             args: [-y, '@modelcontextprotocol/server-github']\n\
+             sandbox:\n  working_dir: /tmp/github\n  env_allowlist: [GITHUB_TOKEN]\n",
+        )
+        .unwrap();
+        let ExtensionConfig::Stdio { sandbox, .. } = &config else {
+            panic!("expected a stdio extension");
+        };
+        assert_eq!(sandbox.working_dir, Some(PathBuf::from("/tmp/github")));
+        assert_eq!(
+            sandbox.env_allowlist,
+            Some(vec!["GITHUB_TOKEN".to_string()])
+        );
+        assert!(!sandbox.no_network);
+
+        // Extensions without restrictions are stored as before
+        let config = ExtensionConfig::stdio("echo", "echo");
+        let value = serde_json::to_value(&config).unwrap();
+        assert!(value.get("sandbox").is_none());
+        let config = config.with_sandbox(Sandbox {
+            no_network: true,
+            ..Default::default()
+        });
+        let value = serde_json::to_value(&config).unwrap();
+        assert_eq!(value["sandbox"], serde_json::json!({"no_network": true}));
+    }
+
+    #[test]
+    fn test_streamable_http_config() {
+        let config: ExtensionConfig = serde_yaml::from_str(
+            "type: streamable_http\n\
+             name: linear\n\
+             uri: https://mcp.linear.app/mcp\n\
+             auth:\n  type: bearer\n  token_key: linear_token\n",
+        )
+        .unwrap();
+        let ExtensionConfig::StreamableHttp { auth, .. } = &config else {
+            panic!("expected a streamable http extension");
+        };
+        assert_eq!(
+            auth,
+            &HttpAuthConfig::Bearer {
+                token_key: "linear_token".to_string()
+            }
+        );
+
+        let config = ExtensionConfig::streamable_http("docs", "https://example.com/mcp");
+        let value = serde_json::to_value(&config).unwrap();
+        assert!(value.get("auth").is_none());
+    }
+
+    #[tokio::test]
+    async fn test_device_code_prompt() {
+        let server = wiremock::MockServer::start().await;
+        wiremock::Mock::given(wiremock::matchers::path("/device"))
+            .respond_with(
+                wiremock::ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                    "device_code": "device-1",
+                    "user_code": "ABCD-EFGH",
+                    "verification_uri": "https://example.com/activate",
+                    "expires_in": 600,
+                    "interval": 0
+                })),
+            )
+            .mount(&server)
+            .await;
+        wiremock::Mock::given(wiremock::matchers::path("/token"))
+            .respond_with(
+                wiremock::ResponseTemplate::new(400)
+                    .set_body_json(serde_json::json!({"error": "access_denied"})),
+            )
+            .mount(&server)
+            .await;
+
+        let auth = HttpAuthConfig::OAuth(OAuthConfig {
+            client_id: "goose".to_string(),
+            device_authorization_url: format!("{}/device", server.uri()),
+            token_url: format!("{}/token", server.uri()),
+            scopes: Vec::new(),
+        });
+        let shown = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let prompt: DeviceCodePrompt = {
+            let shown = shown.clone();
+            Arc::new(move |extension, authorization| {
+                shown
+                    .lock()
+                    .unwrap()
+                    .push((extension.to_string(), authorization.user_code.clone()));
+            })
+        };
+        let provider = auth
+            .provider("goose-test-device-code", Some(prompt))
+            .unwrap()
+            .unwrap();
+
+        // The user declined, but was shown the code first
+        assert!(provider.header().await.is_err());
+        assert_eq!(
+            *shown.lock().unwrap(),
+            vec![(
+                "goose-test-device-code".to_string(),
+                "ABCD-EFGH".to_string()
+            )]
+        );
+    }
+}
@@ -1,11 +1,23 @@
 mod agent;
+pub mod audit;
 mod capabilities;
 pub mod extension;
 mod factory;
+pub mod moderation;
 mod reference;
+pub mod router;
+pub mod run;
+pub mod safeguard;
+pub mod tool_output;
 mod truncate;
 
 pub use agent::Agent;
-pub use capabilities::Capabilities;
+pub use audit::{ApprovalDecision, AuditEntry, AuditLog, AuditStage};
+pub use capabilities::{Capabilities, TextDeltas, ToolApproval, ToolPolicies};
 pub use extension::ExtensionConfig;
 pub use factory::{register_agent, AgentFactory};
+pub use moderation::{ModerationError, Moderator, SecretRedactor};
+pub use router::{ModelRouter, Phase, Route};
+pub use run::{RunLimits, RunResult, RunStatus, ToolCallRecord};
+pub use safeguard::{SafeguardDecision, SafeguardPause, SafeguardTrip, Safeguards};
+pub use tool_output::{OverflowStrategy, ToolOutputLimit};
@@ -1,14 +1,18 @@
 /// A simplified agent implementation used as a reference
 /// It makes no attempt to handle context limits, and cannot read resources
+/// It has no semantic memory either, GOOSE_MEMORY only applies to the truncate agent
 use async_trait::async_trait;
 use futures::stream::BoxStream;
 use tokio::sync::Mutex;
 use tracing::{debug, instrument};
 
 use super::Agent;
-use crate::agents::capabilities::Capabilities;
-use crate::agents::extension::{ExtensionConfig, ExtensionResult};
+use crate::agents::capabilities::{Capabilities, TextDeltas, ToolApproval, ToolPolicies};
+use crate::agents::extension::{DeviceCodePrompt, ExtensionConfig, ExtensionResult};
+use crate::agents::moderation::Moderator;
+use crate::agents::safeguard::{stopped_tool_responses, SafeguardPause, SafeguardTrip};
 use crate::message::{Message, ToolRequest};
+use crate::plan::Plan;
 use crate::providers::base::Provider;
 use crate::providers::base::ProviderUsage;
 use crate::register_agent;
@@ -16,6 +20,7 @@ use crate::token_counter::TokenCounter;
 use indoc::indoc;
 use mcp_core::tool::Tool;
 use serde_json::{json, Value};
+use std::sync::Arc;
 
 /// Reference implementation of an Agent
 pub struct ReferenceAgent {
@@ -114,8 +119,9 @@ impl Agent for ReferenceAgent {
             tools.push(read_resource_tool);
             tools.push(list_resources_tool);
         }
+        tools.extend(capabilities.platform_tools());
 
-        let system_prompt = capabilities.get_system_prompt().await;
+        let system_prompt = capabilities.get_system_prompt(&tools).await;
 
         // Set the user_message field in the span instead of creating a new event
         if let Some(content) = messages
@@ -126,16 +132,22 @@ impl Agent for ReferenceAgent {
             debug!("user_message" = &content);
         }
 
+        capabilities.start_safeguards();
+
         Ok(Box::pin(async_stream::try_stream! {
             let _reply_guard = reply_span.enter();
             loop {
+                capabilities.warn_on_unsupported_images(&messages);
+                let (request_system, request_messages) =
+                    capabilities.moderate_request(&system_prompt, &messages).await?;
                 // Get completion from provider
-                let (response, usage) = capabilities.provider().complete(
-                    &system_prompt,
-                    &messages,
+                let (response, usage) = capabilities.complete(
+                    &request_system,
+                    &request_messages,
                     &tools,
                 ).await?;
                 capabilities.record_usage(usage).await;
+                let response = capabilities.moderate_response(response).await?;
 
                 // Yield the assistant's response
                 yield response.clone();
@@ -152,15 +164,17 @@ impl Agent for ReferenceAgent {
                     break;
                 }
 
-                // Then dispatch each in parallel
-                let futures: Vec<_> = tool_requests
-                    .iter()
-                    .filter_map(|request| request.tool_call.clone().ok())
-                    .map(|tool_call| capabilities.dispatch_tool_call(tool_call))
-                    .collect();
+                if let Some(trip) = capabilities.check_safeguards(&tool_requests).await {
+                    yield stopped_tool_responses(&tool_requests, &trip);
+                    yield Message::assistant().with_text(format!(
+                        "I stopped because {}. Tell me how to go on, or what to try differently.",
+                        trip
+                    ));
+                    break;
+                }
 
-                // Process all the futures in parallel but wait until all are finished
-                let outputs = futures::future::join_all(futures).await;
+                // Then dispatch them in parallel and wait until all are finished
+                let outputs = capabilities.dispatch_tool_requests_of(Some(&response), &tool_requests).await;
 
                 // Create a message with the responses
                 let mut message_tool_response = Message::user();
@@ -180,6 +194,56 @@ impl Agent for ReferenceAgent {
         }))
     }
 
+    async fn set_device_code_prompt(&mut self, prompt: DeviceCodePrompt) {
+        let mut capabilities = self.capabilities.lock().await;
+        capabilities.set_device_code_prompt(prompt);
+    }
+
+    async fn set_tool_approval(&mut self, approval: ToolApproval) {
+        let mut capabilities = self.capabilities.lock().await;
+        capabilities.set_tool_approval(approval);
+    }
+
+    async fn set_text_deltas(&mut self, deltas: Option<TextDeltas>) {
+        let mut capabilities = self.capabilities.lock().await;
+        capabilities.set_text_deltas(deltas);
+    }
+
+    async fn tool_policies(&self) -> ToolPolicies {
+        let capabilities = self.capabilities.lock().await;
+        capabilities.tool_policies()
+    }
+
+    async fn set_safeguard_pause(&mut self, pause: SafeguardPause) {
+        let mut capabilities = self.capabilities.lock().await;
+        capabilities.set_safeguard_pause(pause);
+    }
+
+    async fn safeguard_trip(&self) -> Option<SafeguardTrip> {
+        let capabilities = self.capabilities.lock().await;
+        capabilities.safeguard_trip()
+    }
+
+    async fn hold_safeguards(&self, held: bool) {
+        let mut capabilities = self.capabilities.lock().await;
+        capabilities.hold_safeguards(held);
+    }
+
+    async fn plan(&self, messages: &[Message]) -> anyhow::Result<Plan> {
+        let mut capabilities = self.capabilities.lock().await;
+        capabilities.plan(messages).await
+    }
+
+    async fn extend_system_prompt(&mut self, instructions: String) {
+        let mut capabilities = self.capabilities.lock().await;
+        capabilities.extend_system_prompt(instructions);
+    }
+
+    async fn add_moderator(&mut self, moderator: Arc<dyn Moderator>) {
+        let mut capabilities = self.capabilities.lock().await;
+        capabilities.add_moderator(moderator);
+    }
+
     async fn usage(&self) -> Vec<ProviderUsage> {
         let capabilities = self.capabilities.lock().await;
         capabilities.get_usage().await
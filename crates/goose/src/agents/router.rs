@@ -0,0 +1,176 @@
+use std::collections::HashMap;
+
+use serde::{Deserialize, Serialize};
+use tracing::warn;
+
+use crate::config::Config;
+use crate::message::{Message, MessageContent};
+use crate::model::ModelConfig;
+use crate::providers::base::Provider;
+
+/// A phase of a reply that can run on a model of its own
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Phase {
+    /// Plans, and the first response to a user message
+    Planning,
+    /// The responses that read tool results and make the next tool calls
+    ToolCalls,
+    /// Summaries of the conversation and of long tool output
+    Summarization,
+    /// The answer that ends a run of tool calls
+    FinalAnswer,
+}
+
+impl Phase {
+    /// The phase of the request that continues `messages`
+    ///
+    /// A conversation that ends with tool results is in the middle of a run of
+    /// tool calls, any other one starts with planning.
+    pub fn of(messages: &[Message]) -> Self {
+        let after_tools = messages.last().is_some_and(|message| {
+            message
+                .content
+                .iter()
+                .any(|content| matches!(content, MessageContent::ToolResponse(_)))
+        });
+        if after_tools {
+            Self::ToolCalls
+        } else {
+            Self::Planning
+        }
+    }
+}
+
+/// The model a phase runs on, with the agent's provider unless another is named
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Route {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub provider: Option<String>,
+    pub model: String,
+}
+
+impl Route {
+    /// Create the provider of the route, `agent` is the provider of the agent
+    pub fn create(&self, agent: &dyn Provider) -> anyhow::Result<Box<dyn Provider>> {
+        let name = self
+            .provider
+            .clone()
+            .unwrap_or_else(|| agent.instance_metadata().name);
+        Ok(crate::providers::create(
+            &name,
+            ModelConfig::new(self.model.clone()),
+        )?)
+    }
+
+    /// The routes in GOOSE_MODEL_ROUTES, a map from phase to route
+    ///
+    /// For example, to run tool calls on a cheaper model of the same provider:
+    ///
+    /// ```yaml
+    /// GOOSE_MODEL_ROUTES:
+    ///   tool_calls:
+    ///     model: gpt-4o-mini
+    ///   planning:
+    ///     provider: anthropic
+    ///     model: claude-3-5-sonnet-latest
+    /// ```
+    pub fn configured() -> HashMap<Phase, Route> {
+        match Config::global().get("GOOSE_MODEL_ROUTES") {
+            Ok(routes) => routes,
+            Err(crate::config::ConfigError::NotFound(_)) => HashMap::new(),
+            Err(e) => {
+                warn!("Could not read GOOSE_MODEL_ROUTES, using one model: {}", e);
+                HashMap::new()
+            }
+        }
+    }
+}
+
+/// Sends each phase of a reply to the provider configured for it
+///
+/// Phases without a route of their own use the agent's provider. The final
+/// answer can only be told apart once the response has no tool calls, so with
+/// a route for it that last response of the tool calls model is asked for
+/// again from the final answer model.
+#[derive(Default)]
+pub struct ModelRouter {
+    routes: HashMap<Phase, Box<dyn Provider>>,
+}
+
+impl ModelRouter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Run `phase` on `provider`
+    pub fn with_route(mut self, phase: Phase, provider: Box<dyn Provider>) -> Self {
+        self.routes.insert(phase, provider);
+        self
+    }
+
+    /// Configure from GOOSE_MODEL_ROUTES, `agent` is the provider of the agent
+    pub fn from_config(agent: &dyn Provider) -> Self {
+        let mut router = Self::new();
+        for (phase, route) in Route::configured() {
+            match route.create(agent) {
+                Ok(provider) => router = router.with_route(phase, provider),
+                Err(e) => warn!(
+                    "Could not create {} for {:?}, using the agent's model: {}",
+                    route.model, phase, e
+                ),
+            }
+        }
+        router
+    }
+
+    /// The provider of `phase`, if it has a route
+    pub fn route(&self, phase: Phase) -> Option<&dyn Provider> {
+        self.routes.get(&phase).map(|provider| provider.as_ref())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::providers::testing::TestProvider;
+    use mcp_core::{Content, ToolCall};
+
+    #[test]
+    fn test_phase_of_conversation() {
+        let question = Message::user().with_text("what is in src?");
+        let call = Message::assistant().with_tool_request(
+            "1",
+            Ok(ToolCall::new(
+                "developer__shell",
+                serde_json::json!({"command": "ls"}),
+            )),
+        );
+        let result = Message::user().with_tool_response("1", Ok(vec![Content::text("main.rs")]));
+
+        assert_eq!(Phase::of(&[]), Phase::Planning);
+        assert_eq!(Phase::of(std::slice::from_ref(&question)), Phase::Planning);
+        assert_eq!(Phase::of(&[question, call, result]), Phase::ToolCalls);
+    }
+
+    #[test]
+    fn test_routes() {
+        let router =
+            ModelRouter::new().with_route(Phase::ToolCalls, Box::new(TestProvider::new("mini")));
+        let model = |phase| router.route(phase).map(|p| p.get_model_config().model_name);
+        assert_eq!(model(Phase::ToolCalls).as_deref(), Some("mini"));
+        assert_eq!(model(Phase::Planning), None);
+
+        let routes: HashMap<Phase, Route> = serde_yaml::from_str(
+            "tool_calls:\n  model: mini\nfinal_answer:\n  provider: openai\n  model: big\n",
+        )
+        .unwrap();
+        assert_eq!(
+            routes[&Phase::FinalAnswer],
+            Route {
+                provider: Some("openai".to_string()),
+                model: "big".to_string()
+            }
+        );
+    }
+}
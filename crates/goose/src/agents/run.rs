@@ -0,0 +1,521 @@
+//! Running an agent to the end of a task without anyone at the prompt
+//!
+//! `Agent::run_to_completion` keeps replying until the model answers without
+//! calling a tool, or until a limit on responses or cost or a safeguard is
+//! reached, and returns what happened as a `RunResult` that CI jobs can
+//! inspect or serialize.
+use anyhow::Result;
+use futures::StreamExt;
+use mcp_core::role::Role;
+use serde::Serialize;
+use serde_json::Value;
+
+use super::safeguard::SafeguardTrip;
+use super::Agent;
+use crate::message::{Message, MessageContent};
+use crate::providers::base::{ProviderUsage, Usage};
+use crate::providers::pricing;
+use crate::recipe::Recipe;
+
+/// When to stop a run that hasn't finished
+#[derive(Debug, Clone, Copy, Default)]
+pub struct RunLimits {
+    /// Responses of the model, the run stops after the tool results of the last one
+    ///
+    /// Unlike the GOOSE_MAX_TURNS safeguard this ends the run, there is no
+    /// pausing to ask whether to go on.
+    pub max_responses: Option<usize>,
+    /// US dollars at list prices, checked after every turn
+    ///
+    /// The run fails when a model it used has no known price.
+    pub max_cost: Option<f64>,
+}
+
+impl RunLimits {
+    pub fn with_max_responses(mut self, max_responses: usize) -> Self {
+        self.max_responses = Some(max_responses);
+        self
+    }
+
+    pub fn with_max_cost(mut self, max_cost: f64) -> Self {
+        self.max_cost = Some(max_cost);
+        self
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(tag = "status", content = "error", rename_all = "snake_case")]
+pub enum RunStatus {
+    /// The model answered without calling a tool
+    Completed,
+    MaxResponses,
+    BudgetExceeded,
+    Failed(String),
+    /// The agent stopped at a safeguard, there was nobody to ask whether to go on
+    Paused(SafeguardTrip),
+}
+
+impl RunStatus {
+    /// The exit code of a process that ends with this status
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            RunStatus::Completed => 0,
+            RunStatus::Failed(_) => 1,
+            RunStatus::MaxResponses => 2,
+            RunStatus::BudgetExceeded => 3,
+            RunStatus::Paused(_) => 4,
+        }
+    }
+}
+
+/// A tool call of the model and what it returned
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ToolCallRecord {
+    pub id: String,
+    pub name: String,
+    pub arguments: Value,
+    /// The text the tool returned, None until it has
+    pub output: Option<String>,
+    /// Why the call or the tool failed
+    pub error: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct RunResult {
+    pub status: RunStatus,
+    /// The last response of the model
+    pub final_message: Option<Message>,
+    /// The whole conversation, starting with the messages the run was given
+    pub messages: Vec<Message>,
+    pub tool_calls: Vec<ToolCallRecord>,
+    /// Usage of the run by model
+    pub usage: Vec<ProviderUsage>,
+    pub turns: usize,
+}
+
+impl RunResult {
+    /// The cost of the run in US dollars, None when the price of a model is unknown
+    pub fn cost(&self) -> Option<f64> {
+        cost(&self.usage)
+    }
+}
+
+fn cost(usage: &[ProviderUsage]) -> Option<f64> {
+    usage
+        .iter()
+        .map(|usage| pricing::request_cost(&usage.model, &usage.usage))
+        .sum()
+}
+
+/// Why `max_cost` can not be enforced for `usage`
+fn unpriced(usage: &[ProviderUsage]) -> String {
+    let models: Vec<&str> = usage
+        .iter()
+        .filter(|usage| pricing::request_cost(&usage.model, &usage.usage).is_none())
+        .map(|usage| usage.model.as_str())
+        .collect();
+    format!(
+        "max_cost can not be enforced, there is no price for {}. Add one to GOOSE_PRICING",
+        models.join(", ")
+    )
+}
+
+/// The usage in `after` that is not in `before`
+fn usage_since(before: &[ProviderUsage], after: Vec<ProviderUsage>) -> Vec<ProviderUsage> {
+    let minus = |a: Option<i32>, b: Option<i32>| a.map(|a| a - b.unwrap_or(0));
+    after
+        .into_iter()
+        .filter_map(|mut usage| {
+            if let Some(earlier) = before.iter().find(|u| u.model == usage.model) {
+                usage.usage = Usage::new(
+                    minus(usage.usage.input_tokens, earlier.usage.input_tokens),
+                    minus(usage.usage.output_tokens, earlier.usage.output_tokens),
+                    minus(usage.usage.total_tokens, earlier.usage.total_tokens),
+                );
+                if usage.usage.total_tokens == Some(0) {
+                    return None;
+                }
+            }
+            Some(usage)
+        })
+        .collect()
+}
+
+fn record_message(message: &Message, tool_calls: &mut Vec<ToolCallRecord>) {
+    for content in &message.content {
+        match content {
+            MessageContent::ToolRequest(request) => {
+                let record = match &request.tool_call {
+                    Ok(call) => ToolCallRecord {
+                        id: request.id.clone(),
+                        name: call.name.clone(),
+                        arguments: call.arguments.clone(),
+                        output: None,
+                        error: None,
+                    },
+                    Err(e) => ToolCallRecord {
+                        id: request.id.clone(),
+                        name: String::new(),
+                        arguments: Value::Null,
+                        output: None,
+                        error: Some(e.to_string()),
+                    },
+                };
+                tool_calls.push(record);
+            }
+            MessageContent::ToolResponse(response) => {
+                let Some(record) = tool_calls.iter_mut().rev().find(|r| r.id == response.id) else {
+                    continue;
+                };
+                match &response.tool_result {
+                    Ok(contents) => {
+                        let text: Vec<&str> = contents.iter().filter_map(|c| c.as_text()).collect();
+                        record.output = Some(text.join("\n"));
+                    }
+                    Err(e) => record.error = Some(e.to_string()),
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Reply to `messages` until the model is done or a limit of `limits` is reached
+pub(super) async fn run_to_completion<A: Agent + ?Sized>(
+    agent: &A,
+    messages: &[Message],
+    limits: RunLimits,
+) -> RunResult {
+    let mut messages = messages.to_vec();
+    let mut tool_calls = Vec::new();
+    let mut final_message = None;
+    let mut turns = 0;
+    let earlier_usage = agent.usage().await;
+    // The run may reply more than once, the safeguards count the whole of it
+    agent.hold_safeguards(true).await;
+
+    let status = 'run: loop {
+        let mut stream = match agent.reply(&messages).await {
+            Ok(stream) => stream,
+            Err(e) => break RunStatus::Failed(e.to_string()),
+        };
+        loop {
+            let message = match stream.next().await {
+                None => {
+                    // Stopping at a safeguard ends the reply like an answer does
+                    drop(stream);
+                    break 'run match agent.safeguard_trip().await {
+                        Some(trip) => RunStatus::Paused(trip),
+                        None => RunStatus::Completed,
+                    };
+                }
+                Some(Err(e)) => break 'run RunStatus::Failed(e.to_string()),
+                Some(Ok(message)) => message,
+            };
+            record_message(&message, &mut tool_calls);
+            let ends_turn = message.role == Role::User && message.is_tool_response();
+            if message.role == Role::Assistant {
+                turns += 1;
+                final_message = Some(message.clone());
+            }
+            messages.push(message);
+
+            if ends_turn {
+                if limits.max_responses.is_some_and(|max| turns >= max) {
+                    break 'run RunStatus::MaxResponses;
+                }
+                // The usage can only be read once the reply has let go of the agent
+                if limits.max_cost.is_some() {
+                    break;
+                }
+            }
+        }
+        drop(stream);
+        // The answer to calls stopped at a safeguard ends the turn too
+        if let Some(trip) = agent.safeguard_trip().await {
+            break RunStatus::Paused(trip);
+        }
+
+        let usage = usage_since(&earlier_usage, agent.usage().await);
+        if let Some(max) = limits.max_cost {
+            match cost(&usage) {
+                Some(cost) if cost >= max => break RunStatus::BudgetExceeded,
+                Some(_) => {}
+                // Going on would spend without a limit
+                None => break RunStatus::Failed(unpriced(&usage)),
+            }
+        }
+    };
+    agent.hold_safeguards(false).await;
+
+    RunResult {
+        status,
+        final_message,
+        messages,
+        tool_calls,
+        usage: usage_since(&earlier_usage, agent.usage().await),
+        turns,
+    }
+}
+
+/// Start the extensions of `recipe`, add its instructions and run its prompt
+pub(super) async fn run_recipe<A: Agent + ?Sized>(
+    agent: &mut A,
+    recipe: &Recipe,
+    limits: RunLimits,
+) -> Result<RunResult> {
+    let prompt = recipe
+        .prompt
+        .clone()
+        .ok_or_else(|| anyhow::anyhow!("the recipe {} has no prompt to run", recipe.title))?;
+    for config in &recipe.extensions {
+        agent.add_extension(config.clone()).await?;
+    }
+    if let Some(instructions) = &recipe.instructions {
+        agent.extend_system_prompt(instructions.clone()).await;
+    }
+    Ok(run_to_completion(agent, &[Message::user().with_text(prompt)], limits).await)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::agents::extension::{DeviceCodePrompt, ExtensionResult};
+    use crate::agents::reference::ReferenceAgent;
+    use crate::agents::{Moderator, SafeguardPause, TextDeltas, ToolApproval, ToolPolicies};
+    use crate::plan::Plan;
+    use crate::providers::testing::TestProvider;
+    use async_trait::async_trait;
+    use futures::stream::BoxStream;
+    use mcp_core::content::Content;
+    use mcp_core::tool::ToolCall;
+    use serde_json::json;
+    use std::sync::{Arc, Mutex};
+
+    const MODEL: &str = "run-test-model";
+
+    /// Calls a tool `tool_turns` times and then answers, each response uses 1000 tokens
+    struct ScriptedAgent {
+        tool_turns: usize,
+        tokens: Arc<Mutex<i32>>,
+    }
+
+    impl ScriptedAgent {
+        fn new(tool_turns: usize) -> Self {
+            Self {
+                tool_turns,
+                tokens: Arc::new(Mutex::new(0)),
+            }
+        }
+    }
+
+    #[async_trait]
+    impl Agent for ScriptedAgent {
+        async fn reply(&self, messages: &[Message]) -> Result<BoxStream<'_, Result<Message>>> {
+            let mut turn = messages
+                .iter()
+                .filter(|m| m.role == Role::Assistant)
+                .count();
+            Ok(Box::pin(async_stream::try_stream! {
+                loop {
+                    *self.tokens.lock().unwrap() += 1000;
+                    if turn >= self.tool_turns {
+                        yield Message::assistant().with_text("done");
+                        break;
+                    }
+                    let id = turn.to_string();
+                    yield Message::assistant().with_tool_request(
+                        id.clone(),
+                        Ok(ToolCall::new("test__count", json!({"turn": turn}))),
+                    );
+                    yield Message::user().with_tool_response(
+                        id,
+                        Ok(vec![Content::text(format!("counted {}", turn))]),
+                    );
+                    turn += 1;
+                }
+            }))
+        }
+
+        async fn plan(&self, _messages: &[Message]) -> Result<Plan> {
+            unimplemented!()
+        }
+
+        async fn add_extension(
+            &mut self,
+            _config: crate::agents::ExtensionConfig,
+        ) -> ExtensionResult<()> {
+            Ok(())
+        }
+
+        async fn remove_extension(&mut self, _name: &str) {}
+
+        async fn list_extensions(&self) -> Vec<String> {
+            Vec::new()
+        }
+
+        async fn passthrough(&self, _extension: &str, _request: Value) -> ExtensionResult<Value> {
+            Ok(Value::Null)
+        }
+
+        async fn set_device_code_prompt(&mut self, _prompt: DeviceCodePrompt) {}
+
+        async fn set_tool_approval(&mut self, _approval: ToolApproval) {}
+
+        async fn set_text_deltas(&mut self, _deltas: Option<TextDeltas>) {}
+
+        async fn tool_policies(&self) -> ToolPolicies {
+            ToolPolicies::default()
+        }
+
+        async fn set_safeguard_pause(&mut self, _pause: SafeguardPause) {}
+
+        async fn safeguard_trip(&self) -> Option<SafeguardTrip> {
+            None
+        }
+
+        async fn hold_safeguards(&self, _held: bool) {}
+
+        async fn extend_system_prompt(&mut self, _instructions: String) {}
+
+        async fn add_moderator(&mut self, _moderator: Arc<dyn Moderator>) {}
+
+        async fn usage(&self) -> Vec<ProviderUsage> {
+            let tokens = *self.tokens.lock().unwrap();
+            vec![ProviderUsage::new(
+                MODEL.to_string(),
+                Usage::new(Some(tokens), Some(0), Some(tokens)),
+            )]
+        }
+    }
+
+    #[tokio::test]
+    async fn test_run_to_completion() {
+        let agent = ScriptedAgent::new(2);
+        *agent.tokens.lock().unwrap() = 500;
+        let result = agent
+            .run_to_completion(&[Message::user().with_text("count")], RunLimits::default())
+            .await;
+
+        assert_eq!(result.status, RunStatus::Completed);
+        assert_eq!(result.turns, 3);
+        assert_eq!(result.messages.len(), 6);
+        assert_eq!(result.final_message.unwrap().as_concat_text(), "done");
+        assert_eq!(result.tool_calls.len(), 2);
+        assert_eq!(result.tool_calls[1].name, "test__count");
+        assert_eq!(result.tool_calls[1].arguments, json!({"turn": 1}));
+        assert_eq!(result.tool_calls[1].output.as_deref(), Some("counted 1"));
+        // Usage from before the run is not counted
+        assert_eq!(result.usage[0].usage.input_tokens, Some(3000));
+    }
+
+    #[tokio::test]
+    async fn test_run_stops_at_limits() {
+        let agent = ScriptedAgent::new(5);
+        let result = agent
+            .run_to_completion(
+                &[Message::user().with_text("count")],
+                RunLimits::default().with_max_responses(2),
+            )
+            .await;
+        assert_eq!(result.status, RunStatus::MaxResponses);
+        assert_eq!(result.turns, 2);
+        assert!(result.tool_calls.iter().all(|call| call.output.is_some()));
+
+        // 1000 tokens cost 0.001 dollars
+        pricing::set_pricing(MODEL, 1.0, 1.0);
+        let agent = ScriptedAgent::new(5);
+        let result = agent
+            .run_to_completion(
+                &[Message::user().with_text("count")],
+                RunLimits::default().with_max_cost(0.0025),
+            )
+            .await;
+        assert_eq!(result.status, RunStatus::BudgetExceeded);
+        assert_eq!(result.turns, 3);
+        assert_eq!(result.status.exit_code(), 3);
+        assert!(result.cost().unwrap() >= 0.0025);
+    }
+
+    /// Calls the same tool with the same arguments forever, using 1000 tokens each time
+    fn looping_agent(model: &str) -> ReferenceAgent {
+        let provider = TestProvider::new(model)
+            .with_usage(model, Usage::new(Some(1000), Some(0), Some(1000)))
+            .with_reply(|request| {
+                let id = request.messages.len().to_string();
+                Ok(Message::assistant()
+                    .with_tool_request(id, Ok(ToolCall::new("test__count", json!({})))))
+            });
+        ReferenceAgent::new(Box::new(provider))
+    }
+
+    fn assert_paused_at_repeats(result: &RunResult) {
+        let trip = SafeguardTrip::RepeatedToolCall {
+            name: "test__count".to_string(),
+            times: 3,
+        };
+        assert_eq!(result.status, RunStatus::Paused(trip));
+        assert_eq!(result.tool_calls.len(), 4);
+        assert!(result.tool_calls[3]
+            .error
+            .as_deref()
+            .unwrap()
+            .contains("Not run"));
+    }
+
+    #[tokio::test]
+    async fn test_run_pauses_at_safeguards() {
+        let agent = looping_agent("run-test-looping");
+        let result = agent
+            .run_to_completion(&[Message::user().with_text("count")], RunLimits::default())
+            .await;
+
+        assert_paused_at_repeats(&result);
+        assert_ne!(result.status.exit_code(), 0);
+        assert_eq!(
+            serde_json::to_value(&result.status).unwrap(),
+            json!({
+                "status": "paused",
+                "error": {"repeated_tool_call": {"name": "test__count", "times": 3}}
+            })
+        );
+    }
+
+    #[tokio::test]
+    async fn test_run_with_budget_pauses_at_safeguards() {
+        // Checking the cost replies again after every tool turn, the count goes on
+        pricing::set_pricing("run-test-budget", 1.0, 1.0);
+        let agent = looping_agent("run-test-budget");
+        let result = agent
+            .run_to_completion(
+                &[Message::user().with_text("count")],
+                RunLimits::default().with_max_cost(1.0),
+            )
+            .await;
+        assert_paused_at_repeats(&result);
+
+        // A later reply counts from 0 again
+        let result = agent
+            .run_to_completion(&[Message::user().with_text("count")], RunLimits::default())
+            .await;
+        assert_paused_at_repeats(&result);
+    }
+
+    #[tokio::test]
+    async fn test_run_fails_without_a_price() {
+        let agent = looping_agent("run-test-unpriced");
+        let result = agent
+            .run_to_completion(
+                &[Message::user().with_text("count")],
+                RunLimits::default().with_max_cost(1.0),
+            )
+            .await;
+        match &result.status {
+            RunStatus::Failed(error) => assert!(error.contains("run-test-unpriced"), "{}", error),
+            status => panic!("expected the run to fail, got {:?}", status),
+        }
+        // It stops after the first turn instead of running on without a limit
+        assert_eq!(result.turns, 1);
+        assert!(result.cost().is_none());
+    }
+}
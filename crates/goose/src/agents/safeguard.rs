@@ -0,0 +1,246 @@
+use std::fmt;
+use std::sync::Arc;
+
+use futures::future::BoxFuture;
+use mcp_core::ToolError;
+use serde::Serialize;
+use serde_json::Value;
+
+use crate::config::Config;
+use crate::message::{Message, ToolRequest};
+
+/// Responses with tool calls the agent answers for one request, unless GOOSE_MAX_TURNS is set
+const DEFAULT_MAX_TURNS: usize = 100;
+
+/// Times in a row the same tool call may be made, unless GOOSE_MAX_REPEATED_TOOL_CALLS is set
+const DEFAULT_MAX_REPEATED_CALLS: usize = 3;
+
+/// Limits that keep a model from running tools for one request forever
+///
+/// When one is reached the agent pauses and asks the user whether to go on.
+/// A limit of None is never reached.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Safeguards {
+    /// Responses with tool calls before the agent pauses
+    pub max_turns: Option<usize>,
+    /// Identical tool calls, same tool and arguments, in a row before the agent pauses
+    pub max_repeated_calls: Option<usize>,
+}
+
+impl Default for Safeguards {
+    fn default() -> Self {
+        Self {
+            max_turns: Some(DEFAULT_MAX_TURNS),
+            max_repeated_calls: Some(DEFAULT_MAX_REPEATED_CALLS),
+        }
+    }
+}
+
+impl Safeguards {
+    /// Configure from GOOSE_MAX_TURNS and GOOSE_MAX_REPEATED_TOOL_CALLS, where 0 turns a limit off
+    pub fn from_config() -> Self {
+        let config = Config::global();
+        let limit = |key: &str, default: usize| match config.get::<usize>(key) {
+            Ok(0) => None,
+            Ok(limit) => Some(limit),
+            Err(_) => Some(default),
+        };
+        Self {
+            max_turns: limit("GOOSE_MAX_TURNS", DEFAULT_MAX_TURNS),
+            max_repeated_calls: limit("GOOSE_MAX_REPEATED_TOOL_CALLS", DEFAULT_MAX_REPEATED_CALLS),
+        }
+    }
+
+    /// No limits at all
+    pub fn disabled() -> Self {
+        Self {
+            max_turns: None,
+            max_repeated_calls: None,
+        }
+    }
+}
+
+/// Which limit made the agent pause
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SafeguardTrip {
+    /// The model has called tools in this many responses
+    MaxTurns(usize),
+    /// The model has made the same call to `name` this many times in a row
+    RepeatedToolCall { name: String, times: usize },
+}
+
+impl fmt::Display for SafeguardTrip {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SafeguardTrip::MaxTurns(turns) => {
+                write!(f, "goose has called tools {} times for this request", turns)
+            }
+            SafeguardTrip::RepeatedToolCall { name, times } => write!(
+                f,
+                "goose has called {} with the same arguments {} times in a row",
+                name, times
+            ),
+        }
+    }
+}
+
+/// What the user chose when the agent paused
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SafeguardDecision {
+    /// Run the tool calls and keep going until a limit is reached again
+    Continue,
+    /// Run no more tools for this request
+    Stop,
+}
+
+/// Asks the user how to go on when a safeguard is reached
+pub type SafeguardPause =
+    Arc<dyn Fn(SafeguardTrip) -> BoxFuture<'static, SafeguardDecision> + Send + Sync>;
+
+/// Counts the turns and repeated tool calls of one reply against `Safeguards`
+#[derive(Debug)]
+pub struct SafeguardTracker {
+    limits: Safeguards,
+    turns: usize,
+    last_call: Option<(String, Value)>,
+    repeats: usize,
+}
+
+impl SafeguardTracker {
+    pub fn new(limits: Safeguards) -> Self {
+        Self {
+            limits,
+            turns: 0,
+            last_call: None,
+            repeats: 0,
+        }
+    }
+
+    /// Count the tool calls of the next response, returning the limit they go past if any
+    pub fn record(&mut self, requests: &[&ToolRequest]) -> Option<SafeguardTrip> {
+        self.turns += 1;
+        let mut trip = self
+            .limits
+            .max_turns
+            .filter(|max| self.turns > *max)
+            .map(|_| SafeguardTrip::MaxTurns(self.turns - 1));
+
+        for call in requests.iter().filter_map(|r| r.tool_call.as_ref().ok()) {
+            let same = self.last_call.as_ref().is_some_and(|(name, arguments)| {
+                *name == call.name && *arguments == call.arguments
+            });
+            if same {
+                self.repeats += 1;
+            } else {
+                self.last_call = Some((call.name.clone(), call.arguments.clone()));
+                self.repeats = 1;
+            }
+            if trip.is_none()
+                && self
+                    .limits
+                    .max_repeated_calls
+                    .is_some_and(|max| self.repeats > max)
+            {
+                trip = Some(SafeguardTrip::RepeatedToolCall {
+                    name: call.name.clone(),
+                    times: self.repeats - 1,
+                });
+            }
+        }
+        trip
+    }
+
+    /// Start counting again, after the user chose to go on
+    pub fn reset(&mut self) {
+        self.turns = 0;
+        self.last_call = None;
+        self.repeats = 0;
+    }
+}
+
+/// The answer to tool calls the agent stopped before running
+pub fn stopped_tool_responses(requests: &[&ToolRequest], trip: &SafeguardTrip) -> Message {
+    requests.iter().fold(Message::user(), |message, request| {
+        message.with_tool_response(
+            request.id.clone(),
+            Err(ToolError::ExecutionError(format!("Not run, {}", trip))),
+        )
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use mcp_core::ToolCall;
+    use serde_json::json;
+
+    fn request(name: &str, arguments: Value) -> ToolRequest {
+        ToolRequest {
+            id: name.to_string(),
+            tool_call: Ok(ToolCall::new(name, arguments)),
+        }
+    }
+
+    #[test]
+    fn test_repeated_calls() {
+        let mut tracker = SafeguardTracker::new(Safeguards {
+            max_turns: None,
+            max_repeated_calls: Some(3),
+        });
+        let test = request("developer__shell", json!({"command": "cargo test"}));
+        let edit = request("developer__text_editor", json!({"command": "write"}));
+
+        for _ in 0..3 {
+            assert_eq!(tracker.record(&[&test]), None);
+        }
+        assert_eq!(
+            tracker.record(&[&test]),
+            Some(SafeguardTrip::RepeatedToolCall {
+                name: "developer__shell".to_string(),
+                times: 3
+            })
+        );
+
+        // Another call in between starts the count again
+        tracker.reset();
+        for _ in 0..3 {
+            assert_eq!(tracker.record(&[&test]), None);
+            assert_eq!(tracker.record(&[&edit]), None);
+        }
+
+        // Different arguments are a different call
+        let other = request("developer__shell", json!({"command": "cargo build"}));
+        assert_eq!(tracker.record(&[&test, &other, &test, &other]), None);
+    }
+
+    #[test]
+    fn test_max_turns() {
+        let mut tracker = SafeguardTracker::new(Safeguards {
+            max_turns: Some(2),
+            max_repeated_calls: None,
+        });
+        let read = request("developer__shell", json!({"command": "ls"}));
+        assert_eq!(tracker.record(&[&read]), None);
+        assert_eq!(tracker.record(&[&read]), None);
+        assert_eq!(tracker.record(&[&read]), Some(SafeguardTrip::MaxTurns(2)));
+        tracker.reset();
+        assert_eq!(tracker.record(&[&read]), None);
+
+        let mut tracker = SafeguardTracker::new(Safeguards::disabled());
+        for _ in 0..1000 {
+            assert_eq!(tracker.record(&[&read]), None);
+        }
+    }
+
+    #[test]
+    fn test_stopped_tool_responses() {
+        let read = request("developer__shell", json!({"command": "ls"}));
+        let message = stopped_tool_responses(&[&read], &SafeguardTrip::MaxTurns(100));
+        let response = message.content[0].as_tool_response().unwrap();
+        assert_eq!(response.id, "developer__shell");
+        assert!(
+            matches!(&response.tool_result, Err(ToolError::ExecutionError(e)) if e.contains("100 times"))
+        );
+    }
+}
@@ -6,18 +6,25 @@ use tokio::sync::Mutex;
 use tracing::{debug, error, instrument, warn};
 
 use super::Agent;
-use crate::agents::capabilities::Capabilities;
-use crate::agents::extension::{ExtensionConfig, ExtensionResult};
+use crate::agents::capabilities::{Capabilities, TextDeltas, ToolApproval, ToolPolicies};
+use crate::agents::extension::{DeviceCodePrompt, ExtensionConfig, ExtensionResult};
+use crate::agents::moderation::Moderator;
+use crate::agents::safeguard::{stopped_tool_responses, SafeguardPause, SafeguardTrip};
+use crate::memory::SemanticMemory;
 use crate::message::{Message, ToolRequest};
+use crate::plan::Plan;
 use crate::providers::base::Provider;
 use crate::providers::base::ProviderUsage;
 use crate::providers::errors::ProviderError;
 use crate::register_agent;
+use crate::summarize::Summarizer;
 use crate::token_counter::TokenCounter;
 use crate::truncate::{truncate_messages, OldestFirstTruncation};
 use indoc::indoc;
+use mcp_core::role::Role;
 use mcp_core::tool::Tool;
 use serde_json::{json, Value};
+use std::sync::Arc;
 
 const MAX_TRUNCATION_ATTEMPTS: usize = 3;
 const ESTIMATE_FACTOR_DECAY: f32 = 0.9;
@@ -26,14 +33,19 @@ const ESTIMATE_FACTOR_DECAY: f32 = 0.9;
 pub struct TruncateAgent {
     capabilities: Mutex<Capabilities>,
     token_counter: TokenCounter,
+    summarizer: Option<Summarizer>,
+    memory: Option<SemanticMemory>,
 }
 
 impl TruncateAgent {
     pub fn new(provider: Box<dyn Provider>) -> Self {
         let token_counter = TokenCounter::new(provider.get_model_config().tokenizer_name());
+        let summarizer = Summarizer::from_config(provider.as_ref());
         Self {
             capabilities: Mutex::new(Capabilities::new(provider)),
             token_counter,
+            summarizer,
+            memory: SemanticMemory::from_config(),
         }
     }
 
@@ -112,6 +124,10 @@ impl Agent for TruncateAgent {
         let mut capabilities = self.capabilities.lock().await;
         let mut tools = capabilities.get_prefixed_tools().await?;
         let mut truncation_attempt: usize = 0;
+        let mut summarized = self
+            .summarizer
+            .as_ref()
+            .map_or(0, |summarizer| summarizer.restore(&mut messages));
 
         // we add in the read_resource tool by default
         // TODO: make sure there is no collision with another extension's tool name
@@ -157,8 +173,20 @@ impl Agent for TruncateAgent {
             tools.push(read_resource_tool);
             tools.push(list_resources_tool);
         }
-
-        let system_prompt = capabilities.get_system_prompt().await;
+        tools.extend(capabilities.platform_tools());
+
+        let mut system_prompt = capabilities.get_system_prompt(&tools).await;
+        if let (Some(memory), Ok(dir)) = (&self.memory, std::env::current_dir()) {
+            let request = messages
+                .iter()
+                .rev()
+                .find(|m| m.role == Role::User && !m.is_tool_response())
+                .map(|m| m.as_concat_text())
+                .unwrap_or_default();
+            if let Some(recalled) = memory.recall(capabilities.provider(), &dir, &request).await {
+                system_prompt = format!("{}\n\n{}", system_prompt, recalled);
+            }
+        }
 
         // Set the user_message field in the span instead of creating a new event
         if let Some(content) = messages
@@ -169,17 +197,38 @@ impl Agent for TruncateAgent {
             debug!("user_message" = &content);
         }
 
+        capabilities.start_safeguards();
+
         Ok(Box::pin(async_stream::try_stream! {
             let _reply_guard = reply_span.enter();
             loop {
+                capabilities.warn_on_unsupported_images(&messages);
+                // Summarize the oldest messages before the context fills up
+                if let Some(summarizer) = &self.summarizer {
+                    match summarizer.compact(
+                        capabilities.provider(),
+                        capabilities.moderators(),
+                        &system_prompt,
+                        &mut messages,
+                        &tools,
+                        &mut summarized,
+                    ).await {
+                        Ok(Some(usage)) => capabilities.record_usage(usage).await,
+                        Ok(None) => {}
+                        Err(e) => warn!("Could not summarize the conversation: {}", e),
+                    }
+                }
+                let (request_system, request_messages) =
+                    capabilities.moderate_request(&system_prompt, &messages).await?;
                 // Attempt to get completion from provider
-                match capabilities.provider().complete(
-                    &system_prompt,
-                    &messages,
+                match capabilities.complete(
+                    &request_system,
+                    &request_messages,
                     &tools,
                 ).await {
                     Ok((response, usage)) => {
                         capabilities.record_usage(usage).await;
+                        let response = capabilities.moderate_response(response).await?;
 
                         // Reset truncation attempt
                         truncation_attempt = 0;
@@ -199,15 +248,17 @@ impl Agent for TruncateAgent {
                             break;
                         }
 
-                        // Then dispatch each in parallel
-                        let futures: Vec<_> = tool_requests
-                            .iter()
-                            .filter_map(|request| request.tool_call.clone().ok())
-                            .map(|tool_call| capabilities.dispatch_tool_call(tool_call))
-                            .collect();
+                        if let Some(trip) = capabilities.check_safeguards(&tool_requests).await {
+                            yield stopped_tool_responses(&tool_requests, &trip);
+                            yield Message::assistant().with_text(format!(
+                                "I stopped because {}. Tell me how to go on, or what to try differently.",
+                                trip
+                            ));
+                            break;
+                        }
 
-                        // Process all the futures in parallel but wait until all are finished
-                        let outputs = futures::future::join_all(futures).await;
+                        // Then dispatch them in parallel and wait until all are finished
+                        let outputs = capabilities.dispatch_tool_requests_of(Some(&response), &tool_requests).await;
 
                         // Create a message with the responses
                         let mut message_tool_response = Message::user();
@@ -265,10 +316,78 @@ impl Agent for TruncateAgent {
         }))
     }
 
+    async fn set_device_code_prompt(&mut self, prompt: DeviceCodePrompt) {
+        let mut capabilities = self.capabilities.lock().await;
+        capabilities.set_device_code_prompt(prompt);
+    }
+
+    async fn set_tool_approval(&mut self, approval: ToolApproval) {
+        let mut capabilities = self.capabilities.lock().await;
+        capabilities.set_tool_approval(approval);
+    }
+
+    async fn set_text_deltas(&mut self, deltas: Option<TextDeltas>) {
+        let mut capabilities = self.capabilities.lock().await;
+        capabilities.set_text_deltas(deltas);
+    }
+
+    async fn tool_policies(&self) -> ToolPolicies {
+        let capabilities = self.capabilities.lock().await;
+        capabilities.tool_policies()
+    }
+
+    async fn set_safeguard_pause(&mut self, pause: SafeguardPause) {
+        let mut capabilities = self.capabilities.lock().await;
+        capabilities.set_safeguard_pause(pause);
+    }
+
+    async fn safeguard_trip(&self) -> Option<SafeguardTrip> {
+        let capabilities = self.capabilities.lock().await;
+        capabilities.safeguard_trip()
+    }
+
+    async fn hold_safeguards(&self, held: bool) {
+        let mut capabilities = self.capabilities.lock().await;
+        capabilities.hold_safeguards(held);
+    }
+
+    async fn plan(&self, messages: &[Message]) -> anyhow::Result<Plan> {
+        let mut capabilities = self.capabilities.lock().await;
+        capabilities.plan(messages).await
+    }
+
+    async fn extend_system_prompt(&mut self, instructions: String) {
+        let mut capabilities = self.capabilities.lock().await;
+        capabilities.extend_system_prompt(instructions);
+    }
+
+    async fn add_moderator(&mut self, moderator: Arc<dyn Moderator>) {
+        let mut capabilities = self.capabilities.lock().await;
+        capabilities.add_moderator(moderator);
+    }
+
     async fn usage(&self) -> Vec<ProviderUsage> {
         let capabilities = self.capabilities.lock().await;
         capabilities.get_usage().await
     }
+
+    async fn remember(&self, messages: &[Message]) -> anyhow::Result<usize> {
+        let Some(memory) = &self.memory else {
+            return Ok(0);
+        };
+        let capabilities = self.capabilities.lock().await;
+        let dir = std::env::current_dir()?;
+        let (added, usage) = memory
+            .remember(
+                capabilities.provider(),
+                capabilities.moderators(),
+                &dir,
+                messages,
+            )
+            .await?;
+        capabilities.record_usage(usage).await;
+        Ok(added)
+    }
 }
 
 register_agent!("truncate", TruncateAgent);
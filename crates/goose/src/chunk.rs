@@ -0,0 +1,180 @@
+/// Splitting long inputs into pieces that each fit a token budget, e.g. to summarize
+/// a document chunk by chunk before combining the partial summaries
+use crate::token_counter::TokenCounter;
+
+/// A piece of the input, `text` is `input[start..end]` with byte offsets
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TextChunk {
+    pub text: String,
+    pub start: usize,
+    pub end: usize,
+}
+
+/// How good a place the gap before a word is to end a chunk
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum Boundary {
+    Word,
+    Sentence,
+    Paragraph,
+}
+
+/// Split `text` into chunks of at most `max_tokens`, repeating up to `overlap` tokens
+///
+/// Chunks only end between words and prefer paragraph breaks, then sentence
+/// ends, as long as that keeps at least half of the space a chunk could use.
+/// Each chunk after the first starts with the trailing words of the previous
+/// one, at most `overlap` tokens of them. A single word longer than
+/// `max_tokens` becomes a chunk of its own rather than being cut.
+pub fn chunk_text(
+    counter: &TokenCounter,
+    text: &str,
+    max_tokens: usize,
+    overlap: usize,
+) -> Vec<TextChunk> {
+    let words = word_spans(text);
+    let tokens =
+        |from: usize, to: usize| counter.count_tokens(&text[words[from].0..words[to - 1].1]);
+
+    let mut chunks = Vec::new();
+    let mut first = 0;
+    while first < words.len() {
+        // The most words that fit, always at least one
+        let fit = last_true(first + 1, words.len(), |end| {
+            tokens(first, end) <= max_tokens
+        });
+        let end = if fit == words.len() {
+            fit
+        } else {
+            let earliest = first + (fit - first).div_ceil(2);
+            // The last of the strongest boundaries, so the chunk stays as long as possible
+            (earliest..=fit)
+                .max_by_key(|&end| boundary_before(text, &words, end))
+                .unwrap_or(fit)
+        };
+
+        chunks.push(TextChunk {
+            text: text[words[first].0..words[end - 1].1].to_string(),
+            start: words[first].0,
+            end: words[end - 1].1,
+        });
+        if end == words.len() {
+            break;
+        }
+
+        // Step back over as many trailing words as fit the overlap, always moving forward
+        let repeated = last_true(0, end - first - 1, |n| {
+            n == 0 || tokens(end - n, end) <= overlap
+        });
+        first = end - repeated;
+    }
+    chunks
+}
+
+/// The byte ranges of the whitespace separated words in `text`
+fn word_spans(text: &str) -> Vec<(usize, usize)> {
+    let mut spans = Vec::new();
+    let mut start = None;
+    for (i, c) in text.char_indices() {
+        match (c.is_whitespace(), start) {
+            (true, Some(s)) => {
+                spans.push((s, i));
+                start = None;
+            }
+            (false, None) => start = Some(i),
+            _ => {}
+        }
+    }
+    if let Some(s) = start {
+        spans.push((s, text.len()));
+    }
+    spans
+}
+
+/// The kind of break between the word before `index` and the one at `index`
+fn boundary_before(text: &str, words: &[(usize, usize)], index: usize) -> Boundary {
+    let gap = &text[words[index - 1].1..words[index].0];
+    let previous = text[words[index - 1].0..words[index - 1].1].trim_end_matches(['"', '\'', ')']);
+    if gap.matches('\n').count() >= 2 {
+        Boundary::Paragraph
+    } else if previous.ends_with(['.', '!', '?']) {
+        Boundary::Sentence
+    } else {
+        Boundary::Word
+    }
+}
+
+/// The largest value in `low..=high` for which `fits` holds, given it holds for `low`
+/// and never holds again once it fails
+fn last_true(low: usize, high: usize, fits: impl Fn(usize) -> bool) -> usize {
+    let (mut low, mut high) = (low, high);
+    while low < high {
+        let mid = low + (high - low).div_ceil(2);
+        if fits(mid) {
+            low = mid;
+        } else {
+            high = mid - 1;
+        }
+    }
+    low
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::GPT_4O_TOKENIZER;
+
+    const DOCUMENT: &str = "The first paragraph opens the document. It has two sentences.\n\n\
+        The second paragraph is a little longer than the first one. It goes on to make a point. \
+        Then it makes another point!\n\nA short closing paragraph.";
+
+    #[test]
+    fn test_chunks_fit_and_end_at_boundaries() {
+        let counter = TokenCounter::new(GPT_4O_TOKENIZER);
+        let chunks = chunk_text(&counter, DOCUMENT, 20, 0);
+        assert!(chunks.len() > 1);
+        for chunk in &chunks {
+            assert!(counter.count_tokens(&chunk.text) <= 20);
+            assert_eq!(chunk.text, &DOCUMENT[chunk.start..chunk.end]);
+            assert!(chunk.text.ends_with(['.', '!']), "{:?}", chunk.text);
+        }
+        // Without overlap the chunks cover every word exactly once
+        let words: Vec<&str> = chunks
+            .iter()
+            .flat_map(|c| c.text.split_whitespace())
+            .collect();
+        assert_eq!(words, DOCUMENT.split_whitespace().collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn test_overlap_repeats_trailing_words() {
+        let counter = TokenCounter::new(GPT_4O_TOKENIZER);
+        let chunks = chunk_text(&counter, DOCUMENT, 20, 6);
+        assert!(chunks.len() > 1);
+        for pair in chunks.windows(2) {
+            let (previous, next) = (&pair[0], &pair[1]);
+            assert!(next.start > previous.start && next.start < previous.end);
+            let repeated = &DOCUMENT[next.start..previous.end];
+            assert!(counter.count_tokens(repeated) <= 6);
+            // Overlap starts on a word, never inside one
+            assert!(DOCUMENT[..next.start].ends_with(char::is_whitespace));
+        }
+        assert_eq!(chunks.last().unwrap().end, DOCUMENT.len());
+    }
+
+    #[test]
+    fn test_words_are_never_split() {
+        let counter = TokenCounter::new(GPT_4O_TOKENIZER);
+        let text = "short Pneumonoultramicroscopicsilicovolcanoconiosis words";
+        let chunks = chunk_text(&counter, text, 1, 0);
+        let texts: Vec<&str> = chunks.iter().map(|c| c.text.as_str()).collect();
+        assert_eq!(
+            texts,
+            [
+                "short",
+                "Pneumonoultramicroscopicsilicovolcanoconiosis",
+                "words"
+            ]
+        );
+        assert!(chunk_text(&counter, "   ", 10, 0).is_empty());
+    }
+}
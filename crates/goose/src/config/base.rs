@@ -1,4 +1,3 @@
-use keyring::Entry;
 use once_cell::sync::OnceCell;
 use serde::Deserialize;
 use serde_json::Value;
@@ -7,8 +6,11 @@ use std::env;
 use std::path::{Path, PathBuf};
 use thiserror::Error;
 
+use super::secrets::{
+    EncryptedFileStore, EnvStore, KeyringStore, LiveSecrets, Secret, SecretStore,
+};
+
 const KEYRING_SERVICE: &str = "goose";
-const KEYRING_USERNAME: &str = "secrets";
 
 #[cfg(test)]
 const TEST_KEYRING_SERVICE: &str = "goose-test";
@@ -25,6 +27,8 @@ pub enum ConfigError {
     DirectoryError(String),
     #[error("Failed to access keyring: {0}")]
     KeyringError(String),
+    #[error("Failed to access secrets: {0}")]
+    SecretStoreError(String),
 }
 
 impl From<serde_json::Error> for ConfigError {
@@ -60,8 +64,13 @@ impl From<keyring::Error> for ConfigError {
 /// 2. Configuration file (~/.config/goose/config.yaml by default)
 ///
 /// Secrets are loaded with the following precedence:
-/// 1. Environment variables (exact key match)
-/// 2. System keyring
+/// 1. Values set or rotated while the process runs
+/// 2. Environment variables (exact key match)
+/// 3. The secret store, chosen with GOOSE_SECRETS_BACKEND:
+///    - `keyring`, the system keyring, by default
+///    - `file`, a file encrypted with the passphrase in GOOSE_SECRETS_PASSPHRASE,
+///      at GOOSE_SECRETS_FILE or ~/.config/goose/secrets.enc
+///    - `env`, no store, secrets only come from the environment
 ///
 /// # Examples
 ///
@@ -91,7 +100,8 @@ impl From<keyring::Error> for ConfigError {
 /// For Goose-specific configuration, consider prefixing with "goose_" to avoid conflicts.
 pub struct Config {
     config_path: PathBuf,
-    keyring_service: String,
+    secrets: Box<dyn SecretStore>,
+    live_secrets: LiveSecrets,
 }
 
 // Global instance
@@ -106,10 +116,29 @@ impl Default for Config {
         std::fs::create_dir_all(&config_dir).expect("Failed to create config directory");
 
         let config_path = config_dir.join("config.yaml");
-        Config {
+        let mut config = Config {
             config_path,
-            keyring_service: KEYRING_SERVICE.to_string(),
+            secrets: Box::new(KeyringStore::new(KEYRING_SERVICE)),
+            live_secrets: LiveSecrets::default(),
+        };
+        match config.get::<String>("GOOSE_SECRETS_BACKEND").as_deref() {
+            Ok("file") => {
+                let path = config
+                    .get::<String>("GOOSE_SECRETS_FILE")
+                    .map(PathBuf::from)
+                    .unwrap_or_else(|_| config_dir.join("secrets.enc"));
+                // Only from the environment, a passphrase in the config file would defeat the purpose
+                let passphrase = env::var("GOOSE_SECRETS_PASSPHRASE").ok();
+                config.secrets = Box::new(EncryptedFileStore::new(path, passphrase));
+            }
+            Ok("env") => config.secrets = Box::new(EnvStore),
+            Ok("keyring") | Err(_) => {}
+            Ok(other) => tracing::warn!(
+                "Unknown GOOSE_SECRETS_BACKEND '{}', using the system keyring",
+                other
+            ),
         }
+        config
     }
 }
 
@@ -127,9 +156,18 @@ impl Config {
     /// This is primarily useful for testing or for applications that need
     /// to manage multiple configuration files.
     pub fn new<P: AsRef<Path>>(config_path: P, service: &str) -> Result<Self, ConfigError> {
+        Self::with_secret_store(config_path, KeyringStore::new(service))
+    }
+
+    /// Create a new configuration instance that keeps its secrets in `store`
+    pub fn with_secret_store<P: AsRef<Path>>(
+        config_path: P,
+        store: impl SecretStore + 'static,
+    ) -> Result<Self, ConfigError> {
         Ok(Config {
             config_path: config_path.as_ref().to_path_buf(),
-            keyring_service: service.to_string(),
+            secrets: Box::new(store),
+            live_secrets: LiveSecrets::default(),
         })
     }
 
@@ -165,20 +203,6 @@ impl Config {
         }
     }
 
-    // Load current secrets from the keyring
-    fn load_secrets(&self) -> Result<HashMap<String, Value>, ConfigError> {
-        let entry = Entry::new(&self.keyring_service, KEYRING_USERNAME)?;
-
-        match entry.get_password() {
-            Ok(content) => {
-                let values: HashMap<String, Value> = serde_json::from_str(&content)?;
-                Ok(values)
-            }
-            Err(keyring::Error::NoEntry) => Ok(HashMap::new()),
-            Err(e) => Err(ConfigError::KeyringError(e.to_string())),
-        }
-    }
-
     /// Get a configuration value.
     ///
     /// This will attempt to get the value from:
@@ -247,8 +271,9 @@ impl Config {
     /// Get a secret value.
     ///
     /// This will attempt to get the value from:
-    /// 1. Environment variable with the exact key name
-    /// 2. System keyring
+    /// 1. The value it was set or rotated to while the process runs
+    /// 2. Environment variable with the exact key name
+    /// 3. The secret store
     ///
     /// The value will be deserialized into the requested type. This works with
     /// both simple types (String, i32, etc.) and complex types that implement
@@ -257,30 +282,45 @@ impl Config {
     /// # Errors
     ///
     /// Returns a ConfigError if:
-    /// - The key doesn't exist in either environment or keyring
+    /// - The key doesn't exist in either environment or secret store
     /// - The value cannot be deserialized into the requested type
-    /// - There is an error accessing the keyring
+    /// - There is an error accessing the secret store
     pub fn get_secret<T: for<'de> Deserialize<'de>>(&self, key: &str) -> Result<T, ConfigError> {
-        // First check environment variables (convert to uppercase)
+        if let Some(val) = self.live_secrets.read().unwrap().get(key).cloned() {
+            let value: Value = serde_json::from_str(&val).unwrap_or(Value::String(val));
+            return Ok(serde_json::from_value(value)?);
+        }
+
+        // Then check environment variables (convert to uppercase)
         let env_key = key.to_uppercase();
         if let Ok(val) = env::var(&env_key) {
             let value: Value = serde_json::from_str(&val).unwrap_or(Value::String(val));
             return Ok(serde_json::from_value(value)?);
         }
 
-        // Then check keyring
-        let values = self.load_secrets()?;
+        // Then check the secret store
+        let values = self.secrets.load()?;
         values
             .get(key)
             .ok_or_else(|| ConfigError::NotFound(key.to_string()))
             .and_then(|v| Ok(serde_json::from_value(v.clone())?))
     }
 
-    /// Set a secret value in the system keyring.
+    /// Get a secret as a `Secret`, which follows rotations of the key
     ///
-    /// This will store the value in a single JSON object in the system keyring,
+    /// Keep it rather than its value to pick up a rotated key without being
+    /// created again.
+    pub fn get_secret_handle(&self, key: &str) -> Result<Secret, ConfigError> {
+        let value: String = self.get_secret(key)?;
+        Ok(Secret::new(key, value, self.live_secrets.clone()))
+    }
+
+    /// Set a secret value in the secret store.
+    ///
+    /// This will store the value in a single JSON object in the secret store,
     /// alongside any other secrets. The value can be any type that can be
-    /// serialized to JSON.
+    /// serialized to JSON. Use `rotate_secret` to replace a secret running
+    /// sessions already read.
     ///
     /// Note that this does not affect environment variables - those can only
     /// be set through the system environment.
@@ -288,35 +328,62 @@ impl Config {
     /// # Errors
     ///
     /// Returns a ConfigError if:
-    /// - There is an error accessing the keyring
+    /// - There is an error accessing the secret store
     /// - There is an error serializing the value
     pub fn set_secret(&self, key: &str, value: Value) -> Result<(), ConfigError> {
-        let mut values = self.load_secrets()?;
+        let mut values = self.secrets.load()?;
         values.insert(key.to_string(), value);
+        self.secrets.save(&values)
+    }
 
-        let json_value = serde_json::to_string(&values)?;
-        let entry = Entry::new(&self.keyring_service, KEYRING_USERNAME)?;
-        entry.set_password(&json_value)?;
-        Ok(())
+    /// Replace a secret, e.g. a provider key, for this process and in the secret store
+    ///
+    /// Running sessions use the new value from their next request. Unlike
+    /// `set_secret` this takes precedence over an environment variable of the
+    /// key for the rest of the process. With a store that can't be written,
+    /// like `env`, it only lasts for the process.
+    ///
+    /// # Errors
+    ///
+    /// Returns a ConfigError if the store can be written but that fails, the
+    /// process already uses the new value then.
+    pub fn rotate_secret(&self, key: &str, value: Value) -> Result<(), ConfigError> {
+        self.publish_secret(key, &value);
+        if !self.secrets.is_writable() {
+            return Ok(());
+        }
+        let mut values = self.secrets.load()?;
+        values.insert(key.to_string(), value);
+        self.secrets.save(&values)
+    }
+
+    /// Let `Secret`s of `key` and later reads in this process see `value`
+    fn publish_secret(&self, key: &str, value: &Value) {
+        let value = match value {
+            Value::String(s) => s.clone(),
+            other => other.to_string(),
+        };
+        self.live_secrets
+            .write()
+            .unwrap()
+            .insert(key.to_string(), value);
     }
 
-    /// Delete a secret from the system keyring.
+    /// Delete a secret from the secret store.
     ///
-    /// This will remove the specified key from the JSON object in the system keyring.
+    /// This will remove the specified key from the JSON object in the secret store.
     /// Other secrets will remain unchanged.
     ///
     /// # Errors
     ///
     /// Returns a ConfigError if:
-    /// - There is an error accessing the keyring
+    /// - There is an error accessing the secret store
     /// - There is an error serializing the remaining values
     pub fn delete_secret(&self, key: &str) -> Result<(), ConfigError> {
-        let mut values = self.load_secrets()?;
+        let mut values = self.secrets.load()?;
         values.remove(key);
-
-        let json_value = serde_json::to_string(&values)?;
-        let entry = Entry::new(&self.keyring_service, KEYRING_USERNAME)?;
-        entry.set_password(&json_value)?;
+        self.secrets.save(&values)?;
+        self.live_secrets.write().unwrap().remove(key);
         Ok(())
     }
 }
@@ -324,11 +391,12 @@ impl Config {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use keyring::Entry;
     use serial_test::serial;
     use tempfile::NamedTempFile;
 
     fn cleanup_keyring() -> Result<(), ConfigError> {
-        let entry = Entry::new(TEST_KEYRING_SERVICE, KEYRING_USERNAME)?;
+        let entry = Entry::new(TEST_KEYRING_SERVICE, "secrets")?;
         match entry.delete_credential() {
             Ok(_) => Ok(()),
             Err(keyring::Error::NoEntry) => Ok(()),
@@ -464,4 +532,30 @@ mod tests {
         cleanup_keyring()?;
         Ok(())
     }
+
+    #[test]
+    fn test_rotate_secret() -> Result<(), ConfigError> {
+        let dir = tempfile::TempDir::new()?;
+        let store =
+            || EncryptedFileStore::new(dir.path().join("secrets.enc"), Some("pass".to_string()));
+        let config = Config::with_secret_store(dir.path().join("config.yaml"), store())?;
+        config.set_secret("rotation_test_key", Value::String("old".to_string()))?;
+        let secret = config.get_secret_handle("rotation_test_key")?;
+
+        config.rotate_secret("rotation_test_key", Value::String("new".to_string()))?;
+        assert_eq!(secret.expose(), "new");
+        let reopened = Config::with_secret_store(dir.path().join("config.yaml"), store())?;
+        let value: String = reopened.get_secret("rotation_test_key")?;
+        assert_eq!(value, "new");
+
+        // Without a store to write to the rotation only lasts for the process
+        let config = Config::with_secret_store(dir.path().join("config.yaml"), EnvStore)?;
+        assert!(config
+            .set_secret("rotation_test_key", Value::String("x".to_string()))
+            .is_err());
+        config.rotate_secret("rotation_test_key", Value::String("newer".to_string()))?;
+        let value: String = config.get_secret("rotation_test_key")?;
+        assert_eq!(value, "newer");
+        Ok(())
+    }
 }
@@ -1,6 +1,12 @@
 mod base;
 mod extensions;
+mod permission;
+mod schedules;
+mod secrets;
 
 pub use crate::agents::ExtensionConfig;
 pub use base::{Config, ConfigError};
 pub use extensions::{ExtensionEntry, ExtensionManager};
+pub use permission::{ExtensionPermissions, PermissionManager, ToolCategory, ToolPolicy};
+pub use schedules::{ScheduleEntry, ScheduleManager};
+pub use secrets::{EncryptedFileStore, EnvStore, KeyringStore, Secret, SecretStore};
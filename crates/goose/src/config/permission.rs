@@ -0,0 +1,179 @@
+use super::base::Config;
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+/// What a tool can do to the user's machine, guessed from its name
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ToolCategory {
+    ReadOnly,
+    Write,
+    ShellExec,
+    Network,
+}
+
+impl ToolCategory {
+    /// Classify a tool by the words in its name, without the extension prefix
+    ///
+    /// A tool that is not recognised as a shell, network or read-only tool
+    /// counts as a write, so unknown tools fall under the stricter policy.
+    pub fn classify(tool_name: &str) -> Self {
+        let words: Vec<String> = tool_name
+            .split(|c: char| !c.is_ascii_alphanumeric())
+            .map(str::to_lowercase)
+            .collect();
+        let has = |candidates: &[&str]| words.iter().any(|w| candidates.contains(&w.as_str()));
+
+        if has(&[
+            "shell", "bash", "exec", "execute", "command", "script", "run", "terminal",
+        ]) {
+            ToolCategory::ShellExec
+        } else if has(&[
+            "web", "http", "fetch", "url", "download", "scrape", "browse", "request",
+        ]) {
+            ToolCategory::Network
+        } else if has(&[
+            "read", "list", "get", "view", "search", "retrieve", "find", "show", "describe",
+        ]) {
+            ToolCategory::ReadOnly
+        } else {
+            ToolCategory::Write
+        }
+    }
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            ToolCategory::ReadOnly => "read-only",
+            ToolCategory::Write => "write",
+            ToolCategory::ShellExec => "shell",
+            ToolCategory::Network => "network",
+        }
+    }
+}
+
+/// What happens when the agent calls a tool
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ToolPolicy {
+    /// Run the tool without asking
+    #[default]
+    AutoApprove,
+    /// Ask the user before every call
+    AlwaysAsk,
+    /// Never run the tool
+    Deny,
+}
+
+/// The tool policies of one extension
+///
+/// The deny list wins over the allow list, which wins over the category
+/// policies, which win over the extension's default.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct ExtensionPermissions {
+    /// Policy for tools that nothing else applies to
+    #[serde(default)]
+    pub default: ToolPolicy,
+    /// Policies for the tools of a category
+    #[serde(default)]
+    pub categories: HashMap<ToolCategory, ToolPolicy>,
+    /// Tools that are always run without asking
+    #[serde(default)]
+    pub allow: Vec<String>,
+    /// Tools that are never run
+    #[serde(default)]
+    pub deny: Vec<String>,
+}
+
+impl ExtensionPermissions {
+    /// The policy for a tool of this extension, named without the extension prefix
+    pub fn policy(&self, tool_name: &str) -> ToolPolicy {
+        if self.deny.iter().any(|name| name == tool_name) {
+            ToolPolicy::Deny
+        } else if self.allow.iter().any(|name| name == tool_name) {
+            ToolPolicy::AutoApprove
+        } else {
+            self.categories
+                .get(&ToolCategory::classify(tool_name))
+                .copied()
+                .unwrap_or(self.default)
+        }
+    }
+}
+
+/// Tool permission management, stored under `permissions` by extension name
+pub struct PermissionManager;
+
+impl PermissionManager {
+    /// Get the permissions of every extension that has any
+    pub fn get_all() -> Result<HashMap<String, ExtensionPermissions>> {
+        let config = Config::global();
+        match config.get("permissions") {
+            Ok(permissions) => Ok(permissions),
+            Err(super::ConfigError::NotFound(_)) => Ok(HashMap::new()),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Get the permissions of an extension, which auto-approve everything when not configured
+    pub fn get(extension: &str) -> Result<ExtensionPermissions> {
+        Ok(Self::get_all()?.remove(extension).unwrap_or_default())
+    }
+
+    /// Set or replace the permissions of an extension
+    pub fn set(extension: &str, permissions: ExtensionPermissions) -> Result<()> {
+        let config = Config::global();
+        let mut all = Self::get_all()?;
+        all.insert(extension.to_string(), permissions);
+        config.set("permissions", serde_json::to_value(all)?)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_classify() {
+        assert_eq!(ToolCategory::classify("shell"), ToolCategory::ShellExec);
+        assert_eq!(
+            ToolCategory::classify("automation_script"),
+            ToolCategory::ShellExec
+        );
+        assert_eq!(ToolCategory::classify("web_search"), ToolCategory::Network);
+        assert_eq!(
+            ToolCategory::classify("retrieve_memories"),
+            ToolCategory::ReadOnly
+        );
+        assert_eq!(
+            ToolCategory::classify("list_windows"),
+            ToolCategory::ReadOnly
+        );
+        assert_eq!(ToolCategory::classify("text_editor"), ToolCategory::Write);
+    }
+
+    #[test]
+    fn test_policy_precedence() {
+        let permissions: ExtensionPermissions = serde_json::from_value(json!({
+            "default": "always_ask",
+            "categories": {"shell_exec": "deny", "read_only": "auto_approve"},
+            "allow": ["shell"],
+            "deny": ["list_windows"],
+        }))
+        .unwrap();
+
+        assert_eq!(permissions.policy("shell"), ToolPolicy::AutoApprove);
+        assert_eq!(permissions.policy("bash"), ToolPolicy::Deny);
+        assert_eq!(permissions.policy("list_windows"), ToolPolicy::Deny);
+        assert_eq!(permissions.policy("read_file"), ToolPolicy::AutoApprove);
+        assert_eq!(permissions.policy("text_editor"), ToolPolicy::AlwaysAsk);
+
+        // Nothing configured keeps running every tool
+        assert_eq!(
+            ExtensionPermissions::default().policy("shell"),
+            ToolPolicy::AutoApprove
+        );
+    }
+}
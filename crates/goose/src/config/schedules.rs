@@ -0,0 +1,177 @@
+use super::base::Config;
+use crate::cron::CronExpr;
+use anyhow::{anyhow, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+
+const SCHEDULES_KEY: &str = "schedules";
+
+/// Longest schedule name, it is part of the names of the run's files
+const MAX_NAME_LEN: usize = 64;
+
+/// A headless run goose starts on a timer
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq)]
+pub struct ScheduleEntry {
+    pub name: String,
+    /// When to run, a five field cron expression in local time
+    pub cron: String,
+    /// The instructions the run starts with, the recipe's prompt is used without them
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub instructions: Option<String>,
+    /// A recipe to start the run from, as for `--recipe`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub recipe: Option<String>,
+    /// Values for the parameters of the recipe
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub params: BTreeMap<String, String>,
+    /// A builtin extension to add to the run
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub builtin: Option<String>,
+    /// A stdio extension to add to the run, as for `--with-extension`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub extension: Option<String>,
+    #[serde(default = "enabled_by_default")]
+    pub enabled: bool,
+}
+
+fn enabled_by_default() -> bool {
+    true
+}
+
+/// Check that `name` can be used in the paths of a run's files
+///
+/// Only letters, digits, '-' and '_' are allowed, so a name can't reach
+/// outside the schedules directory or make file names the OS rejects.
+fn validate_name(name: &str) -> Result<()> {
+    let valid = !name.is_empty()
+        && name.len() <= MAX_NAME_LEN
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+    if !valid {
+        return Err(anyhow!(
+            "Invalid schedule name '{}', use up to {} letters, digits, '-' and '_'",
+            name,
+            MAX_NAME_LEN
+        ));
+    }
+    Ok(())
+}
+
+impl ScheduleEntry {
+    /// The parsed cron expression
+    pub fn cron(&self) -> Result<CronExpr> {
+        Ok(self.cron.parse()?)
+    }
+
+    /// Check the name and cron expression, and that the run has something to do
+    pub fn validate(&self) -> Result<()> {
+        validate_name(&self.name)?;
+        self.cron()?;
+        if self.instructions.is_none() && self.recipe.is_none() {
+            return Err(anyhow!(
+                "Schedule '{}' has neither instructions nor a recipe",
+                self.name
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// Schedule configuration management
+pub struct ScheduleManager;
+
+impl ScheduleManager {
+    fn load() -> HashMap<String, ScheduleEntry> {
+        Config::global()
+            .get(SCHEDULES_KEY)
+            .unwrap_or_else(|_| HashMap::new())
+    }
+
+    fn save(schedules: HashMap<String, ScheduleEntry>) -> Result<()> {
+        Config::global().set(SCHEDULES_KEY, serde_json::to_value(schedules)?)?;
+        Ok(())
+    }
+
+    /// Set or update a schedule, it has to pass `ScheduleEntry::validate`
+    pub fn set(entry: ScheduleEntry) -> Result<()> {
+        entry.validate()?;
+        let mut schedules = Self::load();
+        schedules.insert(entry.name.clone(), entry);
+        Self::save(schedules)
+    }
+
+    /// Remove a schedule
+    pub fn remove(name: &str) -> Result<()> {
+        let mut schedules = Self::load();
+        if schedules.remove(name).is_none() {
+            return Err(anyhow!("No schedule named '{}'", name));
+        }
+        Self::save(schedules)
+    }
+
+    /// Enable or disable a schedule
+    pub fn set_enabled(name: &str, enabled: bool) -> Result<()> {
+        let mut schedules = Self::load();
+        let entry = schedules
+            .get_mut(name)
+            .ok_or_else(|| anyhow!("No schedule named '{}'", name))?;
+        entry.enabled = enabled;
+        Self::save(schedules)
+    }
+
+    /// Get a schedule by name
+    pub fn get(name: &str) -> Option<ScheduleEntry> {
+        Self::load().remove(name)
+    }
+
+    /// Get all schedules, sorted by name
+    pub fn get_all() -> Vec<ScheduleEntry> {
+        let mut schedules: Vec<ScheduleEntry> = Self::load().into_values().collect();
+        schedules.sort_by(|a, b| a.name.cmp(&b.name));
+        schedules
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(name: &str) -> ScheduleEntry {
+        ScheduleEntry {
+            name: name.to_string(),
+            cron: "0 2 * * *".to_string(),
+            instructions: Some("Tidy up".to_string()),
+            recipe: None,
+            params: BTreeMap::new(),
+            builtin: None,
+            extension: None,
+            enabled: true,
+        }
+    }
+
+    #[test]
+    fn test_validate() {
+        assert!(entry("nightly-triage_2").validate().is_ok());
+        for name in [
+            "",
+            "../escape",
+            "a/b",
+            "with space",
+            ".hidden",
+            &"x".repeat(65),
+        ] {
+            assert!(entry(name).validate().is_err(), "{:?} was accepted", name);
+        }
+
+        let mut recipe = entry("recipe");
+        recipe.instructions = None;
+        assert!(recipe.validate().is_err());
+        recipe.recipe = Some("triage.yaml".to_string());
+        assert!(recipe.validate().is_ok());
+
+        let mut bad_cron = entry("bad-cron");
+        bad_cron.cron = "every night".to_string();
+        assert!(bad_cron.validate().is_err());
+    }
+}
@@ -0,0 +1,305 @@
+use keyring::Entry;
+use ring::aead::{Aad, LessSafeKey, Nonce, UnboundKey, AES_256_GCM, NONCE_LEN};
+use ring::pbkdf2;
+use ring::rand::{SecureRandom, SystemRandom};
+use serde_json::Value;
+use std::collections::HashMap;
+use std::io::Write;
+use std::num::NonZeroU32;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock};
+
+use super::base::ConfigError;
+
+const KEYRING_USERNAME: &str = "secrets";
+
+/// Where secrets are kept, as one map from key to value
+pub trait SecretStore: Send + Sync {
+    fn load(&self) -> Result<HashMap<String, Value>, ConfigError>;
+
+    fn save(&self, values: &HashMap<String, Value>) -> Result<(), ConfigError>;
+
+    /// Whether `save` keeps the values, rotations in a store that can't be written only last for the process
+    fn is_writable(&self) -> bool {
+        true
+    }
+}
+
+/// Secrets in a single entry of the system keyring
+pub struct KeyringStore {
+    service: String,
+}
+
+impl KeyringStore {
+    pub fn new(service: &str) -> Self {
+        Self {
+            service: service.to_string(),
+        }
+    }
+}
+
+impl SecretStore for KeyringStore {
+    fn load(&self) -> Result<HashMap<String, Value>, ConfigError> {
+        let entry = Entry::new(&self.service, KEYRING_USERNAME)?;
+        match entry.get_password() {
+            Ok(content) => Ok(serde_json::from_str(&content)?),
+            Err(keyring::Error::NoEntry) => Ok(HashMap::new()),
+            Err(e) => Err(ConfigError::KeyringError(e.to_string())),
+        }
+    }
+
+    fn save(&self, values: &HashMap<String, Value>) -> Result<(), ConfigError> {
+        let entry = Entry::new(&self.service, KEYRING_USERNAME)?;
+        entry.set_password(&serde_json::to_string(values)?)?;
+        Ok(())
+    }
+}
+
+/// Secrets only from environment variables, for machines without a keyring
+///
+/// Environment variables are read before any store, so this one is empty and
+/// refuses to save.
+pub struct EnvStore;
+
+impl SecretStore for EnvStore {
+    fn load(&self) -> Result<HashMap<String, Value>, ConfigError> {
+        Ok(HashMap::new())
+    }
+
+    fn save(&self, _values: &HashMap<String, Value>) -> Result<(), ConfigError> {
+        Err(ConfigError::SecretStoreError(
+            "secrets are read from the environment, set them there".to_string(),
+        ))
+    }
+
+    fn is_writable(&self) -> bool {
+        false
+    }
+}
+
+/// Marks a goose secrets file and is authenticated with the contents
+const FILE_MAGIC: &[u8] = b"GOOSESECRETS1";
+const SALT_LEN: usize = 16;
+const PBKDF2_ITERATIONS: u32 = 100_000;
+
+/// Secrets in a file encrypted with AES-256-GCM, under a key derived from a passphrase
+///
+/// The file holds a random salt and nonce followed by the encrypted JSON map,
+/// both are renewed on every save.
+pub struct EncryptedFileStore {
+    path: PathBuf,
+    passphrase: Option<String>,
+}
+
+impl EncryptedFileStore {
+    pub fn new<P: AsRef<Path>>(path: P, passphrase: Option<String>) -> Self {
+        Self {
+            path: path.as_ref().to_path_buf(),
+            passphrase,
+        }
+    }
+
+    fn key(&self, salt: &[u8]) -> Result<LessSafeKey, ConfigError> {
+        let passphrase = self.passphrase.as_deref().ok_or_else(|| {
+            ConfigError::SecretStoreError(
+                "GOOSE_SECRETS_PASSPHRASE is needed to open the secrets file".to_string(),
+            )
+        })?;
+        let mut key = [0u8; 32];
+        pbkdf2::derive(
+            pbkdf2::PBKDF2_HMAC_SHA256,
+            NonZeroU32::new(PBKDF2_ITERATIONS).unwrap(),
+            salt,
+            passphrase.as_bytes(),
+            &mut key,
+        );
+        let key =
+            UnboundKey::new(&AES_256_GCM, &key).expect("the key has the length AES-256 needs");
+        Ok(LessSafeKey::new(key))
+    }
+}
+
+impl SecretStore for EncryptedFileStore {
+    fn load(&self) -> Result<HashMap<String, Value>, ConfigError> {
+        if !self.path.exists() {
+            return Ok(HashMap::new());
+        }
+        let content = std::fs::read(&self.path)?;
+        let invalid = || {
+            ConfigError::SecretStoreError(format!(
+                "{} is not a goose secrets file or the passphrase is wrong",
+                self.path.display()
+            ))
+        };
+        let rest = content.strip_prefix(FILE_MAGIC).ok_or_else(invalid)?;
+        if rest.len() < SALT_LEN + NONCE_LEN {
+            return Err(invalid());
+        }
+        let (salt, rest) = rest.split_at(SALT_LEN);
+        let (nonce, ciphertext) = rest.split_at(NONCE_LEN);
+        let nonce = Nonce::try_assume_unique_for_key(nonce).map_err(|_| invalid())?;
+        let mut buffer = ciphertext.to_vec();
+        let plaintext = self
+            .key(salt)?
+            .open_in_place(nonce, Aad::from(FILE_MAGIC), &mut buffer)
+            .map_err(|_| invalid())?;
+        Ok(serde_json::from_slice(plaintext)?)
+    }
+
+    fn save(&self, values: &HashMap<String, Value>) -> Result<(), ConfigError> {
+        let random = SystemRandom::new();
+        let mut salt = [0u8; SALT_LEN];
+        let mut nonce = [0u8; NONCE_LEN];
+        random
+            .fill(&mut salt)
+            .and_then(|_| random.fill(&mut nonce))
+            .map_err(|_| ConfigError::SecretStoreError("no randomness available".to_string()))?;
+
+        let mut buffer = serde_json::to_vec(values)?;
+        self.key(&salt)?
+            .seal_in_place_append_tag(
+                Nonce::assume_unique_for_key(nonce),
+                Aad::from(FILE_MAGIC),
+                &mut buffer,
+            )
+            .map_err(|_| ConfigError::SecretStoreError("failed to encrypt".to_string()))?;
+
+        let mut content = FILE_MAGIC.to_vec();
+        content.extend_from_slice(&salt);
+        content.extend_from_slice(&nonce);
+        content.extend_from_slice(&buffer);
+        if let Some(parent) = self.path.parent() {
+            std::fs::create_dir_all(parent)
+                .map_err(|e| ConfigError::DirectoryError(e.to_string()))?;
+        }
+        // Write next to the file and move it in place, created readable only by the user
+        let tmp = self
+            .path
+            .with_extension(format!("{}.tmp", uuid::Uuid::new_v4()));
+        let mut options = std::fs::OpenOptions::new();
+        options.write(true).create_new(true);
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::OpenOptionsExt;
+            options.mode(0o600);
+        }
+        let written = options.open(&tmp).and_then(|mut file| {
+            file.write_all(&content)?;
+            file.sync_all()
+        });
+        if let Err(e) = written.and_then(|_| std::fs::rename(&tmp, &self.path)) {
+            let _ = std::fs::remove_file(&tmp);
+            return Err(e.into());
+        }
+        Ok(())
+    }
+}
+
+/// Values of secrets set while the process runs, shared with the `Secret`s read before
+pub(super) type LiveSecrets = Arc<RwLock<HashMap<String, String>>>;
+
+/// A secret that follows the updates of its key while the process runs
+///
+/// Providers keep one instead of the value, so a key rotated with
+/// `Config::rotate_secret` is used by the next request of sessions that are
+/// already running. Debug and serialized output never show the value.
+#[derive(Clone)]
+pub struct Secret {
+    key: Option<String>,
+    value: String,
+    live: LiveSecrets,
+}
+
+impl Secret {
+    pub(super) fn new(key: &str, value: String, live: LiveSecrets) -> Self {
+        Self {
+            key: Some(key.to_string()),
+            value,
+            live,
+        }
+    }
+
+    /// A secret given directly, that is never rotated
+    pub fn fixed<S: Into<String>>(value: S) -> Self {
+        Self {
+            key: None,
+            value: value.into(),
+            live: LiveSecrets::default(),
+        }
+    }
+
+    /// The current value
+    pub fn expose(&self) -> String {
+        self.key
+            .as_ref()
+            .and_then(|key| self.live.read().unwrap().get(key).cloned())
+            .unwrap_or_else(|| self.value.clone())
+    }
+}
+
+impl std::fmt::Debug for Secret {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("Secret(***)")
+    }
+}
+
+impl serde::Serialize for Secret {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str("***")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_encrypted_file_store() -> Result<(), ConfigError> {
+        let dir = TempDir::new()?;
+        let path = dir.path().join("secrets.enc");
+        let store = EncryptedFileStore::new(&path, Some("correct horse".to_string()));
+        assert!(store.load()?.is_empty());
+
+        let values = HashMap::from([("OMG_API_KEY".to_string(), Value::from("sk-123"))]);
+        store.save(&values)?;
+        assert_eq!(store.load()?, values);
+        let content = std::fs::read(&path)?;
+        assert!(!content.windows(6).any(|w| w == b"sk-123"));
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = std::fs::metadata(&path)?.permissions().mode();
+            assert_eq!(mode & 0o777, 0o600);
+        }
+        store.save(&values)?;
+        assert_eq!(std::fs::read_dir(dir.path())?.count(), 1);
+
+        let wrong = EncryptedFileStore::new(&path, Some("battery staple".to_string()));
+        assert!(matches!(
+            wrong.load(),
+            Err(ConfigError::SecretStoreError(_))
+        ));
+        let missing = EncryptedFileStore::new(&path, None);
+        assert!(matches!(
+            missing.load(),
+            Err(ConfigError::SecretStoreError(_))
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn test_secret_follows_rotation() {
+        let live = LiveSecrets::default();
+        let secret = Secret::new("OMG_API_KEY", "old".to_string(), live.clone());
+        let fixed = Secret::fixed("given");
+        assert_eq!(secret.expose(), "old");
+
+        live.write()
+            .unwrap()
+            .insert("OMG_API_KEY".to_string(), "new".to_string());
+        assert_eq!(secret.expose(), "new");
+        assert_eq!(fixed.expose(), "given");
+        assert_eq!(format!("{:?}", secret), "Secret(***)");
+    }
+}
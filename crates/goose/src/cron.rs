@@ -0,0 +1,234 @@
+use chrono::{Datelike, Duration, NaiveDate, NaiveDateTime, Timelike};
+use std::fmt;
+use std::str::FromStr;
+use thiserror::Error;
+
+/// How far ahead `next_after` looks before deciding an expression never fires
+const SEARCH_YEARS: i64 = 5;
+
+#[derive(Error, Debug, Clone, PartialEq)]
+pub enum CronError {
+    #[error("Invalid cron expression '{0}': {1}")]
+    Invalid(String, String),
+}
+
+/// A cron expression, the usual five fields `minute hour day-of-month month day-of-week`
+///
+/// Fields take `*`, numbers, ranges `a-b`, steps `*/n` or `a-b/n` and lists of
+/// those separated by commas. Sunday is both 0 and 7. The shortcuts `@hourly`,
+/// `@daily`, `@midnight`, `@weekly`, `@monthly`, `@yearly` and `@annually` are
+/// accepted too. As in cron, when both day fields are restricted a time
+/// matches if either of them does.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CronExpr {
+    source: String,
+    minutes: u64,
+    hours: u64,
+    days: u64,
+    months: u64,
+    weekdays: u64,
+    any_day: bool,
+    any_weekday: bool,
+}
+
+/// Parse one field into a bit set of the values in `min..=max`
+fn parse_field(field: &str, min: u32, max: u32) -> Result<u64, String> {
+    let mut bits = 0u64;
+    for part in field.split(',') {
+        let (range, step) = match part.split_once('/') {
+            Some((range, step)) => {
+                let step: u32 = step
+                    .parse()
+                    .map_err(|_| format!("'{}' is not a valid step", step))?;
+                if step == 0 {
+                    return Err("a step must be at least 1".to_string());
+                }
+                (range, step)
+            }
+            None => (part, 1),
+        };
+        let parse = |value: &str| -> Result<u32, String> {
+            let value: u32 = value
+                .parse()
+                .map_err(|_| format!("'{}' is not a number", value))?;
+            if value < min || value > max {
+                return Err(format!("{} is outside {}-{}", value, min, max));
+            }
+            Ok(value)
+        };
+        let (start, end) = match range {
+            "*" => (min, max),
+            _ => match range.split_once('-') {
+                Some((start, end)) => (parse(start)?, parse(end)?),
+                // `5/15` means every 15 from 5 on
+                None if step > 1 => (parse(range)?, max),
+                None => {
+                    let value = parse(range)?;
+                    (value, value)
+                }
+            },
+        };
+        if start > end {
+            return Err(format!("the range {}-{} is empty", start, end));
+        }
+        for value in (start..=end).step_by(step as usize) {
+            bits |= 1 << value;
+        }
+    }
+    Ok(bits)
+}
+
+impl CronExpr {
+    fn contains(bits: u64, value: u32) -> bool {
+        bits & (1 << value) != 0
+    }
+
+    fn day_matches(&self, date: NaiveDate) -> bool {
+        if !Self::contains(self.months, date.month()) {
+            return false;
+        }
+        let day = Self::contains(self.days, date.day());
+        let weekday = Self::contains(self.weekdays, date.weekday().num_days_from_sunday());
+        match (self.any_day, self.any_weekday) {
+            (false, false) => day || weekday,
+            _ => day && weekday,
+        }
+    }
+
+    /// Whether the expression fires in the minute of `time`
+    pub fn matches(&self, time: NaiveDateTime) -> bool {
+        self.day_matches(time.date())
+            && Self::contains(self.hours, time.hour())
+            && Self::contains(self.minutes, time.minute())
+    }
+
+    /// The first minute after `after` the expression fires in
+    ///
+    /// `None` when it does not fire in the next few years, such as `0 0 30 2 *`.
+    pub fn next_after(&self, after: NaiveDateTime) -> Option<NaiveDateTime> {
+        let mut time = after
+            .with_second(0)
+            .and_then(|time| time.with_nanosecond(0))?
+            + Duration::minutes(1);
+        let limit = time + Duration::days(366 * SEARCH_YEARS);
+        while time < limit {
+            if !self.day_matches(time.date()) {
+                time = time.date().succ_opt()?.and_hms_opt(0, 0, 0)?;
+            } else if !Self::contains(self.hours, time.hour()) {
+                time = time.with_minute(0)? + Duration::hours(1);
+            } else if !Self::contains(self.minutes, time.minute()) {
+                time += Duration::minutes(1);
+            } else {
+                return Some(time);
+            }
+        }
+        None
+    }
+}
+
+impl FromStr for CronExpr {
+    type Err = CronError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = |reason: String| CronError::Invalid(s.to_string(), reason);
+        let expanded = match s.trim() {
+            "@hourly" => "0 * * * *",
+            "@daily" | "@midnight" => "0 0 * * *",
+            "@weekly" => "0 0 * * 0",
+            "@monthly" => "0 0 1 * *",
+            "@yearly" | "@annually" => "0 0 1 1 *",
+            other => other,
+        };
+        let fields: Vec<&str> = expanded.split_whitespace().collect();
+        let [minute, hour, day, month, weekday] = fields[..] else {
+            return Err(invalid(format!("expected 5 fields, got {}", fields.len())));
+        };
+        let mut weekdays = parse_field(weekday, 0, 7).map_err(invalid)?;
+        // Sunday can be written as 7
+        if weekdays & (1 << 7) != 0 {
+            weekdays = (weekdays & !(1 << 7)) | 1;
+        }
+        Ok(Self {
+            source: s.trim().to_string(),
+            minutes: parse_field(minute, 0, 59).map_err(invalid)?,
+            hours: parse_field(hour, 0, 23).map_err(invalid)?,
+            days: parse_field(day, 1, 31).map_err(invalid)?,
+            months: parse_field(month, 1, 12).map_err(invalid)?,
+            weekdays,
+            any_day: day.starts_with('*'),
+            any_weekday: weekday.starts_with('*'),
+        })
+    }
+}
+
+impl fmt::Display for CronExpr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.source)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(date: &str) -> NaiveDateTime {
+        NaiveDateTime::parse_from_str(date, "%Y-%m-%d %H:%M").unwrap()
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        for expr in [
+            "* * * *",
+            "60 * * * *",
+            "*/0 * * * *",
+            "5-1 * * * *",
+            "a * * * *",
+            "* * 0 * *",
+        ] {
+            assert!(
+                expr.parse::<CronExpr>().is_err(),
+                "{} should not parse",
+                expr
+            );
+        }
+    }
+
+    #[test]
+    fn test_next_after() {
+        let nightly: CronExpr = "30 2 * * *".parse().unwrap();
+        assert_eq!(
+            nightly.next_after(at("2024-03-01 02:29")),
+            Some(at("2024-03-01 02:30"))
+        );
+        assert_eq!(
+            nightly.next_after(at("2024-03-01 02:30")),
+            Some(at("2024-03-02 02:30"))
+        );
+
+        let weekdays: CronExpr = "*/15 9-17 * * 1-5".parse().unwrap();
+        // 2024-03-02 is a Saturday
+        assert_eq!(
+            weekdays.next_after(at("2024-03-01 17:50")),
+            Some(at("2024-03-04 09:00"))
+        );
+
+        let sunday: CronExpr = "0 0 * * 7".parse().unwrap();
+        assert_eq!(
+            sunday.next_after(at("2024-03-01 00:00")),
+            Some(at("2024-03-03 00:00"))
+        );
+
+        let never: CronExpr = "0 0 30 2 *".parse().unwrap();
+        assert_eq!(never.next_after(at("2024-03-01 00:00")), None);
+    }
+
+    #[test]
+    fn test_either_day_field_matches() {
+        // The 1st of the month or any Monday
+        let expr: CronExpr = "0 12 1 * 1".parse().unwrap();
+        assert!(expr.matches(at("2024-03-01 12:00")));
+        assert!(expr.matches(at("2024-03-04 12:00")));
+        assert!(!expr.matches(at("2024-03-05 12:00")));
+        assert!(!expr.matches(at("2024-03-04 12:01")));
+    }
+}
@@ -0,0 +1,477 @@
+//! Transcripts of a conversation as Markdown or a standalone HTML page
+//!
+//! Tool calls are shown with their arguments and results, images and audio
+//! are embedded as data URIs so the transcript is a single file. Sensitive
+//! text is left out, also in tool results, and links go only to http, https
+//! and mailto URLs.
+use std::fmt::Write;
+use std::str::FromStr;
+
+use chrono::DateTime;
+use mcp_core::content::Content;
+use mcp_core::role::Role;
+use pulldown_cmark::{html, CowStr, Event, Parser, Tag};
+
+use crate::message::{Message, MessageContent};
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ExportFormat {
+    #[default]
+    Markdown,
+    Html,
+}
+
+impl ExportFormat {
+    /// The file extension of the format
+    pub fn extension(&self) -> &'static str {
+        match self {
+            ExportFormat::Markdown => "md",
+            ExportFormat::Html => "html",
+        }
+    }
+}
+
+impl FromStr for ExportFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "md" | "markdown" => Ok(ExportFormat::Markdown),
+            "html" | "htm" => Ok(ExportFormat::Html),
+            _ => Err(format!(
+                "unknown export format '{}', expected markdown or html",
+                s
+            )),
+        }
+    }
+}
+
+/// The transcript of `messages` in `format`, `title` heads the transcript
+pub fn export(messages: &[Message], format: ExportFormat, title: &str) -> String {
+    match format {
+        ExportFormat::Markdown => to_markdown(messages, title),
+        ExportFormat::Html => to_html(messages, title),
+    }
+}
+
+/// A part of a message as it is shown in a transcript
+enum Block {
+    /// Markdown written by the user or the model
+    Text(String),
+    Code {
+        label: String,
+        language: &'static str,
+        code: String,
+    },
+    Image {
+        mime_type: String,
+        data: String,
+    },
+    Audio {
+        format: String,
+        data: String,
+    },
+    Thinking(String),
+    Note(String),
+    Sources(Vec<(String, String)>),
+}
+
+fn heading(message: &Message) -> String {
+    let author = match message.role {
+        Role::User if message.is_tool_response() && message.as_concat_text().is_empty() => {
+            "Tool results"
+        }
+        Role::User => "User",
+        Role::Assistant => "Goose",
+    };
+    match DateTime::from_timestamp(message.created, 0) {
+        Some(created) => format!("{} · {}", author, created.format("%Y-%m-%d %H:%M:%S UTC")),
+        None => author.to_string(),
+    }
+}
+
+fn blocks(message: &Message) -> Vec<Block> {
+    let mut blocks = Vec::new();
+    for content in &message.content {
+        match content {
+            MessageContent::Text(text) if message.is_sensitive(&text.text) => {
+                blocks.push(Block::Note("Sensitive text left out".to_string()))
+            }
+            MessageContent::Text(text) => blocks.push(Block::Text(text.text.clone())),
+            MessageContent::Image(image) => blocks.push(Block::Image {
+                mime_type: image.mime_type.clone(),
+                data: image.data.clone(),
+            }),
+            MessageContent::Audio(audio) => blocks.push(Block::Audio {
+                format: audio.format.clone(),
+                data: audio.data.clone(),
+            }),
+            MessageContent::ToolRequest(request) => match &request.tool_call {
+                Ok(call) => blocks.push(Block::Code {
+                    label: format!("Tool call {}", call.name),
+                    language: "json",
+                    code: serde_json::to_string_pretty(&call.arguments).unwrap_or_default(),
+                }),
+                Err(e) => blocks.push(Block::Note(format!("Invalid tool call: {}", e))),
+            },
+            MessageContent::ToolResponse(response) => match &response.tool_result {
+                Ok(contents) => {
+                    for content in contents {
+                        match content {
+                            Content::Text(text) if message.is_sensitive(&text.text) => {
+                                blocks.push(Block::Note("Sensitive text left out".to_string()))
+                            }
+                            Content::Text(text) => blocks.push(Block::Code {
+                                label: "Tool result".to_string(),
+                                language: "",
+                                code: text.text.clone(),
+                            }),
+                            Content::Image(image) => blocks.push(Block::Image {
+                                mime_type: image.mime_type.clone(),
+                                data: image.data.clone(),
+                            }),
+                            Content::Resource(resource) => blocks.push(Block::Code {
+                                label: "Tool result".to_string(),
+                                language: "",
+                                code: resource.get_text(),
+                            }),
+                        }
+                    }
+                }
+                Err(e) => blocks.push(Block::Code {
+                    label: "Tool error".to_string(),
+                    language: "",
+                    code: e.to_string(),
+                }),
+            },
+            MessageContent::Refusal(refusal) => {
+                blocks.push(Block::Note(format!("Refused: {}", refusal.refusal)))
+            }
+            MessageContent::Thinking(thinking) => {
+                blocks.push(Block::Thinking(thinking.thinking.clone()))
+            }
+        }
+    }
+    if !message.citations.is_empty() {
+        blocks.push(Block::Sources(
+            message
+                .citations
+                .iter()
+                .map(|c| {
+                    (
+                        c.title.clone().unwrap_or_else(|| c.url.clone()),
+                        c.url.clone(),
+                    )
+                })
+                .collect(),
+        ));
+    }
+    blocks
+}
+
+/// Whether a link to `url` is safe to follow from a transcript
+///
+/// Only http, https and mailto links are kept, and those without a scheme,
+/// which stay within the page.
+fn is_safe_url(url: &str) -> bool {
+    // Browsers ignore whitespace and control characters in a scheme
+    let url: String = url
+        .chars()
+        .filter(|c| !c.is_ascii_whitespace() && !c.is_control())
+        .collect();
+    match url.split_once(':') {
+        Some((scheme, _)) if !scheme.contains(['/', '?', '#']) => {
+            matches!(
+                scheme.to_ascii_lowercase().as_str(),
+                "http" | "https" | "mailto"
+            )
+        }
+        _ => true,
+    }
+}
+
+/// A code fence longer than any run of backticks in `code`
+fn fence(code: &str) -> String {
+    let longest = code.split(|c| c != '`').map(str::len).max().unwrap_or(0);
+    "`".repeat(longest.max(2) + 1)
+}
+
+/// The transcript of `messages` as Markdown
+pub fn to_markdown(messages: &[Message], title: &str) -> String {
+    let mut out = format!("# {}\n", title);
+    for message in messages {
+        let _ = write!(out, "\n## {}\n", heading(message));
+        for block in blocks(message) {
+            out.push('\n');
+            match block {
+                Block::Text(text) => {
+                    let _ = writeln!(out, "{}", text.trim_end());
+                }
+                Block::Code {
+                    label,
+                    language,
+                    code,
+                } => {
+                    let fence = fence(&code);
+                    let _ = writeln!(
+                        out,
+                        "**{}**\n\n{}{}\n{}\n{}",
+                        label,
+                        fence,
+                        language,
+                        code.trim_end(),
+                        fence
+                    );
+                }
+                Block::Image { mime_type, data } => {
+                    let _ = writeln!(out, "![image](data:{};base64,{})", mime_type, data);
+                }
+                Block::Audio { format, data } => {
+                    let _ = writeln!(out, "[Audio clip](data:audio/{};base64,{})", format, data);
+                }
+                Block::Thinking(thinking) => {
+                    let _ = writeln!(
+                        out,
+                        "<details>\n<summary>Thinking</summary>\n\n{}\n\n</details>",
+                        thinking.trim_end()
+                    );
+                }
+                Block::Note(note) => {
+                    let _ = writeln!(out, "*{}*", note);
+                }
+                Block::Sources(sources) => {
+                    let _ = writeln!(out, "Sources:\n");
+                    for (title, url) in sources {
+                        if is_safe_url(&url) {
+                            let _ = writeln!(out, "- [{}](<{}>)", title, url);
+                        } else {
+                            let _ = writeln!(out, "- {}", title);
+                        }
+                    }
+                }
+            }
+        }
+    }
+    out
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+/// Markdown as HTML, with any raw HTML in it shown as text and unsafe links emptied
+fn markdown_html(text: &str) -> String {
+    let events = Parser::new(text).map(|event| match event {
+        Event::Html(html) | Event::InlineHtml(html) => Event::Text(html),
+        Event::Start(Tag::Link {
+            link_type,
+            dest_url,
+            title,
+            id,
+        }) if !is_safe_url(&dest_url) => Event::Start(Tag::Link {
+            link_type,
+            dest_url: CowStr::Borrowed(""),
+            title,
+            id,
+        }),
+        event => event,
+    });
+    let mut out = String::new();
+    html::push_html(&mut out, events);
+    out
+}
+
+const STYLE: &str = "body{font-family:system-ui,sans-serif;max-width:52rem;margin:2rem auto;padding:0 1rem;line-height:1.5;color:#1f2328}\
+section{border-top:1px solid #d0d7de;padding:.5rem 0}\
+h2{font-size:1rem;color:#59636e}\
+pre{background:#f6f8fa;padding:.75rem;overflow-x:auto;white-space:pre-wrap}\
+.label{font-weight:600;margin-bottom:.25rem}\
+.note{font-style:italic;color:#59636e}\
+img{max-width:100%}";
+
+/// The transcript of `messages` as a standalone HTML page
+pub fn to_html(messages: &[Message], title: &str) -> String {
+    let mut out = String::new();
+    let _ = write!(
+        out,
+        "<!DOCTYPE html>\n<html lang=\"en\">\n<head>\n<meta charset=\"utf-8\">\n<title>{0}</title>\n<style>{1}</style>\n</head>\n<body>\n<h1>{0}</h1>\n",
+        escape(title),
+        STYLE
+    );
+    for message in messages {
+        let _ = writeln!(out, "<section>\n<h2>{}</h2>", escape(&heading(message)));
+        for block in blocks(message) {
+            match block {
+                Block::Text(text) => out.push_str(&markdown_html(&text)),
+                Block::Code {
+                    label,
+                    language,
+                    code,
+                } => {
+                    let class = if language.is_empty() {
+                        String::new()
+                    } else {
+                        format!(" class=\"language-{}\"", language)
+                    };
+                    let _ = writeln!(
+                        out,
+                        "<div class=\"label\">{}</div>\n<pre><code{}>{}</code></pre>",
+                        escape(&label),
+                        class,
+                        escape(&code)
+                    );
+                }
+                Block::Image { mime_type, data } => {
+                    let _ = writeln!(
+                        out,
+                        "<img alt=\"image\" src=\"data:{};base64,{}\">",
+                        escape(&mime_type),
+                        escape(&data)
+                    );
+                }
+                Block::Audio { format, data } => {
+                    let _ = writeln!(
+                        out,
+                        "<audio controls src=\"data:audio/{};base64,{}\"></audio>",
+                        escape(&format),
+                        escape(&data)
+                    );
+                }
+                Block::Thinking(thinking) => {
+                    let _ = writeln!(
+                        out,
+                        "<details>\n<summary>Thinking</summary>\n{}</details>",
+                        markdown_html(&thinking)
+                    );
+                }
+                Block::Note(note) => {
+                    let _ = writeln!(out, "<p class=\"note\">{}</p>", escape(&note));
+                }
+                Block::Sources(sources) => {
+                    out.push_str("<p>Sources:</p>\n<ul>\n");
+                    for (title, url) in sources {
+                        if is_safe_url(&url) {
+                            let _ = writeln!(
+                                out,
+                                "<li><a href=\"{}\">{}</a></li>",
+                                escape(&url),
+                                escape(&title)
+                            );
+                        } else {
+                            let _ = writeln!(out, "<li>{}</li>", escape(&title));
+                        }
+                    }
+                    out.push_str("</ul>\n");
+                }
+            }
+        }
+        out.push_str("</section>\n");
+    }
+    out.push_str("</body>\n</html>\n");
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::message::Citation;
+    use mcp_core::tool::ToolCall;
+    use serde_json::json;
+
+    fn conversation() -> Vec<Message> {
+        vec![
+            Message::user().with_text("List the files <please>"),
+            Message::assistant()
+                .with_text("Sure, I'll run `ls`.")
+                .with_tool_request(
+                    "1",
+                    Ok(ToolCall::new("developer__shell", json!({"command": "ls"}))),
+                ),
+            Message::user().with_tool_response(
+                "1",
+                Ok(vec![
+                    Content::text("main.rs\n```\nlib.rs"),
+                    Content::image("aGVsbG8=", "image/png"),
+                ]),
+            ),
+            Message::assistant().with_sensitive_text("the token is abc"),
+        ]
+    }
+
+    #[test]
+    fn test_markdown() {
+        let markdown = to_markdown(&conversation(), "Files");
+        assert!(markdown.starts_with("# Files\n"));
+        assert!(markdown.contains("## User · "));
+        assert!(markdown.contains("## Tool results · "));
+        assert!(markdown.contains(
+            "**Tool call developer__shell**\n\n```json\n{\n  \"command\": \"ls\"\n}\n```"
+        ));
+        // The fence is longer than the one in the output
+        assert!(markdown.contains("**Tool result**\n\n````\nmain.rs\n```\nlib.rs\n````"));
+        assert!(markdown.contains("![image](data:image/png;base64,aGVsbG8=)"));
+        assert!(!markdown.contains("abc"));
+    }
+
+    #[test]
+    fn test_html() {
+        let html = to_html(&conversation(), "Files <1>");
+        assert!(html.starts_with("<!DOCTYPE html>"));
+        assert!(html.contains("<title>Files &lt;1&gt;</title>"));
+        assert!(html.contains("<p>List the files &lt;please&gt;</p>"));
+        assert!(html.contains("<code>ls</code>"));
+        assert!(html.contains("<pre><code>main.rs\n```\nlib.rs</code></pre>"));
+        assert!(html.contains("<img alt=\"image\" src=\"data:image/png;base64,aGVsbG8=\">"));
+        assert!(!html.contains("abc"));
+    }
+
+    #[test]
+    fn test_format_from_str() {
+        assert_eq!("HTML".parse(), Ok(ExportFormat::Html));
+        assert_eq!("md".parse(), Ok(ExportFormat::Markdown));
+        assert!("pdf".parse::<ExportFormat>().is_err());
+    }
+
+    #[test]
+    fn test_unsafe_content_is_left_out() {
+        let mut answer = Message::assistant().with_text(
+            "[safe](https://example.com) [click](javascript:alert(1)) [too](JaVa\tScRiPt:alert(2))",
+        );
+        answer.citations = vec![
+            Citation {
+                url: "javascript:alert(3)".to_string(),
+                title: Some("Bad".to_string()),
+                start_index: None,
+                end_index: None,
+            },
+            Citation {
+                url: "https://example.com/docs".to_string(),
+                title: None,
+                start_index: None,
+                end_index: None,
+            },
+        ];
+        let messages = vec![
+            Message::user()
+                .with_tool_response("1", Ok(vec![Content::text("the key is abc")]))
+                .with_sensitive("the key is abc"),
+            answer,
+        ];
+
+        let html = to_html(&messages, "Links");
+        assert!(!html.contains("abc"));
+        assert!(!html.to_lowercase().contains("javascript"));
+        assert!(html.contains("<a href=\"https://example.com\">safe</a>"));
+        assert!(html.contains("<a href=\"\">click</a>"));
+        assert!(html.contains("<li>Bad</li>"));
+        assert!(html.contains("<a href=\"https://example.com/docs\">"));
+
+        let markdown = to_markdown(&messages, "Links");
+        assert!(!markdown.contains("abc"));
+        assert!(markdown.contains("- Bad\n"));
+        assert!(markdown.contains("- [https://example.com/docs](<https://example.com/docs>)"));
+    }
+}
@@ -1,9 +1,16 @@
 pub mod agents;
+pub mod chunk;
 pub mod config;
+pub mod cron;
+pub mod export;
+pub mod memory;
 pub mod message;
 pub mod model;
+pub mod plan;
 pub mod prompt_template;
 pub mod providers;
+pub mod recipe;
+pub mod summarize;
 pub mod token_counter;
 pub mod tracing;
 pub mod truncate;
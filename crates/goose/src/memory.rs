@@ -0,0 +1,646 @@
+use anyhow::{Context, Result};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs::{self, File, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use tracing::warn;
+
+use crate::agents::moderation::{moderate_request, Moderator};
+use crate::config::Config;
+use crate::message::Message;
+use crate::model::ModelConfig;
+use crate::prompt_template::load_prompt_file;
+use crate::providers::base::{Provider, ProviderUsage};
+use crate::providers::errors::ProviderError;
+use crate::summarize::transcript;
+
+/// Memories recalled for a request, unless GOOSE_MEMORY_TOP_K is set
+const DEFAULT_TOP_K: usize = 5;
+
+/// Memories less similar than this to a request are never recalled
+const MIN_SIMILARITY: f32 = 0.3;
+
+/// A new fact this similar to a remembered one of the project is not stored again
+const DUPLICATE_SIMILARITY: f32 = 0.92;
+
+/// Longest transcript, in characters, that facts are distilled from, the end is kept
+const MAX_TRANSCRIPT_CHARS: usize = 100_000;
+
+const MEMORY_HEADER: &str = "Facts remembered from earlier sessions in this project, \
+    they may be out of date so check them when it matters:";
+
+/// A fact learned in one session, for the sessions that follow in the same project
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Memory {
+    pub id: u64,
+    /// The directory the session ran in, the fact is recalled there and below
+    pub project: PathBuf,
+    pub text: String,
+    pub embedding: Vec<f32>,
+    /// The provider and model that made `embedding`, see `embedder_id`
+    ///
+    /// Embeddings of different models can't be compared, so the facts are
+    /// embedded again when the embedder changes.
+    #[serde(default)]
+    pub embedder: String,
+    pub created: DateTime<Utc>,
+}
+
+impl Memory {
+    /// Whether the memory applies to sessions in `dir`
+    pub fn applies_to(&self, dir: &Path) -> bool {
+        dir.starts_with(&self.project)
+    }
+}
+
+/// How alike two embeddings are, from -1 to 1, and 0 when either is empty or they differ in length
+pub fn cosine_similarity(a: &[f32], b: &[f32]) -> f32 {
+    if a.len() != b.len() || a.is_empty() {
+        return 0.0;
+    }
+    let dot: f32 = a.iter().zip(b).map(|(x, y)| x * y).sum();
+    let norm = |v: &[f32]| v.iter().map(|x| x * x).sum::<f32>().sqrt();
+    let norms = norm(a) * norm(b);
+    if norms == 0.0 {
+        0.0
+    } else {
+        dot / norms
+    }
+}
+
+/// Names the model `provider` embeds with, as stored with each memory
+pub fn embedder_id(provider: &dyn Provider) -> String {
+    format!(
+        "{}/{}",
+        provider.instance_metadata().name,
+        provider.get_model_config().model_name
+    )
+}
+
+/// The memories of every project, kept in one JSON file
+pub struct MemoryStore {
+    path: PathBuf,
+}
+
+impl MemoryStore {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self { path: path.into() }
+    }
+
+    /// The store in GOOSE_MEMORY_FILE, by default ~/.config/goose/semantic_memory.json
+    pub fn from_config() -> Self {
+        let path = Config::global()
+            .get::<String>("GOOSE_MEMORY_FILE")
+            .map(PathBuf::from)
+            .unwrap_or_else(|_| {
+                dirs::home_dir()
+                    .map(|home| home.join(".config/goose/semantic_memory.json"))
+                    .unwrap_or_else(|| PathBuf::from(".config/goose/semantic_memory.json"))
+            });
+        Self::new(path)
+    }
+
+    /// Every memory, the oldest first
+    pub fn load(&self) -> Result<Vec<Memory>> {
+        match fs::read_to_string(&self.path) {
+            Ok(text) => serde_json::from_str(&text)
+                .with_context(|| format!("Could not read {}", self.path.display())),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Vec::new()),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Take the lock on the store, held until the file is dropped
+    ///
+    /// It is an advisory lock on a file of its own, so sessions that end at
+    /// the same time change the store one after another.
+    fn lock(&self) -> Result<File> {
+        if let Some(dir) = self.path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        let path = self.path.with_extension("lock");
+        let file = OpenOptions::new()
+            .create(true)
+            .truncate(false)
+            .write(true)
+            .open(&path)
+            .with_context(|| format!("Failed to open {}", path.display()))?;
+        file.lock()
+            .with_context(|| format!("Failed to lock {}", path.display()))?;
+        Ok(file)
+    }
+
+    /// Replace the store with `memories`, under the lock
+    fn save(&self, memories: &[Memory]) -> Result<()> {
+        // Write next to the store and move it in place, so a crash never leaves half a file
+        let tmp = self
+            .path
+            .with_extension(format!("{}.tmp", uuid::Uuid::new_v4()));
+        let mut options = OpenOptions::new();
+        options.write(true).create_new(true);
+        // Only the user may read what their sessions learned
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::OpenOptionsExt;
+            options.mode(0o600);
+        }
+        let mut file = options.open(&tmp)?;
+        file.write_all(serde_json::to_string(memories)?.as_bytes())?;
+        file.sync_all()?;
+        if let Err(e) = fs::rename(&tmp, &self.path) {
+            let _ = fs::remove_file(&tmp);
+            return Err(e.into());
+        }
+        Ok(())
+    }
+
+    /// The memories that apply to `dir`, or every one without it
+    pub fn list(&self, dir: Option<&Path>) -> Result<Vec<Memory>> {
+        let memories = self.load()?;
+        Ok(match dir {
+            Some(dir) => memories.into_iter().filter(|m| m.applies_to(dir)).collect(),
+            None => memories,
+        })
+    }
+
+    /// Store facts with their embeddings by `embedder` for `project`, skipping ones it already has
+    ///
+    /// Returns how many were stored.
+    pub fn add(
+        &self,
+        project: &Path,
+        embedder: &str,
+        facts: Vec<(String, Vec<f32>)>,
+    ) -> Result<usize> {
+        let _lock = self.lock()?;
+        let mut memories = self.load()?;
+        let mut next_id = memories.iter().map(|m| m.id).max().unwrap_or(0) + 1;
+        let mut added = 0;
+        for (text, embedding) in facts {
+            let known = memories.iter().any(|m| {
+                m.project == project
+                    && (m.text == text
+                        || (m.embedder == embedder
+                            && cosine_similarity(&m.embedding, &embedding) >= DUPLICATE_SIMILARITY))
+            });
+            if known {
+                continue;
+            }
+            memories.push(Memory {
+                id: next_id,
+                project: project.to_path_buf(),
+                text,
+                embedding,
+                embedder: embedder.to_string(),
+                created: Utc::now(),
+            });
+            next_id += 1;
+            added += 1;
+        }
+        if added > 0 {
+            self.save(&memories)?;
+        }
+        Ok(added)
+    }
+
+    /// Forget a memory, returning false when there is none with `id`
+    pub fn remove(&self, id: u64) -> Result<bool> {
+        let _lock = self.lock()?;
+        let mut memories = self.load()?;
+        let before = memories.len();
+        memories.retain(|m| m.id != id);
+        if memories.len() == before {
+            return Ok(false);
+        }
+        self.save(&memories)?;
+        Ok(true)
+    }
+
+    /// Replace the embeddings of the memories with the given ids by those of `embedder`
+    pub fn reembed(&self, embedder: &str, embeddings: Vec<(u64, Vec<f32>)>) -> Result<()> {
+        let _lock = self.lock()?;
+        let mut memories = self.load()?;
+        for (id, embedding) in embeddings {
+            if let Some(memory) = memories.iter_mut().find(|m| m.id == id) {
+                memory.embedding = embedding;
+                memory.embedder = embedder.to_string();
+            }
+        }
+        self.save(&memories)
+    }
+
+    /// The `k` memories that apply to `dir` and are most similar to `query`, the closest first
+    ///
+    /// Only memories embedded by `embedder` are compared with the query.
+    pub fn relevant(
+        &self,
+        dir: &Path,
+        embedder: &str,
+        query: &[f32],
+        k: usize,
+    ) -> Result<Vec<Memory>> {
+        let mut scored: Vec<(f32, Memory)> = self
+            .list(Some(dir))?
+            .into_iter()
+            .filter(|m| m.embedder == embedder)
+            .map(|m| (cosine_similarity(&m.embedding, query), m))
+            .filter(|(score, _)| *score >= MIN_SIMILARITY)
+            .collect();
+        scored.sort_by(|a, b| b.0.total_cmp(&a.0));
+        Ok(scored.into_iter().take(k).map(|(_, m)| m).collect())
+    }
+}
+
+/// The facts in a distillation response, one per "- " line
+fn parse_facts(text: &str) -> Vec<String> {
+    text.lines()
+        .filter_map(|line| line.trim().strip_prefix("- "))
+        .map(|fact| fact.trim().to_string())
+        .filter(|fact| !fact.is_empty())
+        .collect()
+}
+
+/// The memories as a section of the system prompt
+pub fn render(memories: &[Memory]) -> String {
+    let facts: Vec<String> = memories.iter().map(|m| format!("- {}", m.text)).collect();
+    format!("{}\n{}", MEMORY_HEADER, facts.join("\n"))
+}
+
+/// Remembers facts from sessions and recalls the ones that matter to a new request
+///
+/// At the end of a session the agent's model distills what is worth knowing
+/// about the project from the conversation. The facts are embedded and kept in
+/// a `MemoryStore`, keyed by the directory the session ran in. At the start of
+/// each reply the `top_k` facts closest to the user's request are added to the
+/// system prompt. Only the truncate agent, the default, has a memory.
+pub struct SemanticMemory {
+    store: MemoryStore,
+    top_k: usize,
+    embedder: Option<Box<dyn Provider>>,
+}
+
+impl SemanticMemory {
+    pub fn new(store: MemoryStore) -> Self {
+        Self {
+            store,
+            top_k: DEFAULT_TOP_K,
+            embedder: None,
+        }
+    }
+
+    /// Recall at most this many memories for a request
+    pub fn with_top_k(mut self, top_k: usize) -> Self {
+        self.top_k = top_k;
+        self
+    }
+
+    /// Embed with this provider instead of the agent's, for agents on a provider without embeddings
+    pub fn with_embedder(mut self, embedder: Box<dyn Provider>) -> Self {
+        self.embedder = Some(embedder);
+        self
+    }
+
+    /// Configure from GOOSE_MEMORY, GOOSE_MEMORY_TOP_K and GOOSE_MEMORY_EMBEDDING_PROVIDER
+    ///
+    /// Returns None unless GOOSE_MEMORY is true, memory is off by default.
+    /// Embeddings come from the agent's provider unless another provider is
+    /// named, which is created with its default model.
+    pub fn from_config() -> Option<Self> {
+        let config = Config::global();
+        if !config.get::<bool>("GOOSE_MEMORY").unwrap_or(false) {
+            return None;
+        }
+        let mut memory = Self::new(MemoryStore::from_config())
+            .with_top_k(config.get("GOOSE_MEMORY_TOP_K").unwrap_or(DEFAULT_TOP_K));
+        if let Ok(name) = config.get::<String>("GOOSE_MEMORY_EMBEDDING_PROVIDER") {
+            let model = crate::providers::providers()
+                .into_iter()
+                .find(|metadata| metadata.name == name)
+                .map(|metadata| metadata.default_model)
+                .unwrap_or_default();
+            match crate::providers::create(&name, ModelConfig::new(model)) {
+                Ok(embedder) => memory = memory.with_embedder(embedder),
+                Err(e) => warn!(
+                    "Could not create {} to embed memories with, using the agent's provider: {}",
+                    name, e
+                ),
+            }
+        }
+        Some(memory)
+    }
+
+    /// The provider that embeds for an agent on `provider`
+    fn embedder<'a>(&'a self, provider: &'a dyn Provider) -> &'a dyn Provider {
+        self.embedder.as_deref().unwrap_or(provider)
+    }
+
+    async fn embed(&self, provider: &dyn Provider, inputs: &[String]) -> Result<Vec<Vec<f32>>> {
+        let embedder = self.embedder(provider);
+        let embeddings = embedder.embed(inputs).await.map_err(|e| match e {
+            ProviderError::NotSupported(message) => anyhow::anyhow!(
+                "{}, set GOOSE_MEMORY_EMBEDDING_PROVIDER to a provider that does, \
+                 such as openai or ollama",
+                message
+            ),
+            e => e.into(),
+        })?;
+        anyhow::ensure!(
+            embeddings.len() == inputs.len(),
+            "Got {} embeddings for {} inputs",
+            embeddings.len(),
+            inputs.len()
+        );
+        Ok(embeddings)
+    }
+
+    /// Embed the memories of `dir` that another model embedded again, with the current embedder
+    async fn refresh(&self, provider: &dyn Provider, dir: &Path) -> Result<()> {
+        let embedder = embedder_id(self.embedder(provider));
+        let stale: Vec<Memory> = self
+            .store
+            .list(Some(dir))?
+            .into_iter()
+            .filter(|m| m.embedder != embedder)
+            .collect();
+        if stale.is_empty() {
+            return Ok(());
+        }
+        warn!(
+            "Embedding {} memories again with {}, they were embedded with another model",
+            stale.len(),
+            embedder
+        );
+        let texts: Vec<String> = stale.iter().map(|m| m.text.clone()).collect();
+        let embeddings = self.embed(provider, &texts).await?;
+        self.store.reembed(
+            &embedder,
+            stale.iter().map(|m| m.id).zip(embeddings).collect(),
+        )
+    }
+
+    /// The memories of `dir` that are relevant to `request`, as a section for the system prompt
+    ///
+    /// None when there are none, or they could not be looked up.
+    pub async fn recall(
+        &self,
+        provider: &dyn Provider,
+        dir: &Path,
+        request: &str,
+    ) -> Option<String> {
+        if request.trim().is_empty() || self.store.list(Some(dir)).ok()?.is_empty() {
+            return None;
+        }
+        let recalled = async {
+            self.refresh(provider, dir).await?;
+            let query = self.embed(provider, &[request.to_string()]).await?;
+            let embedder = embedder_id(self.embedder(provider));
+            self.store.relevant(dir, &embedder, &query[0], self.top_k)
+        };
+        match recalled.await {
+            Ok(memories) if !memories.is_empty() => Some(render(&memories)),
+            Ok(_) => None,
+            Err(e) => {
+                warn!("Could not recall memories: {}", e);
+                None
+            }
+        }
+    }
+
+    /// Distill facts about the project in `dir` from `messages` with `provider` and store them
+    ///
+    /// The messages go through `moderators` first, like every request of the
+    /// agent, so redacted secrets are neither sent nor remembered. Returns how
+    /// many new facts were stored, and the usage of the distillation request.
+    pub async fn remember(
+        &self,
+        provider: &dyn Provider,
+        moderators: &[Arc<dyn Moderator>],
+        dir: &Path,
+        messages: &[Message],
+    ) -> Result<(usize, ProviderUsage)> {
+        let system = load_prompt_file("memory.md", &HashMap::<String, String>::new())?;
+        let (system, messages) = moderate_request(moderators, &system, messages).await?;
+        let text = transcript(&messages);
+        let skip = text.chars().count().saturating_sub(MAX_TRANSCRIPT_CHARS);
+        let text: String = text.chars().skip(skip).collect();
+        let (response, usage) = provider
+            .complete(&system, &[Message::user().with_text(text)], &[])
+            .await?;
+
+        let facts = parse_facts(&response.as_concat_text());
+        if facts.is_empty() {
+            return Ok((0, usage));
+        }
+        // Compare the new facts with the known ones in the same embeddings
+        if let Err(e) = self.refresh(provider, dir).await {
+            warn!("Could not embed the remembered facts again: {}", e);
+        }
+        let embeddings = self.embed(provider, &facts).await?;
+        let embedder = embedder_id(self.embedder(provider));
+        let added = self
+            .store
+            .add(dir, &embedder, facts.into_iter().zip(embeddings).collect())?;
+        Ok((added, usage))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::providers::testing::TestProvider;
+    use tempfile::TempDir;
+
+    fn embedding(text: &str) -> Vec<f32> {
+        ["cargo", "test", "tabs", "format"]
+            .iter()
+            .map(|word| text.to_lowercase().contains(word) as u8 as f32)
+            .collect()
+    }
+
+    /// Distills two fixed facts and embeds text by the words it mentions
+    fn memory_provider() -> TestProvider {
+        TestProvider::new("test")
+            .with_text(
+                "- Run the tests with cargo test --workspace\n- The code is indented with tabs",
+            )
+            .with_embedding(embedding)
+    }
+
+    #[test]
+    fn test_cosine_similarity() {
+        assert_eq!(cosine_similarity(&[1.0, 0.0], &[1.0, 0.0]), 1.0);
+        assert_eq!(cosine_similarity(&[1.0, 0.0], &[0.0, 1.0]), 0.0);
+        assert_eq!(cosine_similarity(&[1.0, 0.0], &[1.0]), 0.0);
+        assert_eq!(cosine_similarity(&[0.0, 0.0], &[1.0, 0.0]), 0.0);
+    }
+
+    #[test]
+    fn test_parse_facts() {
+        assert_eq!(
+            parse_facts("Here they are:\n- one\n  - two \n-\nNONE"),
+            ["one", "two"]
+        );
+        assert!(parse_facts("NONE").is_empty());
+    }
+
+    #[test]
+    fn test_store() -> Result<()> {
+        let dir = TempDir::new()?;
+        let store = MemoryStore::new(dir.path().join("memory.json"));
+        let project = Path::new("/work/goose");
+        let facts = vec![
+            ("Use cargo test".to_string(), vec![1.0, 1.0, 0.0, 0.0]),
+            ("Indent with tabs".to_string(), vec![0.0, 0.0, 1.0, 0.0]),
+        ];
+        assert_eq!(store.add(project, "test/a", facts.clone())?, 2);
+        // The same facts are not stored twice, but another project gets its own
+        assert_eq!(store.add(project, "test/a", facts.clone())?, 0);
+        assert_eq!(store.add(Path::new("/work/other"), "test/a", facts)?, 2);
+
+        let memories = store.list(Some(&project.join("crates")))?;
+        assert_eq!(memories.len(), 2);
+        let relevant = store.relevant(project, "test/a", &[1.0, 0.0, 0.0, 0.0], 5)?;
+        assert_eq!(relevant.len(), 1);
+        assert_eq!(relevant[0].text, "Use cargo test");
+        // Embeddings of another model are not compared
+        assert!(store
+            .relevant(project, "test/b", &[1.0, 0.0, 0.0, 0.0], 5)?
+            .is_empty());
+
+        assert!(store.remove(relevant[0].id)?);
+        assert!(!store.remove(relevant[0].id)?);
+        assert_eq!(store.list(None)?.len(), 3);
+        Ok(())
+    }
+
+    #[test]
+    fn test_concurrent_adds() -> Result<()> {
+        let dir = TempDir::new()?;
+        let path = dir.path().join("memory.json");
+        // Separate stores share nothing but the file, like separate sessions
+        std::thread::scope(|scope| {
+            for i in 0..8 {
+                let store = MemoryStore::new(&path);
+                scope.spawn(move || {
+                    let mut embedding = vec![0.0; 8];
+                    embedding[i] = 1.0;
+                    store
+                        .add(
+                            Path::new("/work"),
+                            "test/a",
+                            vec![(format!("fact {}", i), embedding)],
+                        )
+                        .unwrap();
+                });
+            }
+        });
+        let mut ids: Vec<u64> = MemoryStore::new(&path)
+            .list(None)?
+            .iter()
+            .map(|m| m.id)
+            .collect();
+        ids.sort();
+        assert_eq!(ids, (1..=8).collect::<Vec<u64>>());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_remember_and_recall() -> Result<()> {
+        let dir = TempDir::new()?;
+        let memory = SemanticMemory::new(MemoryStore::new(dir.path().join("memory.json")));
+        let project = Path::new("/work/goose");
+        let messages = [Message::user().with_text("Fix the failing test")];
+
+        let (added, _) = memory
+            .remember(&memory_provider(), &[], project, &messages)
+            .await?;
+        assert_eq!(added, 2);
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = fs::metadata(dir.path().join("memory.json"))?
+                .permissions()
+                .mode();
+            assert_eq!(mode & 0o777, 0o600);
+        }
+
+        let recalled = memory
+            .recall(&memory_provider(), project, "Why does cargo test fail?")
+            .await
+            .unwrap();
+        assert!(recalled.starts_with(MEMORY_HEADER));
+        assert!(recalled.contains("- Run the tests with cargo test --workspace"));
+        assert!(!recalled.contains("tabs"));
+
+        // Nothing is remembered for other projects
+        assert!(memory
+            .recall(&memory_provider(), Path::new("/work/other"), "cargo test")
+            .await
+            .is_none());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_recall_embeds_stale_memories_again() -> Result<()> {
+        let dir = TempDir::new()?;
+        let store = MemoryStore::new(dir.path().join("memory.json"));
+        let project = Path::new("/work/goose");
+        // Made by another model, in embeddings of another size
+        store.add(
+            project,
+            "other/model",
+            vec![("Run cargo test before pushing".to_string(), vec![0.5; 16])],
+        )?;
+
+        let memory = SemanticMemory::new(store);
+        let recalled = memory
+            .recall(&memory_provider(), project, "How do I run cargo test?")
+            .await
+            .unwrap();
+        assert!(recalled.contains("Run cargo test before pushing"));
+        let stored = memory.store.list(None)?;
+        assert_eq!(stored[0].embedder, embedder_id(&memory_provider()));
+        assert_eq!(
+            stored[0].embedding,
+            embedding("Run cargo test before pushing")
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_remember_is_moderated() -> Result<()> {
+        struct Block;
+
+        #[async_trait::async_trait]
+        impl Moderator for Block {
+            async fn before_request(
+                &self,
+                _system: &mut String,
+                _messages: &mut Vec<Message>,
+            ) -> Result<(), crate::agents::moderation::ModerationError> {
+                Err(crate::agents::moderation::ModerationError::Blocked(
+                    "secrets".to_string(),
+                ))
+            }
+        }
+
+        let dir = TempDir::new()?;
+        let memory = SemanticMemory::new(MemoryStore::new(dir.path().join("memory.json")));
+        let messages = [Message::user().with_text("Fix the failing test")];
+        let moderators: Vec<Arc<dyn Moderator>> = vec![Arc::new(Block)];
+        let result = memory
+            .remember(
+                &memory_provider(),
+                &moderators,
+                Path::new("/work"),
+                &messages,
+            )
+            .await;
+        assert!(result.unwrap_err().to_string().contains("secrets"));
+        assert!(memory.store.list(None)?.is_empty());
+        Ok(())
+    }
+}
@@ -7,6 +7,8 @@ use std::collections::HashSet;
 ///
 /// The content of the messages uses MCP types to avoid additional conversions
 /// when interacting with MCP servers.
+use crate::providers::base::CacheControl;
+use base64::Engine;
 use chrono::Utc;
 use mcp_core::content::{Content, ImageContent, TextContent};
 use mcp_core::handler::ToolResult;
@@ -25,13 +27,43 @@ pub struct ToolResponse {
     pub tool_result: ToolResult<Vec<Content>>,
 }
 
+/// Base64 encoded audio, sent to audio input models or returned by audio output models
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct AudioContent {
+    pub data: String,
+    /// The encoding of the clip, e.g. "wav" or "mp3"
+    pub format: String,
+}
+
+impl AudioContent {
+    /// The raw bytes of the clip
+    pub fn bytes(&self) -> Result<Vec<u8>, base64::DecodeError> {
+        base64::engine::general_purpose::STANDARD.decode(&self.data)
+    }
+}
+
+/// The model declined to answer, with its explanation
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct RefusalContent {
+    pub refusal: String,
+}
+
+/// A summary of how a reasoning model arrived at its answer, for display only
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct ThinkingContent {
+    pub thinking: String,
+}
+
 #[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
 /// Content passed inside a message, which can be both simple content and tool content
 pub enum MessageContent {
     Text(TextContent),
     Image(ImageContent),
+    Audio(AudioContent),
     ToolRequest(ToolRequest),
     ToolResponse(ToolResponse),
+    Refusal(RefusalContent),
+    Thinking(ThinkingContent),
 }
 
 impl MessageContent {
@@ -50,6 +82,25 @@ impl MessageContent {
         })
     }
 
+    pub fn audio<S: Into<String>, T: Into<String>>(data: S, format: T) -> Self {
+        MessageContent::Audio(AudioContent {
+            data: data.into(),
+            format: format.into(),
+        })
+    }
+
+    pub fn refusal<S: Into<String>>(refusal: S) -> Self {
+        MessageContent::Refusal(RefusalContent {
+            refusal: refusal.into(),
+        })
+    }
+
+    pub fn thinking<S: Into<String>>(thinking: S) -> Self {
+        MessageContent::Thinking(ThinkingContent {
+            thinking: thinking.into(),
+        })
+    }
+
     pub fn tool_request<S: Into<String>>(id: S, tool_call: ToolResult<ToolCall>) -> Self {
         MessageContent::ToolRequest(ToolRequest {
             id: id.into(),
@@ -102,6 +153,22 @@ impl MessageContent {
             _ => None,
         }
     }
+
+    /// Get the explanation if this is a refusal
+    pub fn as_refusal(&self) -> Option<&str> {
+        match self {
+            MessageContent::Refusal(refusal) => Some(&refusal.refusal),
+            _ => None,
+        }
+    }
+
+    /// Get the reasoning summary if this is thinking content
+    pub fn as_thinking(&self) -> Option<&str> {
+        match self {
+            MessageContent::Thinking(thinking) => Some(&thinking.thinking),
+            _ => None,
+        }
+    }
 }
 
 impl From<Content> for MessageContent {
@@ -117,12 +184,31 @@ impl From<Content> for MessageContent {
     }
 }
 
+/// A web source the model cited, with the span of the text that relies on it
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct Citation {
+    pub url: String,
+    pub title: Option<String>,
+    /// Character range in the message text supported by this source
+    pub start_index: Option<usize>,
+    pub end_index: Option<usize>,
+}
+
 #[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
 /// A message to or from an LLM
 pub struct Message {
     pub role: Role,
     pub created: i64,
     pub content: Vec<MessageContent>,
+    /// Sources from a built-in web search the response is grounded on
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub citations: Vec<Citation>,
+    /// Lets the provider cache the conversation up to and including this message
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cache_control: Option<CacheControl>,
+    /// Texts of this message that reach the model as is but are masked in logs, traces and exports
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub sensitive: Vec<String>,
 }
 
 impl Message {
@@ -132,6 +218,9 @@ impl Message {
             role: Role::User,
             created: Utc::now().timestamp(),
             content: Vec::new(),
+            citations: Vec::new(),
+            cache_control: None,
+            sensitive: Vec::new(),
         }
     }
 
@@ -141,6 +230,9 @@ impl Message {
             role: Role::Assistant,
             created: Utc::now().timestamp(),
             content: Vec::new(),
+            citations: Vec::new(),
+            cache_control: None,
+            sensitive: Vec::new(),
         }
     }
 
@@ -155,11 +247,50 @@ impl Message {
         self.with_content(MessageContent::text(text))
     }
 
+    /// Add text content that is masked in logs, such as a pasted credential
+    pub fn with_sensitive_text<S: Into<String>>(mut self, text: S) -> Self {
+        let text = text.into();
+        self.sensitive.push(text.clone());
+        self.with_content(MessageContent::text(text))
+    }
+
+    /// Mask `text` wherever it appears in this message, e.g. in a tool result
+    pub fn with_sensitive<S: Into<String>>(mut self, text: S) -> Self {
+        self.sensitive.push(text.into());
+        self
+    }
+
+    /// Whether `text` was marked sensitive in this message
+    pub fn is_sensitive(&self, text: &str) -> bool {
+        self.sensitive.iter().any(|sensitive| sensitive == text)
+    }
+
     /// Add image content to the message
     pub fn with_image<S: Into<String>, T: Into<String>>(self, data: S, mime_type: T) -> Self {
         self.with_content(MessageContent::image(data, mime_type))
     }
 
+    /// Add audio content to the message
+    pub fn with_audio<S: Into<String>, T: Into<String>>(self, data: S, format: T) -> Self {
+        self.with_content(MessageContent::audio(data, format))
+    }
+
+    /// Add a refusal to the message
+    pub fn with_refusal<S: Into<String>>(self, refusal: S) -> Self {
+        self.with_content(MessageContent::refusal(refusal))
+    }
+
+    /// Add a reasoning summary to the message
+    pub fn with_thinking<S: Into<String>>(self, thinking: S) -> Self {
+        self.with_content(MessageContent::thinking(thinking))
+    }
+
+    /// Mark the conversation up to and including this message as cacheable
+    pub fn with_cache_control(mut self, cache_control: CacheControl) -> Self {
+        self.cache_control = Some(cache_control);
+        self
+    }
+
     /// Add a tool request to the message
     pub fn with_tool_request<S: Into<String>>(
         self,
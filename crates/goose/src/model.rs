@@ -1,4 +1,8 @@
+use crate::providers::base::CacheControl;
 use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::HashMap;
+use thiserror::Error;
 
 const DEFAULT_CONTEXT_LIMIT: usize = 128_000;
 
@@ -6,6 +10,108 @@ const DEFAULT_CONTEXT_LIMIT: usize = 128_000;
 pub const GPT_4O_TOKENIZER: &str = "Xenova--gpt-4o";
 pub const CLAUDE_TOKENIZER: &str = "Xenova--claude-tokenizer";
 
+/// Input modalities and features a model supports, inferred from its name
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ModelCapabilities {
+    /// Accepts `input_audio` content parts
+    pub audio_input: bool,
+    /// Can answer with speech when audio is requested through `modalities`
+    pub audio_output: bool,
+    /// Accepts images, in messages or as binary tool results
+    pub image_input: bool,
+    /// Served by the legacy completions endpoint, which can echo the prompt
+    pub echo: bool,
+    /// Returns token log probabilities with `logprobs`
+    pub logprobs: bool,
+    /// Can search the web on its own when the built-in web search tool is requested
+    pub web_search: bool,
+    /// Rejects the `system` role, instructions have to be sent as the first user message
+    pub system_as_user: bool,
+    /// Accepts `temperature`, `top_p` and stop sequences, reasoning models do not
+    pub sampling: bool,
+    /// Accepts `temperature` and `top_p` in the same request
+    pub temperature_with_top_p: bool,
+    /// Accepts `reasoning_effort`, the reasoning models from o1 on do
+    pub reasoning_effort: bool,
+    /// Continues a partial assistant message that ends the conversation
+    pub prefill: bool,
+    /// Can return a summary of its reasoning along with the answer
+    pub reasoning_summary: bool,
+    /// Accepts tool definitions, and tool calls and results in the conversation
+    pub tools: bool,
+    /// The most images a single request may hold, `None` where no limit is known
+    pub max_images: Option<usize>,
+}
+
+/// How long a reasoning model may think before answering
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ReasoningEffort {
+    Low,
+    Medium,
+    High,
+}
+
+/// What to do with a prefill for a model that can not continue one
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PrefillPolicy {
+    /// Fail the request with `InvalidRequest`
+    #[default]
+    Reject,
+    /// Send the request without the prefill and log a warning
+    Ignore,
+}
+
+/// The shape the model must answer in, for output that is parsed rather than read
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ResponseFormat {
+    /// Any valid JSON object
+    JsonObject,
+    /// A JSON document matching `schema`, which with `strict` the model can not deviate from
+    JsonSchema {
+        name: String,
+        schema: Value,
+        #[serde(default)]
+        strict: bool,
+    },
+}
+
+/// The voice and encoding of a spoken response
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AudioOutput {
+    /// The voice to speak with, e.g. "alloy"
+    pub voice: String,
+    /// The audio encoding, e.g. "wav" or "mp3"
+    pub format: String,
+}
+
+/// How to flatten a conversation into one prompt for backends without a messages API
+///
+/// Each turn is its role's prefix followed by the text, turns are joined with
+/// `separator`, and the prompt ends with the assistant prefix for the model to
+/// carry on from.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PromptTemplate {
+    pub system: String,
+    pub user: String,
+    pub assistant: String,
+    pub separator: String,
+}
+
+impl Default for PromptTemplate {
+    fn default() -> Self {
+        Self {
+            system: "### System:\n".to_string(),
+            user: "### User:\n".to_string(),
+            assistant: "### Assistant:\n".to_string(),
+            separator: "\n".to_string(),
+        }
+    }
+}
+
 /// Configuration for model-specific settings and limits
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ModelConfig {
@@ -21,6 +127,88 @@ pub struct ModelConfig {
     pub temperature: Option<f32>,
     /// Optional maximum tokens to generate
     pub max_tokens: Option<i32>,
+    /// Optional nucleus sampling probability mass (0.0 - 1.0]
+    #[serde(default)]
+    pub top_p: Option<f32>,
+    /// Optional penalty on tokens by how often they appeared so far (-2.0 - 2.0)
+    #[serde(default)]
+    pub frequency_penalty: Option<f32>,
+    /// Optional penalty on tokens that appeared at all so far (-2.0 - 2.0)
+    #[serde(default)]
+    pub presence_penalty: Option<f32>,
+    /// Optional sequences that end generation, they are not part of the response
+    #[serde(default)]
+    pub stop_sequences: Vec<String>,
+    /// Optional provider-specific parameters merged into the request body
+    pub extra_body: Option<Value>,
+    /// Optional language the model should respond in, e.g. "French"
+    pub response_locale: Option<String>,
+    /// Return the prompt along with the completion, only for models with the echo capability
+    #[serde(default)]
+    pub echo: bool,
+    /// Optional token ids that end generation, for backends that accept `stop_token_ids`
+    #[serde(default)]
+    pub stop_token_ids: Vec<u32>,
+    /// Prefix message text with its `created` time, for models that use recency
+    #[serde(default)]
+    pub include_timestamps: bool,
+    /// Optionally ask the provider to store the completion for later retrieval and evals
+    #[serde(default)]
+    pub store: Option<bool>,
+    /// Optional tags stored with the completion, used to filter stored completions
+    #[serde(default)]
+    pub metadata: Option<HashMap<String, String>>,
+    /// Convert the assistant's markdown to plain text before returning it
+    #[serde(default)]
+    pub strip_markdown: bool,
+    /// Trim whitespace around the assistant's text before returning it
+    #[serde(default)]
+    pub trim_response: bool,
+    /// With `trim_response`, keep trailing whitespace, which marks the token
+    /// boundary when a cut off response is continued
+    #[serde(default)]
+    pub preserve_trailing_whitespace: bool,
+    /// Optional expected output, e.g. the current file for an edit, to speed up generation
+    #[serde(default)]
+    pub prediction: Option<String>,
+    /// Optionally also answer with speech, only for models with the audio output capability
+    #[serde(default)]
+    pub audio_output: Option<AudioOutput>,
+    /// Send the conversation as a single prompt to the completions endpoint
+    #[serde(default)]
+    pub prompt_template: Option<PromptTemplate>,
+    /// Send tools as strict functions, so the arguments always match their schema
+    #[serde(default)]
+    pub strict_functions: bool,
+    /// Trade answer quality for latency and cost, only for reasoning models
+    #[serde(default)]
+    pub reasoning_effort: Option<ReasoningEffort>,
+    /// Optional start of the assistant's answer for the model to continue, e.g. "{"
+    #[serde(default)]
+    pub prefill: Option<String>,
+    /// How a prefill is handled for models without the prefill capability
+    #[serde(default)]
+    pub prefill_policy: PrefillPolicy,
+    /// Ask for a summary of the model's reasoning, returned as thinking content
+    ///
+    /// OpenAI only returns one from the Responses API, used for threads.
+    #[serde(default)]
+    pub reasoning_summary: bool,
+    /// Replace content the model can not accept with text placeholders instead of failing
+    #[serde(default)]
+    pub downconvert_unsupported: bool,
+    /// Tags such as `think` whose blocks are moved from the response text into thinking content
+    #[serde(default)]
+    pub thinking_tags: Vec<String>,
+    /// The most images a request may hold, overriding the limit known for the model
+    #[serde(default)]
+    pub max_images: Option<usize>,
+    /// Mark the system prompt and tool definitions as cacheable, for gateways that take `cache_control`
+    #[serde(default)]
+    pub cache_control: Option<CacheControl>,
+    /// Ask for JSON, or JSON matching a schema, instead of free text
+    #[serde(default)]
+    pub response_format: Option<ResponseFormat>,
 }
 
 impl ModelConfig {
@@ -40,6 +228,33 @@ impl ModelConfig {
             context_limit,
             temperature: None,
             max_tokens: None,
+            top_p: None,
+            frequency_penalty: None,
+            presence_penalty: None,
+            stop_sequences: Vec::new(),
+            extra_body: None,
+            response_locale: None,
+            echo: false,
+            stop_token_ids: Vec::new(),
+            include_timestamps: false,
+            store: None,
+            metadata: None,
+            strip_markdown: false,
+            trim_response: false,
+            preserve_trailing_whitespace: false,
+            prediction: None,
+            audio_output: None,
+            prompt_template: None,
+            strict_functions: false,
+            reasoning_effort: None,
+            prefill: None,
+            prefill_policy: PrefillPolicy::default(),
+            reasoning_summary: false,
+            downconvert_unsupported: false,
+            thinking_tags: Vec::new(),
+            max_images: None,
+            cache_control: None,
+            response_format: None,
         }
     }
 
@@ -70,6 +285,69 @@ impl ModelConfig {
         }
     }
 
+    /// Get the capabilities of the current model
+    pub fn capabilities(&self) -> ModelCapabilities {
+        let name = self.model_name.as_str();
+        let reasoning = ["o1", "o3", "o4"]
+            .iter()
+            .any(|family| name.rsplit('/').next().unwrap_or(name).starts_with(family));
+        ModelCapabilities {
+            // OpenAI audio models, https://platform.openai.com/docs/guides/audio
+            audio_input: name.contains("audio"),
+            audio_output: name.contains("audio"),
+            // Most current models take images, only known text-only families are excluded
+            image_input: !["o1-mini", "o1-preview", "gpt-3.5", "audio", "instruct"]
+                .iter()
+                .any(|family| name.contains(family)),
+            echo: ["instruct", "davinci", "babbage"]
+                .iter()
+                .any(|family| name.contains(family)),
+            // Reasoning models (o1, o3) do not return logprobs
+            logprobs: name.contains("gpt"),
+            // OpenAI search models (gpt-4o-search-preview, gpt-4o-mini-search-preview)
+            // and Perplexity sonar models served through OhMyGPT
+            web_search: name.contains("search") || name.contains("sonar"),
+            // Early OpenAI reasoning models and Gemma have no system role
+            system_as_user: ["o1-mini", "o1-preview", "gemma"]
+                .iter()
+                .any(|family| name.contains(family)),
+            // OpenAI reasoning models, also when routed as e.g. "openai/o3-mini"
+            sampling: !reasoning,
+            // Anthropic asks for one or the other, newer Claude models reject both
+            temperature_with_top_p: !name.contains("claude"),
+            // The o1 previews predate the parameter
+            reasoning_effort: reasoning
+                && !name.contains("o1-mini")
+                && !name.contains("o1-preview"),
+            // Anthropic continues a trailing assistant message instead of starting a new one
+            prefill: name.contains("claude"),
+            // Reasoning models that take an effort also summarize how they reasoned
+            reasoning_summary: reasoning
+                && !name.contains("o1-mini")
+                && !name.contains("o1-preview"),
+            // The o1 previews and the legacy completion models predate function calling
+            tools: ![
+                "o1-mini",
+                "o1-preview",
+                "gpt-3.5-turbo-instruct",
+                "davinci",
+                "babbage",
+            ]
+            .iter()
+            .any(|family| name.contains(family)),
+            // Documented per request limits of the Anthropic, Gemini and OpenAI APIs
+            max_images: if name.contains("claude") {
+                Some(100)
+            } else if name.contains("gemini") {
+                Some(3000)
+            } else if name.contains("gpt") || reasoning {
+                Some(500)
+            } else {
+                None
+            },
+        }
+    }
+
     /// Set an explicit context limit
     pub fn with_context_limit(mut self, limit: Option<usize>) -> Self {
         // Default is None and therefore DEFAULT_CONTEXT_LIMIT, only set
@@ -93,6 +371,180 @@ impl ModelConfig {
         self
     }
 
+    /// Set the nucleus sampling probability mass
+    pub fn with_top_p(mut self, top_p: Option<f32>) -> Self {
+        self.top_p = top_p;
+        self
+    }
+
+    /// Set the penalty on tokens by how often they appeared so far
+    pub fn with_frequency_penalty(mut self, penalty: Option<f32>) -> Self {
+        self.frequency_penalty = penalty;
+        self
+    }
+
+    /// Set the penalty on tokens that appeared at all so far
+    pub fn with_presence_penalty(mut self, penalty: Option<f32>) -> Self {
+        self.presence_penalty = penalty;
+        self
+    }
+
+    /// Set the sequences that end generation
+    pub fn with_stop_sequences(mut self, stop: Vec<String>) -> Self {
+        self.stop_sequences = stop;
+        self
+    }
+
+    /// Start a validated builder, an alternative to `new` and the `with_*` setters
+    pub fn builder(model_name: impl Into<String>) -> ModelConfigBuilder {
+        ModelConfigBuilder {
+            config: ModelConfig::new(model_name.into()),
+        }
+    }
+
+    /// Set extra body parameters, keys the request already sets take precedence
+    pub fn with_extra_body(mut self, extra_body: Option<Value>) -> Self {
+        self.extra_body = extra_body;
+        self
+    }
+
+    /// Set the language the model should respond in
+    pub fn with_response_locale(mut self, locale: Option<String>) -> Self {
+        self.response_locale = locale;
+        self
+    }
+
+    /// Set whether the prompt is echoed back with the completion
+    pub fn with_echo(mut self, echo: bool) -> Self {
+        self.echo = echo;
+        self
+    }
+
+    /// Set the token ids that stop generation
+    pub fn with_stop_token_ids(mut self, ids: Vec<u32>) -> Self {
+        self.stop_token_ids = ids;
+        self
+    }
+
+    /// Set whether message timestamps are included in the request content
+    pub fn with_include_timestamps(mut self, include: bool) -> Self {
+        self.include_timestamps = include;
+        self
+    }
+
+    /// Set whether the provider stores the completion
+    pub fn with_store(mut self, store: Option<bool>) -> Self {
+        self.store = store;
+        self
+    }
+
+    /// Set the metadata stored with the completion
+    pub fn with_metadata(mut self, metadata: Option<HashMap<String, String>>) -> Self {
+        self.metadata = metadata;
+        self
+    }
+
+    /// Set whether markdown is stripped from the response text
+    pub fn with_strip_markdown(mut self, strip: bool) -> Self {
+        self.strip_markdown = strip;
+        self
+    }
+
+    /// Set whether whitespace around the response text is trimmed
+    pub fn with_trim_response(mut self, trim: bool) -> Self {
+        self.trim_response = trim;
+        self
+    }
+
+    /// Set whether trimming keeps trailing whitespace, for continuation flows
+    pub fn with_preserve_trailing_whitespace(mut self, preserve: bool) -> Self {
+        self.preserve_trailing_whitespace = preserve;
+        self
+    }
+
+    /// Set the predicted output
+    pub fn with_prediction(mut self, prediction: Option<String>) -> Self {
+        self.prediction = prediction;
+        self
+    }
+
+    /// Set the voice and format of a spoken response, or `None` for text only
+    pub fn with_audio_output(mut self, audio_output: Option<AudioOutput>) -> Self {
+        self.audio_output = audio_output;
+        self
+    }
+
+    /// Set the template for backends that take a single prompt instead of messages
+    pub fn with_prompt_template(mut self, template: Option<PromptTemplate>) -> Self {
+        self.prompt_template = template;
+        self
+    }
+
+    /// Set whether tools are sent with `strict: true`
+    pub fn with_strict_functions(mut self, strict: bool) -> Self {
+        self.strict_functions = strict;
+        self
+    }
+
+    /// Set the reasoning effort, or `None` for the model's default
+    pub fn with_reasoning_effort(mut self, effort: Option<ReasoningEffort>) -> Self {
+        self.reasoning_effort = effort;
+        self
+    }
+
+    /// Set the start of the assistant's answer, or `None` to let the model begin
+    pub fn with_prefill(mut self, prefill: Option<String>) -> Self {
+        self.prefill = prefill;
+        self
+    }
+
+    /// Set how a prefill is handled for models that can not continue one
+    pub fn with_prefill_policy(mut self, policy: PrefillPolicy) -> Self {
+        self.prefill_policy = policy;
+        self
+    }
+
+    /// Set whether to ask for a summary of the model's reasoning
+    pub fn with_reasoning_summary(mut self, summary: bool) -> Self {
+        self.reasoning_summary = summary;
+        self
+    }
+
+    /// Set whether unsupported content is replaced by text placeholders instead of failing the request
+    pub fn with_downconvert_unsupported(mut self, downconvert: bool) -> Self {
+        self.downconvert_unsupported = downconvert;
+        self
+    }
+
+    /// Set the tags whose blocks are extracted from the response text as thinking content
+    pub fn with_thinking_tags(mut self, tags: Vec<String>) -> Self {
+        self.thinking_tags = tags;
+        self
+    }
+
+    /// Set the most images a request may hold, `None` uses the limit known for the model
+    pub fn with_max_images(mut self, max_images: Option<usize>) -> Self {
+        self.max_images = max_images;
+        self
+    }
+
+    /// Set whether the system prompt and tool definitions are sent as cacheable
+    pub fn with_cache_control(mut self, cache_control: Option<CacheControl>) -> Self {
+        self.cache_control = cache_control;
+        self
+    }
+
+    /// Set the format of the answer, or `None` for free text
+    pub fn with_response_format(mut self, response_format: Option<ResponseFormat>) -> Self {
+        self.response_format = response_format;
+        self
+    }
+
+    /// The most images a request may hold, from the override or the model's capabilities
+    pub fn max_images(&self) -> Option<usize> {
+        self.max_images.or(self.capabilities().max_images)
+    }
+
     // Get the tokenizer name
     pub fn tokenizer_name(&self) -> &str {
         &self.tokenizer_name
@@ -105,6 +557,79 @@ impl ModelConfig {
     }
 }
 
+/// Invalid settings rejected by `ModelConfigBuilder::build`
+#[derive(Error, Debug, PartialEq)]
+pub enum ModelConfigError {
+    #[error("temperature must be between 0 and 2, got {0}")]
+    Temperature(f32),
+    #[error("top_p must be greater than 0 and at most 1, got {0}")]
+    TopP(f32),
+    #[error("max_tokens must be positive, got {0}")]
+    MaxTokens(i32),
+    #[error("context_limit must be positive")]
+    ContextLimit,
+    #[error("{0} does not accept both temperature and top_p, set only one")]
+    TemperatureWithTopP(String),
+}
+
+/// Builds a `ModelConfig`, checking the settings are valid for the model
+#[derive(Debug, Clone)]
+pub struct ModelConfigBuilder {
+    config: ModelConfig,
+}
+
+impl ModelConfigBuilder {
+    pub fn temperature(mut self, temperature: f32) -> Self {
+        self.config.temperature = Some(temperature);
+        self
+    }
+
+    pub fn top_p(mut self, top_p: f32) -> Self {
+        self.config.top_p = Some(top_p);
+        self
+    }
+
+    pub fn max_tokens(mut self, max_tokens: i32) -> Self {
+        self.config.max_tokens = Some(max_tokens);
+        self
+    }
+
+    pub fn context_limit(mut self, limit: usize) -> Self {
+        self.config.context_limit = Some(limit);
+        self
+    }
+
+    pub fn build(self) -> Result<ModelConfig, ModelConfigError> {
+        let config = self.config;
+        if let Some(temperature) = config.temperature {
+            if !(0.0..=2.0).contains(&temperature) {
+                return Err(ModelConfigError::Temperature(temperature));
+            }
+        }
+        if let Some(top_p) = config.top_p {
+            if !(top_p > 0.0 && top_p <= 1.0) {
+                return Err(ModelConfigError::TopP(top_p));
+            }
+        }
+        if let Some(max_tokens) = config.max_tokens {
+            if max_tokens <= 0 {
+                return Err(ModelConfigError::MaxTokens(max_tokens));
+            }
+        }
+        if config.context_limit == Some(0) {
+            return Err(ModelConfigError::ContextLimit);
+        }
+        // Anthropic models reject requests that set both sampling parameters
+        if config.temperature.is_some()
+            && config.top_p.is_some()
+            && config.model_name.contains("claude")
+        {
+            return Err(ModelConfigError::TemperatureWithTopP(config.model_name));
+        }
+        Ok(config)
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -128,6 +653,105 @@ mod tests {
         assert_eq!(config.context_limit(), DEFAULT_CONTEXT_LIMIT);
     }
 
+    #[test]
+    fn test_model_capabilities() {
+        let config = ModelConfig::new("gpt-4o-audio-preview".to_string());
+        assert!(config.capabilities().audio_input);
+        assert!(config.capabilities().audio_output);
+        assert!(!config.capabilities().image_input);
+
+        let config = ModelConfig::new("gpt-4o".to_string());
+        assert!(config.capabilities().image_input);
+        assert!(!config.capabilities().audio_input);
+        assert!(!config.capabilities().audio_output);
+        assert!(!config.capabilities().web_search);
+
+        let config = ModelConfig::new("gpt-4o-search-preview".to_string());
+        assert!(config.capabilities().web_search);
+        assert!(!config.capabilities().system_as_user);
+
+        let config = ModelConfig::new("o1-mini".to_string());
+        assert!(config.capabilities().system_as_user);
+        assert!(!config.capabilities().sampling);
+        assert!(
+            !ModelConfig::new("openai/o3-mini".to_string())
+                .capabilities()
+                .sampling
+        );
+        assert!(
+            ModelConfig::new("gpt-4o".to_string())
+                .capabilities()
+                .sampling
+        );
+
+        let config = ModelConfig::new("claude-3-5-sonnet-latest".to_string());
+        assert!(!config.capabilities().temperature_with_top_p);
+        assert!(config.capabilities().sampling);
+        assert!(config.capabilities().prefill);
+        assert!(
+            !ModelConfig::new("gpt-4o".to_string())
+                .capabilities()
+                .prefill
+        );
+    }
+
+    #[test]
+    fn test_builder_valid() {
+        let config = ModelConfig::builder("gpt-4o")
+            .temperature(0.7)
+            .top_p(0.9)
+            .max_tokens(1000)
+            .context_limit(50_000)
+            .build()
+            .unwrap();
+        assert_eq!(config.temperature, Some(0.7));
+        assert_eq!(config.top_p, Some(0.9));
+        assert_eq!(config.max_tokens, Some(1000));
+        assert_eq!(config.context_limit(), 50_000);
+    }
+
+    #[test]
+    fn test_builder_validation() {
+        let build = |builder: ModelConfigBuilder| builder.build().unwrap_err();
+
+        assert_eq!(
+            build(ModelConfig::builder("gpt-4o").temperature(2.5)),
+            ModelConfigError::Temperature(2.5)
+        );
+        assert_eq!(
+            build(ModelConfig::builder("gpt-4o").temperature(-0.1)),
+            ModelConfigError::Temperature(-0.1)
+        );
+        assert_eq!(
+            build(ModelConfig::builder("gpt-4o").top_p(0.0)),
+            ModelConfigError::TopP(0.0)
+        );
+        assert_eq!(
+            build(ModelConfig::builder("gpt-4o").top_p(1.5)),
+            ModelConfigError::TopP(1.5)
+        );
+        assert_eq!(
+            build(ModelConfig::builder("gpt-4o").max_tokens(0)),
+            ModelConfigError::MaxTokens(0)
+        );
+        assert_eq!(
+            build(ModelConfig::builder("gpt-4o").max_tokens(-5)),
+            ModelConfigError::MaxTokens(-5)
+        );
+        assert_eq!(
+            build(ModelConfig::builder("gpt-4o").context_limit(0)),
+            ModelConfigError::ContextLimit
+        );
+        assert_eq!(
+            build(
+                ModelConfig::builder("claude-3-5-sonnet")
+                    .temperature(0.5)
+                    .top_p(0.9)
+            ),
+            ModelConfigError::TemperatureWithTopP("claude-3-5-sonnet".to_string())
+        );
+    }
+
     #[test]
     fn test_model_config_settings() {
         let config = ModelConfig::new("test-model".to_string())
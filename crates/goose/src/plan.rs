@@ -0,0 +1,214 @@
+use crate::message::Message;
+use crate::prompt_template::load_prompt_file;
+use crate::providers::base::{Provider, ProviderUsage};
+use crate::providers::errors::ProviderError;
+use crate::summarize::transcript;
+use lazy_static::lazy_static;
+use mcp_core::tool::Tool;
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fmt;
+
+lazy_static! {
+    /// A fenced block, as the plan prompt asks for the JSON
+    static ref FENCED: Regex = Regex::new(r"(?s)```(?:json)?\s*(.*?)\s*```").unwrap();
+    /// A comma before a closing bracket, which the JSON in some plans has
+    static ref TRAILING_COMMA: Regex = Regex::new(r",\s*([\]}])").unwrap();
+    /// The numbering or bullet in front of a step in the editable text
+    static ref STEP_MARKER: Regex = Regex::new(r"^(?:\d+[.)]|[-*])\s*").unwrap();
+}
+
+/// How far a step of the plan got
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum StepStatus {
+    #[default]
+    Pending,
+    Done,
+    Skipped,
+}
+
+/// A single step the agent intends to take
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PlanStep {
+    pub description: String,
+    #[serde(default)]
+    pub status: StepStatus,
+}
+
+impl PlanStep {
+    pub fn new<S: Into<String>>(description: S) -> Self {
+        Self {
+            description: description.into(),
+            status: StepStatus::Pending,
+        }
+    }
+}
+
+/// The steps proposed for a request, to be reviewed before any of them runs
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Plan {
+    pub steps: Vec<PlanStep>,
+}
+
+impl Plan {
+    pub fn new(steps: Vec<PlanStep>) -> Self {
+        Self { steps }
+    }
+
+    /// Read the plan from a model response, a JSON list of `{"description": ...}`
+    ///
+    /// The list may be inside a fenced block and have trailing commas.
+    pub fn parse(text: &str) -> Result<Self, ProviderError> {
+        let json = FENCED
+            .captures(text)
+            .and_then(|caps| caps.get(1))
+            .map_or(text.trim(), |json| json.as_str());
+        let json = TRAILING_COMMA.replace_all(json, "$1");
+        let steps: Vec<PlanStep> = serde_json::from_str(&json)
+            .map_err(|e| ProviderError::invalid_response("The plan is not a list of steps", e))?;
+        let steps: Vec<PlanStep> = steps
+            .into_iter()
+            .filter(|step| !step.description.trim().is_empty())
+            .collect();
+        if steps.is_empty() {
+            return Err(ProviderError::ExecutionError(
+                "The plan has no steps".to_string(),
+            ));
+        }
+        Ok(Self::new(steps))
+    }
+
+    /// The plan as numbered lines, for the user to edit
+    pub fn to_editable(&self) -> String {
+        self.steps
+            .iter()
+            .enumerate()
+            .map(|(i, step)| format!("{}. {}\n", i + 1, step.description))
+            .collect()
+    }
+
+    /// Read a plan the user edited, one step per line
+    ///
+    /// Numbering and bullets are optional, blank lines and lines starting with
+    /// `#` are left out.
+    pub fn from_editable(text: &str) -> Self {
+        let steps = text
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty() && !line.starts_with('#'))
+            .map(|line| PlanStep::new(STEP_MARKER.replace(line, "").trim()))
+            .filter(|step| !step.description.is_empty())
+            .collect();
+        Self::new(steps)
+    }
+
+    /// The index of the first step that has not run yet
+    pub fn next_step(&self) -> Option<usize> {
+        self.steps
+            .iter()
+            .position(|step| step.status == StepStatus::Pending)
+    }
+
+    /// The message asking the agent to carry out the step at `index` and nothing else
+    pub fn step_message(&self, index: usize) -> Message {
+        Message::user().with_text(format!(
+            "Carry out step {} of {} of the approved plan: {}\n\n\
+             Do only this step, then stop and report what you did.",
+            index + 1,
+            self.steps.len(),
+            self.steps[index].description
+        ))
+    }
+}
+
+impl fmt::Display for Plan {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, step) in self.steps.iter().enumerate() {
+            let mark = match step.status {
+                StepStatus::Pending => " ",
+                StepStatus::Done => "x",
+                StepStatus::Skipped => "-",
+            };
+            writeln!(f, "[{}] {}. {}", mark, i + 1, step.description)?;
+        }
+        Ok(())
+    }
+}
+
+/// Ask `provider` for a plan for the latest request in `messages`
+///
+/// The model sees the conversation as a transcript and the tools only by name
+/// and description, so nothing runs while planning.
+pub async fn create_plan(
+    provider: &dyn Provider,
+    messages: &[Message],
+    tools: &[Tool],
+) -> Result<(Plan, ProviderUsage), ProviderError> {
+    let tools: Vec<HashMap<&str, &str>> = tools
+        .iter()
+        .map(|tool| {
+            HashMap::from([
+                ("name", tool.name.as_str()),
+                ("description", tool.description.as_str()),
+            ])
+        })
+        .collect();
+    let system = load_prompt_file("plan.md", &HashMap::from([("tools", tools)]))
+        .map_err(|e| ProviderError::ExecutionError(e.to_string()))?;
+    let request = Message::user().with_text(transcript(messages));
+    let (response, usage) = provider.complete(&system, &[request], &[]).await?;
+    Ok((Plan::parse(&response.as_concat_text())?, usage))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_plan() {
+        let text = "Here is the plan:\n```json\n[\n    {\"description\": \"create a directory 'demo'\"},\n    {\"description\": \"run the tests\"},\n]\n```";
+        let plan = Plan::parse(text).unwrap();
+        assert_eq!(
+            plan.steps,
+            vec![
+                PlanStep::new("create a directory 'demo'"),
+                PlanStep::new("run the tests")
+            ]
+        );
+
+        assert!(Plan::parse("I would start by looking around").is_err());
+        assert!(Plan::parse("[]").is_err());
+    }
+
+    #[test]
+    fn test_editable_round_trip() {
+        let plan = Plan::new(vec![
+            PlanStep::new("read the config"),
+            PlanStep::new("fix it"),
+        ]);
+        assert_eq!(plan.to_editable(), "1. read the config\n2. fix it\n");
+
+        let edited =
+            "# Remove a line to drop a step\n1. read the config\n\n- back it up\n2) fix it\n";
+        let plan = Plan::from_editable(edited);
+        let steps: Vec<_> = plan.steps.iter().map(|s| s.description.as_str()).collect();
+        assert_eq!(steps, ["read the config", "back it up", "fix it"]);
+    }
+
+    #[test]
+    fn test_next_step() {
+        let mut plan = Plan::new(vec![PlanStep::new("one"), PlanStep::new("two")]);
+        assert_eq!(plan.next_step(), Some(0));
+        plan.steps[0].status = StepStatus::Done;
+        assert_eq!(plan.next_step(), Some(1));
+        assert!(plan
+            .step_message(1)
+            .as_concat_text()
+            .contains("step 2 of 2"));
+        plan.steps[1].status = StepStatus::Skipped;
+        assert_eq!(plan.next_step(), None);
+        assert_eq!(plan.to_string(), "[x] 1. one\n[-] 2. two\n");
+    }
+}
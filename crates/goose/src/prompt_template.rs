@@ -37,6 +37,25 @@ pub fn load_prompt_file<T: Serialize>(
     load_prompt(&template_content, context_data)
 }
 
+/// Render `template`, which can extend or include the embedded prompts by file name
+///
+/// A template that starts with `{% extends "system.md" %}` keeps the built-in
+/// system prompt and only replaces the blocks it defines.
+pub fn load_prompt_extending<T: Serialize>(
+    template: &str,
+    context_data: &T,
+) -> Result<String, TeraError> {
+    let mut tera = Tera::default();
+    tera.add_raw_templates(PROMPTS_DIR.files().filter_map(|file| {
+        let name = file.path().to_str()?;
+        Some((name, String::from_utf8_lossy(file.contents())))
+    }))?;
+    tera.add_raw_template("inline_template", template)?;
+    let context = Context::from_serialize(context_data)?;
+    let rendered = tera.render("inline_template", &context)?;
+    Ok(rendered.trim().to_string())
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -88,6 +107,22 @@ mod tests {
         assert!(result.is_err());
     }
 
+    #[test]
+    fn test_load_prompt_extending() {
+        let template = r#"{% extends "system.md" %}{% block intro %}You are Goose on {{ os }}.{% endblock intro %}"#;
+        let context = json!({
+            "os": "linux",
+            "extensions": [],
+            "additional_instructions": ["Answer in French.", "Be brief."],
+        });
+        let result = load_prompt_extending(template, &context).unwrap();
+        assert!(result.starts_with("You are Goose on linux."));
+        assert!(result.contains("No extensions are defined."));
+        assert!(result.ends_with("# Additional Instructions:\n\nAnswer in French.\n\nBe brief."));
+
+        assert!(load_prompt_extending(r#"{% extends "missing.md" %}"#, &context).is_err());
+    }
+
     #[test]
     fn test_load_prompt_with_tools() {
         let template = "### Tool Descriptions\n{% for tool in tools %}\n{{tool.name}}: {{tool.description}}{% endfor %}";
@@ -6,8 +6,14 @@ use std::time::Duration;
 
 use super::base::{ConfigKey, Provider, ProviderMetadata, ProviderUsage};
 use super::errors::ProviderError;
-use super::formats::anthropic::{create_request, get_usage, response_to_message};
-use super::utils::{emit_debug_trace, get_model};
+use super::formats::anthropic::{
+    computer_use_beta, create_request, get_usage, response_to_message,
+};
+use super::utils::{
+    emit_debug_trace, get_model, rate_limit_reset, send_with_retry, validate_params,
+    validate_tool_pairing, RetryConfig,
+};
+use crate::config::Secret;
 use crate::message::Message;
 use crate::model::ModelConfig;
 use mcp_core::tool::Tool;
@@ -26,8 +32,9 @@ pub struct AnthropicProvider {
     #[serde(skip)]
     client: Client,
     host: String,
-    api_key: String,
+    api_key: Secret,
     model: ModelConfig,
+    retry: RetryConfig,
 }
 
 impl Default for AnthropicProvider {
@@ -40,7 +47,7 @@ impl Default for AnthropicProvider {
 impl AnthropicProvider {
     pub fn from_env(model: ModelConfig) -> Result<Self> {
         let config = crate::config::Config::global();
-        let api_key: String = config.get_secret("ANTHROPIC_API_KEY")?;
+        let api_key = config.get_secret_handle("ANTHROPIC_API_KEY")?;
         let host: String = config
             .get("ANTHROPIC_HOST")
             .unwrap_or_else(|_| "https://api.anthropic.com".to_string());
@@ -54,30 +61,37 @@ impl AnthropicProvider {
             host,
             api_key,
             model,
+            retry: RetryConfig::default(),
         })
     }
 
     async fn post(&self, payload: Value) -> Result<Value, ProviderError> {
         let url = format!("{}/v1/messages", self.host.trim_end_matches('/'));
 
-        let response = self
-            .client
-            .post(&url)
-            .header("x-api-key", &self.api_key)
-            .header("anthropic-version", "2023-06-01")
-            .json(&payload)
-            .send()
-            .await?;
+        let beta = computer_use_beta(&payload);
+        let response = send_with_retry(&self.retry, || async {
+            let mut request = self
+                .client
+                .post(&url)
+                .header("x-api-key", &self.api_key.expose())
+                .header("anthropic-version", "2023-06-01");
+            if let Some(beta) = beta {
+                request = request.header("anthropic-beta", beta);
+            }
+            Ok(request.json(&payload).send().await?)
+        })
+        .await?;
 
         let status = response.status();
+        let reset = rate_limit_reset(response.headers());
         let payload: Option<Value> = response.json().await.ok();
 
         // https://docs.anthropic.com/en/api/errors
         match status {
-            StatusCode::OK => payload.ok_or_else( || ProviderError::RequestFailed("Response body is not valid JSON".to_string()) ),
+            StatusCode::OK => payload.ok_or_else( || ProviderError::request_failed("Response body is not valid JSON".to_string()) ),
             StatusCode::UNAUTHORIZED | StatusCode::FORBIDDEN => {
-                Err(ProviderError::Authentication(format!("Authentication failed. Please ensure your API keys are valid and have the required permissions. \
-                    Status: {}. Response: {:?}", status, payload)))
+                Err(ProviderError::authentication(format!("Authentication failed. Please ensure your API keys are valid and have the required permissions. \
+                    Status: {}. Response: {:?}", status, payload)).with_key_of(&Self::metadata()))
             }
             StatusCode::BAD_REQUEST => {
                 let mut error_msg = "Unknown error".to_string();
@@ -92,10 +106,10 @@ impl AnthropicProvider {
                 tracing::debug!(
                     "{}", format!("Provider request failed with status: {}. Payload: {:?}", status, payload)
                 );
-                Err(ProviderError::RequestFailed(format!("Request failed with status: {}. Message: {}", status, error_msg)))
+                Err(ProviderError::request_failed(format!("Request failed with status: {}. Message: {}", status, error_msg)))
             }
             StatusCode::TOO_MANY_REQUESTS => {
-                Err(ProviderError::RateLimitExceeded(format!("{:?}", payload)))
+                Err(ProviderError::rate_limited(format!("{:?}", payload)).with_retry_after(reset))
             }
             StatusCode::INTERNAL_SERVER_ERROR | StatusCode::SERVICE_UNAVAILABLE => {
                 Err(ProviderError::ServerError(format!("{:?}", payload)))
@@ -104,7 +118,7 @@ impl AnthropicProvider {
                 tracing::debug!(
                     "{}", format!("Provider request failed with status: {}. Payload: {:?}", status, payload)
                 );
-                Err(ProviderError::RequestFailed(format!("Request failed with status: {}", status)))
+                Err(ProviderError::request_failed(format!("Request failed with status: {}", status)))
             }
         }
     }
@@ -135,6 +149,10 @@ impl Provider for AnthropicProvider {
         )
     }
 
+    fn instance_metadata(&self) -> ProviderMetadata {
+        Self::metadata()
+    }
+
     fn get_model_config(&self) -> ModelConfig {
         self.model.clone()
     }
@@ -149,6 +167,8 @@ impl Provider for AnthropicProvider {
         messages: &[Message],
         tools: &[Tool],
     ) -> Result<(Message, ProviderUsage), ProviderError> {
+        validate_params(&self.model)?;
+        validate_tool_pairing(messages)?;
         let payload = create_request(&self.model, system, messages, tools)?;
 
         // Make request
@@ -159,7 +179,7 @@ impl Provider for AnthropicProvider {
         let usage = get_usage(&response)?;
 
         let model = get_model(&response);
-        emit_debug_trace(self, &payload, &response, &usage);
+        emit_debug_trace(self, messages, &payload, &response, &usage);
         Ok((message, ProviderUsage::new(model, usage)))
     }
 }
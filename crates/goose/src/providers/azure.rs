@@ -0,0 +1,420 @@
+use anyhow::{anyhow, Context, Result};
+use async_trait::async_trait;
+use chrono::{DateTime, Duration as ChronoDuration, Utc};
+use reqwest::{Client, RequestBuilder, StatusCode};
+use serde::Deserialize;
+use serde_json::{json, Value};
+use std::time::Duration;
+use tokio::sync::Mutex;
+
+use super::base::{ConfigKey, MessageStream, Provider, ProviderMetadata, ProviderUsage, Usage};
+use super::errors::ProviderError;
+use super::formats::openai::{create_request, get_usage, response_to_message};
+use super::streaming::{openai_message_stream, sse_events};
+use super::utils::{
+    emit_debug_trace, get_model, get_system_fingerprint, handle_response_openai_compat,
+    send_with_retry, validate_params, validate_tool_pairing, ImageFormat, RetryConfig,
+};
+use crate::config::Secret;
+use crate::message::Message;
+use crate::model::ModelConfig;
+use mcp_core::tool::Tool;
+
+pub const AZURE_DEFAULT_MODEL: &str = "gpt-4o";
+pub const AZURE_KNOWN_MODELS: &[&str] = &["gpt-4o", "gpt-4o-mini", "gpt-4", "o1", "o1-mini"];
+pub const AZURE_DEFAULT_API_VERSION: &str = "2024-10-21";
+
+pub const AZURE_DOC_URL: &str =
+    "https://learn.microsoft.com/en-us/azure/ai-services/openai/concepts/models";
+
+/// The Entra ID scope of Azure OpenAI
+const COGNITIVE_SERVICES_SCOPE: &str = "https://cognitiveservices.azure.com/.default";
+/// Entra ID tokens are refreshed this long before they expire
+const TOKEN_EXPIRY_MARGIN: ChronoDuration = ChronoDuration::minutes(5);
+
+/// An Entra ID access token and when it expires
+#[derive(Clone)]
+struct AccessToken {
+    token: String,
+    expires_at: DateTime<Utc>,
+}
+
+/// The answer of the Entra ID token endpoint
+#[derive(Deserialize)]
+struct TokenResponse {
+    access_token: String,
+    expires_in: i64,
+}
+
+/// The output of `az account get-access-token`
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct CliToken {
+    access_token: String,
+    /// Seconds since the epoch, printed by newer versions of the CLI
+    #[serde(default)]
+    expires_on: Option<i64>,
+}
+
+/// How requests are authenticated
+#[derive(serde::Serialize)]
+enum AzureAuth {
+    /// The key of the Azure OpenAI resource, sent as `api-key`
+    ApiKey(#[serde(skip)] Secret),
+    /// An Entra ID service principal with a client secret
+    ClientSecret {
+        tenant_id: String,
+        client_id: String,
+        #[serde(skip)]
+        client_secret: Secret,
+    },
+    /// Tokens of the user signed in to the Azure CLI
+    AzureCli,
+}
+
+/// Deployments of the Azure OpenAI Service
+///
+/// Azure addresses a model by the name of its deployment in the URL, with the
+/// API version as a query parameter. Requests authenticate with the resource's
+/// API key or, without one, with an Entra ID token from a service principal
+/// (`AZURE_TENANT_ID`, `AZURE_CLIENT_ID`, `AZURE_CLIENT_SECRET`) or the Azure CLI.
+/// The model name only selects the tokenizer and limits, the deployment decides
+/// which model answers.
+#[derive(serde::Serialize)]
+pub struct AzureProvider {
+    #[serde(skip)]
+    client: Client,
+    endpoint: String,
+    deployment: String,
+    api_version: String,
+    auth: AzureAuth,
+    model: ModelConfig,
+    retry: RetryConfig,
+    #[serde(skip)]
+    token: Mutex<Option<AccessToken>>,
+}
+
+impl Default for AzureProvider {
+    fn default() -> Self {
+        let model = ModelConfig::new(AzureProvider::metadata().default_model);
+        AzureProvider::from_env(model).expect("Failed to initialize Azure OpenAI provider")
+    }
+}
+
+impl AzureProvider {
+    pub fn from_env(model: ModelConfig) -> Result<Self> {
+        let config = crate::config::Config::global();
+        let endpoint: String = config.get("AZURE_OPENAI_ENDPOINT")?;
+        let deployment: String = config.get("AZURE_OPENAI_DEPLOYMENT_NAME")?;
+        let api_version: String = config
+            .get("AZURE_OPENAI_API_VERSION")
+            .unwrap_or_else(|_| AZURE_DEFAULT_API_VERSION.to_string());
+        let auth = match config.get_secret_handle("AZURE_OPENAI_API_KEY") {
+            Ok(api_key) => AzureAuth::ApiKey(api_key),
+            Err(_) => match (
+                config.get::<String>("AZURE_TENANT_ID"),
+                config.get::<String>("AZURE_CLIENT_ID"),
+                config.get_secret_handle("AZURE_CLIENT_SECRET"),
+            ) {
+                (Ok(tenant_id), Ok(client_id), Ok(client_secret)) => AzureAuth::ClientSecret {
+                    tenant_id,
+                    client_id,
+                    client_secret,
+                },
+                _ => AzureAuth::AzureCli,
+            },
+        };
+
+        let client = Client::builder()
+            .timeout(Duration::from_secs(600))
+            .build()?;
+
+        Ok(Self {
+            client,
+            endpoint,
+            deployment,
+            api_version,
+            auth,
+            model,
+            retry: RetryConfig::default(),
+            token: Mutex::new(None),
+        })
+    }
+
+    fn url(&self, path: &str) -> String {
+        format!(
+            "{}/openai/deployments/{}/{}?api-version={}",
+            self.endpoint.trim_end_matches('/'),
+            self.deployment,
+            path,
+            self.api_version
+        )
+    }
+
+    /// The cached Entra ID token, requested again when missing or about to expire
+    async fn access_token(&self) -> Result<String, ProviderError> {
+        let mut cached = self.token.lock().await;
+        if let Some(token) = cached.as_ref() {
+            if token.expires_at - TOKEN_EXPIRY_MARGIN > Utc::now() {
+                return Ok(token.token.clone());
+            }
+        }
+        let token = match &self.auth {
+            AzureAuth::ClientSecret {
+                tenant_id,
+                client_id,
+                client_secret,
+            } => {
+                self.client_secret_token(tenant_id, client_id, &client_secret.expose())
+                    .await
+            }
+            _ => azure_cli_token().await,
+        }
+        .map_err(|e| ProviderError::authentication(e.to_string()))?;
+        *cached = Some(token.clone());
+        Ok(token.token)
+    }
+
+    async fn client_secret_token(
+        &self,
+        tenant_id: &str,
+        client_id: &str,
+        client_secret: &str,
+    ) -> Result<AccessToken> {
+        let url = format!(
+            "https://login.microsoftonline.com/{}/oauth2/v2.0/token",
+            tenant_id
+        );
+        let response = self
+            .client
+            .post(url)
+            .form(&[
+                ("grant_type", "client_credentials"),
+                ("client_id", client_id),
+                ("client_secret", client_secret),
+                ("scope", COGNITIVE_SERVICES_SCOPE),
+            ])
+            .send()
+            .await?;
+        if !response.status().is_success() {
+            return Err(anyhow!(
+                "Entra ID refused the client credentials: {}",
+                response.text().await?
+            ));
+        }
+        let token: TokenResponse = response.json().await?;
+        Ok(AccessToken {
+            token: token.access_token,
+            expires_at: Utc::now() + ChronoDuration::seconds(token.expires_in),
+        })
+    }
+
+    async fn authorize(&self, request: RequestBuilder) -> Result<RequestBuilder, ProviderError> {
+        Ok(match &self.auth {
+            AzureAuth::ApiKey(api_key) => request.header("api-key", api_key.expose()),
+            _ => request.bearer_auth(self.access_token().await?),
+        })
+    }
+
+    async fn send(&self, payload: &Value) -> Result<reqwest::Response, ProviderError> {
+        let url = self.url("chat/completions");
+        send_with_retry(&self.retry, || async {
+            let request = self.authorize(self.client.post(&url)).await?;
+            Ok(request.json(payload).send().await?)
+        })
+        .await
+    }
+
+    fn build_request(
+        &self,
+        system: &str,
+        messages: &[Message],
+        tools: &[Tool],
+    ) -> Result<Value, ProviderError> {
+        validate_params(&self.model)?;
+        validate_tool_pairing(messages)?;
+        Ok(create_request(
+            &self.model,
+            system,
+            messages,
+            tools,
+            &ImageFormat::OpenAi,
+        )?)
+    }
+}
+
+/// Ask the Azure CLI for a token of the signed in user
+async fn azure_cli_token() -> Result<AccessToken> {
+    let output = tokio::process::Command::new(if cfg!(windows) { "az.cmd" } else { "az" })
+        .args([
+            "account",
+            "get-access-token",
+            "--scope",
+            COGNITIVE_SERVICES_SCOPE,
+            "--output",
+            "json",
+        ])
+        .output()
+        .await
+        .context(
+            "No Azure OpenAI API key is set and the Azure CLI could not be run, \
+             set AZURE_OPENAI_API_KEY or sign in with `az login`",
+        )?;
+    if !output.status.success() {
+        return Err(anyhow!(
+            "The Azure CLI could not get a token, sign in with `az login`: {}",
+            String::from_utf8_lossy(&output.stderr)
+        ));
+    }
+    let token: CliToken = serde_json::from_slice(&output.stdout)?;
+    let expires_at = token
+        .expires_on
+        .and_then(|seconds| DateTime::from_timestamp(seconds, 0))
+        // Older versions only print a local time, their tokens live at least this long
+        .unwrap_or_else(|| Utc::now() + ChronoDuration::minutes(30));
+    Ok(AccessToken {
+        token: token.access_token,
+        expires_at,
+    })
+}
+
+#[async_trait]
+impl Provider for AzureProvider {
+    fn metadata() -> ProviderMetadata {
+        ProviderMetadata::new(
+            "azure_openai",
+            "Azure OpenAI",
+            "OpenAI models deployed on the Azure OpenAI Service",
+            AZURE_DEFAULT_MODEL,
+            AZURE_KNOWN_MODELS.iter().map(|&s| s.to_string()).collect(),
+            AZURE_DOC_URL,
+            vec![
+                ConfigKey::new("AZURE_OPENAI_ENDPOINT", true, false, None),
+                ConfigKey::new("AZURE_OPENAI_DEPLOYMENT_NAME", true, false, None),
+                ConfigKey::new(
+                    "AZURE_OPENAI_API_VERSION",
+                    false,
+                    false,
+                    Some(AZURE_DEFAULT_API_VERSION),
+                ),
+                ConfigKey::new("AZURE_OPENAI_API_KEY", false, true, None),
+            ],
+        )
+    }
+
+    fn instance_metadata(&self) -> ProviderMetadata {
+        Self::metadata().with_image_input(self.model.capabilities().image_input)
+    }
+
+    fn get_model_config(&self) -> ModelConfig {
+        self.model.clone()
+    }
+
+    fn request_body(
+        &self,
+        system: &str,
+        messages: &[Message],
+        tools: &[Tool],
+    ) -> Result<Value, ProviderError> {
+        self.build_request(system, messages, tools)
+    }
+
+    #[tracing::instrument(
+        skip(self, system, messages, tools),
+        fields(model_config, input, output, input_tokens, output_tokens, total_tokens)
+    )]
+    async fn complete(
+        &self,
+        system: &str,
+        messages: &[Message],
+        tools: &[Tool],
+    ) -> Result<(Message, ProviderUsage), ProviderError> {
+        let payload = self.build_request(system, messages, tools)?;
+
+        // Make request
+        let response = handle_response_openai_compat(self.send(&payload).await?)
+            .await
+            .map_err(|e| e.with_key_of(&Self::metadata()))?;
+
+        // Parse response
+        let message = response_to_message(response.clone())?;
+        let usage = match get_usage(&response) {
+            Ok(usage) => usage,
+            Err(ProviderError::UsageError(e)) => {
+                tracing::warn!("Failed to get usage data: {}", e);
+                Usage::default()
+            }
+            Err(e) => return Err(e),
+        };
+        let model = get_model(&response);
+        emit_debug_trace(self, messages, &payload, &response, &usage);
+        Ok((
+            message,
+            ProviderUsage::new(model, usage)
+                .with_system_fingerprint(get_system_fingerprint(&response)),
+        ))
+    }
+
+    async fn stream(
+        &self,
+        system: &str,
+        messages: &[Message],
+        tools: &[Tool],
+    ) -> Result<MessageStream, ProviderError> {
+        let mut payload = self.build_request(system, messages, tools)?;
+        payload["stream"] = json!(true);
+        payload["stream_options"] = json!({"include_usage": true});
+
+        let response = self.send(&payload).await?;
+        if response.status() != StatusCode::OK {
+            handle_response_openai_compat(response)
+                .await
+                .map_err(|e| e.with_key_of(&Self::metadata()))?;
+            return Err(ProviderError::request_failed(
+                "Unexpected response to stream request",
+            ));
+        }
+        Ok(openai_message_stream(sse_events(response.bytes_stream())))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use wiremock::matchers::{header, method, path, query_param};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    #[tokio::test]
+    async fn test_complete_uses_deployment_url() -> Result<()> {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/openai/deployments/prod-gpt4o/chat/completions"))
+            .and(query_param("api-version", AZURE_DEFAULT_API_VERSION))
+            .and(header("api-key", "secret"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+                "model": "gpt-4o-2024-08-06",
+                "choices": [{"message": {"role": "assistant", "content": "Hello!"}}],
+                "usage": {"prompt_tokens": 9, "completion_tokens": 2, "total_tokens": 11}
+            })))
+            .mount(&server)
+            .await;
+
+        let provider = AzureProvider {
+            client: Client::new(),
+            endpoint: format!("{}/", server.uri()),
+            deployment: "prod-gpt4o".to_string(),
+            api_version: AZURE_DEFAULT_API_VERSION.to_string(),
+            auth: AzureAuth::ApiKey(Secret::fixed("secret")),
+            model: ModelConfig::new(AZURE_DEFAULT_MODEL.to_string()),
+            retry: RetryConfig::default(),
+            token: Mutex::new(None),
+        };
+        let (message, usage) = provider
+            .complete("", &[Message::user().with_text("Hi")], &[])
+            .await?;
+        assert_eq!(message.as_concat_text(), "Hello!");
+        assert_eq!(usage.model, "gpt-4o-2024-08-06");
+        assert_eq!(usage.usage.total_tokens, Some(11));
+        Ok(())
+    }
+}
@@ -1,11 +1,26 @@
 use anyhow::Result;
+use futures::stream::BoxStream;
 use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use tokio::sync::{mpsc, watch};
 
 use super::errors::ProviderError;
-use crate::message::Message;
+use super::health::{HealthCheck, HEALTH_CHECK_TIMEOUT};
+use super::streaming::{
+    send_stream_events, send_stream_events_until, skip_repeated_prefix, StreamCheckpoint,
+};
+use crate::message::{Citation, Message, MessageContent};
 use crate::model::ModelConfig;
+use crate::token_counter::TokenCounter;
 use mcp_core::tool::Tool;
 
+/// The provider name for a type name, e.g. "openai" for `goose::providers::openai::OpenAiProvider`
+fn provider_name_of(type_name: &str) -> String {
+    let path = type_name.split('<').next().unwrap_or(type_name);
+    let name = path.rsplit("::").next().unwrap_or(path);
+    name.strip_suffix("Provider").unwrap_or(name).to_lowercase()
+}
+
 /// Metadata about a provider's configuration requirements and capabilities
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ProviderMetadata {
@@ -17,13 +32,19 @@ pub struct ProviderMetadata {
     pub description: String,
     /// The default/recommended model for this provider
     pub default_model: String,
-    /// A list of currently known models
-    /// TODO: eventually query the apis directly
+    /// A list of currently known models, `Provider::fetch_supported_models` asks the API
     pub known_models: Vec<String>,
     /// Link to the docs where models can be found
     pub model_doc_link: String,
     /// Required configuration keys
     pub config_keys: Vec<ConfigKey>,
+    /// Whether images reach the model, `false` when the selected model is text only
+    #[serde(default = "accepts_images")]
+    pub image_input: bool,
+}
+
+fn accepts_images() -> bool {
+    true
 }
 
 impl ProviderMetadata {
@@ -44,6 +65,7 @@ impl ProviderMetadata {
             known_models,
             model_doc_link: model_doc_link.to_string(),
             config_keys,
+            image_input: true,
         }
     }
 
@@ -56,11 +78,75 @@ impl ProviderMetadata {
             known_models: vec![],
             model_doc_link: "".to_string(),
             config_keys: vec![],
+            image_input: true,
+        }
+    }
+
+    /// Set whether images reach the model, e.g. from the capabilities of the configured model
+    pub fn with_image_input(mut self, image_input: bool) -> Self {
+        self.image_input = image_input;
+        self
+    }
+
+    /// What changed from this metadata to `other`, e.g. between two releases of a provider
+    pub fn diff(&self, other: &ProviderMetadata) -> MetadataDiff {
+        let find =
+            |keys: &[ConfigKey], name: &str| keys.iter().find(|key| key.name == name).cloned();
+        let mut diff = MetadataDiff::default();
+        for key in &other.config_keys {
+            match find(&self.config_keys, &key.name) {
+                None => diff.added_keys.push(key.clone()),
+                Some(old) if old != *key => diff.changed_keys.push((old, key.clone())),
+                Some(_) => {}
+            }
         }
+        diff.removed_keys = self
+            .config_keys
+            .iter()
+            .filter(|key| find(&other.config_keys, &key.name).is_none())
+            .cloned()
+            .collect();
+        if self.default_model != other.default_model {
+            diff.default_model = Some((self.default_model.clone(), other.default_model.clone()));
+        }
+        diff
     }
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+/// The differences between two `ProviderMetadata`, as returned by `ProviderMetadata::diff`
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct MetadataDiff {
+    /// Keys that are new, a required one has to be set before upgrading
+    pub added_keys: Vec<ConfigKey>,
+    /// Keys that are no longer read
+    pub removed_keys: Vec<ConfigKey>,
+    /// Keys whose requirement, secrecy or default changed, as `(old, new)`
+    pub changed_keys: Vec<(ConfigKey, ConfigKey)>,
+    /// The default model as `(old, new)`, when it changed
+    pub default_model: Option<(String, String)>,
+}
+
+impl MetadataDiff {
+    pub fn is_empty(&self) -> bool {
+        *self == MetadataDiff::default()
+    }
+
+    /// Keys that must now be set, newly added ones and ones that became required
+    pub fn newly_required(&self) -> Vec<&ConfigKey> {
+        self.added_keys
+            .iter()
+            .filter(|key| key.required)
+            .chain(
+                self.changed_keys
+                    .iter()
+                    .filter(|(old, new)| new.required && !old.required)
+                    .map(|(_, new)| new),
+            )
+            .collect()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct ConfigKey {
     pub name: String,
     pub required: bool,
@@ -83,11 +169,36 @@ impl ConfigKey {
 pub struct ProviderUsage {
     pub model: String,
     pub usage: Usage,
+    /// Identifies the backend configuration that served the request, a change
+    /// means deterministic outputs may no longer reproduce
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub system_fingerprint: Option<String>,
+    /// The output was cut short on the client because it exceeded the output token cap
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub truncated: bool,
 }
 
 impl ProviderUsage {
     pub fn new(model: String, usage: Usage) -> Self {
-        Self { model, usage }
+        Self {
+            model,
+            usage,
+            system_fingerprint: None,
+            truncated: false,
+        }
+    }
+
+    /// Set the backend fingerprint reported with the response
+    pub fn with_system_fingerprint(mut self, system_fingerprint: Option<String>) -> Self {
+        self.system_fingerprint = system_fingerprint;
+        self
+    }
+
+    /// Whether part of the prompt was served from the provider's prompt cache
+    pub fn cache_hit(&self) -> bool {
+        self.usage
+            .cached_input_tokens
+            .is_some_and(|cached| cached > 0)
     }
 }
 
@@ -96,6 +207,18 @@ pub struct Usage {
     pub input_tokens: Option<i32>,
     pub output_tokens: Option<i32>,
     pub total_tokens: Option<i32>,
+    /// Output tokens that matched the supplied prediction
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub accepted_prediction_tokens: Option<i32>,
+    /// Predicted tokens that were not used, these are still billed as output
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rejected_prediction_tokens: Option<i32>,
+    /// Input tokens read from the prompt cache, these are included in `input_tokens`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cached_input_tokens: Option<i32>,
+    /// Output tokens the model spent reasoning before its answer, these are included in `output_tokens`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reasoning_tokens: Option<i32>,
 }
 
 impl Usage {
@@ -108,12 +231,219 @@ impl Usage {
             input_tokens,
             output_tokens,
             total_tokens,
+            accepted_prediction_tokens: None,
+            rejected_prediction_tokens: None,
+            cached_input_tokens: None,
+            reasoning_tokens: None,
+        }
+    }
+
+    /// Set the prediction token counts reported for a request with a predicted output
+    pub fn with_prediction_tokens(mut self, accepted: Option<i32>, rejected: Option<i32>) -> Self {
+        self.accepted_prediction_tokens = accepted;
+        self.rejected_prediction_tokens = rejected;
+        self
+    }
+
+    /// Set the input tokens the provider read from its prompt cache
+    pub fn with_cached_input_tokens(mut self, cached: Option<i32>) -> Self {
+        self.cached_input_tokens = cached;
+        self
+    }
+
+    /// Set the output tokens the model spent on reasoning
+    pub fn with_reasoning_tokens(mut self, reasoning: Option<i32>) -> Self {
+        self.reasoning_tokens = reasoning;
+        self
+    }
+}
+
+/// A breakpoint up to which a provider may cache the prompt, see `Message::with_cache_control`
+///
+/// Serializes to the Anthropic `cache_control` object, which OpenAI-compatible
+/// gateways in front of Claude models accept as well.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum CacheControl {
+    /// Kept for a few minutes, each read refreshes it
+    Ephemeral,
+}
+
+/// A conversation kept on the server by a stateful backend
+///
+/// Passing it to `Provider::complete_in_thread` lets the backend continue from
+/// its stored history, so only the new messages need to be sent.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct ThreadId(pub String);
+
+impl std::fmt::Display for ThreadId {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+/// The request and token allowance of the account, for configuring client side throttling
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RateLimits {
+    /// Requests allowed per minute
+    pub requests_per_minute: Option<u32>,
+    /// Tokens allowed per minute, input and output combined
+    pub tokens_per_minute: Option<u32>,
+    /// Requests left in the current minute
+    pub remaining_requests: Option<u32>,
+    /// Tokens left in the current minute
+    pub remaining_tokens: Option<u32>,
+}
+
+/// An incremental update emitted while streaming a completion
+#[derive(Debug, Clone)]
+pub enum MessageDelta {
+    /// Newly generated content. Text arrives in fragments, tool requests arrive fully assembled
+    Content(MessageContent),
+    /// Usage for the completion, typically reported once at the end of the stream
+    Usage(ProviderUsage),
+    /// Sources the message is grounded on, reported once all of them are known
+    Citations(Vec<Citation>),
+    /// A piece of the `index`th tool call as the backend streamed it, `name` is set when the call starts
+    ///
+    /// Only for showing progress, the assembled call still follows as `Content`.
+    ToolCallFragment {
+        index: usize,
+        name: Option<String>,
+        arguments: String,
+    },
+    /// Why the model stopped, as the backend put it, e.g. "stop" or "end_turn"
+    Finish(String),
+}
+
+/// A stream of deltas making up a single assistant message
+pub type MessageStream = BoxStream<'static, Result<MessageDelta, ProviderError>>;
+
+/// A stream that emits a complete `message` at once, followed by its usage
+pub(crate) fn message_stream(message: Message, usage: ProviderUsage) -> MessageStream {
+    let citations = Some(message.citations).filter(|c| !c.is_empty());
+    let deltas: Vec<_> = message
+        .content
+        .into_iter()
+        .map(MessageDelta::Content)
+        .chain(citations.map(MessageDelta::Citations))
+        .chain(std::iter::once(MessageDelta::Usage(usage)))
+        .map(Ok)
+        .collect();
+    Box::pin(futures::stream::iter(deltas))
+}
+
+/// A typed event sent by `Provider::stream_events`
+#[derive(Debug)]
+pub enum StreamEvent {
+    /// A fragment of the response text
+    TextDelta(String),
+    /// Part of the `index`th tool call; `name` is set when the call starts
+    ToolCallDelta {
+        index: usize,
+        name: Option<String>,
+        args: String,
+    },
+    /// Content other than text and tool calls, e.g. thinking or audio, as it arrives
+    Content(MessageContent),
+    /// Sources the response is grounded on
+    Citations(Vec<Citation>),
+    /// Usage for the completion
+    Usage(ProviderUsage),
+    /// The response is complete, with why the model stopped, e.g. "stop" or "tool_calls"
+    Done { finish_reason: Option<String> },
+    /// The request failed, no further events follow
+    Error(ProviderError),
+    /// The stream was cancelled, with the message received up to that point
+    ///
+    /// Follows a `Usage` event with the tokens estimated on the client side.
+    Cancelled { partial: Message },
+}
+
+/// A stable identity for a request, shared by everything that caches or replays requests
+///
+/// The hex encoded SHA-256 of a canonical serialization with sorted object keys,
+/// so it is the same across runs and regardless of map ordering. It covers
+/// everything that reaches the API, including the sampling parameters, but not
+/// the local tokenizer and context limit settings. Message timestamps only count
+/// when they are sent, see `ModelConfig::include_timestamps`.
+pub fn request_hash(
+    system: &str,
+    messages: &[Message],
+    tools: &[Tool],
+    model_config: &ModelConfig,
+) -> String {
+    let mut messages = serde_json::to_value(messages).unwrap_or_default();
+    if !model_config.include_timestamps {
+        for message in messages.as_array_mut().into_iter().flatten() {
+            if let Some(message) = message.as_object_mut() {
+                message.remove("created");
+            }
+        }
+    }
+    let mut model = serde_json::to_value(model_config).unwrap_or_default();
+    if let Some(model) = model.as_object_mut() {
+        model.remove("tokenizer_name");
+        model.remove("context_limit");
+    }
+    let request = serde_json::json!({
+        "system": system,
+        "messages": messages,
+        "tools": tools,
+        "model": model,
+    });
+
+    let mut canonical = String::new();
+    write_canonical(&request, &mut canonical);
+    let digest = Sha256::digest(canonical.as_bytes());
+    digest.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+/// Serialize `value` as JSON with the keys of every object in sorted order
+fn write_canonical(value: &serde_json::Value, out: &mut String) {
+    match value {
+        serde_json::Value::Object(map) => {
+            let mut entries: Vec<_> = map.iter().collect();
+            entries.sort_by_key(|(key, _)| *key);
+            out.push('{');
+            for (i, (key, value)) in entries.into_iter().enumerate() {
+                if i > 0 {
+                    out.push(',');
+                }
+                out.push_str(&serde_json::Value::String(key.clone()).to_string());
+                out.push(':');
+                write_canonical(value, out);
+            }
+            out.push('}');
         }
+        serde_json::Value::Array(items) => {
+            out.push('[');
+            for (i, item) in items.iter().enumerate() {
+                if i > 0 {
+                    out.push(',');
+                }
+                write_canonical(item, out);
+            }
+            out.push(']');
+        }
+        other => out.push_str(&other.to_string()),
     }
 }
 
 use async_trait::async_trait;
 
+/// Inspects or rewrites the final request body just before it is sent
+///
+/// Useful for stripping parameters a gateway rejects, injecting defaults or
+/// enforcing policy without widening the `complete` signature.
+pub trait RequestInterceptor: Send + Sync + std::fmt::Debug {
+    fn intercept(&self, body: &mut serde_json::Value);
+}
+
+/// Sent after a truncated assistant turn to have the model pick up where it stopped
+const CONTINUE_PROMPT: &str = "Your previous response was cut off. Continue exactly where it \
+stopped, without repeating anything or adding an introduction.";
+
 /// Base trait for AI providers (OpenAI, Anthropic, etc)
 #[async_trait]
 pub trait Provider: Send + Sync {
@@ -122,6 +452,60 @@ pub trait Provider: Send + Sync {
     where
         Self: Sized;
 
+    /// Get the metadata for this provider instance
+    ///
+    /// Unlike `metadata` this is callable on a `dyn Provider`. The default can not
+    /// reach the associated function for unsized types, so providers override it
+    /// with `Self::metadata()` and decorators delegate to the provider they wrap.
+    /// Without an override only the name is set, from the type, and a warning
+    /// is logged once.
+    fn instance_metadata(&self) -> ProviderMetadata {
+        let type_name = std::any::type_name::<Self>();
+        static WARNED: std::sync::Once = std::sync::Once::new();
+        WARNED.call_once(|| {
+            tracing::warn!(
+                "{} does not override instance_metadata, only its name is known",
+                type_name
+            )
+        });
+        ProviderMetadata {
+            name: provider_name_of(type_name),
+            ..ProviderMetadata::empty()
+        }
+    }
+
+    /// Erase the concrete provider type so heterogeneous providers can be stored together
+    fn into_boxed(self) -> Box<dyn Provider>
+    where
+        Self: Sized + 'static,
+    {
+        Box::new(self)
+    }
+
+    /// Adapt the system prompt to the conventions of the model family
+    ///
+    /// This is a pure string transform applied before the request is built, the
+    /// default leaves the prompt unchanged.
+    fn format_system_prompt(&self, raw: &str) -> String {
+        raw.to_string()
+    }
+
+    /// The HTTP body `complete` sends for this conversation, built without sending it
+    ///
+    /// Providers that do not expose their requests answer with `NotSupported`.
+    /// Decorators pass the conversation on the way they would for `complete`.
+    fn request_body(
+        &self,
+        _system: &str,
+        _messages: &[Message],
+        _tools: &[Tool],
+    ) -> Result<serde_json::Value, ProviderError> {
+        Err(ProviderError::NotSupported(format!(
+            "{} does not expose its request body",
+            self.instance_metadata().name
+        )))
+    }
+
     /// Generate the next message using the configured model and other parameters
     ///
     /// # Arguments
@@ -142,15 +526,232 @@ pub trait Provider: Send + Sync {
         tools: &[Tool],
     ) -> Result<(Message, ProviderUsage), ProviderError>;
 
+    /// Generate the next message as a stream of incremental deltas
+    ///
+    /// Takes the same arguments as `complete`. The default implementation waits for
+    /// `complete` and emits the whole response at once, so every provider can be
+    /// streamed even without native support.
+    async fn stream(
+        &self,
+        system: &str,
+        messages: &[Message],
+        tools: &[Tool],
+    ) -> Result<MessageStream, ProviderError> {
+        let (message, usage) = self.complete(system, messages, tools).await?;
+        Ok(message_stream(message, usage))
+    }
+
+    /// Generate the next message, sending typed events over `events` as they arrive
+    ///
+    /// Built on `stream`, so it works for every provider. The last event is
+    /// always `Done` or `Error`. Stops early once the receiver is dropped.
+    async fn stream_events(
+        &self,
+        system: &str,
+        messages: &[Message],
+        tools: &[Tool],
+        events: mpsc::Sender<StreamEvent>,
+    ) {
+        match self.stream(system, messages, tools).await {
+            Ok(stream) => send_stream_events(stream, events).await,
+            Err(e) => {
+                let _ = events.send(StreamEvent::Error(e)).await;
+            }
+        }
+    }
+
+    /// Like `stream_events`, but stops once `cancel` is set to true
+    ///
+    /// A cancelled stream ends with a `Usage` event estimated from the prompt
+    /// and the output received so far, followed by `StreamEvent::Cancelled`
+    /// with the partial message, so the tokens spent are still accounted for.
+    async fn stream_events_until(
+        &self,
+        system: &str,
+        messages: &[Message],
+        tools: &[Tool],
+        events: mpsc::Sender<StreamEvent>,
+        cancel: watch::Receiver<bool>,
+    ) {
+        let input_tokens = self.count_request_tokens(system, messages, tools);
+        match self.stream(system, messages, tools).await {
+            Ok(stream) => {
+                let model = self.get_model_config();
+                send_stream_events_until(stream, events, cancel, &model, input_tokens).await
+            }
+            Err(e) => {
+                let _ = events.send(StreamEvent::Error(e)).await;
+            }
+        }
+    }
+
+    /// Continue an assistant message that was cut off, e.g. because it reached `max_tokens`
+    ///
+    /// Sends the conversation followed by the partial assistant turn and a request
+    /// to carry on, and returns only the newly generated text for the caller to
+    /// append to `partial`. A reply that starts over is trimmed to the new part.
+    async fn continue_completion(
+        &self,
+        system: &str,
+        messages: &[Message],
+        partial: &Message,
+    ) -> Result<(Message, ProviderUsage), ProviderError> {
+        let mut conversation = messages.to_vec();
+        conversation.push(partial.clone());
+        conversation.push(Message::user().with_text(CONTINUE_PROMPT));
+
+        let (mut message, usage) = self.complete(system, &conversation, &[]).await?;
+        let previous = partial.as_concat_text();
+        if let Some(MessageContent::Text(text)) = message.content.first_mut() {
+            if let Some(new) = text.text.strip_prefix(previous.as_str()) {
+                text.text = new.to_string();
+            }
+        }
+        Ok((message, usage))
+    }
+
+    /// Resume a stream that failed part way, from a checkpoint of what was received
+    ///
+    /// Few backends can pick up a stream where it stopped, so this sends the
+    /// conversation followed by the partial text and a request to carry on, like
+    /// `continue_completion`, and streams only the newly generated part. Tool
+    /// requests already received stay in the checkpoint and are not sent again.
+    async fn resume_from(
+        &self,
+        system: &str,
+        messages: &[Message],
+        tools: &[Tool],
+        checkpoint: &StreamCheckpoint,
+    ) -> Result<MessageStream, ProviderError> {
+        if checkpoint.text.is_empty() {
+            return self.stream(system, messages, tools).await;
+        }
+        let mut conversation = messages.to_vec();
+        conversation.push(Message::assistant().with_text(&checkpoint.text));
+        conversation.push(Message::user().with_text(CONTINUE_PROMPT));
+
+        let stream = self.stream(system, &conversation, tools).await?;
+        Ok(skip_repeated_prefix(stream, checkpoint.text.clone()))
+    }
+
+    /// Estimate the prompt tokens the API will bill for this request
+    ///
+    /// Unlike counting the messages alone, this includes the tool definitions,
+    /// the per-message role formatting and the system prompt as the provider
+    /// will actually send it. Providers that otherwise change the request
+    /// before sending it override this to count what they send.
+    fn count_request_tokens(&self, system: &str, messages: &[Message], tools: &[Tool]) -> usize {
+        let counter = TokenCounter::new(self.get_model_config().tokenizer_name());
+        counter.count_chat_tokens(&self.format_system_prompt(system), messages, tools)
+    }
+
     /// Get the model config from the provider
     fn get_model_config(&self) -> ModelConfig;
+
+    /// Open and pool a connection ahead of the first request
+    ///
+    /// Saves the first completion the TCP and TLS handshakes, e.g. when called
+    /// at server startup. Providers without a connection to prepare do nothing.
+    async fn warmup(&self) -> Result<(), ProviderError> {
+        Ok(())
+    }
+
+    /// Check that the provider answers with the configured credentials and model
+    ///
+    /// Sends the smallest possible completion, so the check costs a few tokens
+    /// unless max tokens is set to 1. Never fails, the problem is reported in
+    /// the returned `HealthCheck` instead.
+    async fn health_check(&self) -> HealthCheck {
+        let model = self.get_model_config().model_name;
+        let messages = [Message::user().with_text("ping")];
+        let started = std::time::Instant::now();
+        match tokio::time::timeout(HEALTH_CHECK_TIMEOUT, self.complete("", &messages, &[])).await {
+            Ok(result) => HealthCheck::from_result(model, started.elapsed(), result.map(|_| ())),
+            Err(_) => HealthCheck::timed_out(model),
+        }
+    }
+
+    /// Write out anything buffered, such as batched log lines, e.g. before the process exits
+    ///
+    /// Providers that write everything as it happens do nothing.
+    async fn flush(&self) -> Result<(), ProviderError> {
+        Ok(())
+    }
+
+    /// Generate the next message within a conversation kept on the server
+    ///
+    /// `messages` holds only what the thread has not seen yet when `thread` is
+    /// set, and everything otherwise. The returned thread, possibly a new one,
+    /// is passed with the next turn. Stateless backends send the messages with
+    /// `complete` and return no thread, so callers have to keep sending the
+    /// full history.
+    async fn complete_in_thread(
+        &self,
+        system: &str,
+        messages: &[Message],
+        tools: &[Tool],
+        thread: Option<ThreadId>,
+    ) -> Result<(Message, ProviderUsage, Option<ThreadId>), ProviderError> {
+        let _ = thread;
+        let (message, usage) = self.complete(system, messages, tools).await?;
+        Ok((message, usage, None))
+    }
+
+    /// The rate limits of the account, e.g. to size a token bucket
+    ///
+    /// Providers that can not find them out answer with `NotSupported`.
+    async fn rate_limits(&self) -> Result<RateLimits, ProviderError> {
+        Err(ProviderError::NotSupported(
+            "this provider does not report rate limits".to_string(),
+        ))
+    }
+
+    /// The models the provider's API currently offers, for model selection
+    ///
+    /// Providers without a models endpoint answer with `NotSupported`, callers
+    /// then fall back to `known_models` in the metadata.
+    async fn fetch_supported_models(&self) -> Result<Vec<String>, ProviderError> {
+        Err(ProviderError::NotSupported(
+            "this provider does not list its models".to_string(),
+        ))
+    }
+
+    /// Embed each input as a vector, in the order the inputs were given
+    ///
+    /// Providers without an embeddings endpoint answer with `NotSupported`.
+    async fn embed(&self, _inputs: &[String]) -> Result<Vec<Vec<f32>>, ProviderError> {
+        Err(ProviderError::NotSupported(format!(
+            "{} does not support embeddings",
+            self.instance_metadata().name
+        )))
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    use crate::providers::streaming::{checkpointed_stream, collect_message};
     use serde_json::json;
+    use std::collections::HashMap;
+    use std::slice;
+    use std::sync::{Arc, Mutex};
+
+    #[test]
+    fn test_instance_metadata_defaults_to_the_type_name() {
+        assert_eq!(
+            provider_name_of("goose::providers::openai::OpenAiProvider"),
+            "openai"
+        );
+        assert_eq!(
+            provider_name_of(
+                "goose::providers::otel::Traced<goose::providers::groq::GroqProvider>"
+            ),
+            "traced"
+        );
+        let provider = TruncatingProvider { max_chars: 10 };
+        assert_eq!(provider.instance_metadata().name, "truncating");
+    }
 
     #[test]
     fn test_usage_creation() {
@@ -178,4 +779,411 @@ mod tests {
 
         Ok(())
     }
+
+    fn weather_tool(schema: serde_json::Value) -> Tool {
+        Tool::new("get_weather", "Get the weather for a city", schema)
+    }
+
+    #[test]
+    fn test_metadata_diff() {
+        let metadata = |default_model: &str, config_keys| {
+            ProviderMetadata::new("omg", "OhMyGPT", "", default_model, vec![], "", config_keys)
+        };
+        let old = metadata(
+            "gpt-4o",
+            vec![
+                ConfigKey::new("OMG_API_KEY", true, true, None),
+                ConfigKey::new("OMG_HOST", false, false, Some("https://api.ohmygpt.com")),
+                ConfigKey::new("OMG_TIMEOUT", false, false, Some("600")),
+            ],
+        );
+        let new = metadata(
+            "gpt-4o-mini",
+            vec![
+                ConfigKey::new("OMG_API_KEY", true, true, None),
+                ConfigKey::new("OMG_HOST", true, false, None),
+                ConfigKey::new("OMG_BASE_URL", true, false, None),
+                ConfigKey::new("OMG_ORGANIZATION", false, false, None),
+            ],
+        );
+
+        let diff = old.diff(&new);
+        let names = |keys: &[ConfigKey]| keys.iter().map(|k| k.name.clone()).collect::<Vec<_>>();
+        assert_eq!(
+            names(&diff.added_keys),
+            ["OMG_BASE_URL", "OMG_ORGANIZATION"]
+        );
+        assert_eq!(names(&diff.removed_keys), ["OMG_TIMEOUT"]);
+        assert_eq!(diff.changed_keys.len(), 1);
+        assert_eq!(diff.changed_keys[0].1.name, "OMG_HOST");
+        assert_eq!(
+            diff.default_model,
+            Some(("gpt-4o".to_string(), "gpt-4o-mini".to_string()))
+        );
+        let required: Vec<&str> = diff
+            .newly_required()
+            .iter()
+            .map(|k| k.name.as_str())
+            .collect();
+        assert_eq!(required, ["OMG_BASE_URL", "OMG_HOST"]);
+
+        assert!(old.diff(&old).is_empty());
+    }
+
+    #[test]
+    fn test_request_hash_is_stable() {
+        let config = ModelConfig::new("gpt-4o".to_string()).with_temperature(Some(0.2));
+        let mut first = Message::user().with_text("Weather in Paris?");
+        first.created = 1;
+        let mut second = first.clone();
+        second.created = 2;
+        let tools = [weather_tool(
+            json!({"type": "object", "properties": {"city": {"type": "string"}}}),
+        )];
+        let reordered = [weather_tool(
+            json!({"properties": {"city": {"type": "string"}}, "type": "object"}),
+        )];
+
+        let hash = request_hash("system", slice::from_ref(&first), &tools, &config);
+        assert_eq!(hash.len(), 64);
+        assert_eq!(
+            hash,
+            request_hash("system", slice::from_ref(&first), &tools, &config)
+        );
+        assert_eq!(
+            hash,
+            request_hash("system", slice::from_ref(&second), &reordered, &config)
+        );
+        // Local settings that never reach the API do not matter
+        assert_eq!(
+            hash,
+            request_hash(
+                "system",
+                slice::from_ref(&first),
+                &tools,
+                &config.clone().with_context_limit(Some(1000))
+            )
+        );
+    }
+
+    #[test]
+    fn test_request_hash_is_sensitive() {
+        let config = ModelConfig::new("gpt-4o".to_string()).with_temperature(Some(0.2));
+        let messages = vec![Message::user().with_text("Weather in Paris?")];
+        let tools = [weather_tool(json!({"type": "object"}))];
+        let hash = request_hash("system", &messages, &tools, &config);
+
+        let variants = [
+            request_hash("other system", &messages, &tools, &config),
+            request_hash(
+                "system",
+                &[Message::user().with_text("Weather in Rome?")],
+                &tools,
+                &config,
+            ),
+            request_hash("system", &messages, &[], &config),
+            request_hash(
+                "system",
+                &messages,
+                &tools,
+                &config.clone().with_temperature(Some(0.9)),
+            ),
+            request_hash(
+                "system",
+                &messages,
+                &tools,
+                &config.clone().with_top_p(Some(0.5)),
+            ),
+            request_hash(
+                "system",
+                &messages,
+                &tools,
+                &ModelConfig::new("gpt-4o-mini".to_string()).with_temperature(Some(0.2)),
+            ),
+        ];
+        for variant in variants {
+            assert_ne!(hash, variant);
+        }
+    }
+
+    /// Writes a fixed text in pieces of at most `max_chars`, like a low `max_tokens`
+    struct TruncatingProvider {
+        max_chars: usize,
+    }
+
+    const STORY: &str = "Once upon a time a goose flew south for the winter.";
+
+    #[async_trait]
+    impl Provider for TruncatingProvider {
+        fn metadata() -> ProviderMetadata {
+            ProviderMetadata::empty()
+        }
+
+        fn get_model_config(&self) -> ModelConfig {
+            ModelConfig::new("truncating".to_string())
+        }
+
+        async fn complete(
+            &self,
+            _system: &str,
+            messages: &[Message],
+            _tools: &[Tool],
+        ) -> Result<(Message, ProviderUsage), ProviderError> {
+            // A continuation request ends with the partial answer and the nudge
+            let written = match messages {
+                [.., partial, nudge] if nudge.as_concat_text() == CONTINUE_PROMPT => {
+                    partial.as_concat_text().len()
+                }
+                _ => 0,
+            };
+            let end = (written + self.max_chars).min(STORY.len());
+            Ok((
+                Message::assistant().with_text(&STORY[written..end]),
+                ProviderUsage::new("truncating".to_string(), Usage::default()),
+            ))
+        }
+    }
+
+    #[tokio::test]
+    async fn test_warmup_and_flush_are_no_ops_by_default() {
+        assert!(TruncatingProvider { max_chars: 10 }.warmup().await.is_ok());
+        assert!(TruncatingProvider { max_chars: 10 }.flush().await.is_ok());
+        assert!(matches!(
+            TruncatingProvider { max_chars: 10 }.rate_limits().await,
+            Err(ProviderError::NotSupported(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_thread_id_round_trip() {
+        /// Keeps each thread's history and answers with how many messages it has seen
+        #[derive(Default)]
+        struct ThreadedProvider {
+            threads: Mutex<HashMap<ThreadId, Vec<Message>>>,
+        }
+
+        #[async_trait]
+        impl Provider for ThreadedProvider {
+            fn metadata() -> ProviderMetadata {
+                ProviderMetadata::empty()
+            }
+
+            fn get_model_config(&self) -> ModelConfig {
+                ModelConfig::new("threaded".to_string())
+            }
+
+            async fn complete(
+                &self,
+                system: &str,
+                messages: &[Message],
+                tools: &[Tool],
+            ) -> Result<(Message, ProviderUsage), ProviderError> {
+                let (message, usage, _) = self
+                    .complete_in_thread(system, messages, tools, None)
+                    .await?;
+                Ok((message, usage))
+            }
+
+            async fn complete_in_thread(
+                &self,
+                _system: &str,
+                messages: &[Message],
+                _tools: &[Tool],
+                thread: Option<ThreadId>,
+            ) -> Result<(Message, ProviderUsage, Option<ThreadId>), ProviderError> {
+                let mut threads = self.threads.lock().unwrap();
+                let thread =
+                    thread.unwrap_or_else(|| ThreadId(format!("thread_{}", threads.len() + 1)));
+                let history = threads.entry(thread.clone()).or_default();
+                history.extend_from_slice(messages);
+                let reply = Message::assistant()
+                    .with_text(format!("I have seen {} messages", history.len()));
+                history.push(reply.clone());
+                let usage = ProviderUsage::new("threaded".to_string(), Usage::default());
+                Ok((reply, usage, Some(thread)))
+            }
+        }
+
+        let provider = ThreadedProvider::default();
+        let (_, _, thread) = provider
+            .complete_in_thread("", &[Message::user().with_text("Hi")], &[], None)
+            .await
+            .unwrap();
+        assert_eq!(thread, Some(ThreadId("thread_1".to_string())));
+
+        // Only the new message is sent, the backend remembers the rest
+        let (reply, _, next) = provider
+            .complete_in_thread(
+                "",
+                &[Message::user().with_text("And now?")],
+                &[],
+                thread.clone(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(reply.as_concat_text(), "I have seen 3 messages");
+        assert_eq!(next, thread);
+
+        // Stateless providers never hand out a thread
+        let (_, _, thread) = TruncatingProvider { max_chars: 10 }
+            .complete_in_thread("", &[Message::user().with_text("Hi")], &[], thread)
+            .await
+            .unwrap();
+        assert_eq!(thread, None);
+    }
+
+    /// The prompt tokens the API reported for the weather example below
+    const WEATHER_PROMPT_TOKENS: usize = 124;
+
+    #[test]
+    fn test_count_request_tokens_matches_api_usage() {
+        struct WeatherProvider;
+
+        #[async_trait]
+        impl Provider for WeatherProvider {
+            fn metadata() -> ProviderMetadata {
+                ProviderMetadata::empty()
+            }
+
+            fn get_model_config(&self) -> ModelConfig {
+                ModelConfig::new("gpt-4o".to_string())
+            }
+
+            async fn complete(
+                &self,
+                _system: &str,
+                _messages: &[Message],
+                _tools: &[Tool],
+            ) -> Result<(Message, ProviderUsage), ProviderError> {
+                unimplemented!()
+            }
+        }
+
+        let system = "You are a helpful assistant that can answer questions about the weather.";
+        let messages = vec![
+            Message::user().with_text("What's the weather like in San Francisco?"),
+            Message::assistant()
+                .with_text("Looks like it's 60 degrees Fahrenheit in San Francisco."),
+            Message::user().with_text("How about New York?"),
+        ];
+        let tools = vec![Tool::new(
+            "get_current_weather",
+            "Get the current weather in a given location",
+            json!({
+                "properties": {
+                    "location": {
+                        "type": "string",
+                        "description": "The city and state, e.g. San Francisco, CA"
+                    },
+                    "unit": {
+                        "type": "string",
+                        "description": "The unit of temperature to return",
+                        "enum": ["celsius", "fahrenheit"]
+                    }
+                },
+                "required": ["location"]
+            }),
+        )];
+
+        let estimate = WeatherProvider.count_request_tokens(system, &messages, &tools);
+        let tolerance = WEATHER_PROMPT_TOKENS / 10;
+        assert!(
+            estimate.abs_diff(WEATHER_PROMPT_TOKENS) <= tolerance,
+            "estimated {} prompt tokens, the API reported {}",
+            estimate,
+            WEATHER_PROMPT_TOKENS
+        );
+        // Tool definitions make up a large share of the prompt
+        assert!(estimate > WeatherProvider.count_request_tokens(system, &messages, &[]) + 40);
+    }
+
+    #[tokio::test]
+    async fn test_continue_completion() -> Result<()> {
+        let provider = TruncatingProvider { max_chars: 30 };
+        let messages = vec![Message::user().with_text("Tell me a story")];
+
+        let (first, _) = provider.complete("", &messages, &[]).await?;
+        assert_eq!(first.as_concat_text().len(), 30);
+        let (rest, _) = provider.continue_completion("", &messages, &first).await?;
+        assert_eq!(
+            format!("{}{}", first.as_concat_text(), rest.as_concat_text()),
+            STORY
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_resume_from_checkpoint() -> Result<()> {
+        let provider = TruncatingProvider { max_chars: 100 };
+        let messages = vec![Message::user().with_text("Tell me a story")];
+
+        // The connection drops after the first two fragments
+        let deltas = vec![
+            Ok(MessageDelta::Content(MessageContent::text("Once upon "))),
+            Ok(MessageDelta::Content(MessageContent::text("a time"))),
+            Err(ProviderError::StreamDisconnected(
+                "connection reset".to_string(),
+            )),
+        ];
+        let stored = Arc::new(Mutex::new(String::new()));
+        let sink = stored.clone();
+        let stream = checkpointed_stream(
+            Box::pin(futures::stream::iter(deltas)),
+            StreamCheckpoint::default(),
+            provider.get_model_config().tokenizer_name(),
+            move |checkpoint| {
+                *sink.lock().unwrap() = serde_json::to_string(checkpoint).unwrap();
+            },
+        );
+        assert!(collect_message(stream).await.is_err());
+
+        let checkpoint: StreamCheckpoint = serde_json::from_str(&stored.lock().unwrap())?;
+        assert_eq!(checkpoint.text, "Once upon a time");
+        assert!(checkpoint.output_tokens > 0);
+
+        let resumed = provider
+            .resume_from("", &messages, &[], &checkpoint)
+            .await?;
+        let (rest, _) = collect_message(resumed).await?;
+        assert_eq!(
+            format!("{}{}", checkpoint.text, rest.as_concat_text()),
+            STORY
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_skip_repeated_prefix() {
+        let text = |fragments: &[&'static str]| -> MessageStream {
+            let deltas: Vec<_> = fragments
+                .iter()
+                .map(|f| Ok(MessageDelta::Content(MessageContent::text(*f))))
+                .collect();
+            Box::pin(futures::stream::iter(deltas))
+        };
+        let collect = |stream: MessageStream| async move {
+            collect_message(stream).await.unwrap().0.as_concat_text()
+        };
+
+        // A reply that starts over is trimmed to the new part
+        let restated = text(&["Once up", "on a time a goose", " flew"]);
+        assert_eq!(
+            collect(skip_repeated_prefix(
+                restated,
+                "Once upon a time".to_string()
+            ))
+            .await,
+            " a goose flew"
+        );
+        // A reply that carries on is passed through
+        let continued = text(&["Once", " more"]);
+        assert_eq!(
+            collect(skip_repeated_prefix(
+                continued,
+                "Once upon a time".to_string()
+            ))
+            .await,
+            "Once more"
+        );
+    }
 }
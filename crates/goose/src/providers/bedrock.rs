@@ -0,0 +1,331 @@
+use anyhow::Result;
+use async_trait::async_trait;
+use chrono::Utc;
+use reqwest::{Client, StatusCode};
+use serde_json::Value;
+use std::time::Duration;
+use tokio::sync::Mutex;
+use url::Url;
+
+use super::aws::{profile_region, uri_encode, AwsCredentials, CredentialChain, Signer};
+use super::base::{ConfigKey, Provider, ProviderMetadata, ProviderUsage};
+use super::errors::ProviderError;
+use super::formats::bedrock::{create_request, get_usage, response_to_message};
+use super::utils::{emit_debug_trace, validate_params, validate_tool_pairing};
+use crate::message::Message;
+use crate::model::ModelConfig;
+use mcp_core::tool::Tool;
+
+pub const BEDROCK_DEFAULT_REGION: &str = "us-east-1";
+pub const BEDROCK_DEFAULT_MODEL: &str = "anthropic.claude-3-5-sonnet-20241022-v2:0";
+pub const BEDROCK_KNOWN_MODELS: &[&str] = &[
+    "anthropic.claude-3-5-sonnet-20241022-v2:0",
+    "anthropic.claude-3-5-haiku-20241022-v1:0",
+    "us.anthropic.claude-3-5-sonnet-20241022-v2:0",
+    "amazon.nova-pro-v1:0",
+    "meta.llama3-1-70b-instruct-v1:0",
+    "mistral.mistral-large-2407-v1:0",
+];
+
+pub const BEDROCK_DOC_URL: &str =
+    "https://docs.aws.amazon.com/bedrock/latest/userguide/conversation-inference-supported-models-features.html";
+
+/// Models on Amazon Bedrock through the Converse API, signed with SigV4
+///
+/// Credentials come from the usual AWS sources, see `aws::CredentialChain`,
+/// and are refreshed shortly before temporary ones expire.
+#[derive(serde::Serialize)]
+pub struct BedrockProvider {
+    #[serde(skip)]
+    client: Client,
+    region: String,
+    profile: Option<String>,
+    host: String,
+    model: ModelConfig,
+    #[serde(skip)]
+    credentials: CredentialChain,
+    #[serde(skip)]
+    cached: Mutex<Option<AwsCredentials>>,
+}
+
+impl Default for BedrockProvider {
+    fn default() -> Self {
+        let model = ModelConfig::new(BedrockProvider::metadata().default_model);
+        BedrockProvider::from_env(model).expect("Failed to initialize Bedrock provider")
+    }
+}
+
+impl BedrockProvider {
+    pub fn from_env(model: ModelConfig) -> Result<Self> {
+        let config = crate::config::Config::global();
+        let profile: Option<String> = config.get("AWS_PROFILE").ok();
+        let region: String = config
+            .get("AWS_REGION")
+            .ok()
+            .or_else(|| profile_region(profile.as_deref().unwrap_or("default")))
+            .unwrap_or_else(|| BEDROCK_DEFAULT_REGION.to_string());
+        let host: String = config
+            .get("BEDROCK_HOST")
+            .unwrap_or_else(|_| format!("https://bedrock-runtime.{}.amazonaws.com", region));
+
+        let client = Client::builder()
+            .timeout(Duration::from_secs(600))
+            .build()?;
+
+        Ok(Self {
+            client,
+            credentials: CredentialChain::new(profile.clone(), region.clone()),
+            region,
+            profile,
+            host,
+            model,
+            cached: Mutex::new(None),
+        })
+    }
+
+    /// The cached credentials, resolved again when missing or about to expire
+    async fn credentials(&self) -> Result<AwsCredentials, ProviderError> {
+        let mut cached = self.cached.lock().await;
+        match cached.as_ref() {
+            Some(credentials) if !credentials.is_expiring() => Ok(credentials.clone()),
+            _ => {
+                let credentials = self
+                    .credentials
+                    .resolve()
+                    .await
+                    .map_err(|e| ProviderError::authentication(e.to_string()))?;
+                *cached = Some(credentials.clone());
+                Ok(credentials)
+            }
+        }
+    }
+
+    async fn post(&self, payload: Value) -> Result<Value, ProviderError> {
+        // The model ID holds a colon, which has to reach the signature encoded
+        let url = format!(
+            "{}/model/{}/converse",
+            self.host.trim_end_matches('/'),
+            uri_encode(&self.model.model_name)
+        );
+        let url = Url::parse(&url).map_err(|e| ProviderError::request_failed(e.to_string()))?;
+        let body = serde_json::to_vec(&payload)
+            .map_err(|e| ProviderError::request_failed(e.to_string()))?;
+
+        let credentials = self.credentials().await?;
+        let signer = Signer {
+            credentials: &credentials,
+            region: &self.region,
+            service: "bedrock",
+        };
+        let content_type = [("content-type", "application/json")];
+        let mut request = self
+            .client
+            .post(url.clone())
+            .header("content-type", "application/json");
+        for (name, value) in signer.sign("POST", &url, &content_type, &body, Utc::now()) {
+            request = request.header(name, value);
+        }
+
+        let response = request.body(body).send().await?;
+        let status = response.status();
+        // e.g. "ThrottlingException:http://internal.amazon.com/coral/com.amazon.bedrock/"
+        let error_type = response
+            .headers()
+            .get("x-amzn-errortype")
+            .and_then(|v| v.to_str().ok())
+            .map(|v| v.split(':').next().unwrap_or(v).to_string());
+        let request_id = response
+            .headers()
+            .get("x-amzn-requestid")
+            .and_then(|v| v.to_str().ok())
+            .map(str::to_string);
+        let payload: Option<Value> = response.json().await.ok();
+
+        if status == StatusCode::OK {
+            return payload.ok_or_else(|| {
+                ProviderError::request_failed("Response body is not valid JSON".to_string())
+            });
+        }
+
+        let message = payload
+            .as_ref()
+            .and_then(|p| p.get("message").or_else(|| p.get("Message")))
+            .and_then(|m| m.as_str())
+            .unwrap_or("Unknown error")
+            .to_string();
+        tracing::debug!(
+            "Provider request failed with status: {}. Error: {:?}. Payload: {:?}",
+            status,
+            error_type,
+            payload
+        );
+        // https://docs.aws.amazon.com/bedrock/latest/APIReference/API_runtime_Converse.html#API_runtime_Converse_Errors
+        match error_type.as_deref() {
+            Some("ThrottlingException" | "ServiceQuotaExceededException") => {
+                Err(ProviderError::rate_limited(message))
+            }
+            Some("ExpiredTokenException") => {
+                *self.cached.lock().await = None;
+                Err(ProviderError::authentication(message))
+            }
+            Some(
+                "AccessDeniedException"
+                | "UnrecognizedClientException"
+                | "InvalidSignatureException",
+            ) => Err(ProviderError::authentication(message)),
+            Some("ValidationException")
+                if ["too long", "too many", "input length", "context window"]
+                    .iter()
+                    .any(|hint| message.to_lowercase().contains(hint)) =>
+            {
+                Err(ProviderError::ContextLengthExceeded(message))
+            }
+            Some(
+                "InternalServerException"
+                | "ServiceUnavailableException"
+                | "ModelNotReadyException"
+                | "ModelTimeoutException",
+            ) => Err(ProviderError::ServerError(message)),
+            _ => match status {
+                StatusCode::TOO_MANY_REQUESTS => Err(ProviderError::rate_limited(message)),
+                StatusCode::UNAUTHORIZED | StatusCode::FORBIDDEN => {
+                    Err(ProviderError::authentication(message))
+                }
+                status if status.is_server_error() => Err(ProviderError::ServerError(message)),
+                _ => Err(ProviderError::request_failed(format!(
+                    "Request failed with status: {}. Message: {}",
+                    status, message
+                ))
+                .with_status(status.as_u16())
+                .with_code(error_type)
+                .with_request_id(request_id)),
+            },
+        }
+    }
+}
+
+#[async_trait]
+impl Provider for BedrockProvider {
+    fn metadata() -> ProviderMetadata {
+        ProviderMetadata::new(
+            "bedrock",
+            "Amazon Bedrock",
+            "Claude, Llama, Nova and other models on Amazon Bedrock",
+            BEDROCK_DEFAULT_MODEL,
+            BEDROCK_KNOWN_MODELS
+                .iter()
+                .map(|&s| s.to_string())
+                .collect(),
+            BEDROCK_DOC_URL,
+            vec![
+                ConfigKey::new("AWS_REGION", true, false, Some(BEDROCK_DEFAULT_REGION)),
+                ConfigKey::new("AWS_PROFILE", false, false, None),
+            ],
+        )
+    }
+
+    fn instance_metadata(&self) -> ProviderMetadata {
+        Self::metadata()
+    }
+
+    fn get_model_config(&self) -> ModelConfig {
+        self.model.clone()
+    }
+
+    #[tracing::instrument(
+        skip(self, system, messages, tools),
+        fields(model_config, input, output, input_tokens, output_tokens, total_tokens)
+    )]
+    async fn complete(
+        &self,
+        system: &str,
+        messages: &[Message],
+        tools: &[Tool],
+    ) -> Result<(Message, ProviderUsage), ProviderError> {
+        validate_params(&self.model)?;
+        validate_tool_pairing(messages)?;
+        let payload = create_request(&self.model, system, messages, tools)?;
+
+        // Make request
+        let response = self.post(payload.clone()).await?;
+
+        // Parse response
+        let message = response_to_message(response.clone())?;
+        let usage = get_usage(&response)?;
+        emit_debug_trace(self, messages, &payload, &response, &usage);
+        Ok((
+            message,
+            ProviderUsage::new(self.model.model_name.clone(), usage),
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+    use wiremock::matchers::{header_exists, method, path};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    fn provider(host: &str) -> BedrockProvider {
+        let credentials = AwsCredentials::new("AKID".to_string(), "secret".to_string());
+        BedrockProvider {
+            client: Client::new(),
+            region: "us-east-1".to_string(),
+            profile: None,
+            host: host.to_string(),
+            model: ModelConfig::new(BEDROCK_DEFAULT_MODEL.to_string()),
+            credentials: CredentialChain::new(None, "us-east-1".to_string()),
+            cached: Mutex::new(Some(credentials)),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_complete_signs_converse_request() -> Result<()> {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path(
+                "/model/anthropic.claude-3-5-sonnet-20241022-v2%3A0/converse",
+            ))
+            .and(header_exists("authorization"))
+            .and(header_exists("x-amz-date"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+                "output": {"message": {"role": "assistant", "content": [{"text": "Hello!"}]}},
+                "stopReason": "end_turn",
+                "usage": {"inputTokens": 12, "outputTokens": 3, "totalTokens": 15}
+            })))
+            .mount(&server)
+            .await;
+
+        let (message, usage) = provider(&server.uri())
+            .complete("", &[Message::user().with_text("Hi")], &[])
+            .await?;
+        assert_eq!(message.as_concat_text(), "Hello!");
+        assert_eq!(usage.usage.total_tokens, Some(15));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_throttling_is_a_rate_limit() {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .respond_with(
+                ResponseTemplate::new(400)
+                    .insert_header(
+                        "x-amzn-errortype",
+                        "ThrottlingException:http://internal.amazon.com/coral/com.amazon.bedrock/",
+                    )
+                    .set_body_json(json!({"message": "Too many requests, please wait"})),
+            )
+            .mount(&server)
+            .await;
+
+        let result = provider(&server.uri())
+            .complete("", &[Message::user().with_text("Hi")], &[])
+            .await;
+        assert!(matches!(
+            result,
+            Err(ProviderError::RateLimitExceeded { .. })
+        ));
+    }
+}
@@ -0,0 +1,59 @@
+use std::sync::LazyLock;
+use tokio::runtime::{Handle, Runtime};
+
+use super::base::{Provider, ProviderUsage};
+use super::errors::ProviderError;
+use crate::message::Message;
+use mcp_core::tool::Tool;
+
+/// Runtime shared by all blocking calls, created on first use
+static RUNTIME: LazyLock<Result<Runtime, String>> = LazyLock::new(|| {
+    tokio::runtime::Builder::new_multi_thread()
+        .enable_all()
+        .build()
+        .map_err(|e| e.to_string())
+});
+
+/// Run `Provider::complete` to completion for callers without an async runtime
+///
+/// Intended for scripts and FFI boundaries. Calling it from inside a tokio
+/// runtime would deadlock or panic, so that returns an `ExecutionError` instead;
+/// async code should await `complete` directly.
+pub fn complete_blocking(
+    provider: &dyn Provider,
+    system: &str,
+    messages: &[Message],
+    tools: &[Tool],
+) -> Result<(Message, ProviderUsage), ProviderError> {
+    if Handle::try_current().is_ok() {
+        return Err(ProviderError::ExecutionError(
+            "complete_blocking was called from within an async runtime, await Provider::complete instead"
+                .to_string(),
+        ));
+    }
+    let runtime = RUNTIME.as_ref().map_err(|e| {
+        ProviderError::ExecutionError(format!("Failed to start the async runtime: {}", e))
+    })?;
+    runtime.block_on(provider.complete(system, messages, tools))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::providers::testing::TestProvider;
+
+    #[test]
+    fn test_complete_blocking_without_runtime() {
+        let provider = TestProvider::new("static").with_text("done");
+        for _ in 0..2 {
+            let (message, _) = complete_blocking(&provider, "", &[], &[]).unwrap();
+            assert_eq!(message.as_concat_text(), "done");
+        }
+    }
+
+    #[tokio::test]
+    async fn test_complete_blocking_inside_runtime_errors() {
+        let result = complete_blocking(&TestProvider::new("static"), "", &[], &[]);
+        assert!(matches!(result, Err(ProviderError::ExecutionError(_))));
+    }
+}
@@ -0,0 +1,319 @@
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use futures::StreamExt;
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+use super::base::{
+    MessageDelta, MessageStream, Provider, ProviderMetadata, ProviderUsage, RateLimits, ThreadId,
+};
+use super::errors::ProviderError;
+use super::pricing::request_cost;
+use crate::message::Message;
+use crate::model::ModelConfig;
+use mcp_core::tool::Tool;
+
+/// The period a spend cap applies to, windows start at midnight UTC
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BudgetWindow {
+    Daily,
+    Monthly,
+    /// As long as the provider lives, i.e. one agent session, or as long as its state file
+    Session,
+}
+
+impl BudgetWindow {
+    /// Identifies the window `now` falls in, e.g. "2025-01-31" or "2025-01"
+    fn label(&self, now: DateTime<Utc>) -> String {
+        match self {
+            BudgetWindow::Daily => now.format("%Y-%m-%d").to_string(),
+            BudgetWindow::Monthly => now.format("%Y-%m").to_string(),
+            BudgetWindow::Session => "session".to_string(),
+        }
+    }
+}
+
+/// The spend within a window, as stored in the state file
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+struct Spend {
+    window: String,
+    dollars: f64,
+}
+
+/// A provider decorator that stops sending requests once a spend cap is reached
+///
+/// The cost of each response is computed from its usage with the pricing
+/// table and added to the total for the current window. Once the total reaches
+/// the cap new requests fail with `BudgetExceeded` until the next window
+/// starts. A request already in flight is not interrupted, so the total can
+/// end slightly above the cap. Responses from models without a known price
+/// are not counted. With `with_state_file` the total survives restarts.
+pub struct BudgetedProvider {
+    inner: Box<dyn Provider>,
+    cap: f64,
+    window: BudgetWindow,
+    state_file: Option<PathBuf>,
+    spend: Arc<Mutex<Spend>>,
+}
+
+impl BudgetedProvider {
+    /// Cap the spend at `cap` US dollars per month
+    pub fn new(inner: Box<dyn Provider>, cap: f64) -> Self {
+        Self {
+            inner,
+            cap,
+            window: BudgetWindow::Monthly,
+            state_file: None,
+            spend: Arc::new(Mutex::new(Spend::default())),
+        }
+    }
+
+    /// Apply the cap per day or per month
+    pub fn with_window(mut self, window: BudgetWindow) -> Self {
+        self.window = window;
+        self
+    }
+
+    /// Keep the running total in this file, continuing from the total already stored there
+    pub fn with_state_file(mut self, path: impl Into<PathBuf>) -> Self {
+        let path = path.into();
+        match std::fs::read_to_string(&path) {
+            Ok(contents) => match serde_json::from_str(&contents) {
+                Ok(spend) => self.spend = Arc::new(Mutex::new(spend)),
+                Err(e) => tracing::warn!("Ignoring invalid budget state {}: {}", path.display(), e),
+            },
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+            Err(e) => tracing::warn!("Could not read budget state {}: {}", path.display(), e),
+        }
+        self.state_file = Some(path);
+        self
+    }
+
+    /// The dollars spent in the current window
+    pub fn spent(&self) -> f64 {
+        let mut spend = self.spend.lock().unwrap();
+        roll_over(&mut spend, self.window);
+        spend.dollars
+    }
+
+    fn check(&self) -> Result<(), ProviderError> {
+        let spent = self.spent();
+        if spent >= self.cap {
+            return Err(ProviderError::BudgetExceeded(format!(
+                "spent ${:.2} of the ${:.2} {} cap",
+                spent,
+                self.cap,
+                match self.window {
+                    BudgetWindow::Daily => "daily",
+                    BudgetWindow::Monthly => "monthly",
+                    BudgetWindow::Session => "session",
+                }
+            )));
+        }
+        Ok(())
+    }
+
+    /// Records the cost of a response, shared with streams that outlive the call
+    fn charger(&self) -> impl Fn(&ProviderUsage) + Send + Sync + 'static {
+        let spend = self.spend.clone();
+        let window = self.window;
+        let state_file = self.state_file.clone();
+        move |usage: &ProviderUsage| {
+            let Some(cost) = request_cost(&usage.model, &usage.usage) else {
+                tracing::warn!("No price known for model {}, not counted", usage.model);
+                return;
+            };
+            let mut spend = spend.lock().unwrap();
+            roll_over(&mut spend, window);
+            spend.dollars += cost;
+            if let Some(path) = &state_file {
+                save(path, &spend);
+            }
+        }
+    }
+}
+
+/// Start over from zero when a new window has begun
+fn roll_over(spend: &mut Spend, window: BudgetWindow) {
+    let current = window.label(Utc::now());
+    if spend.window != current {
+        *spend = Spend {
+            window: current,
+            dollars: 0.0,
+        };
+    }
+}
+
+fn save(path: &Path, spend: &Spend) {
+    let result = serde_json::to_string(spend)
+        .map_err(std::io::Error::other)
+        .and_then(|contents| std::fs::write(path, contents));
+    if let Err(e) = result {
+        tracing::warn!("Could not save budget state {}: {}", path.display(), e);
+    }
+}
+
+#[async_trait]
+impl Provider for BudgetedProvider {
+    fn metadata() -> ProviderMetadata {
+        ProviderMetadata::empty()
+    }
+
+    fn instance_metadata(&self) -> ProviderMetadata {
+        self.inner.instance_metadata()
+    }
+
+    fn get_model_config(&self) -> ModelConfig {
+        self.inner.get_model_config()
+    }
+
+    fn format_system_prompt(&self, raw: &str) -> String {
+        self.inner.format_system_prompt(raw)
+    }
+
+    fn request_body(
+        &self,
+        system: &str,
+        messages: &[Message],
+        tools: &[Tool],
+    ) -> Result<serde_json::Value, ProviderError> {
+        self.inner.request_body(system, messages, tools)
+    }
+
+    fn count_request_tokens(&self, system: &str, messages: &[Message], tools: &[Tool]) -> usize {
+        self.inner.count_request_tokens(system, messages, tools)
+    }
+
+    async fn warmup(&self) -> Result<(), ProviderError> {
+        self.inner.warmup().await
+    }
+
+    async fn flush(&self) -> Result<(), ProviderError> {
+        self.inner.flush().await
+    }
+
+    async fn rate_limits(&self) -> Result<RateLimits, ProviderError> {
+        self.inner.rate_limits().await
+    }
+
+    async fn fetch_supported_models(&self) -> Result<Vec<String>, ProviderError> {
+        self.inner.fetch_supported_models().await
+    }
+
+    async fn embed(&self, inputs: &[String]) -> Result<Vec<Vec<f32>>, ProviderError> {
+        self.inner.embed(inputs).await
+    }
+
+    async fn complete(
+        &self,
+        system: &str,
+        messages: &[Message],
+        tools: &[Tool],
+    ) -> Result<(Message, ProviderUsage), ProviderError> {
+        self.check()?;
+        let (message, usage) = self.inner.complete(system, messages, tools).await?;
+        self.charger()(&usage);
+        Ok((message, usage))
+    }
+
+    async fn complete_in_thread(
+        &self,
+        system: &str,
+        messages: &[Message],
+        tools: &[Tool],
+        thread: Option<ThreadId>,
+    ) -> Result<(Message, ProviderUsage, Option<ThreadId>), ProviderError> {
+        self.check()?;
+        let (message, usage, thread) = self
+            .inner
+            .complete_in_thread(system, messages, tools, thread)
+            .await?;
+        self.charger()(&usage);
+        Ok((message, usage, thread))
+    }
+
+    async fn stream(
+        &self,
+        system: &str,
+        messages: &[Message],
+        tools: &[Tool],
+    ) -> Result<MessageStream, ProviderError> {
+        self.check()?;
+        let mut stream = self.inner.stream(system, messages, tools).await?;
+
+        let charge = self.charger();
+        Ok(Box::pin(async_stream::try_stream! {
+            while let Some(delta) = stream.next().await {
+                let delta = delta?;
+                if let MessageDelta::Usage(usage) = &delta {
+                    charge(usage);
+                }
+                yield delta;
+            }
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::providers::base::Usage;
+    use crate::providers::testing::TestProvider;
+
+    /// Every response costs $2.50 at gpt-4o prices
+    fn costly() -> Box<TestProvider> {
+        Box::new(TestProvider::new("gpt-4o").with_usage(
+            "gpt-4o-2024-08-06",
+            Usage::new(Some(1_000_000), Some(0), Some(1_000_000)),
+        ))
+    }
+
+    #[tokio::test]
+    async fn test_requests_stop_at_the_cap() {
+        let provider = BudgetedProvider::new(costly(), 5.0);
+        provider.complete("", &[], &[]).await.unwrap();
+        let mut stream = provider.stream("", &[], &[]).await.unwrap();
+        while stream.next().await.is_some() {}
+        assert_eq!(provider.spent(), 5.0);
+
+        assert!(matches!(
+            provider.complete("", &[], &[]).await,
+            Err(ProviderError::BudgetExceeded(_))
+        ));
+        assert!(matches!(
+            provider.stream("", &[], &[]).await,
+            Err(ProviderError::BudgetExceeded(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_total_persists_and_resets_with_the_window() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("budget.json");
+
+        let provider = BudgetedProvider::new(costly(), 2.0)
+            .with_window(BudgetWindow::Daily)
+            .with_state_file(&path);
+        provider.complete("", &[], &[]).await.unwrap();
+
+        // A restart continues from the stored total
+        let restarted = BudgetedProvider::new(costly(), 2.0)
+            .with_window(BudgetWindow::Daily)
+            .with_state_file(&path);
+        assert_eq!(restarted.spent(), 2.5);
+        assert!(restarted.complete("", &[], &[]).await.is_err());
+
+        // A total from an earlier day no longer counts
+        let stale = Spend {
+            window: "2000-01-01".to_string(),
+            dollars: 100.0,
+        };
+        std::fs::write(&path, serde_json::to_string(&stale).unwrap()).unwrap();
+        let next_day = BudgetedProvider::new(costly(), 2.0)
+            .with_window(BudgetWindow::Daily)
+            .with_state_file(&path);
+        assert_eq!(next_day.spent(), 0.0);
+        assert!(next_day.complete("", &[], &[]).await.is_ok());
+    }
+}
@@ -0,0 +1,294 @@
+use async_trait::async_trait;
+use std::collections::VecDeque;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use super::base::{MessageStream, Provider, ProviderMetadata, ProviderUsage, RateLimits};
+use super::errors::ProviderError;
+use crate::message::Message;
+use crate::model::ModelConfig;
+use mcp_core::tool::Tool;
+
+const DEFAULT_FAILURE_THRESHOLD: usize = 5;
+const DEFAULT_WINDOW: usize = 10;
+const DEFAULT_COOLDOWN: Duration = Duration::from_secs(30);
+
+#[derive(Debug)]
+enum CircuitState {
+    /// Requests flow normally, `outcomes` holds the most recent results (true = failure)
+    Closed { outcomes: VecDeque<bool> },
+    /// Requests fail fast until the cooldown has passed
+    Open { until: Instant },
+    /// A single probe request is in flight to decide whether to close again
+    ///
+    /// A probe that never reports back (e.g. its future was dropped) is replaced
+    /// by a new one after another cooldown.
+    HalfOpen { since: Instant },
+}
+
+/// A provider decorator that stops calling a consistently failing provider
+///
+/// Once `failure_threshold` of the last `window` requests failed the circuit
+/// opens and requests fail with `CircuitOpen` for the cooldown. After that a
+/// single probe is let through; success closes the circuit, failure reopens it.
+/// Only provider side failures count, errors caused by the request itself such
+/// as an exceeded context length do not.
+pub struct CircuitBreakerProvider {
+    inner: Box<dyn Provider>,
+    failure_threshold: usize,
+    window: usize,
+    cooldown: Duration,
+    state: Mutex<CircuitState>,
+}
+
+impl CircuitBreakerProvider {
+    pub fn new(inner: Box<dyn Provider>) -> Self {
+        Self {
+            inner,
+            failure_threshold: DEFAULT_FAILURE_THRESHOLD,
+            window: DEFAULT_WINDOW,
+            cooldown: DEFAULT_COOLDOWN,
+            state: Mutex::new(CircuitState::Closed {
+                outcomes: VecDeque::new(),
+            }),
+        }
+    }
+
+    /// Number of failures within the window that opens the circuit
+    pub fn with_failure_threshold(mut self, threshold: usize) -> Self {
+        self.failure_threshold = threshold.max(1);
+        self
+    }
+
+    /// Number of recent requests considered when computing the failure rate
+    pub fn with_window(mut self, window: usize) -> Self {
+        self.window = window.max(1);
+        self
+    }
+
+    /// How long the circuit stays open before a probe is attempted
+    pub fn with_cooldown(mut self, cooldown: Duration) -> Self {
+        self.cooldown = cooldown;
+        self
+    }
+
+    /// Check whether a request may proceed, moving an expired open circuit to half-open
+    fn acquire(&self) -> Result<(), ProviderError> {
+        let mut state = self.state.lock().unwrap();
+        match &*state {
+            CircuitState::Closed { .. } => Ok(()),
+            CircuitState::Open { until } if Instant::now() >= *until => {
+                *state = CircuitState::HalfOpen {
+                    since: Instant::now(),
+                };
+                Ok(())
+            }
+            CircuitState::HalfOpen { since } if since.elapsed() >= self.cooldown => {
+                *state = CircuitState::HalfOpen {
+                    since: Instant::now(),
+                };
+                Ok(())
+            }
+            CircuitState::Open { until } => Err(ProviderError::CircuitOpen(format!(
+                "retrying in {}s",
+                until.saturating_duration_since(Instant::now()).as_secs()
+            ))),
+            CircuitState::HalfOpen { .. } => Err(ProviderError::CircuitOpen(
+                "a probe request is in flight".to_string(),
+            )),
+        }
+    }
+
+    fn record<T>(&self, result: &Result<T, ProviderError>) {
+        let failed = matches!(result, Err(e) if is_provider_failure(e));
+        let mut state = self.state.lock().unwrap();
+        match &mut *state {
+            CircuitState::Closed { outcomes } => {
+                outcomes.push_back(failed);
+                while outcomes.len() > self.window {
+                    outcomes.pop_front();
+                }
+                if outcomes.iter().filter(|f| **f).count() >= self.failure_threshold {
+                    tracing::warn!(
+                        "Provider is failing, opening circuit for {:?}",
+                        self.cooldown
+                    );
+                    *state = CircuitState::Open {
+                        until: Instant::now() + self.cooldown,
+                    };
+                }
+            }
+            CircuitState::HalfOpen { .. } if failed => {
+                *state = CircuitState::Open {
+                    until: Instant::now() + self.cooldown,
+                };
+            }
+            CircuitState::HalfOpen { .. } => {
+                *state = CircuitState::Closed {
+                    outcomes: VecDeque::new(),
+                };
+            }
+            CircuitState::Open { .. } => {}
+        }
+    }
+}
+
+/// Whether an error indicates the provider is unhealthy rather than a bad request
+fn is_provider_failure(error: &ProviderError) -> bool {
+    matches!(
+        error,
+        ProviderError::ServerError(_)
+            | ProviderError::RateLimitExceeded { .. }
+            | ProviderError::RequestFailed { .. }
+            | ProviderError::Http { .. }
+            | ProviderError::ConnectTimeout { .. }
+            | ProviderError::ReadTimeout { .. }
+            | ProviderError::InvalidResponse { .. }
+            | ProviderError::ExecutionError(_)
+            | ProviderError::StreamDisconnected(_)
+    )
+}
+
+#[async_trait]
+impl Provider for CircuitBreakerProvider {
+    fn metadata() -> ProviderMetadata {
+        ProviderMetadata::empty()
+    }
+
+    fn instance_metadata(&self) -> ProviderMetadata {
+        self.inner.instance_metadata()
+    }
+
+    fn get_model_config(&self) -> ModelConfig {
+        self.inner.get_model_config()
+    }
+
+    fn format_system_prompt(&self, raw: &str) -> String {
+        self.inner.format_system_prompt(raw)
+    }
+
+    fn request_body(
+        &self,
+        system: &str,
+        messages: &[Message],
+        tools: &[Tool],
+    ) -> Result<serde_json::Value, ProviderError> {
+        self.inner.request_body(system, messages, tools)
+    }
+
+    fn count_request_tokens(&self, system: &str, messages: &[Message], tools: &[Tool]) -> usize {
+        self.inner.count_request_tokens(system, messages, tools)
+    }
+
+    async fn warmup(&self) -> Result<(), ProviderError> {
+        self.inner.warmup().await
+    }
+
+    async fn flush(&self) -> Result<(), ProviderError> {
+        self.inner.flush().await
+    }
+
+    async fn rate_limits(&self) -> Result<RateLimits, ProviderError> {
+        self.inner.rate_limits().await
+    }
+
+    async fn fetch_supported_models(&self) -> Result<Vec<String>, ProviderError> {
+        self.inner.fetch_supported_models().await
+    }
+
+    async fn embed(&self, inputs: &[String]) -> Result<Vec<Vec<f32>>, ProviderError> {
+        self.inner.embed(inputs).await
+    }
+
+    async fn complete(
+        &self,
+        system: &str,
+        messages: &[Message],
+        tools: &[Tool],
+    ) -> Result<(Message, ProviderUsage), ProviderError> {
+        self.acquire()?;
+        let result = self.inner.complete(system, messages, tools).await;
+        self.record(&result);
+        result
+    }
+
+    async fn stream(
+        &self,
+        system: &str,
+        messages: &[Message],
+        tools: &[Tool],
+    ) -> Result<MessageStream, ProviderError> {
+        self.acquire()?;
+        let result = self.inner.stream(system, messages, tools).await;
+        self.record(&result);
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::providers::testing::{Seen, TestProvider};
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::Arc;
+
+    /// Fails with a server error while `failing` is set
+    fn flaky() -> (CircuitBreakerProvider, Arc<AtomicBool>, Arc<Seen>) {
+        let failing = Arc::new(AtomicBool::new(true));
+        let inner = TestProvider::new("flaky").with_reply({
+            let failing = failing.clone();
+            move |_| match failing.load(Ordering::SeqCst) {
+                true => Err(ProviderError::ServerError("503".to_string())),
+                false => Ok(Message::assistant().with_text("ok")),
+            }
+        });
+        let seen = inner.seen();
+        let provider = CircuitBreakerProvider::new(Box::new(inner))
+            .with_failure_threshold(2)
+            .with_window(3)
+            .with_cooldown(Duration::from_millis(50));
+        (provider, failing, seen)
+    }
+
+    #[tokio::test]
+    async fn test_opens_after_threshold() {
+        let (provider, _, calls) = flaky();
+
+        for _ in 0..2 {
+            let result = provider.complete("", &[], &[]).await;
+            assert!(matches!(result, Err(ProviderError::ServerError(_))));
+        }
+        let result = provider.complete("", &[], &[]).await;
+        assert!(matches!(result, Err(ProviderError::CircuitOpen(_))));
+        assert_eq!(calls.calls(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_probe_closes_circuit() {
+        let (provider, failing, calls) = flaky();
+        for _ in 0..2 {
+            let _ = provider.complete("", &[], &[]).await;
+        }
+
+        failing.store(false, Ordering::SeqCst);
+        tokio::time::sleep(Duration::from_millis(60)).await;
+        assert!(provider.complete("", &[], &[]).await.is_ok());
+        assert!(provider.complete("", &[], &[]).await.is_ok());
+        assert_eq!(calls.calls(), 4);
+    }
+
+    #[tokio::test]
+    async fn test_failed_probe_reopens_circuit() {
+        let (provider, _, calls) = flaky();
+        for _ in 0..2 {
+            let _ = provider.complete("", &[], &[]).await;
+        }
+
+        tokio::time::sleep(Duration::from_millis(60)).await;
+        let result = provider.complete("", &[], &[]).await;
+        assert!(matches!(result, Err(ProviderError::ServerError(_))));
+        let result = provider.complete("", &[], &[]).await;
+        assert!(matches!(result, Err(ProviderError::CircuitOpen(_))));
+        assert_eq!(calls.calls(), 3);
+    }
+}
@@ -0,0 +1,304 @@
+use async_trait::async_trait;
+use mcp_core::content::Content;
+use std::collections::HashMap;
+
+use super::base::{MessageStream, Provider, ProviderMetadata, ProviderUsage, RateLimits};
+use super::errors::ProviderError;
+use crate::message::{Message, MessageContent};
+use crate::model::ModelConfig;
+use crate::token_counter::TokenCounter;
+use mcp_core::tool::Tool;
+
+/// Blocks shorter than this are cheaper to repeat than to refer to
+const DEFAULT_MIN_BLOCK_CHARS: usize = 200;
+
+/// How much `CompressingProvider` changes the conversation
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CompressionLevel {
+    /// Strip trailing whitespace and collapse runs of blank lines
+    Whitespace,
+    /// Also replace repeated blocks, such as a file read twice, with a reference to the first copy
+    Deduplicate,
+}
+
+/// The prompt tokens of a conversation before and after compression
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CompressionSavings {
+    pub original_tokens: usize,
+    pub compressed_tokens: usize,
+}
+
+impl CompressionSavings {
+    pub fn saved_tokens(&self) -> usize {
+        self.original_tokens.saturating_sub(self.compressed_tokens)
+    }
+}
+
+/// A provider decorator that shrinks a conversation before it is sent
+///
+/// Text and tool outputs lose trailing whitespace and extra blank lines, which
+/// never changes their meaning; indentation is kept so code reads the same.
+/// With `CompressionLevel::Deduplicate` a block of at least
+/// `with_min_block_chars` characters that already appeared earlier in the
+/// conversation is replaced by a note pointing at the message holding the
+/// first copy. The savings of each request are logged at debug level.
+pub struct CompressingProvider {
+    inner: Box<dyn Provider>,
+    level: CompressionLevel,
+    min_block_chars: usize,
+}
+
+impl CompressingProvider {
+    /// Compress with `CompressionLevel::Deduplicate`
+    pub fn new(inner: Box<dyn Provider>) -> Self {
+        Self {
+            inner,
+            level: CompressionLevel::Deduplicate,
+            min_block_chars: DEFAULT_MIN_BLOCK_CHARS,
+        }
+    }
+
+    pub fn with_level(mut self, level: CompressionLevel) -> Self {
+        self.level = level;
+        self
+    }
+
+    /// Only deduplicate blocks of at least this many characters
+    pub fn with_min_block_chars(mut self, min_block_chars: usize) -> Self {
+        self.min_block_chars = min_block_chars;
+        self
+    }
+
+    /// The conversation as it would be sent, with the tokens saved
+    pub fn compress(
+        &self,
+        system: &str,
+        messages: &[Message],
+    ) -> (Vec<Message>, CompressionSavings) {
+        let compressed = self.compress_messages(messages);
+        let savings = self.savings(system, messages, &compressed);
+        (compressed, savings)
+    }
+
+    /// The conversation as it would be sent
+    fn compress_messages(&self, messages: &[Message]) -> Vec<Message> {
+        // Where each block was first seen, by message number as the model counts them
+        let mut seen: HashMap<String, usize> = HashMap::new();
+        let mut compress = |text: &str, index: usize| -> String {
+            let text = squeeze_whitespace(text);
+            if self.level != CompressionLevel::Deduplicate || text.len() < self.min_block_chars {
+                return text;
+            }
+            match seen.get(&text) {
+                Some(first) => format!(
+                    "[Identical to the content of message {} above, omitted to save space]",
+                    first
+                ),
+                None => {
+                    seen.insert(text.clone(), index);
+                    text
+                }
+            }
+        };
+
+        messages
+            .iter()
+            .enumerate()
+            .map(|(i, message)| {
+                let mut message = message.clone();
+                for content in message.content.iter_mut() {
+                    match content {
+                        MessageContent::Text(text) => text.text = compress(&text.text, i + 1),
+                        MessageContent::ToolResponse(response) => {
+                            if let Ok(result) = response.tool_result.as_mut() {
+                                for item in result.iter_mut() {
+                                    if let Content::Text(text) = item {
+                                        text.text = compress(&text.text, i + 1);
+                                    }
+                                }
+                            }
+                        }
+                        _ => {}
+                    }
+                }
+                message
+            })
+            .collect()
+    }
+
+    fn savings(
+        &self,
+        system: &str,
+        messages: &[Message],
+        compressed: &[Message],
+    ) -> CompressionSavings {
+        let counter = TokenCounter::new(self.inner.get_model_config().tokenizer_name());
+        CompressionSavings {
+            original_tokens: counter.count_chat_tokens(system, messages, &[]),
+            compressed_tokens: counter.count_chat_tokens(system, compressed, &[]),
+        }
+    }
+
+    /// The conversation to send, measuring the savings only when they are logged
+    fn compressed(&self, system: &str, messages: &[Message]) -> Vec<Message> {
+        let compressed = self.compress_messages(messages);
+        if tracing::enabled!(tracing::Level::DEBUG) {
+            let savings = self.savings(system, messages, &compressed);
+            tracing::debug!(
+                original_tokens = savings.original_tokens,
+                compressed_tokens = savings.compressed_tokens,
+                "Compressed the conversation, saving {} tokens",
+                savings.saved_tokens()
+            );
+        }
+        compressed
+    }
+}
+
+/// Strip trailing whitespace from every line and keep at most one blank line in a row
+fn squeeze_whitespace(text: &str) -> String {
+    let mut lines: Vec<&str> = Vec::new();
+    for line in text.trim_end().lines().map(str::trim_end) {
+        if line.is_empty() && lines.last().is_some_and(|last| last.is_empty()) {
+            continue;
+        }
+        lines.push(line);
+    }
+    lines.join("\n")
+}
+
+#[async_trait]
+impl Provider for CompressingProvider {
+    fn metadata() -> ProviderMetadata {
+        ProviderMetadata::empty()
+    }
+
+    fn instance_metadata(&self) -> ProviderMetadata {
+        self.inner.instance_metadata()
+    }
+
+    fn get_model_config(&self) -> ModelConfig {
+        self.inner.get_model_config()
+    }
+
+    fn format_system_prompt(&self, raw: &str) -> String {
+        self.inner.format_system_prompt(raw)
+    }
+
+    fn request_body(
+        &self,
+        system: &str,
+        messages: &[Message],
+        tools: &[Tool],
+    ) -> Result<serde_json::Value, ProviderError> {
+        let messages = self.compress_messages(messages);
+        self.inner.request_body(system, &messages, tools)
+    }
+
+    fn count_request_tokens(&self, system: &str, messages: &[Message], tools: &[Tool]) -> usize {
+        let messages = self.compress_messages(messages);
+        self.inner.count_request_tokens(system, &messages, tools)
+    }
+
+    async fn warmup(&self) -> Result<(), ProviderError> {
+        self.inner.warmup().await
+    }
+
+    async fn flush(&self) -> Result<(), ProviderError> {
+        self.inner.flush().await
+    }
+
+    async fn rate_limits(&self) -> Result<RateLimits, ProviderError> {
+        self.inner.rate_limits().await
+    }
+
+    async fn fetch_supported_models(&self) -> Result<Vec<String>, ProviderError> {
+        self.inner.fetch_supported_models().await
+    }
+
+    async fn embed(&self, inputs: &[String]) -> Result<Vec<Vec<f32>>, ProviderError> {
+        self.inner.embed(inputs).await
+    }
+
+    async fn complete(
+        &self,
+        system: &str,
+        messages: &[Message],
+        tools: &[Tool],
+    ) -> Result<(Message, ProviderUsage), ProviderError> {
+        let messages = self.compressed(system, messages);
+        self.inner.complete(system, &messages, tools).await
+    }
+
+    async fn stream(
+        &self,
+        system: &str,
+        messages: &[Message],
+        tools: &[Tool],
+    ) -> Result<MessageStream, ProviderError> {
+        let messages = self.compressed(system, messages);
+        self.inner.stream(system, &messages, tools).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::providers::testing::TestProvider;
+
+    /// Answers with the last message it was sent
+    fn echo() -> Box<TestProvider> {
+        Box::new(TestProvider::new("gpt-4o").with_reply(|request| {
+            let text = request
+                .messages
+                .last()
+                .map(|m| m.as_concat_text())
+                .unwrap_or_default();
+            Ok(Message::assistant().with_text(text))
+        }))
+    }
+
+    #[test]
+    fn test_repetitive_conversation_shrinks() {
+        // Tool output padded into columns, with blank lines between sections
+        let file = (1..=40)
+            .map(|i| format!("{:<30}{:>8}   \n\n\n\n", format!("step_{}.rs", i), i * 100))
+            .collect::<String>();
+        let messages = vec![
+            Message::user().with_text(format!("Review this file:\n{}", file)),
+            Message::assistant().with_text("Looks fine."),
+            Message::user().with_text(format!("Review this file:\n{}", file)),
+        ];
+
+        let light = CompressingProvider::new(echo()).with_level(CompressionLevel::Whitespace);
+        let (compressed, whitespace) = light.compress("", &messages);
+        // Tokenizers merge runs of whitespace, so the text shrinks more than its token count
+        assert!(whitespace.compressed_tokens <= whitespace.original_tokens);
+        let text = compressed[0].as_concat_text();
+        assert!(text.len() < messages[0].as_concat_text().len());
+        // Column padding survives, trailing spaces and extra blank lines do not
+        assert!(text.contains(&format!("step_1.rs{}100\n\nstep_2.rs", " ".repeat(26))));
+
+        let (compressed, deduplicated) = CompressingProvider::new(echo()).compress("", &messages);
+        assert_eq!(compressed[0].as_concat_text(), text);
+        assert_eq!(
+            compressed[2].as_concat_text(),
+            "[Identical to the content of message 1 above, omitted to save space]"
+        );
+        // The repeated file accounts for almost half the conversation
+        assert!(deduplicated.compressed_tokens * 2 < deduplicated.original_tokens + 50);
+        assert!(deduplicated.saved_tokens() > whitespace.saved_tokens());
+    }
+
+    #[tokio::test]
+    async fn test_short_repeats_are_kept() {
+        let provider = CompressingProvider::new(echo());
+        let messages = vec![
+            Message::user().with_text("yes"),
+            Message::assistant().with_text("ok"),
+            Message::user().with_text("yes"),
+        ];
+        let (message, _) = provider.complete("", &messages, &[]).await.unwrap();
+        assert_eq!(message.as_concat_text(), "yes");
+    }
+}
@@ -1,7 +1,7 @@
 use anyhow::Result;
 use async_trait::async_trait;
 use reqwest::{Client, StatusCode};
-use serde::{Deserialize, Serialize};
+use serde::Serialize;
 use serde_json::Value;
 use std::time::Duration;
 
@@ -9,8 +9,10 @@ use super::base::{ConfigKey, Provider, ProviderMetadata, ProviderUsage, Usage};
 use super::errors::ProviderError;
 use super::formats::openai::{create_request, get_usage, response_to_message};
 use super::oauth;
-use super::utils::{get_model, ImageFormat};
-use crate::config::ConfigError;
+use super::utils::{
+    get_model, get_system_fingerprint, rate_limit_reset, send_with_retry, ImageFormat, RetryConfig,
+};
+use crate::config::{ConfigError, Secret};
 use crate::message::Message;
 use crate::model::ModelConfig;
 use mcp_core::tool::Tool;
@@ -31,9 +33,9 @@ pub const DATABRICKS_KNOWN_MODELS: &[&str] = &[
 pub const DATABRICKS_DOC_URL: &str =
     "https://docs.databricks.com/en/generative-ai/external-models/index.html";
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize)]
 pub enum DatabricksAuth {
-    Token(String),
+    Token(Secret),
     OAuth {
         host: String,
         client_id: String,
@@ -52,7 +54,7 @@ impl DatabricksAuth {
             scopes: DEFAULT_SCOPES.iter().map(|s| s.to_string()).collect(),
         }
     }
-    pub fn token(token: String) -> Self {
+    pub fn token(token: Secret) -> Self {
         Self::Token(token)
     }
 }
@@ -61,10 +63,11 @@ impl DatabricksAuth {
 pub struct DatabricksProvider {
     #[serde(skip)]
     client: Client,
-    host: String,
+    host: Secret,
     auth: DatabricksAuth,
     model: ModelConfig,
     image_format: ImageFormat,
+    retry: RetryConfig,
 }
 
 impl Default for DatabricksProvider {
@@ -80,10 +83,11 @@ impl DatabricksProvider {
 
         // For compatibility for now we check both config and secret for databricks host
         // but it is not actually a secret value
-        let mut host: Result<String, ConfigError> = config.get("DATABRICKS_HOST");
+        let mut host: Result<Secret, ConfigError> =
+            config.get::<String>("DATABRICKS_HOST").map(Secret::fixed);
 
         if host.is_err() {
-            host = config.get_secret("DATABRICKS_HOST")
+            host = config.get_secret_handle("DATABRICKS_HOST")
         }
 
         if host.is_err() {
@@ -100,29 +104,31 @@ impl DatabricksProvider {
             .build()?;
 
         // If we find a databricks token we prefer that
-        if let Ok(api_key) = config.get_secret("DATABRICKS_TOKEN") {
+        if let Ok(api_key) = config.get_secret_handle("DATABRICKS_TOKEN") {
             return Ok(Self {
                 client,
                 host,
                 auth: DatabricksAuth::token(api_key),
                 model,
                 image_format: ImageFormat::OpenAi,
+                retry: RetryConfig::default(),
             });
         }
 
         // Otherwise use Oauth flow
         Ok(Self {
             client,
-            auth: DatabricksAuth::oauth(host.clone()),
+            auth: DatabricksAuth::oauth(host.expose()),
             host,
             model,
             image_format: ImageFormat::OpenAi,
+            retry: RetryConfig::default(),
         })
     }
 
     async fn ensure_auth_header(&self) -> Result<String> {
         match &self.auth {
-            DatabricksAuth::Token(token) => Ok(format!("Bearer {}", token)),
+            DatabricksAuth::Token(token) => Ok(format!("Bearer {}", token.expose())),
             DatabricksAuth::OAuth {
                 host,
                 client_id,
@@ -139,27 +145,31 @@ impl DatabricksProvider {
     async fn post(&self, payload: Value) -> Result<Value, ProviderError> {
         let url = format!(
             "{}/serving-endpoints/{}/invocations",
-            self.host.trim_end_matches('/'),
+            self.host.expose().trim_end_matches('/'),
             self.model.model_name
         );
 
         let auth_header = self.ensure_auth_header().await?;
-        let response = self
-            .client
-            .post(&url)
-            .header("Authorization", auth_header)
-            .json(&payload)
-            .send()
-            .await?;
+        let response = send_with_retry(&self.retry, || async {
+            Ok(self
+                .client
+                .post(&url)
+                .header("Authorization", &auth_header)
+                .json(&payload)
+                .send()
+                .await?)
+        })
+        .await?;
 
         let status = response.status();
+        let reset = rate_limit_reset(response.headers());
         let payload: Option<Value> = response.json().await.ok();
 
         match status {
-            StatusCode::OK => payload.ok_or_else( || ProviderError::RequestFailed("Response body is not valid JSON".to_string()) ),
+            StatusCode::OK => payload.ok_or_else( || ProviderError::request_failed("Response body is not valid JSON".to_string()) ),
             StatusCode::UNAUTHORIZED | StatusCode::FORBIDDEN => {
-                Err(ProviderError::Authentication(format!("Authentication failed. Please ensure your API keys are valid and have the required permissions. \
-                    Status: {}. Response: {:?}", status, payload)))
+                Err(ProviderError::authentication(format!("Authentication failed. Please ensure your API keys are valid and have the required permissions. \
+                    Status: {}. Response: {:?}", status, payload)).with_key_of(&Self::metadata()))
             }
             StatusCode::BAD_REQUEST => {
                 // Databricks provides a generic 'error' but also includes 'external_model_message' which is provider specific
@@ -185,10 +195,10 @@ impl DatabricksProvider {
                 tracing::debug!(
                     "{}", format!("Provider request failed with status: {}. Payload: {:?}", status, payload)
                 );
-                Err(ProviderError::RequestFailed(format!("Request failed with status: {}. Message: {}", status, error_msg)))
+                Err(ProviderError::request_failed(format!("Request failed with status: {}. Message: {}", status, error_msg)))
             }
             StatusCode::TOO_MANY_REQUESTS => {
-                Err(ProviderError::RateLimitExceeded(format!("{:?}", payload)))
+                Err(ProviderError::rate_limited(format!("{:?}", payload)).with_retry_after(reset))
             }
             StatusCode::INTERNAL_SERVER_ERROR | StatusCode::SERVICE_UNAVAILABLE => {
                 Err(ProviderError::ServerError(format!("{:?}", payload)))
@@ -197,7 +207,7 @@ impl DatabricksProvider {
                 tracing::debug!(
                     "{}", format!("Provider request failed with status: {}. Payload: {:?}", status, payload)
                 );
-                Err(ProviderError::RequestFailed(format!("Request failed with status: {}", status)))
+                Err(ProviderError::request_failed(format!("Request failed with status: {}", status)))
             }
         }
     }
@@ -223,6 +233,10 @@ impl Provider for DatabricksProvider {
         )
     }
 
+    fn instance_metadata(&self) -> ProviderMetadata {
+        Self::metadata()
+    }
+
     fn get_model_config(&self) -> ModelConfig {
         self.model.clone()
     }
@@ -257,8 +271,12 @@ impl Provider for DatabricksProvider {
             Err(e) => return Err(e),
         };
         let model = get_model(&response);
-        super::utils::emit_debug_trace(self, &payload, &response, &usage);
+        super::utils::emit_debug_trace(self, messages, &payload, &response, &usage);
 
-        Ok((message, ProviderUsage::new(model, usage)))
+        Ok((
+            message,
+            ProviderUsage::new(model, usage)
+                .with_system_fingerprint(get_system_fingerprint(&response)),
+        ))
     }
 }
@@ -0,0 +1,253 @@
+use anyhow::Result;
+use async_trait::async_trait;
+use base64::Engine;
+use image::codecs::jpeg::JpegEncoder;
+use image::imageops::FilterType;
+use image::ImageFormat;
+use std::io::Cursor;
+
+use super::base::{MessageStream, Provider, ProviderMetadata, ProviderUsage, RateLimits};
+use super::errors::ProviderError;
+use crate::message::{Message, MessageContent};
+use crate::model::ModelConfig;
+use mcp_core::content::{Content, ImageContent};
+use mcp_core::tool::Tool;
+
+/// The JPEG quality for resized JPEG images when no quality is configured
+const DEFAULT_JPEG_QUALITY: u8 = 90;
+
+/// Resize an image so neither side exceeds `max_dimension`, keeping its aspect ratio
+///
+/// Returns `None` for images already within the limit. Resized images stay
+/// JPEG or become PNG, unless `jpeg_quality` is set, in which case they are
+/// re-encoded as JPEG at that quality (1-100) to shrink the payload further.
+pub fn downscale_image(
+    image: &ImageContent,
+    max_dimension: u32,
+    jpeg_quality: Option<u8>,
+) -> Result<Option<ImageContent>> {
+    let bytes = base64::engine::general_purpose::STANDARD.decode(&image.data)?;
+    let format = ImageFormat::from_mime_type(&image.mime_type)
+        .map_or_else(|| image::guess_format(&bytes), Ok)?;
+    let decoded = image::load_from_memory_with_format(&bytes, format)?;
+    if decoded.width() <= max_dimension && decoded.height() <= max_dimension {
+        return Ok(None);
+    }
+
+    let resized = decoded.resize(max_dimension, max_dimension, FilterType::Lanczos3);
+    let mut encoded = Cursor::new(Vec::new());
+    let mime_type = if jpeg_quality.is_some() || format == ImageFormat::Jpeg {
+        let quality = jpeg_quality.unwrap_or(DEFAULT_JPEG_QUALITY).clamp(1, 100);
+        // JPEG has no alpha channel
+        resized
+            .to_rgb8()
+            .write_with_encoder(JpegEncoder::new_with_quality(&mut encoded, quality))?;
+        "image/jpeg"
+    } else {
+        resized.write_to(&mut encoded, ImageFormat::Png)?;
+        "image/png"
+    };
+
+    Ok(Some(ImageContent {
+        data: base64::engine::general_purpose::STANDARD.encode(encoded.into_inner()),
+        mime_type: mime_type.to_string(),
+        annotations: image.annotations.clone(),
+    }))
+}
+
+/// A provider decorator that shrinks images larger than a maximum dimension
+///
+/// Vision models bill by image size and reject images past their pixel
+/// limits. Images in messages and in tool results are passed through
+/// `downscale_image` before the request is sent; images within the limit,
+/// and images that can not be decoded, are sent unchanged.
+pub struct ImageDownscalingProvider {
+    inner: Box<dyn Provider>,
+    max_dimension: u32,
+    jpeg_quality: Option<u8>,
+}
+
+impl ImageDownscalingProvider {
+    /// Fit every image within `max_dimension` pixels in both directions
+    pub fn new(inner: Box<dyn Provider>, max_dimension: u32) -> Self {
+        Self {
+            inner,
+            max_dimension,
+            jpeg_quality: None,
+        }
+    }
+
+    /// Re-encode resized images as JPEG at this quality, `None` keeps their format
+    pub fn with_jpeg_quality(mut self, jpeg_quality: Option<u8>) -> Self {
+        self.jpeg_quality = jpeg_quality;
+        self
+    }
+
+    fn downscale(&self, image: ImageContent) -> ImageContent {
+        match downscale_image(&image, self.max_dimension, self.jpeg_quality) {
+            Ok(Some(resized)) => resized,
+            Ok(None) => image,
+            Err(e) => {
+                tracing::warn!("Could not downscale {} image: {}", image.mime_type, e);
+                image
+            }
+        }
+    }
+
+    fn downscale_messages(&self, messages: &[Message]) -> Vec<Message> {
+        messages
+            .iter()
+            .cloned()
+            .map(|mut message| {
+                for content in message.content.iter_mut() {
+                    match content {
+                        MessageContent::Image(image) => {
+                            *image = self.downscale(image.clone());
+                        }
+                        MessageContent::ToolResponse(response) => {
+                            if let Ok(result) = response.tool_result.as_mut() {
+                                for item in result.iter_mut() {
+                                    if let Content::Image(image) = item {
+                                        *image = self.downscale(image.clone());
+                                    }
+                                }
+                            }
+                        }
+                        _ => {}
+                    }
+                }
+                message
+            })
+            .collect()
+    }
+}
+
+#[async_trait]
+impl Provider for ImageDownscalingProvider {
+    fn metadata() -> ProviderMetadata {
+        ProviderMetadata::empty()
+    }
+
+    fn instance_metadata(&self) -> ProviderMetadata {
+        self.inner.instance_metadata()
+    }
+
+    fn get_model_config(&self) -> ModelConfig {
+        self.inner.get_model_config()
+    }
+
+    fn format_system_prompt(&self, raw: &str) -> String {
+        self.inner.format_system_prompt(raw)
+    }
+
+    fn request_body(
+        &self,
+        system: &str,
+        messages: &[Message],
+        tools: &[Tool],
+    ) -> Result<serde_json::Value, ProviderError> {
+        let messages = self.downscale_messages(messages);
+        self.inner.request_body(system, &messages, tools)
+    }
+
+    fn count_request_tokens(&self, system: &str, messages: &[Message], tools: &[Tool]) -> usize {
+        self.inner.count_request_tokens(system, messages, tools)
+    }
+
+    async fn warmup(&self) -> Result<(), ProviderError> {
+        self.inner.warmup().await
+    }
+
+    async fn flush(&self) -> Result<(), ProviderError> {
+        self.inner.flush().await
+    }
+
+    async fn rate_limits(&self) -> Result<RateLimits, ProviderError> {
+        self.inner.rate_limits().await
+    }
+
+    async fn fetch_supported_models(&self) -> Result<Vec<String>, ProviderError> {
+        self.inner.fetch_supported_models().await
+    }
+
+    async fn embed(&self, inputs: &[String]) -> Result<Vec<Vec<f32>>, ProviderError> {
+        self.inner.embed(inputs).await
+    }
+
+    async fn complete(
+        &self,
+        system: &str,
+        messages: &[Message],
+        tools: &[Tool],
+    ) -> Result<(Message, ProviderUsage), ProviderError> {
+        let messages = self.downscale_messages(messages);
+        self.inner.complete(system, &messages, tools).await
+    }
+
+    async fn stream(
+        &self,
+        system: &str,
+        messages: &[Message],
+        tools: &[Tool],
+    ) -> Result<MessageStream, ProviderError> {
+        let messages = self.downscale_messages(messages);
+        self.inner.stream(system, &messages, tools).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn png(width: u32, height: u32) -> ImageContent {
+        let mut bytes = Cursor::new(Vec::new());
+        image::RgbaImage::new(width, height)
+            .write_to(&mut bytes, ImageFormat::Png)
+            .unwrap();
+        ImageContent {
+            data: base64::engine::general_purpose::STANDARD.encode(bytes.into_inner()),
+            mime_type: "image/png".to_string(),
+            annotations: None,
+        }
+    }
+
+    fn dimensions(image: &ImageContent) -> (u32, u32) {
+        let bytes = base64::engine::general_purpose::STANDARD
+            .decode(&image.data)
+            .unwrap();
+        let decoded = image::load_from_memory(&bytes).unwrap();
+        (decoded.width(), decoded.height())
+    }
+
+    #[test]
+    fn test_oversized_image_is_downscaled() {
+        let resized = downscale_image(&png(3000, 1500), 1024, None)
+            .unwrap()
+            .unwrap();
+        assert_eq!(dimensions(&resized), (1024, 512));
+        assert_eq!(resized.mime_type, "image/png");
+
+        let resized = downscale_image(&png(600, 2400), 1024, Some(70))
+            .unwrap()
+            .unwrap();
+        assert_eq!(dimensions(&resized), (256, 1024));
+        assert_eq!(resized.mime_type, "image/jpeg");
+    }
+
+    #[test]
+    fn test_small_image_is_unchanged() {
+        assert!(downscale_image(&png(1024, 800), 1024, Some(70))
+            .unwrap()
+            .is_none());
+        assert!(downscale_image(
+            &ImageContent {
+                data: "bm90IGFuIGltYWdl".to_string(),
+                mime_type: "image/png".to_string(),
+                annotations: None,
+            },
+            1024,
+            None
+        )
+        .is_err());
+    }
+}
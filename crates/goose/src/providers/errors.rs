@@ -1,27 +1,221 @@
+use super::base::{ProviderMetadata, Usage};
+use std::time::Duration;
 use thiserror::Error;
 
+/// Errors raised by providers
+///
+/// The display messages are written for end users and suggest what to do next,
+/// the raw details from the provider are kept in the variant for logs.
 #[derive(Error, Debug)]
 pub enum ProviderError {
-    #[error("Authentication error: {0}")]
-    Authentication(String),
+    /// The provider rejected the credentials, `key` names the config key that holds them
+    #[error("Authentication failed, check {}: {message}", key_hint(.key))]
+    Authentication {
+        message: String,
+        key: Option<String>,
+    },
 
-    #[error("Context length exceeded: {0}")]
+    #[error("The conversation is too long for the model's context window, try shortening the prompt or starting a new session: {0}")]
     ContextLengthExceeded(String),
 
-    #[error("Rate limit exceeded: {0}")]
-    RateLimitExceeded(String),
+    /// The provider is rate limiting, `retry_after` is how long it asked to wait when it said
+    #[error("Rate limit exceeded, wait a moment before retrying: {message}")]
+    RateLimitExceeded {
+        message: String,
+        retry_after: Option<Duration>,
+    },
 
-    #[error("Server error: {0}")]
+    #[error("The provider returned a server error, try again later: {0}")]
     ServerError(String),
 
-    #[error("Request failed: {0}")]
-    RequestFailed(String),
+    /// The provider rejected the request
+    ///
+    /// `status` is the HTTP status it answered with, `code` its own error code,
+    /// e.g. "invalid_request_error", and `request_id` the ID to quote when
+    /// reporting the failure to the provider. `partial_usage` holds any tokens
+    /// it still billed.
+    #[error("Request failed: {message}")]
+    RequestFailed {
+        message: String,
+        status: Option<u16>,
+        code: Option<String>,
+        request_id: Option<String>,
+        partial_usage: Option<Box<Usage>>,
+    },
+
+    /// The request could not be sent or its response not received, `source` has the cause
+    #[error(
+        "Could not reach the provider, check the network connection and provider URL: {message}"
+    )]
+    Http {
+        message: String,
+        #[source]
+        source: reqwest::Error,
+    },
+
+    /// No connection to the provider could be opened in time, `source` has the cause
+    #[error("Timed out connecting to the provider, check the network connection and provider URL: {message}")]
+    ConnectTimeout {
+        message: String,
+        #[source]
+        source: reqwest::Error,
+    },
+
+    /// The connection was opened but the response did not arrive in time, `source` has the cause
+    #[error(
+        "The provider took too long to respond, try again or raise the read timeout: {message}"
+    )]
+    ReadTimeout {
+        message: String,
+        #[source]
+        source: reqwest::Error,
+    },
+
+    /// The provider answered with a body that could not be parsed, `source` has the cause
+    #[error("The provider returned an unreadable response: {message}")]
+    InvalidResponse {
+        message: String,
+        #[source]
+        source: Box<dyn std::error::Error + Send + Sync>,
+    },
 
     #[error("Execution error: {0}")]
     ExecutionError(String),
 
-    #[error("Usage data error: {0}")]
+    #[error("Could not read usage data from the response: {0}")]
     UsageError(String),
+
+    #[error("The response stream disconnected, try again: {0}")]
+    StreamDisconnected(String),
+
+    #[error("Not supported by the selected provider or model: {0}")]
+    NotSupported(String),
+
+    #[error("The model took too long to start responding, try again or use a faster model: {0}")]
+    FirstTokenTimeout(String),
+
+    #[error("The provider is failing repeatedly, requests are paused: {0}")]
+    CircuitOpen(String),
+
+    #[error("The request was cancelled because goose is shutting down: {0}")]
+    ShuttingDown(String),
+
+    #[error("The model kept calling tools past the configured limit, ask for an answer without tools or start a new session: {0}")]
+    ToolIterationLimit(String),
+
+    #[error(
+        "The spend cap for this period is reached, raise the cap or wait until it resets: {0}"
+    )]
+    BudgetExceeded(String),
+
+    #[error("The request combines parameters the model does not accept: {0}")]
+    InvalidRequest(String),
+
+    #[error("The conversation pairs tool calls and tool responses incorrectly, check the agent loop: {0}")]
+    InvalidConversation(String),
+
+    /// The request holds more images than the model accepts, checked before sending
+    #[error("The request has {provided} images but the model accepts at most {limit}, send fewer images or split the request")]
+    TooManyImages { limit: usize, provided: usize },
+
+    /// The backend does not know the requested model, `suggestions` holds close known names
+    #[error("The model '{requested}' was not found{}", suggestion_hint(.suggestions))]
+    ModelNotFound {
+        requested: String,
+        suggestions: Vec<String>,
+    },
+
+    /// A stream failed after producing output, `partial` holds the text received so far
+    /// and `partial_usage` the usage, if the provider reported it before failing
+    #[error("The response stream was interrupted, the output received so far is kept: {reason}")]
+    StreamInterrupted {
+        reason: String,
+        partial: String,
+        partial_usage: Option<Box<Usage>>,
+    },
+}
+
+impl ProviderError {
+    /// The raw details reported by the provider, without the user facing guidance
+    pub fn details(&self) -> &str {
+        match self {
+            ProviderError::Authentication {
+                message: details, ..
+            }
+            | ProviderError::ContextLengthExceeded(details)
+            | ProviderError::RateLimitExceeded {
+                message: details, ..
+            }
+            | ProviderError::ServerError(details)
+            | ProviderError::RequestFailed {
+                message: details, ..
+            }
+            | ProviderError::ExecutionError(details)
+            | ProviderError::UsageError(details)
+            | ProviderError::StreamDisconnected(details)
+            | ProviderError::NotSupported(details)
+            | ProviderError::FirstTokenTimeout(details)
+            | ProviderError::CircuitOpen(details)
+            | ProviderError::ShuttingDown(details)
+            | ProviderError::ToolIterationLimit(details)
+            | ProviderError::BudgetExceeded(details)
+            | ProviderError::InvalidRequest(details)
+            | ProviderError::InvalidConversation(details) => details,
+            ProviderError::StreamInterrupted { reason, .. } => reason,
+            ProviderError::ModelNotFound { requested, .. } => requested,
+            ProviderError::TooManyImages { .. } => "too many images in the request",
+            ProviderError::Http { message, .. }
+            | ProviderError::ConnectTimeout { message, .. }
+            | ProviderError::ReadTimeout { message, .. }
+            | ProviderError::InvalidResponse { message, .. } => message,
+        }
+    }
+
+    /// The HTTP status a server in front of goose should answer with for this error
+    ///
+    /// Problems with the request map to 4xx codes, failures of the upstream
+    /// provider to 502, timeouts to 504 and paused or stopping providers to 503.
+    pub fn http_status(&self) -> u16 {
+        match self {
+            ProviderError::Authentication { .. } => 401,
+            ProviderError::BudgetExceeded(_) => 402,
+            ProviderError::ModelNotFound { .. } => 404,
+            ProviderError::ToolIterationLimit(_) => 422,
+            ProviderError::RateLimitExceeded { .. } => 429,
+            ProviderError::ContextLengthExceeded(_)
+            | ProviderError::RequestFailed { .. }
+            | ProviderError::NotSupported(_)
+            | ProviderError::InvalidRequest(_)
+            | ProviderError::InvalidConversation(_)
+            | ProviderError::TooManyImages { .. } => 400,
+            ProviderError::ExecutionError(_) => 500,
+            ProviderError::ServerError(_)
+            | ProviderError::Http { .. }
+            | ProviderError::InvalidResponse { .. }
+            | ProviderError::UsageError(_)
+            | ProviderError::StreamDisconnected(_)
+            | ProviderError::StreamInterrupted { .. } => 502,
+            ProviderError::CircuitOpen(_) | ProviderError::ShuttingDown(_) => 503,
+            ProviderError::ConnectTimeout { .. }
+            | ProviderError::ReadTimeout { .. }
+            | ProviderError::FirstTokenTimeout(_) => 504,
+        }
+    }
+}
+
+fn key_hint(key: &Option<String>) -> String {
+    match key {
+        Some(key) => format!("that {} is set and valid", key),
+        None => "that the provider's credentials are set and valid".to_string(),
+    }
+}
+
+fn suggestion_hint(suggestions: &[String]) -> String {
+    if suggestions.is_empty() {
+        ", check the model name".to_string()
+    } else {
+        format!(", did you mean {}?", suggestions.join(" or "))
+    }
 }
 
 impl From<anyhow::Error> for ProviderError {
@@ -32,6 +226,373 @@ impl From<anyhow::Error> for ProviderError {
 
 impl From<reqwest::Error> for ProviderError {
     fn from(error: reqwest::Error) -> Self {
-        ProviderError::ExecutionError(error.to_string())
+        let message = error.to_string();
+        match (error.is_timeout(), error.is_connect()) {
+            (true, true) => ProviderError::ConnectTimeout {
+                message,
+                source: error,
+            },
+            (true, false) => ProviderError::ReadTimeout {
+                message,
+                source: error,
+            },
+            _ => ProviderError::Http {
+                message,
+                source: error,
+            },
+        }
+    }
+}
+
+impl ProviderError {
+    /// An authentication error, without a known config key
+    pub fn authentication(message: impl Into<String>) -> Self {
+        ProviderError::Authentication {
+            message: message.into(),
+            key: None,
+        }
+    }
+
+    /// Name the API key of the provider described by `metadata` in an authentication error
+    ///
+    /// The key is the provider's required secret config key, or its first
+    /// secret one. Other errors are returned as they are.
+    pub fn with_key_of(self, metadata: &ProviderMetadata) -> Self {
+        match self {
+            ProviderError::Authentication { message, key: None } => {
+                let secrets = || metadata.config_keys.iter().filter(|key| key.secret);
+                let key = secrets()
+                    .find(|key| key.required)
+                    .or_else(|| secrets().next())
+                    .map(|key| key.name.clone());
+                ProviderError::Authentication { message, key }
+            }
+            error => error,
+        }
+    }
+
+    /// A rate limit error, without a known wait
+    pub fn rate_limited(message: impl Into<String>) -> Self {
+        ProviderError::RateLimitExceeded {
+            message: message.into(),
+            retry_after: None,
+        }
+    }
+
+    /// Attach the wait a rate limited provider asked for
+    pub fn with_retry_after(mut self, retry_after: Option<Duration>) -> Self {
+        if let ProviderError::RateLimitExceeded {
+            retry_after: field, ..
+        } = &mut self
+        {
+            *field = retry_after;
+        }
+        self
+    }
+
+    /// How long a rate limited provider asked to wait, when it said
+    pub fn retry_after(&self) -> Option<Duration> {
+        match self {
+            ProviderError::RateLimitExceeded { retry_after, .. } => *retry_after,
+            _ => None,
+        }
+    }
+
+    /// A rejected request, without any usage reported
+    pub fn request_failed(message: impl Into<String>) -> Self {
+        ProviderError::RequestFailed {
+            message: message.into(),
+            status: None,
+            code: None,
+            request_id: None,
+            partial_usage: None,
+        }
+    }
+
+    /// Attach the HTTP status of a rejected request
+    pub fn with_status(mut self, status: u16) -> Self {
+        if let ProviderError::RequestFailed { status: field, .. } = &mut self {
+            *field = Some(status);
+        }
+        self
+    }
+
+    /// Attach the provider's error code of a rejected request
+    pub fn with_code(mut self, code: Option<String>) -> Self {
+        if let ProviderError::RequestFailed { code: field, .. } = &mut self {
+            *field = code;
+        }
+        self
+    }
+
+    /// Attach the ID the provider gave a rejected request
+    pub fn with_request_id(mut self, request_id: Option<String>) -> Self {
+        if let ProviderError::RequestFailed {
+            request_id: field, ..
+        } = &mut self
+        {
+            *field = request_id;
+        }
+        self
+    }
+
+    /// The HTTP status the provider answered a rejected request with, when it is known
+    pub fn status(&self) -> Option<u16> {
+        match self {
+            ProviderError::RequestFailed { status, .. } => *status,
+            _ => None,
+        }
+    }
+
+    /// The provider's own code for a rejected request, when it sent one
+    pub fn code(&self) -> Option<&str> {
+        match self {
+            ProviderError::RequestFailed { code, .. } => code.as_deref(),
+            _ => None,
+        }
+    }
+
+    /// The ID the provider gave a rejected request, when it sent one
+    pub fn request_id(&self) -> Option<&str> {
+        match self {
+            ProviderError::RequestFailed { request_id, .. } => request_id.as_deref(),
+            _ => None,
+        }
+    }
+
+    /// Whether sending the same request again later may succeed
+    ///
+    /// This says nothing about whether a retry is free, a request that timed out
+    /// may have been processed and billed; `send_with_retry` only retries
+    /// failures where the request never reached the provider.
+    pub fn is_retryable(&self) -> bool {
+        match self {
+            ProviderError::RateLimitExceeded { .. }
+            | ProviderError::ServerError(_)
+            | ProviderError::Http { .. }
+            | ProviderError::ConnectTimeout { .. }
+            | ProviderError::ReadTimeout { .. }
+            | ProviderError::StreamDisconnected(_)
+            | ProviderError::StreamInterrupted { .. }
+            | ProviderError::FirstTokenTimeout(_) => true,
+            ProviderError::RequestFailed { status, .. } => {
+                status.is_some_and(|status| matches!(status, 408 | 409 | 429 | 500..=599))
+            }
+            _ => false,
+        }
+    }
+
+    /// Attach the usage a failed request was still billed for, where the variant can carry it
+    ///
+    /// Only `RequestFailed` and `StreamInterrupted` carry usage. The other
+    /// variants, e.g. a `ServerError` or `RateLimitExceeded` from a stream that
+    /// failed before any text, drop it with a warning, so cost tracking misses
+    /// those tokens.
+    pub fn with_partial_usage(mut self, usage: Option<Usage>) -> Self {
+        match &mut self {
+            ProviderError::RequestFailed { partial_usage, .. }
+            | ProviderError::StreamInterrupted { partial_usage, .. } => {
+                *partial_usage = usage.map(Box::new);
+            }
+            _ => {
+                if let Some(usage) = usage {
+                    tracing::warn!(?usage, "Dropping the usage of a failed request: {}", self);
+                }
+            }
+        }
+        self
+    }
+
+    /// The tokens billed for a request that failed late, for cost tracking
+    pub fn partial_usage(&self) -> Option<&Usage> {
+        match self {
+            ProviderError::RequestFailed { partial_usage, .. }
+            | ProviderError::StreamInterrupted { partial_usage, .. } => partial_usage.as_deref(),
+            _ => None,
+        }
+    }
+
+    /// An unparseable response, keeping the parse error as the source
+    pub fn invalid_response(
+        message: impl Into<String>,
+        source: impl Into<Box<dyn std::error::Error + Send + Sync>>,
+    ) -> Self {
+        ProviderError::InvalidResponse {
+            message: message.into(),
+            source: source.into(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_display_is_actionable() {
+        let cases = [
+            (ProviderError::authentication("401"), "credentials"),
+            (ProviderError::rate_limited("429"), "wait"),
+            (
+                ProviderError::ContextLengthExceeded("too long".into()),
+                "shortening the prompt",
+            ),
+            (ProviderError::ServerError("500".into()), "try again later"),
+            (
+                ProviderError::ModelNotFound {
+                    requested: "gpt-4-o".into(),
+                    suggestions: vec!["gpt-4o".into()],
+                },
+                "did you mean gpt-4o?",
+            ),
+        ];
+        for (error, phrase) in cases {
+            assert!(error.to_string().contains(phrase), "{}", error);
+        }
+    }
+
+    #[test]
+    fn test_authentication_names_the_key_of_the_provider() {
+        use crate::providers::base::Provider;
+        use crate::providers::{anthropic::AnthropicProvider, databricks::DatabricksProvider};
+
+        let error =
+            ProviderError::authentication("401").with_key_of(&AnthropicProvider::metadata());
+        assert!(error
+            .to_string()
+            .contains("check that ANTHROPIC_API_KEY is set"));
+        assert_eq!(error.details(), "401");
+
+        // Without a required secret the first secret key is named
+        let error =
+            ProviderError::authentication("401").with_key_of(&DatabricksProvider::metadata());
+        assert!(error.to_string().contains("DATABRICKS_TOKEN"));
+
+        let error =
+            ProviderError::ServerError("500".into()).with_key_of(&AnthropicProvider::metadata());
+        assert!(matches!(error, ProviderError::ServerError(_)));
+    }
+
+    #[test]
+    fn test_parse_failure_keeps_source() {
+        let parse_error = serde_json::from_str::<serde_json::Value>("{\"usage\": ").unwrap_err();
+        let error = ProviderError::invalid_response("Invalid stream chunk", parse_error);
+
+        let source = std::error::Error::source(&error).expect("source should be set");
+        assert!(source.to_string().contains("EOF while parsing"));
+        assert_eq!(error.details(), "Invalid stream chunk");
+
+        // The full chain is visible through anyhow's alternate formatting
+        let chained = format!("{:#}", anyhow::Error::new(error));
+        assert!(chained.contains("Invalid stream chunk: EOF while parsing"));
+    }
+
+    #[test]
+    fn test_details_keep_raw_message() {
+        let error = ProviderError::authentication("invalid x-api-key");
+        assert_eq!(error.details(), "invalid x-api-key");
+        assert!(error.to_string().ends_with("invalid x-api-key"));
+    }
+
+    #[test]
+    fn test_http_status() {
+        let reqwest_error = || reqwest::Client::new().get("not a url").build().unwrap_err();
+        let text = || "details".to_string();
+        let cases = [
+            (ProviderError::authentication(text()), 401),
+            (ProviderError::ContextLengthExceeded(text()), 400),
+            (ProviderError::rate_limited(text()), 429),
+            (ProviderError::ServerError(text()), 502),
+            (ProviderError::request_failed(text()), 400),
+            (
+                ProviderError::Http {
+                    message: text(),
+                    source: reqwest_error(),
+                },
+                502,
+            ),
+            (
+                ProviderError::ConnectTimeout {
+                    message: text(),
+                    source: reqwest_error(),
+                },
+                504,
+            ),
+            (
+                ProviderError::ReadTimeout {
+                    message: text(),
+                    source: reqwest_error(),
+                },
+                504,
+            ),
+            (ProviderError::invalid_response(text(), text()), 502),
+            (ProviderError::ExecutionError(text()), 500),
+            (ProviderError::UsageError(text()), 502),
+            (ProviderError::StreamDisconnected(text()), 502),
+            (ProviderError::NotSupported(text()), 400),
+            (ProviderError::FirstTokenTimeout(text()), 504),
+            (ProviderError::CircuitOpen(text()), 503),
+            (ProviderError::ShuttingDown(text()), 503),
+            (ProviderError::ToolIterationLimit(text()), 422),
+            (ProviderError::BudgetExceeded(text()), 402),
+            (ProviderError::InvalidRequest(text()), 400),
+            (
+                ProviderError::ModelNotFound {
+                    requested: text(),
+                    suggestions: vec![],
+                },
+                404,
+            ),
+            (
+                ProviderError::StreamInterrupted {
+                    reason: text(),
+                    partial: text(),
+                    partial_usage: None,
+                },
+                502,
+            ),
+        ];
+        for (error, status) in cases {
+            assert_eq!(error.http_status(), status, "{:?}", error);
+        }
+    }
+
+    #[test]
+    fn test_request_failed_keeps_status_and_code() {
+        let error = ProviderError::request_failed("Invalid value for temperature")
+            .with_status(400)
+            .with_code(Some("invalid_request_error".into()))
+            .with_request_id(Some("req_123".into()));
+        assert_eq!(error.status(), Some(400));
+        assert_eq!(error.code(), Some("invalid_request_error"));
+        assert_eq!(error.request_id(), Some("req_123"));
+        assert!(!error.is_retryable());
+
+        // A conflict or an unexpected server status clears up on its own
+        assert!(ProviderError::request_failed("conflict")
+            .with_status(409)
+            .is_retryable());
+        assert!(ProviderError::request_failed("bad gateway")
+            .with_status(502)
+            .is_retryable());
+        assert!(!ProviderError::request_failed("unknown").is_retryable());
+
+        assert!(ProviderError::rate_limited("429").is_retryable());
+        assert!(!ProviderError::authentication("401").is_retryable());
+        assert!(!ProviderError::ContextLengthExceeded("too long".into()).is_retryable());
+        // Only rejected requests carry a status of their own
+        assert_eq!(ProviderError::ServerError("500".into()).status(), None);
+    }
+
+    #[test]
+    fn test_partial_usage() {
+        let usage = Usage::new(Some(25), None, None);
+        let error =
+            ProviderError::request_failed("cut off").with_partial_usage(Some(usage.clone()));
+        assert_eq!(error.partial_usage().unwrap().input_tokens, Some(25));
+
+        // Variants without a place for it drop the usage
+        let error = ProviderError::ServerError("overloaded".into()).with_partial_usage(Some(usage));
+        assert!(error.partial_usage().is_none());
     }
 }
@@ -1,39 +1,585 @@
 use super::{
     anthropic::AnthropicProvider,
+    azure::AzureProvider,
     base::{Provider, ProviderMetadata},
+    bedrock::BedrockProvider,
+    budget::{BudgetWindow, BudgetedProvider},
     databricks::DatabricksProvider,
+    fallback::{FallbackConfig, FallbackProvider},
     google::GoogleProvider,
     groq::GroqProvider,
+    health::HealthCheck,
     ollama::OllamaProvider,
     omg::OmgProvider,
     openai::OpenAiProvider,
     openrouter::OpenRouterProvider,
+    pricing::{extend_pricing, ModelPricing},
+    rate_limit::{RateLimitConfig, RateLimitedProvider, RateLimiter},
 };
+use crate::config::Config;
 use crate::model::ModelConfig;
 use anyhow::Result;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::sync::{Arc, OnceLock, RwLock};
+use std::time::Duration;
+
+/// Config key with the deployment wide mapping from requested to permitted models
+const MODEL_REMAP_KEY: &str = "GOOSE_MODEL_REMAP";
+/// Config key with prices that override the built-in ones, mapping model names to `ModelPricing`
+const PRICING_KEY: &str = "GOOSE_PRICING";
+/// Config key with the most US dollars a session may spend before requests are refused
+const SESSION_BUDGET_KEY: &str = "GOOSE_SESSION_BUDGET";
+/// Config key with the client side rate limits, mapping provider names to `RateLimitConfig`
+const RATE_LIMITS_KEY: &str = "GOOSE_RATE_LIMITS";
+/// Config key with the providers to fail over to, a list of `FallbackConfig`
+const FALLBACKS_KEY: &str = "GOOSE_PROVIDER_FALLBACKS";
+
+type ProviderConstructor =
+    Arc<dyn Fn(ModelConfig) -> Result<Box<dyn Provider + Send + Sync>> + Send + Sync>;
+
+/// Providers added at runtime by `register_provider`, by name
+static PROVIDER_REGISTRY: OnceLock<
+    RwLock<HashMap<String, (ProviderMetadata, ProviderConstructor)>>,
+> = OnceLock::new();
+
+fn registry() -> &'static RwLock<HashMap<String, (ProviderMetadata, ProviderConstructor)>> {
+    PROVIDER_REGISTRY.get_or_init(|| RwLock::new(HashMap::new()))
+}
+
+/// Register a provider implemented outside this crate under `metadata.name`
+///
+/// It is listed by `providers` and built by `create` like the built-in ones,
+/// including the model remap and session budget. A provider registered under
+/// the name of a built-in one replaces it, registering a name again replaces
+/// the earlier registration.
+pub fn register_provider(
+    metadata: ProviderMetadata,
+    constructor: impl Fn(ModelConfig) -> Result<Box<dyn Provider + Send + Sync>> + Send + Sync + 'static,
+) {
+    if let Ok(mut map) = registry().write() {
+        map.insert(metadata.name.clone(), (metadata, Arc::new(constructor)));
+    }
+}
+
+/// The registered constructor for `name`, cloned so the lock is not held while it runs
+fn registered(name: &str) -> Option<ProviderConstructor> {
+    let map = registry().read().ok()?;
+    map.get(name).map(|(_, constructor)| constructor.clone())
+}
 
 pub fn providers() -> Vec<ProviderMetadata> {
-    vec![
+    let mut providers = vec![
         AnthropicProvider::metadata(),
+        AzureProvider::metadata(),
+        BedrockProvider::metadata(),
         DatabricksProvider::metadata(),
         GoogleProvider::metadata(),
         GroqProvider::metadata(),
         OllamaProvider::metadata(),
         OpenAiProvider::metadata(),
         OpenRouterProvider::metadata(),
-    ]
+    ];
+    if let Ok(map) = registry().read() {
+        providers.retain(|metadata| !map.contains_key(&metadata.name));
+        let mut custom: Vec<_> = map.values().map(|(metadata, _)| metadata.clone()).collect();
+        custom.sort_by(|a, b| a.name.cmp(&b.name));
+        providers.extend(custom);
+    }
+    providers
+}
+
+/// How long `supported_models` waits for a provider to answer
+const PING_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Whether a provider is ready to use, as reported by `status`
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ProviderStatus {
+    pub name: String,
+    pub display_name: String,
+    /// `GOOSE_MODEL` for the configured provider, the default model for the others
+    pub model: String,
+    /// Required config keys that have neither a value nor a default
+    pub missing_keys: Vec<String>,
+    /// Whether a test request succeeded, only checked by `status_live`
+    pub reachable: Option<bool>,
+    /// Why the test request failed
+    pub error: Option<String>,
+    /// The latency, credentials and model found by the test request
+    pub health: Option<HealthCheck>,
+}
+
+impl ProviderStatus {
+    /// All required config keys are present
+    pub fn is_configured(&self) -> bool {
+        self.missing_keys.is_empty()
+    }
+}
+
+/// The readiness of every provider, from the config alone without network requests
+pub fn status() -> Vec<ProviderStatus> {
+    status_from(providers(), Config::global())
+}
+
+/// Like `status`, additionally sending a minimal request to every configured provider
+pub async fn status_live() -> Vec<ProviderStatus> {
+    let mut statuses = status();
+    for status in statuses.iter_mut().filter(|s| s.is_configured()) {
+        let health = health_check(&status.name, &status.model).await;
+        status.reachable = Some(health.is_healthy());
+        status.error = health.error.clone();
+        status.health = Some(health);
+    }
+    statuses
+}
+
+fn status_from(metadata: Vec<ProviderMetadata>, config: &Config) -> Vec<ProviderStatus> {
+    let configured_provider: Option<String> = config.get("GOOSE_PROVIDER").ok();
+    let configured_model: Option<String> = config.get("GOOSE_MODEL").ok();
+    metadata
+        .into_iter()
+        .map(|meta| {
+            let missing_keys = meta
+                .config_keys
+                .iter()
+                .filter(|key| key.required && key.default.is_none())
+                .filter(|key| {
+                    let value: Result<serde_json::Value, _> = if key.secret {
+                        config.get_secret(&key.name)
+                    } else {
+                        config.get(&key.name)
+                    };
+                    value.is_err()
+                })
+                .map(|key| key.name.clone())
+                .collect();
+            let model = match &configured_model {
+                Some(model) if configured_provider.as_deref() == Some(meta.name.as_str()) => {
+                    model.clone()
+                }
+                _ => meta.default_model.clone(),
+            };
+            ProviderStatus {
+                name: meta.name,
+                display_name: meta.display_name,
+                model,
+                missing_keys,
+                reachable: None,
+                error: None,
+                health: None,
+            }
+        })
+        .collect()
+}
+
+/// The models the named provider offers, for choosing one during configuration
+///
+/// Asks the provider's models endpoint, see `Provider::fetch_supported_models`,
+/// and falls back to the known models in its metadata when the provider can
+/// not be created yet, has no such endpoint or the request fails.
+pub async fn supported_models(name: &str) -> Vec<String> {
+    // The OMG gateway can be created by name but is not in the list offered for setup
+    let Some(metadata) = providers()
+        .into_iter()
+        .chain(std::iter::once(OmgProvider::metadata()))
+        .find(|metadata| metadata.name == name)
+    else {
+        return Vec::new();
+    };
+    let fetched = match create(name, ModelConfig::new(metadata.default_model.clone())) {
+        Ok(provider) => tokio::time::timeout(PING_TIMEOUT, provider.fetch_supported_models())
+            .await
+            .map_err(|_| anyhow::anyhow!("No answer within {}s", PING_TIMEOUT.as_secs()))
+            .and_then(|result| Ok(result?)),
+        Err(e) => Err(e),
+    };
+    match fetched {
+        Ok(models) if !models.is_empty() => models,
+        Ok(_) => metadata.known_models,
+        Err(e) => {
+            tracing::debug!("Could not list the models of {}: {}", name, e);
+            metadata.known_models
+        }
+    }
+}
+
+/// Check the named provider with `Provider::health_check`, capped at a single output token
+pub async fn health_check(name: &str, model: &str) -> HealthCheck {
+    match create(
+        name,
+        ModelConfig::new(model.to_string()).with_max_tokens(Some(1)),
+    ) {
+        Ok(provider) => provider.health_check().await,
+        Err(e) => HealthCheck::not_created(model.to_string(), e.to_string()),
+    }
 }
 
+/// Create the named provider for the model, after applying the configured model remap
+///
+/// `GOOSE_MODEL_REMAP` maps requested model names to the ones the account may
+/// use, e.g. `{"gpt-4*": "gpt-4o"}`. See `remap_model` for how names match.
+/// With `GOOSE_PROVIDER_FALLBACKS` set, see `with_fallbacks`, with
+/// `GOOSE_RATE_LIMITS` set, see `with_rate_limit`, and with
+/// `GOOSE_SESSION_BUDGET` set, see `with_session_budget`. With the `otel`
+/// feature every request is traced and counted.
 pub fn create(name: &str, model: ModelConfig) -> Result<Box<dyn Provider + Send + Sync>> {
-    match name {
-        "openai" => Ok(Box::new(OpenAiProvider::from_env(model)?)),
-        "anthropic" => Ok(Box::new(AnthropicProvider::from_env(model)?)),
-        "databricks" => Ok(Box::new(DatabricksProvider::from_env(model)?)),
-        "groq" => Ok(Box::new(GroqProvider::from_env(model)?)),
-        "ollama" => Ok(Box::new(OllamaProvider::from_env(model)?)),
-        "openrouter" => Ok(Box::new(OpenRouterProvider::from_env(model)?)),
-        "google" => Ok(Box::new(GoogleProvider::from_env(model)?)),
-        "omg" => Ok(Box::new(OmgProvider::from_env(model)?)),
-        _ => Err(anyhow::anyhow!("Unknown provider: {}", name)),
+    create_from(name, model, Config::global())
+}
+
+fn create_from(
+    name: &str,
+    model: ModelConfig,
+    config: &Config,
+) -> Result<Box<dyn Provider + Send + Sync>> {
+    let remap: HashMap<String, String> = config.get(MODEL_REMAP_KEY).unwrap_or_default();
+    let primary = create_backend(name, remap_model(model.clone(), &remap), config)?;
+    let provider = with_fallbacks(primary, model, &remap, config)?;
+    Ok(with_telemetry(with_session_budget(provider, config)))
+}
+
+/// The named provider on its own, within the rate limits configured for it
+fn create_backend(
+    name: &str,
+    model: ModelConfig,
+    config: &Config,
+) -> Result<Box<dyn Provider + Send + Sync>> {
+    let provider: Box<dyn Provider + Send + Sync> = match registered(name) {
+        Some(constructor) => constructor(model)?,
+        None => match name {
+            "openai" => Box::new(OpenAiProvider::from_env(model)?),
+            "anthropic" => Box::new(AnthropicProvider::from_env(model)?),
+            "azure_openai" => Box::new(AzureProvider::from_env(model)?),
+            "bedrock" => Box::new(BedrockProvider::from_env(model)?),
+            "databricks" => Box::new(DatabricksProvider::from_env(model)?),
+            "groq" => Box::new(GroqProvider::from_env(model)?),
+            "ollama" => Box::new(OllamaProvider::from_env(model)?),
+            "openrouter" => Box::new(OpenRouterProvider::from_env(model)?),
+            "google" => Box::new(GoogleProvider::from_env(model)?),
+            "omg" => Box::new(OmgProvider::from_env(model)?),
+            _ => return Err(anyhow::anyhow!("Unknown provider: {}", name)),
+        },
+    };
+    Ok(with_rate_limit(name, provider, config))
+}
+
+/// Fail over from `primary` to the providers in `GOOSE_PROVIDER_FALLBACKS`, in order
+///
+/// Each entry names a provider and optionally the model to use there, e.g.
+/// `[{"provider": "anthropic", "model": "claude-3-5-sonnet-latest"}, {"provider": "openai"}]`.
+/// Without a model the provider's default model is used, with the other
+/// settings of `model`. The model remap applies to the fallbacks as well.
+/// Without fallbacks the primary is returned as is.
+fn with_fallbacks(
+    primary: Box<dyn Provider + Send + Sync>,
+    model: ModelConfig,
+    remap: &HashMap<String, String>,
+    config: &Config,
+) -> Result<Box<dyn Provider + Send + Sync>> {
+    let fallbacks: Vec<FallbackConfig> = config.get(FALLBACKS_KEY).unwrap_or_default();
+    if fallbacks.is_empty() {
+        return Ok(primary);
+    }
+    let mut chain: Vec<Box<dyn Provider>> = vec![primary];
+    for fallback in fallbacks {
+        let model_name = match fallback.model {
+            Some(model_name) => model_name,
+            None => providers()
+                .into_iter()
+                .find(|metadata| metadata.name == fallback.provider)
+                .map(|metadata| metadata.default_model)
+                .ok_or_else(|| anyhow::anyhow!("Unknown provider: {}", fallback.provider))?,
+        };
+        let model = remap_model(with_model_name(model.clone(), &model_name), remap);
+        chain.push(create_backend(&fallback.provider, model, config)?);
+    }
+    Ok(Box::new(FallbackProvider::new(chain)?))
+}
+
+/// Keep requests within the client side limits configured for the provider
+///
+/// `GOOSE_RATE_LIMITS` maps provider names to their limits, e.g.
+/// `{"openai": {"requests_per_minute": 500, "tokens_per_minute": 30000, "max_concurrent": 4}}`.
+/// Every provider of a name created in the process shares one `RateLimiter`.
+/// Without limits for the name the provider is returned as is.
+fn with_rate_limit(
+    name: &str,
+    provider: Box<dyn Provider + Send + Sync>,
+    config: &Config,
+) -> Box<dyn Provider + Send + Sync> {
+    let mut limits: HashMap<String, RateLimitConfig> =
+        config.get(RATE_LIMITS_KEY).unwrap_or_default();
+    match limits.remove(name).filter(|limits| !limits.is_empty()) {
+        Some(limits) => Box::new(RateLimitedProvider::new(
+            provider,
+            RateLimiter::shared(name, limits),
+        )),
+        None => provider,
+    }
+}
+
+/// Trace and count every request, see `OtelProvider`
+#[cfg(feature = "otel")]
+fn with_telemetry(provider: Box<dyn Provider + Send + Sync>) -> Box<dyn Provider + Send + Sync> {
+    Box::new(super::otel::OtelProvider::new(provider))
+}
+
+#[cfg(not(feature = "otel"))]
+fn with_telemetry(provider: Box<dyn Provider + Send + Sync>) -> Box<dyn Provider + Send + Sync> {
+    provider
+}
+
+/// Refuse new requests once the session has spent `GOOSE_SESSION_BUDGET` US dollars
+///
+/// The cost of each response comes from the pricing table, where
+/// `GOOSE_PRICING` overrides the built-in prices, e.g.
+/// `{"gpt-4o": {"input_per_million": 2.0, "output_per_million": 8.0}}`.
+/// Without a budget the provider is returned as is.
+fn with_session_budget(
+    provider: Box<dyn Provider + Send + Sync>,
+    config: &Config,
+) -> Box<dyn Provider + Send + Sync> {
+    let pricing: HashMap<String, ModelPricing> = config.get(PRICING_KEY).unwrap_or_default();
+    extend_pricing(pricing);
+    match config.get::<f64>(SESSION_BUDGET_KEY) {
+        Ok(cap) => {
+            Box::new(BudgetedProvider::new(provider, cap).with_window(BudgetWindow::Session))
+        }
+        Err(_) => provider,
+    }
+}
+
+/// Replace the requested model according to `remap`, keeping the other settings
+///
+/// A key matches the model name exactly, or as a prefix when it ends with `*`.
+/// An exact match wins over prefixes and the longest prefix wins over shorter
+/// ones. The tokenizer and known context limit follow the permitted model.
+fn remap_model(model: ModelConfig, remap: &HashMap<String, String>) -> ModelConfig {
+    let name = model.model_name.as_str();
+    let target = remap.get(name).or_else(|| {
+        remap
+            .iter()
+            .filter_map(|(key, target)| Some((key.strip_suffix('*')?, target)))
+            .filter(|(prefix, _)| name.starts_with(prefix))
+            .max_by_key(|(prefix, _)| prefix.len())
+            .map(|(_, target)| target)
+    });
+    let Some(target) = target.filter(|target| target.as_str() != name) else {
+        return model;
+    };
+
+    tracing::info!("Remapping model {} to {}", name, target);
+    let target = target.clone();
+    with_model_name(model, &target)
+}
+
+/// `model` with another model name, the tokenizer and known context limit follow the name
+fn with_model_name(model: ModelConfig, name: &str) -> ModelConfig {
+    let renamed = ModelConfig::new(name.to_string());
+    ModelConfig {
+        context_limit: renamed.context_limit.or(model.context_limit),
+        model_name: renamed.model_name,
+        tokenizer_name: renamed.tokenizer_name,
+        ..model
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::CLAUDE_TOKENIZER;
+    use crate::providers::base::{ConfigKey, Usage};
+    use crate::providers::errors::ProviderError;
+    use crate::providers::testing::TestProvider;
+    use serde_json::{json, Value};
+
+    #[test]
+    fn test_remap_model() {
+        let remap = HashMap::from([
+            ("gpt-4*".to_string(), "gpt-4o".to_string()),
+            ("gpt-4-32k*".to_string(), "gpt-4o-mini".to_string()),
+            ("claude-2".to_string(), "claude-3-5-sonnet".to_string()),
+        ]);
+        let remapped = |name: &str| remap_model(ModelConfig::new(name.to_string()), &remap);
+
+        assert_eq!(remapped("gpt-4-turbo").model_name, "gpt-4o");
+        assert_eq!(remapped("gpt-4-32k-0613").model_name, "gpt-4o-mini");
+        assert_eq!(remapped("gpt-4o").model_name, "gpt-4o");
+        assert_eq!(remapped("o1").model_name, "o1");
+        // Exact keys must match the whole name
+        assert_eq!(remapped("claude-2.1").model_name, "claude-2.1");
+
+        let model = ModelConfig::new("claude-2".to_string()).with_temperature(Some(0.3));
+        let model = remap_model(model, &remap);
+        assert_eq!(model.model_name, "claude-3-5-sonnet");
+        assert_eq!(model.tokenizer_name(), CLAUDE_TOKENIZER);
+        assert_eq!(model.context_limit(), 200_000);
+        assert_eq!(model.temperature, Some(0.3));
+    }
+
+    #[tokio::test]
+    async fn test_supported_models_fall_back_to_known_models() {
+        // Ollama has no models listing, so its known models are offered
+        assert_eq!(
+            supported_models("ollama").await,
+            OllamaProvider::metadata().known_models
+        );
+        assert!(supported_models("no-such-provider").await.is_empty());
+    }
+
+    #[test]
+    fn test_status_reports_missing_keys() {
+        let file = tempfile::NamedTempFile::new().unwrap();
+        let config = Config::new(file.path(), "goose-status-test").unwrap();
+        let metadata = || {
+            vec![
+                ProviderMetadata::new(
+                    "local",
+                    "Local",
+                    "A local server",
+                    "llama3.2",
+                    vec![],
+                    "",
+                    vec![
+                        ConfigKey::new("STATUS_TEST_HOST", true, false, None),
+                        ConfigKey::new("STATUS_TEST_PORT", true, false, Some("8080")),
+                        ConfigKey::new("STATUS_TEST_TIMEOUT", false, false, None),
+                    ],
+                ),
+                ProviderMetadata::new("hosted", "Hosted", "", "gpt-4o", vec![], "", vec![]),
+            ]
+        };
+
+        let statuses = status_from(metadata(), &config);
+        assert_eq!(statuses[0].missing_keys, ["STATUS_TEST_HOST"]);
+        assert!(!statuses[0].is_configured());
+        assert_eq!(statuses[0].model, "llama3.2");
+        assert_eq!(statuses[0].reachable, None);
+        assert!(statuses[1].is_configured());
+
+        config
+            .set("STATUS_TEST_HOST", Value::String("localhost".to_string()))
+            .unwrap();
+        config
+            .set("GOOSE_PROVIDER", Value::String("local".to_string()))
+            .unwrap();
+        config
+            .set("GOOSE_MODEL", Value::String("qwen2.5".to_string()))
+            .unwrap();
+        let statuses = status_from(metadata(), &config);
+        assert!(statuses[0].is_configured());
+        assert_eq!(statuses[0].model, "qwen2.5");
+        assert_eq!(statuses[1].model, "gpt-4o");
+    }
+
+    /// Every response reads a million tokens of a model only priced through the config
+    fn metered() -> Box<dyn Provider + Send + Sync> {
+        Box::new(TestProvider::new("session-budget-test").with_usage(
+            "session-budget-test",
+            Usage::new(Some(1_000_000), Some(0), Some(1_000_000)),
+        ))
+    }
+
+    #[tokio::test]
+    async fn test_fallbacks_are_created_from_config() {
+        for (name, limited) in [("fallback-primary", true), ("fallback-secondary", false)] {
+            let metadata =
+                ProviderMetadata::new(name, name, "", "fallback-default", vec![], "", vec![]);
+            register_provider(metadata, move |model| {
+                let name = model.model_name.clone();
+                let provider = match limited {
+                    true => {
+                        TestProvider::new(&name).with_error(|| ProviderError::rate_limited("429"))
+                    }
+                    false => TestProvider::new(&name).with_text(&name),
+                };
+                Ok(Box::new(provider.with_model_config(model)))
+            });
+        }
+        let file = tempfile::NamedTempFile::new().unwrap();
+        let config = Config::new(file.path(), "goose-fallback-test").unwrap();
+        let model = || ModelConfig::new("primary-model".to_string()).with_temperature(Some(0.2));
+
+        // Without fallbacks the primary is used as is
+        let provider = create_from("fallback-primary", model(), &config).unwrap();
+        assert!(provider.complete("", &[], &[]).await.is_err());
+
+        config
+            .set(FALLBACKS_KEY, json!([{"provider": "fallback-secondary"}]))
+            .unwrap();
+        let provider = create_from("fallback-primary", model(), &config).unwrap();
+        let (message, _) = provider.complete("", &[], &[]).await.unwrap();
+        assert_eq!(message.as_concat_text(), "fallback-default");
+        assert_eq!(provider.get_model_config().model_name, "primary-model");
+
+        config
+            .set(
+                FALLBACKS_KEY,
+                json!([{"provider": "fallback-secondary", "model": "secondary-model"}]),
+            )
+            .unwrap();
+        let provider = create_from("fallback-primary", model(), &config).unwrap();
+        let (message, _) = provider.complete("", &[], &[]).await.unwrap();
+        assert_eq!(message.as_concat_text(), "secondary-model");
+
+        config
+            .set(FALLBACKS_KEY, json!([{"provider": "fallback-missing"}]))
+            .unwrap();
+        assert!(create_from("fallback-primary", model(), &config).is_err());
+    }
+
+    #[test]
+    fn test_register_provider() {
+        let metadata = ProviderMetadata::new(
+            "registry-test",
+            "Registry Test",
+            "A provider registered at runtime",
+            "session-budget-test",
+            vec![],
+            "",
+            vec![],
+        );
+        register_provider(metadata, |_model| Ok(metered()));
+
+        assert!(providers().iter().any(|meta| meta.name == "registry-test"));
+        let provider = create(
+            "registry-test",
+            ModelConfig::new("session-budget-test".to_string()),
+        )
+        .unwrap();
+        assert_eq!(
+            provider.get_model_config().model_name,
+            "session-budget-test"
+        );
+        assert!(create("registry-missing", ModelConfig::new("gpt-4o".to_string())).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_session_budget_refuses_requests() {
+        let file = tempfile::NamedTempFile::new().unwrap();
+        let config = Config::new(file.path(), "goose-budget-test").unwrap();
+        let provider = with_session_budget(metered(), &config);
+        for _ in 0..3 {
+            assert!(provider.complete("", &[], &[]).await.is_ok());
+        }
+
+        config
+            .set(
+                PRICING_KEY,
+                json!({"session-budget-test": {"input_per_million": 3.0, "output_per_million": 0.0}}),
+            )
+            .unwrap();
+        config.set(SESSION_BUDGET_KEY, json!(5.0)).unwrap();
+        let provider = with_session_budget(metered(), &config);
+        provider.complete("", &[], &[]).await.unwrap();
+        provider.complete("", &[], &[]).await.unwrap();
+        match provider.complete("", &[], &[]).await {
+            Err(ProviderError::BudgetExceeded(message)) => {
+                assert_eq!(message, "spent $6.00 of the $5.00 session cap")
+            }
+            other => panic!(
+                "expected the budget to be exceeded, got {:?}",
+                other.map(|_| ())
+            ),
+        }
     }
 }
@@ -0,0 +1,355 @@
+use anyhow::Result;
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+use super::base::{MessageStream, Provider, ProviderMetadata, ProviderUsage, RateLimits, ThreadId};
+use super::errors::ProviderError;
+use crate::message::Message;
+use crate::model::ModelConfig;
+use mcp_core::tool::Tool;
+
+/// Whether the next provider in the chain may succeed where this one failed
+///
+/// Rate limits, outages and failed connections are specific to one provider or
+/// key. Problems with the request itself, such as a conversation that is too
+/// long, would fail the same way everywhere and are returned right away.
+fn is_unavailable(error: &ProviderError) -> bool {
+    matches!(error, ProviderError::CircuitOpen(_)) || error.is_retryable()
+}
+
+/// A provider to fail over to, an entry of `GOOSE_PROVIDER_FALLBACKS`
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FallbackConfig {
+    pub provider: String,
+    /// The model to use there, the provider's default model when not set
+    #[serde(default)]
+    pub model: Option<String>,
+}
+
+/// Tries an ordered chain of providers, moving on when one is unavailable
+///
+/// Every request starts with the first provider. When it answers with a rate
+/// limit, a server error, a timeout or a connection failure the request is
+/// sent to the next one, and so on down the chain; the error of the last
+/// provider is returned when all of them fail. Streams fail over only while
+/// opening, once output has arrived a failure is passed on. A server side
+/// thread stays with the provider that started it and does not fail over, the
+/// other providers have not seen its history. The first provider provides the
+/// metadata and model config.
+pub struct FallbackProvider {
+    providers: Vec<Box<dyn Provider>>,
+    /// The provider each thread was started on
+    threads: Mutex<HashMap<ThreadId, usize>>,
+}
+
+impl FallbackProvider {
+    /// Fail over along `providers` in order, the first one is the primary
+    pub fn new(providers: Vec<Box<dyn Provider>>) -> Result<Self> {
+        anyhow::ensure!(
+            !providers.is_empty(),
+            "A fallback chain needs at least one provider"
+        );
+        Ok(Self {
+            providers,
+            threads: Mutex::new(HashMap::new()),
+        })
+    }
+
+    /// Add a provider to the end of the chain
+    pub fn with_fallback(mut self, provider: Box<dyn Provider>) -> Self {
+        self.providers.push(provider);
+        self
+    }
+
+    fn primary(&self) -> &dyn Provider {
+        self.providers[0].as_ref()
+    }
+
+    /// Complete on the provider at `index`, remembering the thread it returns
+    async fn complete_on(
+        &self,
+        index: usize,
+        system: &str,
+        messages: &[Message],
+        tools: &[Tool],
+        thread: Option<ThreadId>,
+    ) -> Result<(Message, ProviderUsage, Option<ThreadId>), ProviderError> {
+        let result = self.providers[index]
+            .complete_in_thread(system, messages, tools, thread)
+            .await;
+        if let Ok((_, _, Some(thread))) = &result {
+            self.threads.lock().unwrap().insert(thread.clone(), index);
+        }
+        result
+    }
+
+    /// Log the providers that failed to `action`, the last error when all of them did
+    fn best_effort(
+        &self,
+        action: &str,
+        results: Vec<Result<(), ProviderError>>,
+    ) -> Result<(), ProviderError> {
+        let mut succeeded = false;
+        let mut last_error = None;
+        for (index, result) in results.into_iter().enumerate() {
+            match result {
+                Ok(()) => succeeded = true,
+                Err(error) => {
+                    tracing::warn!(
+                        "Provider {} in the fallback chain failed to {}: {}",
+                        index,
+                        action,
+                        error
+                    );
+                    last_error = Some(error);
+                }
+            }
+        }
+        match last_error {
+            Some(error) if !succeeded => Err(error),
+            _ => Ok(()),
+        }
+    }
+
+    /// Log the failover, or say whether the error should be returned as is
+    fn fail_over(&self, index: usize, error: &ProviderError) -> bool {
+        if index + 1 == self.providers.len() || !is_unavailable(error) {
+            return false;
+        }
+        tracing::warn!(
+            "Provider {} in the fallback chain failed, trying the next one: {}",
+            index,
+            error
+        );
+        true
+    }
+}
+
+#[async_trait]
+impl Provider for FallbackProvider {
+    fn metadata() -> ProviderMetadata {
+        ProviderMetadata::empty()
+    }
+
+    fn instance_metadata(&self) -> ProviderMetadata {
+        self.primary().instance_metadata()
+    }
+
+    fn get_model_config(&self) -> ModelConfig {
+        self.primary().get_model_config()
+    }
+
+    fn format_system_prompt(&self, raw: &str) -> String {
+        self.primary().format_system_prompt(raw)
+    }
+
+    fn count_request_tokens(&self, system: &str, messages: &[Message], tools: &[Tool]) -> usize {
+        self.primary().count_request_tokens(system, messages, tools)
+    }
+
+    /// Warms up every provider, failing only when none of them could be warmed up
+    async fn warmup(&self) -> Result<(), ProviderError> {
+        let mut results = Vec::new();
+        for provider in &self.providers {
+            results.push(provider.warmup().await);
+        }
+        self.best_effort("warm up", results)
+    }
+
+    /// Flushes every provider, failing only when none of them could be flushed
+    async fn flush(&self) -> Result<(), ProviderError> {
+        let mut results = Vec::new();
+        for provider in &self.providers {
+            results.push(provider.flush().await);
+        }
+        self.best_effort("flush", results)
+    }
+
+    async fn rate_limits(&self) -> Result<RateLimits, ProviderError> {
+        self.primary().rate_limits().await
+    }
+
+    async fn fetch_supported_models(&self) -> Result<Vec<String>, ProviderError> {
+        self.primary().fetch_supported_models().await
+    }
+
+    async fn embed(&self, inputs: &[String]) -> Result<Vec<Vec<f32>>, ProviderError> {
+        for (index, provider) in self.providers.iter().enumerate() {
+            match provider.embed(inputs).await {
+                Err(error) if self.fail_over(index, &error) => {}
+                result => return result,
+            }
+        }
+        unreachable!("the last provider's result is always returned")
+    }
+
+    async fn complete(
+        &self,
+        system: &str,
+        messages: &[Message],
+        tools: &[Tool],
+    ) -> Result<(Message, ProviderUsage), ProviderError> {
+        for (index, provider) in self.providers.iter().enumerate() {
+            match provider.complete(system, messages, tools).await {
+                Err(error) if self.fail_over(index, &error) => {}
+                result => return result,
+            }
+        }
+        unreachable!("the last provider's result is always returned")
+    }
+
+    /// Threads the chain did not start are sent to the primary
+    async fn complete_in_thread(
+        &self,
+        system: &str,
+        messages: &[Message],
+        tools: &[Tool],
+        thread: Option<ThreadId>,
+    ) -> Result<(Message, ProviderUsage, Option<ThreadId>), ProviderError> {
+        if let Some(thread) = thread {
+            let owner = self.threads.lock().unwrap().get(&thread).copied();
+            return self
+                .complete_on(owner.unwrap_or(0), system, messages, tools, Some(thread))
+                .await;
+        }
+        for index in 0..self.providers.len() {
+            match self.complete_on(index, system, messages, tools, None).await {
+                Err(error) if self.fail_over(index, &error) => {}
+                result => return result,
+            }
+        }
+        unreachable!("the last provider's result is always returned")
+    }
+
+    async fn stream(
+        &self,
+        system: &str,
+        messages: &[Message],
+        tools: &[Tool],
+    ) -> Result<MessageStream, ProviderError> {
+        for (index, provider) in self.providers.iter().enumerate() {
+            match provider.stream(system, messages, tools).await {
+                Err(error) if self.fail_over(index, &error) => {}
+                result => return result,
+            }
+        }
+        unreachable!("the last provider's result is always returned")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::providers::testing::{Seen, TestProvider};
+    use std::sync::Arc;
+
+    /// Answers with its own name in a thread named after it, or with `error` when one is given
+    fn provider(
+        name: &'static str,
+        error: Option<fn() -> ProviderError>,
+    ) -> (Box<dyn Provider>, Arc<Seen>) {
+        let provider = TestProvider::new(name).with_text(name).with_thread(name);
+        let provider = match error {
+            Some(error) => provider.with_error(error),
+            None => provider,
+        };
+        let seen = provider.seen();
+        (Box::new(provider), seen)
+    }
+
+    #[tokio::test]
+    async fn test_fails_over_when_unavailable() {
+        let (primary, primary_calls) =
+            provider("primary", Some(|| ProviderError::rate_limited("429")));
+        let (outage, _) = provider("outage", Some(|| ProviderError::ServerError("503".into())));
+        let (secondary, secondary_calls) = provider("secondary", None);
+        let chain = FallbackProvider::new(vec![primary, outage])
+            .unwrap()
+            .with_fallback(secondary);
+
+        let (message, usage) = chain.complete("", &[], &[]).await.unwrap();
+        assert_eq!(message.as_concat_text(), "secondary");
+        assert_eq!(usage.model, "secondary");
+        // Every request starts again with the primary
+        chain.complete("", &[], &[]).await.unwrap();
+        assert_eq!(primary_calls.calls(), 2);
+        assert_eq!(secondary_calls.calls(), 2);
+        assert_eq!(chain.get_model_config().model_name, "primary");
+
+        // Streams fail over while opening too
+        let stream = chain.stream("", &[], &[]).await.unwrap();
+        let (message, _) = crate::providers::streaming::collect_message(stream)
+            .await
+            .unwrap();
+        assert_eq!(message.as_concat_text(), "secondary");
+    }
+
+    #[tokio::test]
+    async fn test_request_errors_are_not_retried() {
+        let (primary, _) = provider(
+            "primary",
+            Some(|| ProviderError::ContextLengthExceeded("too long".into())),
+        );
+        let (secondary, secondary_calls) = provider("secondary", None);
+        let chain = FallbackProvider::new(vec![primary, secondary]).unwrap();
+        assert!(matches!(
+            chain.complete("", &[], &[]).await,
+            Err(ProviderError::ContextLengthExceeded(_))
+        ));
+        assert_eq!(secondary_calls.calls(), 0);
+
+        // With every provider down the last error is returned
+        let (primary, _) = provider("primary", Some(|| ProviderError::ServerError("500".into())));
+        let (secondary, _) = provider("secondary", Some(|| ProviderError::rate_limited("429")));
+        let chain = FallbackProvider::new(vec![primary, secondary]).unwrap();
+        assert!(matches!(
+            chain.complete("", &[], &[]).await,
+            Err(ProviderError::RateLimitExceeded { .. })
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_threads_stay_with_their_provider() {
+        let (primary, primary_calls) =
+            provider("primary", Some(|| ProviderError::rate_limited("429")));
+        let (secondary, secondary_calls) = provider("secondary", None);
+        let chain = FallbackProvider::new(vec![primary, secondary]).unwrap();
+
+        let (_, _, thread) = chain.complete_in_thread("", &[], &[], None).await.unwrap();
+        assert_eq!(thread, Some(ThreadId("secondary".to_string())));
+        assert_eq!(primary_calls.calls(), 1);
+
+        // The next turn goes straight to the provider that has the history
+        let (message, _, _) = chain
+            .complete_in_thread("", &[], &[], thread)
+            .await
+            .unwrap();
+        assert_eq!(message.as_concat_text(), "secondary");
+        assert_eq!(primary_calls.calls(), 1);
+        assert_eq!(secondary_calls.calls(), 2);
+    }
+
+    /// Fails to warm up while `down` is set
+    fn cold(down: bool) -> Box<dyn Provider> {
+        Box::new(TestProvider::new("cold").with_warmup(move || match down {
+            true => Err(ProviderError::ServerError("503".into())),
+            false => Ok(()),
+        }))
+    }
+
+    #[tokio::test]
+    async fn test_warmup_is_best_effort() {
+        let chain = FallbackProvider::new(vec![cold(true), cold(false)]).unwrap();
+        assert!(chain.warmup().await.is_ok());
+
+        let chain = FallbackProvider::new(vec![cold(true)]).unwrap();
+        assert!(matches!(
+            chain.warmup().await,
+            Err(ProviderError::ServerError(_))
+        ));
+
+        assert!(FallbackProvider::new(Vec::new()).is_err());
+    }
+}
@@ -0,0 +1,87 @@
+use futures::future::join_all;
+
+use super::base::{Provider, ProviderUsage};
+use super::errors::ProviderError;
+use crate::message::Message;
+use mcp_core::tool::Tool;
+
+/// The response of every model to one request, in the order the models were given
+pub type FanOutResults = Vec<(String, Result<(Message, ProviderUsage), ProviderError>)>;
+
+/// Sends the same request to several models at once, for comparing their answers
+///
+/// Unlike a fallback, every model is always queried and every outcome is
+/// returned, failures included. Since the result holds one response per model
+/// this is not itself a `Provider`.
+pub struct FanOutProvider {
+    providers: Vec<(String, Box<dyn Provider>)>,
+}
+
+impl FanOutProvider {
+    /// Query each `(name, provider)` pair, the name labels its response
+    pub fn new(providers: Vec<(String, Box<dyn Provider>)>) -> Self {
+        Self { providers }
+    }
+
+    /// Complete the conversation with every model concurrently
+    pub async fn complete(
+        &self,
+        system: &str,
+        messages: &[Message],
+        tools: &[Tool],
+    ) -> FanOutResults {
+        let responses = join_all(
+            self.providers
+                .iter()
+                .map(|(_, provider)| provider.complete(system, messages, tools)),
+        )
+        .await;
+        self.providers
+            .iter()
+            .map(|(name, _)| name.clone())
+            .zip(responses)
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::providers::testing::TestProvider;
+    use std::time::{Duration, Instant};
+
+    #[tokio::test]
+    async fn test_every_model_answers() {
+        let delay = Duration::from_millis(100);
+        // Answers with a fixed text after a delay, or fails without one
+        let fixed = |answer: Option<&str>| -> Box<dyn Provider> {
+            let provider = TestProvider::new("fixed").with_delay(delay);
+            Box::new(match answer {
+                Some(answer) => provider.with_text(answer),
+                None => {
+                    provider.with_error(|| ProviderError::ServerError("unavailable".to_string()))
+                }
+            })
+        };
+        let fan_out = FanOutProvider::new(vec![
+            ("gpt-4o".to_string(), fixed(Some("Paris"))),
+            ("claude".to_string(), fixed(Some("Paris, France"))),
+            ("offline".to_string(), fixed(None)),
+        ]);
+
+        let started = Instant::now();
+        let messages = vec![Message::user().with_text("Capital of France?")];
+        let results = fan_out.complete("", &messages, &[]).await;
+        // The models are queried concurrently, not one after the other
+        assert!(started.elapsed() < delay * 3);
+
+        let names: Vec<&str> = results.iter().map(|(name, _)| name.as_str()).collect();
+        assert_eq!(names, ["gpt-4o", "claude", "offline"]);
+        assert_eq!(results[0].1.as_ref().unwrap().0.as_concat_text(), "Paris");
+        assert_eq!(
+            results[1].1.as_ref().unwrap().0.as_concat_text(),
+            "Paris, France"
+        );
+        assert!(matches!(results[2].1, Err(ProviderError::ServerError(_))));
+    }
+}
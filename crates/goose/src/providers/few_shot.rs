@@ -0,0 +1,158 @@
+use async_trait::async_trait;
+
+use super::base::{MessageStream, Provider, ProviderMetadata, ProviderUsage, RateLimits};
+use super::errors::ProviderError;
+use crate::message::Message;
+use crate::model::ModelConfig;
+use mcp_core::tool::Tool;
+
+/// A provider decorator that starts every conversation with labeled examples
+///
+/// Each `(input, output)` pair is sent as a user turn followed by an assistant
+/// turn, after the system prompt and before the real conversation, so few-shot
+/// prompts are formatted the same way everywhere.
+pub struct FewShotProvider {
+    inner: Box<dyn Provider>,
+    examples: Vec<Message>,
+}
+
+impl FewShotProvider {
+    pub fn new(inner: Box<dyn Provider>) -> Self {
+        Self {
+            inner,
+            examples: Vec::new(),
+        }
+    }
+
+    /// Use these `(input, output)` pairs as the examples, in order
+    pub fn with_examples(mut self, examples: Vec<(String, String)>) -> Self {
+        self.examples = examples
+            .into_iter()
+            .flat_map(|(input, output)| {
+                [
+                    Message::user().with_text(input),
+                    Message::assistant().with_text(output),
+                ]
+            })
+            .collect();
+        self
+    }
+
+    fn with_examples_first(&self, messages: &[Message]) -> Vec<Message> {
+        self.examples.iter().chain(messages).cloned().collect()
+    }
+}
+
+#[async_trait]
+impl Provider for FewShotProvider {
+    fn metadata() -> ProviderMetadata {
+        ProviderMetadata::empty()
+    }
+
+    fn instance_metadata(&self) -> ProviderMetadata {
+        self.inner.instance_metadata()
+    }
+
+    fn get_model_config(&self) -> ModelConfig {
+        self.inner.get_model_config()
+    }
+
+    fn format_system_prompt(&self, raw: &str) -> String {
+        self.inner.format_system_prompt(raw)
+    }
+
+    fn request_body(
+        &self,
+        system: &str,
+        messages: &[Message],
+        tools: &[Tool],
+    ) -> Result<serde_json::Value, ProviderError> {
+        let messages = self.with_examples_first(messages);
+        self.inner.request_body(system, &messages, tools)
+    }
+
+    fn count_request_tokens(&self, system: &str, messages: &[Message], tools: &[Tool]) -> usize {
+        let messages = self.with_examples_first(messages);
+        self.inner.count_request_tokens(system, &messages, tools)
+    }
+
+    async fn warmup(&self) -> Result<(), ProviderError> {
+        self.inner.warmup().await
+    }
+
+    async fn flush(&self) -> Result<(), ProviderError> {
+        self.inner.flush().await
+    }
+
+    async fn rate_limits(&self) -> Result<RateLimits, ProviderError> {
+        self.inner.rate_limits().await
+    }
+
+    async fn fetch_supported_models(&self) -> Result<Vec<String>, ProviderError> {
+        self.inner.fetch_supported_models().await
+    }
+
+    async fn embed(&self, inputs: &[String]) -> Result<Vec<Vec<f32>>, ProviderError> {
+        self.inner.embed(inputs).await
+    }
+
+    async fn complete(
+        &self,
+        system: &str,
+        messages: &[Message],
+        tools: &[Tool],
+    ) -> Result<(Message, ProviderUsage), ProviderError> {
+        let messages = self.with_examples_first(messages);
+        self.inner.complete(system, &messages, tools).await
+    }
+
+    async fn stream(
+        &self,
+        system: &str,
+        messages: &[Message],
+        tools: &[Tool],
+    ) -> Result<MessageStream, ProviderError> {
+        let messages = self.with_examples_first(messages);
+        self.inner.stream(system, &messages, tools).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::providers::testing::TestProvider;
+    use mcp_core::role::Role;
+
+    #[tokio::test]
+    async fn test_examples_precede_the_conversation() {
+        let inner = TestProvider::new("recording").with_text("positive");
+        let seen = inner.seen();
+        let provider = FewShotProvider::new(Box::new(inner)).with_examples(vec![
+            ("I loved it".to_string(), "positive".to_string()),
+            ("Never again".to_string(), "negative".to_string()),
+        ]);
+
+        let messages = vec![Message::user().with_text("Pretty good overall")];
+        provider
+            .complete("Classify the sentiment", &messages, &[])
+            .await
+            .unwrap();
+
+        let turns: Vec<(Role, String)> = seen
+            .last()
+            .messages
+            .iter()
+            .map(|m| (m.role.clone(), m.as_concat_text()))
+            .collect();
+        assert_eq!(
+            turns,
+            [
+                (Role::User, "I loved it".to_string()),
+                (Role::Assistant, "positive".to_string()),
+                (Role::User, "Never again".to_string()),
+                (Role::Assistant, "negative".to_string()),
+                (Role::User, "Pretty good overall".to_string()),
+            ]
+        );
+    }
+}
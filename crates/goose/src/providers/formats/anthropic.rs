@@ -1,17 +1,114 @@
 use crate::message::{Message, MessageContent};
 use crate::model::ModelConfig;
-use crate::providers::base::Usage;
+use crate::providers::base::{MessageDelta, ProviderUsage, Usage};
 use crate::providers::errors::ProviderError;
+use crate::providers::utils::{convert_binary_content, convert_image, ImageFormat};
 use anyhow::{anyhow, Result};
 use mcp_core::content::Content;
 use mcp_core::role::Role;
 use mcp_core::tool::{Tool, ToolCall};
+use mcp_core::ToolError;
 use serde_json::{json, Value};
-use std::collections::HashSet;
+use std::collections::{HashMap, HashSet};
+
+/// The computer tool of the computeruse extension, sent as Anthropic's native computer use tool
+pub const COMPUTER_TOOL: &str = "computeruse__computer";
+
+/// The name Anthropic's native computer use tool has in requests and responses
+const NATIVE_COMPUTER_TOOL: &str = "computer";
+
+/// The version of the native computer use tool `model` supports, if any
+fn computer_tool_type(model: &str) -> Option<&'static str> {
+    if model.contains("3-5-sonnet") {
+        Some("computer_20241022")
+    } else if ["3-7-sonnet", "sonnet-4", "opus-4"]
+        .iter()
+        .any(|family| model.contains(family))
+    {
+        Some("computer_20250124")
+    } else {
+        None
+    }
+}
+
+/// The native computer use tool in place of `tool`, when it is the computer tool and `model` supports it
+///
+/// The display size is that of the screenshots, the extension gives it as the
+/// bounds of the `coordinate` parameter.
+fn native_computer_tool(tool: &Tool, model: &str) -> Option<Value> {
+    if tool.name != COMPUTER_TOOL {
+        return None;
+    }
+    let bounds = tool
+        .input_schema
+        .pointer("/properties/coordinate/prefixItems")?
+        .as_array()?;
+    let size = |axis: usize| Some(bounds.get(axis)?.get("maximum")?.as_u64()? + 1);
+    Some(json!({
+        "type": computer_tool_type(model)?,
+        "name": NATIVE_COMPUTER_TOOL,
+        "display_width_px": size(0)?,
+        "display_height_px": size(1)?,
+    }))
+}
+
+/// The beta a request needs for the native computer use tool it holds, if any
+pub fn computer_use_beta(payload: &Value) -> Option<&'static str> {
+    payload
+        .get("tools")?
+        .as_array()?
+        .iter()
+        .find_map(|tool| match tool.get("type")?.as_str()? {
+            "computer_20241022" => Some("computer-use-2024-10-22"),
+            "computer_20250124" => Some("computer-use-2025-01-24"),
+            _ => None,
+        })
+}
+
+/// The name of the tool goose knows as `name` from a response
+fn tool_name_from_response(name: &str) -> &str {
+    if name == NATIVE_COMPUTER_TOOL {
+        COMPUTER_TOOL
+    } else {
+        name
+    }
+}
+
+/// Convert tool output to the content blocks of a `tool_result`
+///
+/// Text and images are kept as blocks, embedded resources become their text.
+/// Content addressed only to the user is left out.
+fn format_tool_result(contents: &[Content]) -> Vec<Value> {
+    contents
+        .iter()
+        .filter(|content| {
+            content
+                .audience()
+                .is_none_or(|audience| audience.contains(&Role::Assistant))
+        })
+        .map(|content| match &*convert_binary_content(content) {
+            Content::Text(text) => json!({"type": "text", "text": text.text}),
+            Content::Image(image) => convert_image(image, &ImageFormat::Anthropic),
+            Content::Resource(resource) => json!({"type": "text", "text": resource.get_text()}),
+        })
+        .collect()
+}
 
 /// Convert internal Message format to Anthropic's API message specification
+///
+/// Tool requests become `tool_use` blocks and tool responses `tool_result`
+/// blocks, failed tools are marked with `is_error`. A tool request that could
+/// not be parsed has no `tool_use` to answer, so it and its response are sent
+/// as text instead.
 pub fn format_messages(messages: &[Message]) -> Vec<Value> {
     let mut anthropic_messages = Vec::new();
+    let invalid_requests: HashSet<&str> = messages
+        .iter()
+        .flat_map(|message| &message.content)
+        .filter_map(|content| content.as_tool_request())
+        .filter(|request| request.tool_call.is_err())
+        .map(|request| request.id.as_str())
+        .collect();
 
     // Convert messages to Anthropic format
     for message in messages {
@@ -23,14 +120,15 @@ pub fn format_messages(messages: &[Message]) -> Vec<Value> {
         let mut content = Vec::new();
         for msg_content in &message.content {
             match msg_content {
+                MessageContent::Text(text) if text.text.is_empty() => {}
                 MessageContent::Text(text) => {
                     content.push(json!({
                         "type": "text",
                         "text": text.text
                     }));
                 }
-                MessageContent::ToolRequest(tool_request) => {
-                    if let Ok(tool_call) = &tool_request.tool_call {
+                MessageContent::ToolRequest(tool_request) => match &tool_request.tool_call {
+                    Ok(tool_call) => {
                         content.push(json!({
                             "type": "tool_use",
                             "id": tool_request.id,
@@ -38,29 +136,57 @@ pub fn format_messages(messages: &[Message]) -> Vec<Value> {
                             "input": tool_call.arguments
                         }));
                     }
+                    Err(e) => {
+                        content.push(json!({
+                            "type": "text",
+                            "text": format!("I made a tool call that could not be parsed: {}", e)
+                        }));
+                    }
+                },
+                MessageContent::ToolResponse(tool_response)
+                    if invalid_requests.contains(tool_response.id.as_str()) =>
+                {
+                    content.push(json!({
+                        "type": "text",
+                        "text": "The tool call could not be run."
+                    }));
                 }
-                MessageContent::ToolResponse(tool_response) => {
-                    if let Ok(result) = &tool_response.tool_result {
-                        let text = result
-                            .iter()
-                            .filter_map(|c| match c {
-                                Content::Text(t) => Some(t.text.clone()),
-                                _ => None,
-                            })
-                            .collect::<Vec<_>>()
-                            .join("\n");
-
+                MessageContent::ToolResponse(tool_response) => match &tool_response.tool_result {
+                    Ok(result) => {
                         content.push(json!({
                             "type": "tool_result",
                             "tool_use_id": tool_response.id,
-                            "content": text
+                            "content": format_tool_result(result)
                         }));
                     }
+                    Err(e) => {
+                        content.push(json!({
+                            "type": "tool_result",
+                            "tool_use_id": tool_response.id,
+                            "content": e.to_string(),
+                            "is_error": true
+                        }));
+                    }
+                },
+                MessageContent::Image(image) => {
+                    content.push(convert_image(image, &ImageFormat::Anthropic));
+                }
+                MessageContent::Audio(_) => continue, // Anthropic doesn't support audio input
+                MessageContent::Thinking(_) => continue, // Only shown to the user
+                // Keep an earlier refusal in the history so the model sees it declined
+                MessageContent::Refusal(refusal) => {
+                    content.push(json!({
+                        "type": "text",
+                        "text": refusal.refusal
+                    }));
                 }
-                MessageContent::Image(_) => continue, // Anthropic doesn't support image content yet
             }
         }
 
+        if let (Some(cache_control), Some(last)) = (message.cache_control, content.last_mut()) {
+            last["cache_control"] = json!(cache_control);
+        }
+
         // Skip messages with empty content
         if !content.is_empty() {
             anthropic_messages.push(json!({
@@ -85,6 +211,14 @@ pub fn format_messages(messages: &[Message]) -> Vec<Value> {
     // During each turn, we mark the final message with cache_control so the conversation can be
     // incrementally cached. The second-to-last user message is also marked for caching with the
     // cache_control parameter, so that this checkpoint can read from the previous cache.
+    // Breakpoints the caller placed take the place of the last two user messages,
+    // as a request may only hold a few of them
+    if messages
+        .iter()
+        .any(|message| message.cache_control.is_some())
+    {
+        return anthropic_messages;
+    }
     let mut user_count = 0;
     for message in anthropic_messages.iter_mut().rev() {
         if message.get("role") == Some(&json!("user")) {
@@ -160,6 +294,12 @@ pub fn response_to_message(response: Value) -> Result<Message> {
                     message = message.with_text(text.to_string());
                 }
             }
+            // Redacted thinking is encrypted and has nothing to show
+            Some("thinking") => {
+                if let Some(thinking) = block.get("thinking").and_then(|t| t.as_str()) {
+                    message = message.with_thinking(thinking);
+                }
+            }
             Some("tool_use") => {
                 let id = block
                     .get("id")
@@ -173,7 +313,7 @@ pub fn response_to_message(response: Value) -> Result<Message> {
                     .get("input")
                     .ok_or_else(|| anyhow!("Missing tool_use input"))?;
 
-                let tool_call = ToolCall::new(name, input.clone());
+                let tool_call = ToolCall::new(tool_name_from_response(name), input.clone());
                 message = message.with_tool_request(id, Ok(tool_call));
             }
             _ => continue,
@@ -183,24 +323,41 @@ pub fn response_to_message(response: Value) -> Result<Message> {
     Ok(message)
 }
 
+/// Combine Anthropic's token counts into a `Usage`
+///
+/// Anthropic reports the tokens written to and read from the prompt cache
+/// apart from `input_tokens`, they are added back so the input covers the
+/// whole prompt as it does for other providers.
+fn usage_from_counts(
+    input_tokens: Option<i32>,
+    cache_creation: Option<i32>,
+    cache_read: Option<i32>,
+    output_tokens: Option<i32>,
+) -> Usage {
+    let input_tokens =
+        input_tokens.map(|input| input + cache_creation.unwrap_or(0) + cache_read.unwrap_or(0));
+    let total_tokens = match (input_tokens, output_tokens) {
+        (Some(i), Some(o)) => Some(i + o),
+        _ => None,
+    };
+    Usage::new(input_tokens, output_tokens, total_tokens).with_cached_input_tokens(cache_read)
+}
+
+/// Read a token count from an Anthropic usage object
+fn token_count(usage: &Value, key: &str) -> Option<i32> {
+    usage.get(key).and_then(|v| v.as_u64()).map(|v| v as i32)
+}
+
 /// Extract usage information from Anthropic's API response
 pub fn get_usage(data: &Value) -> Result<Usage> {
     // Extract usage data if available
     if let Some(usage) = data.get("usage") {
-        let input_tokens = usage
-            .get("input_tokens")
-            .and_then(|v| v.as_u64())
-            .map(|v| v as i32);
-        let output_tokens = usage
-            .get("output_tokens")
-            .and_then(|v| v.as_u64())
-            .map(|v| v as i32);
-        let total_tokens = match (input_tokens, output_tokens) {
-            (Some(i), Some(o)) => Some(i + o),
-            _ => None,
-        };
-
-        Ok(Usage::new(input_tokens, output_tokens, total_tokens))
+        Ok(usage_from_counts(
+            token_count(usage, "input_tokens"),
+            token_count(usage, "cache_creation_input_tokens"),
+            token_count(usage, "cache_read_input_tokens"),
+            token_count(usage, "output_tokens"),
+        ))
     } else {
         tracing::warn!(
             "Failed to get usage data: {}",
@@ -211,6 +368,169 @@ pub fn get_usage(data: &Value) -> Result<Usage> {
     }
 }
 
+/// A `tool_use` block whose input json is still arriving over a stream
+#[derive(Debug)]
+struct PartialToolUse {
+    id: String,
+    name: String,
+    input: String,
+    /// Which tool use of the message it is, counting from 0
+    position: usize,
+}
+
+/// Accumulates Anthropic streaming events into message deltas
+///
+/// Text is emitted as it arrives and each tool use once its block is complete,
+/// with the pieces of its input passed on as they arrive. The input tokens are reported in `message_start` and the running output
+/// token count in every `message_delta`, both are combined into the usage
+/// emitted by `finish`.
+#[derive(Debug, Default)]
+pub struct StreamAccumulator {
+    tool_uses: HashMap<u64, PartialToolUse>,
+    input_tokens: Option<i32>,
+    cache_creation_tokens: Option<i32>,
+    cache_read_tokens: Option<i32>,
+    output_tokens: Option<i32>,
+    model: Option<String>,
+    tool_use_count: usize,
+    stop_reason: Option<String>,
+}
+
+impl StreamAccumulator {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Process a single streamed event, returning the deltas ready to be emitted
+    pub fn push_event(&mut self, event: &Value) -> Vec<MessageDelta> {
+        let index = event["index"].as_u64().unwrap_or_default();
+        match event["type"].as_str() {
+            Some("message_start") => {
+                let message = &event["message"];
+                self.model = message["model"].as_str().map(String::from);
+                let usage = &message["usage"];
+                self.input_tokens = token_count(usage, "input_tokens");
+                self.cache_creation_tokens = token_count(usage, "cache_creation_input_tokens");
+                self.cache_read_tokens = token_count(usage, "cache_read_input_tokens");
+                self.output_tokens = token_count(usage, "output_tokens");
+            }
+            Some("content_block_start") => {
+                let block = &event["content_block"];
+                if block["type"] == "tool_use" {
+                    let tool_use = PartialToolUse {
+                        id: block["id"].as_str().unwrap_or_default().to_string(),
+                        name: block["name"].as_str().unwrap_or_default().to_string(),
+                        input: String::new(),
+                        position: self.tool_use_count,
+                    };
+                    self.tool_use_count += 1;
+                    let fragment = MessageDelta::ToolCallFragment {
+                        index: tool_use.position,
+                        name: Some(tool_name_from_response(&tool_use.name).to_string()),
+                        arguments: String::new(),
+                    };
+                    self.tool_uses.insert(index, tool_use);
+                    return vec![fragment];
+                } else if let Some(text) = block["text"].as_str().filter(|t| !t.is_empty()) {
+                    return vec![MessageDelta::Content(MessageContent::text(text))];
+                }
+            }
+            Some("content_block_delta") => {
+                let delta = &event["delta"];
+                match delta["type"].as_str() {
+                    Some("text_delta") => {
+                        if let Some(text) = delta["text"].as_str().filter(|t| !t.is_empty()) {
+                            return vec![MessageDelta::Content(MessageContent::text(text))];
+                        }
+                    }
+                    Some("thinking_delta") => {
+                        if let Some(thinking) = delta["thinking"].as_str().filter(|t| !t.is_empty())
+                        {
+                            return vec![MessageDelta::Content(MessageContent::thinking(thinking))];
+                        }
+                    }
+                    Some("input_json_delta") => {
+                        if let (Some(tool_use), Some(json)) = (
+                            self.tool_uses.get_mut(&index),
+                            delta["partial_json"].as_str(),
+                        ) {
+                            tool_use.input.push_str(json);
+                            return vec![MessageDelta::ToolCallFragment {
+                                index: tool_use.position,
+                                name: None,
+                                arguments: json.to_string(),
+                            }];
+                        }
+                    }
+                    _ => {}
+                }
+            }
+            Some("content_block_stop") => {
+                if let Some(tool_use) = self.tool_uses.remove(&index) {
+                    return vec![MessageDelta::Content(tool_use_to_content(tool_use))];
+                }
+            }
+            Some("message_delta") => {
+                if let Some(reason) = event["delta"]["stop_reason"].as_str() {
+                    self.stop_reason = Some(reason.to_string());
+                }
+                if let Some(output_tokens) = token_count(&event["usage"], "output_tokens") {
+                    self.output_tokens = Some(output_tokens);
+                }
+            }
+            _ => {}
+        }
+        Vec::new()
+    }
+
+    /// The usage reported so far, `None` before `message_start`
+    pub fn usage(&self) -> Option<Usage> {
+        if self.input_tokens.is_none() && self.output_tokens.is_none() {
+            return None;
+        }
+        Some(usage_from_counts(
+            self.input_tokens,
+            self.cache_creation_tokens,
+            self.cache_read_tokens,
+            self.output_tokens,
+        ))
+    }
+
+    /// Finish the stream, emitting the usage and the stop reason
+    pub fn finish(self) -> Vec<MessageDelta> {
+        let usage = self.usage().unwrap_or_default();
+        let model = self.model.unwrap_or_else(|| "Unknown".to_string());
+        std::iter::once(MessageDelta::Usage(ProviderUsage::new(model, usage)))
+            .chain(self.stop_reason.map(MessageDelta::Finish))
+            .collect()
+    }
+}
+
+fn tool_use_to_content(tool_use: PartialToolUse) -> MessageContent {
+    // A tool without parameters streams no input at all
+    let input = if tool_use.input.trim().is_empty() {
+        Ok(json!({}))
+    } else {
+        serde_json::from_str::<Value>(&tool_use.input)
+    };
+    match input {
+        Ok(input) => MessageContent::tool_request(
+            tool_use.id,
+            Ok(ToolCall::new(
+                tool_name_from_response(&tool_use.name),
+                input,
+            )),
+        ),
+        Err(e) => MessageContent::tool_request(
+            tool_use.id.clone(),
+            Err(ToolError::InvalidParameters(format!(
+                "Could not interpret tool use parameters for id {}: {}",
+                tool_use.id, e
+            ))),
+        ),
+    }
+}
+
 /// Create a complete request payload for Anthropic's API
 pub fn create_request(
     model_config: &ModelConfig,
@@ -218,10 +538,35 @@ pub fn create_request(
     messages: &[Message],
     tools: &[Tool],
 ) -> Result<Value> {
-    let anthropic_messages = format_messages(messages);
-    let tool_specs = format_tools(tools);
+    let mut anthropic_messages = format_messages(messages);
+    let mut tool_specs = format_tools(tools);
     let system_spec = format_system(system);
 
+    // The computer tool goes as the native one, which has to keep its name
+    if let Some(native) = tools
+        .iter()
+        .find_map(|tool| native_computer_tool(tool, &model_config.model_name))
+    {
+        for spec in tool_specs
+            .iter_mut()
+            .filter(|spec| spec["name"] == COMPUTER_TOOL)
+        {
+            let cache_control = spec.get("cache_control").cloned();
+            *spec = native.clone();
+            if let Some(cache_control) = cache_control {
+                spec["cache_control"] = cache_control;
+            }
+        }
+        for block in anthropic_messages
+            .iter_mut()
+            .filter_map(|message| message["content"].as_array_mut())
+            .flatten()
+            .filter(|block| block["type"] == "tool_use" && block["name"] == COMPUTER_TOOL)
+        {
+            block["name"] = json!(NATIVE_COMPUTER_TOOL);
+        }
+    }
+
     // Check if we have any messages to send
     if anthropic_messages.is_empty() {
         return Err(anyhow!("No valid messages to send to Anthropic API"));
@@ -256,6 +601,18 @@ pub fn create_request(
             .unwrap()
             .insert("temperature".to_string(), json!(temp));
     }
+    if let Some(top_p) = model_config.top_p {
+        payload
+            .as_object_mut()
+            .unwrap()
+            .insert("top_p".to_string(), json!(top_p));
+    }
+    if !model_config.stop_sequences.is_empty() {
+        payload.as_object_mut().unwrap().insert(
+            "stop_sequences".to_string(),
+            json!(model_config.stop_sequences),
+        );
+    }
 
     Ok(payload)
 }
@@ -263,6 +620,7 @@ pub fn create_request(
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::providers::base::CacheControl;
     use serde_json::json;
 
     #[test]
@@ -295,10 +653,31 @@ mod tests {
             panic!("Expected Text content");
         }
 
-        assert_eq!(usage.input_tokens, Some(12));
+        // Tokens written to the cache are part of the input
+        assert_eq!(usage.input_tokens, Some(24));
         assert_eq!(usage.output_tokens, Some(15));
-        assert_eq!(usage.total_tokens, Some(27));
+        assert_eq!(usage.total_tokens, Some(39));
+
+        Ok(())
+    }
 
+    #[test]
+    fn test_parse_thinking_response() -> Result<()> {
+        let response = json!({
+            "content": [
+                {"type": "thinking", "thinking": "The user wants a greeting.", "signature": "abc"},
+                {"type": "redacted_thinking", "data": "encrypted"},
+                {"type": "text", "text": "Hello!"}
+            ]
+        });
+        let message = response_to_message(response)?;
+
+        assert_eq!(message.content.len(), 2);
+        assert_eq!(
+            message.content[0].as_thinking(),
+            Some("The user wants a greeting.")
+        );
+        assert_eq!(message.as_concat_text(), "Hello!");
         Ok(())
     }
 
@@ -322,8 +701,8 @@ mod tests {
             "usage": {
                 "input_tokens": 15,
                 "output_tokens": 20,
-                "cache_creation_input_tokens": 15,
-                "cache_read_input_tokens": 0,
+                "cache_creation_input_tokens": 0,
+                "cache_read_input_tokens": 15,
             }
         });
 
@@ -338,9 +717,10 @@ mod tests {
             panic!("Expected ToolRequest content");
         }
 
-        assert_eq!(usage.input_tokens, Some(15));
+        assert_eq!(usage.input_tokens, Some(30));
         assert_eq!(usage.output_tokens, Some(20));
-        assert_eq!(usage.total_tokens, Some(35));
+        assert_eq!(usage.total_tokens, Some(50));
+        assert_eq!(usage.cached_input_tokens, Some(15));
 
         Ok(())
     }
@@ -365,6 +745,74 @@ mod tests {
         assert_eq!(spec[2]["content"][0]["text"], "How are you?");
     }
 
+    #[test]
+    fn test_tool_results_to_anthropic_spec() {
+        let messages = vec![
+            Message::assistant()
+                .with_tool_request("ok", Ok(ToolCall::new("screenshot", json!({}))))
+                .with_tool_request("failed", Ok(ToolCall::new("shell", json!({}))))
+                .with_tool_request(
+                    "invalid",
+                    Err(ToolError::InvalidParameters("bad json".to_string())),
+                ),
+            Message::user()
+                .with_tool_response(
+                    "ok",
+                    Ok(vec![
+                        Content::text("taken"),
+                        Content::image("aGVsbG8=", "image/png"),
+                        Content::text("only for the user").with_audience(vec![Role::User]),
+                    ]),
+                )
+                .with_tool_response(
+                    "failed",
+                    Err(ToolError::ExecutionError("exit 1".to_string())),
+                )
+                .with_tool_response("invalid", Ok(vec![])),
+        ];
+
+        let spec = format_messages(&messages);
+        let uses = spec[0]["content"].as_array().unwrap();
+        assert_eq!(uses[0]["type"], "tool_use");
+        assert_eq!(uses[1]["type"], "tool_use");
+        assert_eq!(uses[2]["type"], "text");
+
+        let results = spec[1]["content"].as_array().unwrap();
+        assert_eq!(results[0]["tool_use_id"], "ok");
+        let blocks = results[0]["content"].as_array().unwrap();
+        assert_eq!(blocks.len(), 2);
+        assert_eq!(blocks[0]["text"], "taken");
+        assert_eq!(blocks[1]["type"], "image");
+        assert_eq!(results[1]["tool_use_id"], "failed");
+        assert_eq!(results[1]["is_error"], true);
+        assert_eq!(results[2]["type"], "text");
+    }
+
+    #[test]
+    fn test_marked_messages_replace_default_breakpoints() {
+        let messages = vec![
+            Message::user()
+                .with_text("Here is the file")
+                .with_text("It is long")
+                .with_cache_control(CacheControl::Ephemeral),
+            Message::assistant().with_text("Got it"),
+            Message::user().with_text("Summarize it"),
+        ];
+
+        let spec = format_messages(&messages);
+
+        assert!(spec[0]["content"][0].get("cache_control").is_none());
+        assert_eq!(
+            spec[0]["content"][1]["cache_control"],
+            json!({"type": "ephemeral"})
+        );
+        assert!(spec[2]["content"][0].get("cache_control").is_none());
+
+        // Without markers the last user messages are the breakpoints
+        let spec = format_messages(&[Message::user().with_text("Hello")]);
+        assert!(spec[0]["content"][0].get("cache_control").is_some());
+    }
+
     #[test]
     fn test_tools_to_anthropic_spec() {
         let tools = vec![
@@ -408,6 +856,70 @@ mod tests {
         assert!(spec[1].get("cache_control").is_some());
     }
 
+    #[test]
+    fn test_native_computer_tool() -> Result<()> {
+        let computer = Tool::new(
+            COMPUTER_TOOL,
+            "Use the computer",
+            json!({
+                "type": "object",
+                "properties": {
+                    "action": {"type": "string"},
+                    "coordinate": {
+                        "type": "array",
+                        "prefixItems": [{"maximum": 1279}, {"maximum": 799}]
+                    }
+                }
+            }),
+        );
+        let messages = vec![
+            Message::user().with_text("Open the settings"),
+            Message::assistant().with_tool_request(
+                "1",
+                Ok(ToolCall::new(
+                    COMPUTER_TOOL,
+                    json!({"action": "screenshot"}),
+                )),
+            ),
+            Message::user().with_tool_response("1", Ok(vec![Content::text("Screenshot")])),
+        ];
+
+        let model = ModelConfig::new("claude-3-7-sonnet-latest".to_string());
+        let payload = create_request(&model, "system", &messages, std::slice::from_ref(&computer))?;
+        assert_eq!(
+            payload["tools"][0],
+            json!({
+                "type": "computer_20250124",
+                "name": "computer",
+                "display_width_px": 1280,
+                "display_height_px": 800,
+                "cache_control": {"type": "ephemeral"}
+            })
+        );
+        assert_eq!(payload["messages"][1]["content"][0]["name"], "computer");
+        assert_eq!(computer_use_beta(&payload), Some("computer-use-2025-01-24"));
+
+        // Models without computer use get it as a plain tool
+        let model = ModelConfig::new("claude-3-opus-latest".to_string());
+        let payload = create_request(&model, "system", &messages, &[computer])?;
+        assert_eq!(payload["tools"][0]["name"], COMPUTER_TOOL);
+        assert_eq!(payload["messages"][1]["content"][0]["name"], COMPUTER_TOOL);
+        assert_eq!(computer_use_beta(&payload), None);
+
+        let response = json!({
+            "content": [{
+                "type": "tool_use",
+                "id": "2",
+                "name": "computer",
+                "input": {"action": "left_click", "coordinate": [10, 20]}
+            }]
+        });
+        let message = response_to_message(response)?;
+        let request = message.content[0].as_tool_request().unwrap();
+        assert_eq!(request.tool_call.as_ref().unwrap().name, COMPUTER_TOOL);
+        Ok(())
+    }
+
     #[test]
     fn test_system_to_anthropic_spec() {
         let system = "You are a helpful assistant.";
@@ -0,0 +1,312 @@
+use crate::message::{Message, MessageContent};
+use crate::model::ModelConfig;
+use crate::providers::base::Usage;
+use crate::providers::errors::ProviderError;
+use crate::providers::utils::{convert_binary_content, is_valid_function_name};
+use anyhow::{anyhow, Result};
+use mcp_core::content::{Content, ImageContent};
+use mcp_core::role::Role;
+use mcp_core::tool::{Tool, ToolCall};
+use serde_json::{json, Map, Value};
+use std::collections::HashSet;
+
+/// Convert an image to a Converse image block, which names the format without the `image/` prefix
+fn format_image(image: &ImageContent) -> Value {
+    let format = image
+        .mime_type
+        .strip_prefix("image/")
+        .unwrap_or(&image.mime_type);
+    json!({
+        "image": {
+            "format": format,
+            "source": {"bytes": image.data}
+        }
+    })
+}
+
+/// Convert tool output to the content blocks of a `toolResult`, leaving out content only for the user
+fn format_tool_result(contents: &[Content]) -> Vec<Value> {
+    contents
+        .iter()
+        .filter(|content| {
+            content
+                .audience()
+                .is_none_or(|audience| audience.contains(&Role::Assistant))
+        })
+        .map(|content| match &*convert_binary_content(content) {
+            Content::Text(text) => json!({"text": text.text}),
+            Content::Image(image) => format_image(image),
+            Content::Resource(resource) => json!({"text": resource.get_text()}),
+        })
+        .collect()
+}
+
+/// Convert internal Message format to the messages of Bedrock's Converse API
+///
+/// A tool request that could not be parsed has no `toolUse` to answer, so it
+/// and its response are sent as text. Messages left without content are
+/// dropped, Converse rejects them.
+pub fn format_messages(messages: &[Message]) -> Vec<Value> {
+    let invalid_requests: HashSet<&str> = messages
+        .iter()
+        .flat_map(|message| &message.content)
+        .filter_map(|content| content.as_tool_request())
+        .filter(|request| request.tool_call.is_err())
+        .map(|request| request.id.as_str())
+        .collect();
+
+    let mut converse_messages = Vec::new();
+    for message in messages {
+        let role = match message.role {
+            Role::User => "user",
+            Role::Assistant => "assistant",
+        };
+        let mut content = Vec::new();
+        for message_content in &message.content {
+            match message_content {
+                MessageContent::Text(text) if text.text.is_empty() => {}
+                MessageContent::Text(text) => content.push(json!({"text": text.text})),
+                MessageContent::Image(image) => content.push(format_image(image)),
+                MessageContent::ToolRequest(request) => match &request.tool_call {
+                    Ok(tool_call) => content.push(json!({
+                        "toolUse": {
+                            "toolUseId": request.id,
+                            "name": tool_call.name,
+                            "input": tool_call.arguments,
+                        }
+                    })),
+                    Err(e) => content.push(json!({
+                        "text": format!("I made a tool call that could not be parsed: {}", e)
+                    })),
+                },
+                MessageContent::ToolResponse(response)
+                    if invalid_requests.contains(response.id.as_str()) =>
+                {
+                    content.push(json!({"text": "The tool call could not be run."}));
+                }
+                MessageContent::ToolResponse(response) => {
+                    let (blocks, status) = match &response.tool_result {
+                        Ok(contents) => (format_tool_result(contents), "success"),
+                        Err(e) => (vec![json!({"text": e.to_string()})], "error"),
+                    };
+                    content.push(json!({
+                        "toolResult": {
+                            "toolUseId": response.id,
+                            "content": blocks,
+                            "status": status,
+                        }
+                    }));
+                }
+                MessageContent::Refusal(refusal) => {
+                    content.push(json!({"text": refusal.refusal}));
+                }
+                MessageContent::Audio(_) | MessageContent::Thinking(_) => {}
+            }
+        }
+        if !content.is_empty() {
+            converse_messages.push(json!({"role": role, "content": content}));
+        }
+    }
+    converse_messages
+}
+
+/// Convert internal Tool format to the `toolConfig` tools of the Converse API
+pub fn format_tools(tools: &[Tool]) -> Vec<Value> {
+    tools
+        .iter()
+        .map(|tool| {
+            json!({
+                "toolSpec": {
+                    "name": tool.name,
+                    "description": tool.description,
+                    "inputSchema": {"json": tool.input_schema},
+                }
+            })
+        })
+        .collect()
+}
+
+/// Convert a Converse response to internal Message format
+pub fn response_to_message(response: Value) -> Result<Message> {
+    let blocks = response
+        .pointer("/output/message/content")
+        .and_then(|content| content.as_array())
+        .ok_or_else(|| anyhow!("No message content in the Converse response"))?;
+
+    let mut message = Message::assistant();
+    for block in blocks {
+        if let Some(text) = block.get("text").and_then(|t| t.as_str()) {
+            message = message.with_text(text);
+        } else if let Some(tool_use) = block.get("toolUse") {
+            let id = tool_use["toolUseId"].as_str().unwrap_or_default();
+            let name = tool_use["name"].as_str().unwrap_or_default();
+            if !is_valid_function_name(name) {
+                let error = mcp_core::ToolError::NotFound(format!(
+                    "The provided function name '{}' had invalid characters, it must match this regex [a-zA-Z0-9_-]+",
+                    name
+                ));
+                message = message.with_tool_request(id, Err(error));
+            } else {
+                let input = tool_use.get("input").cloned().unwrap_or(json!({}));
+                message = message.with_tool_request(id, Ok(ToolCall::new(name, input)));
+            }
+        }
+    }
+    Ok(message)
+}
+
+/// Extract usage information from a Converse response
+///
+/// Tokens read from and written to the prompt cache are reported apart from
+/// `inputTokens`, they are added back so the input covers the whole prompt.
+pub fn get_usage(data: &Value) -> Result<Usage> {
+    let Some(usage) = data.get("usage") else {
+        tracing::warn!(
+            "Failed to get usage data: {}",
+            ProviderError::UsageError("No usage data found in response".to_string())
+        );
+        return Ok(Usage::new(None, None, None));
+    };
+    let count = |key: &str| usage.get(key).and_then(|v| v.as_u64()).map(|v| v as i32);
+    let cache_read = count("cacheReadInputTokens");
+    let input_tokens = count("inputTokens")
+        .map(|input| input + cache_read.unwrap_or(0) + count("cacheWriteInputTokens").unwrap_or(0));
+    let output_tokens = count("outputTokens");
+    let total_tokens = match (input_tokens, output_tokens) {
+        (Some(input), Some(output)) => Some(input + output),
+        _ => count("totalTokens"),
+    };
+    Ok(Usage::new(input_tokens, output_tokens, total_tokens).with_cached_input_tokens(cache_read))
+}
+
+/// Create a complete request payload for the Converse API
+pub fn create_request(
+    model_config: &ModelConfig,
+    system: &str,
+    messages: &[Message],
+    tools: &[Tool],
+) -> Result<Value> {
+    let mut payload = Map::new();
+    if !system.is_empty() {
+        payload.insert("system".to_string(), json!([{"text": system}]));
+    }
+    payload.insert("messages".to_string(), json!(format_messages(messages)));
+    if !tools.is_empty() {
+        payload.insert(
+            "toolConfig".to_string(),
+            json!({"tools": format_tools(tools)}),
+        );
+    }
+    let mut inference_config = Map::new();
+    if let Some(temperature) = model_config.temperature {
+        inference_config.insert("temperature".to_string(), json!(temperature));
+    }
+    if let Some(max_tokens) = model_config.max_tokens {
+        inference_config.insert("maxTokens".to_string(), json!(max_tokens));
+    }
+    if let Some(top_p) = model_config.top_p {
+        inference_config.insert("topP".to_string(), json!(top_p));
+    }
+    if !model_config.stop_sequences.is_empty() {
+        inference_config.insert(
+            "stopSequences".to_string(),
+            json!(model_config.stop_sequences),
+        );
+    }
+    if !inference_config.is_empty() {
+        payload.insert("inferenceConfig".to_string(), json!(inference_config));
+    }
+    Ok(Value::Object(payload))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_format_messages_with_tools() {
+        let messages = vec![
+            Message::user()
+                .with_text("What is on screen?")
+                .with_image("aGVsbG8=", "image/png"),
+            Message::assistant()
+                .with_tool_request("call1", Ok(ToolCall::new("screen", json!({}))))
+                .with_tool_request(
+                    "call2",
+                    Err(mcp_core::ToolError::InvalidParameters("bad".to_string())),
+                ),
+            Message::user()
+                .with_tool_response("call1", Ok(vec![Content::text("a window")]))
+                .with_tool_response("call2", Ok(vec![])),
+            Message::assistant().with_text(""),
+        ];
+        let spec = format_messages(&messages);
+
+        assert_eq!(spec.len(), 3);
+        assert_eq!(spec[0]["content"][1]["image"]["format"], "png");
+        assert_eq!(
+            spec[0]["content"][1]["image"]["source"]["bytes"],
+            "aGVsbG8="
+        );
+        assert_eq!(spec[1]["content"][0]["toolUse"]["toolUseId"], "call1");
+        assert!(spec[1]["content"][1]["text"].is_string());
+        let result = &spec[2]["content"][0]["toolResult"];
+        assert_eq!(result["toolUseId"], "call1");
+        assert_eq!(result["status"], "success");
+        assert_eq!(result["content"][0]["text"], "a window");
+        assert!(spec[2]["content"][1]["text"].is_string());
+    }
+
+    #[test]
+    fn test_create_request() -> Result<()> {
+        let model = ModelConfig::new("anthropic.claude-3-5-sonnet-20241022-v2:0".to_string())
+            .with_max_tokens(Some(1024))
+            .with_stop_sequences(vec!["Human:".to_string()]);
+        let tool = Tool::new(
+            "calculator",
+            "Evaluate an expression",
+            json!({"type": "object", "properties": {"expression": {"type": "string"}}}),
+        );
+        let payload = create_request(
+            &model,
+            "You are helpful",
+            &[Message::user().with_text("2 + 2?")],
+            &[tool],
+        )?;
+
+        assert_eq!(payload["system"][0]["text"], "You are helpful");
+        assert_eq!(payload["inferenceConfig"]["maxTokens"], 1024);
+        assert_eq!(
+            payload["inferenceConfig"]["stopSequences"],
+            json!(["Human:"])
+        );
+        let spec = &payload["toolConfig"]["tools"][0]["toolSpec"];
+        assert_eq!(spec["name"], "calculator");
+        assert_eq!(spec["inputSchema"]["json"]["type"], "object");
+        Ok(())
+    }
+
+    #[test]
+    fn test_response_to_message() -> Result<()> {
+        let response = json!({
+            "output": {"message": {"role": "assistant", "content": [
+                {"text": "Let me calculate"},
+                {"toolUse": {"toolUseId": "tooluse_1", "name": "calculator", "input": {"expression": "2 + 2"}}}
+            ]}},
+            "stopReason": "tool_use",
+            "usage": {"inputTokens": 10, "outputTokens": 5, "totalTokens": 15, "cacheReadInputTokens": 20}
+        });
+        let message = response_to_message(response.clone())?;
+        assert_eq!(message.as_concat_text(), "Let me calculate");
+        let request = message.content[1].as_tool_request().unwrap();
+        assert_eq!(request.id, "tooluse_1");
+        let tool_call = request.tool_call.as_ref().unwrap();
+        assert_eq!(tool_call.arguments, json!({"expression": "2 + 2"}));
+
+        let usage = get_usage(&response)?;
+        assert_eq!(usage.input_tokens, Some(30));
+        assert_eq!(usage.total_tokens, Some(35));
+        assert_eq!(usage.cached_input_tokens, Some(20));
+        Ok(())
+    }
+}
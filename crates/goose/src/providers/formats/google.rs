@@ -9,9 +9,23 @@ use mcp_core::role::Role;
 use mcp_core::tool::{Tool, ToolCall};
 use rand::{distributions::Alphanumeric, Rng};
 use serde_json::{json, Map, Value};
+use std::collections::HashMap;
 
 /// Convert internal Message format to Google's API message specification
+///
+/// Gemini matches a `functionResponse` to its `functionCall` by name, so the
+/// name of each response is looked up from the earlier tool request.
 pub fn format_messages(messages: &[Message]) -> Vec<Value> {
+    let function_names: HashMap<&str, String> = messages
+        .iter()
+        .flat_map(|message| &message.content)
+        .filter_map(|content| content.as_tool_request())
+        .filter_map(|request| {
+            let tool_call = request.tool_call.as_ref().ok()?;
+            Some((request.id.as_str(), sanitize_function_name(&tool_call.name)))
+        })
+        .collect();
+
     messages
         .iter()
         .map(|message| {
@@ -50,6 +64,10 @@ pub fn format_messages(messages: &[Message]) -> Vec<Value> {
                         }
                     },
                     MessageContent::ToolResponse(response) => {
+                        let name = function_names
+                            .get(response.id.as_str())
+                            .cloned()
+                            .unwrap_or_else(|| response.id.clone());
                         match &response.tool_result {
                             Ok(contents) => {
                                 // Send only contents with no audience or with Assistant in the audience
@@ -76,7 +94,7 @@ pub fn format_messages(messages: &[Message]) -> Vec<Value> {
                                         _ => {
                                             parts.push(json!({
                                                 "functionResponse": {
-                                                    "name": response.id,
+                                                    "name": name,
                                                     "response": {"content": content},
                                                 }}
                                             ));
@@ -85,11 +103,23 @@ pub fn format_messages(messages: &[Message]) -> Vec<Value> {
                                 }
                             }
                             Err(e) => {
-                                parts.push(json!({"text":format!("Error: {}", e)}));
+                                parts.push(json!({
+                                    "functionResponse": {
+                                        "name": name,
+                                        "response": {"error": e.to_string()},
+                                    }
+                                }));
                             }
                         }
                     }
-
+                    MessageContent::Image(image) => {
+                        parts.push(json!({
+                            "inline_data": {
+                                "mime_type": image.mime_type,
+                                "data": image.data,
+                            }
+                        }));
+                    }
                     _ => {}
                 }
             }
@@ -192,6 +222,9 @@ pub fn response_to_message(response: Value) -> Result<Message> {
             role,
             created,
             content,
+            citations: Vec::new(),
+            cache_control: None,
+            sensitive: Vec::new(),
         });
     }
     let candidate = candidate.unwrap();
@@ -221,13 +254,12 @@ pub fn response_to_message(response: Value) -> Result<Message> {
                 ));
                 content.push(MessageContent::tool_request(id, Err(error)));
             } else {
-                let parameters = function_call.get("args");
-                if let Some(params) = parameters {
-                    content.push(MessageContent::tool_request(
-                        id,
-                        Ok(ToolCall::new(&name, params.clone())),
-                    ));
-                }
+                // Calls without arguments leave out `args`
+                let params = function_call.get("args").cloned().unwrap_or(json!({}));
+                content.push(MessageContent::tool_request(
+                    id,
+                    Ok(ToolCall::new(&name, params)),
+                ));
             }
         }
     }
@@ -235,6 +267,9 @@ pub fn response_to_message(response: Value) -> Result<Message> {
         role,
         created,
         content,
+        citations: Vec::new(),
+        cache_control: None,
+        sensitive: Vec::new(),
     })
 }
 
@@ -253,7 +288,13 @@ pub fn get_usage(data: &Value) -> Result<Usage> {
             .get("totalTokenCount")
             .and_then(|v| v.as_u64())
             .map(|v| v as i32);
-        Ok(Usage::new(input_tokens, output_tokens, total_tokens))
+        // Tokens served from a context cache are included in the prompt count
+        let cached_tokens = usage_meta_data
+            .get("cachedContentTokenCount")
+            .and_then(|v| v.as_u64())
+            .map(|v| v as i32);
+        Ok(Usage::new(input_tokens, output_tokens, total_tokens)
+            .with_cached_input_tokens(cached_tokens))
     } else {
         tracing::warn!(
             "Failed to get usage data: {}",
@@ -290,6 +331,21 @@ pub fn create_request(
     if let Some(tokens) = model_config.max_tokens {
         generation_config.insert("maxOutputTokens".to_string(), json!(tokens));
     }
+    if let Some(top_p) = model_config.top_p {
+        generation_config.insert("topP".to_string(), json!(top_p));
+    }
+    if let Some(penalty) = model_config.frequency_penalty {
+        generation_config.insert("frequencyPenalty".to_string(), json!(penalty));
+    }
+    if let Some(penalty) = model_config.presence_penalty {
+        generation_config.insert("presencePenalty".to_string(), json!(penalty));
+    }
+    if !model_config.stop_sequences.is_empty() {
+        generation_config.insert(
+            "stopSequences".to_string(),
+            json!(model_config.stop_sequences),
+        );
+    }
     if !generation_config.is_empty() {
         payload.insert("generationConfig".to_string(), json!(generation_config));
     }
@@ -307,6 +363,9 @@ mod tests {
             role,
             created: 0,
             content: vec![MessageContent::text(text.to_string())],
+            citations: Vec::new(),
+            cache_control: None,
+            sensitive: Vec::new(),
         }
     }
 
@@ -315,6 +374,9 @@ mod tests {
             role: Role::User,
             created: 0,
             content: vec![MessageContent::tool_request(id.to_string(), Ok(tool_call))],
+            citations: Vec::new(),
+            cache_control: None,
+            sensitive: Vec::new(),
         }
     }
 
@@ -326,6 +388,9 @@ mod tests {
                 id.to_string(),
                 Ok(tool_response),
             )],
+            citations: Vec::new(),
+            cache_control: None,
+            sensitive: Vec::new(),
         }
     }
 
@@ -352,6 +417,42 @@ mod tests {
         assert_eq!(usage.input_tokens, Some(1));
         assert_eq!(usage.output_tokens, Some(2));
         assert_eq!(usage.total_tokens, Some(3));
+        assert_eq!(usage.cached_input_tokens, None);
+    }
+
+    #[test]
+    fn test_message_to_google_spec_named_tool_responses() {
+        let messages = vec![
+            Message::user()
+                .with_text("What is on screen?")
+                .with_image("aGVsbG8=", "image/png"),
+            Message::assistant()
+                .with_tool_request("call1", Ok(ToolCall::new("developer__shell", json!({}))))
+                .with_tool_request("call2", Ok(ToolCall::new("developer__screen", json!({})))),
+            Message::user()
+                .with_tool_response("call1", Ok(vec![Content::text("done")]))
+                .with_tool_response(
+                    "call2",
+                    Err(mcp_core::ToolError::ExecutionError(
+                        "no display".to_string(),
+                    )),
+                ),
+        ];
+        let payload = format_messages(&messages);
+        assert_eq!(
+            payload[0]["parts"][1]["inline_data"]["mime_type"],
+            "image/png"
+        );
+        let responses = &payload[2]["parts"];
+        assert_eq!(responses[0]["functionResponse"]["name"], "developer__shell");
+        assert_eq!(
+            responses[1]["functionResponse"]["name"],
+            "developer__screen"
+        );
+        assert_eq!(
+            responses[1]["functionResponse"]["response"]["error"],
+            "Execution failed: no display"
+        );
     }
 
     #[test]
@@ -1,3 +1,6 @@
 pub mod anthropic;
+pub mod bedrock;
 pub mod google;
+pub mod ollama;
 pub mod openai;
+pub mod openai_responses;
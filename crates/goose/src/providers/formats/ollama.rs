@@ -0,0 +1,468 @@
+use crate::message::{Message, MessageContent};
+use crate::model::ModelConfig;
+use crate::prompt_template::load_prompt_file;
+use crate::providers::base::Usage;
+use crate::providers::formats::openai::format_tools;
+use crate::providers::utils::{convert_binary_content, extract_thinking, is_valid_function_name};
+use anyhow::{anyhow, Result};
+use lazy_static::lazy_static;
+use mcp_core::content::Content;
+use mcp_core::role::Role;
+use mcp_core::tool::{Tool, ToolCall};
+use rand::{distributions::Alphanumeric, Rng};
+use regex::Regex;
+use serde_json::{json, Map, Value};
+use std::collections::HashMap;
+
+lazy_static! {
+    /// A fenced JSON block, as emulated tool calls are written
+    static ref JSON_BLOCK: Regex = Regex::new(r"(?s)```(?:json)?\s*(\{.*?\})\s*```").unwrap();
+}
+
+/// Settings of an Ollama chat request besides the conversation
+#[derive(Debug, Clone, Default)]
+pub struct ChatOptions {
+    /// How long Ollama keeps the model loaded, a duration like "10m" or seconds
+    pub keep_alive: Option<Value>,
+    /// The context window to load the model with
+    pub num_ctx: Option<usize>,
+    /// Describe the tools in the system prompt and read calls from the reply text
+    pub emulate_tools: bool,
+}
+
+fn tool_call_id() -> String {
+    rand::thread_rng()
+        .sample_iter(&Alphanumeric)
+        .take(8)
+        .map(char::from)
+        .collect()
+}
+
+/// The text and images of tool output, leaving out content only for the user
+fn tool_output(contents: &[Content]) -> (String, Vec<String>) {
+    let mut texts = Vec::new();
+    let mut images = Vec::new();
+    for content in contents.iter().filter(|content| {
+        content
+            .audience()
+            .is_none_or(|audience| audience.contains(&Role::Assistant))
+    }) {
+        match &*convert_binary_content(content) {
+            Content::Text(text) => texts.push(text.text.clone()),
+            Content::Image(image) => images.push(image.data.clone()),
+            Content::Resource(resource) => texts.push(resource.get_text()),
+        }
+    }
+    (texts.join("\n"), images)
+}
+
+/// An emulated tool call as the model is asked to write it
+fn emulated_call(tool_call: &ToolCall) -> String {
+    let call = json!({"tool": tool_call.name, "arguments": tool_call.arguments});
+    format!("```json\n{}\n```", call)
+}
+
+/// Convert internal Message format to the messages of Ollama's chat API
+///
+/// Tool results become `tool` messages. When tools are emulated, tool calls
+/// are written as the JSON blocks the model was asked for and their results
+/// as user messages, since such models don't know either role.
+pub fn format_messages(messages: &[Message], emulate_tools: bool) -> Vec<Value> {
+    let tool_names: HashMap<&str, &str> = messages
+        .iter()
+        .flat_map(|message| &message.content)
+        .filter_map(|content| content.as_tool_request())
+        .filter_map(|request| {
+            let tool_call = request.tool_call.as_ref().ok()?;
+            Some((request.id.as_str(), tool_call.name.as_str()))
+        })
+        .collect();
+
+    let mut ollama_messages = Vec::new();
+    for message in messages {
+        let role = match message.role {
+            Role::User => "user",
+            Role::Assistant => "assistant",
+        };
+        let mut texts = Vec::new();
+        let mut images = Vec::new();
+        let mut tool_calls = Vec::new();
+        let mut tool_messages = Vec::new();
+
+        for content in &message.content {
+            match content {
+                MessageContent::Text(text) if !text.text.is_empty() => {
+                    texts.push(text.text.clone())
+                }
+                MessageContent::Image(image) => images.push(image.data.clone()),
+                MessageContent::ToolRequest(request) => match &request.tool_call {
+                    Ok(tool_call) if emulate_tools => texts.push(emulated_call(tool_call)),
+                    Ok(tool_call) => tool_calls.push(json!({
+                        "function": {"name": tool_call.name, "arguments": tool_call.arguments}
+                    })),
+                    Err(e) => texts.push(format!(
+                        "I made a tool call that could not be parsed: {}",
+                        e
+                    )),
+                },
+                MessageContent::ToolResponse(response) => {
+                    let name = tool_names.get(response.id.as_str()).copied();
+                    let (output, output_images) = match &response.tool_result {
+                        Ok(contents) => tool_output(contents),
+                        Err(e) => (format!("The tool failed: {}", e), Vec::new()),
+                    };
+                    match name {
+                        Some(name) if !emulate_tools => {
+                            let mut tool_message = json!({
+                                "role": "tool",
+                                "tool_name": name,
+                                "content": output,
+                            });
+                            if !output_images.is_empty() {
+                                tool_message["images"] = json!(output_images);
+                            }
+                            tool_messages.push(tool_message);
+                        }
+                        Some(name) => {
+                            texts.push(format!("Result of {}:\n{}", name, output));
+                            images.extend(output_images);
+                        }
+                        None => texts.push("The tool call could not be run.".to_string()),
+                    }
+                }
+                MessageContent::Refusal(refusal) => texts.push(refusal.refusal.clone()),
+                _ => {}
+            }
+        }
+
+        if !texts.is_empty() || !images.is_empty() || !tool_calls.is_empty() {
+            let mut converted = json!({"role": role, "content": texts.join("\n\n")});
+            if !images.is_empty() {
+                converted["images"] = json!(images);
+            }
+            if !tool_calls.is_empty() {
+                converted["tool_calls"] = json!(tool_calls);
+            }
+            ollama_messages.push(converted);
+        }
+        ollama_messages.extend(tool_messages);
+    }
+    ollama_messages
+}
+
+/// The system prompt addition that describes the tools to a model without function calling
+pub fn tool_emulation_prompt(tools: &[Tool]) -> Result<String> {
+    let tools: Vec<HashMap<&str, String>> = tools
+        .iter()
+        .map(|tool| {
+            HashMap::from([
+                ("name", tool.name.clone()),
+                ("description", tool.description.clone()),
+                ("schema", tool.input_schema.to_string()),
+            ])
+        })
+        .collect();
+    Ok(load_prompt_file(
+        "tool_emulation.md",
+        &HashMap::from([("tools", tools)]),
+    )?)
+}
+
+/// Read the tool calls a model wrote as JSON blocks, returning the text around them
+pub fn parse_emulated_tool_calls(text: &str) -> (String, Vec<ToolCall>) {
+    let as_call = |json: &str| -> Option<ToolCall> {
+        let value: Value = serde_json::from_str(json).ok()?;
+        let name = value.get("tool")?.as_str()?;
+        let arguments = value.get("arguments").cloned().unwrap_or(json!({}));
+        Some(ToolCall::new(name, arguments))
+    };
+
+    // Some models answer with the bare object instead of a fenced block
+    if let Some(call) = as_call(text.trim()) {
+        return (String::new(), vec![call]);
+    }
+
+    let mut calls = Vec::new();
+    let mut remaining = String::new();
+    let mut last = 0;
+    for captures in JSON_BLOCK.captures_iter(text) {
+        let block = captures.get(0).unwrap();
+        if let Some(call) = as_call(&captures[1]) {
+            remaining.push_str(&text[last..block.start()]);
+            last = block.end();
+            calls.push(call);
+        }
+    }
+    remaining.push_str(&text[last..]);
+    (remaining.trim().to_string(), calls)
+}
+
+fn tool_request(id: String, tool_call: ToolCall) -> MessageContent {
+    if is_valid_function_name(&tool_call.name) {
+        MessageContent::tool_request(id, Ok(tool_call))
+    } else {
+        let error = mcp_core::ToolError::NotFound(format!(
+            "The provided function name '{}' had invalid characters, it must match this regex [a-zA-Z0-9_-]+",
+            tool_call.name
+        ));
+        MessageContent::tool_request(id, Err(error))
+    }
+}
+
+/// Convert an Ollama chat response to internal Message format
+///
+/// Reasoning comes either in the `thinking` field or inline in the answer
+/// between `thinking_tags`, both end up as thinking content. Inline reasoning is
+/// removed before looking for emulated tool calls, so a call the model only
+/// considered is not run.
+pub fn response_to_message(
+    response: &Value,
+    emulate_tools: bool,
+    thinking_tags: &[String],
+) -> Result<Message> {
+    let message = response
+        .get("message")
+        .ok_or_else(|| anyhow!("No message in the Ollama response"))?;
+    let text = message
+        .get("content")
+        .and_then(|c| c.as_str())
+        .unwrap_or_default();
+
+    let mut content = Vec::new();
+    if let Some(thinking) = message["thinking"]
+        .as_str()
+        .filter(|t| !t.trim().is_empty())
+    {
+        content.push(MessageContent::thinking(thinking.trim()));
+    }
+    let (thinking, text) = if thinking_tags.is_empty() {
+        (Vec::new(), text.to_string())
+    } else {
+        extract_thinking(text, thinking_tags)
+    };
+    content.extend(thinking.into_iter().map(MessageContent::thinking));
+    let text = text.as_str();
+
+    if emulate_tools {
+        let (text, calls) = parse_emulated_tool_calls(text);
+        if !text.is_empty() {
+            content.push(MessageContent::text(text));
+        }
+        for call in calls {
+            content.push(tool_request(tool_call_id(), call));
+        }
+    } else if !text.is_empty() {
+        content.push(MessageContent::text(text));
+    }
+
+    let native_calls = message
+        .get("tool_calls")
+        .and_then(|calls| calls.as_array())
+        .into_iter()
+        .flatten();
+    for call in native_calls {
+        let function = &call["function"];
+        let name = function["name"].as_str().unwrap_or_default();
+        // Arguments are an object, though some models send them as a JSON string
+        let arguments = match &function["arguments"] {
+            Value::String(arguments) => serde_json::from_str(arguments).unwrap_or(json!({})),
+            Value::Null => json!({}),
+            arguments => arguments.clone(),
+        };
+        let id = call
+            .get("id")
+            .and_then(|id| id.as_str())
+            .map(str::to_string)
+            .unwrap_or_else(tool_call_id);
+        content.push(tool_request(id, ToolCall::new(name, arguments)));
+    }
+
+    Ok(Message {
+        role: Role::Assistant,
+        created: chrono::Utc::now().timestamp(),
+        content,
+        citations: Vec::new(),
+        cache_control: None,
+        sensitive: Vec::new(),
+    })
+}
+
+/// Extract usage information from an Ollama chat response
+pub fn get_usage(data: &Value) -> Usage {
+    let count = |key: &str| data.get(key).and_then(|v| v.as_u64()).map(|v| v as i32);
+    let input_tokens = count("prompt_eval_count");
+    let output_tokens = count("eval_count");
+    let total_tokens = match (input_tokens, output_tokens) {
+        (Some(input), Some(output)) => Some(input + output),
+        _ => None,
+    };
+    Usage::new(input_tokens, output_tokens, total_tokens)
+}
+
+/// Create a complete request payload for Ollama's chat API
+pub fn create_request(
+    model_config: &ModelConfig,
+    system: &str,
+    messages: &[Message],
+    tools: &[Tool],
+    options: &ChatOptions,
+) -> Result<Value> {
+    let emulate_tools = options.emulate_tools && !tools.is_empty();
+    let system = if emulate_tools {
+        format!("{}\n\n{}", system, tool_emulation_prompt(tools)?)
+    } else {
+        system.to_string()
+    };
+
+    let mut chat_messages = Vec::new();
+    if !system.trim().is_empty() {
+        chat_messages.push(json!({"role": "system", "content": system}));
+    }
+    chat_messages.extend(format_messages(messages, emulate_tools));
+
+    let mut payload = Map::new();
+    payload.insert("model".to_string(), json!(model_config.model_name));
+    payload.insert("messages".to_string(), json!(chat_messages));
+    payload.insert("stream".to_string(), json!(false));
+    if !tools.is_empty() && !emulate_tools {
+        payload.insert("tools".to_string(), json!(format_tools(tools)?));
+    }
+    if let Some(keep_alive) = &options.keep_alive {
+        payload.insert("keep_alive".to_string(), keep_alive.clone());
+    }
+
+    let mut model_options = Map::new();
+    if let Some(num_ctx) = options.num_ctx {
+        model_options.insert("num_ctx".to_string(), json!(num_ctx));
+    }
+    if let Some(temperature) = model_config.temperature {
+        model_options.insert("temperature".to_string(), json!(temperature));
+    }
+    if let Some(max_tokens) = model_config.max_tokens {
+        model_options.insert("num_predict".to_string(), json!(max_tokens));
+    }
+    if let Some(top_p) = model_config.top_p {
+        model_options.insert("top_p".to_string(), json!(top_p));
+    }
+    if let Some(penalty) = model_config.frequency_penalty {
+        model_options.insert("frequency_penalty".to_string(), json!(penalty));
+    }
+    if let Some(penalty) = model_config.presence_penalty {
+        model_options.insert("presence_penalty".to_string(), json!(penalty));
+    }
+    if !model_config.stop_sequences.is_empty() {
+        model_options.insert("stop".to_string(), json!(model_config.stop_sequences));
+    }
+    if !model_options.is_empty() {
+        payload.insert("options".to_string(), json!(model_options));
+    }
+    Ok(Value::Object(payload))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn conversation() -> Vec<Message> {
+        vec![
+            Message::user().with_text("List the files"),
+            Message::assistant().with_tool_request(
+                "call1",
+                Ok(ToolCall::new("developer__shell", json!({"command": "ls"}))),
+            ),
+            Message::user().with_tool_response("call1", Ok(vec![Content::text("a.rs")])),
+        ]
+    }
+
+    #[test]
+    fn test_format_messages() {
+        let native = format_messages(&conversation(), false);
+        assert_eq!(native.len(), 3);
+        assert_eq!(
+            native[1]["tool_calls"][0]["function"]["arguments"]["command"],
+            "ls"
+        );
+        assert_eq!(native[2]["role"], "tool");
+        assert_eq!(native[2]["tool_name"], "developer__shell");
+        assert_eq!(native[2]["content"], "a.rs");
+
+        let emulated = format_messages(&conversation(), true);
+        assert_eq!(emulated.len(), 3);
+        assert!(emulated[1].get("tool_calls").is_none());
+        let (_, calls) = parse_emulated_tool_calls(emulated[1]["content"].as_str().unwrap());
+        assert_eq!(calls[0].name, "developer__shell");
+        assert_eq!(emulated[2]["role"], "user");
+        assert_eq!(emulated[2]["content"], "Result of developer__shell:\na.rs");
+    }
+
+    #[test]
+    fn test_parse_emulated_tool_calls() {
+        let text = "Let me look.\n```json\n{\"tool\": \"shell\", \"arguments\": {\"command\": \"ls\"}}\n```";
+        let (remaining, calls) = parse_emulated_tool_calls(text);
+        assert_eq!(remaining, "Let me look.");
+        assert_eq!(calls[0].name, "shell");
+        assert_eq!(calls[0].arguments, json!({"command": "ls"}));
+
+        let (_, calls) = parse_emulated_tool_calls("{\"tool\": \"list_windows\"}");
+        assert_eq!(calls[0].arguments, json!({}));
+
+        // JSON that isn't a call stays text
+        let text = "The config is:\n```json\n{\"debug\": true}\n```";
+        let (remaining, calls) = parse_emulated_tool_calls(text);
+        assert!(calls.is_empty());
+        assert_eq!(remaining, text);
+    }
+
+    #[test]
+    fn test_response_to_message_thinking() -> Result<()> {
+        let tags = vec!["think".to_string()];
+        let response = json!({"message": {
+            "role": "assistant",
+            "content": "<think>Maybe {\"tool\": \"shell\", \"arguments\": {}}?</think>No tool needed."
+        }});
+        let message = response_to_message(&response, true, &tags)?;
+        assert_eq!(message.content.len(), 2);
+        assert!(message.content[0].as_thinking().is_some());
+        assert_eq!(message.as_concat_text(), "No tool needed.");
+
+        let response = json!({"message": {
+            "role": "assistant",
+            "thinking": "Simple arithmetic.",
+            "content": "4"
+        }});
+        let message = response_to_message(&response, false, &[])?;
+        assert_eq!(message.content[0].as_thinking(), Some("Simple arithmetic."));
+        assert_eq!(message.as_concat_text(), "4");
+        Ok(())
+    }
+
+    #[test]
+    fn test_create_request_with_emulated_tools() -> Result<()> {
+        let tool = Tool::new(
+            "shell",
+            "Run a command",
+            json!({"type": "object", "properties": {"command": {"type": "string"}}}),
+        );
+        let options = ChatOptions {
+            keep_alive: Some(json!("30m")),
+            num_ctx: Some(8192),
+            emulate_tools: true,
+        };
+        let payload = create_request(
+            &ModelConfig::new("gemma2".to_string()),
+            "You are goose",
+            &[Message::user().with_text("hi")],
+            &[tool],
+            &options,
+        )?;
+
+        assert!(payload.get("tools").is_none());
+        assert_eq!(payload["keep_alive"], "30m");
+        assert_eq!(payload["options"]["num_ctx"], 8192);
+        let system = payload["messages"][0]["content"].as_str().unwrap();
+        assert!(system.starts_with("You are goose"));
+        assert!(system.contains("## shell"));
+        assert!(system.contains("\"command\""));
+        Ok(())
+    }
+}
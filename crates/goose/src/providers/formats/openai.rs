@@ -1,14 +1,16 @@
-use crate::message::{Message, MessageContent};
-use crate::model::ModelConfig;
-use crate::providers::base::Usage;
+use crate::message::{Citation, Message, MessageContent};
+use crate::model::{ModelConfig, ResponseFormat};
+use crate::providers::base::{CacheControl, MessageDelta, ProviderUsage, Usage};
 use crate::providers::errors::ProviderError;
 use crate::providers::utils::{
-    convert_image, is_valid_function_name, sanitize_function_name, ImageFormat,
+    apply_response_locale, convert_audio, convert_binary_content, convert_image,
+    get_system_fingerprint, is_valid_function_name, sanitize_function_name, ImageFormat,
 };
 use anyhow::{anyhow, Error};
 use mcp_core::ToolError;
 use mcp_core::{Content, Role, Tool, ToolCall};
 use serde_json::{json, Value};
+use std::borrow::Cow;
 
 /// Convert internal Message format to OpenAI's API message specification
 ///   some openai compatible endpoints use the anthropic image spec at the content level
@@ -24,10 +26,12 @@ pub fn format_messages(messages: &[Message], image_format: &ImageFormat) -> Vec<
 
         for content in &message.content {
             match content {
+                MessageContent::Text(text) if text.text.is_empty() => {}
+                MessageContent::Text(text) if converted.get("content").is_none() => {
+                    converted["content"] = json!(text.text);
+                }
                 MessageContent::Text(text) => {
-                    if !text.text.is_empty() {
-                        converted["content"] = json!(text.text);
-                    }
+                    append_content(&mut converted, json!({"type": "text", "text": text.text}));
                 }
                 MessageContent::ToolRequest(request) => match &request.tool_call {
                     Ok(tool_call) => {
@@ -66,7 +70,7 @@ pub fn format_messages(messages: &[Message], image_format: &ImageFormat) -> Vec<
                                         .audience()
                                         .is_none_or(|audience| audience.contains(&Role::Assistant))
                                 })
-                                .map(|content| content.unannotated())
+                                .map(convert_binary_content)
                                 .collect();
 
                             // Process all content, replacing images with placeholder text
@@ -74,19 +78,20 @@ pub fn format_messages(messages: &[Message], image_format: &ImageFormat) -> Vec<
                             let mut image_messages = Vec::new();
 
                             for content in abridged {
-                                match content {
+                                match &*content {
                                     Content::Image(image) => {
                                         // Add placeholder text in the tool response
-                                        tool_content.push(Content::text("This tool result included an image that is uploaded in the next message."));
+                                        tool_content.push(Cow::Owned(Content::text("This tool result included an image that is uploaded in the next message.")));
 
                                         // Create a separate image message
                                         image_messages.push(json!({
                                             "role": "user",
-                                            "content": [convert_image(&image, image_format)]
+                                            "content": [convert_image(image, image_format)]
                                         }));
                                     }
                                     Content::Resource(resource) => {
-                                        tool_content.push(Content::text(resource.get_text()));
+                                        tool_content
+                                            .push(Cow::Owned(Content::text(resource.get_text())));
                                     }
                                     _ => {
                                         tool_content.push(content);
@@ -95,11 +100,11 @@ pub fn format_messages(messages: &[Message], image_format: &ImageFormat) -> Vec<
                             }
                             let tool_response_content: Value = json!(tool_content
                                 .iter()
-                                .map(|content| match content {
-                                    Content::Text(text) => text.text.clone(),
-                                    _ => String::new(),
+                                .map(|content| match &**content {
+                                    Content::Text(text) => text.text.as_str(),
+                                    _ => "",
                                 })
-                                .collect::<Vec<String>>()
+                                .collect::<Vec<_>>()
                                 .join(" "));
 
                             // First add the tool response with all content
@@ -122,26 +127,209 @@ pub fn format_messages(messages: &[Message], image_format: &ImageFormat) -> Vec<
                     }
                 }
                 MessageContent::Image(image) => {
-                    // Handle direct image content
-                    converted["content"] = json!([convert_image(image, image_format)]);
+                    append_content(&mut converted, convert_image(image, image_format));
+                }
+                MessageContent::Audio(audio) => {
+                    append_content(&mut converted, convert_audio(audio));
+                }
+                MessageContent::Refusal(refusal) => {
+                    converted["refusal"] = json!(refusal.refusal);
                 }
+                // The summary is for display, the model does not read it back
+                MessageContent::Thinking(_) => {}
             }
         }
 
-        if converted.get("content").is_some() || converted.get("tool_calls").is_some() {
+        if ["content", "tool_calls", "refusal"]
+            .iter()
+            .any(|key| converted.get(key).is_some())
+        {
             output.insert(0, converted);
         }
+        // Tool results follow as messages of their own, so the breakpoint goes on the last one
+        if let (Some(cache_control), Some(last)) = (message.cache_control, output.last_mut()) {
+            mark_cacheable(last, cache_control);
+        }
         messages_spec.extend(output);
     }
 
     messages_spec
 }
 
+/// Put a `cache_control` breakpoint on the last content part of a message
+fn mark_cacheable(message: &mut Value, cache_control: CacheControl) {
+    let Some(content) = message.get_mut("content") else {
+        return;
+    };
+    match content {
+        Value::String(text) => {
+            let text = std::mem::take(text);
+            *content = json!([{"type": "text", "text": text, "cache_control": cache_control}]);
+        }
+        Value::Array(parts) => {
+            if let Some(last) = parts.last_mut() {
+                last["cache_control"] = json!(cache_control);
+            }
+        }
+        _ => {}
+    }
+}
+
+/// Add a content part to a message, turning plain text content into a text part first
+fn append_content(converted: &mut Value, part: Value) {
+    let mut parts = match converted.get_mut("content").map(Value::take) {
+        Some(Value::String(text)) => vec![json!({"type": "text", "text": text})],
+        Some(Value::Array(parts)) => parts,
+        _ => Vec::new(),
+    };
+    parts.push(part);
+    converted["content"] = json!(parts);
+}
+
+/// Convert messages to OpenAI-style chat JSON, an array of chat messages
+///
+/// The inverse of `messages_from_openai_json`. The system prompt is not part of
+/// the messages, so no system message is included.
+pub fn messages_to_openai_json(messages: &[Message]) -> Value {
+    Value::Array(format_messages(messages, &ImageFormat::OpenAi))
+}
+
+/// Parse OpenAI-style chat JSON, as sent by OpenAI clients, into messages
+///
+/// Accepts text and content part arrays with text, `image_url` data URLs and
+/// `input_audio`, assistant `tool_calls` and `tool` role results. System
+/// messages are rejected since goose takes the system prompt separately.
+pub fn messages_from_openai_json(value: &Value) -> Result<Vec<Message>, ProviderError> {
+    let invalid =
+        |reason: String| ProviderError::request_failed(format!("Invalid chat JSON: {}", reason));
+    let entries = value
+        .as_array()
+        .ok_or_else(|| invalid("expected an array of messages".to_string()))?;
+
+    let mut messages = Vec::new();
+    for entry in entries {
+        let role = entry["role"]
+            .as_str()
+            .ok_or_else(|| invalid("message without a role".to_string()))?;
+        let message = match role {
+            "user" => content_from_openai_json(Message::user(), &entry["content"])?,
+            "assistant" => {
+                let mut message =
+                    content_from_openai_json(Message::assistant(), &entry["content"])?;
+                if let Some(refusal) = entry["refusal"].as_str() {
+                    message = message.with_refusal(refusal);
+                }
+                for tool_call in entry["tool_calls"].as_array().into_iter().flatten() {
+                    let id = tool_call["id"].as_str().unwrap_or_default().to_string();
+                    let name = tool_call["function"]["name"].as_str().unwrap_or_default();
+                    let arguments = match &tool_call["function"]["arguments"] {
+                        Value::String(arguments) => arguments.clone(),
+                        Value::Null => "{}".to_string(),
+                        arguments => arguments.to_string(),
+                    };
+                    message
+                        .content
+                        .push(tool_call_to_content(id, name, &arguments));
+                }
+                message
+            }
+            "tool" => {
+                let id = entry["tool_call_id"]
+                    .as_str()
+                    .ok_or_else(|| invalid("tool message without a tool_call_id".to_string()))?;
+                let text =
+                    content_from_openai_json(Message::user(), &entry["content"])?.as_concat_text();
+                Message::user().with_tool_response(id, Ok(vec![Content::text(text)]))
+            }
+            "system" | "developer" => return Err(ProviderError::NotSupported(
+                "system messages are not part of the conversation, pass them as the system prompt"
+                    .to_string(),
+            )),
+            other => return Err(invalid(format!("unknown role '{}'", other))),
+        };
+        messages.push(message);
+    }
+    Ok(messages)
+}
+
+/// Add the `content` of an OpenAI chat message, a string or an array of content parts
+fn content_from_openai_json(
+    mut message: Message,
+    content: &Value,
+) -> Result<Message, ProviderError> {
+    let parts = match content {
+        Value::Null => return Ok(message),
+        Value::String(text) => return Ok(message.with_text(text)),
+        Value::Array(parts) => parts,
+        _ => {
+            return Err(ProviderError::request_failed(
+                "Invalid chat JSON: content must be a string or an array".to_string(),
+            ))
+        }
+    };
+
+    for part in parts {
+        match part["type"].as_str() {
+            Some("text") => message = message.with_text(part["text"].as_str().unwrap_or_default()),
+            Some("image_url") => {
+                let url = part["image_url"]["url"].as_str().unwrap_or_default();
+                let (mime_type, data) = url
+                    .strip_prefix("data:")
+                    .and_then(|rest| rest.split_once(";base64,"))
+                    .ok_or_else(|| {
+                        ProviderError::NotSupported(
+                            "only base64 data URLs are supported for images".to_string(),
+                        )
+                    })?;
+                message = message.with_image(data, mime_type);
+            }
+            Some("input_audio") => {
+                let audio = &part["input_audio"];
+                message = message.with_audio(
+                    audio["data"].as_str().unwrap_or_default(),
+                    audio["format"].as_str().unwrap_or_default(),
+                );
+            }
+            other => {
+                return Err(ProviderError::request_failed(format!(
+                    "Invalid chat JSON: unsupported content part {:?}",
+                    other
+                )))
+            }
+        }
+    }
+    Ok(message)
+}
+
+/// Name of the tool that enables the provider's built-in web search
+///
+/// It is not sent as a function: models with the web search capability get
+/// `web_search_options` instead and search on their own, see `web_search_tool`.
+/// The name is reserved, `::` is not allowed in function names, so a user or
+/// extension tool called `web_search` is sent as the function it is.
+pub const WEB_SEARCH_TOOL_NAME: &str = "goose::web_search";
+
+/// A tool that asks for the provider's built-in web search rather than a user function
+pub fn web_search_tool() -> Tool {
+    Tool::new(
+        WEB_SEARCH_TOOL_NAME,
+        "Search the web to ground the answer in current sources",
+        json!({"type": "object", "properties": {}}),
+    )
+}
+
 /// Convert internal Tool format to OpenAI's API tool specification
+///
+/// Tools are sorted by name so the serialized request is identical however the
+/// caller collected them, which keeps provider side prompt caching effective.
+/// Object keys are already canonical since serde_json maps are ordered by key.
 pub fn format_tools(tools: &[Tool]) -> anyhow::Result<Vec<Value>> {
     let mut tool_names = std::collections::HashSet::new();
     let mut result = Vec::new();
 
+    let mut tools: Vec<&Tool> = tools.iter().collect();
+    tools.sort_by(|a, b| a.name.cmp(&b.name));
+
     for tool in tools {
         if !tool_names.insert(&tool.name) {
             return Err(anyhow!("Duplicate tool name: {}", tool.name));
@@ -164,89 +352,452 @@ pub fn format_tools(tools: &[Tool]) -> anyhow::Result<Vec<Value>> {
     Ok(result)
 }
 
+/// Adapt a schema to the subset strict function calling accepts
+///
+/// Strict mode requires every object to list all of its properties as required
+/// and to forbid additional properties. The latter is added where the schema
+/// leaves it out, while optional properties and open objects are errors, since
+/// making them strict would change what the tool accepts.
+fn strict_schema(schema: &Value, path: &str) -> anyhow::Result<Value> {
+    let Some(object) = schema.as_object() else {
+        return Ok(schema.clone());
+    };
+    let mut strict = object.clone();
+
+    if let Some(properties) = object.get("properties").and_then(|p| p.as_object()) {
+        let required: Vec<&str> = object
+            .get("required")
+            .and_then(|r| r.as_array())
+            .map(|r| r.iter().filter_map(|name| name.as_str()).collect())
+            .unwrap_or_default();
+        let mut strict_properties = serde_json::Map::new();
+        for (name, property) in properties {
+            let property_path = format!("{}.properties.{}", path, name);
+            if !required.contains(&name.as_str()) {
+                return Err(anyhow!(
+                    "{} is optional, strict mode requires every property",
+                    property_path
+                ));
+            }
+            strict_properties.insert(name.clone(), strict_schema(property, &property_path)?);
+        }
+        strict.insert("properties".to_string(), Value::Object(strict_properties));
+    }
+    if object.get("type") == Some(&json!("object")) || object.contains_key("properties") {
+        match object.get("additionalProperties") {
+            None => {
+                strict.insert("additionalProperties".to_string(), json!(false));
+            }
+            Some(Value::Bool(false)) => {}
+            Some(_) => {
+                return Err(anyhow!(
+                    "{}.additionalProperties must be false in strict mode",
+                    path
+                ))
+            }
+        }
+    }
+
+    if let Some(items) = object.get("items") {
+        strict.insert(
+            "items".to_string(),
+            strict_schema(items, &format!("{}.items", path))?,
+        );
+    }
+    for key in ["anyOf", "$defs", "definitions"] {
+        let nested = match object.get(key) {
+            Some(Value::Array(schemas)) => Value::Array(
+                schemas
+                    .iter()
+                    .enumerate()
+                    .map(|(i, s)| strict_schema(s, &format!("{}.{}[{}]", path, key, i)))
+                    .collect::<anyhow::Result<_>>()?,
+            ),
+            Some(Value::Object(schemas)) => Value::Object(
+                schemas
+                    .iter()
+                    .map(|(name, s)| {
+                        Ok((
+                            name.clone(),
+                            strict_schema(s, &format!("{}.{}.{}", path, key, name))?,
+                        ))
+                    })
+                    .collect::<anyhow::Result<_>>()?,
+            ),
+            _ => continue,
+        };
+        strict.insert(key.to_string(), nested);
+    }
+    Ok(Value::Object(strict))
+}
+
+/// Responses do not repeat the requested audio format, most gateways default to wav
+const DEFAULT_AUDIO_FORMAT: &str = "wav";
+
 /// Convert OpenAI's API response to internal Message format
 pub fn response_to_message(response: Value) -> anyhow::Result<Message> {
     let original = response["choices"][0]["message"].clone();
     let mut content = Vec::new();
 
+    // Gateways name the summary after the backend, "reasoning_content" for DeepSeek style APIs
+    if let Some(thinking) = ["reasoning", "reasoning_content"]
+        .iter()
+        .find_map(|key| original.get(*key).and_then(|r| r.as_str()))
+        .filter(|thinking| !thinking.is_empty())
+    {
+        content.push(MessageContent::thinking(thinking));
+    }
+
     if let Some(text) = original.get("content") {
         if let Some(text_str) = text.as_str() {
             content.push(MessageContent::text(text_str));
         }
     }
 
+    // Models with structured outputs decline in `refusal`, with a null content
+    if let Some(refusal) = original.get("refusal").and_then(|r| r.as_str()) {
+        content.push(MessageContent::refusal(refusal));
+    }
+
+    if let Some(audio) = original.get("audio").filter(|a| a["data"].is_string()) {
+        // Audio replies carry their text as a transcript, with a null content
+        if content.is_empty() {
+            if let Some(transcript) = audio["transcript"].as_str() {
+                content.push(MessageContent::text(transcript));
+            }
+        }
+        content.push(MessageContent::audio(
+            audio["data"].as_str().unwrap_or_default(),
+            audio["format"].as_str().unwrap_or(DEFAULT_AUDIO_FORMAT),
+        ));
+    }
+
     if let Some(tool_calls) = original.get("tool_calls") {
         if let Some(tool_calls_array) = tool_calls.as_array() {
             for tool_call in tool_calls_array {
                 let id = tool_call["id"].as_str().unwrap_or_default().to_string();
-                let function_name = tool_call["function"]["name"]
-                    .as_str()
-                    .unwrap_or_default()
-                    .to_string();
-                let arguments = tool_call["function"]["arguments"]
-                    .as_str()
-                    .unwrap_or_default()
-                    .to_string();
-
-                if !is_valid_function_name(&function_name) {
-                    let error = ToolError::NotFound(format!(
-                        "The provided function name '{}' had invalid characters, it must match this regex [a-zA-Z0-9_-]+",
-                        function_name
-                    ));
-                    content.push(MessageContent::tool_request(id, Err(error)));
-                } else {
-                    match serde_json::from_str::<Value>(&arguments) {
-                        Ok(params) => {
-                            content.push(MessageContent::tool_request(
-                                id,
-                                Ok(ToolCall::new(&function_name, params)),
-                            ));
-                        }
-                        Err(e) => {
-                            let error = ToolError::InvalidParameters(format!(
-                                "Could not interpret tool use parameters for id {}: {}",
-                                id, e
-                            ));
-                            content.push(MessageContent::tool_request(id, Err(error)));
-                        }
-                    }
-                }
+                let function_name = tool_call["function"]["name"].as_str().unwrap_or_default();
+                // Arguments are a json encoded string, but some gateways send the object itself
+                let arguments = match &tool_call["function"]["arguments"] {
+                    Value::String(arguments) => arguments.clone(),
+                    Value::Null => String::new(),
+                    arguments => arguments.to_string(),
+                };
+
+                content.push(tool_call_to_content(id, function_name, &arguments));
             }
         }
     }
 
+    if content.is_empty() {
+        return Err(anyhow::anyhow!(
+            "Response message has neither content nor a refusal"
+        ));
+    }
+
     Ok(Message {
         role: Role::Assistant,
         created: chrono::Utc::now().timestamp(),
         content,
+        citations: Some(parse_citations(&original))
+            .filter(|c| !c.is_empty())
+            .unwrap_or_else(|| cited_urls(&response)),
+        cache_control: None,
+        sensitive: Vec::new(),
     })
 }
 
+/// Read the plain list of source urls Perplexity models put next to the choices
+fn cited_urls(response: &Value) -> Vec<Citation> {
+    let Some(urls) = response.get("citations").and_then(|c| c.as_array()) else {
+        return Vec::new();
+    };
+    urls.iter()
+        .filter_map(|url| url.as_str())
+        .map(|url| Citation {
+            url: url.to_string(),
+            title: None,
+            start_index: None,
+            end_index: None,
+        })
+        .collect()
+}
+
+/// Read the `url_citation` annotations a web search model attaches to its answer
+fn parse_citations(message: &Value) -> Vec<Citation> {
+    let Some(annotations) = message.get("annotations").and_then(|a| a.as_array()) else {
+        return Vec::new();
+    };
+    annotations
+        .iter()
+        .filter(|annotation| annotation["type"] == "url_citation")
+        .filter_map(|annotation| {
+            let citation = &annotation["url_citation"];
+            let index = |key: &str| citation[key].as_u64().map(|i| i as usize);
+            Some(Citation {
+                url: citation["url"].as_str()?.to_string(),
+                title: citation["title"].as_str().map(String::from),
+                start_index: index("start_index"),
+                end_index: index("end_index"),
+            })
+        })
+        .collect()
+}
+
+/// Convert a single OpenAI tool call into a tool request, validating the name and arguments
+pub(crate) fn tool_call_to_content(
+    id: String,
+    function_name: &str,
+    arguments: &str,
+) -> MessageContent {
+    if !is_valid_function_name(function_name) {
+        let error = ToolError::NotFound(format!(
+            "The provided function name '{}' had invalid characters, it must match this regex [a-zA-Z0-9_-]+",
+            function_name
+        ));
+        return MessageContent::tool_request(id, Err(error));
+    }
+
+    match serde_json::from_str::<Value>(arguments) {
+        Ok(params) => MessageContent::tool_request(id, Ok(ToolCall::new(function_name, params))),
+        Err(e) => {
+            let error = ToolError::InvalidParameters(format!(
+                "Could not interpret tool use parameters for id {}: {}",
+                id, e
+            ));
+            MessageContent::tool_request(id, Err(error))
+        }
+    }
+}
+
+/// A tool call whose id, name and arguments are still arriving over a stream
+#[derive(Debug, Default, Clone)]
+struct PartialToolCall {
+    id: String,
+    name: String,
+    arguments: String,
+}
+
+/// Accumulates OpenAI `chat.completion.chunk` payloads into message deltas
+///
+/// Text and audio transcripts are emitted as soon as they arrive, while tool
+/// calls and audio are buffered until the stream finishes since their arguments
+/// are only valid json, and the clip only playable, once complete. The pieces of
+/// the tool calls are passed on as they arrive too, for showing progress.
+#[derive(Debug, Default)]
+pub struct StreamAccumulator {
+    tool_calls: Vec<PartialToolCall>,
+    audio: Option<(String, String)>,
+    /// Kept whole so a refusal is a single content
+    refusal: Option<String>,
+    /// Annotations arrive with the text they belong to
+    citations: Vec<Citation>,
+    /// Perplexity repeats the whole list of urls in every chunk
+    cited_urls: Vec<Citation>,
+    usage: Option<Usage>,
+    model: Option<String>,
+    system_fingerprint: Option<String>,
+    finish_reason: Option<String>,
+}
+
+impl StreamAccumulator {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Process a single streamed chunk, returning the deltas ready to be emitted
+    pub fn push_chunk(&mut self, chunk: &Value) -> Vec<MessageDelta> {
+        let mut deltas = Vec::new();
+
+        if let Some(model) = chunk.get("model").and_then(|m| m.as_str()) {
+            self.model = Some(model.to_string());
+        }
+        if let Some(fingerprint) = get_system_fingerprint(chunk) {
+            self.system_fingerprint = Some(fingerprint);
+        }
+        // With stream_options.include_usage the final chunk has usage and no choices
+        if chunk.get("usage").is_some_and(|u| !u.is_null()) {
+            self.usage = get_usage(chunk).ok();
+        }
+
+        if chunk.get("citations").is_some() {
+            self.cited_urls = cited_urls(chunk);
+        }
+
+        if let Some(reason) = chunk["choices"][0]["finish_reason"].as_str() {
+            self.finish_reason = Some(reason.to_string());
+        }
+        let delta = &chunk["choices"][0]["delta"];
+        self.citations.extend(parse_citations(delta));
+        // Tool call chunks commonly carry an empty content, which is not worth emitting
+        if let Some(text) = delta.get("content").and_then(|c| c.as_str()) {
+            if !text.is_empty() {
+                deltas.push(MessageDelta::Content(MessageContent::text(text)));
+            }
+        }
+
+        if let Some(refusal) = delta.get("refusal").and_then(|r| r.as_str()) {
+            self.refusal.get_or_insert_default().push_str(refusal);
+        }
+
+        if let Some(audio) = delta.get("audio") {
+            if let Some(transcript) = audio["transcript"].as_str().filter(|t| !t.is_empty()) {
+                deltas.push(MessageDelta::Content(MessageContent::text(transcript)));
+            }
+            if let Some(data) = audio["data"].as_str() {
+                let (buffered, format) = self
+                    .audio
+                    .get_or_insert_with(|| (String::new(), DEFAULT_AUDIO_FORMAT.to_string()));
+                buffered.push_str(data);
+                if let Some(f) = audio["format"].as_str() {
+                    *format = f.to_string();
+                }
+            }
+        }
+
+        if let Some(tool_calls) = delta.get("tool_calls").and_then(|t| t.as_array()) {
+            for tool_call in tool_calls {
+                let index = tool_call["index"].as_u64().unwrap_or_default() as usize;
+                if self.tool_calls.len() <= index {
+                    self.tool_calls
+                        .resize(index + 1, PartialToolCall::default());
+                }
+                let partial = &mut self.tool_calls[index];
+                if let Some(id) = tool_call["id"].as_str() {
+                    partial.id.push_str(id);
+                }
+                let name = tool_call["function"]["name"].as_str();
+                if let Some(name) = name {
+                    partial.name.push_str(name);
+                }
+                let arguments = tool_call["function"]["arguments"].as_str();
+                if let Some(arguments) = arguments {
+                    partial.arguments.push_str(arguments);
+                }
+                deltas.push(MessageDelta::ToolCallFragment {
+                    index,
+                    name: name.map(String::from),
+                    arguments: arguments.unwrap_or_default().to_string(),
+                });
+            }
+        }
+
+        deltas
+    }
+
+    /// The usage reported so far, if any chunk carried it
+    pub fn usage(&self) -> Option<Usage> {
+        self.usage.clone()
+    }
+
+    /// Finish the stream, emitting the refusal, audio, assembled tool requests and citations followed by usage
+    /// and the finish reason
+    pub fn finish(self) -> Vec<MessageDelta> {
+        let refusal = self
+            .refusal
+            .map(|refusal| MessageDelta::Content(MessageContent::refusal(refusal)));
+        let audio = self
+            .audio
+            .map(|(data, format)| MessageDelta::Content(MessageContent::audio(data, format)));
+        let mut deltas: Vec<MessageDelta> = refusal
+            .into_iter()
+            .chain(audio)
+            .chain(self.tool_calls.into_iter().map(|call| {
+                MessageDelta::Content(tool_call_to_content(call.id, &call.name, &call.arguments))
+            }))
+            .collect();
+        let citations = if self.citations.is_empty() {
+            self.cited_urls
+        } else {
+            self.citations
+        };
+        if !citations.is_empty() {
+            deltas.push(MessageDelta::Citations(citations));
+        }
+
+        let model = self.model.unwrap_or_else(|| "Unknown".to_string());
+        deltas.push(MessageDelta::Usage(
+            ProviderUsage::new(model, self.usage.unwrap_or_default())
+                .with_system_fingerprint(self.system_fingerprint),
+        ));
+        deltas.extend(self.finish_reason.map(MessageDelta::Finish));
+        deltas
+    }
+}
+
 pub fn get_usage(data: &Value) -> Result<Usage, ProviderError> {
     let usage = data
         .get("usage")
         .ok_or_else(|| ProviderError::UsageError("No usage data in response".to_string()))?;
 
-    let input_tokens = usage
-        .get("prompt_tokens")
-        .and_then(|v| v.as_i64())
-        .map(|v| v as i32);
+    // Callers fall back to empty usage on `UsageError` so the completion itself is not lost
+    if !usage.is_object() && !usage.is_null() {
+        return Err(ProviderError::UsageError(format!(
+            "Malformed usage data: {}",
+            usage
+        )));
+    }
+    // Nonconforming gateways send e.g. strings for some counts, those are
+    // left out and the counts that parse are kept
+    let count = |key: &str| match &usage[key] {
+        Value::Null => None,
+        value => {
+            let count = value.as_i64().map(|v| v as i32);
+            if count.is_none() {
+                tracing::warn!("Ignoring malformed {} in usage data: {}", key, value);
+            }
+            count
+        }
+    };
+    let input_tokens = count("prompt_tokens");
+    let output_tokens = count("completion_tokens");
+    let reported_total = count("total_tokens");
+    let sum = match (input_tokens, output_tokens) {
+        (Some(input), Some(output)) => Some(input + output),
+        _ => None,
+    };
+    // Some backends count extra tokens (e.g. reasoning) in the total, keep what they report
+    if let (Some(total), Some(sum)) = (reported_total, sum) {
+        if total != sum {
+            tracing::warn!(
+                "Reported total_tokens {} does not match prompt + completion tokens {}",
+                total,
+                sum
+            );
+        }
+    }
+    let total_tokens = reported_total.or(sum);
 
-    let output_tokens = usage
-        .get("completion_tokens")
-        .and_then(|v| v.as_i64())
+    let details = &usage["completion_tokens_details"];
+    let detail_tokens = |key: &str| details[key].as_i64().map(|v| v as i32);
+    let cached_tokens = usage["prompt_tokens_details"]["cached_tokens"]
+        .as_i64()
         .map(|v| v as i32);
+    Ok(Usage::new(input_tokens, output_tokens, total_tokens)
+        .with_prediction_tokens(
+            detail_tokens("accepted_prediction_tokens"),
+            detail_tokens("rejected_prediction_tokens"),
+        )
+        .with_cached_input_tokens(cached_tokens)
+        .with_reasoning_tokens(detail_tokens("reasoning_tokens")))
+}
 
-    let total_tokens = usage
-        .get("total_tokens")
-        .and_then(|v| v.as_i64())
-        .map(|v| v as i32)
-        .or_else(|| match (input_tokens, output_tokens) {
-            (Some(input), Some(output)) => Some(input + output),
-            _ => None,
-        });
-
-    Ok(Usage::new(input_tokens, output_tokens, total_tokens))
+/// Send the system prompt as part of the first user message, for models without a system role
+///
+/// When the conversation does not start with a user message the prompt becomes
+/// a user turn of its own.
+fn fold_system_into_user(system: &str, messages_spec: &mut Vec<Value>) {
+    if system.is_empty() {
+        return;
+    }
+    let first = messages_spec
+        .first_mut()
+        .filter(|message| message["role"] == "user");
+    match first.map(|message| &mut message["content"]) {
+        Some(Value::String(text)) => *text = format!("{}\n\n{}", system, text),
+        Some(Value::Array(parts)) => parts.insert(0, json!({"type": "text", "text": system})),
+        _ => messages_spec.insert(0, json!({"role": "user", "content": system})),
+    }
 }
 
 pub fn create_request(
@@ -256,20 +807,55 @@ pub fn create_request(
     tools: &[Tool],
     image_format: &ImageFormat,
 ) -> anyhow::Result<Value, Error> {
-    let system_message = json!({
-        "role": "system",
-        "content": system
-    });
-
-    let messages_spec = format_messages(messages, image_format);
-    let tools_spec = if !tools.is_empty() {
-        format_tools(tools)?
+    let system = apply_response_locale(system, model_config.response_locale.as_deref());
+    let mut messages_spec = if model_config.include_timestamps {
+        format_messages(&timestamped(messages), image_format)
+    } else {
+        format_messages(messages, image_format)
+    };
+    let (web_search, tools): (Vec<Tool>, Vec<Tool>) = tools
+        .iter()
+        .cloned()
+        .partition(|tool| tool.name == WEB_SEARCH_TOOL_NAME);
+    let mut tools_spec = if !tools.is_empty() {
+        format_tools(&tools)?
     } else {
         vec![]
     };
+    if model_config.strict_functions {
+        for spec in tools_spec.iter_mut() {
+            let function = &mut spec["function"];
+            let name = function["name"].as_str().unwrap_or_default().to_string();
+            let parameters = strict_schema(&function["parameters"], "parameters")
+                .map_err(|e| anyhow!("Tool '{}' can not be strict: {}", name, e))?;
+            function["parameters"] = parameters;
+            function["strict"] = json!(true);
+        }
+    }
 
-    let mut messages_array = vec![system_message];
-    messages_array.extend(messages_spec);
+    let mut messages_array = if model_config.capabilities().system_as_user {
+        fold_system_into_user(&system, &mut messages_spec);
+        messages_spec
+    } else {
+        let mut messages_array = vec![json!({
+            "role": "system",
+            "content": system
+        })];
+        messages_array.extend(messages_spec);
+        messages_array
+    };
+    // The system prompt and the tools make up the prefix every turn shares
+    if let Some(cache_control) = model_config.cache_control {
+        if let Some(system) = messages_array
+            .first_mut()
+            .filter(|message| message["role"] == "system")
+        {
+            mark_cacheable(system, cache_control);
+        }
+        if let Some(last) = tools_spec.last_mut() {
+            last["cache_control"] = json!(cache_control);
+        }
+    }
 
     let mut payload = json!({
         "model": model_config.model_name,
@@ -277,10 +863,14 @@ pub fn create_request(
     });
 
     if !tools_spec.is_empty() {
+        let payload = payload.as_object_mut().unwrap();
+        payload.insert("tools".to_string(), json!(tools_spec));
+    }
+    if !web_search.is_empty() {
         payload
             .as_object_mut()
             .unwrap()
-            .insert("tools".to_string(), json!(tools_spec));
+            .insert("web_search_options".to_string(), json!({}));
     }
     if let Some(temp) = model_config.temperature {
         payload
@@ -294,14 +884,194 @@ pub fn create_request(
             .unwrap()
             .insert("max_tokens".to_string(), json!(tokens));
     }
+    if let Some(top_p) = model_config.top_p {
+        payload
+            .as_object_mut()
+            .unwrap()
+            .insert("top_p".to_string(), json!(top_p));
+    }
+    if let Some(penalty) = model_config.frequency_penalty {
+        payload
+            .as_object_mut()
+            .unwrap()
+            .insert("frequency_penalty".to_string(), json!(penalty));
+    }
+    if let Some(penalty) = model_config.presence_penalty {
+        payload
+            .as_object_mut()
+            .unwrap()
+            .insert("presence_penalty".to_string(), json!(penalty));
+    }
+    if !model_config.stop_sequences.is_empty() {
+        payload
+            .as_object_mut()
+            .unwrap()
+            .insert("stop".to_string(), json!(model_config.stop_sequences));
+    }
+    if !model_config.stop_token_ids.is_empty() {
+        payload.as_object_mut().unwrap().insert(
+            "stop_token_ids".to_string(),
+            json!(model_config.stop_token_ids),
+        );
+    }
+    if let Some(prediction) = &model_config.prediction {
+        payload.as_object_mut().unwrap().insert(
+            "prediction".to_string(),
+            json!({"type": "content", "content": prediction}),
+        );
+    }
+    if let Some(audio) = &model_config.audio_output {
+        let payload = payload.as_object_mut().unwrap();
+        payload.insert("modalities".to_string(), json!(["text", "audio"]));
+        payload.insert(
+            "audio".to_string(),
+            json!({"voice": audio.voice, "format": audio.format}),
+        );
+    }
+    if let Some(effort) = model_config.reasoning_effort {
+        payload
+            .as_object_mut()
+            .unwrap()
+            .insert("reasoning_effort".to_string(), json!(effort));
+    }
+    if let Some(format) = &model_config.response_format {
+        payload.as_object_mut().unwrap().insert(
+            "response_format".to_string(),
+            format_response_format(format),
+        );
+    }
+    if let Some(store) = model_config.store {
+        payload
+            .as_object_mut()
+            .unwrap()
+            .insert("store".to_string(), json!(store));
+    }
+    if let Some(metadata) = &model_config.metadata {
+        payload
+            .as_object_mut()
+            .unwrap()
+            .insert("metadata".to_string(), json!(metadata));
+    }
+    if let Some(Value::Object(extra)) = &model_config.extra_body {
+        merge_extra_body(&mut payload, extra);
+    }
     Ok(payload)
 }
 
+/// The `response_format` parameter, which nests a schema under `json_schema`
+fn format_response_format(format: &ResponseFormat) -> Value {
+    match format {
+        ResponseFormat::JsonObject => json!({"type": "json_object"}),
+        ResponseFormat::JsonSchema {
+            name,
+            schema,
+            strict,
+        } => json!({
+            "type": "json_schema",
+            "json_schema": {"name": name, "schema": schema, "strict": strict},
+        }),
+    }
+}
+
+/// Prefix the first text of each message with the time it was created
+fn timestamped(messages: &[Message]) -> Vec<Message> {
+    messages
+        .iter()
+        .map(|message| {
+            let mut message = message.clone();
+            let created = chrono::DateTime::from_timestamp(message.created, 0)
+                .map(|time| time.to_rfc3339_opts(chrono::SecondsFormat::Secs, true));
+            if let (Some(created), Some(MessageContent::Text(text))) = (
+                created,
+                message
+                    .content
+                    .iter_mut()
+                    .find(|c| matches!(c, MessageContent::Text(_))),
+            ) {
+                text.text = format!("[{}] {}", created, text.text);
+            }
+            message
+        })
+        .collect()
+}
+
+/// The model IDs of a `/models` listing, sorted by name
+pub fn response_to_models(response: &Value) -> anyhow::Result<Vec<String>> {
+    let data = response["data"]
+        .as_array()
+        .ok_or_else(|| anyhow::anyhow!("Models response has no data array"))?;
+    let mut models: Vec<String> = data
+        .iter()
+        .filter_map(|model| model["id"].as_str())
+        .map(str::to_string)
+        .collect();
+    models.sort();
+    models.dedup();
+    Ok(models)
+}
+
+/// The body of a request to the embeddings endpoint
+pub fn create_embedding_request(model: &str, inputs: &[String]) -> Value {
+    json!({
+        "model": model,
+        "input": inputs,
+        "encoding_format": "float",
+    })
+}
+
+/// The vectors of an embeddings response, in the order of the inputs they were made from
+pub fn response_to_embeddings(response: &Value, inputs: usize) -> anyhow::Result<Vec<Vec<f32>>> {
+    let data = response["data"]
+        .as_array()
+        .ok_or_else(|| anyhow::anyhow!("Embeddings response has no data array"))?;
+    let mut embeddings: Vec<Option<Vec<f32>>> = vec![None; inputs];
+    for (position, item) in data.iter().enumerate() {
+        // The API may answer out of order, `index` says which input a vector belongs to
+        let index = item["index"].as_u64().map_or(position, |i| i as usize);
+        let vector = item["embedding"]
+            .as_array()
+            .ok_or_else(|| anyhow::anyhow!("Embedding {} is not a list of floats", index))?
+            .iter()
+            .map(|value| value.as_f64().map(|v| v as f32))
+            .collect::<Option<Vec<f32>>>()
+            .ok_or_else(|| anyhow::anyhow!("Embedding {} is not a list of floats", index))?;
+        match embeddings.get_mut(index) {
+            Some(slot) => *slot = Some(vector),
+            None => anyhow::bail!(
+                "Embedding index {} is out of range for {} inputs",
+                index,
+                inputs
+            ),
+        }
+    }
+    embeddings
+        .into_iter()
+        .enumerate()
+        .map(|(i, embedding)| {
+            embedding.ok_or_else(|| anyhow::anyhow!("Embeddings response is missing input {}", i))
+        })
+        .collect()
+}
+
+/// Shallow merge extra parameters into the payload without overriding what it already sets
+fn merge_extra_body(payload: &mut Value, extra: &serde_json::Map<String, Value>) {
+    let payload = payload.as_object_mut().unwrap();
+    for (key, value) in extra {
+        if key == "model" || key == "messages" || payload.contains_key(key) {
+            tracing::debug!("Ignoring extra body parameter '{}'", key);
+            continue;
+        }
+        payload.insert(key.clone(), value.clone());
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::model::{AudioOutput, ReasoningEffort};
     use mcp_core::content::Content;
     use serde_json::json;
+    use std::collections::HashMap;
 
     const OPENAI_TOOL_USE_RESPONSE: &str = r#"{
         "choices": [{
@@ -334,6 +1104,652 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn test_format_messages_with_audio() -> anyhow::Result<()> {
+        let message = Message::user().with_audio("UklGRiQAAABXQVZF", "wav");
+        let spec = format_messages(&[message], &ImageFormat::OpenAi);
+
+        assert_eq!(spec.len(), 1);
+        assert_eq!(
+            spec[0]["content"],
+            json!([{
+                "type": "input_audio",
+                "input_audio": {"data": "UklGRiQAAABXQVZF", "format": "wav"}
+            }])
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_format_messages_with_binary_tool_output() -> anyhow::Result<()> {
+        use mcp_core::resource::ResourceContents;
+
+        let blob = |uri: &str, mime_type: &str, blob: &str| {
+            Content::resource(ResourceContents::BlobResourceContents {
+                uri: uri.to_string(),
+                mime_type: Some(mime_type.to_string()),
+                blob: blob.to_string(),
+            })
+        };
+        let message = Message::user().with_tool_response(
+            "call_1",
+            Ok(vec![
+                // "%PDF\xe2\xe3", not valid UTF-8
+                blob("file:///report.pdf", "application/pdf", "JVBERuLj"),
+                blob("file:///shot.png", "image/png", "iVBORw0K"),
+            ]),
+        );
+        let spec = format_messages(&[message], &ImageFormat::OpenAi);
+
+        assert_eq!(spec.len(), 2);
+        let output = spec[0]["content"].as_str().unwrap();
+        assert!(output.contains("application/pdf), base64 encoded]\nJVBERuLj"));
+        assert_eq!(
+            spec[1]["content"][0]["image_url"]["url"],
+            "data:image/png;base64,iVBORw0K"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_response_to_message_tolerates_unknown_fields() -> anyhow::Result<()> {
+        let response = json!({
+            "id": "chatcmpl-123",
+            "object": "chat.completion",
+            "system_fingerprint": {"unexpected": "shape"},
+            "choices": [{
+                "index": 0,
+                "message": {
+                    "role": "assistant",
+                    "content": "Hello!",
+                    "refusal": null,
+                    "annotations": [{"type": "url_citation"}],
+                    "tool_calls": [{
+                        "id": "1",
+                        "type": "function",
+                        "function": {"name": "example_fn", "arguments": {"param": "value"}}
+                    }]
+                },
+                "logprobs": {"content": []},
+                "finish_reason": "tool_calls"
+            }],
+            "usage": {
+                "prompt_tokens": 10,
+                "completion_tokens": 5,
+                "total_tokens": 15,
+                "prompt_tokens_details": {"cached_tokens": 0}
+            },
+            "provider_routing": ["a", "b"]
+        });
+
+        let message = response_to_message(response.clone())?;
+        assert_eq!(message.content.len(), 2);
+        assert_eq!(message.content[0].as_text(), Some("Hello!"));
+        let request = message.content[1].as_tool_request().unwrap();
+        let tool_call = request.tool_call.as_ref().unwrap();
+        assert_eq!(tool_call.arguments, json!({"param": "value"}));
+
+        let usage = get_usage(&response)?;
+        assert_eq!(usage.total_tokens, Some(15));
+        Ok(())
+    }
+
+    #[test]
+    fn test_get_usage_with_partial_fields() -> anyhow::Result<()> {
+        let response = json!({"usage": {"prompt_tokens": 12, "completion_tokens": 3}});
+        let usage = get_usage(&response)?;
+        assert_eq!(usage.input_tokens, Some(12));
+        assert_eq!(usage.output_tokens, Some(3));
+        assert_eq!(usage.total_tokens, Some(15));
+
+        let response = json!({"usage": {"prompt_tokens": 12, "total_tokens": 20}});
+        let usage = get_usage(&response)?;
+        assert_eq!(usage.output_tokens, None);
+        assert_eq!(usage.total_tokens, Some(20));
+
+        // An inconsistent total is kept as reported
+        let response =
+            json!({"usage": {"prompt_tokens": 12, "completion_tokens": 3, "total_tokens": 40}});
+        assert_eq!(get_usage(&response)?.total_tokens, Some(40));
+
+        // A malformed count is left out, the others are kept
+        let response =
+            json!({"usage": {"prompt_tokens": "12", "completion_tokens": 3, "total_tokens": 15}});
+        let usage = get_usage(&response)?;
+        assert_eq!(usage.input_tokens, None);
+        assert_eq!(usage.output_tokens, Some(3));
+        assert_eq!(usage.total_tokens, Some(15));
+        Ok(())
+    }
+
+    #[test]
+    fn test_create_request_merges_extra_body() -> anyhow::Result<()> {
+        let model_config = ModelConfig::new("gpt-4o".to_string())
+            .with_temperature(Some(0.5))
+            .with_extra_body(Some(json!({
+                "route": "fallback",
+                "provider": {"order": ["a", "b"]},
+                "temperature": 1.0,
+                "model": "other-model",
+                "messages": []
+            })));
+        let messages = vec![Message::user().with_text("Hello")];
+        let payload = create_request(
+            &model_config,
+            "system",
+            &messages,
+            &[],
+            &ImageFormat::OpenAi,
+        )?;
+
+        assert_eq!(payload["route"], "fallback");
+        assert_eq!(payload["provider"], json!({"order": ["a", "b"]}));
+        assert_eq!(payload["temperature"], json!(0.5));
+        assert_eq!(payload["model"], "gpt-4o");
+        assert_eq!(payload["messages"].as_array().unwrap().len(), 2);
+        Ok(())
+    }
+
+    #[test]
+    fn test_create_request_is_deterministic() -> anyhow::Result<()> {
+        let schema = json!({
+            "type": "object",
+            "properties": {"b": {"type": "string"}, "a": {"type": "number"}}
+        });
+        let first = Tool::new("first", "First tool", schema.clone());
+        let second = Tool::new("second", "Second tool", schema);
+        let model_config = ModelConfig::new("gpt-4o".to_string()).with_temperature(Some(0.0));
+        let messages = vec![
+            Message::user().with_text("Hello"),
+            Message::assistant().with_text("Hi there"),
+        ];
+
+        let serialize = |tools: &[Tool]| -> anyhow::Result<String> {
+            let payload = create_request(
+                &model_config,
+                "system",
+                &messages,
+                tools,
+                &ImageFormat::OpenAi,
+            )?;
+            Ok(serde_json::to_string(&payload)?)
+        };
+
+        let a = serialize(&[first.clone(), second.clone()])?;
+        let b = serialize(&[first.clone(), second.clone()])?;
+        let c = serialize(&[second, first])?;
+        assert_eq!(a, b);
+        assert_eq!(a, c);
+        Ok(())
+    }
+
+    #[test]
+    fn test_create_request_stop_token_ids() -> anyhow::Result<()> {
+        let messages = vec![Message::user().with_text("Hello")];
+
+        let model_config =
+            ModelConfig::new("llama3.3".to_string()).with_stop_token_ids(vec![128001, 128009]);
+        let payload = create_request(&model_config, "", &messages, &[], &ImageFormat::OpenAi)?;
+        assert_eq!(payload["stop_token_ids"], json!([128001, 128009]));
+
+        let model_config = ModelConfig::new("llama3.3".to_string());
+        let payload = create_request(&model_config, "", &messages, &[], &ImageFormat::OpenAi)?;
+        assert!(payload.get("stop_token_ids").is_none());
+        Ok(())
+    }
+
+    #[test]
+    fn test_create_request_generation_params() -> anyhow::Result<()> {
+        let messages = vec![Message::user().with_text("Hello")];
+
+        let model_config = ModelConfig::new("gpt-4o".to_string())
+            .with_frequency_penalty(Some(0.5))
+            .with_presence_penalty(Some(-0.5))
+            .with_stop_sequences(vec!["\n\n".to_string()]);
+        let payload = create_request(&model_config, "", &messages, &[], &ImageFormat::OpenAi)?;
+        assert_eq!(payload["frequency_penalty"], json!(0.5));
+        assert_eq!(payload["presence_penalty"], json!(-0.5));
+        assert_eq!(payload["stop"], json!(["\n\n"]));
+
+        let model_config = ModelConfig::new("gpt-4o".to_string());
+        let payload = create_request(&model_config, "", &messages, &[], &ImageFormat::OpenAi)?;
+        for key in ["frequency_penalty", "presence_penalty", "stop"] {
+            assert!(payload.get(key).is_none());
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_openai_json_round_trip() -> anyhow::Result<()> {
+        let messages = vec![
+            Message::user().with_text("What is in this picture?"),
+            Message::user().with_image("aGVsbG8=", "image/png"),
+            Message::assistant()
+                .with_text("Let me check")
+                .with_tool_request(
+                    "call_1",
+                    Ok(ToolCall::new("describe_image", json!({"detail": "high"}))),
+                ),
+            Message::user().with_tool_response("call_1", Ok(vec![Content::text("A goose")])),
+            Message::assistant().with_text("It is a goose."),
+        ];
+
+        let value = messages_to_openai_json(&messages);
+        let parsed = messages_from_openai_json(&value)?;
+        assert_eq!(parsed.len(), messages.len());
+        for (parsed, original) in parsed.iter().zip(&messages) {
+            assert_eq!(parsed.role, original.role);
+            assert_eq!(parsed.content, original.content);
+        }
+        assert_eq!(messages_to_openai_json(&parsed), value);
+        Ok(())
+    }
+
+    #[test]
+    fn test_messages_from_openai_json_parts() -> anyhow::Result<()> {
+        let value = json!([
+            {"role": "user", "content": [
+                {"type": "text", "text": "Transcribe this"},
+                {"type": "input_audio", "input_audio": {"data": "UklGR", "format": "wav"}}
+            ]},
+            {"role": "assistant", "content": null, "tool_calls": [{
+                "id": "call_2",
+                "type": "function",
+                "function": {"name": "transcribe", "arguments": "{\"language\":\"en\"}"}
+            }]}
+        ]);
+        let messages = messages_from_openai_json(&value)?;
+        assert_eq!(messages[0].content.len(), 2);
+        assert!(matches!(messages[0].content[1], MessageContent::Audio(_)));
+        let request = messages[1].content[0].as_tool_request().unwrap();
+        assert_eq!(
+            request.tool_call.as_ref().unwrap().arguments,
+            json!({"language": "en"})
+        );
+
+        let system = json!([{"role": "system", "content": "Be brief"}]);
+        assert!(matches!(
+            messages_from_openai_json(&system),
+            Err(ProviderError::NotSupported(_))
+        ));
+        let remote_image = json!([{"role": "user", "content": [
+            {"type": "image_url", "image_url": {"url": "https://example.com/a.png"}}
+        ]}]);
+        assert!(messages_from_openai_json(&remote_image).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_web_search_tool_and_citations() -> anyhow::Result<()> {
+        let messages = vec![Message::user().with_text("What happened today?")];
+        let tools = vec![
+            web_search_tool(),
+            // A tool of its own by that name is sent like any other
+            Tool::new("web_search", "Search the web", json!({"type": "object"})),
+        ];
+        let model_config = ModelConfig::new("gpt-4o-search-preview".to_string());
+        let payload = create_request(&model_config, "", &messages, &tools, &ImageFormat::OpenAi)?;
+        assert_eq!(payload["web_search_options"], json!({}));
+        let tools = payload["tools"].as_array().unwrap();
+        assert_eq!(tools.len(), 1);
+        assert_eq!(tools[0]["function"]["name"], "web_search");
+        // Left to the backend's default, not every compatible backend accepts it
+        assert!(payload.get("tool_choice").is_none());
+
+        let response = json!({
+            "choices": [{
+                "message": {
+                    "role": "assistant",
+                    "content": "Goose 2.0 shipped.",
+                    "annotations": [{
+                        "type": "url_citation",
+                        "url_citation": {
+                            "url": "https://example.com/news",
+                            "title": "News",
+                            "start_index": 0,
+                            "end_index": 17
+                        }
+                    }]
+                }
+            }]
+        });
+        let message = response_to_message(response)?;
+        assert_eq!(
+            message.citations,
+            vec![Citation {
+                url: "https://example.com/news".to_string(),
+                title: Some("News".to_string()),
+                start_index: Some(0),
+                end_index: Some(17),
+            }]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_citations_from_url_list() -> anyhow::Result<()> {
+        let response = json!({
+            "citations": ["https://example.com/a", "https://example.com/b"],
+            "choices": [{"message": {"role": "assistant", "content": "Sources [1][2]"}}]
+        });
+        let message = response_to_message(response)?;
+        let urls: Vec<&str> = message.citations.iter().map(|c| c.url.as_str()).collect();
+        assert_eq!(urls, ["https://example.com/a", "https://example.com/b"]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_stream_accumulator_keeps_system_fingerprint() {
+        let mut accumulator = StreamAccumulator::new();
+        accumulator.push_chunk(&json!({"model": "gpt-4o", "system_fingerprint": "fp_44709d6fcb", "choices": [{"delta": {"content": "Hi"}}]}));
+        let fingerprint = accumulator
+            .finish()
+            .into_iter()
+            .find_map(|delta| match delta {
+                MessageDelta::Usage(usage) => Some(usage.system_fingerprint),
+                _ => None,
+            });
+        assert_eq!(fingerprint, Some(Some("fp_44709d6fcb".to_string())));
+    }
+
+    #[test]
+    fn test_stream_accumulator_collects_citations() {
+        let annotation = |url: &str| json!({"type": "url_citation", "url_citation": {"url": url, "start_index": 0, "end_index": 4}});
+        let chunks = [
+            json!({"choices": [{"delta": {"content": "News", "annotations": [annotation("https://example.com/a")]}}]}),
+            json!({"choices": [{"delta": {"content": " today", "annotations": [annotation("https://example.com/b")]}}]}),
+        ];
+
+        let mut accumulator = StreamAccumulator::new();
+        for chunk in &chunks {
+            accumulator.push_chunk(chunk);
+        }
+        match &accumulator.finish()[0] {
+            MessageDelta::Citations(citations) => {
+                let urls: Vec<&str> = citations.iter().map(|c| c.url.as_str()).collect();
+                assert_eq!(urls, ["https://example.com/a", "https://example.com/b"]);
+            }
+            _ => panic!("Expected Citations delta"),
+        }
+
+        // The url list is repeated in every chunk and only kept once
+        let mut accumulator = StreamAccumulator::new();
+        for _ in 0..3 {
+            accumulator.push_chunk(&json!({"citations": ["https://example.com/c"], "choices": []}));
+        }
+        match &accumulator.finish()[0] {
+            MessageDelta::Citations(citations) => assert_eq!(citations.len(), 1),
+            _ => panic!("Expected Citations delta"),
+        }
+    }
+
+    #[test]
+    fn test_create_request_prediction() -> anyhow::Result<()> {
+        let messages = vec![Message::user().with_text("Rename x to y")];
+        let model_config =
+            ModelConfig::new("gpt-4o".to_string()).with_prediction(Some("let x = 1;".to_string()));
+        let payload = create_request(&model_config, "", &messages, &[], &ImageFormat::OpenAi)?;
+        assert_eq!(
+            payload["prediction"],
+            json!({"type": "content", "content": "let x = 1;"})
+        );
+
+        let model_config = ModelConfig::new("gpt-4o".to_string());
+        let payload = create_request(&model_config, "", &messages, &[], &ImageFormat::OpenAi)?;
+        assert!(payload.get("prediction").is_none());
+
+        let response = json!({"usage": {
+            "prompt_tokens": 20,
+            "completion_tokens": 10,
+            "total_tokens": 30,
+            "completion_tokens_details": {
+                "accepted_prediction_tokens": 8,
+                "rejected_prediction_tokens": 2
+            }
+        }});
+        let usage = get_usage(&response)?;
+        assert_eq!(usage.accepted_prediction_tokens, Some(8));
+        assert_eq!(usage.rejected_prediction_tokens, Some(2));
+        Ok(())
+    }
+
+    #[test]
+    fn test_get_usage_reasoning_tokens() -> anyhow::Result<()> {
+        let response = json!({"usage": {
+            "prompt_tokens": 40,
+            "completion_tokens": 600,
+            "total_tokens": 640,
+            "completion_tokens_details": {"reasoning_tokens": 512}
+        }});
+        let usage = get_usage(&response)?;
+        assert_eq!(usage.output_tokens, Some(600));
+        assert_eq!(usage.reasoning_tokens, Some(512));
+        Ok(())
+    }
+
+    #[test]
+    fn test_get_usage_cached_tokens() -> anyhow::Result<()> {
+        let response = json!({"usage": {
+            "prompt_tokens": 2048,
+            "completion_tokens": 20,
+            "total_tokens": 2068,
+            "prompt_tokens_details": {"cached_tokens": 1920}
+        }});
+        let usage = get_usage(&response)?;
+        assert_eq!(usage.cached_input_tokens, Some(1920));
+        assert!(ProviderUsage::new("gpt-4o".to_string(), usage).cache_hit());
+
+        let response = json!({"usage": {
+            "prompt_tokens": 2048,
+            "completion_tokens": 20,
+            "prompt_tokens_details": {"cached_tokens": 0}
+        }});
+        let usage = ProviderUsage::new("gpt-4o".to_string(), get_usage(&response)?);
+        assert!(!usage.cache_hit());
+        Ok(())
+    }
+
+    #[test]
+    fn test_create_request_store() -> anyhow::Result<()> {
+        let messages = vec![Message::user().with_text("Hello")];
+
+        let metadata = HashMap::from([("team".to_string(), "evals".to_string())]);
+        let model_config = ModelConfig::new("gpt-4o".to_string())
+            .with_store(Some(true))
+            .with_metadata(Some(metadata));
+        let payload = create_request(&model_config, "", &messages, &[], &ImageFormat::OpenAi)?;
+        assert_eq!(payload["store"], json!(true));
+        assert_eq!(payload["metadata"], json!({"team": "evals"}));
+
+        let model_config = ModelConfig::new("gpt-4o".to_string());
+        let payload = create_request(&model_config, "", &messages, &[], &ImageFormat::OpenAi)?;
+        assert!(payload.get("store").is_none());
+        assert!(payload.get("metadata").is_none());
+        Ok(())
+    }
+
+    #[test]
+    fn test_create_request_cache_control() -> anyhow::Result<()> {
+        let tool = Tool::new("read", "Read a file", json!({"type": "object"}));
+        let messages = vec![
+            Message::user().with_text("Hello"),
+            Message::assistant()
+                .with_text("Hi")
+                .with_cache_control(CacheControl::Ephemeral),
+            Message::user().with_text("Bye"),
+        ];
+
+        let model_config = ModelConfig::new("gpt-4o".to_string());
+        let payload = create_request(
+            &model_config,
+            "system",
+            &messages,
+            std::slice::from_ref(&tool),
+            &ImageFormat::OpenAi,
+        )?;
+        assert_eq!(payload["messages"][0]["content"], "system");
+        assert!(payload["tools"][0].get("cache_control").is_none());
+        assert_eq!(
+            payload["messages"][2]["content"],
+            json!([{"type": "text", "text": "Hi", "cache_control": {"type": "ephemeral"}}])
+        );
+        assert_eq!(payload["messages"][3]["content"], "Bye");
+
+        let model_config = model_config.with_cache_control(Some(CacheControl::Ephemeral));
+        let payload = create_request(
+            &model_config,
+            "system",
+            &messages,
+            &[tool],
+            &ImageFormat::OpenAi,
+        )?;
+        assert_eq!(
+            payload["messages"][0]["content"][0]["cache_control"],
+            json!({"type": "ephemeral"})
+        );
+        assert_eq!(
+            payload["tools"][0]["cache_control"],
+            json!({"type": "ephemeral"})
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_response_to_models() -> anyhow::Result<()> {
+        let response = json!({
+            "object": "list",
+            "data": [
+                {"id": "gpt-4o-mini", "object": "model", "owned_by": "openai"},
+                {"id": "claude-3-5-sonnet", "object": "model"},
+                {"object": "model"},
+                {"id": "gpt-4o-mini", "object": "model"}
+            ]
+        });
+        assert_eq!(
+            response_to_models(&response)?,
+            vec!["claude-3-5-sonnet", "gpt-4o-mini"]
+        );
+        assert!(response_to_models(&json!({"error": "nope"})).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_response_to_embeddings() -> anyhow::Result<()> {
+        let inputs = vec!["first".to_string(), "second".to_string()];
+        let payload = create_embedding_request("text-embedding-3-small", &inputs);
+        assert_eq!(payload["input"], json!(["first", "second"]));
+
+        let response = json!({
+            "object": "list",
+            "data": [
+                {"object": "embedding", "index": 1, "embedding": [0.5, -0.25]},
+                {"object": "embedding", "index": 0, "embedding": [1.0, 0.0]}
+            ],
+            "usage": {"prompt_tokens": 2, "total_tokens": 2}
+        });
+        let embeddings = response_to_embeddings(&response, inputs.len())?;
+        assert_eq!(embeddings, vec![vec![1.0, 0.0], vec![0.5, -0.25]]);
+
+        assert!(response_to_embeddings(&response, 3).is_err());
+        assert!(response_to_embeddings(&response, 1).is_err());
+        assert!(response_to_embeddings(&json!({"data": [{"embedding": "abc"}]}), 1).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_create_request_response_format() -> anyhow::Result<()> {
+        let messages = vec![Message::user().with_text("List three colors")];
+        let schema = json!({
+            "type": "object",
+            "properties": {"colors": {"type": "array", "items": {"type": "string"}}},
+            "required": ["colors"]
+        });
+
+        let model_config = ModelConfig::new("gpt-4o".to_string()).with_response_format(Some(
+            ResponseFormat::JsonSchema {
+                name: "colors".to_string(),
+                schema: schema.clone(),
+                strict: true,
+            },
+        ));
+        let payload = create_request(&model_config, "", &messages, &[], &ImageFormat::OpenAi)?;
+        assert_eq!(
+            payload["response_format"],
+            json!({
+                "type": "json_schema",
+                "json_schema": {"name": "colors", "schema": schema, "strict": true}
+            })
+        );
+
+        // The typed format wins over one in the extra body
+        let model_config = ModelConfig::new("gpt-4o".to_string())
+            .with_response_format(Some(ResponseFormat::JsonObject))
+            .with_extra_body(Some(json!({"response_format": {"type": "text"}})));
+        let payload = create_request(&model_config, "", &messages, &[], &ImageFormat::OpenAi)?;
+        assert_eq!(payload["response_format"], json!({"type": "json_object"}));
+
+        let model_config = ModelConfig::new("gpt-4o".to_string());
+        let payload = create_request(&model_config, "", &messages, &[], &ImageFormat::OpenAi)?;
+        assert!(payload.get("response_format").is_none());
+        Ok(())
+    }
+
+    #[test]
+    fn test_create_request_system_as_user() -> anyhow::Result<()> {
+        let model_config = ModelConfig::new("gemma-2-9b-it".to_string());
+        let messages = vec![Message::user().with_text("Hello")];
+        let payload = create_request(
+            &model_config,
+            "Be brief",
+            &messages,
+            &[],
+            &ImageFormat::OpenAi,
+        )?;
+        assert_eq!(
+            payload["messages"],
+            json!([{"role": "user", "content": "Be brief\n\nHello"}])
+        );
+
+        // A conversation that does not start with the user gets a turn of its own
+        let messages = vec![Message::assistant().with_text("Hi")];
+        let payload = create_request(
+            &model_config,
+            "Be brief",
+            &messages,
+            &[],
+            &ImageFormat::OpenAi,
+        )?;
+        assert_eq!(
+            payload["messages"][0],
+            json!({"role": "user", "content": "Be brief"})
+        );
+        assert_eq!(payload["messages"][1]["role"], "assistant");
+        Ok(())
+    }
+
+    #[test]
+    fn test_create_request_timestamps() -> anyhow::Result<()> {
+        let mut message = Message::user().with_text("Hello");
+        message.created = 1_700_000_000;
+        let messages = vec![message];
+
+        let model_config = ModelConfig::new("gpt-4o".to_string());
+        let payload = create_request(&model_config, "", &messages, &[], &ImageFormat::OpenAi)?;
+        assert_eq!(payload["messages"][1]["content"], "Hello");
+
+        let model_config = model_config.with_include_timestamps(true);
+        let payload = create_request(&model_config, "", &messages, &[], &ImageFormat::OpenAi)?;
+        assert_eq!(
+            payload["messages"][1]["content"],
+            "[2023-11-14T22:13:20Z] Hello"
+        );
+
+        // The timestamp survives serialization of the message itself, for recordings
+        let restored: Message = serde_json::from_str(&serde_json::to_string(&messages[0])?)?;
+        assert_eq!(restored.created, 1_700_000_000);
+        Ok(())
+    }
+
     #[test]
     fn test_format_tools() -> anyhow::Result<()> {
         let tool = Tool::new(
@@ -359,6 +1775,111 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn test_response_to_message_reasoning_summary() -> anyhow::Result<()> {
+        // Chat completions don't take the parameter, gateways may still send a summary
+        let model = ModelConfig::new("o3-mini".to_string()).with_reasoning_summary(true);
+        let request = create_request(&model, "system", &[], &[], &ImageFormat::OpenAi)?;
+        assert!(request.get("reasoning").is_none());
+
+        let response = json!({
+            "choices": [{
+                "message": {
+                    "role": "assistant",
+                    "content": "The answer is 12.",
+                    "reasoning": "Multiplied 3 by 4 and checked the result."
+                },
+                "finish_reason": "stop"
+            }]
+        });
+        let message = response_to_message(response)?;
+        assert_eq!(
+            message.content[0].as_thinking(),
+            Some("Multiplied 3 by 4 and checked the result.")
+        );
+        // The summary is kept apart from the answer and not sent back
+        assert_eq!(message.as_concat_text(), "The answer is 12.");
+        let spec = format_messages(&[message], &ImageFormat::OpenAi);
+        assert_eq!(spec[0]["content"], "The answer is 12.");
+        assert!(!spec[0].to_string().contains("Multiplied"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_create_request_reasoning_effort() -> anyhow::Result<()> {
+        let model = ModelConfig::new("o3-mini".to_string())
+            .with_reasoning_effort(Some(ReasoningEffort::High));
+        let request = create_request(&model, "system", &[], &[], &ImageFormat::OpenAi)?;
+        assert_eq!(request["reasoning_effort"], "high");
+
+        let model = ModelConfig::new("o3-mini".to_string());
+        let request = create_request(&model, "system", &[], &[], &ImageFormat::OpenAi)?;
+        assert!(request.get("reasoning_effort").is_none());
+        Ok(())
+    }
+
+    #[test]
+    fn test_strict_functions() -> anyhow::Result<()> {
+        let tool = Tool::new(
+            "add_event",
+            "Add a calendar event",
+            json!({
+                "type": "object",
+                "properties": {
+                    "title": {"type": "string"},
+                    "attendees": {
+                        "type": "array",
+                        "items": {
+                            "type": "object",
+                            "properties": {"email": {"type": "string"}},
+                            "required": ["email"]
+                        }
+                    }
+                },
+                "required": ["title", "attendees"]
+            }),
+        );
+        let model = ModelConfig::new("gpt-4o".to_string()).with_strict_functions(true);
+        let request = create_request(&model, "", &[], &[tool], &ImageFormat::OpenAi)?;
+
+        let function = &request["tools"][0]["function"];
+        assert_eq!(function["strict"], json!(true));
+        let parameters = &function["parameters"];
+        assert_eq!(parameters["additionalProperties"], json!(false));
+        assert_eq!(
+            parameters["properties"]["attendees"]["items"]["additionalProperties"],
+            json!(false)
+        );
+        assert_eq!(parameters["properties"]["title"], json!({"type": "string"}));
+
+        // Without strict mode the schema is sent as given
+        let plain = ModelConfig::new("gpt-4o".to_string());
+        let tool = Tool::new(
+            "search",
+            "Search",
+            json!({"type": "object", "properties": {"query": {"type": "string"}, "limit": {"type": "integer"}}, "required": ["query"]}),
+        );
+        let request = create_request(
+            &plain,
+            "",
+            &[],
+            std::slice::from_ref(&tool),
+            &ImageFormat::OpenAi,
+        )?;
+        assert!(request["tools"][0]["function"].get("strict").is_none());
+
+        // An optional property can not be made strict
+        let error = create_request(&model, "", &[], &[tool], &ImageFormat::OpenAi)
+            .unwrap_err()
+            .to_string();
+        assert!(
+            error.contains("parameters.properties.limit is optional"),
+            "{}",
+            error
+        );
+        Ok(())
+    }
+
     #[test]
     fn test_format_messages_complex() -> anyhow::Result<()> {
         let mut messages = vec![
@@ -502,6 +2023,129 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn test_response_to_message_audio() -> anyhow::Result<()> {
+        let response = json!({
+            "choices": [{
+                "message": {
+                    "role": "assistant",
+                    "content": null,
+                    "audio": {
+                        "id": "audio_1",
+                        "data": "aGVsbG8=",
+                        "expires_at": 1729018505,
+                        "transcript": "hello"
+                    }
+                }
+            }]
+        });
+
+        let message = response_to_message(response)?;
+        assert_eq!(message.as_concat_text(), "hello");
+        match &message.content[1] {
+            MessageContent::Audio(audio) => {
+                assert_eq!(audio.format, "wav");
+                assert_eq!(audio.bytes()?, b"hello");
+            }
+            _ => panic!("Expected Audio content"),
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_response_to_message_refusal() -> anyhow::Result<()> {
+        let response = json!({
+            "choices": [{
+                "message": {
+                    "role": "assistant",
+                    "content": null,
+                    "refusal": "I'm sorry, I can't help with that request."
+                },
+                "finish_reason": "stop"
+            }]
+        });
+        let message = response_to_message(response)?;
+        assert_eq!(message.content.len(), 1);
+        assert_eq!(
+            message.content[0].as_refusal(),
+            Some("I'm sorry, I can't help with that request.")
+        );
+        assert_eq!(message.as_concat_text(), "");
+        // The refusal is kept when the history is sent back
+        let spec = messages_to_openai_json(std::slice::from_ref(&message));
+        assert_eq!(
+            messages_from_openai_json(&spec)?[0].content,
+            message.content
+        );
+
+        let empty = json!({"choices": [{"message": {"role": "assistant", "content": null}}]});
+        assert!(response_to_message(empty).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_create_request_audio_output() -> anyhow::Result<()> {
+        let messages = vec![Message::user().with_text("Say hello")];
+        let model_config = ModelConfig::new("gpt-4o-audio-preview".to_string()).with_audio_output(
+            Some(AudioOutput {
+                voice: "alloy".to_string(),
+                format: "mp3".to_string(),
+            }),
+        );
+        let payload = create_request(&model_config, "", &messages, &[], &ImageFormat::OpenAi)?;
+        assert_eq!(payload["modalities"], json!(["text", "audio"]));
+        assert_eq!(payload["audio"], json!({"voice": "alloy", "format": "mp3"}));
+
+        let model_config = ModelConfig::new("gpt-4o-audio-preview".to_string());
+        let payload = create_request(&model_config, "", &messages, &[], &ImageFormat::OpenAi)?;
+        assert!(payload.get("modalities").is_none());
+
+        // A reply with both text and speech keeps the text once, next to the audio
+        let response = json!({
+            "choices": [{
+                "message": {
+                    "role": "assistant",
+                    "content": "Hello!",
+                    "audio": {"id": "audio_1", "data": "aGVsbG8=", "format": "mp3", "transcript": "Hello!"}
+                }
+            }]
+        });
+        let message = response_to_message(response)?;
+        assert_eq!(message.content.len(), 2);
+        assert_eq!(message.as_concat_text(), "Hello!");
+        match &message.content[1] {
+            MessageContent::Audio(audio) => assert_eq!(audio.format, "mp3"),
+            _ => panic!("Expected Audio content"),
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_stream_accumulator_assembles_audio() -> anyhow::Result<()> {
+        let chunks = [
+            json!({"choices": [{"delta": {"audio": {"id": "audio_1", "transcript": "hel"}}}]}),
+            json!({"choices": [{"delta": {"audio": {"data": "aGVs", "transcript": "lo"}}}]}),
+            json!({"choices": [{"delta": {"audio": {"data": "bG8="}}}]}),
+        ];
+
+        let mut accumulator = StreamAccumulator::new();
+        let mut deltas = Vec::new();
+        for chunk in &chunks {
+            deltas.extend(accumulator.push_chunk(chunk));
+        }
+        // Transcripts stream like text, the clip arrives once complete
+        assert_eq!(deltas.len(), 2);
+        deltas.extend(accumulator.finish());
+        match &deltas[2] {
+            MessageDelta::Content(MessageContent::Audio(audio)) => {
+                assert_eq!(audio.bytes()?, b"hello")
+            }
+            _ => panic!("Expected Audio content"),
+        }
+        assert!(matches!(deltas[3], MessageDelta::Usage(_)));
+        Ok(())
+    }
+
     #[test]
     fn test_response_to_message_valid_toolrequest() -> anyhow::Result<()> {
         let response: Value = serde_json::from_str(OPENAI_TOOL_USE_RESPONSE)?;
@@ -562,4 +2206,59 @@ mod tests {
 
         Ok(())
     }
+
+    #[test]
+    fn test_stream_accumulator_assembles_tool_calls() -> anyhow::Result<()> {
+        let chunks = [
+            json!({"model": "gpt-4o", "choices": [{"delta": {"content": "Let me check"}}]}),
+            json!({"choices": [{"delta": {"tool_calls": [{
+                "index": 0, "id": "call_1",
+                "function": {"name": "example_fn", "arguments": "{\"param\""}
+            }]}}]}),
+            json!({"choices": [{"delta": {"tool_calls": [{
+                "index": 0, "function": {"arguments": ": \"value\"}"}
+            }]}, "finish_reason": "tool_calls"}]}),
+            json!({"choices": [], "usage": {"prompt_tokens": 10, "completion_tokens": 5}}),
+        ];
+
+        let mut accumulator = StreamAccumulator::new();
+        let mut deltas = Vec::new();
+        for chunk in &chunks {
+            deltas.extend(accumulator.push_chunk(chunk));
+        }
+        // The text and the pieces of the tool call are emitted while the stream is in flight
+        assert_eq!(deltas.len(), 3);
+        assert!(matches!(
+            &deltas[1],
+            MessageDelta::ToolCallFragment { index: 0, name: Some(name), arguments }
+                if name == "example_fn" && arguments == "{\"param\""
+        ));
+        assert!(matches!(
+            &deltas[2],
+            MessageDelta::ToolCallFragment { index: 0, name: None, arguments }
+                if arguments == ": \"value\"}"
+        ));
+        deltas.extend(accumulator.finish());
+        assert_eq!(deltas.len(), 6);
+
+        match &deltas[3] {
+            MessageDelta::Content(MessageContent::ToolRequest(request)) => {
+                assert_eq!(request.id, "call_1");
+                let tool_call = request.tool_call.as_ref().unwrap();
+                assert_eq!(tool_call.name, "example_fn");
+                assert_eq!(tool_call.arguments, json!({"param": "value"}));
+            }
+            _ => panic!("Expected ToolRequest content"),
+        }
+        match &deltas[4] {
+            MessageDelta::Usage(usage) => {
+                assert_eq!(usage.model, "gpt-4o");
+                assert_eq!(usage.usage.total_tokens, Some(15));
+            }
+            _ => panic!("Expected Usage delta"),
+        }
+        assert!(matches!(&deltas[5], MessageDelta::Finish(reason) if reason == "tool_calls"));
+
+        Ok(())
+    }
 }
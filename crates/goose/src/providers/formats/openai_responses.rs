@@ -0,0 +1,316 @@
+use crate::message::{Message, MessageContent};
+use crate::providers::base::Usage;
+use crate::providers::formats::openai::tool_call_to_content;
+use anyhow::anyhow;
+use mcp_core::Role;
+use serde_json::{json, Map, Value};
+
+/// Convert a chat completions request to the Responses API, continuing `previous_response_id`
+///
+/// The system message becomes the `instructions`, messages and tool results
+/// become input items and the tools lose their `function` wrapper. Responses
+/// are always stored unless `store` is disabled, since later turns can only
+/// continue from a stored response.
+pub fn create_responses_request(chat: Value, previous_response_id: Option<&str>) -> Value {
+    let Value::Object(chat) = chat else {
+        return chat;
+    };
+    let mut payload = Map::new();
+    let mut reasoning = Map::new();
+    for (key, value) in chat {
+        match key.as_str() {
+            "messages" => {
+                let messages = value.as_array().cloned().unwrap_or_default();
+                let (instructions, input) = format_input(messages);
+                if let Some(instructions) = instructions {
+                    payload.insert("instructions".to_string(), json!(instructions));
+                }
+                payload.insert("input".to_string(), json!(input));
+            }
+            "tools" => {
+                let tools: Vec<Value> = value
+                    .as_array()
+                    .into_iter()
+                    .flatten()
+                    .map(|tool| {
+                        let mut function = tool["function"].clone();
+                        function["type"] = json!("function");
+                        function
+                    })
+                    .collect();
+                payload.insert("tools".to_string(), json!(tools));
+            }
+            "max_tokens" => {
+                payload.insert("max_output_tokens".to_string(), value);
+            }
+            "reasoning_effort" => {
+                reasoning.insert("effort".to_string(), value);
+            }
+            "reasoning" => {
+                if let Value::Object(options) = value {
+                    reasoning.extend(options);
+                }
+            }
+            "response_format" => {
+                let mut format = value["json_schema"].clone();
+                match format.as_object_mut() {
+                    Some(schema) => {
+                        schema.insert("type".to_string(), json!("json_schema"));
+                    }
+                    None => format = value,
+                }
+                payload.insert("text".to_string(), json!({"format": format}));
+            }
+            "web_search_options" => {
+                let tools = payload.entry("tools").or_insert_with(|| json!([]));
+                if let Some(tools) = tools.as_array_mut() {
+                    tools.push(json!({"type": "web_search_preview"}));
+                }
+            }
+            // Usage is always part of the response
+            "stream_options" => {}
+            _ => {
+                payload.insert(key, value);
+            }
+        }
+    }
+    if !reasoning.is_empty() {
+        payload.insert("reasoning".to_string(), Value::Object(reasoning));
+    }
+    payload.entry("store").or_insert(json!(true));
+    if let Some(id) = previous_response_id {
+        payload.insert("previous_response_id".to_string(), json!(id));
+    }
+    Value::Object(payload)
+}
+
+/// Split chat messages into the instructions and the input items
+fn format_input(messages: Vec<Value>) -> (Option<String>, Vec<Value>) {
+    let mut messages = messages.into_iter().peekable();
+    let instructions = messages
+        .next_if(|message| message["role"] == "system")
+        .and_then(|message| message["content"].as_str().map(str::to_string))
+        .filter(|instructions| !instructions.is_empty());
+
+    let mut input = Vec::new();
+    for mut message in messages {
+        if message["role"] == "tool" {
+            input.push(json!({
+                "type": "function_call_output",
+                "call_id": message["tool_call_id"],
+                "output": message["content"],
+            }));
+            continue;
+        }
+
+        let tool_calls = message
+            .as_object_mut()
+            .and_then(|message| message.remove("tool_calls"));
+        if let Some(object) = message.as_object_mut() {
+            // Refusals are not sent back, the model only needs to know it answered
+            object.remove("refusal");
+        }
+        let is_assistant = message["role"] == "assistant";
+        if let Some(Value::Array(parts)) = message.get_mut("content") {
+            for part in parts.iter_mut() {
+                format_part(part, is_assistant);
+            }
+        }
+        if message.get("content").is_some() {
+            input.push(message);
+        }
+
+        for call in tool_calls
+            .iter()
+            .filter_map(|calls| calls.as_array())
+            .flatten()
+        {
+            input.push(json!({
+                "type": "function_call",
+                "call_id": call["id"],
+                "name": call["function"]["name"],
+                "arguments": call["function"]["arguments"],
+            }));
+        }
+    }
+    (instructions, input)
+}
+
+/// Rename a chat content part to its Responses API type
+fn format_part(part: &mut Value, is_assistant: bool) {
+    match part["type"].as_str() {
+        Some("text") if is_assistant => part["type"] = json!("output_text"),
+        Some("text") => part["type"] = json!("input_text"),
+        Some("image_url") => {
+            *part = json!({"type": "input_image", "image_url": part["image_url"]["url"]});
+        }
+        _ => {}
+    }
+}
+
+/// Convert the output items of a Responses API response to a message
+pub fn responses_to_message(response: &Value) -> anyhow::Result<Message> {
+    let mut content = Vec::new();
+    for item in response["output"].as_array().into_iter().flatten() {
+        match item["type"].as_str() {
+            Some("reasoning") => {
+                let summary: Vec<&str> = item["summary"]
+                    .as_array()
+                    .into_iter()
+                    .flatten()
+                    .filter_map(|part| part["text"].as_str())
+                    .collect();
+                if !summary.is_empty() {
+                    content.push(MessageContent::thinking(summary.join("\n\n")));
+                }
+            }
+            Some("message") => {
+                for part in item["content"].as_array().into_iter().flatten() {
+                    match part["type"].as_str() {
+                        Some("output_text") => {
+                            content.push(MessageContent::text(
+                                part["text"].as_str().unwrap_or_default(),
+                            ));
+                        }
+                        Some("refusal") => {
+                            content.push(MessageContent::refusal(
+                                part["refusal"].as_str().unwrap_or_default(),
+                            ));
+                        }
+                        _ => {}
+                    }
+                }
+            }
+            Some("function_call") => {
+                let id = item["call_id"].as_str().unwrap_or_default().to_string();
+                let name = item["name"].as_str().unwrap_or_default();
+                let arguments = item["arguments"].as_str().unwrap_or_default();
+                content.push(tool_call_to_content(id, name, arguments));
+            }
+            _ => {}
+        }
+    }
+
+    if content.is_empty() {
+        return Err(anyhow!("Response has neither output text nor tool calls"));
+    }
+    Ok(Message {
+        role: Role::Assistant,
+        created: chrono::Utc::now().timestamp(),
+        content,
+        citations: Vec::new(),
+        cache_control: None,
+        sensitive: Vec::new(),
+    })
+}
+
+/// The usage of a Responses API response, empty when it is missing
+pub fn get_responses_usage(response: &Value) -> Usage {
+    let usage = &response["usage"];
+    let count = |value: &Value| value.as_i64().map(|v| v as i32);
+    Usage::new(
+        count(&usage["input_tokens"]),
+        count(&usage["output_tokens"]),
+        count(&usage["total_tokens"]),
+    )
+    .with_cached_input_tokens(count(&usage["input_tokens_details"]["cached_tokens"]))
+    .with_reasoning_tokens(count(&usage["output_tokens_details"]["reasoning_tokens"]))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use mcp_core::ToolCall;
+
+    #[test]
+    fn test_create_responses_request() {
+        let chat = json!({
+            "model": "gpt-4o",
+            "messages": [
+                {"role": "system", "content": "Be brief"},
+                {"role": "user", "content": [{"type": "text", "text": "List files"}]},
+                {"role": "assistant", "content": "Listing", "tool_calls": [{
+                    "id": "call_1",
+                    "type": "function",
+                    "function": {"name": "shell", "arguments": "{\"command\":\"ls\"}"}
+                }]},
+                {"role": "tool", "tool_call_id": "call_1", "content": "README.md"}
+            ],
+            "tools": [{"type": "function", "function": {"name": "shell", "parameters": {}}}],
+            "tool_choice": "auto",
+            "max_tokens": 100,
+            "reasoning_effort": "low"
+        });
+
+        let request = create_responses_request(chat, Some("resp_1"));
+        assert_eq!(
+            request,
+            json!({
+                "model": "gpt-4o",
+                "instructions": "Be brief",
+                "input": [
+                    {"role": "user", "content": [{"type": "input_text", "text": "List files"}]},
+                    {"role": "assistant", "content": "Listing"},
+                    {
+                        "type": "function_call",
+                        "call_id": "call_1",
+                        "name": "shell",
+                        "arguments": "{\"command\":\"ls\"}"
+                    },
+                    {"type": "function_call_output", "call_id": "call_1", "output": "README.md"}
+                ],
+                "tools": [{"type": "function", "name": "shell", "parameters": {}}],
+                "tool_choice": "auto",
+                "max_output_tokens": 100,
+                "reasoning": {"effort": "low"},
+                "store": true,
+                "previous_response_id": "resp_1"
+            })
+        );
+    }
+
+    #[test]
+    fn test_responses_to_message() -> anyhow::Result<()> {
+        let response = json!({
+            "id": "resp_2",
+            "output": [
+                {"type": "reasoning", "summary": [{"type": "summary_text", "text": "Look first"}]},
+                {"type": "message", "role": "assistant", "content": [
+                    {"type": "output_text", "text": "Listing files", "annotations": []}
+                ]},
+                {
+                    "type": "function_call",
+                    "call_id": "call_2",
+                    "name": "shell",
+                    "arguments": "{\"command\":\"ls\"}"
+                }
+            ],
+            "usage": {
+                "input_tokens": 20,
+                "input_tokens_details": {"cached_tokens": 8},
+                "output_tokens": 6,
+                "total_tokens": 26
+            }
+        });
+
+        let message = responses_to_message(&response)?;
+        assert_eq!(message.content[0], MessageContent::thinking("Look first"));
+        assert_eq!(message.as_concat_text(), "Listing files");
+        assert_eq!(
+            message.content[2],
+            MessageContent::tool_request(
+                "call_2",
+                Ok(ToolCall::new("shell", json!({"command": "ls"})))
+            )
+        );
+
+        let usage = get_responses_usage(&response);
+        assert_eq!(usage.input_tokens, Some(20));
+        assert_eq!(usage.output_tokens, Some(6));
+        assert_eq!(usage.total_tokens, Some(26));
+        assert_eq!(usage.cached_input_tokens, Some(8));
+
+        assert!(responses_to_message(&json!({"output": []})).is_err());
+        Ok(())
+    }
+}
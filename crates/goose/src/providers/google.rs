@@ -1,9 +1,12 @@
 use super::errors::ProviderError;
+use crate::config::Secret;
 use crate::message::Message;
 use crate::model::ModelConfig;
 use crate::providers::base::{ConfigKey, Provider, ProviderMetadata, ProviderUsage};
 use crate::providers::formats::google::{create_request, get_usage, response_to_message};
-use crate::providers::utils::{emit_debug_trace, unescape_json_values};
+use crate::providers::utils::{
+    emit_debug_trace, rate_limit_reset, send_with_retry, unescape_json_values, RetryConfig,
+};
 use anyhow::Result;
 use async_trait::async_trait;
 use mcp_core::tool::Tool;
@@ -29,8 +32,9 @@ pub struct GoogleProvider {
     #[serde(skip)]
     client: Client,
     host: String,
-    api_key: String,
+    api_key: Secret,
     model: ModelConfig,
+    retry: RetryConfig,
 }
 
 impl Default for GoogleProvider {
@@ -43,7 +47,7 @@ impl Default for GoogleProvider {
 impl GoogleProvider {
     pub fn from_env(model: ModelConfig) -> Result<Self> {
         let config = crate::config::Config::global();
-        let api_key: String = config.get_secret("GOOGLE_API_KEY")?;
+        let api_key = config.get_secret_handle("GOOGLE_API_KEY")?;
         let host: String = config
             .get("GOOGLE_HOST")
             .unwrap_or_else(|_| GOOGLE_API_HOST.to_string());
@@ -57,6 +61,7 @@ impl GoogleProvider {
             host,
             api_key,
             model,
+            retry: RetryConfig::default(),
         })
     }
 
@@ -65,25 +70,29 @@ impl GoogleProvider {
             "{}/v1beta/models/{}:generateContent?key={}",
             self.host.trim_end_matches('/'),
             self.model.model_name,
-            self.api_key
+            self.api_key.expose()
         );
 
-        let response = self
-            .client
-            .post(&url)
-            .header("CONTENT_TYPE", "application/json")
-            .json(&payload)
-            .send()
-            .await?;
+        let response = send_with_retry(&self.retry, || async {
+            Ok(self
+                .client
+                .post(&url)
+                .header("CONTENT_TYPE", "application/json")
+                .json(&payload)
+                .send()
+                .await?)
+        })
+        .await?;
 
         let status = response.status();
+        let reset = rate_limit_reset(response.headers());
         let payload: Option<Value> = response.json().await.ok();
 
         match status {
-            StatusCode::OK =>  payload.ok_or_else( || ProviderError::RequestFailed("Response body is not valid JSON".to_string()) ),
+            StatusCode::OK =>  payload.ok_or_else( || ProviderError::request_failed("Response body is not valid JSON".to_string()) ),
             StatusCode::UNAUTHORIZED | StatusCode::FORBIDDEN => {
-                Err(ProviderError::Authentication(format!("Authentication failed. Please ensure your API keys are valid and have the required permissions. \
-                    Status: {}. Response: {:?}", status, payload )))
+                Err(ProviderError::authentication(format!("Authentication failed. Please ensure your API keys are valid and have the required permissions. \
+                    Status: {}. Response: {:?}", status, payload)).with_key_of(&Self::metadata()))
             }
             StatusCode::BAD_REQUEST => {
                 let mut error_msg = "Unknown error".to_string();
@@ -99,10 +108,10 @@ impl GoogleProvider {
                 tracing::debug!(
                     "{}", format!("Provider request failed with status: {}. Payload: {:?}", status, payload)
                 );
-                Err(ProviderError::RequestFailed(format!("Request failed with status: {}. Message: {}", status, error_msg)))
+                Err(ProviderError::request_failed(format!("Request failed with status: {}. Message: {}", status, error_msg)))
             }
             StatusCode::TOO_MANY_REQUESTS => {
-                Err(ProviderError::RateLimitExceeded(format!("{:?}", payload)))
+                Err(ProviderError::rate_limited(format!("{:?}", payload)).with_retry_after(reset))
             }
             StatusCode::INTERNAL_SERVER_ERROR | StatusCode::SERVICE_UNAVAILABLE => {
                 Err(ProviderError::ServerError(format!("{:?}", payload)))
@@ -111,7 +120,7 @@ impl GoogleProvider {
                 tracing::debug!(
                     "{}", format!("Provider request failed with status: {}. Payload: {:?}", status, payload)
                 );
-                Err(ProviderError::RequestFailed(format!("Request failed with status: {}", status)))
+                Err(ProviderError::request_failed(format!("Request failed with status: {}", status)))
             }
         }
     }
@@ -134,6 +143,10 @@ impl Provider for GoogleProvider {
         )
     }
 
+    fn instance_metadata(&self) -> ProviderMetadata {
+        Self::metadata()
+    }
+
     fn get_model_config(&self) -> ModelConfig {
         self.model.clone()
     }
@@ -160,7 +173,7 @@ impl Provider for GoogleProvider {
             Some(model_version) => model_version.as_str().unwrap_or_default().to_string(),
             None => self.model.model_name.clone(),
         };
-        emit_debug_trace(self, &payload, &response, &usage);
+        emit_debug_trace(self, messages, &payload, &response, &usage);
         let provider_usage = ProviderUsage::new(model, usage);
         Ok((message, provider_usage))
     }
@@ -1,9 +1,12 @@
 use super::errors::ProviderError;
+use crate::config::Secret;
 use crate::message::Message;
 use crate::model::ModelConfig;
 use crate::providers::base::{ConfigKey, Provider, ProviderMetadata, ProviderUsage, Usage};
 use crate::providers::formats::openai::{create_request, get_usage, response_to_message};
-use crate::providers::utils::get_model;
+use crate::providers::utils::{
+    get_model, get_system_fingerprint, rate_limit_reset, send_with_retry, RetryConfig,
+};
 use anyhow::Result;
 use async_trait::async_trait;
 use mcp_core::Tool;
@@ -22,8 +25,9 @@ pub struct GroqProvider {
     #[serde(skip)]
     client: Client,
     host: String,
-    api_key: String,
+    api_key: Secret,
     model: ModelConfig,
+    retry: RetryConfig,
 }
 
 impl Default for GroqProvider {
@@ -36,7 +40,7 @@ impl Default for GroqProvider {
 impl GroqProvider {
     pub fn from_env(model: ModelConfig) -> Result<Self> {
         let config = crate::config::Config::global();
-        let api_key: String = config.get_secret("GROQ_API_KEY")?;
+        let api_key = config.get_secret_handle("GROQ_API_KEY")?;
         let host: String = config
             .get("GROQ_HOST")
             .unwrap_or_else(|_| GROQ_API_HOST.to_string());
@@ -50,6 +54,7 @@ impl GroqProvider {
             host,
             api_key,
             model,
+            retry: RetryConfig::default(),
         })
     }
 
@@ -59,28 +64,32 @@ impl GroqProvider {
             self.host.trim_end_matches('/')
         );
 
-        let response = self
-            .client
-            .post(&url)
-            .header("Authorization", format!("Bearer {}", self.api_key))
-            .json(&payload)
-            .send()
-            .await?;
+        let response = send_with_retry(&self.retry, || async {
+            Ok(self
+                .client
+                .post(&url)
+                .header("Authorization", format!("Bearer {}", self.api_key.expose()))
+                .json(&payload)
+                .send()
+                .await?)
+        })
+        .await?;
 
         let status = response.status();
+        let reset = rate_limit_reset(response.headers());
         let payload: Option<Value> = response.json().await.ok();
 
         match status {
-            StatusCode::OK => payload.ok_or_else( || ProviderError::RequestFailed("Response body is not valid JSON".to_string()) ),
+            StatusCode::OK => payload.ok_or_else( || ProviderError::request_failed("Response body is not valid JSON".to_string()) ),
             StatusCode::UNAUTHORIZED | StatusCode::FORBIDDEN => {
-                Err(ProviderError::Authentication(format!("Authentication failed. Please ensure your API keys are valid and have the required permissions. \
-                    Status: {}. Response: {:?}", status, payload)))
+                Err(ProviderError::authentication(format!("Authentication failed. Please ensure your API keys are valid and have the required permissions. \
+                    Status: {}. Response: {:?}", status, payload)).with_key_of(&Self::metadata()))
             }
             StatusCode::PAYLOAD_TOO_LARGE => {
                 Err(ProviderError::ContextLengthExceeded(format!("{:?}", payload)))
             }
             StatusCode::TOO_MANY_REQUESTS => {
-                Err(ProviderError::RateLimitExceeded(format!("{:?}", payload)))
+                Err(ProviderError::rate_limited(format!("{:?}", payload)).with_retry_after(reset))
             }
             StatusCode::INTERNAL_SERVER_ERROR | StatusCode::SERVICE_UNAVAILABLE => {
                 Err(ProviderError::ServerError(format!("{:?}", payload)))
@@ -89,7 +98,7 @@ impl GroqProvider {
                 tracing::debug!(
                     "{}", format!("Provider request failed with status: {}. Payload: {:?}", status, payload)
                 );
-                Err(ProviderError::RequestFailed(format!("Request failed with status: {}", status)))
+                Err(ProviderError::request_failed(format!("Request failed with status: {}", status)))
             }
         }
     }
@@ -112,6 +121,10 @@ impl Provider for GroqProvider {
         )
     }
 
+    fn instance_metadata(&self) -> ProviderMetadata {
+        Self::metadata()
+    }
+
     fn get_model_config(&self) -> ModelConfig {
         self.model.clone()
     }
@@ -146,7 +159,11 @@ impl Provider for GroqProvider {
             Err(e) => return Err(e),
         };
         let model = get_model(&response);
-        super::utils::emit_debug_trace(self, &payload, &response, &usage);
-        Ok((message, ProviderUsage::new(model, usage)))
+        super::utils::emit_debug_trace(self, messages, &payload, &response, &usage);
+        Ok((
+            message,
+            ProviderUsage::new(model, usage)
+                .with_system_fingerprint(get_system_fingerprint(&response)),
+        ))
     }
 }
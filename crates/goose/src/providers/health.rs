@@ -0,0 +1,160 @@
+use serde::Serialize;
+use std::time::Duration;
+
+use super::errors::ProviderError;
+
+/// How long `Provider::health_check` waits for an answer
+pub const HEALTH_CHECK_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Whether the provider accepted the credentials
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AuthStatus {
+    Valid,
+    Invalid,
+    /// The check failed before the provider looked at the credentials, e.g. it was unreachable
+    Unknown,
+}
+
+/// The result of `Provider::health_check`
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct HealthCheck {
+    pub model: String,
+    /// How long the provider took to answer, or to fail, None when it could not be created
+    pub latency_ms: Option<u64>,
+    pub auth: AuthStatus,
+    /// Whether the provider serves `model`, None when the check did not get that far
+    pub model_available: Option<bool>,
+    /// Why the check failed
+    pub error: Option<String>,
+}
+
+impl HealthCheck {
+    /// Interpret the outcome of a test request to `model`
+    pub fn from_result(
+        model: String,
+        latency: Duration,
+        result: Result<(), ProviderError>,
+    ) -> Self {
+        let (auth, model_available) = match &result {
+            Ok(()) => (AuthStatus::Valid, Some(true)),
+            Err(ProviderError::Authentication { .. }) => (AuthStatus::Invalid, None),
+            Err(ProviderError::ModelNotFound { .. }) => (AuthStatus::Valid, Some(false)),
+            Err(ProviderError::RequestFailed {
+                status: Some(status),
+                ..
+            }) => match status {
+                401 | 403 => (AuthStatus::Invalid, None),
+                // A 404 may just as well come from a wrong base URL as from the model
+                200..=299 | 400..=499 => (AuthStatus::Valid, None),
+                // Gateways and proxies answer 5xx before the provider sees the key
+                _ => (AuthStatus::Unknown, None),
+            },
+            // The provider only counts requests it has let in
+            Err(ProviderError::RateLimitExceeded { .. })
+            | Err(ProviderError::ContextLengthExceeded(_))
+            | Err(ProviderError::InvalidRequest(_)) => (AuthStatus::Valid, None),
+            Err(_) => (AuthStatus::Unknown, None),
+        };
+        Self {
+            model,
+            latency_ms: Some(latency.as_millis() as u64),
+            auth,
+            model_available,
+            error: result.err().map(|e| e.to_string()),
+        }
+    }
+
+    /// A check that did not get an answer within `HEALTH_CHECK_TIMEOUT`
+    pub fn timed_out(model: String) -> Self {
+        Self {
+            model,
+            latency_ms: Some(HEALTH_CHECK_TIMEOUT.as_millis() as u64),
+            auth: AuthStatus::Unknown,
+            model_available: None,
+            error: Some(format!(
+                "No answer within {}s",
+                HEALTH_CHECK_TIMEOUT.as_secs()
+            )),
+        }
+    }
+
+    /// A provider that could not be created, so no request was sent
+    pub fn not_created(model: String, error: String) -> Self {
+        Self {
+            model,
+            latency_ms: None,
+            auth: AuthStatus::Unknown,
+            model_available: None,
+            error: Some(error),
+        }
+    }
+
+    /// The provider can be used with the model as configured
+    pub fn is_healthy(&self) -> bool {
+        self.error.is_none()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::providers::base::Provider;
+    use crate::providers::testing::TestProvider;
+
+    fn check(result: Result<(), ProviderError>) -> HealthCheck {
+        HealthCheck::from_result("gpt-4o".to_string(), Duration::from_millis(250), result)
+    }
+
+    fn request_failed(status: u16) -> ProviderError {
+        ProviderError::RequestFailed {
+            message: "nope".to_string(),
+            status: Some(status),
+            code: None,
+            request_id: None,
+            partial_usage: None,
+        }
+    }
+
+    #[test]
+    fn test_health_check() {
+        let healthy = check(Ok(()));
+        assert!(healthy.is_healthy());
+        assert_eq!(healthy.latency_ms, Some(250));
+        assert_eq!(healthy.auth, AuthStatus::Valid);
+        assert_eq!(healthy.model_available, Some(true));
+
+        let bad_key = check(Err(ProviderError::authentication(
+            "invalid key".to_string(),
+        )));
+        assert!(!bad_key.is_healthy());
+        assert_eq!(bad_key.auth, AuthStatus::Invalid);
+        assert!(bad_key.error.unwrap().contains("invalid key"));
+
+        assert_eq!(check(Err(request_failed(403))).auth, AuthStatus::Invalid);
+        let missing = check(Err(request_failed(404)));
+        assert_eq!(missing.auth, AuthStatus::Valid);
+        assert_eq!(missing.model_available, None);
+        let bad_gateway = check(Err(request_failed(502)));
+        assert_eq!(bad_gateway.auth, AuthStatus::Unknown);
+        assert!(!bad_gateway.is_healthy());
+
+        let not_created = HealthCheck::not_created("gpt-4o".to_string(), "no key".to_string());
+        assert_eq!(not_created.latency_ms, None);
+        assert_eq!(not_created.auth, AuthStatus::Unknown);
+
+        let unreachable = check(Err(ProviderError::ExecutionError("refused".to_string())));
+        assert_eq!(unreachable.auth, AuthStatus::Unknown);
+        assert_eq!(unreachable.model_available, None);
+    }
+
+    #[tokio::test]
+    async fn test_default_health_check() {
+        let provider = TestProvider::new("gpt-4o")
+            .with_error(|| ProviderError::authentication("key revoked".to_string()));
+        let health = provider.health_check().await;
+        assert_eq!(health.model, "gpt-4o");
+        assert_eq!(health.auth, AuthStatus::Invalid);
+        assert!(!health.is_healthy());
+    }
+}
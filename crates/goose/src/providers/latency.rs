@@ -0,0 +1,368 @@
+use async_trait::async_trait;
+use futures::StreamExt;
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use super::base::{MessageStream, Provider, ProviderMetadata, ProviderUsage, RateLimits};
+use super::errors::ProviderError;
+use super::streaming::{timed_stream, StreamTimings};
+use crate::message::Message;
+use crate::model::ModelConfig;
+use mcp_core::tool::Tool;
+
+/// The number of recent requests percentiles are computed over by default
+pub const DEFAULT_LATENCY_SAMPLES: usize = 1024;
+
+/// The number of recent streams per model the throughput is averaged over by default
+pub const DEFAULT_THROUGHPUT_WINDOW: usize = 20;
+
+/// Streams of a model that have to finish before its duration is estimated
+const MIN_THROUGHPUT_SAMPLES: usize = 3;
+
+/// The latencies of the most recent requests, oldest first
+#[derive(Debug)]
+struct Reservoir {
+    capacity: usize,
+    samples: VecDeque<Duration>,
+}
+
+impl Reservoir {
+    fn new(capacity: usize) -> Self {
+        let capacity = capacity.max(1);
+        Self {
+            capacity,
+            samples: VecDeque::with_capacity(capacity),
+        }
+    }
+
+    fn record(&mut self, latency: Duration) {
+        if self.samples.len() == self.capacity {
+            self.samples.pop_front();
+        }
+        self.samples.push_back(latency);
+    }
+
+    fn percentiles(&self) -> (Duration, Duration, Duration) {
+        let mut sorted: Vec<Duration> = self.samples.iter().copied().collect();
+        sorted.sort_unstable();
+        (
+            nearest_rank(&sorted, 0.50),
+            nearest_rank(&sorted, 0.95),
+            nearest_rank(&sorted, 0.99),
+        )
+    }
+}
+
+fn nearest_rank(sorted: &[Duration], percentile: f64) -> Duration {
+    if sorted.is_empty() {
+        return Duration::ZERO;
+    }
+    let rank = (percentile * sorted.len() as f64).ceil() as usize;
+    sorted[rank.clamp(1, sorted.len()) - 1]
+}
+
+/// The time to first token and generation speed of the most recent streams of each model
+#[derive(Debug)]
+struct Throughput {
+    window: usize,
+    samples: HashMap<String, VecDeque<(Duration, f64)>>,
+}
+
+impl Throughput {
+    fn new(window: usize) -> Self {
+        Self {
+            window: window.max(1),
+            samples: HashMap::new(),
+        }
+    }
+
+    /// Streams with fewer than two tokens say nothing about generation speed and are skipped
+    fn record(&mut self, model: &str, timings: &StreamTimings) {
+        let Some(first_token) = timings.time_to_first_token else {
+            return;
+        };
+        if timings.tokens_per_second <= 0.0 {
+            return;
+        }
+        let samples = self.samples.entry(model.to_string()).or_default();
+        if samples.len() == self.window {
+            samples.pop_front();
+        }
+        samples.push_back((first_token, timings.tokens_per_second));
+    }
+
+    fn estimate(&self, model: &str, output_tokens: usize) -> Option<Duration> {
+        let samples = self.samples.get(model)?;
+        if samples.len() < MIN_THROUGHPUT_SAMPLES.min(self.window) {
+            return None;
+        }
+        let count = samples.len() as f64;
+        let first_token = samples
+            .iter()
+            .map(|(first, _)| first.as_secs_f64())
+            .sum::<f64>()
+            / count;
+        let tokens_per_second = samples.iter().map(|(_, speed)| speed).sum::<f64>() / count;
+        Some(Duration::from_secs_f64(
+            first_token + output_tokens as f64 / tokens_per_second,
+        ))
+    }
+}
+
+/// A provider decorator that keeps a latency snapshot of recent requests
+///
+/// Each completion, successful or not, records its duration; a stream records
+/// the time until it finished. Only the latest `with_capacity` samples are
+/// kept, so memory stays bounded and the percentiles follow recent behaviour.
+/// Streams that finish also record how fast the model generated, which
+/// `estimate_duration` turns into an expected duration for progress displays.
+pub struct LatencyProvider {
+    inner: Box<dyn Provider>,
+    reservoir: Arc<Mutex<Reservoir>>,
+    throughput: Arc<Mutex<Throughput>>,
+}
+
+impl LatencyProvider {
+    pub fn new(inner: Box<dyn Provider>) -> Self {
+        Self {
+            inner,
+            reservoir: Arc::new(Mutex::new(Reservoir::new(DEFAULT_LATENCY_SAMPLES))),
+            throughput: Arc::new(Mutex::new(Throughput::new(DEFAULT_THROUGHPUT_WINDOW))),
+        }
+    }
+
+    /// Average the throughput of a model over its last `streams` streams
+    pub fn with_throughput_window(mut self, streams: usize) -> Self {
+        self.throughput = Arc::new(Mutex::new(Throughput::new(streams)));
+        self
+    }
+
+    /// How long a stream of `expected_output_tokens` is likely to take with the current model
+    ///
+    /// The average time to first token plus the tokens at the average speed of
+    /// recent streams. `None` until a few streams of the model have finished.
+    pub fn estimate_duration(&self, expected_output_tokens: usize) -> Option<Duration> {
+        let model = self.inner.get_model_config().model_name;
+        self.throughput
+            .lock()
+            .unwrap()
+            .estimate(&model, expected_output_tokens)
+    }
+
+    /// Compute percentiles over the last `samples` requests
+    pub fn with_capacity(mut self, samples: usize) -> Self {
+        self.reservoir = Arc::new(Mutex::new(Reservoir::new(samples)));
+        self
+    }
+
+    /// The p50, p95 and p99 latencies of recent requests, zero before the first one
+    pub fn latency_percentiles(&self) -> (Duration, Duration, Duration) {
+        self.reservoir.lock().unwrap().percentiles()
+    }
+}
+
+#[async_trait]
+impl Provider for LatencyProvider {
+    fn metadata() -> ProviderMetadata {
+        ProviderMetadata::empty()
+    }
+
+    fn instance_metadata(&self) -> ProviderMetadata {
+        self.inner.instance_metadata()
+    }
+
+    fn get_model_config(&self) -> ModelConfig {
+        self.inner.get_model_config()
+    }
+
+    fn format_system_prompt(&self, raw: &str) -> String {
+        self.inner.format_system_prompt(raw)
+    }
+
+    fn request_body(
+        &self,
+        system: &str,
+        messages: &[Message],
+        tools: &[Tool],
+    ) -> Result<serde_json::Value, ProviderError> {
+        self.inner.request_body(system, messages, tools)
+    }
+
+    fn count_request_tokens(&self, system: &str, messages: &[Message], tools: &[Tool]) -> usize {
+        self.inner.count_request_tokens(system, messages, tools)
+    }
+
+    async fn warmup(&self) -> Result<(), ProviderError> {
+        self.inner.warmup().await
+    }
+
+    async fn flush(&self) -> Result<(), ProviderError> {
+        self.inner.flush().await
+    }
+
+    async fn rate_limits(&self) -> Result<RateLimits, ProviderError> {
+        self.inner.rate_limits().await
+    }
+
+    async fn fetch_supported_models(&self) -> Result<Vec<String>, ProviderError> {
+        self.inner.fetch_supported_models().await
+    }
+
+    async fn embed(&self, inputs: &[String]) -> Result<Vec<Vec<f32>>, ProviderError> {
+        self.inner.embed(inputs).await
+    }
+
+    async fn complete(
+        &self,
+        system: &str,
+        messages: &[Message],
+        tools: &[Tool],
+    ) -> Result<(Message, ProviderUsage), ProviderError> {
+        let started = Instant::now();
+        let result = self.inner.complete(system, messages, tools).await;
+        self.reservoir.lock().unwrap().record(started.elapsed());
+        result
+    }
+
+    async fn stream(
+        &self,
+        system: &str,
+        messages: &[Message],
+        tools: &[Tool],
+    ) -> Result<MessageStream, ProviderError> {
+        let started = Instant::now();
+        let stream = match self.inner.stream(system, messages, tools).await {
+            Ok(stream) => stream,
+            Err(error) => {
+                self.reservoir.lock().unwrap().record(started.elapsed());
+                return Err(error);
+            }
+        };
+
+        let (mut stream, timer) = timed_stream(stream);
+        let model = self.inner.get_model_config().model_name;
+        let reservoir = self.reservoir.clone();
+        let throughput = self.throughput.clone();
+        Ok(Box::pin(async_stream::try_stream! {
+            let mut failure = None;
+            while let Some(delta) = stream.next().await {
+                match delta {
+                    Ok(delta) => yield delta,
+                    Err(error) => {
+                        failure = Some(error);
+                        break;
+                    }
+                }
+            }
+            reservoir.lock().unwrap().record(started.elapsed());
+            if let Some(error) = failure {
+                Err(error)?;
+            }
+            if let Some(timings) = timer.timings() {
+                throughput.lock().unwrap().record(&model, &timings);
+            }
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::providers::testing::TestProvider;
+
+    #[test]
+    fn test_percentiles_of_known_latencies() {
+        let mut reservoir = Reservoir::new(100);
+        assert_eq!(reservoir.percentiles().0, Duration::ZERO);
+
+        for ms in (1..=100).rev() {
+            reservoir.record(Duration::from_millis(ms));
+        }
+        assert_eq!(
+            reservoir.percentiles(),
+            (
+                Duration::from_millis(50),
+                Duration::from_millis(95),
+                Duration::from_millis(99)
+            )
+        );
+    }
+
+    #[test]
+    fn test_reservoir_keeps_recent_samples() {
+        let mut reservoir = Reservoir::new(10);
+        for _ in 0..50 {
+            reservoir.record(Duration::from_secs(5));
+        }
+        for _ in 0..10 {
+            reservoir.record(Duration::from_millis(1));
+        }
+        assert_eq!(reservoir.samples.len(), 10);
+        assert_eq!(reservoir.percentiles().2, Duration::from_millis(1));
+    }
+
+    #[tokio::test]
+    async fn test_provider_records_requests() {
+        let provider =
+            LatencyProvider::new(Box::new(TestProvider::new("instant"))).with_capacity(2);
+        for _ in 0..3 {
+            provider.complete("", &[], &[]).await.unwrap();
+        }
+        let mut stream = provider.stream("", &[], &[]).await.unwrap();
+        while stream.next().await.is_some() {}
+
+        assert_eq!(provider.reservoir.lock().unwrap().samples.len(), 2);
+        let (p50, p95, p99) = provider.latency_percentiles();
+        assert!(p50 <= p95 && p95 <= p99);
+        assert!(p99 < Duration::from_secs(1));
+    }
+
+    fn timings(first_token_ms: u64, tokens_per_second: f64) -> StreamTimings {
+        StreamTimings {
+            time_to_first_token: Some(Duration::from_millis(first_token_ms)),
+            total: Duration::ZERO,
+            tokens: 100,
+            mean_inter_token_latency: Duration::ZERO,
+            tokens_per_second,
+        }
+    }
+
+    #[test]
+    fn test_estimate_duration_from_recent_throughput() {
+        let provider =
+            LatencyProvider::new(Box::new(TestProvider::new("instant"))).with_throughput_window(3);
+        let record = |first_token_ms, tokens_per_second| {
+            provider
+                .throughput
+                .lock()
+                .unwrap()
+                .record("instant", &timings(first_token_ms, tokens_per_second));
+        };
+        assert_eq!(provider.estimate_duration(100), None);
+
+        record(5000, 1.0);
+        record(400, 40.0);
+        // Streams without tokens and streams of other models do not count
+        record(0, 0.0);
+        provider
+            .throughput
+            .lock()
+            .unwrap()
+            .record("other", &timings(100, 10.0));
+        assert_eq!(provider.estimate_duration(100), None);
+
+        record(600, 50.0);
+        assert!(provider.estimate_duration(100).unwrap() > Duration::from_secs(5));
+
+        // The slow stream falls out of the window: 0.5s to the first token,
+        // then 100 tokens at 50 tokens per second
+        record(500, 60.0);
+        let estimate = provider.estimate_duration(100).unwrap();
+        assert!(
+            (estimate.as_secs_f64() - 2.5).abs() < 1e-6,
+            "{:?}",
+            estimate
+        );
+    }
+}
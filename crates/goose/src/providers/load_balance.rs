@@ -0,0 +1,404 @@
+use anyhow::Result;
+use async_trait::async_trait;
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use super::base::{MessageStream, Provider, ProviderMetadata, ProviderUsage, RateLimits, ThreadId};
+use super::errors::ProviderError;
+use super::omg::OmgProvider;
+use crate::message::Message;
+use crate::model::ModelConfig;
+use mcp_core::tool::Tool;
+
+const DEFAULT_RATE_LIMIT_COOLDOWN: Duration = Duration::from_secs(60);
+
+struct Backend {
+    provider: Box<dyn Provider>,
+    weight: i64,
+}
+
+#[derive(Debug, Default)]
+struct BackendState {
+    /// Running weight used by the smooth weighted round-robin selection
+    current_weight: i64,
+    limited_until: Option<Instant>,
+}
+
+/// Spreads requests across several providers, typically one per API key
+///
+/// Backends are picked by smooth weighted round-robin, so a backend with weight 2
+/// serves twice as many requests as one with weight 1 without bursts. A backend
+/// that answers with a rate limit error is skipped for as long as it asked, or
+/// the cooldown when it didn't say, and the request is retried on the next
+/// available backend. A server side thread is kept on the backend that
+/// started it, since only that backend has its history.
+pub struct LoadBalancedProvider {
+    backends: Vec<Backend>,
+    state: Mutex<Vec<BackendState>>,
+    rate_limit_cooldown: Duration,
+    /// The backend each thread was started on
+    threads: Mutex<HashMap<ThreadId, usize>>,
+}
+
+impl LoadBalancedProvider {
+    /// Balance over `(provider, weight)` pairs, the first backend provides the metadata
+    pub fn new(backends: Vec<(Box<dyn Provider>, u32)>) -> Result<Self> {
+        anyhow::ensure!(
+            !backends.is_empty(),
+            "Load balancing needs at least one backend"
+        );
+        let state = backends.iter().map(|_| BackendState::default()).collect();
+        let backends = backends
+            .into_iter()
+            .map(|(provider, weight)| Backend {
+                provider,
+                weight: i64::from(weight.max(1)),
+            })
+            .collect();
+        Ok(Self {
+            backends,
+            state: Mutex::new(state),
+            rate_limit_cooldown: DEFAULT_RATE_LIMIT_COOLDOWN,
+            threads: Mutex::new(HashMap::new()),
+        })
+    }
+
+    /// Balance over several OhMyGPT API keys with equal weight
+    pub fn from_omg_keys(provider: OmgProvider, api_keys: Vec<String>) -> Result<Self> {
+        Self::new(
+            api_keys
+                .into_iter()
+                .map(|key| (provider.clone().with_api_key(key).into_boxed(), 1))
+                .collect(),
+        )
+    }
+
+    /// How long a rate limited backend is skipped when it doesn't say how long to wait
+    pub fn with_rate_limit_cooldown(mut self, cooldown: Duration) -> Self {
+        self.rate_limit_cooldown = cooldown;
+        self
+    }
+
+    /// Pick the next backend that is not rate limited
+    fn pick(&self) -> Option<usize> {
+        let mut state = self.state.lock().unwrap();
+        let now = Instant::now();
+        let available: Vec<usize> = (0..self.backends.len())
+            .filter(|&i| state[i].limited_until.is_none_or(|until| now >= until))
+            .collect();
+
+        let total: i64 = available.iter().map(|&i| self.backends[i].weight).sum();
+        for &i in &available {
+            state[i].current_weight += self.backends[i].weight;
+        }
+        let chosen = available
+            .into_iter()
+            .max_by_key(|&i| (state[i].current_weight, std::cmp::Reverse(i)))?;
+        state[chosen].current_weight -= total;
+        state[chosen].limited_until = None;
+        Some(chosen)
+    }
+
+    /// Skip the backend at `index` for as long as `error` asks, or the cooldown
+    fn mark_limited(&self, index: usize, error: &ProviderError) {
+        let wait = error.retry_after().unwrap_or(self.rate_limit_cooldown);
+        tracing::warn!(
+            "Backend {} is rate limited, skipping it for {:?}",
+            index,
+            wait
+        );
+        self.state.lock().unwrap()[index].limited_until = Some(Instant::now() + wait);
+    }
+
+    /// Complete on the backend at `index`, remembering the thread it returns
+    async fn complete_on(
+        &self,
+        index: usize,
+        system: &str,
+        messages: &[Message],
+        tools: &[Tool],
+        thread: Option<ThreadId>,
+    ) -> Result<(Message, ProviderUsage, Option<ThreadId>), ProviderError> {
+        let result = self.backends[index]
+            .provider
+            .complete_in_thread(system, messages, tools, thread)
+            .await;
+        match &result {
+            Ok((_, _, Some(thread))) => {
+                self.threads.lock().unwrap().insert(thread.clone(), index);
+            }
+            Err(error @ ProviderError::RateLimitExceeded { .. }) => self.mark_limited(index, error),
+            _ => {}
+        }
+        result
+    }
+
+    fn all_limited() -> ProviderError {
+        ProviderError::rate_limited("all backends are currently rate limited".to_string())
+    }
+}
+
+#[async_trait]
+impl Provider for LoadBalancedProvider {
+    fn metadata() -> ProviderMetadata {
+        ProviderMetadata::empty()
+    }
+
+    fn instance_metadata(&self) -> ProviderMetadata {
+        self.backends[0].provider.instance_metadata()
+    }
+
+    fn get_model_config(&self) -> ModelConfig {
+        self.backends[0].provider.get_model_config()
+    }
+
+    fn format_system_prompt(&self, raw: &str) -> String {
+        self.backends[0].provider.format_system_prompt(raw)
+    }
+
+    fn count_request_tokens(&self, system: &str, messages: &[Message], tools: &[Tool]) -> usize {
+        self.backends[0]
+            .provider
+            .count_request_tokens(system, messages, tools)
+    }
+
+    async fn warmup(&self) -> Result<(), ProviderError> {
+        for backend in &self.backends {
+            backend.provider.warmup().await?;
+        }
+        Ok(())
+    }
+
+    async fn flush(&self) -> Result<(), ProviderError> {
+        for backend in &self.backends {
+            backend.provider.flush().await?;
+        }
+        Ok(())
+    }
+
+    /// The combined limits, each backend has its own key and so its own allowance
+    async fn rate_limits(&self) -> Result<RateLimits, ProviderError> {
+        let sum = |a: Option<u32>, b: Option<u32>| match (a, b) {
+            (Some(a), Some(b)) => Some(a.saturating_add(b)),
+            (a, b) => a.or(b),
+        };
+        let mut total = RateLimits::default();
+        for backend in &self.backends {
+            let limits = backend.provider.rate_limits().await?;
+            total = RateLimits {
+                requests_per_minute: sum(total.requests_per_minute, limits.requests_per_minute),
+                tokens_per_minute: sum(total.tokens_per_minute, limits.tokens_per_minute),
+                remaining_requests: sum(total.remaining_requests, limits.remaining_requests),
+                remaining_tokens: sum(total.remaining_tokens, limits.remaining_tokens),
+            };
+        }
+        Ok(total)
+    }
+
+    async fn fetch_supported_models(&self) -> Result<Vec<String>, ProviderError> {
+        self.backends[0].provider.fetch_supported_models().await
+    }
+
+    async fn embed(&self, inputs: &[String]) -> Result<Vec<Vec<f32>>, ProviderError> {
+        for _ in 0..self.backends.len() {
+            let index = self.pick().ok_or_else(Self::all_limited)?;
+            match self.backends[index].provider.embed(inputs).await {
+                Err(error @ ProviderError::RateLimitExceeded { .. }) => {
+                    self.mark_limited(index, &error)
+                }
+                result => return result,
+            }
+        }
+        Err(Self::all_limited())
+    }
+
+    async fn complete(
+        &self,
+        system: &str,
+        messages: &[Message],
+        tools: &[Tool],
+    ) -> Result<(Message, ProviderUsage), ProviderError> {
+        for _ in 0..self.backends.len() {
+            let index = self.pick().ok_or_else(Self::all_limited)?;
+            match self.backends[index]
+                .provider
+                .complete(system, messages, tools)
+                .await
+            {
+                Err(error @ ProviderError::RateLimitExceeded { .. }) => {
+                    self.mark_limited(index, &error)
+                }
+                result => return result,
+            }
+        }
+        Err(Self::all_limited())
+    }
+
+    /// Threads the balancer did not start are sent to the first backend
+    async fn complete_in_thread(
+        &self,
+        system: &str,
+        messages: &[Message],
+        tools: &[Tool],
+        thread: Option<ThreadId>,
+    ) -> Result<(Message, ProviderUsage, Option<ThreadId>), ProviderError> {
+        if let Some(thread) = thread {
+            let owner = self.threads.lock().unwrap().get(&thread).copied();
+            return self
+                .complete_on(owner.unwrap_or(0), system, messages, tools, Some(thread))
+                .await;
+        }
+        for _ in 0..self.backends.len() {
+            let index = self.pick().ok_or_else(Self::all_limited)?;
+            match self.complete_on(index, system, messages, tools, None).await {
+                Err(ProviderError::RateLimitExceeded { .. }) => {}
+                result => return result,
+            }
+        }
+        Err(Self::all_limited())
+    }
+
+    async fn stream(
+        &self,
+        system: &str,
+        messages: &[Message],
+        tools: &[Tool],
+    ) -> Result<MessageStream, ProviderError> {
+        for _ in 0..self.backends.len() {
+            let index = self.pick().ok_or_else(Self::all_limited)?;
+            match self.backends[index]
+                .provider
+                .stream(system, messages, tools)
+                .await
+            {
+                Err(error @ ProviderError::RateLimitExceeded { .. }) => {
+                    self.mark_limited(index, &error)
+                }
+                result => return result,
+            }
+        }
+        Err(Self::all_limited())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::providers::testing::TestProvider;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::Arc;
+
+    /// Answers with its own name in a thread named after it, or a rate limit
+    /// error asking to wait `retry_after` while `limited` is set
+    fn limited_backend(
+        name: &'static str,
+        retry_after: Option<Duration>,
+    ) -> (Box<dyn Provider>, Arc<AtomicBool>) {
+        let limited = Arc::new(AtomicBool::new(false));
+        let provider = TestProvider::new(name).with_thread(name).with_reply({
+            let limited = limited.clone();
+            move |_| match limited.load(Ordering::SeqCst) {
+                true => Err(ProviderError::rate_limited("429").with_retry_after(retry_after)),
+                false => Ok(Message::assistant().with_text(name)),
+            }
+        });
+        (Box::new(provider), limited)
+    }
+
+    fn backend(name: &'static str) -> (Box<dyn Provider>, Arc<AtomicBool>) {
+        limited_backend(name, None)
+    }
+
+    async fn served_by(provider: &LoadBalancedProvider) -> Result<String, ProviderError> {
+        let (message, _) = provider.complete("", &[], &[]).await?;
+        Ok(message.as_concat_text())
+    }
+
+    #[tokio::test]
+    async fn test_weighted_rotation() {
+        let (a, _) = backend("a");
+        let (b, _) = backend("b");
+        let provider = LoadBalancedProvider::new(vec![(a, 2), (b, 1)]).unwrap();
+
+        let mut served = Vec::new();
+        for _ in 0..6 {
+            served.push(served_by(&provider).await.unwrap());
+        }
+        assert_eq!(served, vec!["a", "b", "a", "a", "b", "a"]);
+        assert_eq!(provider.get_model_config().model_name, "a");
+    }
+
+    #[tokio::test]
+    async fn test_skips_rate_limited_backend() {
+        let (a, a_limited) = backend("a");
+        let (b, b_limited) = backend("b");
+        let provider = LoadBalancedProvider::new(vec![(a, 1), (b, 1)])
+            .unwrap()
+            .with_rate_limit_cooldown(Duration::from_millis(50));
+
+        a_limited.store(true, Ordering::SeqCst);
+        // The request on the limited backend is retried on the other one
+        for _ in 0..3 {
+            assert_eq!(served_by(&provider).await.unwrap(), "b");
+        }
+
+        b_limited.store(true, Ordering::SeqCst);
+        assert!(matches!(
+            served_by(&provider).await,
+            Err(ProviderError::RateLimitExceeded { .. })
+        ));
+
+        a_limited.store(false, Ordering::SeqCst);
+        tokio::time::sleep(Duration::from_millis(60)).await;
+        assert_eq!(served_by(&provider).await.unwrap(), "a");
+    }
+
+    #[tokio::test]
+    async fn test_threads_stay_on_their_backend() {
+        let (a, _) = backend("a");
+        let (b, _) = backend("b");
+        let provider = LoadBalancedProvider::new(vec![(a, 1), (b, 1)]).unwrap();
+
+        let (_, _, thread) = provider
+            .complete_in_thread("", &[], &[], None)
+            .await
+            .unwrap();
+        assert_eq!(thread, Some(ThreadId("a".to_string())));
+        // Without a thread the next request would go to "b"
+        for _ in 0..2 {
+            let (message, _, _) = provider
+                .complete_in_thread("", &[], &[], thread.clone())
+                .await
+                .unwrap();
+            assert_eq!(message.as_concat_text(), "a");
+        }
+        assert_eq!(served_by(&provider).await.unwrap(), "b");
+    }
+
+    #[tokio::test]
+    async fn test_waits_as_long_as_asked() {
+        let (a, limited) = limited_backend("a", Some(Duration::from_millis(50)));
+        limited.store(true, Ordering::SeqCst);
+        let (b, _) = backend("b");
+        let provider = LoadBalancedProvider::new(vec![(a, 1), (b, 1)])
+            .unwrap()
+            .with_rate_limit_cooldown(Duration::from_secs(3600));
+
+        assert_eq!(served_by(&provider).await.unwrap(), "b");
+        limited.store(false, Ordering::SeqCst);
+        assert_eq!(served_by(&provider).await.unwrap(), "b");
+        // Back once the wait it asked for is over, long before the cooldown
+        tokio::time::sleep(Duration::from_millis(60)).await;
+        let served = [
+            served_by(&provider).await.unwrap(),
+            served_by(&provider).await.unwrap(),
+        ];
+        assert!(served.contains(&"a".to_string()));
+    }
+
+    #[test]
+    fn test_needs_a_backend() {
+        assert!(LoadBalancedProvider::new(Vec::new()).is_err());
+    }
+}
@@ -0,0 +1,484 @@
+use async_trait::async_trait;
+use chrono::Utc;
+use futures::StreamExt;
+use serde_json::{json, Value};
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::{mpsc, Arc, Mutex};
+use std::time::Instant;
+use tokio::sync::oneshot;
+
+use super::base::{
+    MessageDelta, MessageStream, Provider, ProviderMetadata, ProviderUsage, RateLimits,
+};
+use super::errors::ProviderError;
+use super::redact::RedactionRule;
+use super::utils::{mask_sensitive, sensitive_texts};
+use crate::message::{Message, MessageContent};
+use crate::model::ModelConfig;
+use mcp_core::tool::Tool;
+
+/// Keys whose values are always replaced before an entry is written
+const SECRET_KEYS: &[&str] = &["api_key", "authorization", "x-api-key", "token", "secret"];
+
+/// Lines for the writer thread, `written` is told once they are in the file
+struct Batch {
+    lines: Vec<String>,
+    written: Option<oneshot::Sender<()>>,
+}
+
+/// Writes redacted entries to the background writer thread
+#[derive(Clone)]
+struct RequestLog {
+    rules: Vec<RedactionRule>,
+    writer: mpsc::Sender<Batch>,
+    /// Entries are handed over once this many are pending
+    batch_size: usize,
+    pending: Arc<Mutex<Vec<String>>>,
+}
+
+impl RequestLog {
+    fn write(
+        &self,
+        request: Value,
+        sensitive: &[String],
+        started: Instant,
+        outcome: Result<Value, &ProviderError>,
+        usage: Option<&ProviderUsage>,
+    ) {
+        let mut entry = json!({
+            "timestamp": Utc::now().to_rfc3339(),
+            "request": request,
+            "latency_ms": started.elapsed().as_millis() as u64,
+            "usage": usage,
+        });
+        match outcome {
+            Ok(response) => entry["response"] = response,
+            Err(error) => entry["error"] = json!(error.to_string()),
+        }
+        mask_sensitive(&mut entry, sensitive);
+        self.redact(&mut entry);
+
+        let mut pending = self.pending.lock().unwrap();
+        pending.push(entry.to_string());
+        if pending.len() >= self.batch_size {
+            // The writer only stops if the file became unwritable, which it already reported
+            let _ = self.writer.send(Batch {
+                lines: std::mem::take(&mut pending),
+                written: None,
+            });
+        }
+    }
+
+    /// Hand over the pending entries, `written` is told once they are in the file
+    fn send_pending(&self, written: Option<oneshot::Sender<()>>) -> bool {
+        let mut pending = self.pending.lock().unwrap();
+        let batch = Batch {
+            lines: std::mem::take(&mut pending),
+            written,
+        };
+        self.writer.send(batch).is_ok()
+    }
+
+    async fn flush(&self) -> Result<(), ProviderError> {
+        let (written, done) = oneshot::channel();
+        let stopped = || {
+            ProviderError::ExecutionError(
+                "the request log writer stopped, see the earlier error".to_string(),
+            )
+        };
+        if !self.send_pending(Some(written)) {
+            return Err(stopped());
+        }
+        done.await.map_err(|_| stopped())
+    }
+
+    fn redact(&self, value: &mut Value) {
+        match value {
+            Value::Object(map) => {
+                for (key, value) in map.iter_mut() {
+                    if SECRET_KEYS.contains(&key.to_lowercase().as_str()) {
+                        *value = json!("[REDACTED]");
+                    } else {
+                        self.redact(value);
+                    }
+                }
+            }
+            Value::Array(items) => items.iter_mut().for_each(|item| self.redact(item)),
+            Value::String(text) => {
+                for rule in &self.rules {
+                    *text = rule.redact(text);
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+fn write_lines(mut file: File, path: PathBuf, batches: mpsc::Receiver<Batch>) {
+    for batch in batches {
+        for line in batch.lines {
+            if let Err(e) = writeln!(file, "{}", line) {
+                tracing::error!("Failed to write request log {}: {}", path.display(), e);
+                return;
+            }
+        }
+        if let Some(written) = batch.written {
+            let _ = written.send(());
+        }
+    }
+}
+
+/// A provider decorator that appends every request and its outcome to a JSONL file
+///
+/// Each call writes one line with the timestamp, the request, the response or
+/// error, the latency in milliseconds and the usage. The request is the HTTP
+/// body the inner provider sends, see `Provider::request_body`, or the
+/// conversation it was passed for providers that do not expose their body. Lines are handed to a
+/// background writer thread, so a slow disk never holds up the request and
+/// concurrent calls never interleave within a line. Values of secret looking
+/// keys are always redacted, `with_rules` additionally scrubs matching text.
+/// Text marked sensitive in the messages is masked wherever it appears.
+///
+/// With `with_batch_size` entries are held back and written in batches, call
+/// `flush` to write the held back ones, e.g. at shutdown. Entries still held
+/// when the provider is dropped are written then.
+pub struct LoggingProvider {
+    inner: Box<dyn Provider>,
+    log: RequestLog,
+}
+
+impl LoggingProvider {
+    /// Wrap a provider, appending entries to the file at `path`
+    pub fn new(inner: Box<dyn Provider>, path: impl AsRef<Path>) -> std::io::Result<Self> {
+        let path = path.as_ref().to_path_buf();
+        let file = OpenOptions::new().create(true).append(true).open(&path)?;
+        let (writer, lines) = mpsc::channel();
+        std::thread::spawn(move || write_lines(file, path, lines));
+
+        Ok(Self {
+            inner,
+            log: RequestLog {
+                rules: Vec::new(),
+                writer,
+                batch_size: 1,
+                pending: Arc::new(Mutex::new(Vec::new())),
+            },
+        })
+    }
+
+    /// Write entries in batches of this many instead of one at a time
+    pub fn with_batch_size(mut self, batch_size: usize) -> Self {
+        self.log.batch_size = batch_size.max(1);
+        self
+    }
+
+    /// Redact text matching these rules in the logged request and response
+    pub fn with_rules(mut self, rules: Vec<RedactionRule>) -> Self {
+        self.log.rules = rules;
+        self
+    }
+
+    fn request_entry(&self, system: &str, messages: &[Message], tools: &[Tool]) -> Value {
+        match self.inner.request_body(system, messages, tools) {
+            Ok(body) => body,
+            Err(_) => json!({
+                "model": self.inner.get_model_config().model_name,
+                "system": system,
+                "messages": messages,
+                "tools": tools,
+            }),
+        }
+    }
+}
+
+impl Drop for LoggingProvider {
+    fn drop(&mut self) {
+        self.log.send_pending(None);
+    }
+}
+
+#[async_trait]
+impl Provider for LoggingProvider {
+    fn metadata() -> ProviderMetadata {
+        ProviderMetadata::empty()
+    }
+
+    fn instance_metadata(&self) -> ProviderMetadata {
+        self.inner.instance_metadata()
+    }
+
+    fn get_model_config(&self) -> ModelConfig {
+        self.inner.get_model_config()
+    }
+
+    fn format_system_prompt(&self, raw: &str) -> String {
+        self.inner.format_system_prompt(raw)
+    }
+
+    fn request_body(
+        &self,
+        system: &str,
+        messages: &[Message],
+        tools: &[Tool],
+    ) -> Result<Value, ProviderError> {
+        self.inner.request_body(system, messages, tools)
+    }
+
+    fn count_request_tokens(&self, system: &str, messages: &[Message], tools: &[Tool]) -> usize {
+        self.inner.count_request_tokens(system, messages, tools)
+    }
+
+    async fn warmup(&self) -> Result<(), ProviderError> {
+        self.inner.warmup().await
+    }
+
+    async fn flush(&self) -> Result<(), ProviderError> {
+        self.log.flush().await?;
+        self.inner.flush().await
+    }
+
+    async fn rate_limits(&self) -> Result<RateLimits, ProviderError> {
+        self.inner.rate_limits().await
+    }
+
+    async fn fetch_supported_models(&self) -> Result<Vec<String>, ProviderError> {
+        self.inner.fetch_supported_models().await
+    }
+
+    async fn embed(&self, inputs: &[String]) -> Result<Vec<Vec<f32>>, ProviderError> {
+        self.inner.embed(inputs).await
+    }
+
+    async fn complete(
+        &self,
+        system: &str,
+        messages: &[Message],
+        tools: &[Tool],
+    ) -> Result<(Message, ProviderUsage), ProviderError> {
+        let request = self.request_entry(system, messages, tools);
+        let sensitive = sensitive_texts(messages);
+        let started = Instant::now();
+        let result = self.inner.complete(system, messages, tools).await;
+
+        match &result {
+            Ok((message, usage)) => self.log.write(
+                request,
+                &sensitive,
+                started,
+                Ok(json!(message)),
+                Some(usage),
+            ),
+            Err(error) => self
+                .log
+                .write(request, &sensitive, started, Err(error), None),
+        }
+        result
+    }
+
+    async fn stream(
+        &self,
+        system: &str,
+        messages: &[Message],
+        tools: &[Tool],
+    ) -> Result<MessageStream, ProviderError> {
+        let request = self.request_entry(system, messages, tools);
+        let sensitive = sensitive_texts(messages);
+        let started = Instant::now();
+        let mut stream = match self.inner.stream(system, messages, tools).await {
+            Ok(stream) => stream,
+            Err(error) => {
+                self.log
+                    .write(request, &sensitive, started, Err(&error), None);
+                return Err(error);
+            }
+        };
+
+        // Log once the stream finishes, with the text and usage that went by
+        let log = self.log.clone();
+        Ok(Box::pin(async_stream::try_stream! {
+            let mut text = String::new();
+            let mut usage = None;
+            let mut failure = None;
+            while let Some(delta) = stream.next().await {
+                match delta {
+                    Ok(delta) => {
+                        match &delta {
+                            MessageDelta::Content(MessageContent::Text(t)) => text.push_str(&t.text),
+                            MessageDelta::Usage(u) => usage = Some(u.clone()),
+                            MessageDelta::Content(_)
+                            | MessageDelta::Citations(_)
+                            | MessageDelta::ToolCallFragment { .. }
+                            | MessageDelta::Finish(_) => {}
+                        }
+                        yield delta;
+                    }
+                    Err(error) => {
+                        failure = Some(error);
+                        break;
+                    }
+                }
+            }
+            match failure {
+                Some(error) => {
+                    log.write(request, &sensitive, started, Err(&error), usage.as_ref());
+                    Err(error)?;
+                }
+                None => log.write(request, &sensitive, started, Ok(json!({"text": text})), usage.as_ref()),
+            }
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::providers::base::Usage;
+    use crate::providers::testing::TestProvider;
+
+    /// Fails when asked about errors, answers otherwise
+    fn scripted() -> Box<TestProvider> {
+        let provider = TestProvider::new("scripted")
+            .with_usage("scripted", Usage::new(Some(3), Some(1), Some(4)))
+            .with_reply(|request| {
+                if request.messages[0].as_concat_text().contains("error") {
+                    return Err(ProviderError::ServerError("boom".to_string()));
+                }
+                Ok(Message::assistant().with_text("hello"))
+            });
+        Box::new(provider)
+    }
+
+    fn read_entries(path: &Path, expected: usize) -> Vec<Value> {
+        // The writer thread appends asynchronously
+        for _ in 0..100 {
+            let content = std::fs::read_to_string(path).unwrap_or_default();
+            let entries: Vec<Value> = content
+                .lines()
+                .map(|line| serde_json::from_str(line).unwrap())
+                .collect();
+            if entries.len() >= expected {
+                return entries;
+            }
+            std::thread::sleep(std::time::Duration::from_millis(10));
+        }
+        panic!("expected {} log entries", expected);
+    }
+
+    #[tokio::test]
+    async fn test_logs_requests_and_errors() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("requests.jsonl");
+        let rules = vec![RedactionRule::new("email", r"\S+@\S+").unwrap()];
+        let provider = LoggingProvider::new(scripted(), &path)
+            .unwrap()
+            .with_rules(rules);
+
+        let messages = vec![Message::user().with_text("hi from jane@example.com")];
+        let (message, _) = provider.complete("system", &messages, &[]).await.unwrap();
+        assert_eq!(message.as_concat_text(), "hello");
+
+        let messages = vec![Message::user().with_text("error please")];
+        assert!(provider.complete("system", &messages, &[]).await.is_err());
+
+        let entries = read_entries(&path, 2);
+        let ok = &entries[0];
+        assert_eq!(ok["request"]["model"], "scripted");
+        assert_eq!(ok["usage"]["usage"]["total_tokens"], 4);
+        assert!(ok["timestamp"].is_string());
+        assert!(ok["latency_ms"].is_u64());
+        assert!(ok["response"].to_string().contains("hello"));
+        let request = ok["request"].to_string();
+        assert!(!request.contains("jane@example.com"));
+        assert!(request.contains("[REDACTED_EMAIL]"));
+
+        let failed = &entries[1];
+        assert!(failed["error"].as_str().unwrap().contains("boom"));
+        assert!(failed.get("response").is_none());
+    }
+
+    #[tokio::test]
+    async fn test_logs_the_body_that_is_sent() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("requests.jsonl");
+        let inner = TestProvider::new("gpt-4o").with_request_body(|request| {
+            json!({
+                "model": "gpt-4o",
+                "messages": [{"role": "user", "content": request.messages[0].as_concat_text()}],
+                "api_key": "sk-secret"
+            })
+        });
+        let provider = LoggingProvider::new(Box::new(inner), &path).unwrap();
+
+        let messages = vec![Message::user().with_text("Hello")];
+        provider.complete("system", &messages, &[]).await.unwrap();
+
+        let request = &read_entries(&path, 1)[0]["request"];
+        assert_eq!(request["messages"][0]["content"], "Hello");
+        assert_eq!(request["api_key"], "[REDACTED]");
+        assert!(request.get("system").is_none());
+    }
+
+    #[tokio::test]
+    async fn test_concurrent_writes_stay_on_separate_lines() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("requests.jsonl");
+        let provider = Arc::new(LoggingProvider::new(scripted(), &path).unwrap());
+
+        let tasks: Vec<_> = (0..20)
+            .map(|i| {
+                let provider = provider.clone();
+                tokio::spawn(async move {
+                    let messages = vec![Message::user().with_text(format!("request {}", i))];
+                    provider.complete("", &messages, &[]).await
+                })
+            })
+            .collect();
+        for task in tasks {
+            task.await.unwrap().unwrap();
+        }
+
+        assert_eq!(read_entries(&path, 20).len(), 20);
+    }
+
+    #[tokio::test]
+    async fn test_batched_entries_are_written_on_flush() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("requests.jsonl");
+        let provider = LoggingProvider::new(scripted(), &path)
+            .unwrap()
+            .with_batch_size(10);
+
+        let messages = vec![Message::user().with_text("hi")];
+        provider.complete("", &messages, &[]).await.unwrap();
+        provider.complete("", &messages, &[]).await.unwrap();
+        tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "");
+
+        // Flush returns once the entries are in the file
+        provider.flush().await.unwrap();
+        assert_eq!(std::fs::read_to_string(&path).unwrap().lines().count(), 2);
+
+        provider.complete("", &messages, &[]).await.unwrap();
+        drop(provider);
+        assert_eq!(read_entries(&path, 3).len(), 3);
+    }
+
+    #[test]
+    fn test_secret_keys_are_redacted() {
+        let (writer, _lines) = mpsc::channel();
+        let log = RequestLog {
+            rules: Vec::new(),
+            writer,
+            batch_size: 1,
+            pending: Arc::new(Mutex::new(Vec::new())),
+        };
+        let mut value = json!({"headers": {"Authorization": "Bearer sk-1"}, "api_key": "sk-2"});
+        log.redact(&mut value);
+        assert_eq!(
+            value,
+            json!({"headers": {"Authorization": "[REDACTED]"}, "api_key": "[REDACTED]"})
+        );
+    }
+}
@@ -1,16 +1,50 @@
 pub mod anthropic;
+pub mod aws;
+pub mod azure;
 pub mod base;
+pub mod bedrock;
+#[cfg(feature = "blocking")]
+pub mod blocking;
+pub mod budget;
+pub mod circuit_breaker;
+pub mod compression;
 pub mod databricks;
+#[cfg(feature = "image")]
+pub mod downscale;
 pub mod errors;
 mod factory;
+pub mod fallback;
+pub mod fan_out;
+pub mod few_shot;
 pub mod formats;
 pub mod google;
 pub mod groq;
+pub mod health;
+pub mod latency;
+pub mod load_balance;
+pub mod logging;
 pub mod oauth;
 pub mod ollama;
 pub mod omg;
 pub mod openai;
 pub mod openrouter;
+#[cfg(feature = "otel")]
+pub mod otel;
+pub mod partial_json;
+pub mod pricing;
+pub mod rate_limit;
+pub mod record;
+pub mod redact;
+pub mod shutdown;
+pub mod streaming;
+pub mod system_wrap;
+#[cfg(any(test, feature = "test-support"))]
+pub mod testing;
+pub mod tool_limit;
+pub mod trim;
 pub mod utils;
 
-pub use factory::{create, providers};
+pub use factory::{
+    create, health_check, providers, register_provider, status, status_live, supported_models,
+    ProviderStatus,
+};
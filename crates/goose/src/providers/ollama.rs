@@ -1,28 +1,82 @@
-use super::base::{ConfigKey, Provider, ProviderMetadata, ProviderUsage, Usage};
+use super::base::{ConfigKey, Provider, ProviderMetadata, ProviderUsage};
 use super::errors::ProviderError;
-use super::utils::{get_model, handle_response_openai_compat};
+use super::utils::{
+    emit_debug_trace, model_not_found, send_with_retry, validate_tool_pairing, RetryConfig,
+};
 use crate::message::Message;
 use crate::model::ModelConfig;
-use crate::providers::formats::openai::{create_request, get_usage, response_to_message};
+use crate::providers::formats::ollama::{
+    create_request, get_usage, response_to_message, ChatOptions,
+};
 use anyhow::Result;
 use async_trait::async_trait;
+use futures::StreamExt;
 use mcp_core::tool::Tool;
-use reqwest::Client;
-use serde_json::Value;
+use reqwest::{Client, StatusCode};
+use serde::Deserialize;
+use serde_json::{json, Value};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
 use std::time::Duration;
+use tokio::sync::OnceCell;
 
 pub const OLLAMA_HOST: &str = "http://localhost:11434";
 pub const OLLAMA_DEFAULT_MODEL: &str = "qwen2.5";
 // Ollama can run many models, we only provide the default
 pub const OLLAMA_KNOWN_MODELS: &[&str] = &[OLLAMA_DEFAULT_MODEL];
 pub const OLLAMA_DOC_URL: &str = "https://ollama.com/library";
+pub const OLLAMA_DEFAULT_EMBEDDING_MODEL: &str = "nomic-embed-text";
 
+/// The largest context window requested unless OLLAMA_NUM_CTX or a context limit says otherwise
+///
+/// Many models accept far more, but the memory for the window is reserved when
+/// the model loads. Ollama's own default of 2048 tokens silently cuts off most
+/// agent conversations, though.
+pub const OLLAMA_DEFAULT_MAX_CONTEXT: usize = 32_768;
+
+/// Progress of a model download, as reported by Ollama's pull API
+#[derive(Debug, Clone, Deserialize)]
+pub struct PullStatus {
+    pub status: String,
+    #[serde(default)]
+    pub total: Option<u64>,
+    #[serde(default)]
+    pub completed: Option<u64>,
+}
+
+/// Called with every progress update while a model is pulled
+pub type PullProgress = Arc<dyn Fn(&PullStatus) + Send + Sync>;
+
+/// What the model manifest says about the model
+#[derive(Debug, Clone, Default)]
+struct ModelInfo {
+    context_length: Option<usize>,
+    supports_tools: bool,
+}
+
+/// Local models through Ollama's native chat API
+///
+/// A model that is not installed yet is pulled on first use, unless
+/// OLLAMA_AUTO_PULL is false. The context window is sized from the model
+/// manifest and models without function calling get the tools described in
+/// the system prompt instead, see `formats::ollama::tool_emulation_prompt`.
 #[derive(serde::Serialize)]
 pub struct OllamaProvider {
     #[serde(skip)]
     client: Client,
     host: String,
     model: ModelConfig,
+    keep_alive: Option<Value>,
+    num_ctx: Option<usize>,
+    auto_pull: bool,
+    embedding_model: String,
+    #[serde(skip)]
+    info: OnceCell<ModelInfo>,
+    #[serde(skip)]
+    emulate_tools: AtomicBool,
+    #[serde(skip)]
+    pull_progress: Option<PullProgress>,
+    retry: RetryConfig,
 }
 
 impl Default for OllamaProvider {
@@ -47,15 +101,188 @@ impl OllamaProvider {
             client,
             host,
             model,
+            keep_alive: config.get("OLLAMA_KEEP_ALIVE").ok(),
+            num_ctx: config.get("OLLAMA_NUM_CTX").ok(),
+            auto_pull: config.get("OLLAMA_AUTO_PULL").unwrap_or(true),
+            embedding_model: config
+                .get("OLLAMA_EMBEDDING_MODEL")
+                .unwrap_or_else(|_| OLLAMA_DEFAULT_EMBEDDING_MODEL.to_string()),
+            info: OnceCell::new(),
+            emulate_tools: AtomicBool::new(false),
+            pull_progress: None,
+            retry: RetryConfig::default(),
         })
     }
 
-    async fn post(&self, payload: Value) -> Result<Value, ProviderError> {
-        let url = format!("{}/v1/chat/completions", self.host.trim_end_matches('/'));
+    /// Report pull progress to `progress` instead of the log
+    pub fn with_pull_progress(mut self, progress: PullProgress) -> Self {
+        self.pull_progress = Some(progress);
+        self
+    }
+
+    fn url(&self, path: &str) -> String {
+        format!("{}/{}", self.host.trim_end_matches('/'), path)
+    }
+
+    /// The model's manifest from /api/show, `None` when the model is not installed
+    async fn show(&self) -> Result<Option<ModelInfo>, ProviderError> {
+        let response = self
+            .client
+            .post(self.url("api/show"))
+            .json(&json!({"model": self.model.model_name}))
+            .send()
+            .await?;
+        if response.status() == StatusCode::NOT_FOUND {
+            return Ok(None);
+        }
+        let show = handle_response(response).await?;
+
+        let context_length = show
+            .get("model_info")
+            .and_then(|info| info.as_object())
+            .and_then(|info| {
+                info.iter()
+                    .find(|(key, _)| key.ends_with(".context_length"))
+                    .and_then(|(_, value)| value.as_u64())
+            })
+            .map(|length| length as usize);
+        // Older versions don't list capabilities, their templates mention the tools
+        let supports_tools = match show.get("capabilities").and_then(|c| c.as_array()) {
+            Some(capabilities) => capabilities.iter().any(|c| c == "tools"),
+            None => show
+                .get("template")
+                .and_then(|t| t.as_str())
+                .is_some_and(|template| template.contains(".Tools")),
+        };
+        Ok(Some(ModelInfo {
+            context_length,
+            supports_tools,
+        }))
+    }
+
+    /// Download the model, reporting progress as it arrives
+    pub async fn pull(&self) -> Result<(), ProviderError> {
+        // Downloads take as long as they take, without the client's request timeout
+        let response = Client::new()
+            .post(self.url("api/pull"))
+            .json(&json!({"model": self.model.model_name, "stream": true}))
+            .send()
+            .await?;
+        if !response.status().is_success() {
+            return handle_response(response).await.map(|_| ());
+        }
+
+        let mut buffer = Vec::new();
+        let mut stream = response.bytes_stream();
+        let mut last_status = String::new();
+        while let Some(chunk) = stream.next().await {
+            buffer.extend_from_slice(&chunk?);
+            while let Some(end) = buffer.iter().position(|&b| b == b'\n') {
+                let line: Vec<u8> = buffer.drain(..=end).collect();
+                let Ok(update) = serde_json::from_slice::<Value>(&line) else {
+                    continue;
+                };
+                if let Some(error) = update.get("error").and_then(|e| e.as_str()) {
+                    return Err(ProviderError::request_failed(format!(
+                        "Could not pull {}: {}",
+                        self.model.model_name, error
+                    )));
+                }
+                let Ok(status) = serde_json::from_value::<PullStatus>(update) else {
+                    continue;
+                };
+                match &self.pull_progress {
+                    Some(progress) => progress(&status),
+                    None if status.status != last_status => {
+                        tracing::info!("Pulling {}: {}", self.model.model_name, status.status)
+                    }
+                    None => {}
+                }
+                last_status = status.status;
+            }
+        }
+        Ok(())
+    }
+
+    /// The model's manifest, pulling the model first when it is missing
+    async fn model_info(&self) -> Result<&ModelInfo, ProviderError> {
+        self.info
+            .get_or_try_init(|| async {
+                let info = match self.show().await {
+                    Ok(Some(info)) => info,
+                    Ok(None) if self.auto_pull => {
+                        self.pull().await?;
+                        self.show().await?.unwrap_or_default()
+                    }
+                    Ok(None) => return Err(model_not_found(&self.model.model_name, &[])),
+                    Err(e) => {
+                        tracing::debug!("Could not read the model manifest: {}", e);
+                        ModelInfo {
+                            supports_tools: true,
+                            ..Default::default()
+                        }
+                    }
+                };
+                if !info.supports_tools {
+                    tracing::info!(
+                        "{} has no native function calling, tools are described in the prompt",
+                        self.model.model_name
+                    );
+                    self.emulate_tools.store(true, Ordering::SeqCst);
+                }
+                Ok(info)
+            })
+            .await
+    }
 
-        let response = self.client.post(&url).json(&payload).send().await?;
+    /// The context window to request, from the override, the configured limit or the manifest
+    fn negotiated_context(&self, info: Option<&ModelInfo>) -> Option<usize> {
+        if self.num_ctx.is_some() {
+            return self.num_ctx;
+        }
+        let cap = self
+            .model
+            .context_limit
+            .unwrap_or(OLLAMA_DEFAULT_MAX_CONTEXT);
+        info.and_then(|info| info.context_length)
+            .map(|length| length.min(cap))
+    }
+
+    async fn post(&self, payload: &Value) -> Result<Value, ProviderError> {
+        let url = self.url("api/chat");
+        let response = send_with_retry(&self.retry, || async {
+            Ok(self.client.post(&url).json(payload).send().await?)
+        })
+        .await?;
+        handle_response(response).await
+    }
+}
 
-        handle_response_openai_compat(response).await
+/// Map Ollama's `{"error": ...}` answers to provider errors
+async fn handle_response(response: reqwest::Response) -> Result<Value, ProviderError> {
+    let status = response.status();
+    let payload: Option<Value> = response.json().await.ok();
+    if status == StatusCode::OK {
+        return payload.ok_or_else(|| {
+            ProviderError::request_failed("Response body is not valid JSON".to_string())
+        });
+    }
+    let message = payload
+        .as_ref()
+        .and_then(|p| p.get("error"))
+        .and_then(|e| e.as_str())
+        .unwrap_or("Unknown error")
+        .to_string();
+    tracing::debug!(
+        "Provider request failed with status: {}. Payload: {:?}",
+        status,
+        payload
+    );
+    match status {
+        StatusCode::INTERNAL_SERVER_ERROR | StatusCode::SERVICE_UNAVAILABLE => {
+            Err(ProviderError::ServerError(message))
+        }
+        _ => Err(ProviderError::request_failed(message).with_status(status.as_u16())),
     }
 }
 
@@ -69,17 +296,52 @@ impl Provider for OllamaProvider {
             OLLAMA_DEFAULT_MODEL,
             OLLAMA_KNOWN_MODELS.iter().map(|&s| s.to_string()).collect(),
             OLLAMA_DOC_URL,
-            vec![ConfigKey::new(
-                "OLLAMA_HOST",
-                true,
-                false,
-                Some(OLLAMA_HOST),
-            )],
+            vec![
+                ConfigKey::new("OLLAMA_HOST", true, false, Some(OLLAMA_HOST)),
+                ConfigKey::new("OLLAMA_KEEP_ALIVE", false, false, None),
+                ConfigKey::new("OLLAMA_NUM_CTX", false, false, None),
+                ConfigKey::new("OLLAMA_AUTO_PULL", false, false, Some("true")),
+                ConfigKey::new(
+                    "OLLAMA_EMBEDDING_MODEL",
+                    false,
+                    false,
+                    Some(OLLAMA_DEFAULT_EMBEDDING_MODEL),
+                ),
+            ],
         )
     }
 
+    fn instance_metadata(&self) -> ProviderMetadata {
+        Self::metadata()
+    }
+
+    /// The model config, with the context window negotiated once the first request is made
     fn get_model_config(&self) -> ModelConfig {
-        self.model.clone()
+        match self.negotiated_context(self.info.get()) {
+            Some(num_ctx) => self.model.clone().with_context_limit(Some(num_ctx)),
+            None => self.model.clone(),
+        }
+    }
+
+    /// The body of the next request, sized by the manifest once the first request has read it
+    fn request_body(
+        &self,
+        system: &str,
+        messages: &[Message],
+        tools: &[Tool],
+    ) -> Result<Value, ProviderError> {
+        let options = ChatOptions {
+            keep_alive: self.keep_alive.clone(),
+            num_ctx: self.negotiated_context(self.info.get()),
+            emulate_tools: self.emulate_tools.load(Ordering::SeqCst),
+        };
+        Ok(create_request(
+            &self.model,
+            system,
+            messages,
+            tools,
+            &options,
+        )?)
     }
 
     #[tracing::instrument(
@@ -92,28 +354,259 @@ impl Provider for OllamaProvider {
         messages: &[Message],
         tools: &[Tool],
     ) -> Result<(Message, ProviderUsage), ProviderError> {
-        let payload = create_request(
-            &self.model,
-            system,
-            messages,
-            tools,
-            &super::utils::ImageFormat::OpenAi,
-        )?;
+        validate_tool_pairing(messages)?;
+        let info = self.model_info().await?;
+        let mut options = ChatOptions {
+            keep_alive: self.keep_alive.clone(),
+            num_ctx: self.negotiated_context(Some(info)),
+            emulate_tools: self.emulate_tools.load(Ordering::SeqCst),
+        };
 
-        let response = self.post(payload.clone()).await?;
+        let mut payload = create_request(&self.model, system, messages, tools, &options)?;
+        let response = match self.post(&payload).await {
+            // The manifest can miss that a model has no function calling
+            Err(ProviderError::RequestFailed { message, .. })
+                if !options.emulate_tools && message.contains("does not support tools") =>
+            {
+                tracing::info!(
+                    "{} rejected native tools, describing them in the prompt",
+                    self.model.model_name
+                );
+                self.emulate_tools.store(true, Ordering::SeqCst);
+                options.emulate_tools = true;
+                payload = create_request(&self.model, system, messages, tools, &options)?;
+                self.post(&payload).await?
+            }
+            result => result?,
+        };
 
         // Parse response
-        let message = response_to_message(response.clone())?;
-        let usage = match get_usage(&response) {
-            Ok(usage) => usage,
-            Err(ProviderError::UsageError(e)) => {
-                tracing::warn!("Failed to get usage data: {}", e);
-                Usage::default()
-            }
-            Err(e) => return Err(e),
+        // DeepSeek-R1 style models reason inline between think tags
+        let thinking_tags = if self.model.thinking_tags.is_empty() {
+            vec!["think".to_string()]
+        } else {
+            self.model.thinking_tags.clone()
         };
-        let model = get_model(&response);
-        super::utils::emit_debug_trace(self, &payload, &response, &usage);
+        let message = response_to_message(&response, options.emulate_tools, &thinking_tags)?;
+        let usage = get_usage(&response);
+        let model = response
+            .get("model")
+            .and_then(|m| m.as_str())
+            .unwrap_or(&self.model.model_name)
+            .to_string();
+        emit_debug_trace(self, messages, &payload, &response, &usage);
         Ok((message, ProviderUsage::new(model, usage)))
     }
+
+    async fn embed(&self, inputs: &[String]) -> Result<Vec<Vec<f32>>, ProviderError> {
+        if inputs.is_empty() {
+            return Ok(Vec::new());
+        }
+        let url = self.url("api/embed");
+        let payload = json!({"model": self.embedding_model, "input": inputs});
+        let response = send_with_retry(&self.retry, || async {
+            Ok(self.client.post(&url).json(&payload).send().await?)
+        })
+        .await?;
+        let response = handle_response(response).await?;
+        let embeddings: Vec<Vec<f32>> = response
+            .get("embeddings")
+            .cloned()
+            .and_then(|e| serde_json::from_value(e).ok())
+            .ok_or_else(|| {
+                ProviderError::request_failed("Response has no embeddings".to_string())
+            })?;
+        if embeddings.len() != inputs.len() {
+            return Err(ProviderError::request_failed(format!(
+                "Got {} embeddings for {} inputs",
+                embeddings.len(),
+                inputs.len()
+            )));
+        }
+        Ok(embeddings)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Mutex;
+    use wiremock::matchers::{body_partial_json, method, path};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    fn provider(host: &str, model: &str) -> OllamaProvider {
+        OllamaProvider {
+            client: Client::new(),
+            host: host.to_string(),
+            model: ModelConfig::new(model.to_string()),
+            keep_alive: Some(json!("30m")),
+            num_ctx: None,
+            auto_pull: true,
+            embedding_model: OLLAMA_DEFAULT_EMBEDDING_MODEL.to_string(),
+            info: OnceCell::new(),
+            emulate_tools: AtomicBool::new(false),
+            pull_progress: None,
+            retry: RetryConfig::new(1),
+        }
+    }
+
+    fn chat_response(content: &str) -> ResponseTemplate {
+        ResponseTemplate::new(200).set_body_json(json!({
+            "model": "gemma2",
+            "message": {"role": "assistant", "content": content},
+            "done": true,
+            "prompt_eval_count": 20,
+            "eval_count": 5
+        }))
+    }
+
+    #[tokio::test]
+    async fn test_pulls_missing_model_and_sizes_context() -> Result<()> {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/api/show"))
+            .respond_with(
+                ResponseTemplate::new(404).set_body_json(json!({"error": "model not found"})),
+            )
+            .up_to_n_times(1)
+            .mount(&server)
+            .await;
+        Mock::given(method("POST"))
+            .and(path("/api/show"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+                "model_info": {"gemma2.context_length": 8192},
+                "capabilities": ["completion"]
+            })))
+            .mount(&server)
+            .await;
+        Mock::given(method("POST"))
+            .and(path("/api/pull"))
+            .respond_with(ResponseTemplate::new(200).set_body_string(
+                "{\"status\":\"pulling manifest\"}\n\
+                 {\"status\":\"pulling 7462734796d6\",\"total\":100,\"completed\":100}\n\
+                 {\"status\":\"success\"}\n",
+            ))
+            .expect(1)
+            .mount(&server)
+            .await;
+        Mock::given(method("POST"))
+            .and(path("/api/chat"))
+            .and(body_partial_json(
+                json!({"keep_alive": "30m", "options": {"num_ctx": 8192}}),
+            ))
+            .respond_with(chat_response(
+                "```json\n{\"tool\": \"shell\", \"arguments\": {\"command\": \"ls\"}}\n```",
+            ))
+            .mount(&server)
+            .await;
+
+        let statuses = Arc::new(Mutex::new(Vec::new()));
+        let seen = statuses.clone();
+        let provider = provider(&server.uri(), "gemma2").with_pull_progress(Arc::new(
+            move |status: &PullStatus| seen.lock().unwrap().push(status.status.clone()),
+        ));
+        let tool = Tool::new("shell", "Run a command", json!({"type": "object"}));
+        let (message, usage) = provider
+            .complete("", &[Message::user().with_text("List files")], &[tool])
+            .await?;
+
+        assert_eq!(statuses.lock().unwrap().last().unwrap(), "success");
+        assert_eq!(provider.get_model_config().context_limit(), 8192);
+        // gemma2 has no function calling, so the call was read from the text
+        let request = message.content[0].as_tool_request().unwrap();
+        assert_eq!(request.tool_call.as_ref().unwrap().name, "shell");
+        assert_eq!(usage.usage.total_tokens, Some(25));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_retries_server_errors() -> Result<()> {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/api/show"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+                "capabilities": ["completion", "tools"]
+            })))
+            .mount(&server)
+            .await;
+        Mock::given(method("POST"))
+            .and(path("/api/chat"))
+            .respond_with(ResponseTemplate::new(503))
+            .up_to_n_times(1)
+            .mount(&server)
+            .await;
+        Mock::given(method("POST"))
+            .and(path("/api/chat"))
+            .respond_with(chat_response("Hello"))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let mut provider = provider(&server.uri(), "llama3.2");
+        provider.retry =
+            RetryConfig::new(2).with_backoff(Duration::from_millis(1), Duration::from_millis(1));
+        let (message, _) = provider
+            .complete("", &[Message::user().with_text("Hi")], &[])
+            .await?;
+        assert_eq!(message.as_concat_text(), "Hello");
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_falls_back_to_emulated_tools() -> Result<()> {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/api/show"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+                "capabilities": ["completion", "tools"]
+            })))
+            .mount(&server)
+            .await;
+        Mock::given(method("POST"))
+            .and(path("/api/chat"))
+            .and(body_partial_json(json!({"tools": [{"type": "function"}]})))
+            .respond_with(ResponseTemplate::new(400).set_body_json(
+                json!({"error": "registry.ollama.ai/library/tiny does not support tools"}),
+            ))
+            .mount(&server)
+            .await;
+        Mock::given(method("POST"))
+            .and(path("/api/chat"))
+            .respond_with(chat_response("No tools needed."))
+            .mount(&server)
+            .await;
+
+        let provider = provider(&server.uri(), "tiny");
+        let tool = Tool::new("shell", "Run a command", json!({"type": "object"}));
+        let (message, _) = provider
+            .complete("", &[Message::user().with_text("Hello")], &[tool])
+            .await?;
+        assert_eq!(message.as_concat_text(), "No tools needed.");
+        assert!(provider.emulate_tools.load(Ordering::SeqCst));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_embeds_with_the_embedding_model() -> Result<()> {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/api/embed"))
+            .and(body_partial_json(json!({
+                "model": OLLAMA_DEFAULT_EMBEDDING_MODEL,
+                "input": ["first", "second"]
+            })))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+                "model": OLLAMA_DEFAULT_EMBEDDING_MODEL,
+                "embeddings": [[0.1, 0.2], [0.3, 0.4]]
+            })))
+            .mount(&server)
+            .await;
+
+        let provider = provider(&server.uri(), "gemma2");
+        let embeddings = provider
+            .embed(&["first".to_string(), "second".to_string()])
+            .await?;
+        assert_eq!(embeddings, vec![vec![0.1, 0.2], vec![0.3, 0.4]]);
+        Ok(())
+    }
 }
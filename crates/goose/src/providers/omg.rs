@@ -1,70 +1,1005 @@
-use crate::message::Message;
-use crate::model::ModelConfig;
-use crate::providers::base::{ConfigKey, Provider, ProviderMetadata, ProviderUsage, Usage};
+use crate::config::{ConfigError, Secret};
+use crate::message::{Message, MessageContent};
+use crate::model::{ModelConfig, PromptTemplate};
+use crate::providers::base::{
+    CacheControl, ConfigKey, MessageDelta, MessageStream, Provider, ProviderMetadata,
+    ProviderUsage, RateLimits, RequestInterceptor, Usage,
+};
 use crate::providers::errors::ProviderError;
-use crate::providers::formats::openai::{create_request, get_usage, response_to_message};
-use crate::providers::utils::{emit_debug_trace, get_model, handle_response_openai_compat};
-use mcp_core::tool::Tool;
+use crate::providers::formats::openai::{
+    create_embedding_request, create_request, get_usage, response_to_embeddings,
+    response_to_message, response_to_models,
+};
+use crate::providers::streaming::{
+    buffered_stream, cap_output_tokens, compatible_message_stream, finished_stream,
+    first_token_timeout, reconnecting_stream, sse_events, DEFAULT_STREAM_BUFFER_SIZE,
+};
+use crate::providers::utils::{
+    apply_response_locale, check_content_support, downconvert_content, effective_prefill,
+    emit_debug_trace, finish_message, get_model, get_system_fingerprint,
+    handle_response_openai_compat, is_model_not_found, model_not_found, needs_finishing,
+    perplexity, prepend_prefill, rate_limits_from_headers, send_with_retry, validate_params,
+    validate_tool_pairing, validate_tools, RetryConfig, DEFAULT_MAX_ATTEMPTS,
+};
+use crate::token_counter::TokenCounter;
 use anyhow::Result;
 use async_trait::async_trait;
-use reqwest::{Client, header};
-use serde::Serialize;
-use serde_json::Value;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use futures::StreamExt;
+use mcp_core::role::Role;
+use mcp_core::tool::Tool;
+use reqwest::{header, Certificate, Client, RequestBuilder, Response, StatusCode};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::io::Write;
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Duration;
+use uuid::Uuid;
 
 const OMG_API_URL: &str = "https://api.ohmygpt.com/v1";
+/// The balance endpoint, relative to the host without its trailing `/v1`
+const OMG_BALANCE_PATH: &str = "api/v1/user/admin/balance";
 const OMG_DEFAULT_MODEL: &str = "gpt-4o";
+const OMG_DEFAULT_EMBEDDING_MODEL: &str = "text-embedding-3-small";
 const OMG_DOC_URL: &str = "https://docs.ohmygpt.com";
 const OMG_KNOWN_MODELS: &[&str] = &["gpt-4o", "claude-3-5-sonnet"];
+const OMG_DEFAULT_CONNECT_TIMEOUT_SECS: u64 = 5;
+const OMG_DEFAULT_READ_TIMEOUT_SECS: u64 = 600;
+/// Smaller request bodies are sent as is even when compression is enabled
+const OMG_DEFAULT_COMPRESS_MIN_BYTES: usize = 64 * 1024;
+/// Lets the backend recognize a resent request and answer it only once
+const IDEMPOTENCY_KEY_HEADER: &str = "Idempotency-Key";
+
+/// Remaining credit on an OhMyGPT account
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Balance {
+    /// Remaining credit, in the units reported by the account
+    pub remaining: f64,
+    /// Credit used so far, when the endpoint reports it
+    pub used: Option<f64>,
+}
+
+/// Named settings for the provider, loaded from a shared profile file
+///
+/// The file maps profile names to settings and may be JSON, YAML or, with a
+/// `.toml` extension, TOML:
+///
+/// ```yaml
+/// fast:
+///   model: gpt-4o-mini
+///   temperature: 0.2
+///   max_output_tokens: 1024
+/// review:
+///   temperature: 0.0
+///   stop: ["</review>"]
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct OmgProfile {
+    pub model: Option<String>,
+    pub temperature: Option<f32>,
+    pub top_p: Option<f32>,
+    pub frequency_penalty: Option<f32>,
+    pub presence_penalty: Option<f32>,
+    #[serde(default)]
+    pub stop: Vec<String>,
+    pub max_tokens: Option<i32>,
+    pub context_limit: Option<usize>,
+    pub base_url: Option<String>,
+    pub stream_max_reconnects: Option<usize>,
+    pub max_output_tokens: Option<i32>,
+    pub max_input_tokens: Option<usize>,
+}
+
+impl OmgProfile {
+    /// Load the profile called `name` from the file at `path`
+    pub fn load(path: impl AsRef<Path>, name: &str) -> Result<Self> {
+        let path = path.as_ref();
+        let contents = std::fs::read_to_string(path).map_err(|e| {
+            anyhow::anyhow!("Failed to read profile file {}: {}", path.display(), e)
+        })?;
+        let is_toml = path.extension().is_some_and(|ext| ext == "toml");
+        // YAML is a superset of JSON, so it reads both
+        let profiles: Result<HashMap<String, OmgProfile>> = if is_toml {
+            toml::from_str(&contents).map_err(anyhow::Error::from)
+        } else {
+            serde_yaml::from_str(&contents).map_err(anyhow::Error::from)
+        };
+        let mut profiles = profiles
+            .map_err(|e| anyhow::anyhow!("Invalid profile file {}: {}", path.display(), e))?;
+
+        let profile = profiles.remove(name).ok_or_else(|| {
+            let mut names: Vec<_> = profiles.keys().cloned().collect();
+            names.sort();
+            anyhow::anyhow!(
+                "Unknown profile '{}' in {}, available profiles: {}",
+                name,
+                path.display(),
+                names.join(", ")
+            )
+        })?;
+        profile.validate()?;
+        Ok(profile)
+    }
+
+    fn validate(&self) -> Result<()> {
+        if let Some(temperature) = self.temperature {
+            if !(0.0..=2.0).contains(&temperature) {
+                anyhow::bail!(
+                    "Profile temperature must be between 0 and 2, got {}",
+                    temperature
+                );
+            }
+        }
+        if let Some(top_p) = self.top_p {
+            if !(top_p > 0.0 && top_p <= 1.0) {
+                anyhow::bail!("Profile top_p must be above 0 and at most 1, got {}", top_p);
+            }
+        }
+        for (name, penalty) in [
+            ("frequency_penalty", self.frequency_penalty),
+            ("presence_penalty", self.presence_penalty),
+        ] {
+            if let Some(penalty) = penalty.filter(|p| !(-2.0..=2.0).contains(p)) {
+                anyhow::bail!("Profile {} must be between -2 and 2, got {}", name, penalty);
+            }
+        }
+        if let Some(base_url) = &self.base_url {
+            reqwest::Url::parse(base_url)
+                .map_err(|e| anyhow::anyhow!("Invalid profile base_url {}: {}", base_url, e))?;
+        }
+        Ok(())
+    }
+
+    /// Build the model config described by the profile
+    fn model_config(&self) -> ModelConfig {
+        let model_name = self
+            .model
+            .clone()
+            .unwrap_or_else(|| OMG_DEFAULT_MODEL.to_string());
+        ModelConfig::new(model_name)
+            .with_temperature(self.temperature)
+            .with_top_p(self.top_p)
+            .with_frequency_penalty(self.frequency_penalty)
+            .with_presence_penalty(self.presence_penalty)
+            .with_stop_sequences(self.stop.clone())
+            .with_max_tokens(self.max_tokens)
+            .with_context_limit(self.context_limit)
+    }
+}
 
 #[derive(Debug, Clone, Serialize)]
 pub struct OmgProvider {
     #[serde(skip)]
     client: Client,
-    api_key: String,
+    host: String,
+    api_key: Secret,
     model: ModelConfig,
+    /// The model `embed` asks for, separate from the chat model
+    embedding_model: String,
+    /// How many times a dropped stream is restarted, only used for deterministic requests
+    stream_max_reconnects: usize,
+    /// How many streamed deltas are buffered ahead of a slow consumer
+    stream_buffer_size: usize,
+    /// Abort a stream whose first token takes longer than this
+    first_token_timeout: Option<Duration>,
+    /// How long opening a connection may take
+    connect_timeout: Duration,
+    /// How long a non-streaming request may take in total, streams are bounded by the first token timeout
+    read_timeout: Duration,
+    /// Hard ceiling on the output tokens of any request
+    max_output_tokens: Option<i32>,
+    /// Requests whose estimated input exceeds this are rejected before sending
+    max_input_tokens: Option<usize>,
+    /// Check tool schemas before sending instead of relying on the API to reject them
+    strict_tools: bool,
+    /// Gzip request bodies of at least this many bytes, only set for endpoints that accept them
+    compress_min_bytes: Option<usize>,
+    /// The system prompt for requests that come without one
+    default_system: Option<String>,
+    /// Pretty print request bodies in dry runs, the bytes sent are always compact
+    pretty_requests: bool,
+    /// How requests that hit a rate limit, server error or failed connection are retried
+    retry: RetryConfig,
+    /// Run in order on every request body before it is sent
+    #[serde(skip)]
+    interceptors: Vec<Arc<dyn RequestInterceptor>>,
+    /// Sent with every request, e.g. for a gateway that routes or authorizes on a header
+    #[serde(skip)]
+    custom_headers: header::HeaderMap,
 }
 
+/// The system prompt, messages and tools of a request that passed `checked_request`
+type CheckedRequest<'a> = (String, Cow<'a, [Message]>, &'a [Tool]);
+
 impl OmgProvider {
     pub fn from_env(model: ModelConfig) -> Result<Self> {
+        Self::from_config(model, &OmgProfile::default())
+    }
+
+    /// Create the provider from a named profile in a JSON or YAML profile file
+    ///
+    /// Settings from the environment or goose config still take precedence over the profile.
+    pub fn from_profile(path: impl AsRef<Path>, profile_name: &str) -> Result<Self> {
+        let profile = OmgProfile::load(path, profile_name)?;
+        Self::from_config(profile.model_config(), &profile)
+    }
+
+    fn from_config(model: ModelConfig, profile: &OmgProfile) -> Result<Self> {
         let config = crate::config::Config::global();
-        let api_key: String = config.get_secret("OMG_API_KEY")?;
+        let api_key = config.get_secret_handle("OMG_API_KEY")?;
+        let stream_max_reconnects: usize = config
+            .get("OMG_STREAM_MAX_RECONNECTS")
+            .ok()
+            .or(profile.stream_max_reconnects)
+            .unwrap_or(0);
+        let stream_buffer_size: usize = config
+            .get("OMG_STREAM_BUFFER_SIZE")
+            .unwrap_or(DEFAULT_STREAM_BUFFER_SIZE);
+        let first_token_timeout: Option<Duration> = config
+            .get("OMG_FIRST_TOKEN_TIMEOUT_MS")
+            .ok()
+            .map(Duration::from_millis);
+        let connect_timeout = Duration::from_secs(
+            config
+                .get("OMG_CONNECT_TIMEOUT")
+                .unwrap_or(OMG_DEFAULT_CONNECT_TIMEOUT_SECS),
+        );
+        let read_timeout = Duration::from_secs(
+            config
+                .get("OMG_READ_TIMEOUT")
+                .unwrap_or(OMG_DEFAULT_READ_TIMEOUT_SECS),
+        );
+        // A malformed cap must not silently become no cap
+        let max_output_tokens: Option<i32> =
+            optional_setting(config, "OMG_MAX_OUTPUT_TOKENS")?.or(profile.max_output_tokens);
+        let max_input_tokens: Option<usize> =
+            optional_setting(config, "OMG_MAX_INPUT_TOKENS")?.or(profile.max_input_tokens);
+        let host = parse_host(
+            config
+                .get("OMG_HOST")
+                .ok()
+                .or_else(|| profile.base_url.clone())
+                .unwrap_or_else(|| OMG_API_URL.to_string()),
+        )?;
+        let custom_headers = match config.get::<Value>("OMG_CUSTOM_HEADERS") {
+            Ok(value) => parse_custom_headers(&value)?,
+            Err(_) => header::HeaderMap::new(),
+        };
+        let strict_tools: bool = config.get("OMG_STRICT_TOOLS").unwrap_or(false);
+        // Endpoints that do not accept compressed bodies answer with a 400, so this is opt in
+        let compress_requests: bool = config.get("OMG_COMPRESS_REQUESTS").unwrap_or(false);
+        let compress_min_bytes = compress_requests.then(|| {
+            config
+                .get("OMG_COMPRESS_MIN_BYTES")
+                .unwrap_or(OMG_DEFAULT_COMPRESS_MIN_BYTES)
+        });
+        let default_system: Option<String> = config.get("OMG_DEFAULT_SYSTEM").ok();
+        let ca_bundle: Option<String> = config.get("OMG_CA_BUNDLE").ok();
+        let tls_insecure: bool = config.get("OMG_TLS_INSECURE").unwrap_or(false);
+        let pretty_requests: bool = config.get("OMG_PRETTY_REQUESTS").unwrap_or(false);
+        // Only gateways in front of Claude models read the breakpoints, others may reject them
+        let cache_prompt: bool = config.get("OMG_CACHE_PROMPT").unwrap_or(false);
+        let model = if cache_prompt {
+            model.with_cache_control(Some(CacheControl::Ephemeral))
+        } else {
+            model
+        };
+        let max_attempts: u32 = config
+            .get("OMG_MAX_ATTEMPTS")
+            .unwrap_or(DEFAULT_MAX_ATTEMPTS);
+        let embedding_model: String = config
+            .get("OMG_EMBEDDING_MODEL")
+            .unwrap_or_else(|_| OMG_DEFAULT_EMBEDDING_MODEL.to_string());
 
         Ok(Self {
-            client: Client::new(),
+            client: build_client(ca_bundle.as_deref(), tls_insecure, connect_timeout)?,
+            host,
             api_key,
             model,
+            embedding_model,
+            stream_max_reconnects,
+            stream_buffer_size,
+            first_token_timeout,
+            connect_timeout,
+            read_timeout,
+            max_output_tokens,
+            max_input_tokens,
+            strict_tools,
+            compress_min_bytes,
+            default_system,
+            pretty_requests,
+            retry: RetryConfig::new(max_attempts),
+            interceptors: Vec::new(),
+            custom_headers,
         })
     }
 
+    /// Add an interceptor that can rewrite each request body before it is sent
+    pub fn with_interceptor(mut self, interceptor: Arc<dyn RequestInterceptor>) -> Self {
+        self.interceptors.push(interceptor);
+        self
+    }
+
+    fn intercept(&self, payload: &mut Value) {
+        for interceptor in &self.interceptors {
+            interceptor.intercept(payload);
+        }
+    }
+
+    /// Use a different API key, e.g. to spread load across several keys
+    pub fn with_api_key(mut self, api_key: String) -> Self {
+        self.api_key = Secret::fixed(api_key);
+        self
+    }
+
+    /// The caller's system prompt, or `OMG_DEFAULT_SYSTEM` when the caller sent none
+    fn system_or_default<'a>(&'a self, system: &'a str) -> &'a str {
+        match &self.default_system {
+            Some(default) if system.trim().is_empty() => default,
+            _ => system,
+        }
+    }
+
+    /// The system prompt and conversation to send, checked against the model and the input cap
+    ///
+    /// Every endpoint goes through here, so the chat and completions requests
+    /// are validated and formatted the same way.
+    fn checked_request<'a>(
+        &self,
+        system: &str,
+        messages: &'a [Message],
+        tools: &'a [Tool],
+    ) -> Result<CheckedRequest<'a>, ProviderError> {
+        let (messages, tools) = if self.model.downconvert_unsupported {
+            let (messages, tools) = downconvert_content(&self.model, messages, tools);
+            (Cow::Owned(messages), tools)
+        } else {
+            (Cow::Borrowed(messages), tools)
+        };
+        check_content_support(&self.model, &messages, tools)?;
+        validate_params(&self.model)?;
+        validate_tool_pairing(&messages)?;
+        if self.strict_tools {
+            validate_tools(tools)?;
+        }
+        // Add the locale first so it sits inside what format_system_prompt wraps around the prompt
+        let system = apply_response_locale(
+            self.system_or_default(system),
+            self.model.response_locale.as_deref(),
+        );
+        let system = self.format_system_prompt(&system);
+
+        if let Some(cap) = self.max_input_tokens {
+            let counter = TokenCounter::new(self.model.tokenizer_name());
+            let estimated = counter.count_chat_tokens(&system, &messages, tools);
+            if estimated > cap {
+                return Err(ProviderError::ContextLengthExceeded(format!(
+                    "Estimated input of {} tokens exceeds the configured OMG_MAX_INPUT_TOKENS of {}",
+                    estimated, cap
+                )));
+            }
+        }
+        Ok((system, messages, tools))
+    }
+
+    /// Build the chat completion payload, enforcing the configured token caps
+    fn build_request(
+        &self,
+        system: &str,
+        messages: &[Message],
+        tools: &[Tool],
+    ) -> Result<Value, ProviderError> {
+        let (system, messages, tools) = self.checked_request(system, messages, tools)?;
+        let mut model = self.model.clone();
+        model.max_tokens = clamp_max_tokens(model.max_tokens, self.max_output_tokens);
+
+        let mut payload = create_request(
+            &model,
+            &system,
+            &messages,
+            tools,
+            &super::utils::ImageFormat::OpenAi,
+        )?;
+        // The default once tools are present, but the OMG gateway only calls tools when asked
+        if let Some(payload) = payload.as_object_mut().filter(|p| p.contains_key("tools")) {
+            payload.insert("tool_choice".to_string(), json!("auto"));
+        }
+        Ok(payload)
+    }
+
     fn create_headers(&self) -> Result<header::HeaderMap, ProviderError> {
-        let mut headers = header::HeaderMap::new();
+        // Custom headers come first so they can not replace the content type or the key
+        let mut headers = self.custom_headers.clone();
         headers.insert(
             header::CONTENT_TYPE,
             header::HeaderValue::from_static("application/json"),
         );
         headers.insert(
             header::AUTHORIZATION,
-            header::HeaderValue::from_str(&format!("Bearer {}", self.api_key))
+            header::HeaderValue::from_str(&format!("Bearer {}", self.api_key.expose()))
                 .map_err(|e| ProviderError::ExecutionError(e.to_string()))?,
         );
         Ok(headers)
     }
 
-    async fn post(&self, payload: Value) -> Result<Value, ProviderError> {
-        let url = format!("{}/chat/completions", OMG_API_URL);
+    /// The conversation ending in the configured prefill, and the prefill to prepend to the answer
+    fn prefilled<'a>(
+        &'a self,
+        messages: &'a [Message],
+    ) -> Result<(Cow<'a, [Message]>, Option<&'a str>), ProviderError> {
+        // The model continues from a trailing assistant message holding the prefill
+        let prefill = effective_prefill(&self.model)?;
+        let messages = match prefill {
+            Some(text) => Cow::Owned([messages, &[Message::assistant().with_text(text)]].concat()),
+            None => Cow::Borrowed(messages),
+        };
+        Ok((messages, prefill))
+    }
 
-        let response = self
+    /// The body `complete` would send for this conversation, without sending it
+    ///
+    /// Pretty printed with `OMG_PRETTY_REQUESTS`, which is easier to read for
+    /// large tool definitions. Otherwise these are exactly the bytes sent,
+    /// before any compression.
+    pub fn dry_run(
+        &self,
+        system: &str,
+        messages: &[Message],
+        tools: &[Tool],
+    ) -> Result<String, ProviderError> {
+        let payload = self.request_body(system, messages, tools)?;
+        let body = if self.pretty_requests {
+            serde_json::to_string_pretty(&payload)
+        } else {
+            serde_json::to_string(&payload)
+        };
+        body.map_err(|e| ProviderError::ExecutionError(e.to_string()))
+    }
+
+    /// A POST request carrying `payload`, gzipped when it is large enough and compression is enabled
+    ///
+    /// Every attempt at the same logical request must use the same `idempotency_key`.
+    fn request(
+        &self,
+        url: &str,
+        payload: &Value,
+        idempotency_key: &str,
+    ) -> Result<RequestBuilder, ProviderError> {
+        let body = serde_json::to_vec(payload)
+            .map_err(|e| ProviderError::ExecutionError(e.to_string()))?;
+        let request = self
             .client
-            .post(&url)
+            .post(url)
             .headers(self.create_headers()?)
-            .json(&payload)
-            .send()
-            .await?;
+            .header(IDEMPOTENCY_KEY_HEADER, idempotency_key);
+
+        match self.compress_min_bytes {
+            Some(min_bytes) if body.len() >= min_bytes => {
+                let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+                encoder
+                    .write_all(&body)
+                    .map_err(|e| ProviderError::ExecutionError(e.to_string()))?;
+                let compressed = encoder
+                    .finish()
+                    .map_err(|e| ProviderError::ExecutionError(e.to_string()))?;
+                Ok(request
+                    .header(header::CONTENT_ENCODING, "gzip")
+                    .body(compressed))
+            }
+            _ => Ok(request.body(body)),
+        }
+    }
+
+    async fn post(&self, path: &str, payload: Value) -> Result<Value, ProviderError> {
+        let url = format!("{}/{}", self.host.trim_end_matches('/'), path);
+
+        let idempotency_key = Uuid::new_v4().to_string();
+        let response = send_with_retry(&self.retry, || async {
+            Ok(self
+                .request(&url, &payload, &idempotency_key)?
+                .timeout(self.read_timeout)
+                .send()
+                .await?)
+        })
+        .await?;
+
+        self.handle_response(response, &payload).await
+    }
+
+    /// Map the response to an error where needed, suggesting model names when the model is unknown
+    async fn handle_response(
+        &self,
+        response: Response,
+        payload: &Value,
+    ) -> Result<Value, ProviderError> {
+        if response.status() != StatusCode::NOT_FOUND {
+            return handle_response_openai_compat(response)
+                .await
+                .map_err(|e| e.with_key_of(&Self::metadata()));
+        }
+
+        let body: Option<Value> = response.json().await.ok();
+        if !is_model_not_found(body.as_ref()) {
+            return Err(ProviderError::request_failed(format!(
+                "Request failed with status: {}",
+                StatusCode::NOT_FOUND
+            ))
+            .with_status(StatusCode::NOT_FOUND.as_u16()));
+        }
+        let requested = payload["model"].as_str().unwrap_or(&self.model.model_name);
+        let known: Vec<String> = OMG_KNOWN_MODELS.iter().map(|&s| s.to_string()).collect();
+        Err(model_not_found(requested, &known))
+    }
+
+    async fn post_stream(
+        &self,
+        payload: Value,
+        idempotency_key: &str,
+    ) -> Result<MessageStream, ProviderError> {
+        let url = format!("{}/chat/completions", self.host.trim_end_matches('/'));
+
+        let response = send_with_retry(&self.retry, || async {
+            Ok(self
+                .request(&url, &payload, idempotency_key)?
+                .send()
+                .await?)
+        })
+        .await?;
+
+        let status = response.status();
+        if status != StatusCode::OK {
+            // Any status other than OK is mapped to an error
+            self.handle_response(response, &payload).await?;
+            return Err(ProviderError::request_failed(
+                "Unexpected response to stream request".to_string(),
+            )
+            .with_status(status.as_u16()));
+        }
+
+        // Claude models may arrive as Anthropic style events
+        Ok(compatible_message_stream(sse_events(
+            response.bytes_stream(),
+        )))
+    }
+
+    /// Sample `n` completions and rank them by average token log probability
+    ///
+    /// Useful for best-of-n sampling and self-consistency checks. Fails with
+    /// `NotSupported` for models that do not return logprobs.
+    pub async fn complete_ranked(
+        &self,
+        system: &str,
+        messages: &[Message],
+        tools: &[Tool],
+        n: u32,
+    ) -> Result<Vec<RankedChoice>, ProviderError> {
+        if !self.model.capabilities().logprobs {
+            return Err(ProviderError::NotSupported(format!(
+                "{} does not return logprobs, which ranking requires",
+                self.model.model_name
+            )));
+        }
+
+        let mut payload = self.build_request(system, messages, tools)?;
+        payload["n"] = json!(n.max(1));
+        payload["logprobs"] = json!(true);
+        self.intercept(&mut payload);
+
+        let response = self.post("chat/completions", payload.clone()).await?;
+        let usage = get_usage(&response).unwrap_or_default();
+        emit_debug_trace(self, messages, &payload, &response, &usage);
+        ranked_choices(&response)
+    }
+
+    /// Complete the conversation and report how perplexed the model was by its own answer
+    ///
+    /// Requests logprobs and returns the perplexity of the generated tokens
+    /// next to the message, `None` when the completion is empty. Fails with
+    /// `NotSupported` for models that do not return logprobs.
+    pub async fn complete_with_perplexity(
+        &self,
+        system: &str,
+        messages: &[Message],
+        tools: &[Tool],
+    ) -> Result<(Message, ProviderUsage, Option<f64>), ProviderError> {
+        if !self.model.capabilities().logprobs {
+            return Err(ProviderError::NotSupported(format!(
+                "{} does not return logprobs, which perplexity requires",
+                self.model.model_name
+            )));
+        }
+
+        let mut payload = self.build_request(system, messages, tools)?;
+        payload["logprobs"] = json!(true);
+        self.intercept(&mut payload);
+
+        let response = self.post("chat/completions", payload.clone()).await?;
+        let message = response_to_message(response.clone())?;
+        let logprobs: Vec<f32> = response["choices"][0]["logprobs"]["content"]
+            .as_array()
+            .map(|tokens| {
+                tokens
+                    .iter()
+                    .filter_map(|t| t["logprob"].as_f64())
+                    .map(|logprob| logprob as f32)
+                    .collect()
+            })
+            .unwrap_or_default();
+        let usage = get_usage(&response).unwrap_or_default();
+        let model = get_model(&response);
+        emit_debug_trace(self, messages, &payload, &response, &usage);
+        Ok((
+            message,
+            ProviderUsage::new(model, usage)
+                .with_system_fingerprint(get_system_fingerprint(&response)),
+            perplexity(&logprobs),
+        ))
+    }
+
+    /// Complete through the completions endpoint, for backends that take a single prompt
+    async fn complete_prompt(
+        &self,
+        template: &PromptTemplate,
+        system: &str,
+        messages: &[Message],
+        tools: &[Tool],
+    ) -> Result<(Message, ProviderUsage), ProviderError> {
+        if !tools.is_empty() {
+            return Err(ProviderError::NotSupported(
+                "tools can not be used with a prompt template".to_string(),
+            ));
+        }
+
+        let (system, checked, _) = self.checked_request(system, messages, tools)?;
+        let mut payload = json!({
+            "model": self.model.model_name,
+            "prompt": templated_prompt(template, &system, &checked),
+        });
+        self.generation_params(&mut payload);
+        // Stop before the model writes the next user turn itself
+        let user_prefix = template.user.trim();
+        if !user_prefix.is_empty() {
+            let mut stop = self.model.stop_sequences.clone();
+            stop.push(user_prefix.to_string());
+            payload["stop"] = json!(stop);
+        }
+        self.intercept(&mut payload);
+
+        let response = self.post("completions", payload.clone()).await?;
+        let message = completion_to_message(&response)?;
+        let usage = get_usage(&response).unwrap_or_default();
+        let model = get_model(&response);
+        emit_debug_trace(self, messages, &payload, &response, &usage);
+        Ok((
+            message,
+            ProviderUsage::new(model, usage)
+                .with_system_fingerprint(get_system_fingerprint(&response)),
+        ))
+    }
+
+    /// Add the sampling and length settings of the model to a completions request
+    ///
+    /// Chat requests get them from `create_request`, the legacy completions
+    /// endpoint takes the same names.
+    fn generation_params(&self, payload: &mut Value) {
+        if let Some(tokens) = clamp_max_tokens(self.model.max_tokens, self.max_output_tokens) {
+            payload["max_tokens"] = json!(tokens);
+        }
+        if let Some(temperature) = self.model.temperature {
+            payload["temperature"] = json!(temperature);
+        }
+        if let Some(top_p) = self.model.top_p {
+            payload["top_p"] = json!(top_p);
+        }
+        if let Some(penalty) = self.model.frequency_penalty {
+            payload["frequency_penalty"] = json!(penalty);
+        }
+        if let Some(penalty) = self.model.presence_penalty {
+            payload["presence_penalty"] = json!(penalty);
+        }
+        if !self.model.stop_sequences.is_empty() {
+            payload["stop"] = json!(self.model.stop_sequences);
+        }
+    }
+
+    /// Complete through the legacy completions endpoint with the prompt echoed back
+    async fn complete_echo(
+        &self,
+        system: &str,
+        messages: &[Message],
+        tools: &[Tool],
+    ) -> Result<(Message, ProviderUsage), ProviderError> {
+        if !self.model.capabilities().echo {
+            return Err(ProviderError::NotSupported(format!(
+                "echo requires a completions model, {} does not support it",
+                self.model.model_name
+            )));
+        }
+        if !tools.is_empty() {
+            return Err(ProviderError::NotSupported(
+                "echo can not be combined with tools".to_string(),
+            ));
+        }
+
+        let (system, checked, _) = self.checked_request(system, messages, tools)?;
+        let mut payload = json!({
+            "model": self.model.model_name,
+            "prompt": completion_prompt(&system, &checked),
+            "echo": true,
+        });
+        self.generation_params(&mut payload);
+        self.intercept(&mut payload);
+
+        let response = self.post("completions", payload.clone()).await?;
+        let message = completion_to_message(&response)?;
+        let usage = get_usage(&response).unwrap_or_default();
+        let model = get_model(&response);
+        emit_debug_trace(self, messages, &payload, &response, &usage);
+        Ok((
+            message,
+            ProviderUsage::new(model, usage)
+                .with_system_fingerprint(get_system_fingerprint(&response)),
+        ))
+    }
+
+    /// Query the remaining credit for the configured API key
+    ///
+    /// Returns `NotSupported` when the endpoint is not available for this key,
+    /// including keys that work for completions but are not admin keys.
+    pub async fn account_balance(&self) -> Result<Balance, ProviderError> {
+        let url = balance_url(&self.host);
+        let response = send_with_retry(&self.retry, || async {
+            Ok(self
+                .client
+                .post(&url)
+                .headers(self.create_headers()?)
+                .timeout(self.read_timeout)
+                .send()
+                .await?)
+        })
+        .await?;
+
+        match response.status() {
+            StatusCode::NOT_FOUND
+            | StatusCode::METHOD_NOT_ALLOWED
+            | StatusCode::NOT_IMPLEMENTED => Err(ProviderError::NotSupported(
+                "The balance endpoint is not available for this API key".to_string(),
+            )),
+            // The key may well be valid, the balance needs an admin key
+            StatusCode::UNAUTHORIZED | StatusCode::FORBIDDEN => Err(ProviderError::NotSupported(
+                "The balance can only be read with an admin API key".to_string(),
+            )),
+            _ => parse_balance(&handle_response_openai_compat(response).await?),
+        }
+    }
+}
+
+/// Build the http client, trusting the extra roots in `ca_bundle` when given
+///
+/// `insecure` disables certificate verification entirely and is only meant for
+/// development setups.
+fn build_client(
+    ca_bundle: Option<&str>,
+    insecure: bool,
+    connect_timeout: Duration,
+) -> Result<Client> {
+    let mut builder = Client::builder().connect_timeout(connect_timeout);
+    if let Some(path) = ca_bundle {
+        let pem = std::fs::read(path)
+            .map_err(|e| anyhow::anyhow!("Failed to read OMG_CA_BUNDLE {}: {}", path, e))?;
+        let certificates = Certificate::from_pem_bundle(&pem)
+            .map_err(|e| anyhow::anyhow!("Invalid certificate in OMG_CA_BUNDLE {}: {}", path, e))?;
+        if certificates.is_empty() {
+            anyhow::bail!("No PEM certificates found in OMG_CA_BUNDLE {}", path);
+        }
+        for certificate in certificates {
+            builder = builder.add_root_certificate(certificate);
+        }
+    }
+    if insecure {
+        tracing::warn!("OMG_TLS_INSECURE is set, TLS certificates will not be verified");
+        builder = builder.danger_accept_invalid_certs(true);
+    }
+    Ok(builder.build()?)
+}
+
+/// The setting at `key`, None when it is not set and an error when it does not parse
+fn optional_setting<T: for<'de> Deserialize<'de>>(
+    config: &crate::config::Config,
+    key: &str,
+) -> Result<Option<T>> {
+    match config.get(key) {
+        Ok(value) => Ok(Some(value)),
+        Err(ConfigError::NotFound(_)) => Ok(None),
+        Err(e) => Err(anyhow::anyhow!("Invalid {}: {}", key, e)),
+    }
+}
+
+/// Check that `OMG_HOST` is an http or https URL, without a trailing slash
+fn parse_host(host: String) -> Result<String> {
+    let url = reqwest::Url::parse(&host)
+        .map_err(|e| anyhow::anyhow!("Invalid OMG_HOST {}: {}", host, e))?;
+    if !matches!(url.scheme(), "http" | "https") {
+        anyhow::bail!(
+            "Invalid OMG_HOST {}: the scheme must be http or https",
+            host
+        );
+    }
+    Ok(host.trim_end_matches('/').to_string())
+}
+
+/// The balance endpoint of `host`, which lives beside the versioned API rather than under it
+fn balance_url(host: &str) -> String {
+    let host = host.trim_end_matches('/');
+    let root = host.strip_suffix("/v1").unwrap_or(host);
+    format!("{}/{}", root, OMG_BALANCE_PATH)
+}
+
+/// Read `OMG_CUSTOM_HEADERS`, given as a JSON object or as `Name: value` pairs separated by commas
+fn parse_custom_headers(value: &Value) -> Result<header::HeaderMap> {
+    let pairs: Vec<(String, String)> = match value {
+        Value::Object(map) => map
+            .iter()
+            .map(|(name, value)| {
+                let value = match value {
+                    Value::String(text) => text.clone(),
+                    other => other.to_string(),
+                };
+                (name.clone(), value)
+            })
+            .collect(),
+        Value::String(text) => text
+            .split([',', '\n'])
+            .filter(|pair| !pair.trim().is_empty())
+            .map(|pair| {
+                let (name, value) = pair.split_once(':').ok_or_else(|| {
+                    anyhow::anyhow!(
+                        "Invalid OMG_CUSTOM_HEADERS entry '{}', expected 'Name: value'",
+                        pair.trim()
+                    )
+                })?;
+                Ok((name.trim().to_string(), value.trim().to_string()))
+            })
+            .collect::<Result<_>>()?,
+        _ => anyhow::bail!("OMG_CUSTOM_HEADERS must be an object or 'Name: value' pairs"),
+    };
+
+    let mut headers = header::HeaderMap::new();
+    for (name, value) in pairs {
+        let header_name = header::HeaderName::from_bytes(name.as_bytes()).map_err(|e| {
+            anyhow::anyhow!(
+                "Invalid header name '{}' in OMG_CUSTOM_HEADERS: {}",
+                name,
+                e
+            )
+        })?;
+        let header_value = header::HeaderValue::from_str(&value).map_err(|e| {
+            anyhow::anyhow!(
+                "Invalid value for header '{}' in OMG_CUSTOM_HEADERS: {}",
+                name,
+                e
+            )
+        })?;
+        headers.insert(header_name, header_value);
+    }
+    Ok(headers)
+}
+
+/// One of several sampled completions, scored by the model's confidence
+#[derive(Debug, Clone)]
+pub struct RankedChoice {
+    pub message: Message,
+    /// Average log probability of the generated tokens, higher is more confident
+    pub score: f64,
+}
+
+/// Parse every choice of a response with logprobs, most confident first
+fn ranked_choices(response: &Value) -> Result<Vec<RankedChoice>, ProviderError> {
+    let choices = response["choices"].as_array().ok_or_else(|| {
+        ProviderError::request_failed(format!("No choices in response: {}", response))
+    })?;
+
+    let mut ranked = choices
+        .iter()
+        .map(|choice| {
+            let message = response_to_message(json!({ "choices": [choice] }))?;
+            let logprobs: Vec<f64> = choice["logprobs"]["content"]
+                .as_array()
+                .map(|tokens| {
+                    tokens
+                        .iter()
+                        .filter_map(|t| t["logprob"].as_f64())
+                        .collect()
+                })
+                .unwrap_or_default();
+            let score = if logprobs.is_empty() {
+                f64::NEG_INFINITY
+            } else {
+                logprobs.iter().sum::<f64>() / logprobs.len() as f64
+            };
+            Ok(RankedChoice { message, score })
+        })
+        .collect::<Result<Vec<_>, ProviderError>>()?;
+
+    ranked.sort_by(|a, b| b.score.total_cmp(&a.score));
+    Ok(ranked)
+}
 
-        handle_response_openai_compat(response).await
+/// Clamp the requested output tokens to the cap, applying the cap when none was requested
+fn clamp_max_tokens(requested: Option<i32>, cap: Option<i32>) -> Option<i32> {
+    match (requested, cap) {
+        (Some(requested), Some(cap)) if requested > cap => {
+            tracing::warn!(
+                "Clamping max_tokens from {} to the configured OMG_MAX_OUTPUT_TOKENS of {}",
+                requested,
+                cap
+            );
+            Some(cap)
+        }
+        (None, Some(cap)) => Some(cap),
+        (requested, _) => requested,
     }
 }
 
+/// Flatten the conversation into a single prompt for the completions endpoint
+fn completion_prompt(system: &str, messages: &[Message]) -> String {
+    std::iter::once(system.to_string())
+        .chain(messages.iter().map(|m| m.as_concat_text()))
+        .filter(|text| !text.is_empty())
+        .collect::<Vec<_>>()
+        .join("\n\n")
+}
+
+/// Flatten the conversation into one role tagged prompt, see `PromptTemplate`
+fn templated_prompt(template: &PromptTemplate, system: &str, messages: &[Message]) -> String {
+    let system = (!system.is_empty()).then(|| format!("{}{}", template.system, system));
+    let turns = messages.iter().map(|message| {
+        let prefix = match message.role {
+            Role::User => &template.user,
+            Role::Assistant => &template.assistant,
+        };
+        format!("{}{}", prefix, message.as_concat_text())
+    });
+    system
+        .into_iter()
+        .chain(turns)
+        .chain(std::iter::once(template.assistant.clone()))
+        .collect::<Vec<_>>()
+        .join(&template.separator)
+}
+
+/// Read the text of a completions response, which includes the prompt when echoed
+fn completion_to_message(response: &Value) -> Result<Message, ProviderError> {
+    let text = response["choices"][0]["text"].as_str().ok_or_else(|| {
+        ProviderError::request_failed(format!("No completion text in response: {}", response))
+    })?;
+    Ok(Message::assistant().with_text(text))
+}
+
+/// Parse the balance payload, which nests the values under `data` and may encode numbers as strings
+fn parse_balance(payload: &Value) -> Result<Balance, ProviderError> {
+    fn number(value: &Value) -> Option<f64> {
+        value
+            .as_f64()
+            .or_else(|| value.as_str().and_then(|s| s.trim().parse().ok()))
+    }
+
+    let data = payload.get("data").unwrap_or(payload);
+    let remaining = data.get("balance").and_then(number).ok_or_else(|| {
+        ProviderError::NotSupported(format!("Balance missing from response: {}", payload))
+    })?;
+    let used = ["used", "used_balance", "total_used"]
+        .iter()
+        .find_map(|key| data.get(key).and_then(number));
+
+    Ok(Balance { remaining, used })
+}
+
 impl Default for OmgProvider {
     fn default() -> Self {
         let model = ModelConfig::new(OmgProvider::metadata().default_model);
@@ -82,34 +1017,161 @@ impl Provider for OmgProvider {
             OMG_DEFAULT_MODEL,
             OMG_KNOWN_MODELS.iter().map(|&s| s.to_string()).collect(),
             OMG_DOC_URL,
-            vec![ConfigKey::new("OMG_API_KEY", true, true, None)],
+            vec![
+                ConfigKey::new("OMG_API_KEY", true, true, None),
+                ConfigKey::new("OMG_HOST", false, false, Some(OMG_API_URL)),
+                ConfigKey::new("OMG_CUSTOM_HEADERS", false, false, None),
+                ConfigKey::new("OMG_STREAM_MAX_RECONNECTS", false, false, Some("0")),
+                ConfigKey::new("OMG_STREAM_BUFFER_SIZE", false, false, Some("32")),
+                ConfigKey::new("OMG_FIRST_TOKEN_TIMEOUT_MS", false, false, None),
+                ConfigKey::new("OMG_CONNECT_TIMEOUT", false, false, Some("5")),
+                ConfigKey::new("OMG_READ_TIMEOUT", false, false, Some("600")),
+                ConfigKey::new("OMG_MAX_OUTPUT_TOKENS", false, false, None),
+                ConfigKey::new("OMG_MAX_INPUT_TOKENS", false, false, None),
+                ConfigKey::new("OMG_STRICT_TOOLS", false, false, Some("false")),
+                ConfigKey::new("OMG_COMPRESS_REQUESTS", false, false, Some("false")),
+                ConfigKey::new("OMG_COMPRESS_MIN_BYTES", false, false, Some("65536")),
+                ConfigKey::new("OMG_DEFAULT_SYSTEM", false, false, None),
+                ConfigKey::new("OMG_CA_BUNDLE", false, false, None),
+                ConfigKey::new("OMG_TLS_INSECURE", false, false, Some("false")),
+                ConfigKey::new("OMG_PRETTY_REQUESTS", false, false, Some("false")),
+                ConfigKey::new("OMG_MAX_ATTEMPTS", false, false, Some("3")),
+                ConfigKey::new("OMG_CACHE_PROMPT", false, false, Some("false")),
+                ConfigKey::new(
+                    "OMG_EMBEDDING_MODEL",
+                    false,
+                    false,
+                    Some(OMG_DEFAULT_EMBEDDING_MODEL),
+                ),
+            ],
         )
     }
 
+    fn instance_metadata(&self) -> ProviderMetadata {
+        Self::metadata().with_image_input(self.model.capabilities().image_input)
+    }
+
     fn get_model_config(&self) -> ModelConfig {
         self.model.clone()
     }
 
+    fn count_request_tokens(&self, system: &str, messages: &[Message], tools: &[Tool]) -> usize {
+        let system = apply_response_locale(
+            self.system_or_default(system),
+            self.model.response_locale.as_deref(),
+        );
+        let counter = TokenCounter::new(self.model.tokenizer_name());
+        counter.count_chat_tokens(&self.format_system_prompt(&system), messages, tools)
+    }
+
+    fn request_body(
+        &self,
+        system: &str,
+        messages: &[Message],
+        tools: &[Tool],
+    ) -> Result<Value, ProviderError> {
+        if self.model.echo || self.model.prompt_template.is_some() {
+            return Err(ProviderError::NotSupported(
+                "only chat completion requests can be shown".to_string(),
+            ));
+        }
+        let (messages, _) = self.prefilled(messages)?;
+        let mut payload = self.build_request(system, &messages, tools)?;
+        self.intercept(&mut payload);
+        Ok(payload)
+    }
+
+    /// List the models, which leaves a connection to the host in the pool
+    ///
+    /// Any response will do, only failing to connect is an error.
+    async fn warmup(&self) -> Result<(), ProviderError> {
+        let url = format!("{}/models", self.host.trim_end_matches('/'));
+        self.client
+            .get(url)
+            .headers(self.create_headers()?)
+            .timeout(self.read_timeout)
+            .send()
+            .await?;
+        Ok(())
+    }
+
+    /// Read from the headers of a models listing, which costs no tokens
+    async fn rate_limits(&self) -> Result<RateLimits, ProviderError> {
+        let url = format!("{}/models", self.host.trim_end_matches('/'));
+        let response = self
+            .client
+            .get(url)
+            .headers(self.create_headers()?)
+            .timeout(self.read_timeout)
+            .send()
+            .await?;
+        let limits = rate_limits_from_headers(response.headers());
+        if !response.status().is_success() {
+            handle_response_openai_compat(response)
+                .await
+                .map_err(|e| e.with_key_of(&Self::metadata()))?;
+        }
+        limits.ok_or_else(|| {
+            ProviderError::NotSupported("the API sent no rate limit headers".to_string())
+        })
+    }
+
+    /// Every model the gateway proxies, which is far more than `OMG_KNOWN_MODELS`
+    async fn fetch_supported_models(&self) -> Result<Vec<String>, ProviderError> {
+        let url = format!("{}/models", self.host.trim_end_matches('/'));
+        let response = self
+            .client
+            .get(url)
+            .headers(self.create_headers()?)
+            .timeout(self.read_timeout)
+            .send()
+            .await?;
+        let listing = handle_response_openai_compat(response)
+            .await
+            .map_err(|e| e.with_key_of(&Self::metadata()))?;
+        Ok(response_to_models(&listing)?)
+    }
+
+    async fn embed(&self, inputs: &[String]) -> Result<Vec<Vec<f32>>, ProviderError> {
+        if inputs.is_empty() {
+            return Ok(Vec::new());
+        }
+        let payload = create_embedding_request(&self.embedding_model, inputs);
+        let response = self.post("embeddings", payload).await?;
+        Ok(response_to_embeddings(&response, inputs.len())?)
+    }
+
     async fn complete(
         &self,
         system: &str,
         messages: &[Message],
         tools: &[Tool],
     ) -> Result<(Message, ProviderUsage), ProviderError> {
+        if self.model.echo {
+            return self.complete_echo(system, messages, tools).await;
+        }
+        if let Some(template) = &self.model.prompt_template {
+            return self
+                .complete_prompt(template, system, messages, tools)
+                .await;
+        }
+
+        let (messages, prefill) = self.prefilled(messages)?;
+        let messages = messages.as_ref();
+
         // Create the request payload using OpenAI format
-        let payload = create_request(
-            &self.model,
-            system,
-            messages,
-            tools,
-            &super::utils::ImageFormat::OpenAi,
-        )?;
+        let mut payload = self.build_request(system, messages, tools)?;
+        self.intercept(&mut payload);
 
         // Make request
-        let response = self.post(payload.clone()).await?;
+        let response = self.post("chat/completions", payload.clone()).await?;
 
         // Parse response
-        let message = response_to_message(response.clone())?;
+        let mut message = response_to_message(response.clone())?;
+        if let Some(prefill) = prefill {
+            message = prepend_prefill(message, prefill);
+        }
+        message = finish_message(message, &self.model);
         let usage = match get_usage(&response) {
             Ok(usage) => usage,
             Err(ProviderError::UsageError(e)) => {
@@ -119,7 +1181,1251 @@ impl Provider for OmgProvider {
             Err(e) => return Err(e),
         };
         let model = get_model(&response);
-        emit_debug_trace(self, &payload, &response, &usage);
-        Ok((message, ProviderUsage::new(model, usage)))
+        emit_debug_trace(self, messages, &payload, &response, &usage);
+        Ok((
+            message,
+            ProviderUsage::new(model, usage)
+                .with_system_fingerprint(get_system_fingerprint(&response)),
+        ))
+    }
+
+    async fn stream(
+        &self,
+        system: &str,
+        messages: &[Message],
+        tools: &[Tool],
+    ) -> Result<MessageStream, ProviderError> {
+        if self.model.echo {
+            return Err(ProviderError::NotSupported(
+                "echo is not supported when streaming".to_string(),
+            ));
+        }
+        if let Some(template) = &self.model.prompt_template {
+            // The completions endpoint is not streamed, the response arrives at once
+            let (message, usage) = self
+                .complete_prompt(template, system, messages, tools)
+                .await?;
+            let deltas: Vec<_> = message
+                .content
+                .into_iter()
+                .map(MessageDelta::Content)
+                .chain(std::iter::once(MessageDelta::Usage(usage)))
+                .map(Ok)
+                .collect();
+            return Ok(Box::pin(futures::stream::iter(deltas)));
+        }
+
+        let (messages, prefill) = self.prefilled(messages)?;
+        let mut payload = self.build_request(system, &messages, tools)?;
+        payload["stream"] = json!(true);
+        payload["stream_options"] = json!({"include_usage": true});
+        self.intercept(&mut payload);
+
+        // Restarting only reproduces the same output when sampling is greedy
+        let resumable = payload["temperature"].as_f64() == Some(0.0);
+        // Reconnects resend the same logical request
+        let idempotency_key = Uuid::new_v4().to_string();
+        let provider = self.clone();
+        let mut stream = reconnecting_stream(
+            move || {
+                let provider = provider.clone();
+                let payload = payload.clone();
+                let idempotency_key = idempotency_key.clone();
+                async move { provider.post_stream(payload, &idempotency_key).await }
+            },
+            self.stream_max_reconnects,
+            resumable,
+        );
+        if let Some(timeout) = self.first_token_timeout {
+            stream = first_token_timeout(stream, timeout);
+        }
+        // Enforce the ceiling even if the backend ignores max_tokens
+        if let Some(cap) = self.max_output_tokens {
+            stream = cap_output_tokens(stream, cap.max(0) as usize, &self.model);
+        }
+        // The prefill is part of the answer, but not of the output the cap counts
+        if let Some(prefill) = prefill {
+            let first = MessageDelta::Content(MessageContent::text(prefill));
+            stream = Box::pin(futures::stream::once(async { Ok(first) }).chain(stream));
+        }
+        // The clean up needs the whole text, so the message arrives at once
+        if needs_finishing(&self.model) {
+            let model = self.model.clone();
+            stream = finished_stream(stream, move |message| finish_message(message, &model));
+        }
+        Ok(buffered_stream(stream, self.stream_buffer_size))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::{PrefillPolicy, ResponseFormat};
+    use crate::providers::redact::RedactingProvider;
+    use crate::providers::streaming::collect_message;
+    use mcp_core::content::Content;
+    use serial_test::serial;
+    use std::sync::Mutex;
+
+    fn test_provider(
+        max_output_tokens: Option<i32>,
+        max_input_tokens: Option<usize>,
+    ) -> OmgProvider {
+        OmgProvider {
+            client: Client::new(),
+            host: OMG_API_URL.to_string(),
+            api_key: Secret::fixed("test"),
+            model: ModelConfig::new(OMG_DEFAULT_MODEL.to_string()),
+            embedding_model: OMG_DEFAULT_EMBEDDING_MODEL.to_string(),
+            stream_max_reconnects: 0,
+            stream_buffer_size: DEFAULT_STREAM_BUFFER_SIZE,
+            first_token_timeout: None,
+            connect_timeout: Duration::from_secs(OMG_DEFAULT_CONNECT_TIMEOUT_SECS),
+            read_timeout: Duration::from_secs(OMG_DEFAULT_READ_TIMEOUT_SECS),
+            max_output_tokens,
+            max_input_tokens,
+            strict_tools: false,
+            compress_min_bytes: None,
+            default_system: None,
+            pretty_requests: false,
+            retry: RetryConfig::new(1),
+            interceptors: Vec::new(),
+            custom_headers: header::HeaderMap::new(),
+        }
+    }
+
+    #[test]
+    fn test_providers_are_object_safe() {
+        let omg = test_provider(None, None);
+        let redacted = RedactingProvider::new(test_provider(None, None).into_boxed());
+        let providers: Vec<Box<dyn Provider>> = vec![omg.into_boxed(), Box::new(redacted)];
+
+        for provider in &providers {
+            assert_eq!(provider.instance_metadata().name, "omg");
+            assert_eq!(provider.get_model_config().model_name, OMG_DEFAULT_MODEL);
+        }
+    }
+
+    #[test]
+    fn test_build_request_applies_response_locale() {
+        let mut provider = test_provider(None, None);
+        provider.model = ModelConfig::new("claude-3-5-sonnet".to_string())
+            .with_response_locale(Some("French".to_string()));
+        let messages = vec![Message::user().with_text("Hello")];
+        let payload = provider.build_request("Be brief.", &messages, &[]).unwrap();
+
+        assert_eq!(
+            payload["messages"][0]["content"],
+            "Be brief.\n\nRespond in French."
+        );
+    }
+
+    #[test]
+    fn test_default_system_only_replaces_an_empty_prompt() {
+        let mut provider = test_provider(None, None);
+        provider.default_system = Some("You are the team assistant.".to_string());
+        let messages = vec![Message::user().with_text("Hello")];
+
+        let payload = provider.build_request("", &messages, &[]).unwrap();
+        let system = payload["messages"][0]["content"].as_str().unwrap();
+        assert!(system.contains("You are the team assistant."));
+
+        let payload = provider.build_request("Be brief.", &messages, &[]).unwrap();
+        let system = payload["messages"][0]["content"].as_str().unwrap();
+        assert!(system.contains("Be brief."));
+        assert!(!system.contains("team assistant"));
+    }
+
+    #[test]
+    fn test_clamp_max_tokens() {
+        assert_eq!(clamp_max_tokens(Some(8000), Some(1000)), Some(1000));
+        assert_eq!(clamp_max_tokens(Some(500), Some(1000)), Some(500));
+        assert_eq!(clamp_max_tokens(None, Some(1000)), Some(1000));
+        assert_eq!(clamp_max_tokens(Some(8000), None), Some(8000));
+        assert_eq!(clamp_max_tokens(None, None), None);
+    }
+
+    #[test]
+    fn test_build_request_applies_caps() {
+        let mut provider = test_provider(Some(256), None);
+        provider.model = provider.model.clone().with_max_tokens(Some(4096));
+        let messages = vec![Message::user().with_text("Hello")];
+        let payload = provider.build_request("system", &messages, &[]).unwrap();
+        assert_eq!(payload["max_tokens"], json!(256));
+
+        let provider = test_provider(None, None);
+        let payload = provider.build_request("system", &messages, &[]).unwrap();
+        assert!(payload.get("max_tokens").is_none());
+    }
+
+    #[test]
+    fn test_build_request_rejects_large_input() {
+        let provider = test_provider(None, Some(10));
+        let messages = vec![Message::user().with_text("word ".repeat(50))];
+        assert!(matches!(
+            provider.build_request("system", &messages, &[]),
+            Err(ProviderError::ContextLengthExceeded(_))
+        ));
+    }
+
+    #[test]
+    fn test_build_request_rejects_audio_for_text_models() {
+        let messages = vec![Message::user().with_audio("UklGRiQAAABXQVZF", "mp3")];
+
+        let provider = test_provider(None, None);
+        assert!(matches!(
+            provider.build_request("system", &messages, &[]),
+            Err(ProviderError::NotSupported(_))
+        ));
+
+        let mut provider = test_provider(None, None);
+        provider.model = ModelConfig::new("gpt-4o-audio-preview".to_string());
+        assert!(provider.build_request("system", &messages, &[]).is_ok());
+    }
+
+    #[test]
+    fn test_images_are_sent_to_vision_models() {
+        let messages = vec![Message::user()
+            .with_text("What is on the screen?")
+            .with_image("aGVsbG8=", "image/png")];
+
+        let mut provider = test_provider(None, None);
+        assert!(provider.instance_metadata().image_input);
+        let payload = provider.build_request("", &messages, &[]).unwrap();
+        let parts = payload["messages"][1]["content"].as_array().unwrap();
+        assert_eq!(
+            parts[1],
+            json!({"type": "image_url", "image_url": {"url": "data:image/png;base64,aGVsbG8="}})
+        );
+
+        provider.model = ModelConfig::new("o1-mini".to_string());
+        assert!(!provider.instance_metadata().image_input);
+        assert!(matches!(
+            provider.build_request("", &messages, &[]),
+            Err(ProviderError::NotSupported(_))
+        ));
+    }
+
+    #[test]
+    fn test_build_request_validates_tools_in_strict_mode() {
+        let messages = vec![Message::user().with_text("Hello")];
+        let tools = vec![Tool::new("broken", "Broken", json!({"type": "string"}))];
+
+        let mut provider = test_provider(None, None);
+        assert!(provider.build_request("system", &messages, &tools).is_ok());
+        provider.strict_tools = true;
+        assert!(matches!(
+            provider.build_request("system", &messages, &tools),
+            Err(ProviderError::RequestFailed { .. })
+        ));
+    }
+
+    #[test]
+    fn test_completion_to_message_includes_echoed_prompt() {
+        let messages = vec![Message::user().with_text("Say this is a test")];
+        assert_eq!(
+            completion_prompt("", &messages),
+            "Say this is a test".to_string()
+        );
+
+        let response = json!({
+            "id": "cmpl-123",
+            "object": "text_completion",
+            "model": "gpt-3.5-turbo-instruct",
+            "choices": [{"text": "Say this is a test\n\nThis is a test.", "index": 0}],
+            "usage": {"prompt_tokens": 5, "completion_tokens": 7, "total_tokens": 12}
+        });
+        let message = completion_to_message(&response).unwrap();
+        assert!(message.as_concat_text().starts_with("Say this is a test"));
+        assert_eq!(get_usage(&response).unwrap().total_tokens, Some(12));
+    }
+
+    #[test]
+    fn test_templated_prompt() {
+        let messages = vec![
+            Message::user().with_text("Hi"),
+            Message::assistant().with_text("Hello! How can I help?"),
+            Message::user().with_text("Name a bird"),
+        ];
+        assert_eq!(
+            templated_prompt(&PromptTemplate::default(), "Be brief.", &messages),
+            "### System:\nBe brief.\n### User:\nHi\n### Assistant:\nHello! How can I help?\n### User:\nName a bird\n### Assistant:\n"
+        );
+
+        let template = PromptTemplate {
+            system: "".to_string(),
+            user: "USER: ".to_string(),
+            assistant: "ASSISTANT:".to_string(),
+            separator: "\n\n".to_string(),
+        };
+        assert_eq!(
+            templated_prompt(&template, "", &messages[2..]),
+            "USER: Name a bird\n\nASSISTANT:"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_prompt_template_uses_completions_endpoint() {
+        let server = wiremock::MockServer::start().await;
+        wiremock::Mock::given(wiremock::matchers::path("/completions"))
+            .respond_with(wiremock::ResponseTemplate::new(200).set_body_json(json!({
+                "model": "prompt-only",
+                "choices": [{"text": "A goose.", "index": 0}],
+                "usage": {"prompt_tokens": 9, "completion_tokens": 3, "total_tokens": 12}
+            })))
+            .mount(&server)
+            .await;
+
+        let mut provider = test_provider(None, None);
+        provider.host = server.uri();
+        provider.model = ModelConfig::new("prompt-only".to_string())
+            .with_prompt_template(Some(PromptTemplate::default()));
+        let messages = vec![Message::user().with_text("Name a bird")];
+        let (message, usage) = provider.complete("", &messages, &[]).await.unwrap();
+        assert_eq!(message.as_concat_text(), "A goose.");
+        assert_eq!(usage.usage.total_tokens, Some(12));
+
+        let requests = server.received_requests().await.unwrap();
+        let sent: Value = serde_json::from_slice(&requests[0].body).unwrap();
+        assert_eq!(sent["prompt"], "### User:\nName a bird\n### Assistant:\n");
+        assert_eq!(sent["stop"], json!(["### User:"]));
+    }
+
+    #[tokio::test]
+    async fn test_completions_requests_are_checked() {
+        // Nothing is listening, the checks fail before anything is sent
+        let mut provider = test_provider(None, Some(10));
+        provider.host = "http://127.0.0.1:9".to_string();
+        provider.model = ModelConfig::new("prompt-only".to_string())
+            .with_prompt_template(Some(PromptTemplate::default()));
+        let messages = vec![Message::user().with_text("word ".repeat(50))];
+        assert!(matches!(
+            provider.complete("", &messages, &[]).await,
+            Err(ProviderError::ContextLengthExceeded(_))
+        ));
+
+        provider.max_input_tokens = None;
+        let messages = vec![Message::user().with_audio("UklGRiQAAABXQVZF", "mp3")];
+        assert!(matches!(
+            provider.complete("", &messages, &[]).await,
+            Err(ProviderError::NotSupported(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_echo_rejected_for_chat_models() {
+        let mut provider = test_provider(None, None);
+        provider.model = provider.model.clone().with_echo(true);
+        let messages = vec![Message::user().with_text("Hello")];
+        assert!(matches!(
+            provider.complete("", &messages, &[]).await,
+            Err(ProviderError::NotSupported(_))
+        ));
+    }
+
+    #[test]
+    fn test_optional_setting() {
+        let file = tempfile::NamedTempFile::new().unwrap();
+        let config = crate::config::Config::new(file.path(), "goose-omg-test").unwrap();
+        config
+            .set("OMG_MAX_OUTPUT_TOKENS", Value::String("8k".to_string()))
+            .unwrap();
+        config.set("OMG_MAX_INPUT_TOKENS", json!(4096)).unwrap();
+
+        let error = optional_setting::<i32>(&config, "OMG_MAX_OUTPUT_TOKENS").unwrap_err();
+        assert!(error.to_string().contains("Invalid OMG_MAX_OUTPUT_TOKENS"));
+        assert_eq!(
+            optional_setting::<usize>(&config, "OMG_MAX_INPUT_TOKENS").unwrap(),
+            Some(4096)
+        );
+        assert_eq!(
+            optional_setting::<usize>(&config, "OMG_UNSET_CAP").unwrap(),
+            None
+        );
+    }
+
+    #[test]
+    #[serial]
+    fn test_from_env_applies_timeouts() {
+        std::env::set_var("OMG_API_KEY", "test");
+        std::env::set_var("OMG_CONNECT_TIMEOUT", "2");
+        std::env::set_var("OMG_READ_TIMEOUT", "120");
+        let provider = OmgProvider::from_env(ModelConfig::new(OMG_DEFAULT_MODEL.to_string()));
+        std::env::remove_var("OMG_API_KEY");
+        std::env::remove_var("OMG_CONNECT_TIMEOUT");
+        std::env::remove_var("OMG_READ_TIMEOUT");
+
+        let provider = provider.unwrap();
+        assert_eq!(provider.connect_timeout, Duration::from_secs(2));
+        assert_eq!(provider.read_timeout, Duration::from_secs(120));
+    }
+
+    #[tokio::test]
+    async fn test_slow_response_is_a_read_timeout() {
+        let server = wiremock::MockServer::start().await;
+        wiremock::Mock::given(wiremock::matchers::method("POST"))
+            .respond_with(
+                wiremock::ResponseTemplate::new(200)
+                    .set_body_json(json!({}))
+                    .set_delay(Duration::from_secs(5)),
+            )
+            .mount(&server)
+            .await;
+
+        let mut provider = test_provider(None, None);
+        provider.host = server.uri();
+        provider.read_timeout = Duration::from_millis(100);
+        assert!(matches!(
+            provider.post("chat/completions", json!({})).await,
+            Err(ProviderError::ReadTimeout { .. })
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_large_requests_are_gzipped() {
+        use flate2::read::GzDecoder;
+        use std::io::Read;
+
+        let server = wiremock::MockServer::start().await;
+        wiremock::Mock::given(wiremock::matchers::method("POST"))
+            .respond_with(wiremock::ResponseTemplate::new(200).set_body_json(json!({})))
+            .mount(&server)
+            .await;
+
+        let mut provider = test_provider(None, None);
+        provider.host = server.uri();
+        provider.compress_min_bytes = Some(1024);
+        let small = json!({"prompt": "hi"});
+        let large = json!({"prompt": "word ".repeat(1000)});
+        provider
+            .post("chat/completions", small.clone())
+            .await
+            .unwrap();
+        provider
+            .post("chat/completions", large.clone())
+            .await
+            .unwrap();
+
+        let requests = server.received_requests().await.unwrap();
+        assert!(requests[0].headers.get("content-encoding").is_none());
+        assert_eq!(
+            serde_json::from_slice::<Value>(&requests[0].body).unwrap(),
+            small
+        );
+
+        assert_eq!(requests[1].headers["content-encoding"], "gzip");
+        assert!(requests[1].body.len() < 1024);
+        let mut decompressed = String::new();
+        GzDecoder::new(requests[1].body.as_slice())
+            .read_to_string(&mut decompressed)
+            .unwrap();
+        assert_eq!(serde_json::from_str::<Value>(&decompressed).unwrap(), large);
+    }
+
+    #[tokio::test]
+    async fn test_complete_trims_response_when_configured() {
+        let server = wiremock::MockServer::start().await;
+        wiremock::Mock::given(wiremock::matchers::method("POST"))
+            .respond_with(wiremock::ResponseTemplate::new(200).set_body_json(json!({
+                "model": OMG_DEFAULT_MODEL,
+                "choices": [{"message": {"role": "assistant", "content": "\n Hello there \n"}}],
+                "usage": {"prompt_tokens": 5, "completion_tokens": 3, "total_tokens": 8}
+            })))
+            .mount(&server)
+            .await;
+
+        let mut provider = test_provider(None, None);
+        provider.host = server.uri();
+        let messages = vec![Message::user().with_text("Hi")];
+        let (message, _) = provider.complete("", &messages, &[]).await.unwrap();
+        assert_eq!(message.as_concat_text(), "\n Hello there \n");
+
+        provider.model = provider.model.clone().with_trim_response(true);
+        let (message, _) = provider.complete("", &messages, &[]).await.unwrap();
+        assert_eq!(message.as_concat_text(), "Hello there");
+
+        provider.model = provider
+            .model
+            .clone()
+            .with_preserve_trailing_whitespace(true);
+        let (message, _) = provider.complete("", &messages, &[]).await.unwrap();
+        assert_eq!(message.as_concat_text(), "Hello there \n");
+    }
+
+    #[tokio::test]
+    async fn test_malformed_usage_keeps_the_completion() {
+        let server = wiremock::MockServer::start().await;
+        wiremock::Mock::given(wiremock::matchers::method("POST"))
+            .respond_with(wiremock::ResponseTemplate::new(200).set_body_json(json!({
+                "model": OMG_DEFAULT_MODEL,
+                "choices": [{"message": {"role": "assistant", "content": "Hello there"}}],
+                "usage": {"prompt_tokens": "5", "completion_tokens": 3, "total_tokens": 8}
+            })))
+            .mount(&server)
+            .await;
+
+        let mut provider = test_provider(None, None);
+        provider.host = server.uri();
+        let messages = vec![Message::user().with_text("Hi")];
+        let (message, usage) = provider.complete("", &messages, &[]).await.unwrap();
+        assert_eq!(message.as_concat_text(), "Hello there");
+        assert_eq!(usage.usage.input_tokens, None);
+        assert_eq!(usage.usage.output_tokens, Some(3));
+        assert_eq!(usage.usage.total_tokens, Some(8));
+    }
+
+    #[test]
+    fn test_host_and_custom_headers() {
+        assert_eq!(
+            parse_host("https://gateway.example.com/v1/".to_string()).unwrap(),
+            "https://gateway.example.com/v1"
+        );
+        assert!(parse_host("gateway.example.com".to_string()).is_err());
+        assert!(parse_host("ftp://gateway.example.com".to_string()).is_err());
+
+        let headers =
+            parse_custom_headers(&json!("X-Team: evals, X-Region: eu-west\nX-Empty:")).unwrap();
+        assert_eq!(headers["x-team"], "evals");
+        assert_eq!(headers["x-region"], "eu-west");
+        assert_eq!(headers["x-empty"], "");
+        let headers = parse_custom_headers(&json!({"X-Team": "evals", "X-Priority": 2})).unwrap();
+        assert_eq!(headers["x-priority"], "2");
+        assert!(parse_custom_headers(&json!("no separator")).is_err());
+        assert!(parse_custom_headers(&json!({"bad name": "x"})).is_err());
+        assert!(parse_custom_headers(&json!(["X-Team: evals"])).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_custom_headers_are_sent() {
+        let server = wiremock::MockServer::start().await;
+        wiremock::Mock::given(wiremock::matchers::method("POST"))
+            .and(wiremock::matchers::header("x-team", "evals"))
+            .and(wiremock::matchers::header("authorization", "Bearer test"))
+            .respond_with(wiremock::ResponseTemplate::new(200).set_body_json(json!({
+                "model": OMG_DEFAULT_MODEL,
+                "choices": [{"message": {"role": "assistant", "content": "ok"}}]
+            })))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let mut provider = test_provider(None, None);
+        provider.host = server.uri();
+        // A custom header can add to the defaults but not replace the key
+        provider.custom_headers =
+            parse_custom_headers(&json!({"X-Team": "evals", "Authorization": "Bearer other"}))
+                .unwrap();
+        let messages = vec![Message::user().with_text("Hi")];
+        provider.complete("", &messages, &[]).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_fetch_supported_models() {
+        let server = wiremock::MockServer::start().await;
+        wiremock::Mock::given(wiremock::matchers::method("GET"))
+            .and(wiremock::matchers::path("/models"))
+            .respond_with(wiremock::ResponseTemplate::new(200).set_body_json(json!({
+                "object": "list",
+                "data": [
+                    {"id": "gpt-4o", "object": "model"},
+                    {"id": "deepseek-chat", "object": "model"},
+                    {"id": "claude-3-5-sonnet", "object": "model"}
+                ]
+            })))
+            .mount(&server)
+            .await;
+
+        let mut provider = test_provider(None, None);
+        provider.host = server.uri();
+        assert_eq!(
+            provider.fetch_supported_models().await.unwrap(),
+            vec!["claude-3-5-sonnet", "deepseek-chat", "gpt-4o"]
+        );
+
+        provider.host = format!("{}/missing", server.uri());
+        assert!(provider.fetch_supported_models().await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_embed() {
+        let server = wiremock::MockServer::start().await;
+        wiremock::Mock::given(wiremock::matchers::method("POST"))
+            .and(wiremock::matchers::path("/embeddings"))
+            .and(wiremock::matchers::body_partial_json(json!({
+                "model": OMG_DEFAULT_EMBEDDING_MODEL,
+                "input": ["first", "second"]
+            })))
+            .respond_with(wiremock::ResponseTemplate::new(200).set_body_json(json!({
+                "data": [
+                    {"index": 0, "embedding": [0.25, 0.5]},
+                    {"index": 1, "embedding": [-1.0, 0.0]}
+                ]
+            })))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let mut provider = test_provider(None, None);
+        provider.host = server.uri();
+        let inputs = vec!["first".to_string(), "second".to_string()];
+        let embeddings = provider.embed(&inputs).await.unwrap();
+        assert_eq!(embeddings, vec![vec![0.25, 0.5], vec![-1.0, 0.0]]);
+        // Nothing to embed needs no request
+        assert!(provider.embed(&[]).await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_complete_with_json_schema() {
+        let server = wiremock::MockServer::start().await;
+        wiremock::Mock::given(wiremock::matchers::method("POST"))
+            .and(wiremock::matchers::body_partial_json(json!({
+                "response_format": {"type": "json_schema", "json_schema": {"name": "answer"}}
+            })))
+            .respond_with(wiremock::ResponseTemplate::new(200).set_body_json(json!({
+                "model": OMG_DEFAULT_MODEL,
+                "choices": [{"message": {"role": "assistant", "content": "{\"answer\": 42}"}}]
+            })))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let mut provider = test_provider(None, None);
+        provider.host = server.uri();
+        provider.model = provider
+            .model
+            .with_response_format(Some(ResponseFormat::JsonSchema {
+                name: "answer".to_string(),
+                schema: json!({"type": "object", "properties": {"answer": {"type": "integer"}}}),
+                strict: true,
+            }));
+        let messages = vec![Message::user().with_text("What is six times seven?")];
+        let (message, _) = provider.complete("", &messages, &[]).await.unwrap();
+        let answer: Value = serde_json::from_str(&message.as_concat_text()).unwrap();
+        assert_eq!(answer["answer"], 42);
+    }
+
+    #[tokio::test]
+    async fn test_complete_round_trips_tool_calls() {
+        let server = wiremock::MockServer::start().await;
+        // The second turn carries the tool result back
+        wiremock::Mock::given(wiremock::matchers::method("POST"))
+            .and(wiremock::matchers::body_partial_json(json!({
+                "messages": [{}, {}, {"role": "assistant"}, {"role": "tool", "tool_call_id": "call_1", "content": "18C and sunny"}]
+            })))
+            .respond_with(wiremock::ResponseTemplate::new(200).set_body_json(json!({
+                "model": OMG_DEFAULT_MODEL,
+                "choices": [{"message": {"role": "assistant", "content": "It is 18C and sunny."}}]
+            })))
+            .mount(&server)
+            .await;
+        wiremock::Mock::given(wiremock::matchers::method("POST"))
+            .and(wiremock::matchers::body_partial_json(
+                json!({"tool_choice": "auto"}),
+            ))
+            .respond_with(wiremock::ResponseTemplate::new(200).set_body_json(json!({
+                "model": OMG_DEFAULT_MODEL,
+                "choices": [{"message": {
+                    "role": "assistant",
+                    "content": null,
+                    "tool_calls": [{
+                        "id": "call_1",
+                        "type": "function",
+                        "function": {"name": "get_weather", "arguments": "{\"city\":\"Paris\"}"}
+                    }]
+                }}]
+            })))
+            .mount(&server)
+            .await;
+
+        let mut provider = test_provider(None, None);
+        provider.host = server.uri();
+        let tools = vec![Tool::new(
+            "get_weather",
+            "Get the weather for a city",
+            json!({"type": "object", "properties": {"city": {"type": "string"}}}),
+        )];
+        let mut messages = vec![Message::user().with_text("Weather in Paris?")];
+        let (message, _) = provider.complete("", &messages, &tools).await.unwrap();
+        let request = message.content[0].as_tool_request().unwrap();
+        let call = request.tool_call.as_ref().unwrap();
+        assert_eq!(call.name, "get_weather");
+        assert_eq!(call.arguments, json!({"city": "Paris"}));
+
+        messages.push(message.clone());
+        messages.push(
+            Message::user().with_tool_response("call_1", Ok(vec![Content::text("18C and sunny")])),
+        );
+        let (message, _) = provider.complete("", &messages, &tools).await.unwrap();
+        assert_eq!(message.as_concat_text(), "It is 18C and sunny.");
+    }
+
+    #[tokio::test]
+    async fn test_prefill_is_sent_and_prepended() {
+        let server = wiremock::MockServer::start().await;
+        wiremock::Mock::given(wiremock::matchers::method("POST"))
+            .respond_with(wiremock::ResponseTemplate::new(200).set_body_json(json!({
+                "model": "claude-3-5-sonnet-latest",
+                "choices": [{"message": {"role": "assistant", "content": "\"answer\": 42}"}}],
+                "usage": {"prompt_tokens": 5, "completion_tokens": 3, "total_tokens": 8}
+            })))
+            .mount(&server)
+            .await;
+
+        let mut provider = test_provider(None, None);
+        provider.host = server.uri();
+        provider.model = ModelConfig::new("claude-3-5-sonnet-latest".to_string())
+            .with_prefill(Some("{".to_string()));
+        let messages = vec![Message::user().with_text("Answer in JSON")];
+        let (message, _) = provider.complete("", &messages, &[]).await.unwrap();
+        assert_eq!(message.as_concat_text(), "{\"answer\": 42}");
+
+        let requests = server.received_requests().await.unwrap();
+        let body: Value = serde_json::from_slice(&requests[0].body).unwrap();
+        let sent = body["messages"].as_array().unwrap();
+        assert_eq!(sent.last().unwrap()["role"], "assistant");
+        assert_eq!(sent.last().unwrap()["content"], "{");
+
+        // Models that can not continue a prefill reject it, or drop it when told to
+        provider.model = ModelConfig::new("gpt-4o".to_string()).with_prefill(Some("{".to_string()));
+        assert!(matches!(
+            provider.complete("", &messages, &[]).await,
+            Err(ProviderError::InvalidRequest(_))
+        ));
+        provider.model = provider
+            .model
+            .clone()
+            .with_prefill_policy(PrefillPolicy::Ignore);
+        let (message, _) = provider.complete("", &messages, &[]).await.unwrap();
+        assert_eq!(message.as_concat_text(), "\"answer\": 42}");
+        let requests = server.received_requests().await.unwrap();
+        let body: Value = serde_json::from_slice(&requests[1].body).unwrap();
+        assert_eq!(
+            body["messages"].as_array().unwrap().last().unwrap()["role"],
+            "user"
+        );
     }
-} 
\ No newline at end of file
+
+    #[tokio::test]
+    async fn test_pretty_dry_run_only_changes_whitespace() {
+        let server = wiremock::MockServer::start().await;
+        wiremock::Mock::given(wiremock::matchers::method("POST"))
+            .respond_with(wiremock::ResponseTemplate::new(200).set_body_json(json!({
+                "model": OMG_DEFAULT_MODEL,
+                "choices": [{"message": {"role": "assistant", "content": "Done"}}],
+                "usage": {"prompt_tokens": 5, "completion_tokens": 1, "total_tokens": 6}
+            })))
+            .mount(&server)
+            .await;
+
+        let mut provider = test_provider(None, None);
+        provider.host = server.uri();
+        let messages = vec![Message::user().with_text("List the files in  src")];
+        let tools = vec![Tool::new(
+            "list_files",
+            "List the files in a directory",
+            json!({"type": "object", "properties": {"path": {"type": "string"}}}),
+        )];
+        let wire = provider.dry_run("Be brief.", &messages, &tools).unwrap();
+
+        provider.pretty_requests = true;
+        let pretty = provider.dry_run("Be brief.", &messages, &tools).unwrap();
+        assert_ne!(pretty, wire);
+        assert!(pretty.lines().count() > 1);
+        let squeezed = |body: &str| body.split_whitespace().collect::<String>();
+        assert_eq!(squeezed(&pretty), squeezed(&wire));
+
+        // The request itself stays compact
+        provider
+            .complete("Be brief.", &messages, &tools)
+            .await
+            .unwrap();
+        let requests = server.received_requests().await.unwrap();
+        assert_eq!(String::from_utf8_lossy(&requests[0].body), wire);
+    }
+
+    #[tokio::test]
+    async fn test_stream_extracts_thinking() {
+        // The think block is split across chunks
+        let chunks = [
+            json!({"choices": [{"delta": {"content": "<thi"}}]}),
+            json!({"choices": [{"delta": {"content": "nk>Look up</think>"}}]}),
+            json!({"choices": [{"delta": {"content": "Hello"}, "finish_reason": "stop"}]}),
+        ];
+        let mut body: String = chunks
+            .iter()
+            .map(|chunk| format!("data: {}\n\n", chunk))
+            .collect();
+        body.push_str("data: [DONE]\n\n");
+        let server = wiremock::MockServer::start().await;
+        wiremock::Mock::given(wiremock::matchers::method("POST"))
+            .respond_with(
+                wiremock::ResponseTemplate::new(200).set_body_raw(body, "text/event-stream"),
+            )
+            .mount(&server)
+            .await;
+
+        let mut provider = test_provider(None, None);
+        provider.host = server.uri();
+        provider.model = provider
+            .model
+            .clone()
+            .with_thinking_tags(vec!["think".to_string()]);
+        let messages = vec![Message::user().with_text("Hi")];
+        let stream = provider.stream("", &messages, &[]).await.unwrap();
+        let (message, _) = collect_message(stream).await.unwrap();
+
+        let thinking: Vec<_> = message
+            .content
+            .iter()
+            .filter_map(|c| c.as_thinking())
+            .collect();
+        assert_eq!(thinking, vec!["Look up"]);
+        assert_eq!(message.as_concat_text(), "Hello");
+    }
+
+    #[tokio::test]
+    async fn test_idempotency_key_is_stable_across_reconnects() {
+        // Every connection drops before the end of the stream
+        let server = wiremock::MockServer::start().await;
+        wiremock::Mock::given(wiremock::matchers::method("POST"))
+            .respond_with(wiremock::ResponseTemplate::new(200).set_body_raw(
+                "data: {\"choices\":[{\"delta\":{\"content\":\"Hi\"}}]}\n\n",
+                "text/event-stream",
+            ))
+            .mount(&server)
+            .await;
+
+        let mut provider = test_provider(None, None);
+        provider.host = server.uri();
+        provider.model = provider.model.clone().with_temperature(Some(0.0));
+        provider.stream_max_reconnects = 2;
+        let messages = vec![Message::user().with_text("Hi")];
+        for _ in 0..2 {
+            let stream = provider.stream("", &messages, &[]).await.unwrap();
+            assert!(collect_message(stream).await.is_err());
+        }
+
+        let requests = server.received_requests().await.unwrap();
+        let keys: Vec<&str> = requests
+            .iter()
+            .map(|r| r.headers[IDEMPOTENCY_KEY_HEADER].to_str().unwrap())
+            .collect();
+        assert_eq!(keys.len(), 6);
+        assert!(keys[..3].iter().all(|key| *key == keys[0]));
+        assert!(keys[3..].iter().all(|key| *key == keys[3]));
+        assert_ne!(keys[0], keys[3]);
+    }
+
+    #[test]
+    fn test_build_client_rejects_malformed_ca_bundle() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("ca.pem");
+        std::fs::write(&path, "not a certificate").unwrap();
+
+        let error =
+            build_client(Some(path.to_str().unwrap()), false, Duration::from_secs(5)).unwrap_err();
+        assert!(error.to_string().contains("OMG_CA_BUNDLE"));
+        assert!(build_client(Some("/does/not/exist.pem"), false, Duration::from_secs(5)).is_err());
+        assert!(build_client(None, true, Duration::from_secs(5)).is_ok());
+    }
+
+    #[test]
+    fn test_ranked_choices_sorted_by_confidence() {
+        let response = json!({
+            "choices": [
+                {
+                    "index": 0,
+                    "message": {"role": "assistant", "content": "Maybe"},
+                    "logprobs": {"content": [{"token": "Maybe", "logprob": -2.0}]}
+                },
+                {
+                    "index": 1,
+                    "message": {"role": "assistant", "content": "Yes"},
+                    "logprobs": {"content": [
+                        {"token": "Y", "logprob": -0.1},
+                        {"token": "es", "logprob": -0.3}
+                    ]}
+                },
+                {"index": 2, "message": {"role": "assistant", "content": "No logprobs"}}
+            ]
+        });
+
+        let ranked = ranked_choices(&response).unwrap();
+        let texts: Vec<_> = ranked.iter().map(|c| c.message.as_concat_text()).collect();
+        assert_eq!(texts, vec!["Yes", "Maybe", "No logprobs"]);
+        assert!((ranked[0].score + 0.2).abs() < 1e-9);
+    }
+
+    #[tokio::test]
+    async fn test_complete_ranked_requires_logprobs() {
+        let mut provider = test_provider(None, None);
+        provider.model = ModelConfig::new("claude-3-5-sonnet".to_string());
+        assert!(matches!(
+            provider.complete_ranked("", &[], &[], 3).await,
+            Err(ProviderError::NotSupported(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_complete_with_perplexity() {
+        let server = wiremock::MockServer::start().await;
+        wiremock::Mock::given(wiremock::matchers::path("/chat/completions"))
+            .respond_with(wiremock::ResponseTemplate::new(200).set_body_json(json!({
+                "model": "gpt-4o",
+                "choices": [{
+                    "index": 0,
+                    "message": {"role": "assistant", "content": "Paris"},
+                    "logprobs": {"content": [
+                        {"token": "Par", "logprob": -0.5},
+                        {"token": "is", "logprob": -1.5}
+                    ]}
+                }],
+                "usage": {"prompt_tokens": 5, "completion_tokens": 2, "total_tokens": 7}
+            })))
+            .mount(&server)
+            .await;
+
+        let mut provider = test_provider(None, None);
+        provider.host = server.uri();
+        let messages = vec![Message::user().with_text("Capital of France?")];
+        let (message, usage, perplexity) = provider
+            .complete_with_perplexity("", &messages, &[])
+            .await
+            .unwrap();
+        assert_eq!(message.as_concat_text(), "Paris");
+        assert_eq!(usage.usage.total_tokens, Some(7));
+        assert!((perplexity.unwrap() - std::f64::consts::E).abs() < 1e-6);
+
+        let requests = server.received_requests().await.unwrap();
+        let sent: Value = serde_json::from_slice(&requests[0].body).unwrap();
+        assert_eq!(sent["logprobs"], json!(true));
+    }
+
+    /// Collects everything a tracing subscriber writes
+    #[derive(Clone, Default)]
+    struct CapturedLog(Arc<Mutex<Vec<u8>>>);
+
+    impl std::io::Write for CapturedLog {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_sensitive_text_is_sent_but_not_logged() {
+        let server = wiremock::MockServer::start().await;
+        wiremock::Mock::given(wiremock::matchers::path("/chat/completions"))
+            .respond_with(wiremock::ResponseTemplate::new(200).set_body_json(json!({
+                "model": "gpt-4o",
+                "choices": [{
+                    "index": 0,
+                    "message": {"role": "assistant", "content": "Stored hunter2 for you"}
+                }]
+            })))
+            .mount(&server)
+            .await;
+
+        let log = CapturedLog::default();
+        let writer = log.clone();
+        let subscriber = tracing_subscriber::fmt()
+            .with_max_level(tracing::Level::DEBUG)
+            .with_writer(move || writer.clone())
+            .finish();
+        let _guard = tracing::subscriber::set_default(subscriber);
+
+        let mut provider = test_provider(None, None);
+        provider.host = server.uri();
+        let messages = vec![
+            Message::user().with_text("Remember my password"),
+            Message::user().with_sensitive_text("hunter2"),
+        ];
+        provider.complete("", &messages, &[]).await.unwrap();
+
+        let requests = server.received_requests().await.unwrap();
+        let body = String::from_utf8(requests[0].body.clone()).unwrap();
+        assert!(body.contains("hunter2"));
+
+        let logged = String::from_utf8(log.0.lock().unwrap().clone()).unwrap();
+        assert!(logged.contains("Remember my password"));
+        assert!(logged.contains("[SENSITIVE]"));
+        assert!(!logged.contains("hunter2"));
+    }
+
+    #[tokio::test]
+    async fn test_warmup() {
+        let server = wiremock::MockServer::start().await;
+        wiremock::Mock::given(wiremock::matchers::method("GET"))
+            .and(wiremock::matchers::path("/models"))
+            .respond_with(wiremock::ResponseTemplate::new(200).set_body_json(json!({"data": []})))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let mut provider = test_provider(None, None);
+        provider.host = server.uri();
+        provider.warmup().await.unwrap();
+
+        // Nothing listens here any more
+        provider.host = "http://127.0.0.1:9".to_string();
+        assert!(provider.warmup().await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_rate_limits_from_headers() {
+        let server = wiremock::MockServer::start().await;
+        wiremock::Mock::given(wiremock::matchers::path("/models"))
+            .respond_with(
+                wiremock::ResponseTemplate::new(200)
+                    .insert_header("x-ratelimit-limit-requests", "500")
+                    .insert_header("x-ratelimit-limit-tokens", "30000")
+                    .insert_header("x-ratelimit-remaining-requests", "499")
+                    .insert_header("x-ratelimit-remaining-tokens", "29000")
+                    .set_body_json(json!({"data": []})),
+            )
+            .mount(&server)
+            .await;
+
+        let mut provider = test_provider(None, None);
+        provider.host = server.uri();
+        let limits = provider.rate_limits().await.unwrap();
+        assert_eq!(
+            limits,
+            RateLimits {
+                requests_per_minute: Some(500),
+                tokens_per_minute: Some(30000),
+                remaining_requests: Some(499),
+                remaining_tokens: Some(29000),
+            }
+        );
+
+        // Backends that send no limits can not tell them
+        wiremock::Mock::given(wiremock::matchers::path("/v2/models"))
+            .respond_with(wiremock::ResponseTemplate::new(200).set_body_json(json!({"data": []})))
+            .mount(&server)
+            .await;
+        provider.host = format!("{}/v2", server.uri());
+        assert!(matches!(
+            provider.rate_limits().await,
+            Err(ProviderError::NotSupported(_))
+        ));
+        assert!(rate_limits_from_headers(&header::HeaderMap::new()).is_none());
+    }
+
+    #[tokio::test]
+    async fn test_error_keeps_reported_usage() {
+        let server = wiremock::MockServer::start().await;
+        wiremock::Mock::given(wiremock::matchers::path("/chat/completions"))
+            .respond_with(wiremock::ResponseTemplate::new(400).set_body_json(json!({
+                "error": {"message": "Output blocked by the content filter"},
+                "usage": {"prompt_tokens": 1200, "completion_tokens": 0, "total_tokens": 1200}
+            })))
+            .mount(&server)
+            .await;
+
+        let mut provider = test_provider(None, None);
+        provider.host = server.uri();
+        let messages = vec![Message::user().with_text("Hello")];
+        let error = provider.complete("", &messages, &[]).await.unwrap_err();
+        assert!(matches!(error, ProviderError::RequestFailed { .. }));
+        let usage = error.partial_usage().unwrap();
+        assert_eq!(usage.input_tokens, Some(1200));
+        assert_eq!(usage.output_tokens, Some(0));
+
+        // Errors without a usage object carry none
+        assert!(ProviderError::request_failed("boom")
+            .partial_usage()
+            .is_none());
+    }
+
+    #[derive(Debug)]
+    struct FixedTemperature(f64);
+
+    impl RequestInterceptor for FixedTemperature {
+        fn intercept(&self, body: &mut Value) {
+            body["temperature"] = json!(self.0);
+        }
+    }
+
+    #[test]
+    fn test_interceptor_rewrites_request() {
+        let mut provider =
+            test_provider(None, None).with_interceptor(Arc::new(FixedTemperature(0.0)));
+        provider.model = provider.model.clone().with_temperature(Some(0.9));
+
+        let messages = vec![Message::user().with_text("Hello")];
+        let mut payload = provider.build_request("system", &messages, &[]).unwrap();
+        assert_eq!(payload["temperature"], json!(0.9f32));
+
+        provider.intercept(&mut payload);
+        assert_eq!(payload["temperature"], json!(0.0));
+    }
+
+    #[test]
+    fn test_load_profile() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("profiles.json");
+        std::fs::write(
+            &path,
+            r#"{
+                "fast": {"model": "gpt-4o-mini", "temperature": 0.2, "max_output_tokens": 1024},
+                "review": {"top_p": 0.5, "frequency_penalty": 0.3, "stop": ["</review>"]},
+                "gateway": {"base_url": "https://omg.example.com/v1", "context_limit": 64000}
+            }"#,
+        )
+        .unwrap();
+
+        let profile = OmgProfile::load(&path, "fast").unwrap();
+        assert_eq!(profile.max_output_tokens, Some(1024));
+        let model = profile.model_config();
+        assert_eq!(model.model_name, "gpt-4o-mini");
+        assert_eq!(model.temperature, Some(0.2));
+
+        let model = OmgProfile::load(&path, "review").unwrap().model_config();
+        assert_eq!(model.top_p, Some(0.5));
+        assert_eq!(model.frequency_penalty, Some(0.3));
+        assert_eq!(model.stop_sequences, vec!["</review>".to_string()]);
+
+        let profile = OmgProfile::load(&path, "gateway").unwrap();
+        assert_eq!(
+            profile.base_url.as_deref(),
+            Some("https://omg.example.com/v1")
+        );
+        assert_eq!(profile.model_config().context_limit(), 64000);
+
+        let error = OmgProfile::load(&path, "missing").unwrap_err();
+        assert!(error
+            .to_string()
+            .contains("available profiles: fast, gateway, review"));
+    }
+
+    #[test]
+    fn test_load_profile_rejects_invalid_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("profiles.yaml");
+
+        std::fs::write(&path, "fast:\n  modle: gpt-4o\n").unwrap();
+        assert!(OmgProfile::load(&path, "fast").is_err());
+
+        std::fs::write(&path, "fast:\n  temperature: 5\n").unwrap();
+        assert!(OmgProfile::load(&path, "fast").is_err());
+
+        std::fs::write(&path, "fast:\n  presence_penalty: 3\n").unwrap();
+        assert!(OmgProfile::load(&path, "fast").is_err());
+
+        std::fs::write(&path, "fast: [not, a, profile").unwrap();
+        assert!(OmgProfile::load(&path, "fast").is_err());
+
+        let path = dir.path().join("profiles.toml");
+        std::fs::write(&path, "[fast]\nmodle = \"gpt-4o\"\n").unwrap();
+        assert!(OmgProfile::load(&path, "fast").is_err());
+    }
+
+    #[test]
+    fn test_load_toml_profile() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("profiles.toml");
+        std::fs::write(
+            &path,
+            "[fast]\nmodel = \"gpt-4o-mini\"\ntemperature = 0.2\n\n\
+             [review]\nstop = [\"</review>\"]\n",
+        )
+        .unwrap();
+
+        let model = OmgProfile::load(&path, "fast").unwrap().model_config();
+        assert_eq!(model.model_name, "gpt-4o-mini");
+        assert_eq!(model.temperature, Some(0.2));
+        let model = OmgProfile::load(&path, "review").unwrap().model_config();
+        assert_eq!(model.stop_sequences, vec!["</review>".to_string()]);
+    }
+
+    #[test]
+    fn test_parse_balance() {
+        let payload = json!({
+            "statusCode": 200,
+            "message": "ok",
+            "data": {"balance": "12345.5", "used": 100}
+        });
+        assert_eq!(
+            parse_balance(&payload).unwrap(),
+            Balance {
+                remaining: 12345.5,
+                used: Some(100.0)
+            }
+        );
+
+        let payload = json!({"balance": 42});
+        assert_eq!(parse_balance(&payload).unwrap().remaining, 42.0);
+        assert_eq!(parse_balance(&payload).unwrap().used, None);
+    }
+
+    #[test]
+    fn test_balance_url() {
+        assert_eq!(
+            balance_url(OMG_API_URL),
+            "https://api.ohmygpt.com/api/v1/user/admin/balance"
+        );
+        assert_eq!(
+            balance_url("https://gateway.internal/omg/v1/"),
+            "https://gateway.internal/omg/api/v1/user/admin/balance"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_account_balance_uses_host() {
+        let server = wiremock::MockServer::start().await;
+        wiremock::Mock::given(wiremock::matchers::method("POST"))
+            .and(wiremock::matchers::path("/api/v1/user/admin/balance"))
+            .and(wiremock::matchers::header("x-team", "evals"))
+            .respond_with(wiremock::ResponseTemplate::new(503))
+            .up_to_n_times(1)
+            .mount(&server)
+            .await;
+        wiremock::Mock::given(wiremock::matchers::method("POST"))
+            .and(wiremock::matchers::path("/api/v1/user/admin/balance"))
+            .respond_with(
+                wiremock::ResponseTemplate::new(200)
+                    .set_body_json(json!({"data": {"balance": "12.5"}})),
+            )
+            .mount(&server)
+            .await;
+
+        let mut provider = test_provider(None, None);
+        provider.host = format!("{}/v1", server.uri());
+        provider.custom_headers = parse_custom_headers(&json!({"X-Team": "evals"})).unwrap();
+        provider.retry =
+            RetryConfig::new(2).with_backoff(Duration::from_millis(1), Duration::from_millis(1));
+        assert_eq!(provider.account_balance().await.unwrap().remaining, 12.5);
+    }
+
+    #[tokio::test]
+    async fn test_account_balance_without_admin_key() {
+        for status in [401, 403] {
+            let server = wiremock::MockServer::start().await;
+            wiremock::Mock::given(wiremock::matchers::method("POST"))
+                .respond_with(wiremock::ResponseTemplate::new(status))
+                .mount(&server)
+                .await;
+
+            let mut provider = test_provider(None, None);
+            provider.host = format!("{}/v1", server.uri());
+            assert!(matches!(
+                provider.account_balance().await,
+                Err(ProviderError::NotSupported(_))
+            ));
+        }
+    }
+
+    #[test]
+    fn test_parse_balance_missing() {
+        let payload = json!({"data": {"email": "someone@example.com"}});
+        assert!(matches!(
+            parse_balance(&payload),
+            Err(ProviderError::NotSupported(_))
+        ));
+    }
+}
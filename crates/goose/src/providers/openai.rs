@@ -1,13 +1,28 @@
 use anyhow::Result;
 use async_trait::async_trait;
 use reqwest::Client;
-use serde_json::Value;
+use reqwest::StatusCode;
+use serde_json::{json, Value};
 use std::time::Duration;
 
-use super::base::{ConfigKey, Provider, ProviderMetadata, ProviderUsage, Usage};
+use super::base::{
+    ConfigKey, MessageStream, Provider, ProviderMetadata, ProviderUsage, ThreadId, Usage,
+};
 use super::errors::ProviderError;
-use super::formats::openai::{create_request, get_usage, response_to_message};
-use super::utils::{emit_debug_trace, get_model, handle_response_openai_compat, ImageFormat};
+use super::formats::openai::{
+    create_embedding_request, create_request, get_usage, response_to_embeddings,
+    response_to_message, response_to_models,
+};
+use super::formats::openai_responses::{
+    create_responses_request, get_responses_usage, responses_to_message,
+};
+use super::streaming::{finished_stream, openai_message_stream, sse_events};
+use super::utils::{
+    check_content_support, downconvert_content, emit_debug_trace, finish_message, get_model,
+    get_system_fingerprint, handle_response_openai_compat, needs_finishing, send_with_retry,
+    validate_params, validate_tool_pairing, ImageFormat, RetryConfig,
+};
+use crate::config::Secret;
 use crate::message::Message;
 use crate::model::ModelConfig;
 use mcp_core::tool::Tool;
@@ -22,6 +37,8 @@ pub const OPEN_AI_KNOWN_MODELS: &[&str] = &[
     "o1-mini",
 ];
 
+pub const OPEN_AI_DEFAULT_EMBEDDING_MODEL: &str = "text-embedding-3-small";
+
 pub const OPEN_AI_DOC_URL: &str = "https://platform.openai.com/docs/models";
 
 #[derive(Debug, serde::Serialize)]
@@ -29,8 +46,10 @@ pub struct OpenAiProvider {
     #[serde(skip)]
     client: Client,
     host: String,
-    api_key: String,
+    api_key: Secret,
     model: ModelConfig,
+    embedding_model: String,
+    retry: RetryConfig,
 }
 
 impl Default for OpenAiProvider {
@@ -43,10 +62,13 @@ impl Default for OpenAiProvider {
 impl OpenAiProvider {
     pub fn from_env(model: ModelConfig) -> Result<Self> {
         let config = crate::config::Config::global();
-        let api_key: String = config.get_secret("OPENAI_API_KEY")?;
+        let api_key = config.get_secret_handle("OPENAI_API_KEY")?;
         let host: String = config
             .get("OPENAI_HOST")
             .unwrap_or_else(|_| "https://api.openai.com".to_string());
+        let embedding_model: String = config
+            .get("OPENAI_EMBEDDING_MODEL")
+            .unwrap_or_else(|_| OPEN_AI_DEFAULT_EMBEDDING_MODEL.to_string());
         let client = Client::builder()
             .timeout(Duration::from_secs(600))
             .build()?;
@@ -56,21 +78,65 @@ impl OpenAiProvider {
             host,
             api_key,
             model,
+            embedding_model,
+            retry: RetryConfig::default(),
         })
     }
 
-    async fn post(&self, payload: Value) -> Result<Value, ProviderError> {
-        let url = format!("{}/v1/chat/completions", self.host.trim_end_matches('/'));
+    async fn post(&self, path: &str, payload: Value) -> Result<Value, ProviderError> {
+        let url = format!("{}/{}", self.host.trim_end_matches('/'), path);
 
-        let response = self
-            .client
-            .post(&url)
-            .header("Authorization", format!("Bearer {}", self.api_key))
-            .json(&payload)
-            .send()
-            .await?;
+        let response = send_with_retry(&self.retry, || async {
+            Ok(self
+                .client
+                .post(&url)
+                .header("Authorization", format!("Bearer {}", self.api_key.expose()))
+                .json(&payload)
+                .send()
+                .await?)
+        })
+        .await?;
+
+        handle_response_openai_compat(response)
+            .await
+            .map_err(|e| e.with_key_of(&Self::metadata()))
+    }
+
+    /// The request body for a conversation, rejecting what the model can not accept
+    fn build_request(
+        &self,
+        system: &str,
+        messages: &[Message],
+        tools: &[Tool],
+    ) -> Result<Value, ProviderError> {
+        validate_tool_pairing(messages)?;
+        self.build_chat_request(system, messages, tools)
+    }
 
-        handle_response_openai_compat(response).await
+    /// Like `build_request`, for messages that may answer tool calls sent earlier
+    fn build_chat_request(
+        &self,
+        system: &str,
+        messages: &[Message],
+        tools: &[Tool],
+    ) -> Result<Value, ProviderError> {
+        let downconverted = self
+            .model
+            .downconvert_unsupported
+            .then(|| downconvert_content(&self.model, messages, tools));
+        let (messages, tools) = match &downconverted {
+            Some((messages, tools)) => (messages.as_slice(), *tools),
+            None => (messages, tools),
+        };
+        check_content_support(&self.model, messages, tools)?;
+        validate_params(&self.model)?;
+        Ok(create_request(
+            &self.model,
+            system,
+            messages,
+            tools,
+            &ImageFormat::OpenAi,
+        )?)
     }
 }
 
@@ -90,14 +156,33 @@ impl Provider for OpenAiProvider {
             vec![
                 ConfigKey::new("OPENAI_API_KEY", true, true, None),
                 ConfigKey::new("OPENAI_HOST", false, false, Some("https://api.openai.com")),
+                ConfigKey::new(
+                    "OPENAI_EMBEDDING_MODEL",
+                    false,
+                    false,
+                    Some(OPEN_AI_DEFAULT_EMBEDDING_MODEL),
+                ),
             ],
         )
     }
 
+    fn instance_metadata(&self) -> ProviderMetadata {
+        Self::metadata().with_image_input(self.model.capabilities().image_input)
+    }
+
     fn get_model_config(&self) -> ModelConfig {
         self.model.clone()
     }
 
+    fn request_body(
+        &self,
+        system: &str,
+        messages: &[Message],
+        tools: &[Tool],
+    ) -> Result<Value, ProviderError> {
+        self.build_request(system, messages, tools)
+    }
+
     #[tracing::instrument(
         skip(self, system, messages, tools),
         fields(model_config, input, output, input_tokens, output_tokens, total_tokens)
@@ -108,13 +193,13 @@ impl Provider for OpenAiProvider {
         messages: &[Message],
         tools: &[Tool],
     ) -> Result<(Message, ProviderUsage), ProviderError> {
-        let payload = create_request(&self.model, system, messages, tools, &ImageFormat::OpenAi)?;
+        let payload = self.build_request(system, messages, tools)?;
 
         // Make request
-        let response = self.post(payload.clone()).await?;
+        let response = self.post("v1/chat/completions", payload.clone()).await?;
 
         // Parse response
-        let message = response_to_message(response.clone())?;
+        let message = finish_message(response_to_message(response.clone())?, &self.model);
         let usage = match get_usage(&response) {
             Ok(usage) => usage,
             Err(ProviderError::UsageError(e)) => {
@@ -124,7 +209,319 @@ impl Provider for OpenAiProvider {
             Err(e) => return Err(e),
         };
         let model = get_model(&response);
-        emit_debug_trace(self, &payload, &response, &usage);
-        Ok((message, ProviderUsage::new(model, usage)))
+        emit_debug_trace(self, messages, &payload, &response, &usage);
+        Ok((
+            message,
+            ProviderUsage::new(model, usage)
+                .with_system_fingerprint(get_system_fingerprint(&response)),
+        ))
+    }
+
+    async fn stream(
+        &self,
+        system: &str,
+        messages: &[Message],
+        tools: &[Tool],
+    ) -> Result<MessageStream, ProviderError> {
+        let mut payload = self.build_request(system, messages, tools)?;
+        payload["stream"] = json!(true);
+        // The last chunk then reports the usage of the whole completion
+        payload["stream_options"] = json!({"include_usage": true});
+
+        let url = format!("{}/v1/chat/completions", self.host.trim_end_matches('/'));
+        let response = send_with_retry(&self.retry, || async {
+            Ok(self
+                .client
+                .post(&url)
+                .header("Authorization", format!("Bearer {}", self.api_key.expose()))
+                .json(&payload)
+                .send()
+                .await?)
+        })
+        .await?;
+        if response.status() != StatusCode::OK {
+            handle_response_openai_compat(response)
+                .await
+                .map_err(|e| e.with_key_of(&Self::metadata()))?;
+            return Err(ProviderError::request_failed(
+                "Unexpected response to stream request",
+            ));
+        }
+        let stream = openai_message_stream(sse_events(response.bytes_stream()));
+        if !needs_finishing(&self.model) {
+            return Ok(stream);
+        }
+        // The clean up needs the whole text, so the message arrives at once
+        let model = self.model.clone();
+        Ok(finished_stream(stream, move |message| {
+            finish_message(message, &model)
+        }))
+    }
+
+    /// Continues a stored response with the Responses API, the thread is the response id
+    async fn complete_in_thread(
+        &self,
+        system: &str,
+        messages: &[Message],
+        tools: &[Tool],
+        thread: Option<ThreadId>,
+    ) -> Result<(Message, ProviderUsage, Option<ThreadId>), ProviderError> {
+        // Tool calls answered here may have been sent in an earlier turn of the thread
+        let mut chat = match thread {
+            Some(_) => self.build_chat_request(system, messages, tools)?,
+            None => self.build_request(system, messages, tools)?,
+        };
+        // Only the Responses API returns a summary of the reasoning
+        if self.model.reasoning_summary {
+            chat["reasoning"] = json!({"summary": "auto"});
+        }
+        let payload = create_responses_request(chat, thread.as_ref().map(|t| t.0.as_str()));
+
+        let response = self.post("v1/responses", payload.clone()).await?;
+
+        let message = finish_message(responses_to_message(&response)?, &self.model);
+        let usage = get_responses_usage(&response);
+        emit_debug_trace(self, messages, &payload, &response, &usage);
+        let thread = response["id"]
+            .as_str()
+            .filter(|_| payload["store"] != json!(false))
+            .map(|id| ThreadId(id.to_string()));
+        Ok((
+            message,
+            ProviderUsage::new(get_model(&response), usage),
+            thread,
+        ))
+    }
+
+    async fn fetch_supported_models(&self) -> Result<Vec<String>, ProviderError> {
+        let url = format!("{}/v1/models", self.host.trim_end_matches('/'));
+        let response = self
+            .client
+            .get(url)
+            .header("Authorization", format!("Bearer {}", self.api_key.expose()))
+            .send()
+            .await?;
+        let listing = handle_response_openai_compat(response)
+            .await
+            .map_err(|e| e.with_key_of(&Self::metadata()))?;
+        Ok(response_to_models(&listing)?)
+    }
+
+    async fn embed(&self, inputs: &[String]) -> Result<Vec<Vec<f32>>, ProviderError> {
+        if inputs.is_empty() {
+            return Ok(Vec::new());
+        }
+        let payload = create_embedding_request(&self.embedding_model, inputs);
+        let response = self.post("v1/embeddings", payload).await?;
+        Ok(response_to_embeddings(&response, inputs.len())?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::providers::streaming::collect_message;
+
+    fn test_provider(host: String) -> OpenAiProvider {
+        OpenAiProvider {
+            client: Client::new(),
+            host,
+            api_key: Secret::fixed("test"),
+            model: ModelConfig::new(OPEN_AI_DEFAULT_MODEL.to_string()),
+            embedding_model: OPEN_AI_DEFAULT_EMBEDDING_MODEL.to_string(),
+            retry: RetryConfig::new(1),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_stream() {
+        let chunks = [
+            json!({"model": "gpt-4o", "choices": [{"delta": {"role": "assistant", "content": "Listing"}}]}),
+            json!({"model": "gpt-4o", "choices": [{"delta": {"content": " files"}}]}),
+            json!({"model": "gpt-4o", "choices": [{"delta": {"tool_calls": [{
+                "index": 0,
+                "id": "call_1",
+                "type": "function",
+                "function": {"name": "developer__shell", "arguments": ""}
+            }]}}]}),
+            json!({"model": "gpt-4o", "choices": [{"delta": {"tool_calls": [{
+                "index": 0,
+                "function": {"arguments": "{\"command\":"}
+            }]}}]}),
+            json!({"model": "gpt-4o", "choices": [{"delta": {"tool_calls": [{
+                "index": 0,
+                "function": {"arguments": " \"ls\"}"}
+            }]}, "finish_reason": "tool_calls"}]}),
+            json!({"model": "gpt-4o", "choices": [], "usage": {
+                "prompt_tokens": 12,
+                "completion_tokens": 5,
+                "total_tokens": 17
+            }}),
+        ];
+        let mut body: String = chunks
+            .iter()
+            .map(|chunk| format!("data: {}\n\n", chunk))
+            .collect();
+        body.push_str("data: [DONE]\n\n");
+
+        let server = wiremock::MockServer::start().await;
+        wiremock::Mock::given(wiremock::matchers::method("POST"))
+            .and(wiremock::matchers::path("/v1/chat/completions"))
+            .and(wiremock::matchers::header("authorization", "Bearer test"))
+            .respond_with(
+                wiremock::ResponseTemplate::new(200).set_body_raw(body, "text/event-stream"),
+            )
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let provider = test_provider(server.uri());
+        let messages = vec![Message::user().with_text("What is here?")];
+        let stream = provider.stream("", &messages, &[]).await.unwrap();
+        let (message, usage) = collect_message(stream).await.unwrap();
+
+        assert_eq!(message.as_concat_text(), "Listing files");
+        let requests: Vec<_> = message
+            .content
+            .iter()
+            .filter_map(|c| c.as_tool_request())
+            .collect();
+        assert_eq!(requests.len(), 1);
+        assert_eq!(requests[0].id, "call_1");
+        let tool_call = requests[0].tool_call.as_ref().unwrap();
+        assert_eq!(tool_call.name, "developer__shell");
+        assert_eq!(tool_call.arguments, json!({"command": "ls"}));
+
+        assert_eq!(usage.model, "gpt-4o");
+        assert_eq!(usage.usage.input_tokens, Some(12));
+        assert_eq!(usage.usage.output_tokens, Some(5));
+        assert_eq!(usage.usage.total_tokens, Some(17));
+
+        let requests = server.received_requests().await.unwrap();
+        let sent: Value = serde_json::from_slice(&requests[0].body).unwrap();
+        assert_eq!(sent["stream"], json!(true));
+        assert_eq!(sent["stream_options"], json!({"include_usage": true}));
+    }
+
+    #[tokio::test]
+    async fn test_stream_applies_clean_up() {
+        let chunks = [
+            json!({"model": "gpt-4o", "choices": [{"delta": {"content": "<thi"}}]}),
+            json!({"model": "gpt-4o", "choices": [{"delta": {"content": "nk>Look up</think>  **Hel"}}]}),
+            json!({"model": "gpt-4o", "choices": [{"delta": {"content": "lo** "}, "finish_reason": "stop"}]}),
+        ];
+        let mut body: String = chunks
+            .iter()
+            .map(|chunk| format!("data: {}\n\n", chunk))
+            .collect();
+        body.push_str("data: [DONE]\n\n");
+
+        let server = wiremock::MockServer::start().await;
+        wiremock::Mock::given(wiremock::matchers::method("POST"))
+            .and(wiremock::matchers::path("/v1/chat/completions"))
+            .respond_with(
+                wiremock::ResponseTemplate::new(200).set_body_raw(body, "text/event-stream"),
+            )
+            .mount(&server)
+            .await;
+
+        let mut provider = test_provider(server.uri());
+        provider.model = provider
+            .model
+            .with_thinking_tags(vec!["think".to_string()])
+            .with_strip_markdown(true)
+            .with_trim_response(true);
+        let messages = vec![Message::user().with_text("Hi")];
+        let stream = provider.stream("", &messages, &[]).await.unwrap();
+        let (message, _) = collect_message(stream).await.unwrap();
+
+        let thinking: Vec<_> = message
+            .content
+            .iter()
+            .filter_map(|c| c.as_thinking())
+            .collect();
+        assert_eq!(thinking, vec!["Look up"]);
+        assert_eq!(message.as_concat_text(), "Hello");
+    }
+
+    #[tokio::test]
+    async fn test_complete_in_thread() {
+        let server = wiremock::MockServer::start().await;
+        wiremock::Mock::given(wiremock::matchers::method("POST"))
+            .and(wiremock::matchers::path("/v1/responses"))
+            .respond_with(wiremock::ResponseTemplate::new(200).set_body_json(json!({
+                "id": "resp_1",
+                "model": "gpt-4o",
+                "output": [{"type": "message", "role": "assistant", "content": [
+                    {"type": "output_text", "text": "Hello"}
+                ]}],
+                "usage": {"input_tokens": 10, "output_tokens": 2, "total_tokens": 12}
+            })))
+            .expect(2)
+            .mount(&server)
+            .await;
+
+        let provider = test_provider(server.uri());
+        let (message, usage, thread) = provider
+            .complete_in_thread("Be brief", &[Message::user().with_text("Hi")], &[], None)
+            .await
+            .unwrap();
+        assert_eq!(message.as_concat_text(), "Hello");
+        assert_eq!(usage.usage.total_tokens, Some(12));
+        assert_eq!(thread, Some(ThreadId("resp_1".to_string())));
+
+        provider
+            .complete_in_thread(
+                "Be brief",
+                &[Message::user().with_text("And?")],
+                &[],
+                thread,
+            )
+            .await
+            .unwrap();
+
+        let requests = server.received_requests().await.unwrap();
+        let first: Value = serde_json::from_slice(&requests[0].body).unwrap();
+        assert_eq!(first["instructions"], "Be brief");
+        assert_eq!(first["store"], json!(true));
+        assert!(first.get("previous_response_id").is_none());
+        let second: Value = serde_json::from_slice(&requests[1].body).unwrap();
+        assert_eq!(second["previous_response_id"], "resp_1");
+        assert_eq!(
+            second["input"],
+            json!([{"role": "user", "content": "And?"}])
+        );
+    }
+
+    #[tokio::test]
+    async fn test_reasoning_summary_on_responses() {
+        let server = wiremock::MockServer::start().await;
+        wiremock::Mock::given(wiremock::matchers::method("POST"))
+            .and(wiremock::matchers::path("/v1/responses"))
+            .respond_with(wiremock::ResponseTemplate::new(200).set_body_json(json!({
+                "id": "resp_1",
+                "model": "o3-mini",
+                "output": [{"type": "message", "role": "assistant", "content": [
+                    {"type": "output_text", "text": "12"}
+                ]}]
+            })))
+            .mount(&server)
+            .await;
+
+        let mut provider = test_provider(server.uri());
+        provider.model = ModelConfig::new("o3-mini".to_string())
+            .with_reasoning_effort(Some(crate::model::ReasoningEffort::Low))
+            .with_reasoning_summary(true);
+        provider
+            .complete_in_thread("", &[Message::user().with_text("3 * 4?")], &[], None)
+            .await
+            .unwrap();
+
+        let requests = server.received_requests().await.unwrap();
+        let sent: Value = serde_json::from_slice(&requests[0].body).unwrap();
+        assert_eq!(
+            sent["reasoning"],
+            json!({"effort": "low", "summary": "auto"})
+        );
     }
 }
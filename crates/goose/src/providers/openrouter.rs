@@ -6,7 +6,11 @@ use std::time::Duration;
 
 use super::base::{ConfigKey, Provider, ProviderMetadata, ProviderUsage, Usage};
 use super::errors::ProviderError;
-use super::utils::{emit_debug_trace, get_model, handle_response_openai_compat};
+use super::utils::{
+    emit_debug_trace, get_model, get_system_fingerprint, handle_response_openai_compat,
+    send_with_retry, RetryConfig,
+};
+use crate::config::Secret;
 use crate::message::Message;
 use crate::model::ModelConfig;
 use crate::providers::formats::openai::{create_request, get_usage, response_to_message};
@@ -24,8 +28,9 @@ pub struct OpenRouterProvider {
     #[serde(skip)]
     client: Client,
     host: String,
-    api_key: String,
+    api_key: Secret,
     model: ModelConfig,
+    retry: RetryConfig,
 }
 
 impl Default for OpenRouterProvider {
@@ -38,7 +43,7 @@ impl Default for OpenRouterProvider {
 impl OpenRouterProvider {
     pub fn from_env(model: ModelConfig) -> Result<Self> {
         let config = crate::config::Config::global();
-        let api_key: String = config.get_secret("OPENROUTER_API_KEY")?;
+        let api_key = config.get_secret_handle("OPENROUTER_API_KEY")?;
         let host: String = config
             .get("OPENROUTER_HOST")
             .unwrap_or_else(|_| "https://openrouter.ai".to_string());
@@ -52,6 +57,7 @@ impl OpenRouterProvider {
             host,
             api_key,
             model,
+            retry: RetryConfig::default(),
         })
     }
 
@@ -61,18 +67,23 @@ impl OpenRouterProvider {
             self.host.trim_end_matches('/')
         );
 
-        let response = self
-            .client
-            .post(&url)
-            .header("Content-Type", "application/json")
-            .header("Authorization", format!("Bearer {}", self.api_key))
-            .header("HTTP-Referer", "https://github.com/block/goose")
-            .header("X-Title", "Goose")
-            .json(&payload)
-            .send()
-            .await?;
-
-        handle_response_openai_compat(response).await
+        let response = send_with_retry(&self.retry, || async {
+            Ok(self
+                .client
+                .post(&url)
+                .header("Content-Type", "application/json")
+                .header("Authorization", format!("Bearer {}", self.api_key.expose()))
+                .header("HTTP-Referer", "https://github.com/block/goose")
+                .header("X-Title", "Goose")
+                .json(&payload)
+                .send()
+                .await?)
+        })
+        .await?;
+
+        handle_response_openai_compat(response)
+            .await
+            .map_err(|e| e.with_key_of(&Self::metadata()))
     }
 }
 
@@ -181,6 +192,10 @@ impl Provider for OpenRouterProvider {
         )
     }
 
+    fn instance_metadata(&self) -> ProviderMetadata {
+        Self::metadata()
+    }
+
     fn get_model_config(&self) -> ModelConfig {
         self.model.clone()
     }
@@ -212,7 +227,11 @@ impl Provider for OpenRouterProvider {
             Err(e) => return Err(e),
         };
         let model = get_model(&response);
-        emit_debug_trace(self, &payload, &response, &usage);
-        Ok((message, ProviderUsage::new(model, usage)))
+        emit_debug_trace(self, messages, &payload, &response, &usage);
+        Ok((
+            message,
+            ProviderUsage::new(model, usage)
+                .with_system_fingerprint(get_system_fingerprint(&response)),
+        ))
     }
 }
@@ -0,0 +1,352 @@
+use async_trait::async_trait;
+use futures::StreamExt;
+use std::time::Instant;
+use tracing::field::Empty;
+use tracing::{Instrument, Span};
+
+use super::base::{
+    MessageDelta, MessageStream, Provider, ProviderMetadata, ProviderUsage, RateLimits, ThreadId,
+};
+use super::errors::ProviderError;
+use super::pricing::request_cost;
+use crate::message::Message;
+use crate::model::ModelConfig;
+use mcp_core::tool::Tool;
+
+/// A provider decorator that wraps every call in a span following the OpenTelemetry GenAI conventions
+///
+/// Spans are emitted through `tracing` with the `gen_ai.*` attribute names and
+/// the `otel.*` fields understood by `tracing-opentelemetry`, so installing that
+/// layer exports them as standard client spans. Duration comes from the span,
+/// token usage, cost and the finish reason are recorded once the response is
+/// known. Every call is also counted in `tracing::metrics`.
+pub struct OtelProvider {
+    inner: Box<dyn Provider>,
+}
+
+impl OtelProvider {
+    pub fn new(inner: Box<dyn Provider>) -> Self {
+        Self { inner }
+    }
+
+    fn span(&self) -> Span {
+        let model = self.inner.get_model_config();
+        tracing::info_span!(
+            "chat",
+            "otel.name" = format!("chat {}", model.model_name),
+            "otel.kind" = "client",
+            "otel.status_code" = Empty,
+            "gen_ai.operation.name" = "chat",
+            "gen_ai.system" = self.inner.instance_metadata().name,
+            "gen_ai.request.model" = model.model_name,
+            "gen_ai.request.temperature" = model.temperature,
+            "gen_ai.request.top_p" = model.top_p,
+            "gen_ai.request.max_tokens" = model.max_tokens,
+            "gen_ai.response.model" = Empty,
+            "gen_ai.response.finish_reasons" = Empty,
+            "gen_ai.usage.input_tokens" = Empty,
+            "gen_ai.usage.output_tokens" = Empty,
+            "goose.cost_usd" = Empty,
+            "error.type" = Empty,
+        )
+    }
+}
+
+/// Count the call in the metrics, by the model that was asked for
+fn record_metrics(model: &str, started: Instant, result: Result<&ProviderUsage, &ProviderError>) {
+    let duration = started.elapsed();
+    match result {
+        Ok(usage) => crate::tracing::metrics::record_completion(
+            model,
+            duration,
+            Some(&usage.usage),
+            request_cost(&usage.model, &usage.usage),
+            None,
+        ),
+        Err(error) => crate::tracing::metrics::record_completion(
+            model,
+            duration,
+            error.partial_usage(),
+            None,
+            Some(error_type(error)),
+        ),
+    }
+}
+
+fn record_usage(span: &Span, usage: &ProviderUsage) {
+    span.record("gen_ai.response.model", usage.model.as_str());
+    if let Some(tokens) = usage.usage.input_tokens {
+        span.record("gen_ai.usage.input_tokens", tokens);
+    }
+    if let Some(tokens) = usage.usage.output_tokens {
+        span.record("gen_ai.usage.output_tokens", tokens);
+    }
+    if let Some(cost) = request_cost(&usage.model, &usage.usage) {
+        span.record("goose.cost_usd", cost);
+    }
+}
+
+/// Responses do not keep the finish reason, a requested tool call is the only other outcome
+fn record_finish_reason(span: &Span, tool_call: bool) {
+    let reason = if tool_call { "tool_calls" } else { "stop" };
+    span.record("gen_ai.response.finish_reasons", reason);
+}
+
+fn record_error(span: &Span, error: &ProviderError) {
+    span.record("otel.status_code", "ERROR");
+    span.record("error.type", error_type(error));
+}
+
+/// A short stable name for the error, as `error.type` expects
+pub fn error_type(error: &ProviderError) -> &'static str {
+    match error {
+        ProviderError::Authentication { .. } => "authentication",
+        ProviderError::ContextLengthExceeded(_) => "context_length_exceeded",
+        ProviderError::RateLimitExceeded { .. } => "rate_limit_exceeded",
+        ProviderError::ServerError(_) => "server_error",
+        ProviderError::Http { .. } => "http",
+        ProviderError::InvalidResponse { .. } => "invalid_response",
+        ProviderError::ModelNotFound { .. } => "model_not_found",
+        ProviderError::FirstTokenTimeout(_)
+        | ProviderError::ConnectTimeout { .. }
+        | ProviderError::ReadTimeout { .. } => "timeout",
+        ProviderError::ToolIterationLimit(_) => "tool_iteration_limit",
+        ProviderError::BudgetExceeded(_) => "budget_exceeded",
+        ProviderError::InvalidRequest(_) => "invalid_request",
+        ProviderError::InvalidConversation(_) => "invalid_conversation",
+        ProviderError::TooManyImages { .. } => "too_many_images",
+        _ => "request_failed",
+    }
+}
+
+#[async_trait]
+impl Provider for OtelProvider {
+    fn metadata() -> ProviderMetadata {
+        ProviderMetadata::empty()
+    }
+
+    fn instance_metadata(&self) -> ProviderMetadata {
+        self.inner.instance_metadata()
+    }
+
+    fn get_model_config(&self) -> ModelConfig {
+        self.inner.get_model_config()
+    }
+
+    fn format_system_prompt(&self, raw: &str) -> String {
+        self.inner.format_system_prompt(raw)
+    }
+
+    fn request_body(
+        &self,
+        system: &str,
+        messages: &[Message],
+        tools: &[Tool],
+    ) -> Result<serde_json::Value, ProviderError> {
+        self.inner.request_body(system, messages, tools)
+    }
+
+    fn count_request_tokens(&self, system: &str, messages: &[Message], tools: &[Tool]) -> usize {
+        self.inner.count_request_tokens(system, messages, tools)
+    }
+
+    async fn warmup(&self) -> Result<(), ProviderError> {
+        self.inner.warmup().await
+    }
+
+    async fn flush(&self) -> Result<(), ProviderError> {
+        self.inner.flush().await
+    }
+
+    async fn rate_limits(&self) -> Result<RateLimits, ProviderError> {
+        self.inner.rate_limits().await
+    }
+
+    async fn fetch_supported_models(&self) -> Result<Vec<String>, ProviderError> {
+        self.inner.fetch_supported_models().await
+    }
+
+    async fn embed(&self, inputs: &[String]) -> Result<Vec<Vec<f32>>, ProviderError> {
+        self.inner.embed(inputs).await
+    }
+
+    async fn complete(
+        &self,
+        system: &str,
+        messages: &[Message],
+        tools: &[Tool],
+    ) -> Result<(Message, ProviderUsage), ProviderError> {
+        let span = self.span();
+        let started = Instant::now();
+        let result = self
+            .inner
+            .complete(system, messages, tools)
+            .instrument(span.clone())
+            .await;
+        match &result {
+            Ok((message, usage)) => {
+                record_usage(&span, usage);
+                record_finish_reason(&span, message.is_tool_call());
+            }
+            Err(error) => record_error(&span, error),
+        }
+        let model = self.inner.get_model_config().model_name;
+        record_metrics(&model, started, result.as_ref().map(|(_, usage)| usage));
+        result
+    }
+
+    async fn complete_in_thread(
+        &self,
+        system: &str,
+        messages: &[Message],
+        tools: &[Tool],
+        thread: Option<ThreadId>,
+    ) -> Result<(Message, ProviderUsage, Option<ThreadId>), ProviderError> {
+        let span = self.span();
+        let started = Instant::now();
+        let result = self
+            .inner
+            .complete_in_thread(system, messages, tools, thread)
+            .instrument(span.clone())
+            .await;
+        match &result {
+            Ok((message, usage, _)) => {
+                record_usage(&span, usage);
+                record_finish_reason(&span, message.is_tool_call());
+            }
+            Err(error) => record_error(&span, error),
+        }
+        let model = self.inner.get_model_config().model_name;
+        record_metrics(&model, started, result.as_ref().map(|(_, usage, _)| usage));
+        result
+    }
+
+    async fn stream(
+        &self,
+        system: &str,
+        messages: &[Message],
+        tools: &[Tool],
+    ) -> Result<MessageStream, ProviderError> {
+        let span = self.span();
+        let model = self.inner.get_model_config().model_name;
+        let started = Instant::now();
+        let mut stream = match self
+            .inner
+            .stream(system, messages, tools)
+            .instrument(span.clone())
+            .await
+        {
+            Ok(stream) => stream,
+            Err(error) => {
+                record_error(&span, &error);
+                record_metrics(&model, started, Err(&error));
+                return Err(error);
+            }
+        };
+
+        // The span stays open until the stream is finished or dropped
+        Ok(Box::pin(async_stream::try_stream! {
+            let mut tool_call = false;
+            let mut final_usage = ProviderUsage::new(model.clone(), Default::default());
+            while let Some(delta) = stream.next().await {
+                match delta {
+                    Ok(delta) => {
+                        match &delta {
+                            MessageDelta::Usage(usage) => {
+                                record_usage(&span, usage);
+                                final_usage = usage.clone();
+                            }
+                            MessageDelta::Content(content) => {
+                                tool_call |= content.as_tool_request().is_some()
+                            }
+                            MessageDelta::Citations(_)
+                            | MessageDelta::ToolCallFragment { .. }
+                            | MessageDelta::Finish(_) => {}
+                        }
+                        yield delta;
+                    }
+                    Err(error) => {
+                        record_error(&span, &error);
+                        record_metrics(&model, started, Err(&error));
+                        Err(error)?;
+                    }
+                }
+            }
+            record_finish_reason(&span, tool_call);
+            record_metrics(&model, started, Ok(&final_usage));
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::providers::base::Usage;
+    use crate::providers::testing::TestProvider;
+    use std::collections::HashMap;
+    use std::sync::{Arc, Mutex};
+    use tracing::field::{Field, Visit};
+    use tracing::span::{Attributes, Id, Record};
+    use tracing_subscriber::layer::{Context, Layer, SubscriberExt};
+
+    /// Collects the fields recorded on every span
+    #[derive(Clone, Default)]
+    struct FieldsLayer(Arc<Mutex<HashMap<String, String>>>);
+
+    impl Visit for FieldsLayer {
+        fn record_debug(&mut self, field: &Field, value: &dyn std::fmt::Debug) {
+            let value = format!("{:?}", value).trim_matches('"').to_string();
+            self.0
+                .lock()
+                .unwrap()
+                .insert(field.name().to_string(), value);
+        }
+    }
+
+    impl<S: tracing::Subscriber> Layer<S> for FieldsLayer {
+        fn on_new_span(&self, attrs: &Attributes<'_>, _id: &Id, _ctx: Context<'_, S>) {
+            attrs.record(&mut self.clone());
+        }
+
+        fn on_record(&self, _id: &Id, values: &Record<'_>, _ctx: Context<'_, S>) {
+            values.record(&mut self.clone());
+        }
+    }
+
+    /// Answers with usage, or a rate limit error without messages
+    fn usage_provider() -> Box<TestProvider> {
+        let provider = TestProvider::new("gpt-4o")
+            .with_model_config(ModelConfig::new("gpt-4o".to_string()).with_max_tokens(Some(100)))
+            .with_usage("gpt-4o-2024-08-06", Usage::new(Some(12), Some(3), Some(15)))
+            .with_reply(|request| match request.messages.is_empty() {
+                true => Err(ProviderError::rate_limited("slow down".to_string())),
+                false => Ok(Message::assistant().with_text("hi")),
+            });
+        Box::new(provider)
+    }
+
+    #[tokio::test]
+    async fn test_complete_records_gen_ai_attributes() {
+        let fields = FieldsLayer::default();
+        let subscriber = tracing_subscriber::registry().with(fields.clone());
+        let _guard = tracing::subscriber::set_default(subscriber);
+
+        let provider = OtelProvider::new(usage_provider());
+        let messages = vec![Message::user().with_text("hello")];
+        provider.complete("", &messages, &[]).await.unwrap();
+
+        let recorded = fields.0.lock().unwrap().clone();
+        assert_eq!(recorded["otel.name"], "chat gpt-4o");
+        assert_eq!(recorded["gen_ai.request.model"], "gpt-4o");
+        assert_eq!(recorded["gen_ai.request.max_tokens"], "100");
+        assert_eq!(recorded["gen_ai.response.model"], "gpt-4o-2024-08-06");
+        assert_eq!(recorded["gen_ai.usage.input_tokens"], "12");
+        assert_eq!(recorded["gen_ai.usage.output_tokens"], "3");
+        assert_eq!(recorded["gen_ai.response.finish_reasons"], "stop");
+
+        assert!(provider.complete("", &[], &[]).await.is_err());
+        let recorded = fields.0.lock().unwrap().clone();
+        assert_eq!(recorded["error.type"], "rate_limit_exceeded");
+        assert_eq!(recorded["otel.status_code"], "ERROR");
+    }
+}
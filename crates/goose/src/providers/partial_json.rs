@@ -0,0 +1,237 @@
+use serde_json::{Map, Number, Value};
+
+/// Parse a possibly truncated JSON document into the most complete value so far
+///
+/// Open strings, arrays and objects are closed, while a trailing key without a
+/// value or a partial literal such as `tr` is dropped. Numbers at the end of the
+/// input are kept even though more digits may follow. Returns `None` when nothing
+/// usable has arrived yet. This is meant for rendering progress, the complete
+/// document should still be parsed strictly once it has fully arrived.
+pub fn parse_partial_json(input: &str) -> Option<Value> {
+    let mut parser = PartialParser { src: input, pos: 0 };
+    parser.value().map(|(value, _)| value)
+}
+
+struct PartialParser<'a> {
+    src: &'a str,
+    pos: usize,
+}
+
+/// A parsed value and whether it was complete
+type Parsed = Option<(Value, bool)>;
+
+impl PartialParser<'_> {
+    fn peek(&self) -> Option<u8> {
+        self.src.as_bytes().get(self.pos).copied()
+    }
+
+    fn skip_whitespace(&mut self) {
+        while matches!(self.peek(), Some(b' ' | b'\n' | b'\r' | b'\t')) {
+            self.pos += 1;
+        }
+    }
+
+    fn value(&mut self) -> Parsed {
+        self.skip_whitespace();
+        match self.peek()? {
+            b'{' => self.object(),
+            b'[' => self.array(),
+            b'"' => self
+                .string()
+                .map(|(text, complete)| (Value::String(text), complete)),
+            b't' => self.literal("true", Value::Bool(true)),
+            b'f' => self.literal("false", Value::Bool(false)),
+            b'n' => self.literal("null", Value::Null),
+            b'-' | b'0'..=b'9' => self.number(),
+            _ => None,
+        }
+    }
+
+    fn literal(&mut self, word: &str, value: Value) -> Parsed {
+        if self.src[self.pos..].starts_with(word) {
+            self.pos += word.len();
+            Some((value, true))
+        } else {
+            None
+        }
+    }
+
+    fn number(&mut self) -> Parsed {
+        let start = self.pos;
+        while matches!(
+            self.peek(),
+            Some(b'-' | b'+' | b'.' | b'e' | b'E' | b'0'..=b'9')
+        ) {
+            self.pos += 1;
+        }
+        let complete = self.peek().is_some();
+        let number: Number = self.src[start..self.pos].parse().ok()?;
+        Some((Value::Number(number), complete))
+    }
+
+    fn string(&mut self) -> Option<(String, bool)> {
+        // Skip the opening quote
+        self.pos += 1;
+        let mut text = String::new();
+        loop {
+            let rest = &self.src[self.pos..];
+            let mut chars = rest.chars();
+            match chars.next() {
+                None => return Some((text, false)),
+                Some('"') => {
+                    self.pos += 1;
+                    return Some((text, true));
+                }
+                Some('\\') => match chars.next() {
+                    None => return Some((text, false)),
+                    Some('u') => {
+                        let Some(hex) = rest.get(2..6) else {
+                            return Some((text, false));
+                        };
+                        let code = u32::from_str_radix(hex, 16).ok()?;
+                        text.push(char::from_u32(code).unwrap_or(char::REPLACEMENT_CHARACTER));
+                        self.pos += 6;
+                    }
+                    Some(escaped) => {
+                        text.push(match escaped {
+                            'n' => '\n',
+                            't' => '\t',
+                            'r' => '\r',
+                            'b' => '\u{8}',
+                            'f' => '\u{c}',
+                            other => other,
+                        });
+                        self.pos += 1 + escaped.len_utf8();
+                    }
+                },
+                Some(c) => {
+                    text.push(c);
+                    self.pos += c.len_utf8();
+                }
+            }
+        }
+    }
+
+    fn array(&mut self) -> Parsed {
+        self.pos += 1;
+        let mut items = Vec::new();
+        loop {
+            self.skip_whitespace();
+            match self.peek() {
+                None => return Some((Value::Array(items), false)),
+                Some(b']') => {
+                    self.pos += 1;
+                    return Some((Value::Array(items), true));
+                }
+                Some(b',') => {
+                    self.pos += 1;
+                    continue;
+                }
+                Some(_) => {}
+            }
+            match self.value() {
+                Some((item, true)) => items.push(item),
+                Some((item, false)) => {
+                    items.push(item);
+                    return Some((Value::Array(items), false));
+                }
+                None => return Some((Value::Array(items), false)),
+            }
+        }
+    }
+
+    fn object(&mut self) -> Parsed {
+        self.pos += 1;
+        let mut map = Map::new();
+        loop {
+            self.skip_whitespace();
+            match self.peek() {
+                None => return Some((Value::Object(map), false)),
+                Some(b'}') => {
+                    self.pos += 1;
+                    return Some((Value::Object(map), true));
+                }
+                Some(b',') => {
+                    self.pos += 1;
+                    continue;
+                }
+                Some(b'"') => {}
+                Some(_) => return None,
+            }
+
+            // A key is only kept once it is complete and has a value
+            let (key, complete) = self.string()?;
+            self.skip_whitespace();
+            if !complete || self.peek() != Some(b':') {
+                return Some((Value::Object(map), false));
+            }
+            self.pos += 1;
+
+            match self.value() {
+                Some((value, true)) => {
+                    map.insert(key, value);
+                }
+                Some((value, false)) => {
+                    map.insert(key, value);
+                    return Some((Value::Object(map), false));
+                }
+                None => return Some((Value::Object(map), false)),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_complete_documents() {
+        let document = r#"{"name": "Ada", "tags": ["a", "b"], "age": 36, "ok": true, "x": null}"#;
+        assert_eq!(
+            parse_partial_json(document),
+            Some(serde_json::from_str::<Value>(document).unwrap())
+        );
+    }
+
+    #[test]
+    fn test_truncated_documents() {
+        let cases = [
+            ("", None),
+            ("{", Some(json!({}))),
+            (r#"{"na"#, Some(json!({}))),
+            (r#"{"name""#, Some(json!({}))),
+            (r#"{"name": "#, Some(json!({}))),
+            (r#"{"name": "Ad"#, Some(json!({"name": "Ad"}))),
+            (
+                r#"{"name": "Ada", "tags": ["a", "#,
+                Some(json!({"name": "Ada", "tags": ["a"]})),
+            ),
+            (r#"{"ok": tr"#, Some(json!({}))),
+            (r#"{"age": 3"#, Some(json!({"age": 3}))),
+            (
+                r#"{"a": {"b": [1, {"c": "d"#,
+                Some(json!({"a": {"b": [1, {"c": "d"}]}})),
+            ),
+            (r#"{"text": "line\"#, Some(json!({"text": "line"}))),
+            (r#"{"text": "café \u00"#, Some(json!({"text": "café "}))),
+        ];
+        for (input, expected) in cases {
+            assert_eq!(parse_partial_json(input), expected, "input: {}", input);
+        }
+    }
+
+    #[test]
+    fn test_fragments_grow_monotonically() {
+        let document = r#"{"title": "Report", "items": [{"id": 1}, {"id": 2}], "done": false}"#;
+        let mut previous_keys = 0;
+        for end in 1..=document.len() {
+            let snapshot = parse_partial_json(&document[..end]).unwrap();
+            let keys = snapshot.as_object().unwrap().len();
+            assert!(keys >= previous_keys);
+            previous_keys = keys;
+        }
+        assert_eq!(previous_keys, 3);
+    }
+}
@@ -0,0 +1,138 @@
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::{LazyLock, RwLock};
+
+use super::base::Usage;
+
+/// The list price of a model in US dollars per million tokens
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct ModelPricing {
+    pub input_per_million: f64,
+    pub output_per_million: f64,
+}
+
+impl ModelPricing {
+    const fn new(input_per_million: f64, output_per_million: f64) -> Self {
+        Self {
+            input_per_million,
+            output_per_million,
+        }
+    }
+
+    /// The cost of a request with this usage, missing token counts count as zero
+    pub fn cost(&self, usage: &Usage) -> f64 {
+        let tokens = |count: Option<i32>| count.unwrap_or(0).max(0) as f64 / 1_000_000.0;
+        tokens(usage.input_tokens) * self.input_per_million
+            + tokens(usage.output_tokens) * self.output_per_million
+    }
+}
+
+/// Prices by model name prefix, more specific prefixes first
+const PRICING: &[(&str, ModelPricing)] = &[
+    ("gpt-4o-mini", ModelPricing::new(0.15, 0.60)),
+    ("gpt-4o", ModelPricing::new(2.50, 10.00)),
+    ("gpt-4-turbo", ModelPricing::new(10.00, 30.00)),
+    ("gpt-3.5-turbo", ModelPricing::new(0.50, 1.50)),
+    ("o1-mini", ModelPricing::new(1.10, 4.40)),
+    ("o1", ModelPricing::new(15.00, 60.00)),
+    ("claude-3-5-haiku", ModelPricing::new(0.80, 4.00)),
+    ("claude-3-5-sonnet", ModelPricing::new(3.00, 15.00)),
+    ("claude-3-opus", ModelPricing::new(15.00, 75.00)),
+    ("claude-3-haiku", ModelPricing::new(0.25, 1.25)),
+];
+
+/// Prices set at runtime by model name prefix, these take precedence over `PRICING`
+static CUSTOM_PRICING: LazyLock<RwLock<HashMap<String, ModelPricing>>> =
+    LazyLock::new(|| RwLock::new(HashMap::new()));
+
+/// Price `model` and its dated versions at these US dollar rates per million tokens
+///
+/// Overrides the built-in price for every request in the process, e.g. with a
+/// negotiated rate.
+pub fn set_pricing(model: &str, input_per_million: f64, output_per_million: f64) {
+    CUSTOM_PRICING.write().unwrap().insert(
+        model.to_string(),
+        ModelPricing::new(input_per_million, output_per_million),
+    );
+}
+
+/// Load prices from a JSON file mapping model names to `ModelPricing`, as with `set_pricing`
+///
+/// For example `{"gpt-4o": {"input_per_million": 2.0, "output_per_million": 8.0}}`.
+/// Returns the number of models priced.
+pub fn load_pricing(path: &Path) -> Result<usize> {
+    let contents = std::fs::read_to_string(path)
+        .with_context(|| format!("Could not read pricing file {}", path.display()))?;
+    let table: HashMap<String, ModelPricing> = serde_json::from_str(&contents)
+        .with_context(|| format!("Invalid pricing file {}", path.display()))?;
+    let count = table.len();
+    extend_pricing(table);
+    Ok(count)
+}
+
+/// Price several models at once, as with `set_pricing`
+pub fn extend_pricing(table: HashMap<String, ModelPricing>) {
+    CUSTOM_PRICING.write().unwrap().extend(table);
+}
+
+/// The price of a model, matching dated versions such as "gpt-4o-2024-08-06" too
+pub fn model_pricing(model: &str) -> Option<ModelPricing> {
+    // The longest matching custom prefix is the most specific one
+    let custom = CUSTOM_PRICING
+        .read()
+        .unwrap()
+        .iter()
+        .filter(|(prefix, _)| model.starts_with(prefix.as_str()))
+        .max_by_key(|(prefix, _)| prefix.len())
+        .map(|(_, pricing)| *pricing);
+    custom.or_else(|| {
+        PRICING
+            .iter()
+            .find(|(prefix, _)| model.starts_with(prefix))
+            .map(|(_, pricing)| *pricing)
+    })
+}
+
+/// The cost in US dollars of a request to `model`, `None` when its price is unknown
+pub fn request_cost(model: &str, usage: &Usage) -> Option<f64> {
+    model_pricing(model).map(|pricing| pricing.cost(usage))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_request_cost() {
+        let usage = Usage::new(Some(1_000_000), Some(500_000), None);
+        assert_eq!(request_cost("gpt-4o-2024-08-06", &usage), Some(7.5));
+        let mini = request_cost("gpt-4o-mini", &usage).unwrap();
+        assert!((mini - 0.45).abs() < 1e-9);
+        assert_eq!(request_cost("local-model", &usage), None);
+        assert_eq!(request_cost("gpt-4o", &Usage::default()), Some(0.0));
+    }
+
+    #[test]
+    fn test_custom_pricing() {
+        // Other tests use the built-in prices, so only models they do not use are overridden
+        let usage = Usage::new(Some(1_000_000), Some(1_000_000), None);
+        assert_eq!(request_cost("claude-3-opus-20240229", &usage), Some(90.0));
+        set_pricing("claude-3-opus", 10.0, 50.0);
+        assert_eq!(request_cost("claude-3-opus-20240229", &usage), Some(60.0));
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("pricing.json");
+        std::fs::write(
+            &path,
+            r#"{"private-llm": {"input_per_million": 0.5, "output_per_million": 1.5}}"#,
+        )
+        .unwrap();
+        assert_eq!(load_pricing(&path).unwrap(), 1);
+        assert_eq!(request_cost("private-llm-v2", &usage), Some(2.0));
+
+        std::fs::write(&path, "not json").unwrap();
+        assert!(load_pricing(&path).is_err());
+    }
+}
@@ -0,0 +1,410 @@
+use async_trait::async_trait;
+use futures::StreamExt;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Arc, LazyLock, Mutex};
+use std::time::Duration;
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+use tokio::time::Instant;
+
+use super::base::{
+    MessageDelta, MessageStream, Provider, ProviderMetadata, ProviderUsage, RateLimits, ThreadId,
+    Usage,
+};
+use super::errors::ProviderError;
+use crate::message::Message;
+use crate::model::ModelConfig;
+use mcp_core::tool::Tool;
+
+const WINDOW: Duration = Duration::from_secs(60);
+
+/// Client side limits of a provider, each one is off unless set
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RateLimitConfig {
+    pub requests_per_minute: Option<u32>,
+    /// Input and output tokens per minute, requests are counted with their
+    /// estimated input until the response reports the actual usage
+    pub tokens_per_minute: Option<u32>,
+    /// Completions in flight at once, including open streams
+    pub max_concurrent: Option<usize>,
+}
+
+impl RateLimitConfig {
+    pub fn is_empty(&self) -> bool {
+        *self == Self::default()
+    }
+}
+
+impl From<RateLimits> for RateLimitConfig {
+    fn from(limits: RateLimits) -> Self {
+        Self {
+            requests_per_minute: limits.requests_per_minute,
+            tokens_per_minute: limits.tokens_per_minute,
+            max_concurrent: None,
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+struct Window {
+    /// Start, id and tokens of the requests of the last minute, oldest first
+    requests: VecDeque<(Instant, u64, u64)>,
+    next_id: u64,
+}
+
+impl Window {
+    fn expire(&mut self, now: Instant) {
+        while let Some(&(start, _, _)) = self.requests.front() {
+            if now.duration_since(start) < WINDOW {
+                break;
+            }
+            self.requests.pop_front();
+        }
+    }
+
+    fn tokens(&self) -> u64 {
+        self.requests.iter().map(|&(_, _, tokens)| tokens).sum()
+    }
+}
+
+/// Requests and tokens per minute and concurrent completions of one provider
+///
+/// Requests wait for the budget rather than fail, the oldest request of the
+/// last minute has to age out before a request over a limit is sent. A
+/// request larger than the whole token budget is sent once nothing else
+/// counts against it, so it is not held back forever.
+#[derive(Debug)]
+pub struct RateLimiter {
+    config: RateLimitConfig,
+    window: Mutex<Window>,
+    concurrency: Option<Arc<Semaphore>>,
+}
+
+/// The limiters of the process, by provider name
+static LIMITERS: LazyLock<Mutex<HashMap<String, Arc<RateLimiter>>>> =
+    LazyLock::new(|| Mutex::new(HashMap::new()));
+
+/// A request counted against the limits, holding its concurrency slot until dropped
+struct Admission {
+    id: u64,
+    _permit: Option<OwnedSemaphorePermit>,
+}
+
+impl RateLimiter {
+    pub fn new(config: RateLimitConfig) -> Self {
+        Self {
+            config,
+            window: Mutex::new(Window::default()),
+            concurrency: config
+                .max_concurrent
+                .map(|max| Arc::new(Semaphore::new(max.max(1)))),
+        }
+    }
+
+    /// The limiter every provider named `name` shares in this process
+    ///
+    /// The first call for a name creates it with `config`, later calls get
+    /// the same limiter so sessions on one API key draw on one budget.
+    pub fn shared(name: &str, config: RateLimitConfig) -> Arc<Self> {
+        LIMITERS
+            .lock()
+            .unwrap()
+            .entry(name.to_string())
+            .or_insert_with(|| Arc::new(Self::new(config)))
+            .clone()
+    }
+
+    pub fn config(&self) -> RateLimitConfig {
+        self.config
+    }
+
+    /// Whether the request needs its tokens estimated
+    fn counts_tokens(&self) -> bool {
+        self.config.tokens_per_minute.is_some()
+    }
+
+    /// Wait for a concurrency slot and room in the window for a request of `tokens`
+    async fn admit(&self, tokens: u64) -> Admission {
+        let permit = match &self.concurrency {
+            Some(semaphore) => Some(
+                semaphore
+                    .clone()
+                    .acquire_owned()
+                    .await
+                    .expect("the semaphore is never closed"),
+            ),
+            None => None,
+        };
+        loop {
+            let wait = {
+                let mut window = self.window.lock().unwrap();
+                let now = Instant::now();
+                window.expire(now);
+                let over_requests = self
+                    .config
+                    .requests_per_minute
+                    .is_some_and(|rpm| window.requests.len() as u64 >= rpm.max(1) as u64);
+                let over_tokens = self.config.tokens_per_minute.is_some_and(|tpm| {
+                    !window.requests.is_empty() && window.tokens() + tokens > tpm as u64
+                });
+                if !over_requests && !over_tokens {
+                    let id = window.next_id;
+                    window.next_id += 1;
+                    window.requests.push_back((now, id, tokens));
+                    return Admission {
+                        id,
+                        _permit: permit,
+                    };
+                }
+                let (oldest, _, _) = window.requests[0];
+                WINDOW.saturating_sub(now.duration_since(oldest))
+            };
+            tracing::debug!("Waiting {:?} for the client side rate limit", wait);
+            tokio::time::sleep(wait).await;
+        }
+    }
+
+    /// Count the request with the tokens it actually used
+    fn settle(&self, admission: &Admission, usage: Option<&Usage>) {
+        let Some(usage) = usage else {
+            return;
+        };
+        let tokens = usage.total_tokens.or_else(|| {
+            usage
+                .input_tokens
+                .zip(usage.output_tokens)
+                .map(|(input, output)| input + output)
+        });
+        if let Some(tokens) = tokens {
+            let mut window = self.window.lock().unwrap();
+            if let Some(request) = window
+                .requests
+                .iter_mut()
+                .find(|(_, id, _)| *id == admission.id)
+            {
+                request.2 = tokens.max(0) as u64;
+            }
+        }
+    }
+}
+
+/// A provider decorator that keeps requests within client side rate limits
+///
+/// Decorated providers with the same limiter, e.g. from `RateLimiter::shared`,
+/// share its budget, which keeps several sessions on one API key from
+/// running into the provider's own limits.
+pub struct RateLimitedProvider {
+    inner: Box<dyn Provider>,
+    limiter: Arc<RateLimiter>,
+}
+
+impl RateLimitedProvider {
+    pub fn new(inner: Box<dyn Provider>, limiter: Arc<RateLimiter>) -> Self {
+        Self { inner, limiter }
+    }
+
+    async fn admit(&self, system: &str, messages: &[Message], tools: &[Tool]) -> Admission {
+        let tokens = if self.limiter.counts_tokens() {
+            self.inner.count_request_tokens(system, messages, tools) as u64
+        } else {
+            0
+        };
+        self.limiter.admit(tokens).await
+    }
+}
+
+#[async_trait]
+impl Provider for RateLimitedProvider {
+    fn metadata() -> ProviderMetadata {
+        ProviderMetadata::empty()
+    }
+
+    fn instance_metadata(&self) -> ProviderMetadata {
+        self.inner.instance_metadata()
+    }
+
+    fn get_model_config(&self) -> ModelConfig {
+        self.inner.get_model_config()
+    }
+
+    fn format_system_prompt(&self, raw: &str) -> String {
+        self.inner.format_system_prompt(raw)
+    }
+
+    fn request_body(
+        &self,
+        system: &str,
+        messages: &[Message],
+        tools: &[Tool],
+    ) -> Result<serde_json::Value, ProviderError> {
+        self.inner.request_body(system, messages, tools)
+    }
+
+    fn count_request_tokens(&self, system: &str, messages: &[Message], tools: &[Tool]) -> usize {
+        self.inner.count_request_tokens(system, messages, tools)
+    }
+
+    async fn warmup(&self) -> Result<(), ProviderError> {
+        self.inner.warmup().await
+    }
+
+    async fn flush(&self) -> Result<(), ProviderError> {
+        self.inner.flush().await
+    }
+
+    async fn rate_limits(&self) -> Result<RateLimits, ProviderError> {
+        self.inner.rate_limits().await
+    }
+
+    async fn fetch_supported_models(&self) -> Result<Vec<String>, ProviderError> {
+        self.inner.fetch_supported_models().await
+    }
+
+    async fn embed(&self, inputs: &[String]) -> Result<Vec<Vec<f32>>, ProviderError> {
+        self.inner.embed(inputs).await
+    }
+
+    async fn complete(
+        &self,
+        system: &str,
+        messages: &[Message],
+        tools: &[Tool],
+    ) -> Result<(Message, ProviderUsage), ProviderError> {
+        let admission = self.admit(system, messages, tools).await;
+        let result = self.inner.complete(system, messages, tools).await;
+        let usage = match &result {
+            Ok((_, usage)) => Some(&usage.usage),
+            Err(error) => error.partial_usage(),
+        };
+        self.limiter.settle(&admission, usage);
+        result
+    }
+
+    async fn complete_in_thread(
+        &self,
+        system: &str,
+        messages: &[Message],
+        tools: &[Tool],
+        thread: Option<ThreadId>,
+    ) -> Result<(Message, ProviderUsage, Option<ThreadId>), ProviderError> {
+        let admission = self.admit(system, messages, tools).await;
+        let result = self
+            .inner
+            .complete_in_thread(system, messages, tools, thread)
+            .await;
+        let usage = match &result {
+            Ok((_, usage, _)) => Some(&usage.usage),
+            Err(error) => error.partial_usage(),
+        };
+        self.limiter.settle(&admission, usage);
+        result
+    }
+
+    async fn stream(
+        &self,
+        system: &str,
+        messages: &[Message],
+        tools: &[Tool],
+    ) -> Result<MessageStream, ProviderError> {
+        let admission = self.admit(system, messages, tools).await;
+        let mut stream = match self.inner.stream(system, messages, tools).await {
+            Ok(stream) => stream,
+            Err(error) => {
+                self.limiter.settle(&admission, error.partial_usage());
+                return Err(error);
+            }
+        };
+
+        let limiter = self.limiter.clone();
+        Ok(Box::pin(async_stream::try_stream! {
+            // Keep the concurrency slot until the stream is finished or dropped
+            let admission = admission;
+            while let Some(delta) = stream.next().await {
+                let delta = match delta {
+                    Ok(delta) => delta,
+                    Err(error) => {
+                        limiter.settle(&admission, error.partial_usage());
+                        Err(error)?
+                    }
+                };
+                if let MessageDelta::Usage(usage) = &delta {
+                    limiter.settle(&admission, Some(&usage.usage));
+                }
+                yield delta;
+            }
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::providers::testing::{Seen, TestProvider};
+
+    /// Answers with 500 tokens after a second
+    fn limited(config: RateLimitConfig) -> (RateLimitedProvider, Arc<Seen>) {
+        let inner = TestProvider::new("counting")
+            .with_delay(Duration::from_secs(1))
+            .with_usage("counting", Usage::new(Some(400), Some(100), Some(500)));
+        let seen = inner.seen();
+        let provider =
+            RateLimitedProvider::new(Box::new(inner), Arc::new(RateLimiter::new(config)));
+        (provider, seen)
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_requests_per_minute() {
+        let (provider, _) = limited(RateLimitConfig {
+            requests_per_minute: Some(2),
+            ..Default::default()
+        });
+        let start = Instant::now();
+        for _ in 0..2 {
+            provider.complete("", &[], &[]).await.unwrap();
+        }
+        assert!(start.elapsed() < Duration::from_secs(5));
+        // The third waits for the first to leave the window
+        provider.complete("", &[], &[]).await.unwrap();
+        assert!(start.elapsed() >= WINDOW);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_tokens_per_minute_use_reported_usage() {
+        let (provider, _) = limited(RateLimitConfig {
+            tokens_per_minute: Some(1000),
+            ..Default::default()
+        });
+        let start = Instant::now();
+        for _ in 0..2 {
+            provider.complete("", &[], &[]).await.unwrap();
+        }
+        assert!(start.elapsed() < Duration::from_secs(5));
+        // Two responses of 500 tokens used up the minute
+        provider.complete("", &[], &[]).await.unwrap();
+        assert!(start.elapsed() >= WINDOW);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_max_concurrent() {
+        let (provider, seen) = limited(RateLimitConfig {
+            max_concurrent: Some(2),
+            ..Default::default()
+        });
+        let calls = (0..5).map(|_| provider.complete("", &[], &[]));
+        let results = futures::future::join_all(calls).await;
+        assert!(results.iter().all(Result::is_ok));
+        assert_eq!(seen.most_concurrent(), 2);
+    }
+
+    #[test]
+    fn test_shared_by_name() {
+        let config = RateLimitConfig {
+            requests_per_minute: Some(10),
+            ..Default::default()
+        };
+        let first = RateLimiter::shared("rate-limit-test", config);
+        let second = RateLimiter::shared("rate-limit-test", RateLimitConfig::default());
+        assert!(Arc::ptr_eq(&first, &second));
+        assert_eq!(second.config(), config);
+    }
+}
@@ -0,0 +1,354 @@
+use anyhow::{Context, Result};
+use async_trait::async_trait;
+use futures::StreamExt;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::collections::{HashMap, VecDeque};
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+
+use super::base::{MessageStream, Provider, ProviderMetadata, ProviderUsage, RateLimits};
+use super::errors::ProviderError;
+use super::streaming::StreamCollector;
+use crate::message::Message;
+use crate::model::ModelConfig;
+use mcp_core::tool::Tool;
+
+/// A request and the response it got, one per line in a recording
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Exchange {
+    pub system: String,
+    pub messages: Vec<Message>,
+    pub tools: Vec<Tool>,
+    pub response: Message,
+    pub usage: ProviderUsage,
+}
+
+impl Exchange {
+    fn key(&self) -> String {
+        request_key(&self.system, &self.messages, &self.tools)
+    }
+}
+
+/// Identifies a request by what the model sees
+///
+/// Message IDs and timestamps differ on every run, so only the roles and the
+/// content are compared.
+fn request_key(system: &str, messages: &[Message], tools: &[Tool]) -> String {
+    let messages: Vec<Value> = messages
+        .iter()
+        .map(|message| json!({"role": message.role, "content": message.content}))
+        .collect();
+    json!({"system": system, "messages": messages, "tools": tools}).to_string()
+}
+
+/// A provider decorator that appends every completed exchange to a JSON lines file
+///
+/// The file holds the conversation and the response, not the provider's
+/// credentials, so it can be attached to a bug report or checked in as a test
+/// fixture and served again with `ReplayProvider`. Failed requests are not
+/// recorded, and a stream is recorded once it has finished.
+pub struct RecordingProvider {
+    inner: Box<dyn Provider>,
+    file: Arc<Mutex<File>>,
+}
+
+impl RecordingProvider {
+    /// Record to `path`, adding to the exchanges already in it
+    pub fn new(inner: Box<dyn Provider>, path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .with_context(|| format!("Could not open recording {}", path.display()))?;
+        Ok(Self {
+            inner,
+            file: Arc::new(Mutex::new(file)),
+        })
+    }
+
+    /// Writes an exchange once its response is known, shared with streams that outlive the call
+    fn recorder(
+        &self,
+        system: &str,
+        messages: &[Message],
+        tools: &[Tool],
+    ) -> impl FnOnce(&Message, &ProviderUsage) + Send + 'static {
+        let file = self.file.clone();
+        let (system, messages, tools) = (system.to_string(), messages.to_vec(), tools.to_vec());
+        move |response: &Message, usage: &ProviderUsage| {
+            let exchange = Exchange {
+                system,
+                messages,
+                tools,
+                response: response.clone(),
+                usage: usage.clone(),
+            };
+            let result = serde_json::to_string(&exchange)
+                .map_err(std::io::Error::other)
+                .and_then(|line| writeln!(file.lock().unwrap(), "{}", line));
+            if let Err(e) = result {
+                tracing::warn!("Could not record the exchange: {}", e);
+            }
+        }
+    }
+}
+
+#[async_trait]
+impl Provider for RecordingProvider {
+    fn metadata() -> ProviderMetadata {
+        ProviderMetadata::empty()
+    }
+
+    fn instance_metadata(&self) -> ProviderMetadata {
+        self.inner.instance_metadata()
+    }
+
+    fn get_model_config(&self) -> ModelConfig {
+        self.inner.get_model_config()
+    }
+
+    fn format_system_prompt(&self, raw: &str) -> String {
+        self.inner.format_system_prompt(raw)
+    }
+
+    fn request_body(
+        &self,
+        system: &str,
+        messages: &[Message],
+        tools: &[Tool],
+    ) -> Result<Value, ProviderError> {
+        self.inner.request_body(system, messages, tools)
+    }
+
+    fn count_request_tokens(&self, system: &str, messages: &[Message], tools: &[Tool]) -> usize {
+        self.inner.count_request_tokens(system, messages, tools)
+    }
+
+    async fn warmup(&self) -> Result<(), ProviderError> {
+        self.inner.warmup().await
+    }
+
+    async fn flush(&self) -> Result<(), ProviderError> {
+        self.inner.flush().await
+    }
+
+    async fn rate_limits(&self) -> Result<RateLimits, ProviderError> {
+        self.inner.rate_limits().await
+    }
+
+    async fn fetch_supported_models(&self) -> Result<Vec<String>, ProviderError> {
+        self.inner.fetch_supported_models().await
+    }
+
+    async fn embed(&self, inputs: &[String]) -> Result<Vec<Vec<f32>>, ProviderError> {
+        self.inner.embed(inputs).await
+    }
+
+    async fn complete(
+        &self,
+        system: &str,
+        messages: &[Message],
+        tools: &[Tool],
+    ) -> Result<(Message, ProviderUsage), ProviderError> {
+        let record = self.recorder(system, messages, tools);
+        let (message, usage) = self.inner.complete(system, messages, tools).await?;
+        record(&message, &usage);
+        Ok((message, usage))
+    }
+
+    async fn stream(
+        &self,
+        system: &str,
+        messages: &[Message],
+        tools: &[Tool],
+    ) -> Result<MessageStream, ProviderError> {
+        let record = self.recorder(system, messages, tools);
+        let mut stream = self.inner.stream(system, messages, tools).await?;
+        Ok(Box::pin(async_stream::try_stream! {
+            let mut collector = StreamCollector::new(Box::pin(futures::stream::empty()));
+            while let Some(delta) = stream.next().await {
+                let delta = delta?;
+                collector.push(delta.clone());
+                yield delta;
+            }
+            let (message, usage) = collector.finish();
+            record(&message, &usage);
+        }))
+    }
+}
+
+/// Serves completions from a recording made with `RecordingProvider`, without a network
+///
+/// A request is answered with the response recorded for the same system
+/// prompt, messages and tools. A request recorded more than once gets its
+/// responses in the order they were recorded, the last one repeating. A
+/// request that was never recorded fails with `ExecutionError`, so a test
+/// notices when the agent behaves differently than it did while recording.
+pub struct ReplayProvider {
+    model: ModelConfig,
+    responses: Mutex<HashMap<String, VecDeque<Exchange>>>,
+}
+
+impl ReplayProvider {
+    /// Replay the exchanges in the JSON lines file at `path`
+    pub fn from_file(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        let contents = std::fs::read_to_string(path)
+            .with_context(|| format!("Could not read recording {}", path.display()))?;
+        let exchanges = contents
+            .lines()
+            .enumerate()
+            .filter(|(_, line)| !line.trim().is_empty())
+            .map(|(number, line)| {
+                serde_json::from_str(line).with_context(|| {
+                    format!(
+                        "Invalid exchange on line {} of {}",
+                        number + 1,
+                        path.display()
+                    )
+                })
+            })
+            .collect::<Result<Vec<Exchange>>>()?;
+        Ok(Self::new(exchanges))
+    }
+
+    /// Replay these exchanges, the model config is named after the first recorded model
+    pub fn new(exchanges: Vec<Exchange>) -> Self {
+        let model_name = exchanges
+            .first()
+            .map(|exchange| exchange.usage.model.clone())
+            .unwrap_or_else(|| "replay".to_string());
+        let mut responses: HashMap<String, VecDeque<Exchange>> = HashMap::new();
+        for exchange in exchanges {
+            responses
+                .entry(exchange.key())
+                .or_default()
+                .push_back(exchange);
+        }
+        Self {
+            model: ModelConfig::new(model_name),
+            responses: Mutex::new(responses),
+        }
+    }
+}
+
+#[async_trait]
+impl Provider for ReplayProvider {
+    fn metadata() -> ProviderMetadata {
+        ProviderMetadata::empty()
+    }
+
+    fn get_model_config(&self) -> ModelConfig {
+        self.model.clone()
+    }
+
+    async fn complete(
+        &self,
+        system: &str,
+        messages: &[Message],
+        tools: &[Tool],
+    ) -> Result<(Message, ProviderUsage), ProviderError> {
+        let mut responses = self.responses.lock().unwrap();
+        let recorded = responses
+            .get_mut(&request_key(system, messages, tools))
+            .ok_or_else(|| {
+                ProviderError::ExecutionError(format!(
+                    "No recorded response for this request, the last message was: {}",
+                    messages
+                        .last()
+                        .map(|m| m.as_concat_text())
+                        .unwrap_or_default()
+                ))
+            })?;
+        let exchange = if recorded.len() > 1 {
+            recorded.pop_front().unwrap()
+        } else {
+            recorded[0].clone()
+        };
+        Ok((exchange.response, exchange.usage))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::providers::base::Usage;
+    use crate::providers::streaming::collect_message;
+    use crate::providers::testing::TestProvider;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    /// Answers with the number of the call
+    fn counting() -> Box<TestProvider> {
+        let calls = AtomicUsize::new(0);
+        let provider = TestProvider::new("gpt-4o")
+            .with_usage("gpt-4o-2024-08-06", Usage::new(Some(10), Some(2), Some(12)))
+            .with_reply(move |_| {
+                let call = calls.fetch_add(1, Ordering::SeqCst) + 1;
+                Ok(Message::assistant().with_text(format!("answer {}", call)))
+            });
+        Box::new(provider)
+    }
+
+    async fn answer(
+        provider: &ReplayProvider,
+        messages: &[Message],
+        tools: &[Tool],
+    ) -> Result<String, ProviderError> {
+        let (message, usage) = provider.complete("system", messages, tools).await?;
+        assert_eq!(usage.usage.total_tokens, Some(12));
+        Ok(message.as_concat_text())
+    }
+
+    #[tokio::test]
+    async fn test_recorded_exchanges_replay() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("session.jsonl");
+        let tool = Tool::new("read", "Read a file", json!({"type": "object"}));
+        let question = || vec![Message::user().with_text("What is in the file?")];
+
+        let recorder = RecordingProvider::new(counting(), &path).unwrap();
+        recorder
+            .complete("system", &question(), std::slice::from_ref(&tool))
+            .await
+            .unwrap();
+        recorder
+            .complete("system", &question(), std::slice::from_ref(&tool))
+            .await
+            .unwrap();
+        let stream = recorder
+            .stream("system", &[Message::user().with_text("Bye")], &[])
+            .await
+            .unwrap();
+        collect_message(stream).await.unwrap();
+
+        // Fresh messages carry new IDs and timestamps and still match
+        let replay = ReplayProvider::from_file(&path).unwrap();
+        assert_eq!(replay.get_model_config().model_name, "gpt-4o-2024-08-06");
+        let tools = [tool];
+        assert_eq!(
+            answer(&replay, &question(), &tools).await.unwrap(),
+            "answer 1"
+        );
+        assert_eq!(
+            answer(&replay, &question(), &tools).await.unwrap(),
+            "answer 2"
+        );
+        // The last response for a request repeats
+        assert_eq!(
+            answer(&replay, &question(), &tools).await.unwrap(),
+            "answer 2"
+        );
+        let bye = [Message::user().with_text("Bye")];
+        assert_eq!(answer(&replay, &bye, &[]).await.unwrap(), "answer 3");
+
+        // A different request was never recorded
+        assert!(matches!(
+            answer(&replay, &question(), &[]).await,
+            Err(ProviderError::ExecutionError(_))
+        ));
+    }
+}
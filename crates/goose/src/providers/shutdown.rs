@@ -0,0 +1,239 @@
+use async_trait::async_trait;
+use futures::StreamExt;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use tokio::sync::{watch, Notify};
+
+use super::base::{MessageStream, Provider, ProviderMetadata, ProviderUsage, RateLimits};
+use super::errors::ProviderError;
+use crate::message::Message;
+use crate::model::ModelConfig;
+use mcp_core::tool::Tool;
+
+#[derive(Debug)]
+struct ShutdownState {
+    /// Flips to true once shutdown starts, requests watch it to cancel themselves
+    cancel: watch::Sender<bool>,
+    in_flight: AtomicUsize,
+    idle: Notify,
+}
+
+/// Counts a request as in flight until dropped
+struct InFlight(Arc<ShutdownState>);
+
+impl InFlight {
+    fn start(state: &Arc<ShutdownState>) -> Result<Self, ProviderError> {
+        // Register before checking the flag so shutdown can not miss this request
+        state.in_flight.fetch_add(1, Ordering::SeqCst);
+        let guard = InFlight(state.clone());
+        if *state.cancel.borrow() {
+            return Err(shutting_down());
+        }
+        Ok(guard)
+    }
+}
+
+impl Drop for InFlight {
+    fn drop(&mut self) {
+        if self.0.in_flight.fetch_sub(1, Ordering::SeqCst) == 1 {
+            self.0.idle.notify_waiters();
+        }
+    }
+}
+
+fn shutting_down() -> ProviderError {
+    ProviderError::ShuttingDown("the provider is shutting down".to_string())
+}
+
+/// A provider decorator that can cancel every outstanding request for a clean shutdown
+///
+/// After `shutdown` is called new requests fail with `ShuttingDown`, in-flight
+/// completions and streams are cancelled, and the call returns once they have
+/// all been torn down.
+pub struct ShutdownProvider {
+    inner: Box<dyn Provider>,
+    state: Arc<ShutdownState>,
+}
+
+impl ShutdownProvider {
+    pub fn new(inner: Box<dyn Provider>) -> Self {
+        Self {
+            inner,
+            state: Arc::new(ShutdownState {
+                cancel: watch::Sender::new(false),
+                in_flight: AtomicUsize::new(0),
+                idle: Notify::new(),
+            }),
+        }
+    }
+
+    /// Cancel in-flight requests, reject new ones and wait until all have finished
+    ///
+    /// The wrapped provider is then flushed, so buffered log lines are not lost.
+    pub async fn shutdown(&self) {
+        self.state.cancel.send_replace(true);
+        loop {
+            let idle = self.state.idle.notified();
+            if self.state.in_flight.load(Ordering::SeqCst) == 0 {
+                break;
+            }
+            idle.await;
+        }
+        if let Err(e) = self.inner.flush().await {
+            tracing::warn!("Could not flush the provider during shutdown: {}", e);
+        }
+    }
+
+    /// The number of requests currently in flight, including open streams
+    pub fn in_flight(&self) -> usize {
+        self.state.in_flight.load(Ordering::SeqCst)
+    }
+}
+
+#[async_trait]
+impl Provider for ShutdownProvider {
+    fn metadata() -> ProviderMetadata {
+        ProviderMetadata::empty()
+    }
+
+    fn instance_metadata(&self) -> ProviderMetadata {
+        self.inner.instance_metadata()
+    }
+
+    fn get_model_config(&self) -> ModelConfig {
+        self.inner.get_model_config()
+    }
+
+    fn format_system_prompt(&self, raw: &str) -> String {
+        self.inner.format_system_prompt(raw)
+    }
+
+    fn request_body(
+        &self,
+        system: &str,
+        messages: &[Message],
+        tools: &[Tool],
+    ) -> Result<serde_json::Value, ProviderError> {
+        self.inner.request_body(system, messages, tools)
+    }
+
+    fn count_request_tokens(&self, system: &str, messages: &[Message], tools: &[Tool]) -> usize {
+        self.inner.count_request_tokens(system, messages, tools)
+    }
+
+    async fn warmup(&self) -> Result<(), ProviderError> {
+        self.inner.warmup().await
+    }
+
+    async fn flush(&self) -> Result<(), ProviderError> {
+        self.inner.flush().await
+    }
+
+    async fn rate_limits(&self) -> Result<RateLimits, ProviderError> {
+        self.inner.rate_limits().await
+    }
+
+    async fn fetch_supported_models(&self) -> Result<Vec<String>, ProviderError> {
+        self.inner.fetch_supported_models().await
+    }
+
+    async fn embed(&self, inputs: &[String]) -> Result<Vec<Vec<f32>>, ProviderError> {
+        self.inner.embed(inputs).await
+    }
+
+    async fn complete(
+        &self,
+        system: &str,
+        messages: &[Message],
+        tools: &[Tool],
+    ) -> Result<(Message, ProviderUsage), ProviderError> {
+        let _guard = InFlight::start(&self.state)?;
+        let mut cancel = self.state.cancel.subscribe();
+        tokio::select! {
+            result = self.inner.complete(system, messages, tools) => result,
+            _ = cancel.wait_for(|cancelled| *cancelled) => Err(shutting_down()),
+        }
+    }
+
+    async fn stream(
+        &self,
+        system: &str,
+        messages: &[Message],
+        tools: &[Tool],
+    ) -> Result<MessageStream, ProviderError> {
+        let guard = InFlight::start(&self.state)?;
+        let mut cancel = self.state.cancel.subscribe();
+        let mut stream = tokio::select! {
+            result = self.inner.stream(system, messages, tools) => result?,
+            _ = cancel.wait_for(|cancelled| *cancelled) => return Err(shutting_down()),
+        };
+
+        Ok(Box::pin(async_stream::try_stream! {
+            // Keep the request counted until the stream is finished or dropped
+            let _guard = guard;
+            loop {
+                let delta = tokio::select! {
+                    delta = stream.next() => delta,
+                    _ = cancel.wait_for(|cancelled| *cancelled) => Some(Err(shutting_down())),
+                };
+                match delta {
+                    Some(delta) => yield delta?,
+                    None => break,
+                }
+            }
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::providers::testing::TestProvider;
+    use std::time::Duration;
+
+    /// Takes a long time to answer
+    fn slow() -> Box<TestProvider> {
+        Box::new(TestProvider::new("slow").with_delay(Duration::from_secs(60)))
+    }
+
+    #[tokio::test]
+    async fn test_shutdown_cancels_in_flight_requests() {
+        let provider = Arc::new(ShutdownProvider::new(slow()));
+
+        let tasks: Vec<_> = (0..3)
+            .map(|_| {
+                let provider = provider.clone();
+                tokio::spawn(async move { provider.complete("", &[], &[]).await })
+            })
+            .collect();
+        while provider.in_flight() < 3 {
+            tokio::task::yield_now().await;
+        }
+
+        tokio::time::timeout(Duration::from_secs(5), provider.shutdown())
+            .await
+            .expect("shutdown should not wait for the slow requests");
+        assert_eq!(provider.in_flight(), 0);
+
+        for task in tasks {
+            assert!(matches!(
+                task.await.unwrap(),
+                Err(ProviderError::ShuttingDown(_))
+            ));
+        }
+        assert!(matches!(
+            provider.complete("", &[], &[]).await,
+            Err(ProviderError::ShuttingDown(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_shutdown_without_requests_returns() {
+        let provider = ShutdownProvider::new(slow());
+        provider.shutdown().await;
+        assert!(matches!(
+            provider.stream("", &[], &[]).await,
+            Err(ProviderError::ShuttingDown(_))
+        ));
+    }
+}
@@ -0,0 +1,1658 @@
+use futures::stream::BoxStream;
+use futures::{Stream, StreamExt};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::future::Future;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::{mpsc, oneshot, watch};
+
+use super::base::{message_stream, MessageDelta, MessageStream, ProviderUsage, StreamEvent, Usage};
+use super::errors::ProviderError;
+use super::formats::anthropic::StreamAccumulator as AnthropicStreamAccumulator;
+use super::formats::openai::StreamAccumulator;
+use super::partial_json::parse_partial_json;
+use super::utils::error_usage;
+use crate::message::{Message, MessageContent, ToolRequest};
+use crate::model::ModelConfig;
+use crate::token_counter::TokenCounter;
+
+/// A single server-sent event
+#[derive(Debug, Clone, PartialEq)]
+pub struct SseEvent {
+    /// The `event:` field, if the server named the event
+    pub event: Option<String>,
+    /// The `data:` lines of the event, joined with newlines
+    pub data: String,
+}
+
+/// Incrementally assembles server-sent events from individual lines
+#[derive(Debug, Default)]
+struct SseParser {
+    event: Option<String>,
+    data: Vec<String>,
+}
+
+impl SseParser {
+    /// Feed a single line (without its terminator), returning an event once complete
+    fn line(&mut self, line: &str) -> Option<SseEvent> {
+        if line.is_empty() {
+            return self.flush();
+        }
+        if let Some(value) = line.strip_prefix("data:") {
+            self.data.push(value.trim_start().to_string());
+        } else if let Some(value) = line.strip_prefix("event:") {
+            self.event = Some(value.trim_start().to_string());
+        }
+        None
+    }
+
+    fn flush(&mut self) -> Option<SseEvent> {
+        let event = self.event.take();
+        if self.data.is_empty() {
+            return None;
+        }
+        let data = std::mem::take(&mut self.data).join("\n");
+        Some(SseEvent { event, data })
+    }
+}
+
+/// Decode a raw byte stream into server-sent events
+///
+/// Events are separated by a blank line, comment lines (starting with `:`) are
+/// ignored. Errors while reading the body are reported as `StreamDisconnected`.
+pub fn sse_events<S, B, E>(bytes: S) -> impl Stream<Item = Result<SseEvent, ProviderError>>
+where
+    S: Stream<Item = Result<B, E>> + Send + 'static,
+    B: AsRef<[u8]>,
+    E: std::fmt::Display,
+{
+    async_stream::try_stream! {
+        let mut bytes = Box::pin(bytes);
+        let mut buffer = String::new();
+        let mut parser = SseParser::default();
+
+        while let Some(chunk) = bytes.next().await {
+            let chunk = chunk.map_err(|e| ProviderError::StreamDisconnected(e.to_string()))?;
+            buffer.push_str(&String::from_utf8_lossy(chunk.as_ref()));
+
+            while let Some(newline) = buffer.find('\n') {
+                let line: String = buffer.drain(..=newline).collect();
+                if let Some(event) = parser.line(line.trim_end_matches(['\n', '\r'])) {
+                    yield event;
+                }
+            }
+        }
+
+        // Flush a trailing event that was not terminated by a blank line
+        if let Some(event) = parser.line(buffer.trim_end_matches('\r')) {
+            yield event;
+        }
+        if let Some(event) = parser.flush() {
+            yield event;
+        }
+    }
+}
+
+/// Convert a stream of OpenAI-compatible server-sent events into message deltas
+///
+/// The stream ends at the `[DONE]` sentinel; ending without it means the
+/// connection dropped mid-generation and is reported as `StreamDisconnected`.
+/// An `error` event, or a chunk carrying an `error` object, ends the stream with
+/// the error it describes, as `StreamInterrupted` once text was emitted.
+pub fn openai_message_stream<S>(events: S) -> MessageStream
+where
+    S: Stream<Item = Result<SseEvent, ProviderError>> + Send + 'static,
+{
+    Box::pin(async_stream::try_stream! {
+        let mut events = Box::pin(events);
+        let mut accumulator = StreamAccumulator::new();
+        let mut done = false;
+        let mut emitted = String::new();
+
+        while let Some(event) = events.next().await {
+            let event = event?;
+            if event.data == "[DONE]" {
+                done = true;
+                break;
+            }
+
+            let chunk = serde_json::from_str::<Value>(&event.data);
+            if event.event.as_deref() == Some("error")
+                || chunk.as_ref().is_ok_and(|c| c.get("error").is_some_and(|e| !e.is_null()))
+            {
+                // Some backends report the usage so far along with the error
+                let usage = error_usage(chunk.as_ref().ok()).or_else(|| accumulator.usage());
+                Err(interrupted(stream_error(&event.data, chunk.as_ref().ok()), &emitted)
+                    .with_partial_usage(usage))?;
+            }
+            let chunk =
+                chunk.map_err(|e| ProviderError::invalid_response("Invalid stream chunk", e))?;
+            for delta in accumulator.push_chunk(&chunk) {
+                if let MessageDelta::Content(MessageContent::Text(text)) = &delta {
+                    emitted.push_str(&text.text);
+                }
+                yield delta;
+            }
+        }
+
+        if !done {
+            Err(ProviderError::StreamDisconnected(
+                "stream ended before completion".to_string(),
+            ))?;
+        }
+
+        for delta in accumulator.finish() {
+            yield delta;
+        }
+    })
+}
+
+/// Convert a stream of Anthropic server-sent events into message deltas
+///
+/// The stream ends at `message_stop`; ending without it is reported as
+/// `StreamDisconnected`. An `error` event ends the stream with the error it
+/// describes, as `StreamInterrupted` once text was emitted.
+pub fn anthropic_message_stream<S>(events: S) -> MessageStream
+where
+    S: Stream<Item = Result<SseEvent, ProviderError>> + Send + 'static,
+{
+    Box::pin(async_stream::try_stream! {
+        let mut events = Box::pin(events);
+        let mut accumulator = AnthropicStreamAccumulator::new();
+        let mut done = false;
+        let mut emitted = String::new();
+
+        while let Some(event) = events.next().await {
+            let event = event?;
+            let chunk = serde_json::from_str::<Value>(&event.data);
+            if event.event.as_deref() == Some("error")
+                || chunk.as_ref().is_ok_and(|c| c["type"] == "error")
+            {
+                Err(interrupted(stream_error(&event.data, chunk.as_ref().ok()), &emitted)
+                    .with_partial_usage(accumulator.usage()))?;
+            }
+            let chunk =
+                chunk.map_err(|e| ProviderError::invalid_response("Invalid stream event", e))?;
+            if chunk["type"] == "message_stop" {
+                done = true;
+                break;
+            }
+            for delta in accumulator.push_event(&chunk) {
+                if let MessageDelta::Content(MessageContent::Text(text)) = &delta {
+                    emitted.push_str(&text.text);
+                }
+                yield delta;
+            }
+        }
+
+        if !done {
+            Err(ProviderError::StreamDisconnected(
+                "stream ended before completion".to_string(),
+            ))?;
+        }
+
+        for delta in accumulator.finish() {
+            yield delta;
+        }
+    })
+}
+
+/// Convert a stream of either OpenAI or Anthropic style events into message deltas
+///
+/// Gateways serve Claude models with Anthropic's event format, which is
+/// recognised by its leading `message_start` event.
+pub fn compatible_message_stream<S>(events: S) -> MessageStream
+where
+    S: Stream<Item = Result<SseEvent, ProviderError>> + Send + 'static,
+{
+    Box::pin(async_stream::try_stream! {
+        let mut events = Box::pin(events);
+        let first = events.next().await;
+        let anthropic = matches!(&first, Some(Ok(event)) if is_message_start(event));
+        let events = futures::stream::iter(first).chain(events);
+        let mut deltas = if anthropic {
+            anthropic_message_stream(events)
+        } else {
+            openai_message_stream(events)
+        };
+        while let Some(delta) = deltas.next().await {
+            yield delta?;
+        }
+    })
+}
+
+fn is_message_start(event: &SseEvent) -> bool {
+    event.event.as_deref() == Some("message_start")
+        || serde_json::from_str::<Value>(&event.data).is_ok_and(|e| e["type"] == "message_start")
+}
+
+/// Wrap a stream so an unexpected disconnect reconnects up to `max_reconnects` times
+///
+/// `connect` opens a fresh stream for the same request. Restarting is only safe
+/// when the request is deterministic (`resumable`), since the new stream must
+/// reproduce the text already emitted. That text is de-duplicated, and a new
+/// stream that diverges from it fails rather than produce garbled output.
+///
+/// Once text has been emitted, a terminal error is reported as `StreamInterrupted`
+/// carrying that text so the caller can still show what was received.
+pub fn reconnecting_stream<F, Fut>(
+    connect: F,
+    max_reconnects: usize,
+    resumable: bool,
+) -> MessageStream
+where
+    F: Fn() -> Fut + Send + 'static,
+    Fut: Future<Output = Result<MessageStream, ProviderError>> + Send,
+{
+    Box::pin(async_stream::try_stream! {
+        let mut stream = connect().await?;
+        let mut reconnects = 0;
+        // All text yielded to the caller so far
+        let mut emitted = String::new();
+        // How much of `emitted` the current connection has reproduced
+        let mut replayed = 0;
+
+        loop {
+            match stream.next().await {
+                None => break,
+                Some(Ok(MessageDelta::Content(MessageContent::Text(text)))) => {
+                    let text = text.text;
+                    let pending = &emitted[replayed..];
+
+                    // Skip text the previous connection already delivered
+                    if !pending.is_empty() && pending.starts_with(&text) {
+                        replayed += text.len();
+                        continue;
+                    }
+                    if !text.starts_with(pending) {
+                        Err(interrupted(
+                            ProviderError::StreamDisconnected(
+                                "reconnected stream diverged from the text already received".to_string(),
+                            ),
+                            &emitted,
+                        ))?;
+                    }
+                    let fresh = text[pending.len()..].to_string();
+
+                    emitted.push_str(&fresh);
+                    replayed = emitted.len();
+                    yield MessageDelta::Content(MessageContent::text(fresh));
+                }
+                Some(Ok(delta)) => yield delta,
+                Some(Err(ProviderError::StreamDisconnected(reason))) if reconnects < max_reconnects => {
+                    if !resumable {
+                        Err(interrupted(
+                            ProviderError::StreamDisconnected(format!(
+                                "{}; not reconnecting since the request is not deterministic (temperature must be 0)",
+                                reason
+                            )),
+                            &emitted,
+                        ))?;
+                    }
+                    reconnects += 1;
+                    tracing::warn!(
+                        "Stream disconnected ({}), reconnecting (attempt {}/{})",
+                        reason,
+                        reconnects,
+                        max_reconnects
+                    );
+                    stream = connect().await.map_err(|e| interrupted(e, &emitted))?;
+                    replayed = 0;
+                }
+                Some(Err(e)) => Err(interrupted(e, &emitted))?,
+            }
+        }
+    })
+}
+
+/// Observes a message stream while assembling the complete message
+///
+/// UIs can render each delta as it arrives and still get the final message and
+/// usage for the history. Consecutive text deltas are joined into one text
+/// content, other content is kept in the order it arrived.
+pub struct StreamCollector {
+    stream: MessageStream,
+    message: Message,
+    usage: ProviderUsage,
+}
+
+impl StreamCollector {
+    pub fn new(stream: MessageStream) -> Self {
+        Self {
+            stream,
+            message: Message::assistant(),
+            usage: ProviderUsage::new("Unknown".to_string(), Usage::default()),
+        }
+    }
+
+    /// Drive the stream to completion, calling `on_delta` with every delta
+    pub async fn run<F>(
+        mut self,
+        mut on_delta: F,
+    ) -> Result<(Message, ProviderUsage), ProviderError>
+    where
+        F: FnMut(&MessageDelta),
+    {
+        while let Some(delta) = self.stream.next().await {
+            let delta = delta?;
+            on_delta(&delta);
+            self.push(delta);
+        }
+        Ok((self.message, self.usage))
+    }
+
+    /// Add a delta to the message, for callers that drive the stream themselves
+    pub(crate) fn push(&mut self, delta: MessageDelta) {
+        match delta {
+            MessageDelta::Content(MessageContent::Text(text)) => {
+                if let Some(MessageContent::Text(last)) = self.message.content.last_mut() {
+                    last.text.push_str(&text.text);
+                } else if !text.text.is_empty() {
+                    self.message.content.push(MessageContent::Text(text));
+                }
+            }
+            MessageDelta::Content(MessageContent::Thinking(thinking)) => {
+                if let Some(MessageContent::Thinking(last)) = self.message.content.last_mut() {
+                    last.thinking.push_str(&thinking.thinking);
+                } else if !thinking.thinking.is_empty() {
+                    self.message
+                        .content
+                        .push(MessageContent::Thinking(thinking));
+                }
+            }
+            MessageDelta::Content(content) => self.message.content.push(content),
+            MessageDelta::Usage(usage) => self.usage = usage,
+            MessageDelta::Citations(citations) => self.message.citations.extend(citations),
+            MessageDelta::ToolCallFragment { .. } | MessageDelta::Finish(_) => {}
+        }
+    }
+
+    /// The message and usage assembled from the deltas pushed so far
+    pub(crate) fn finish(self) -> (Message, ProviderUsage) {
+        (self.message, self.usage)
+    }
+}
+
+/// Drain a stream into a single assistant message
+pub async fn collect_message(
+    stream: MessageStream,
+) -> Result<(Message, ProviderUsage), ProviderError> {
+    StreamCollector::new(stream).run(|_| {}).await
+}
+
+/// Apply `finish` to the message of a stream, for clean up that needs the whole text
+///
+/// Markdown and think blocks can span fragments, so the stream is read to the
+/// end first. The finished message is then emitted at once, followed by its
+/// usage and the finish reason.
+pub fn finished_stream(
+    stream: MessageStream,
+    finish: impl FnOnce(Message) -> Message + Send + 'static,
+) -> MessageStream {
+    Box::pin(async_stream::try_stream! {
+        let mut finish_reason = None;
+        let (message, usage) = StreamCollector::new(stream)
+            .run(|delta| {
+                if let MessageDelta::Finish(reason) = delta {
+                    finish_reason = Some(reason.clone());
+                }
+            })
+            .await?;
+        let mut deltas = message_stream(finish(message), usage);
+        while let Some(delta) = deltas.next().await {
+            yield delta?;
+        }
+        if let Some(reason) = finish_reason {
+            yield MessageDelta::Finish(reason);
+        }
+    })
+}
+
+/// A progressively more complete view of structured output arriving over a stream
+#[derive(Debug, Clone, PartialEq)]
+pub enum JsonSnapshot {
+    /// Best effort parse of the JSON received so far
+    Partial(Value),
+    /// The fully received and strictly validated document
+    Complete(Value),
+}
+
+/// Turn a stream of JSON text, e.g. from a `json_schema` response format, into snapshots
+///
+/// A snapshot is emitted whenever the leniently parsed value changes, followed by
+/// a final `Complete` value once the stream ends. Output that is not valid JSON
+/// when complete fails with `RequestFailed`.
+pub fn json_snapshots(
+    mut stream: MessageStream,
+) -> BoxStream<'static, Result<JsonSnapshot, ProviderError>> {
+    Box::pin(async_stream::try_stream! {
+        let mut buffer = String::new();
+        let mut last: Option<Value> = None;
+
+        while let Some(delta) = stream.next().await {
+            if let MessageDelta::Content(MessageContent::Text(text)) = delta? {
+                buffer.push_str(&text.text);
+                if let Some(value) = parse_partial_json(&buffer) {
+                    if last.as_ref() != Some(&value) {
+                        last = Some(value.clone());
+                        yield JsonSnapshot::Partial(value);
+                    }
+                }
+            }
+        }
+
+        let value: Value = serde_json::from_str(&buffer).map_err(|e| {
+            ProviderError::request_failed(format!("Structured output is not valid JSON: {}", e))
+        })?;
+        yield JsonSnapshot::Complete(value);
+    })
+}
+
+/// Fail with `FirstTokenTimeout` unless content starts arriving within `timeout`
+///
+/// The clock starts when the adapter is created, so connecting counts towards
+/// it. Only the first non-empty content counts as a token, usage or empty text
+/// deltas do not. Once content has arrived the stream is no longer timed.
+pub fn first_token_timeout(stream: MessageStream, timeout: Duration) -> MessageStream {
+    let deadline = tokio::time::Instant::now() + timeout;
+    Box::pin(async_stream::try_stream! {
+        let mut stream = stream;
+        let mut started = false;
+        loop {
+            let delta = if started {
+                stream.next().await
+            } else {
+                tokio::time::timeout_at(deadline, stream.next())
+                    .await
+                    .map_err(|_| {
+                        ProviderError::FirstTokenTimeout(format!(
+                            "no content within {}ms",
+                            timeout.as_millis()
+                        ))
+                    })?
+            };
+            let Some(delta) = delta else { break };
+            let delta = delta?;
+            started = started
+                || match &delta {
+                    MessageDelta::Content(MessageContent::Text(text)) => !text.text.is_empty(),
+                    MessageDelta::Content(_) | MessageDelta::ToolCallFragment { .. } => true,
+                    MessageDelta::Usage(_)
+                    | MessageDelta::Citations(_)
+                    | MessageDelta::Finish(_) => false,
+                };
+            yield delta;
+        }
+    })
+}
+
+/// How fast a stream produced its output, see `timed_stream`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct StreamTimings {
+    /// From the start until the first content arrived, `None` if none did
+    pub time_to_first_token: Option<Duration>,
+    /// From the start until the stream ended
+    pub total: Duration,
+    /// The number of content deltas, each usually carries a single token
+    pub tokens: usize,
+    /// The average time between consecutive tokens
+    pub mean_inter_token_latency: Duration,
+    /// Tokens per second after the first one, i.e. generation speed without the wait
+    pub tokens_per_second: f64,
+}
+
+/// Access to the timings of a stream wrapped with `timed_stream`
+#[derive(Debug, Clone, Default)]
+pub struct StreamTimer(Arc<Mutex<Option<StreamTimings>>>);
+
+impl StreamTimer {
+    /// The timings, available once the stream has ended
+    pub fn timings(&self) -> Option<StreamTimings> {
+        *self.0.lock().unwrap()
+    }
+}
+
+/// Record when every token of a stream arrives, summarized as `StreamTimings`
+///
+/// The clock starts when the adapter is created, so connecting counts towards
+/// the time to first token. Unwrapped streams are not timed at all. The
+/// timings are also recorded when the stream ends with an error.
+pub fn timed_stream(stream: MessageStream) -> (MessageStream, StreamTimer) {
+    let timer = StreamTimer::default();
+    let result = timer.0.clone();
+    let started = tokio::time::Instant::now();
+    let stream = Box::pin(async_stream::try_stream! {
+        let mut stream = stream;
+        let mut arrivals = Vec::new();
+        while let Some(delta) = stream.next().await {
+            let token = match &delta {
+                Ok(MessageDelta::Content(MessageContent::Text(text))) => !text.text.is_empty(),
+                Ok(MessageDelta::Content(_)) => true,
+                _ => false,
+            };
+            if token {
+                arrivals.push(started.elapsed());
+            }
+            if delta.is_err() {
+                *result.lock().unwrap() = Some(summarize(&arrivals, started.elapsed()));
+            }
+            yield delta?;
+        }
+        *result.lock().unwrap() = Some(summarize(&arrivals, started.elapsed()));
+    });
+    (stream, timer)
+}
+
+fn summarize(arrivals: &[Duration], total: Duration) -> StreamTimings {
+    let generating = match (arrivals.first(), arrivals.last()) {
+        (Some(first), Some(last)) => *last - *first,
+        _ => Duration::ZERO,
+    };
+    let gaps = arrivals.len().saturating_sub(1);
+    StreamTimings {
+        time_to_first_token: arrivals.first().copied(),
+        total,
+        tokens: arrivals.len(),
+        mean_inter_token_latency: if gaps == 0 {
+            Duration::ZERO
+        } else {
+            generating / gaps as u32
+        },
+        tokens_per_second: if generating.is_zero() {
+            0.0
+        } else {
+            gaps as f64 / generating.as_secs_f64()
+        },
+    }
+}
+
+/// End a stream once its text exceeds `max_tokens`, in case the backend ignores `max_tokens`
+///
+/// The output is measured incrementally with the model's tokenizer. The delta
+/// that would cross the cap is dropped and the stream ends with a usage delta
+/// marked `truncated`, so the caller keeps what was received and knows it is
+/// incomplete. That usage is the one the backend reported so far, when it did,
+/// with the output tokens of the text that was kept.
+pub fn cap_output_tokens(
+    stream: MessageStream,
+    max_tokens: usize,
+    model: &ModelConfig,
+) -> MessageStream {
+    let counter = TokenCounter::new(model.tokenizer_name());
+    let model_name = model.model_name.clone();
+    Box::pin(async_stream::try_stream! {
+        let mut stream = stream;
+        let mut tokens = 0;
+        let mut kept = String::new();
+        let mut reported: Option<ProviderUsage> = None;
+        while let Some(delta) = stream.next().await {
+            let delta = delta?;
+            match &delta {
+                MessageDelta::Content(MessageContent::Text(text)) => {
+                    tokens += counter.count_tokens(&text.text);
+                    if tokens > max_tokens {
+                        tracing::warn!(
+                            "Stream exceeded the cap of {} output tokens, stopping it",
+                            max_tokens
+                        );
+                        // Counted as a whole, the pieces on their own come to more tokens
+                        let output = counter.count_tokens(&kept) as i32;
+                        let mut usage = reported.unwrap_or_else(|| {
+                            ProviderUsage::new(model_name, Usage::default())
+                        });
+                        usage.usage.output_tokens = Some(output);
+                        usage.usage.total_tokens =
+                            usage.usage.input_tokens.map(|input| input + output);
+                        usage.truncated = true;
+                        yield MessageDelta::Usage(usage);
+                        return;
+                    }
+                    kept.push_str(&text.text);
+                }
+                MessageDelta::Usage(usage) => reported = Some(usage.clone()),
+                _ => {}
+            }
+            yield delta;
+        }
+    })
+}
+
+/// Default number of deltas `buffered_stream` holds before the reader pauses
+pub const DEFAULT_STREAM_BUFFER_SIZE: usize = 32;
+
+/// Read a stream on a background task through a bounded buffer
+///
+/// At most `buffer_size` deltas are held for the consumer. Once the buffer is
+/// full the reader stops polling the underlying stream, so a slow consumer
+/// applies backpressure to the connection instead of letting tokens pile up in
+/// memory. Dropping the returned stream stops the reader and closes the connection.
+pub fn buffered_stream(stream: MessageStream, buffer_size: usize) -> MessageStream {
+    let (tx, mut rx) = tokio::sync::mpsc::channel(buffer_size.max(1));
+    tokio::spawn(async move {
+        let mut stream = stream;
+        while let Some(delta) = stream.next().await {
+            if tx.send(delta).await.is_err() {
+                // The consumer went away
+                break;
+            }
+        }
+    });
+
+    Box::pin(async_stream::stream! {
+        while let Some(delta) = rx.recv().await {
+            yield delta;
+        }
+    })
+}
+
+/// Send the deltas of a message stream as typed events, see `Provider::stream_events`
+///
+/// Tool calls are sent in the pieces the backend streamed them in. A backend
+/// that only sends assembled tool requests has each one sent as a single
+/// `ToolCallDelta` with its complete arguments. The finish reason is the one
+/// the backend reported, or else "tool_calls" when the model requested tools
+/// and "stop" otherwise.
+pub async fn send_stream_events(mut stream: MessageStream, events: mpsc::Sender<StreamEvent>) {
+    // Tool calls sent in pieces, and tool requests received assembled
+    let mut streamed_tool_calls = 0;
+    let mut tool_calls = 0;
+    let mut finish_reason = None;
+    while let Some(delta) = stream.next().await {
+        let event = match delta {
+            Ok(MessageDelta::Content(MessageContent::Text(text))) => {
+                if text.text.is_empty() {
+                    continue;
+                }
+                StreamEvent::TextDelta(text.text)
+            }
+            Ok(MessageDelta::ToolCallFragment {
+                index,
+                name,
+                arguments,
+            }) => {
+                streamed_tool_calls = streamed_tool_calls.max(index + 1);
+                StreamEvent::ToolCallDelta {
+                    index,
+                    name,
+                    args: arguments,
+                }
+            }
+            Ok(MessageDelta::Content(MessageContent::ToolRequest(request))) => {
+                tool_calls += 1;
+                if tool_calls <= streamed_tool_calls {
+                    // Already sent in pieces
+                    continue;
+                }
+                let (name, args) = match request.tool_call {
+                    Ok(call) => (Some(call.name), call.arguments.to_string()),
+                    Err(_) => (None, String::new()),
+                };
+                StreamEvent::ToolCallDelta {
+                    index: tool_calls - 1,
+                    name,
+                    args,
+                }
+            }
+            Ok(MessageDelta::Content(content)) => StreamEvent::Content(content),
+            Ok(MessageDelta::Citations(citations)) => StreamEvent::Citations(citations),
+            Ok(MessageDelta::Usage(usage)) => StreamEvent::Usage(usage),
+            Ok(MessageDelta::Finish(reason)) => {
+                finish_reason = Some(reason);
+                continue;
+            }
+            Err(e) => {
+                let _ = events.send(StreamEvent::Error(e)).await;
+                return;
+            }
+        };
+        if events.send(event).await.is_err() {
+            // The receiver went away
+            return;
+        }
+    }
+
+    let finish_reason = finish_reason.unwrap_or_else(|| {
+        let derived = if tool_calls.max(streamed_tool_calls) > 0 {
+            "tool_calls"
+        } else {
+            "stop"
+        };
+        derived.to_string()
+    });
+    let _ = events
+        .send(StreamEvent::Done {
+            finish_reason: Some(finish_reason),
+        })
+        .await;
+}
+
+/// Like `send_stream_events`, but stops once `cancel` is set to true
+///
+/// On cancellation the stream is dropped and the partial output is reported
+/// as a `Usage` event estimated with the tokenizer of `model`, counting
+/// `input_tokens` for the prompt, followed by `StreamEvent::Cancelled`.
+pub async fn send_stream_events_until(
+    stream: MessageStream,
+    events: mpsc::Sender<StreamEvent>,
+    mut cancel: watch::Receiver<bool>,
+    model: &ModelConfig,
+    input_tokens: usize,
+) {
+    let (partial_tx, partial_rx) = oneshot::channel();
+    let stream = cancellable_stream(stream, model, input_tokens, move |partial, usage| {
+        let _ = partial_tx.send((partial, usage));
+    });
+    // Whichever branch loses is dropped along with the stream, which reports the partial output
+    tokio::select! {
+        _ = send_stream_events(stream, events.clone()) => {}
+        Ok(_) = cancel.wait_for(|cancelled| *cancelled) => {}
+    }
+    if let Ok((partial, usage)) = partial_rx.await {
+        let _ = events.send(StreamEvent::Usage(usage)).await;
+        let _ = events.send(StreamEvent::Cancelled { partial }).await;
+    }
+}
+
+/// The text of an event stream, ending with the error if the request failed
+pub fn text_deltas(
+    mut events: mpsc::Receiver<StreamEvent>,
+) -> BoxStream<'static, Result<String, ProviderError>> {
+    Box::pin(async_stream::try_stream! {
+        while let Some(event) = events.recv().await {
+            match event {
+                StreamEvent::TextDelta(text) => yield text,
+                StreamEvent::Error(e) => Err(e)?,
+                StreamEvent::Done { .. } | StreamEvent::Cancelled { .. } => break,
+                StreamEvent::ToolCallDelta { .. }
+                | StreamEvent::Content(_)
+                | StreamEvent::Citations(_)
+                | StreamEvent::Usage(_) => {}
+            }
+        }
+    })
+}
+
+/// The output of a stream received so far, enough to resume it with `Provider::resume_from`
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct StreamCheckpoint {
+    /// The text received so far
+    pub text: String,
+    /// The tool requests received so far, each one complete
+    pub tool_requests: Vec<ToolRequest>,
+    /// Estimated number of tokens in `text`
+    pub output_tokens: usize,
+}
+
+impl StreamCheckpoint {
+    /// The partial assistant message
+    pub fn message(&self) -> Message {
+        let mut message = Message::assistant();
+        if !self.text.is_empty() {
+            message = message.with_text(&self.text);
+        }
+        for request in &self.tool_requests {
+            message = message.with_tool_request(&request.id, request.tool_call.clone());
+        }
+        message
+    }
+}
+
+/// Pass a stream through, handing the accumulated output to `sink` after every delta
+///
+/// The checkpoints start from `start`, so a resumed stream keeps adding to the
+/// checkpoint it was resumed from. The sink is also called when the stream
+/// fails, right before the error is passed on, and can store the checkpoint
+/// wherever it has to survive a disconnect or restart.
+pub fn checkpointed_stream<F>(
+    stream: MessageStream,
+    start: StreamCheckpoint,
+    tokenizer_name: &str,
+    mut sink: F,
+) -> MessageStream
+where
+    F: FnMut(&StreamCheckpoint) + Send + 'static,
+{
+    let counter = TokenCounter::new(tokenizer_name);
+    Box::pin(async_stream::try_stream! {
+        let mut stream = stream;
+        let mut checkpoint = start;
+        while let Some(delta) = stream.next().await {
+            let delta = match delta {
+                Ok(delta) => delta,
+                Err(e) => {
+                    sink(&checkpoint);
+                    Err(e)?
+                }
+            };
+            match &delta {
+                MessageDelta::Content(MessageContent::Text(text)) => {
+                    checkpoint.text.push_str(&text.text);
+                    checkpoint.output_tokens += counter.count_tokens(&text.text);
+                }
+                MessageDelta::Content(MessageContent::ToolRequest(request)) => {
+                    checkpoint.tool_requests.push(request.clone());
+                }
+                _ => {}
+            }
+            sink(&checkpoint);
+            yield delta;
+        }
+    })
+}
+
+/// Calls `on_cancel` with the partial output if dropped before its stream ended
+struct CancelGuard<F: FnOnce(Message, ProviderUsage)> {
+    on_cancel: Option<F>,
+    checkpoint: StreamCheckpoint,
+    model: String,
+    input_tokens: usize,
+}
+
+impl<F: FnOnce(Message, ProviderUsage)> CancelGuard<F> {
+    /// The stream ended on its own, so dropping it is no longer a cancellation
+    fn disarm(&mut self) {
+        self.on_cancel = None;
+    }
+}
+
+impl<F: FnOnce(Message, ProviderUsage)> Drop for CancelGuard<F> {
+    fn drop(&mut self) {
+        if let Some(on_cancel) = self.on_cancel.take() {
+            let output_tokens = self.checkpoint.output_tokens;
+            let usage = Usage::new(
+                Some(self.input_tokens as i32),
+                Some(output_tokens as i32),
+                Some((self.input_tokens + output_tokens) as i32),
+            );
+            on_cancel(
+                self.checkpoint.message(),
+                ProviderUsage::new(std::mem::take(&mut self.model), usage),
+            );
+        }
+    }
+}
+
+/// Pass a stream through, calling `on_cancel` if it is dropped before it ends
+///
+/// `on_cancel` receives the message received so far and a usage estimated
+/// with the tokenizer of `model`, counting `input_tokens` for the prompt. It is
+/// not called once the stream has ended or failed.
+pub fn cancellable_stream<F>(
+    stream: MessageStream,
+    model: &ModelConfig,
+    input_tokens: usize,
+    on_cancel: F,
+) -> MessageStream
+where
+    F: FnOnce(Message, ProviderUsage) + Send + 'static,
+{
+    let counter = TokenCounter::new(model.tokenizer_name());
+    let mut guard = CancelGuard {
+        on_cancel: Some(on_cancel),
+        checkpoint: StreamCheckpoint::default(),
+        model: model.model_name.clone(),
+        input_tokens,
+    };
+    Box::pin(async_stream::try_stream! {
+        let mut stream = stream;
+        while let Some(delta) = stream.next().await {
+            let delta = match delta {
+                Ok(delta) => delta,
+                Err(e) => {
+                    guard.disarm();
+                    Err(e)?
+                }
+            };
+            match &delta {
+                MessageDelta::Content(MessageContent::Text(text)) => {
+                    guard.checkpoint.text.push_str(&text.text);
+                    guard.checkpoint.output_tokens += counter.count_tokens(&text.text);
+                }
+                MessageDelta::Content(MessageContent::ToolRequest(request)) => {
+                    guard.checkpoint.tool_requests.push(request.clone());
+                }
+                _ => {}
+            }
+            yield delta;
+        }
+        guard.disarm();
+    })
+}
+
+/// Drop text at the start of a stream that repeats `previous`
+///
+/// Text is held back while it matches the start of `previous`. Once the
+/// stream has repeated all of it only the rest is passed on, once it diverges
+/// (or ends early) everything held back is passed on after all.
+pub(crate) fn skip_repeated_prefix(stream: MessageStream, previous: String) -> MessageStream {
+    Box::pin(async_stream::try_stream! {
+        let mut stream = stream;
+        let mut held = Some(String::new());
+        while let Some(delta) = stream.next().await {
+            let delta = delta?;
+            let Some(buffer) = held.as_mut() else {
+                yield delta;
+                continue;
+            };
+            if let MessageDelta::Content(MessageContent::Text(text)) = &delta {
+                buffer.push_str(&text.text);
+                if buffer.len() < previous.len() && previous.starts_with(buffer.as_str()) {
+                    continue;
+                }
+                let buffer = held.take().unwrap_or_default();
+                let fresh = buffer.strip_prefix(previous.as_str()).unwrap_or(&buffer);
+                if !fresh.is_empty() {
+                    yield MessageDelta::Content(MessageContent::text(fresh));
+                }
+                continue;
+            }
+            if let Some(buffer) = held.take().filter(|b| !b.is_empty()) {
+                yield MessageDelta::Content(MessageContent::text(buffer));
+            }
+            yield delta;
+        }
+        if let Some(buffer) = held.filter(|b| !b.is_empty()) {
+            yield MessageDelta::Content(MessageContent::text(buffer));
+        }
+    })
+}
+
+/// Map an error reported inside a stream to the matching error variant
+fn stream_error(data: &str, payload: Option<&Value>) -> ProviderError {
+    let Some(payload) = payload else {
+        return ProviderError::request_failed(format!("Stream error: {}", data));
+    };
+    let error = payload.get("error").unwrap_or(payload);
+    let message = error
+        .get("message")
+        .and_then(|m| m.as_str())
+        .map(String::from)
+        .unwrap_or_else(|| error.to_string());
+    let kind = ["code", "type"]
+        .iter()
+        .find_map(|key| error.get(*key).and_then(|k| k.as_str()))
+        .unwrap_or_default();
+    match kind {
+        "context_length_exceeded" | "string_above_max_length" => {
+            ProviderError::ContextLengthExceeded(message)
+        }
+        "rate_limit_exceeded" | "rate_limit_error" => ProviderError::rate_limited(message),
+        "server_error" | "api_error" | "overloaded_error" => ProviderError::ServerError(message),
+        _ => ProviderError::request_failed(format!("Stream error: {}", message)),
+    }
+}
+
+/// Attach the text emitted so far to a terminal stream error
+fn interrupted(error: ProviderError, emitted: &str) -> ProviderError {
+    // Already carries the partial output of an inner stream
+    if emitted.is_empty() || matches!(error, ProviderError::StreamInterrupted { .. }) {
+        return error;
+    }
+    ProviderError::StreamInterrupted {
+        reason: error.to_string(),
+        partial: emitted.to_string(),
+        partial_usage: error.partial_usage().cloned().map(Box::new),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    async fn collect_text(stream: MessageStream) -> Result<String, ProviderError> {
+        let deltas: Vec<_> = stream.collect().await;
+        let mut out = String::new();
+        for delta in deltas {
+            if let MessageDelta::Content(MessageContent::Text(t)) = delta? {
+                out.push_str(&t.text);
+            }
+        }
+        Ok(out)
+    }
+
+    /// Serve one scripted stream per connection attempt, `None` simulates a disconnect
+    fn scripted(
+        attempts: Vec<Vec<Option<&'static str>>>,
+    ) -> (
+        impl Fn() -> futures::future::Ready<Result<MessageStream, ProviderError>>,
+        Arc<AtomicUsize>,
+    ) {
+        let calls = Arc::new(AtomicUsize::new(0));
+        let counter = calls.clone();
+        let connect = move || {
+            let n = counter.fetch_add(1, Ordering::SeqCst);
+            let items: Vec<_> = attempts[n]
+                .iter()
+                .map(|item| match item {
+                    Some(s) => Ok(MessageDelta::Content(MessageContent::text(*s))),
+                    None => Err(ProviderError::StreamDisconnected(
+                        "connection reset".to_string(),
+                    )),
+                })
+                .collect();
+            let stream: MessageStream = Box::pin(futures::stream::iter(items));
+            futures::future::ready(Ok(stream))
+        };
+        (connect, calls)
+    }
+
+    #[tokio::test]
+    async fn test_sse_events() {
+        let body =
+            "event: message\ndata: {\"a\":1}\n\n: comment\ndata: one\ndata: two\n\ndata: [DONE]";
+        // Split mid-line to exercise buffering
+        let chunks: Vec<Result<Vec<u8>, std::io::Error>> = vec![
+            Ok(body.as_bytes()[..10].to_vec()),
+            Ok(body.as_bytes()[10..].to_vec()),
+        ];
+        let events: Vec<_> = sse_events(futures::stream::iter(chunks))
+            .map(|e| e.unwrap())
+            .collect()
+            .await;
+
+        assert_eq!(events.len(), 3);
+        assert_eq!(events[0].event.as_deref(), Some("message"));
+        assert_eq!(events[0].data, "{\"a\":1}");
+        assert_eq!(events[1].data, "one\ntwo");
+        assert_eq!(events[2].data, "[DONE]");
+    }
+
+    #[tokio::test]
+    async fn test_openai_message_stream_requires_done() {
+        let events = vec![Ok(SseEvent {
+            event: None,
+            data: r#"{"choices":[{"delta":{"content":"Hi"}}]}"#.to_string(),
+        })];
+        let result = collect_text(openai_message_stream(futures::stream::iter(events))).await;
+        assert!(matches!(result, Err(ProviderError::StreamDisconnected(_))));
+    }
+
+    /// The events Anthropic sends for a short answer followed by a tool call
+    const ANTHROPIC_EVENTS: &str = "event: message_start\n\
+        data: {\"type\":\"message_start\",\"message\":{\"model\":\"claude-3-5-sonnet\",\"usage\":{\"input_tokens\":25,\"output_tokens\":1}}}\n\n\
+        event: content_block_start\n\
+        data: {\"type\":\"content_block_start\",\"index\":0,\"content_block\":{\"type\":\"text\",\"text\":\"\"}}\n\n\
+        event: ping\n\
+        data: {\"type\":\"ping\"}\n\n\
+        event: content_block_delta\n\
+        data: {\"type\":\"content_block_delta\",\"index\":0,\"delta\":{\"type\":\"text_delta\",\"text\":\"Let me \"}}\n\n\
+        event: content_block_delta\n\
+        data: {\"type\":\"content_block_delta\",\"index\":0,\"delta\":{\"type\":\"text_delta\",\"text\":\"check.\"}}\n\n\
+        event: content_block_stop\n\
+        data: {\"type\":\"content_block_stop\",\"index\":0}\n\n\
+        event: content_block_start\n\
+        data: {\"type\":\"content_block_start\",\"index\":1,\"content_block\":{\"type\":\"tool_use\",\"id\":\"toolu_1\",\"name\":\"get_weather\",\"input\":{}}}\n\n\
+        event: content_block_delta\n\
+        data: {\"type\":\"content_block_delta\",\"index\":1,\"delta\":{\"type\":\"input_json_delta\",\"partial_json\":\"{\\\"city\\\": \"}}\n\n\
+        event: content_block_delta\n\
+        data: {\"type\":\"content_block_delta\",\"index\":1,\"delta\":{\"type\":\"input_json_delta\",\"partial_json\":\"\\\"Paris\\\"}\"}}\n\n\
+        event: content_block_stop\n\
+        data: {\"type\":\"content_block_stop\",\"index\":1}\n\n\
+        event: message_delta\n\
+        data: {\"type\":\"message_delta\",\"delta\":{\"stop_reason\":\"tool_use\"},\"usage\":{\"output_tokens\":42}}\n\n\
+        event: message_stop\n\
+        data: {\"type\":\"message_stop\"}\n\n";
+
+    #[tokio::test]
+    async fn test_anthropic_stream_usage() {
+        let chunks: Vec<Result<Vec<u8>, std::io::Error>> =
+            vec![Ok(ANTHROPIC_EVENTS.as_bytes().to_vec())];
+        let stream = compatible_message_stream(sse_events(futures::stream::iter(chunks)));
+        let (message, usage) = collect_message(stream).await.unwrap();
+
+        assert_eq!(message.as_concat_text(), "Let me check.");
+        let tool_call = message.content[1]
+            .as_tool_request()
+            .unwrap()
+            .tool_call
+            .as_ref()
+            .unwrap();
+        assert_eq!(tool_call.name, "get_weather");
+        assert_eq!(tool_call.arguments, serde_json::json!({"city": "Paris"}));
+
+        assert_eq!(usage.model, "claude-3-5-sonnet");
+        assert_eq!(usage.usage.input_tokens, Some(25));
+        assert_eq!(usage.usage.output_tokens, Some(42));
+        assert_eq!(usage.usage.total_tokens, Some(67));
+
+        // Cut off before message_stop
+        let cut = &ANTHROPIC_EVENTS[..ANTHROPIC_EVENTS.find("event: message_stop").unwrap()];
+        let chunks: Vec<Result<Vec<u8>, std::io::Error>> = vec![Ok(cut.as_bytes().to_vec())];
+        let stream = anthropic_message_stream(sse_events(futures::stream::iter(chunks)));
+        assert!(matches!(
+            collect_message(stream).await,
+            Err(ProviderError::StreamDisconnected(_))
+        ));
+
+        // An error after some output keeps the text and the usage reported so far
+        let failed = format!(
+            "{}event: error\ndata: {{\"type\":\"error\",\"error\":{{\"type\":\"overloaded_error\",\"message\":\"Overloaded\"}}}}\n\n",
+            &ANTHROPIC_EVENTS[..ANTHROPIC_EVENTS.find("event: content_block_stop").unwrap()]
+        );
+        let chunks: Vec<Result<Vec<u8>, std::io::Error>> = vec![Ok(failed.into_bytes())];
+        let stream = anthropic_message_stream(sse_events(futures::stream::iter(chunks)));
+        let error = collect_message(stream).await.unwrap_err();
+        assert!(
+            matches!(&error, ProviderError::StreamInterrupted { partial, .. } if partial == "Let me check.")
+        );
+        assert_eq!(error.partial_usage().unwrap().input_tokens, Some(25));
+    }
+
+    #[tokio::test]
+    async fn test_anthropic_stream_thinking() {
+        let events = "event: message_start\n\
+        data: {\"type\":\"message_start\",\"message\":{\"model\":\"claude-3-7-sonnet\",\"usage\":{\"input_tokens\":10,\"output_tokens\":1}}}\n\n\
+        event: content_block_start\n\
+        data: {\"type\":\"content_block_start\",\"index\":0,\"content_block\":{\"type\":\"thinking\",\"thinking\":\"\"}}\n\n\
+        event: content_block_delta\n\
+        data: {\"type\":\"content_block_delta\",\"index\":0,\"delta\":{\"type\":\"thinking_delta\",\"thinking\":\"Two plus \"}}\n\n\
+        event: content_block_delta\n\
+        data: {\"type\":\"content_block_delta\",\"index\":0,\"delta\":{\"type\":\"thinking_delta\",\"thinking\":\"two is four.\"}}\n\n\
+        event: content_block_delta\n\
+        data: {\"type\":\"content_block_delta\",\"index\":0,\"delta\":{\"type\":\"signature_delta\",\"signature\":\"abc\"}}\n\n\
+        event: content_block_start\n\
+        data: {\"type\":\"content_block_start\",\"index\":1,\"content_block\":{\"type\":\"text\",\"text\":\"\"}}\n\n\
+        event: content_block_delta\n\
+        data: {\"type\":\"content_block_delta\",\"index\":1,\"delta\":{\"type\":\"text_delta\",\"text\":\"4\"}}\n\n\
+        event: message_stop\n\
+        data: {\"type\":\"message_stop\"}\n\n";
+        let chunks: Vec<Result<Vec<u8>, std::io::Error>> = vec![Ok(events.as_bytes().to_vec())];
+        let stream = anthropic_message_stream(sse_events(futures::stream::iter(chunks)));
+        let (message, _) = collect_message(stream).await.unwrap();
+
+        assert_eq!(message.content.len(), 2);
+        assert_eq!(
+            message.content[0].as_thinking(),
+            Some("Two plus two is four.")
+        );
+        assert_eq!(message.as_concat_text(), "4");
+    }
+
+    #[tokio::test]
+    async fn test_stream_ending_in_error_event() {
+        let body = "data: {\"choices\":[{\"delta\":{\"content\":\"Hello\"}}]}\n\n\
+            event: error\n\
+            data: {\"error\":{\"type\":\"overloaded_error\",\"message\":\"Overloaded\"}}\n\n\
+            data: {\"choices\":[{\"delta\":{\"content\":\" never sent\"}}]}\n\n";
+        let chunks: Vec<Result<Vec<u8>, std::io::Error>> = vec![Ok(body.as_bytes().to_vec())];
+        let stream = openai_message_stream(sse_events(futures::stream::iter(chunks)));
+
+        match collect_text(stream).await {
+            Err(ProviderError::StreamInterrupted {
+                reason, partial, ..
+            }) => {
+                assert_eq!(partial, "Hello");
+                assert!(reason.contains("Overloaded"), "{}", reason);
+            }
+            other => panic!("unexpected result: {:?}", other),
+        }
+
+        // Before any text the parsed error surfaces as is, also without an event name
+        let events = vec![Ok(SseEvent {
+            event: None,
+            data: r#"{"error":{"code":"context_length_exceeded","message":"Too long"}}"#
+                .to_string(),
+        })];
+        let result = collect_text(openai_message_stream(futures::stream::iter(events))).await;
+        assert!(matches!(result, Err(ProviderError::ContextLengthExceeded(m)) if m == "Too long"));
+    }
+
+    #[tokio::test]
+    async fn test_tool_only_stream_has_no_empty_text() {
+        let chunks = [
+            r#"{"choices":[{"delta":{"role":"assistant","content":""}}]}"#,
+            r#"{"choices":[{"delta":{"content":"","tool_calls":[{"index":0,"id":"call_1","function":{"name":"example_fn","arguments":"{}"}}]}}]}"#,
+            "[DONE]",
+        ];
+        let events: Vec<_> = chunks
+            .iter()
+            .map(|data| {
+                Ok(SseEvent {
+                    event: None,
+                    data: data.to_string(),
+                })
+            })
+            .collect();
+
+        let stream = openai_message_stream(futures::stream::iter(events));
+        let (message, _) = collect_message(stream).await.unwrap();
+
+        assert_eq!(message.content.len(), 1);
+        assert!(message.content[0].as_tool_request().is_some());
+    }
+
+    #[tokio::test]
+    async fn test_stream_collector_observes_and_assembles() {
+        let deltas = vec![
+            Ok(MessageDelta::Content(MessageContent::text("Hello"))),
+            Ok(MessageDelta::Content(MessageContent::text(", world"))),
+            Ok(MessageDelta::Usage(ProviderUsage::new(
+                "gpt-4o".to_string(),
+                Usage::new(Some(3), Some(2), Some(5)),
+            ))),
+        ];
+        let stream: MessageStream = Box::pin(futures::stream::iter(deltas));
+
+        let mut seen = Vec::new();
+        let (message, usage) = StreamCollector::new(stream)
+            .run(|delta| {
+                if let MessageDelta::Content(MessageContent::Text(text)) = delta {
+                    seen.push(text.text.clone());
+                }
+            })
+            .await
+            .unwrap();
+
+        assert_eq!(seen, vec!["Hello", ", world"]);
+        assert_eq!(message.content.len(), 1);
+        assert_eq!(message.as_concat_text(), "Hello, world");
+        assert_eq!(usage.model, "gpt-4o");
+        assert_eq!(usage.usage.total_tokens, Some(5));
+    }
+
+    fn text_stream(fragments: &[&'static str]) -> MessageStream {
+        let deltas: Vec<_> = fragments
+            .iter()
+            .map(|f| Ok(MessageDelta::Content(MessageContent::text(*f))))
+            .collect();
+        Box::pin(futures::stream::iter(deltas))
+    }
+
+    #[tokio::test]
+    async fn test_json_snapshots_from_fragments() {
+        let stream = text_stream(&[r#"{"name": "Gr"#, r#"ace", "langs": ["#, r#""COBOL""#, "]}"]);
+        let snapshots: Vec<_> = json_snapshots(stream).map(|s| s.unwrap()).collect().await;
+
+        assert_eq!(
+            snapshots,
+            vec![
+                JsonSnapshot::Partial(serde_json::json!({"name": "Gr"})),
+                JsonSnapshot::Partial(serde_json::json!({"name": "Grace", "langs": []})),
+                JsonSnapshot::Partial(serde_json::json!({"name": "Grace", "langs": ["COBOL"]})),
+                JsonSnapshot::Complete(serde_json::json!({"name": "Grace", "langs": ["COBOL"]})),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_json_snapshots_rejects_invalid_final_document() {
+        let results: Vec<_> = json_snapshots(text_stream(&[r#"{"name": "Gr"#]))
+            .collect()
+            .await;
+        assert!(matches!(
+            results.last(),
+            Some(Err(ProviderError::RequestFailed { .. }))
+        ));
+    }
+
+    fn delayed_text(delay: Duration, text: &'static str) -> MessageStream {
+        Box::pin(async_stream::stream! {
+            yield Ok(MessageDelta::Content(MessageContent::text("")));
+            tokio::time::sleep(delay).await;
+            yield Ok(MessageDelta::Content(MessageContent::text(text)));
+            tokio::time::sleep(delay).await;
+            yield Ok(MessageDelta::Content(MessageContent::text(text)));
+        })
+    }
+
+    #[tokio::test]
+    async fn test_first_token_timeout() {
+        let stream = first_token_timeout(
+            delayed_text(Duration::from_millis(200), "late"),
+            Duration::from_millis(50),
+        );
+        assert!(matches!(
+            collect_text(stream).await,
+            Err(ProviderError::FirstTokenTimeout(_))
+        ));
+
+        // Only the wait for the first token is timed
+        let stream = first_token_timeout(
+            delayed_text(Duration::from_millis(30), "on time"),
+            Duration::from_millis(50),
+        );
+        assert_eq!(collect_text(stream).await.unwrap(), "on timeon time");
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_timed_stream() {
+        // The first token after 100ms, then one every 50ms
+        let source: MessageStream = Box::pin(async_stream::stream! {
+            tokio::time::sleep(Duration::from_millis(100)).await;
+            for i in 0..5 {
+                if i > 0 {
+                    tokio::time::sleep(Duration::from_millis(50)).await;
+                }
+                yield Ok(MessageDelta::Content(MessageContent::text("tok")));
+            }
+            yield Ok(MessageDelta::Usage(ProviderUsage::new("gpt-4o".to_string(), Usage::default())));
+        });
+
+        let (stream, timer) = timed_stream(source);
+        assert_eq!(timer.timings(), None);
+        assert_eq!(collect_text(stream).await.unwrap(), "toktoktoktoktok");
+
+        // The clock is paused, so the sleeps take exactly as long as requested
+        let timings = timer.timings().unwrap();
+        assert_eq!(
+            timings.time_to_first_token,
+            Some(Duration::from_millis(100))
+        );
+        assert_eq!(timings.total, Duration::from_millis(300));
+        assert_eq!(timings.tokens, 5);
+        assert_eq!(timings.mean_inter_token_latency, Duration::from_millis(50));
+        assert!((timings.tokens_per_second - 20.0).abs() < 1e-6);
+    }
+
+    #[tokio::test]
+    async fn test_cap_output_tokens_truncates_long_streams() {
+        let model = ModelConfig::new("gpt-4o".to_string());
+        let counter = TokenCounter::new(model.tokenizer_name());
+        let word = " goose";
+        let per_word = counter.count_tokens(word);
+        let words: Vec<&'static str> = vec![word; 100];
+
+        let stream = cap_output_tokens(text_stream(&words), 10 * per_word, &model);
+        let (message, usage) = collect_message(stream).await.unwrap();
+        assert_eq!(message.as_concat_text(), word.repeat(10));
+        assert!(usage.truncated);
+        assert_eq!(
+            usage.usage.output_tokens,
+            Some(counter.count_tokens(&word.repeat(10)) as i32)
+        );
+
+        // The usage the backend reported so far is kept
+        let reported: MessageStream = Box::pin(
+            futures::stream::iter([Ok(MessageDelta::Usage(ProviderUsage::new(
+                "gpt-4o-2024-08-06".to_string(),
+                Usage::new(Some(42), Some(1), Some(43)),
+            )))])
+            .chain(text_stream(&words)),
+        );
+        let (_, usage) = collect_message(cap_output_tokens(reported, 10 * per_word, &model))
+            .await
+            .unwrap();
+        assert!(usage.truncated);
+        assert_eq!(usage.model, "gpt-4o-2024-08-06");
+        assert_eq!(usage.usage.input_tokens, Some(42));
+        let output = counter.count_tokens(&word.repeat(10)) as i32;
+        assert_eq!(usage.usage.output_tokens, Some(output));
+        assert_eq!(usage.usage.total_tokens, Some(42 + output));
+
+        // A stream within the cap passes through untouched
+        let stream = cap_output_tokens(text_stream(&words[..5]), 10 * per_word, &model);
+        let (message, usage) = collect_message(stream).await.unwrap();
+        assert_eq!(message.as_concat_text(), word.repeat(5));
+        assert!(!usage.truncated);
+    }
+
+    #[tokio::test]
+    async fn test_buffered_stream_applies_backpressure() {
+        let produced = Arc::new(AtomicUsize::new(0));
+        let counter = produced.clone();
+        let source: MessageStream = Box::pin(futures::stream::iter(0..100).map(move |i| {
+            counter.fetch_add(1, Ordering::SeqCst);
+            Ok(MessageDelta::Content(MessageContent::text(i.to_string())))
+        }));
+
+        let mut stream = buffered_stream(source, 4);
+        // A consumer that has not read anything yet holds back the reader
+        tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+        assert!(produced.load(Ordering::SeqCst) <= 5);
+
+        let mut received = Vec::new();
+        while let Some(delta) = stream.next().await {
+            if let MessageDelta::Content(MessageContent::Text(t)) = delta.unwrap() {
+                received.push(t.text);
+            }
+            tokio::task::yield_now().await;
+            assert!(produced.load(Ordering::SeqCst) <= received.len() + 5);
+        }
+        let expected: Vec<String> = (0..100).map(|i| i.to_string()).collect();
+        assert_eq!(received, expected);
+    }
+
+    #[tokio::test]
+    async fn test_reconnect_deduplicates_text() {
+        let (connect, calls) = scripted(vec![
+            vec![Some("Hello"), Some(", wor"), None],
+            vec![Some("Hello, "), Some("world"), Some("!")],
+        ]);
+
+        let output = collect_text(reconnecting_stream(connect, 2, true)).await;
+        assert_eq!(output.unwrap(), "Hello, world!");
+        assert_eq!(calls.load(Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test]
+    async fn test_reconnect_not_resumable_fails_cleanly() {
+        let (connect, calls) = scripted(vec![vec![Some("Hello"), None], vec![Some("Hello world")]]);
+
+        let output = collect_text(reconnecting_stream(connect, 2, false)).await;
+        assert!(
+            matches!(output, Err(ProviderError::StreamInterrupted { partial, .. }) if partial == "Hello")
+        );
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn test_reconnect_gives_up_after_max_attempts() {
+        let (connect, calls) = scripted(vec![vec![Some("a"), None], vec![Some("a"), None]]);
+
+        let output = collect_text(reconnecting_stream(connect, 1, true)).await;
+        assert!(
+            matches!(output, Err(ProviderError::StreamInterrupted { partial, .. }) if partial == "a")
+        );
+        assert_eq!(calls.load(Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test]
+    async fn test_reconnect_detects_divergence() {
+        let (connect, _) = scripted(vec![vec![Some("Hello"), None], vec![Some("Goodbye")]]);
+
+        let output = collect_text(reconnecting_stream(connect, 1, true)).await;
+        assert!(
+            matches!(output, Err(ProviderError::StreamInterrupted { partial, .. }) if partial == "Hello")
+        );
+    }
+
+    #[tokio::test]
+    async fn test_error_before_output_is_not_wrapped() {
+        let (connect, _) = scripted(vec![vec![None]]);
+
+        let output = collect_text(reconnecting_stream(connect, 0, true)).await;
+        assert!(matches!(output, Err(ProviderError::StreamDisconnected(_))));
+    }
+
+    #[tokio::test]
+    async fn test_stream_events_for_tool_call() {
+        let chunks = [
+            r#"{"model":"gpt-4o","choices":[{"delta":{"role":"assistant","content":"Let me check"}}]}"#,
+            r#"{"choices":[{"delta":{"tool_calls":[{"index":0,"id":"call_1","function":{"name":"get_weather","arguments":"{\"city\":"}}]}}]}"#,
+            r#"{"choices":[{"delta":{"tool_calls":[{"index":0,"function":{"arguments":"\"Paris\"}"}}]}}]}"#,
+            r#"{"choices":[],"usage":{"prompt_tokens":12,"completion_tokens":8,"total_tokens":20}}"#,
+            "[DONE]",
+        ];
+        let events: Vec<_> = chunks
+            .iter()
+            .map(|data| {
+                Ok(SseEvent {
+                    event: None,
+                    data: data.to_string(),
+                })
+            })
+            .collect();
+
+        let (tx, mut rx) = mpsc::channel(8);
+        send_stream_events(openai_message_stream(futures::stream::iter(events)), tx).await;
+
+        let mut received = Vec::new();
+        while let Some(event) = rx.recv().await {
+            received.push(event);
+        }
+        assert_eq!(received.len(), 5);
+        assert!(matches!(&received[0], StreamEvent::TextDelta(text) if text == "Let me check"));
+        // The tool call is sent in the pieces it arrived in
+        let mut args = String::new();
+        for event in &received[1..3] {
+            match event {
+                StreamEvent::ToolCallDelta {
+                    index,
+                    name,
+                    args: piece,
+                } => {
+                    assert_eq!(*index, 0);
+                    assert_eq!(name.is_some(), args.is_empty());
+                    args.push_str(piece);
+                }
+                other => panic!("Expected a tool call delta, got {:?}", other),
+            }
+        }
+        assert_eq!(
+            serde_json::from_str::<Value>(&args).unwrap(),
+            serde_json::json!({"city": "Paris"})
+        );
+        assert!(
+            matches!(&received[3], StreamEvent::Usage(usage) if usage.usage.total_tokens == Some(20))
+        );
+        assert!(matches!(
+            &received[4],
+            StreamEvent::Done { finish_reason } if finish_reason.as_deref() == Some("tool_calls")
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_stream_events_forward_backend_data() {
+        let citation = crate::message::Citation {
+            url: "https://example.com".to_string(),
+            title: None,
+            start_index: None,
+            end_index: None,
+        };
+        let request = MessageContent::tool_request(
+            "call_1",
+            Ok(mcp_core::ToolCall::new("lookup", serde_json::json!({}))),
+        );
+        let deltas = vec![
+            MessageDelta::Content(MessageContent::thinking("Hmm")),
+            MessageDelta::Content(MessageContent::text("Cut off")),
+            MessageDelta::Content(request),
+            MessageDelta::Citations(vec![citation]),
+            MessageDelta::Finish("length".to_string()),
+        ];
+        let stream: MessageStream = Box::pin(futures::stream::iter(deltas.into_iter().map(Ok)));
+
+        let (tx, mut rx) = mpsc::channel(8);
+        send_stream_events(stream, tx).await;
+        let mut received = Vec::new();
+        while let Some(event) = rx.recv().await {
+            received.push(event);
+        }
+        assert_eq!(received.len(), 5);
+        assert!(matches!(
+            &received[0],
+            StreamEvent::Content(MessageContent::Thinking(_))
+        ));
+        assert!(matches!(&received[1], StreamEvent::TextDelta(text) if text == "Cut off"));
+        // An assembled tool request is sent whole
+        assert!(matches!(
+            &received[2],
+            StreamEvent::ToolCallDelta { index: 0, name: Some(name), args } if name == "lookup" && args == "{}"
+        ));
+        assert!(matches!(&received[3], StreamEvent::Citations(citations) if citations.len() == 1));
+        // The backend's reason, not one made up from the content
+        assert!(matches!(
+            &received[4],
+            StreamEvent::Done { finish_reason } if finish_reason.as_deref() == Some("length")
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_cancelled_stream_reports_partial_usage() {
+        let words = ["The", " answer", " is"];
+        // The model keeps generating until the stream is cancelled
+        let stream: MessageStream = Box::pin(
+            futures::stream::iter(
+                words.map(|word| Ok(MessageDelta::Content(MessageContent::text(word)))),
+            )
+            .chain(futures::stream::pending()),
+        );
+        let model = ModelConfig::new("gpt-4o".to_string());
+        let (cancel_tx, cancel_rx) = watch::channel(false);
+        let (tx, mut rx) = mpsc::channel(8);
+        let sender = tokio::spawn(async move {
+            send_stream_events_until(stream, tx, cancel_rx, &model, 12).await
+        });
+
+        for word in words {
+            assert!(matches!(rx.recv().await, Some(StreamEvent::TextDelta(text)) if text == word));
+        }
+        cancel_tx.send(true).unwrap();
+
+        let counter = TokenCounter::new(ModelConfig::new("gpt-4o".to_string()).tokenizer_name());
+        let output_tokens: usize = words.iter().map(|word| counter.count_tokens(word)).sum();
+        match rx.recv().await {
+            Some(StreamEvent::Usage(usage)) => {
+                assert_eq!(usage.model, "gpt-4o");
+                assert_eq!(usage.usage.input_tokens, Some(12));
+                assert_eq!(usage.usage.output_tokens, Some(output_tokens as i32));
+                assert_eq!(usage.usage.total_tokens, Some(12 + output_tokens as i32));
+            }
+            other => panic!("Expected the estimated usage, got {:?}", other),
+        }
+        match rx.recv().await {
+            Some(StreamEvent::Cancelled { partial }) => {
+                assert_eq!(partial.as_concat_text(), "The answer is");
+            }
+            other => panic!("Expected the partial message, got {:?}", other),
+        }
+        sender.await.unwrap();
+        assert!(rx.recv().await.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_finished_stream_is_not_cancelled() {
+        let stream: MessageStream = Box::pin(futures::stream::iter([Ok(MessageDelta::Content(
+            MessageContent::text("Done"),
+        ))]));
+        let cancelled = Arc::new(Mutex::new(false));
+        let flag = cancelled.clone();
+        let model = ModelConfig::new("gpt-4o".to_string());
+        let stream = cancellable_stream(stream, &model, 0, move |_, _| {
+            *flag.lock().unwrap() = true;
+        });
+        let deltas: Vec<_> = stream.collect().await;
+        assert_eq!(deltas.len(), 1);
+        assert!(!*cancelled.lock().unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_text_deltas_filter_events() {
+        let (tx, rx) = mpsc::channel(8);
+        let deltas = vec![
+            Ok(MessageDelta::Content(MessageContent::text("Hello"))),
+            Ok(MessageDelta::Usage(ProviderUsage::new(
+                "gpt-4o".to_string(),
+                Usage::default(),
+            ))),
+            Ok(MessageDelta::Content(MessageContent::text(", world"))),
+        ];
+        send_stream_events(Box::pin(futures::stream::iter(deltas)), tx).await;
+        let text: Vec<_> = text_deltas(rx).map(|t| t.unwrap()).collect().await;
+        assert_eq!(text, ["Hello", ", world"]);
+
+        let (tx, rx) = mpsc::channel(8);
+        let deltas = vec![
+            Ok(MessageDelta::Content(MessageContent::text("Hel"))),
+            Err(ProviderError::StreamDisconnected(
+                "connection reset".to_string(),
+            )),
+        ];
+        send_stream_events(Box::pin(futures::stream::iter(deltas)), tx).await;
+        let text: Vec<_> = text_deltas(rx).collect().await;
+        assert_eq!(text.len(), 2);
+        assert!(matches!(text[1], Err(ProviderError::StreamDisconnected(_))));
+    }
+}
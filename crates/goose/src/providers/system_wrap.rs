@@ -0,0 +1,151 @@
+use async_trait::async_trait;
+
+use super::base::{MessageStream, Provider, ProviderMetadata, ProviderUsage, RateLimits};
+use super::errors::ProviderError;
+use crate::message::Message;
+use crate::model::ModelConfig;
+use mcp_core::tool::Tool;
+
+/// A provider decorator that surrounds every system prompt with fixed text
+///
+/// The prefix and suffix are concatenated around the caller's system prompt,
+/// even an empty one, before it is passed on. Nothing else about the request
+/// changes, so policy text such as safety rules lives in one place.
+pub struct SystemWrappingProvider {
+    inner: Box<dyn Provider>,
+    prefix: String,
+    suffix: String,
+}
+
+impl SystemWrappingProvider {
+    pub fn new(inner: Box<dyn Provider>) -> Self {
+        Self {
+            inner,
+            prefix: String::new(),
+            suffix: String::new(),
+        }
+    }
+
+    /// Put this text before every system prompt
+    pub fn with_prefix(mut self, prefix: impl Into<String>) -> Self {
+        self.prefix = prefix.into();
+        self
+    }
+
+    /// Put this text after every system prompt
+    pub fn with_suffix(mut self, suffix: impl Into<String>) -> Self {
+        self.suffix = suffix.into();
+        self
+    }
+
+    fn wrap(&self, system: &str) -> String {
+        format!("{}{}{}", self.prefix, system, self.suffix)
+    }
+}
+
+#[async_trait]
+impl Provider for SystemWrappingProvider {
+    fn metadata() -> ProviderMetadata {
+        ProviderMetadata::empty()
+    }
+
+    fn instance_metadata(&self) -> ProviderMetadata {
+        self.inner.instance_metadata()
+    }
+
+    fn get_model_config(&self) -> ModelConfig {
+        self.inner.get_model_config()
+    }
+
+    fn format_system_prompt(&self, raw: &str) -> String {
+        self.inner.format_system_prompt(raw)
+    }
+
+    fn request_body(
+        &self,
+        system: &str,
+        messages: &[Message],
+        tools: &[Tool],
+    ) -> Result<serde_json::Value, ProviderError> {
+        self.inner.request_body(&self.wrap(system), messages, tools)
+    }
+
+    fn count_request_tokens(&self, system: &str, messages: &[Message], tools: &[Tool]) -> usize {
+        self.inner
+            .count_request_tokens(&self.wrap(system), messages, tools)
+    }
+
+    async fn warmup(&self) -> Result<(), ProviderError> {
+        self.inner.warmup().await
+    }
+
+    async fn flush(&self) -> Result<(), ProviderError> {
+        self.inner.flush().await
+    }
+
+    async fn rate_limits(&self) -> Result<RateLimits, ProviderError> {
+        self.inner.rate_limits().await
+    }
+
+    async fn fetch_supported_models(&self) -> Result<Vec<String>, ProviderError> {
+        self.inner.fetch_supported_models().await
+    }
+
+    async fn embed(&self, inputs: &[String]) -> Result<Vec<Vec<f32>>, ProviderError> {
+        self.inner.embed(inputs).await
+    }
+
+    async fn complete(
+        &self,
+        system: &str,
+        messages: &[Message],
+        tools: &[Tool],
+    ) -> Result<(Message, ProviderUsage), ProviderError> {
+        self.inner
+            .complete(&self.wrap(system), messages, tools)
+            .await
+    }
+
+    async fn stream(
+        &self,
+        system: &str,
+        messages: &[Message],
+        tools: &[Tool],
+    ) -> Result<MessageStream, ProviderError> {
+        self.inner.stream(&self.wrap(system), messages, tools).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::providers::testing::TestProvider;
+    use futures::StreamExt;
+
+    #[tokio::test]
+    async fn test_system_prompt_is_wrapped_once() {
+        let inner = TestProvider::new("recording");
+        let seen = inner.seen();
+        let provider = SystemWrappingProvider::new(Box::new(inner))
+            .with_prefix("Follow the safety rules.\n\n")
+            .with_suffix("\n\nNever share credentials.");
+
+        let messages = vec![Message::user().with_text("hi")];
+        provider
+            .complete("You are a helpful assistant.", &messages, &[])
+            .await
+            .unwrap();
+        // The default stream goes through complete, which must not wrap a second time
+        let mut stream = provider.stream("", &messages, &[]).await.unwrap();
+        while stream.next().await.is_some() {}
+
+        let sent: Vec<String> = seen.requests().into_iter().map(|r| r.system).collect();
+        assert_eq!(
+            sent,
+            [
+                "Follow the safety rules.\n\nYou are a helpful assistant.\n\nNever share credentials.",
+                "Follow the safety rules.\n\n\n\nNever share credentials.",
+            ]
+        );
+    }
+}
@@ -0,0 +1,272 @@
+//! A configurable provider for tests
+//!
+//! Tests build the provider they need from `TestProvider` instead of writing
+//! a fake of their own. It answers with "ok" unless told otherwise and keeps
+//! every request it was sent in a `Seen` shared with the test. The tests of
+//! the `Provider` defaults in `base` bring their own, since this one replaces
+//! some of them.
+
+use async_trait::async_trait;
+use serde_json::Value;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use super::base::{Provider, ProviderMetadata, ProviderUsage, ThreadId, Usage};
+use super::errors::ProviderError;
+use crate::message::Message;
+use crate::model::ModelConfig;
+use crate::token_counter::TokenCounter;
+use mcp_core::tool::Tool;
+
+type Reply = dyn Fn(&TestRequest) -> Result<Message, ProviderError> + Send + Sync;
+type Warmup = dyn Fn() -> Result<(), ProviderError> + Send + Sync;
+type Embedding = dyn Fn(&str) -> Vec<f32> + Send + Sync;
+type RequestBody = dyn Fn(&TestRequest) -> Value + Send + Sync;
+
+/// A request sent to a `TestProvider`
+#[derive(Debug, Clone)]
+pub struct TestRequest {
+    pub system: String,
+    pub messages: Vec<Message>,
+    pub tools: Vec<Tool>,
+}
+
+/// What a `TestProvider` was sent, still readable once the provider is boxed
+#[derive(Debug, Default)]
+pub struct Seen {
+    requests: Mutex<Vec<TestRequest>>,
+    running: AtomicUsize,
+    most_running: AtomicUsize,
+}
+
+impl Seen {
+    /// Every request so far, oldest first
+    pub fn requests(&self) -> Vec<TestRequest> {
+        self.requests.lock().unwrap().clone()
+    }
+
+    /// The latest request, panics when there was none
+    pub fn last(&self) -> TestRequest {
+        self.requests
+            .lock()
+            .unwrap()
+            .last()
+            .cloned()
+            .expect("the provider was not sent a request")
+    }
+
+    pub fn calls(&self) -> usize {
+        self.requests.lock().unwrap().len()
+    }
+
+    /// The most requests that were answered at the same time
+    pub fn most_concurrent(&self) -> usize {
+        self.most_running.load(Ordering::SeqCst)
+    }
+}
+
+/// A provider that answers with a fixed text or a reply function
+pub struct TestProvider {
+    model: ModelConfig,
+    reply: Arc<Reply>,
+    usage: ProviderUsage,
+    delay: Option<Duration>,
+    thread: Option<ThreadId>,
+    warmup: Option<Arc<Warmup>>,
+    token_count: Option<fn(&[Message]) -> usize>,
+    embedding: Option<Arc<Embedding>>,
+    request_body: Option<Arc<RequestBody>>,
+    seen: Arc<Seen>,
+}
+
+impl TestProvider {
+    pub fn new(model: &str) -> Self {
+        Self {
+            model: ModelConfig::new(model.to_string()),
+            reply: Arc::new(|_| Ok(Message::assistant().with_text("ok"))),
+            usage: ProviderUsage::new(model.to_string(), Usage::default()),
+            delay: None,
+            thread: None,
+            warmup: None,
+            token_count: None,
+            embedding: None,
+            request_body: None,
+            seen: Arc::new(Seen::default()),
+        }
+    }
+
+    /// Replace the model config, e.g. to set a context limit
+    pub fn with_model_config(mut self, model: ModelConfig) -> Self {
+        self.model = model;
+        self
+    }
+
+    /// Answer every request with `text`
+    pub fn with_text(self, text: &str) -> Self {
+        let text = text.to_string();
+        self.with_reply(move |_| Ok(Message::assistant().with_text(&text)))
+    }
+
+    /// Answer every request with `error`
+    pub fn with_error(self, error: fn() -> ProviderError) -> Self {
+        self.with_reply(move |_| Err(error()))
+    }
+
+    /// Answer with what `reply` makes of the request
+    pub fn with_reply(
+        mut self,
+        reply: impl Fn(&TestRequest) -> Result<Message, ProviderError> + Send + Sync + 'static,
+    ) -> Self {
+        self.reply = Arc::new(reply);
+        self
+    }
+
+    /// Report `usage` of `model` with every answer
+    pub fn with_usage(mut self, model: &str, usage: Usage) -> Self {
+        self.usage = ProviderUsage::new(model.to_string(), usage);
+        self
+    }
+
+    /// Take `delay` to answer, on the tokio clock
+    pub fn with_delay(mut self, delay: Duration) -> Self {
+        self.delay = Some(delay);
+        self
+    }
+
+    /// Continue every conversation in `thread`, like a stateful backend with a single thread
+    pub fn with_thread(mut self, thread: &str) -> Self {
+        self.thread = Some(ThreadId(thread.to_string()));
+        self
+    }
+
+    /// Warm up with `warmup` instead of doing nothing
+    pub fn with_warmup(
+        mut self,
+        warmup: impl Fn() -> Result<(), ProviderError> + Send + Sync + 'static,
+    ) -> Self {
+        self.warmup = Some(Arc::new(warmup));
+        self
+    }
+
+    /// Count the tokens of a request with `count` instead of the tokenizer
+    pub fn with_token_count(mut self, count: fn(&[Message]) -> usize) -> Self {
+        self.token_count = Some(count);
+        self
+    }
+
+    /// Embed every input with `embedding`
+    pub fn with_embedding(
+        mut self,
+        embedding: impl Fn(&str) -> Vec<f32> + Send + Sync + 'static,
+    ) -> Self {
+        self.embedding = Some(Arc::new(embedding));
+        self
+    }
+
+    /// Expose what `body` makes of a request as its HTTP body
+    pub fn with_request_body(
+        mut self,
+        body: impl Fn(&TestRequest) -> Value + Send + Sync + 'static,
+    ) -> Self {
+        self.request_body = Some(Arc::new(body));
+        self
+    }
+
+    /// The requests this provider is sent
+    pub fn seen(&self) -> Arc<Seen> {
+        self.seen.clone()
+    }
+}
+
+#[async_trait]
+impl Provider for TestProvider {
+    fn metadata() -> ProviderMetadata {
+        ProviderMetadata::empty()
+    }
+
+    fn get_model_config(&self) -> ModelConfig {
+        self.model.clone()
+    }
+
+    fn count_request_tokens(&self, system: &str, messages: &[Message], tools: &[Tool]) -> usize {
+        match self.token_count {
+            Some(count) => count(messages),
+            None => {
+                let counter = TokenCounter::new(self.model.tokenizer_name());
+                counter.count_chat_tokens(system, messages, tools)
+            }
+        }
+    }
+
+    fn request_body(
+        &self,
+        system: &str,
+        messages: &[Message],
+        tools: &[Tool],
+    ) -> Result<Value, ProviderError> {
+        let request = TestRequest {
+            system: system.to_string(),
+            messages: messages.to_vec(),
+            tools: tools.to_vec(),
+        };
+        match &self.request_body {
+            Some(body) => Ok(body(&request)),
+            None => Err(ProviderError::NotSupported(
+                "this provider does not expose its request body".to_string(),
+            )),
+        }
+    }
+
+    async fn warmup(&self) -> Result<(), ProviderError> {
+        match &self.warmup {
+            Some(warmup) => warmup(),
+            None => Ok(()),
+        }
+    }
+
+    async fn complete(
+        &self,
+        system: &str,
+        messages: &[Message],
+        tools: &[Tool],
+    ) -> Result<(Message, ProviderUsage), ProviderError> {
+        let request = TestRequest {
+            system: system.to_string(),
+            messages: messages.to_vec(),
+            tools: tools.to_vec(),
+        };
+        self.seen.requests.lock().unwrap().push(request.clone());
+
+        let running = self.seen.running.fetch_add(1, Ordering::SeqCst) + 1;
+        self.seen.most_running.fetch_max(running, Ordering::SeqCst);
+        match self.delay {
+            Some(delay) => tokio::time::sleep(delay).await,
+            None => tokio::task::yield_now().await,
+        }
+        self.seen.running.fetch_sub(1, Ordering::SeqCst);
+
+        let message = (self.reply)(&request)?;
+        Ok((message, self.usage.clone()))
+    }
+
+    async fn complete_in_thread(
+        &self,
+        system: &str,
+        messages: &[Message],
+        tools: &[Tool],
+        _thread: Option<ThreadId>,
+    ) -> Result<(Message, ProviderUsage, Option<ThreadId>), ProviderError> {
+        let (message, usage) = self.complete(system, messages, tools).await?;
+        Ok((message, usage, self.thread.clone()))
+    }
+
+    async fn embed(&self, inputs: &[String]) -> Result<Vec<Vec<f32>>, ProviderError> {
+        match &self.embedding {
+            Some(embedding) => Ok(inputs.iter().map(|input| embedding(input)).collect()),
+            None => Err(ProviderError::NotSupported(
+                "this provider does not support embeddings".to_string(),
+            )),
+        }
+    }
+}
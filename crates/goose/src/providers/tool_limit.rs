@@ -0,0 +1,231 @@
+use async_trait::async_trait;
+use futures::StreamExt;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
+use super::base::{
+    MessageDelta, MessageStream, Provider, ProviderMetadata, ProviderUsage, RateLimits,
+};
+use super::errors::ProviderError;
+use crate::message::Message;
+use crate::model::ModelConfig;
+use mcp_core::tool::Tool;
+
+/// Caps how many times the model may call tools within one conversation
+///
+/// A safety valve against agent loops where the model keeps requesting tools.
+/// Each conversation gets its own `session`, which counts the responses that
+/// requested tools. Once `with_max_tool_iterations` of them were returned, any
+/// further request that offers tools fails with `ToolIterationLimit`, while
+/// requests without tools, e.g. asking for a final answer, still go through.
+/// Without a limit, the default, nothing is enforced.
+pub struct ToolIterationLimiter {
+    inner: Arc<dyn Provider>,
+    max_tool_iterations: Option<usize>,
+}
+
+impl ToolIterationLimiter {
+    pub fn new(inner: Box<dyn Provider>) -> Self {
+        Self {
+            inner: Arc::from(inner),
+            max_tool_iterations: None,
+        }
+    }
+
+    /// Allow at most this many tool calling responses per session, `None` for no limit
+    pub fn with_max_tool_iterations(mut self, max_tool_iterations: Option<usize>) -> Self {
+        self.max_tool_iterations = max_tool_iterations;
+        self
+    }
+
+    /// A provider for a new conversation, with its own tool iteration count
+    pub fn session(&self) -> ToolSession {
+        ToolSession {
+            inner: self.inner.clone(),
+            max_tool_iterations: self.max_tool_iterations,
+            iterations: Arc::new(AtomicUsize::new(0)),
+        }
+    }
+}
+
+/// The provider for a single conversation, see `ToolIterationLimiter`
+pub struct ToolSession {
+    inner: Arc<dyn Provider>,
+    max_tool_iterations: Option<usize>,
+    iterations: Arc<AtomicUsize>,
+}
+
+impl ToolSession {
+    /// How many responses in this conversation requested tools so far
+    pub fn tool_iterations(&self) -> usize {
+        self.iterations.load(Ordering::SeqCst)
+    }
+
+    fn check(&self, tools: &[Tool]) -> Result<(), ProviderError> {
+        match self.max_tool_iterations {
+            Some(max) if !tools.is_empty() && self.tool_iterations() >= max => {
+                Err(ProviderError::ToolIterationLimit(format!(
+                    "the model requested tools {} times, the limit is {}",
+                    self.tool_iterations(),
+                    max
+                )))
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+#[async_trait]
+impl Provider for ToolSession {
+    fn metadata() -> ProviderMetadata {
+        ProviderMetadata::empty()
+    }
+
+    fn instance_metadata(&self) -> ProviderMetadata {
+        self.inner.instance_metadata()
+    }
+
+    fn get_model_config(&self) -> ModelConfig {
+        self.inner.get_model_config()
+    }
+
+    fn format_system_prompt(&self, raw: &str) -> String {
+        self.inner.format_system_prompt(raw)
+    }
+
+    fn request_body(
+        &self,
+        system: &str,
+        messages: &[Message],
+        tools: &[Tool],
+    ) -> Result<serde_json::Value, ProviderError> {
+        self.inner.request_body(system, messages, tools)
+    }
+
+    fn count_request_tokens(&self, system: &str, messages: &[Message], tools: &[Tool]) -> usize {
+        self.inner.count_request_tokens(system, messages, tools)
+    }
+
+    async fn warmup(&self) -> Result<(), ProviderError> {
+        self.inner.warmup().await
+    }
+
+    async fn flush(&self) -> Result<(), ProviderError> {
+        self.inner.flush().await
+    }
+
+    async fn rate_limits(&self) -> Result<RateLimits, ProviderError> {
+        self.inner.rate_limits().await
+    }
+
+    async fn fetch_supported_models(&self) -> Result<Vec<String>, ProviderError> {
+        self.inner.fetch_supported_models().await
+    }
+
+    async fn embed(&self, inputs: &[String]) -> Result<Vec<Vec<f32>>, ProviderError> {
+        self.inner.embed(inputs).await
+    }
+
+    async fn complete(
+        &self,
+        system: &str,
+        messages: &[Message],
+        tools: &[Tool],
+    ) -> Result<(Message, ProviderUsage), ProviderError> {
+        self.check(tools)?;
+        let (message, usage) = self.inner.complete(system, messages, tools).await?;
+        if message.is_tool_call() {
+            self.iterations.fetch_add(1, Ordering::SeqCst);
+        }
+        Ok((message, usage))
+    }
+
+    async fn stream(
+        &self,
+        system: &str,
+        messages: &[Message],
+        tools: &[Tool],
+    ) -> Result<MessageStream, ProviderError> {
+        self.check(tools)?;
+        let mut stream = self.inner.stream(system, messages, tools).await?;
+
+        let iterations = self.iterations.clone();
+        Ok(Box::pin(async_stream::try_stream! {
+            let mut counted = false;
+            while let Some(delta) = stream.next().await {
+                let delta = delta?;
+                if let MessageDelta::Content(content) = &delta {
+                    if content.as_tool_request().is_some() && !counted {
+                        iterations.fetch_add(1, Ordering::SeqCst);
+                        counted = true;
+                    }
+                }
+                yield delta;
+            }
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::providers::testing::TestProvider;
+    use mcp_core::tool::ToolCall;
+    use serde_json::json;
+
+    /// Requests a tool whenever tools are offered
+    fn looping() -> Box<TestProvider> {
+        Box::new(TestProvider::new("looping").with_reply(|request| {
+            match request.tools.first() {
+                Some(tool) => Ok(Message::assistant()
+                    .with_tool_request("call_1", Ok(ToolCall::new(&tool.name, json!({}))))),
+                None => Ok(Message::assistant().with_text("done")),
+            }
+        }))
+    }
+
+    #[tokio::test]
+    async fn test_limit_stops_tool_loops() {
+        let limiter = ToolIterationLimiter::new(looping()).with_max_tool_iterations(Some(2));
+        let tools = vec![Tool::new(
+            "search",
+            "Search the web",
+            json!({"type": "object"}),
+        )];
+        let session = limiter.session();
+
+        session.complete("", &[], &tools).await.unwrap();
+        let mut stream = session.stream("", &[], &tools).await.unwrap();
+        while stream.next().await.is_some() {}
+        assert_eq!(session.tool_iterations(), 2);
+
+        assert!(matches!(
+            session.complete("", &[], &tools).await,
+            Err(ProviderError::ToolIterationLimit(_))
+        ));
+        assert!(matches!(
+            session.stream("", &[], &tools).await,
+            Err(ProviderError::ToolIterationLimit(_))
+        ));
+        // The model can still be asked to wrap up without tools
+        let (message, _) = session.complete("", &[], &[]).await.unwrap();
+        assert_eq!(message.as_concat_text(), "done");
+
+        // Other conversations are counted separately
+        assert!(limiter.session().complete("", &[], &tools).await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_no_limit_by_default() {
+        let session = ToolIterationLimiter::new(looping()).session();
+        let tools = vec![Tool::new(
+            "search",
+            "Search the web",
+            json!({"type": "object"}),
+        )];
+        for _ in 0..10 {
+            session.complete("", &[], &tools).await.unwrap();
+        }
+        assert_eq!(session.tool_iterations(), 10);
+    }
+}
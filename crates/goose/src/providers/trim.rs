@@ -0,0 +1,317 @@
+use async_trait::async_trait;
+use mcp_core::role::Role;
+use std::slice;
+
+use super::base::{MessageStream, Provider, ProviderMetadata, ProviderUsage, RateLimits};
+use super::errors::ProviderError;
+use crate::message::{Message, MessageContent};
+use crate::model::ModelConfig;
+use crate::token_counter::TokenCounter;
+use mcp_core::tool::Tool;
+
+/// `count_chat_tokens` adds these for the primed assistant reply on every call
+const REPLY_PRIMING_TOKENS: usize = 3;
+
+const SUMMARY_PROMPT: &str = "Summarize the conversation so far in a few sentences. \
+Keep facts, decisions and open questions that later messages may depend on.";
+
+/// How `TrimmingProvider` makes room when a conversation outgrows the context window
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TrimmingPolicy {
+    /// Drop the oldest messages one at a time
+    DropOldest,
+    /// Drop the oldest exchanges, a user message together with every reply up to the next one
+    DropOldestPairs,
+    /// Replace the dropped messages with a summary written by the wrapped provider
+    Summarize,
+}
+
+/// A provider decorator that trims old messages until a request fits the context window
+///
+/// The budget is the model's context limit minus `max_tokens` reserved for the
+/// output. The system prompt, the tools and the latest user turn are always
+/// sent; when they alone exceed the budget the request fails with
+/// `ContextLengthExceeded`. Tool responses are never kept without their request.
+pub struct TrimmingProvider {
+    inner: Box<dyn Provider>,
+    policy: TrimmingPolicy,
+}
+
+impl TrimmingProvider {
+    pub fn new(inner: Box<dyn Provider>) -> Self {
+        Self {
+            inner,
+            policy: TrimmingPolicy::DropOldest,
+        }
+    }
+
+    pub fn with_policy(mut self, policy: TrimmingPolicy) -> Self {
+        self.policy = policy;
+        self
+    }
+
+    async fn trim(
+        &self,
+        system: &str,
+        messages: &[Message],
+        tools: &[Tool],
+    ) -> Result<Vec<Message>, ProviderError> {
+        let config = self.inner.get_model_config();
+        let counter = TokenCounter::new(config.tokenizer_name());
+        let reserved = config.max_tokens.unwrap_or(0).max(0) as usize;
+        let budget = config.context_limit().saturating_sub(reserved);
+
+        let fixed = counter.count_chat_tokens(system, &[], tools);
+        let costs: Vec<usize> = messages
+            .iter()
+            .map(|m| counter.count_chat_tokens("", slice::from_ref(m), &[]) - REPLY_PRIMING_TOKENS)
+            .collect();
+
+        let keep_from = latest_user_turn(messages);
+        let minimum = fixed + costs[keep_from..].iter().sum::<usize>();
+        if minimum > budget {
+            return Err(ProviderError::ContextLengthExceeded(format!(
+                "The system prompt and latest user turn need {} tokens but only {} fit in the context window",
+                minimum, budget
+            )));
+        }
+
+        let mut total = fixed + costs.iter().sum::<usize>();
+        let mut start = 0;
+        while total > budget && start < keep_from {
+            let end = self.next_cut(messages, start, keep_from);
+            total -= costs[start..end].iter().sum::<usize>();
+            start = end;
+        }
+        let mut kept = messages[start..].to_vec();
+
+        if self.policy == TrimmingPolicy::Summarize && start > 0 {
+            let summary = self.summarize(&messages[..start]).await?;
+            let summary = format!("Summary of the earlier conversation:\n{}", summary);
+            if total + counter.count_tokens(&summary) <= budget {
+                match kept.first_mut() {
+                    Some(first) if is_user_turn(first) => {
+                        first.content.insert(0, MessageContent::text(summary))
+                    }
+                    _ => kept.insert(0, Message::user().with_text(summary)),
+                }
+            } else {
+                tracing::warn!(
+                    "Dropped the conversation summary, it does not fit the context window"
+                );
+            }
+        }
+
+        if start > 0 {
+            tracing::debug!("Trimmed {} messages to fit the context window", start);
+        }
+        Ok(kept)
+    }
+
+    /// The index after the next block of messages to drop, starting at `start`
+    fn next_cut(&self, messages: &[Message], start: usize, keep_from: usize) -> usize {
+        let mut end = start + 1;
+        match self.policy {
+            TrimmingPolicy::DropOldestPairs => {
+                while end < keep_from && !is_user_turn(&messages[end]) {
+                    end += 1;
+                }
+            }
+            TrimmingPolicy::DropOldest | TrimmingPolicy::Summarize => {
+                while end < keep_from && messages[end].is_tool_response() {
+                    end += 1;
+                }
+            }
+        }
+        end
+    }
+
+    async fn summarize(&self, messages: &[Message]) -> Result<String, ProviderError> {
+        let mut request = messages.to_vec();
+        request.push(Message::user().with_text(SUMMARY_PROMPT));
+        let (summary, _) = self.inner.complete(SUMMARY_PROMPT, &request, &[]).await?;
+        Ok(summary.as_concat_text())
+    }
+}
+
+/// A message the user typed, as opposed to a tool response sent on their behalf
+fn is_user_turn(message: &Message) -> bool {
+    message.role == Role::User && !message.is_tool_response()
+}
+
+/// The index where the latest user turn starts, everything from there on is always kept
+fn latest_user_turn(messages: &[Message]) -> usize {
+    messages.iter().rposition(is_user_turn).unwrap_or(0)
+}
+
+#[async_trait]
+impl Provider for TrimmingProvider {
+    fn metadata() -> ProviderMetadata {
+        ProviderMetadata::empty()
+    }
+
+    fn instance_metadata(&self) -> ProviderMetadata {
+        self.inner.instance_metadata()
+    }
+
+    fn get_model_config(&self) -> ModelConfig {
+        self.inner.get_model_config()
+    }
+
+    fn format_system_prompt(&self, raw: &str) -> String {
+        self.inner.format_system_prompt(raw)
+    }
+
+    fn count_request_tokens(&self, system: &str, messages: &[Message], tools: &[Tool]) -> usize {
+        self.inner.count_request_tokens(system, messages, tools)
+    }
+
+    async fn warmup(&self) -> Result<(), ProviderError> {
+        self.inner.warmup().await
+    }
+
+    async fn flush(&self) -> Result<(), ProviderError> {
+        self.inner.flush().await
+    }
+
+    async fn rate_limits(&self) -> Result<RateLimits, ProviderError> {
+        self.inner.rate_limits().await
+    }
+
+    async fn fetch_supported_models(&self) -> Result<Vec<String>, ProviderError> {
+        self.inner.fetch_supported_models().await
+    }
+
+    async fn embed(&self, inputs: &[String]) -> Result<Vec<Vec<f32>>, ProviderError> {
+        self.inner.embed(inputs).await
+    }
+
+    async fn complete(
+        &self,
+        system: &str,
+        messages: &[Message],
+        tools: &[Tool],
+    ) -> Result<(Message, ProviderUsage), ProviderError> {
+        let messages = self.trim(system, messages, tools).await?;
+        self.inner.complete(system, &messages, tools).await
+    }
+
+    async fn stream(
+        &self,
+        system: &str,
+        messages: &[Message],
+        tools: &[Tool],
+    ) -> Result<MessageStream, ProviderError> {
+        let messages = self.trim(system, messages, tools).await?;
+        self.inner.stream(system, &messages, tools).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::providers::testing::TestProvider;
+    use mcp_core::tool::ToolCall;
+    use serde_json::json;
+
+    /// Answers summary requests with a fixed text, within `context_limit`
+    fn recording(context_limit: usize) -> TestProvider {
+        let model = ModelConfig::new("gpt-4o".to_string())
+            .with_context_limit(Some(context_limit))
+            .with_max_tokens(Some(10));
+        TestProvider::new("gpt-4o")
+            .with_model_config(model)
+            .with_reply(|request| {
+                let reply = match request.system == SUMMARY_PROMPT {
+                    true => "they talked",
+                    false => "ok",
+                };
+                Ok(Message::assistant().with_text(reply))
+            })
+    }
+
+    fn conversation() -> Vec<Message> {
+        vec![
+            Message::user().with_text("first question about the weather today"),
+            Message::assistant()
+                .with_text("first answer, it is sunny and warm with a light breeze from the west all afternoon"),
+            Message::user().with_text("second question about tomorrow"),
+            Message::assistant().with_tool_request(
+                "call_1",
+                Ok(ToolCall::new("forecast", json!({"day": "tomorrow"}))),
+            ),
+            Message::user().with_tool_response("call_1", Ok(vec![])),
+            Message::assistant().with_text("second answer, rain is expected"),
+            Message::user().with_text("latest question"),
+        ]
+    }
+
+    /// Send the conversation with room for the system prompt, the last `keep` messages and `slack`
+    async fn trimmed(policy: TrimmingPolicy, keep: usize, slack: usize) -> Vec<Message> {
+        let messages = conversation();
+        let counter = TokenCounter::new(ModelConfig::new("gpt-4o".to_string()).tokenizer_name());
+        let needed = counter.count_chat_tokens("system", &messages[messages.len() - keep..], &[]);
+        let inner = recording(needed + slack + 10);
+        let seen = inner.seen();
+
+        let provider = TrimmingProvider::new(Box::new(inner)).with_policy(policy);
+        provider.complete("system", &messages, &[]).await.unwrap();
+        // The summary, if any, was requested before the conversation was sent
+        seen.last().messages
+    }
+
+    #[tokio::test]
+    async fn test_fitting_conversation_is_untouched() {
+        assert_eq!(trimmed(TrimmingPolicy::DropOldest, 7, 0).await.len(), 7);
+    }
+
+    #[tokio::test]
+    async fn test_drop_oldest_keeps_tool_pairs() {
+        let sent = trimmed(TrimmingPolicy::DropOldest, 4, 0).await;
+        assert_eq!(sent.len(), 4);
+        assert!(sent[0].is_tool_call());
+        assert_eq!(sent[3].as_concat_text(), "latest question");
+
+        // Cutting through the tool pair drops the orphaned response as well
+        let sent = trimmed(TrimmingPolicy::DropOldest, 3, 0).await;
+        assert_eq!(sent.len(), 2);
+        assert_eq!(sent[0].as_concat_text(), "second answer, rain is expected");
+    }
+
+    #[tokio::test]
+    async fn test_drop_oldest_pairs_drops_whole_exchanges() {
+        let sent = trimmed(TrimmingPolicy::DropOldestPairs, 6, 0).await;
+        assert_eq!(sent.len(), 5);
+        assert_eq!(sent[0].as_concat_text(), "second question about tomorrow");
+
+        let sent = trimmed(TrimmingPolicy::DropOldestPairs, 4, 0).await;
+        assert_eq!(sent.len(), 1);
+        assert_eq!(sent[0].as_concat_text(), "latest question");
+    }
+
+    #[tokio::test]
+    async fn test_summarize_replaces_dropped_messages() {
+        let counter = TokenCounter::new(ModelConfig::new("gpt-4o".to_string()).tokenizer_name());
+        let summary = counter.count_tokens("Summary of the earlier conversation:\nthey talked");
+
+        let sent = trimmed(TrimmingPolicy::Summarize, 5, summary).await;
+        assert_eq!(sent.len(), 5);
+        let first = sent[0].as_concat_text();
+        assert!(first.starts_with("Summary of the earlier conversation:\nthey talked"));
+        assert!(first.ends_with("second question about tomorrow"));
+
+        // Without room for the summary the messages are still dropped
+        let sent = trimmed(TrimmingPolicy::Summarize, 5, 0).await;
+        assert_eq!(sent[0].as_concat_text(), "second question about tomorrow");
+    }
+
+    #[tokio::test]
+    async fn test_minimum_that_does_not_fit_is_rejected() {
+        let messages = conversation();
+        let provider = TrimmingProvider::new(Box::new(recording(12)));
+        assert!(matches!(
+            provider.complete("system", &messages, &[]).await,
+            Err(ProviderError::ContextLengthExceeded(_))
+        ));
+    }
+}
@@ -1,12 +1,24 @@
-use super::base::Usage;
+use super::base::{RateLimits, Usage};
 use anyhow::Result;
 use regex::Regex;
+use reqwest::header::{self, HeaderMap};
 use reqwest::{Response, StatusCode};
 use serde::{Deserialize, Serialize};
 use serde_json::{json, Map, Value};
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::future::Future;
+use std::sync::LazyLock;
+use std::time::Duration;
 
+use crate::message::{AudioContent, Message, MessageContent};
+use crate::model::{ModelConfig, PrefillPolicy};
 use crate::providers::errors::ProviderError;
-use mcp_core::content::ImageContent;
+use crate::providers::formats::openai::{get_usage, WEB_SEARCH_TOOL_NAME};
+use base64::Engine;
+use mcp_core::content::{Content, ImageContent};
+use mcp_core::resource::ResourceContents;
+use mcp_core::tool::Tool;
 
 #[derive(Debug, Copy, Clone, Serialize, Deserialize)]
 pub enum ImageFormat {
@@ -34,18 +46,48 @@ pub fn convert_image(image: &ImageContent, image_format: &ImageFormat) -> Value
     }
 }
 
+/// Read the `x-ratelimit-*` headers OpenAI compatible APIs send with their responses
+///
+/// Returns `None` when the response carries none of them.
+pub fn rate_limits_from_headers(headers: &HeaderMap) -> Option<RateLimits> {
+    let header = |name: &str| {
+        headers
+            .get(name)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.trim().parse::<u32>().ok())
+    };
+    let limits = RateLimits {
+        requests_per_minute: header("x-ratelimit-limit-requests"),
+        tokens_per_minute: header("x-ratelimit-limit-tokens"),
+        remaining_requests: header("x-ratelimit-remaining-requests"),
+        remaining_tokens: header("x-ratelimit-remaining-tokens"),
+    };
+    (limits != RateLimits::default()).then_some(limits)
+}
+
 /// Handle response from OpenAI compatible endpoints
 /// Error codes: https://platform.openai.com/docs/guides/error-codes
 /// Context window exceeded: https://community.openai.com/t/help-needed-tackling-context-length-limits-in-openai-models/617543
 pub async fn handle_response_openai_compat(response: Response) -> Result<Value, ProviderError> {
     let status = response.status();
+    let request_id = request_id(response.headers());
+    let reset = rate_limit_reset(response.headers());
     // Try to parse the response body as JSON (if applicable)
-    let payload: Option<Value> = response.json().await.ok();
+    let body = response.bytes().await?;
+    let parsed = serde_json::from_slice::<Value>(&body);
+    let payload: Option<Value> = parsed.as_ref().ok().cloned();
+    let failed = |message: String| {
+        ProviderError::request_failed(message)
+            .with_status(status.as_u16())
+            .with_code(error_code(payload.as_ref()))
+            .with_request_id(request_id.clone())
+            .with_partial_usage(error_usage(payload.as_ref()))
+    };
 
     match status {
-        StatusCode::OK => payload.ok_or_else( || ProviderError::RequestFailed("Response body is not valid JSON".to_string()) ),
+        StatusCode::OK => parsed.map_err(|e| ProviderError::invalid_response("Response body is not valid JSON", e)),
         StatusCode::UNAUTHORIZED | StatusCode::FORBIDDEN => {
-            Err(ProviderError::Authentication(format!("Authentication failed. Please ensure your API keys are valid and have the required permissions. \
+            Err(ProviderError::authentication(format!("Authentication failed. Please ensure your API keys are valid and have the required permissions. \
                 Status: {}. Response: {:?}", status, payload)))
         }
         StatusCode::BAD_REQUEST => {
@@ -59,19 +101,21 @@ pub async fn handle_response_openai_compat(response: Response) -> Result<Value,
                           .unwrap_or("Unknown error")
                           .to_string();
 
-                if let Some(code) = error.get("code").and_then(|c| c.as_str()) {
-                    if code == "context_length_exceeded" || code == "string_above_max_length" {
-                        return Err(ProviderError::ContextLengthExceeded(message));
-                    }
+                if is_context_length_error(error) {
+                    return Err(ProviderError::ContextLengthExceeded(message));
                 }
             }}
             tracing::debug!(
                 "{}", format!("Provider request failed with status: {}. Payload: {:?}", status, payload)
             );
-            Err(ProviderError::RequestFailed(format!("Request failed with status: {}. Message: {}", status, message)))
+            Err(failed(format!("Request failed with status: {}. Message: {}", status, message)))
+        }
+        // Gateways in front of the API reject an oversized body before the model sees it
+        StatusCode::PAYLOAD_TOO_LARGE => {
+            Err(ProviderError::ContextLengthExceeded(format!("{:?}", payload)))
         }
         StatusCode::TOO_MANY_REQUESTS => {
-            Err(ProviderError::RateLimitExceeded(format!("{:?}", payload)))
+            Err(ProviderError::rate_limited(format!("{:?}", payload)).with_retry_after(reset))
         }
         StatusCode::INTERNAL_SERVER_ERROR | StatusCode::SERVICE_UNAVAILABLE => {
             Err(ProviderError::ServerError(format!("{:?}", payload)))
@@ -80,9 +124,937 @@ pub async fn handle_response_openai_compat(response: Response) -> Result<Value,
             tracing::debug!(
                 "{}", format!("Provider request failed with status: {}. Payload: {:?}", status, payload)
             );
-            Err(ProviderError::RequestFailed(format!("Request failed with status: {}", status)))
+            Err(failed(format!("Request failed with status: {}", status)))
+        }
+    }
+}
+
+/// Attempts `send_with_retry` makes by default, including the first one
+pub const DEFAULT_MAX_ATTEMPTS: u32 = 3;
+
+/// How `send_with_retry` retries a request that failed for a transient reason
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct RetryConfig {
+    /// Attempts in total, including the first one, so 1 disables retries
+    pub max_attempts: u32,
+    /// The wait before the first retry, doubled for every further one
+    pub initial_backoff: Duration,
+    /// The longest wait between two attempts, also for a longer `Retry-After`
+    pub max_backoff: Duration,
+}
+
+impl Default for RetryConfig {
+    fn default() -> Self {
+        Self {
+            max_attempts: DEFAULT_MAX_ATTEMPTS,
+            initial_backoff: Duration::from_secs(1),
+            max_backoff: Duration::from_secs(30),
+        }
+    }
+}
+
+impl RetryConfig {
+    /// The default backoff with `max_attempts` attempts in total
+    pub fn new(max_attempts: u32) -> Self {
+        Self {
+            max_attempts,
+            ..Self::default()
+        }
+    }
+
+    /// Set the wait before the first retry and the longest wait between attempts
+    pub fn with_backoff(mut self, initial: Duration, max: Duration) -> Self {
+        self.initial_backoff = initial;
+        self.max_backoff = max;
+        self
+    }
+
+    /// The wait after the `attempt`th failed attempt, counting from 1
+    fn backoff(&self, attempt: u32) -> Duration {
+        self.initial_backoff
+            .saturating_mul(2u32.saturating_pow(attempt.saturating_sub(1)))
+            .min(self.max_backoff)
+    }
+}
+
+/// Whether a response status is worth retrying: rate limits and server errors
+///
+/// Other 4xx responses fail the same way every time, as does a 501.
+pub fn is_retryable_status(status: StatusCode) -> bool {
+    status == StatusCode::TOO_MANY_REQUESTS
+        || (status.is_server_error() && status != StatusCode::NOT_IMPLEMENTED)
+}
+
+/// Retry only errors where the request never reached the provider, so nothing is billed twice
+fn is_retryable_error(error: &ProviderError) -> bool {
+    match error {
+        ProviderError::ConnectTimeout { .. } => true,
+        ProviderError::Http { source, .. } => source.is_connect(),
+        _ => false,
+    }
+}
+
+/// The wait a `Retry-After` header asks for, given in seconds or as an HTTP date
+pub fn retry_after(headers: &HeaderMap) -> Option<Duration> {
+    let value = headers.get(header::RETRY_AFTER)?.to_str().ok()?.trim();
+    if let Ok(seconds) = value.parse::<u64>() {
+        return Some(Duration::from_secs(seconds));
+    }
+    let date = chrono::DateTime::parse_from_rfc2822(value).ok()?;
+    // A date in the past means the wait is already over
+    Some(
+        (date.with_timezone(&chrono::Utc) - chrono::Utc::now())
+            .to_std()
+            .unwrap_or(Duration::ZERO),
+    )
+}
+
+/// How long a rate limited provider asked to wait, from `Retry-After` or the `x-ratelimit-reset` headers
+///
+/// OpenAI compatible APIs send the reset of each limit as a duration such as
+/// `6m0s` or `20ms`, the longer one is taken. Others send `x-ratelimit-reset`
+/// in seconds or as a Unix timestamp.
+pub fn rate_limit_reset(headers: &HeaderMap) -> Option<Duration> {
+    if let Some(wait) = retry_after(headers) {
+        return Some(wait);
+    }
+    let header = |name: &str| headers.get(name).and_then(|value| value.to_str().ok());
+    let resets = ["x-ratelimit-reset-requests", "x-ratelimit-reset-tokens"]
+        .into_iter()
+        .filter_map(|name| header(name).and_then(parse_reset_duration))
+        .max();
+    if resets.is_some() {
+        return resets;
+    }
+    let reset: f64 = header("x-ratelimit-reset")?.trim().parse().ok()?;
+    let now = chrono::Utc::now().timestamp() as f64;
+    // Past a billion it can only be a timestamp, a wait that long makes no sense
+    let seconds = if reset > 1e9 { reset - now } else { reset };
+    Some(Duration::from_secs_f64(seconds.max(0.0)))
+}
+
+/// A duration like `1h2m3.5s` or `250ms`
+fn parse_reset_duration(value: &str) -> Option<Duration> {
+    let mut total = 0.0;
+    let mut rest = value.trim();
+    if rest.is_empty() {
+        return None;
+    }
+    while !rest.is_empty() {
+        let split = rest
+            .find(|c: char| !c.is_ascii_digit() && c != '.')
+            .unwrap_or(rest.len());
+        let amount: f64 = rest[..split].parse().ok()?;
+        rest = &rest[split..];
+        let unit_end = rest
+            .find(|c: char| c.is_ascii_digit())
+            .unwrap_or(rest.len());
+        let seconds = match &rest[..unit_end] {
+            "h" => 3600.0,
+            "m" => 60.0,
+            "s" | "" => 1.0,
+            "ms" => 0.001,
+            _ => return None,
+        };
+        total += amount * seconds;
+        rest = &rest[unit_end..];
+    }
+    Some(Duration::from_secs_f64(total))
+}
+
+/// Send a request with `send`, retrying rate limits, server errors and failed connections
+///
+/// Waits with exponential backoff between attempts, or as long as a
+/// `Retry-After` header asks, up to `max_backoff`. Once the attempts are used
+/// up the last response or error is returned, so the caller maps a final 429
+/// to `RateLimitExceeded` and a 5xx to `ServerError` as usual. Responses that
+/// can not succeed on a retry, such as other 4xx, are returned right away.
+pub async fn send_with_retry<F, Fut>(
+    retry: &RetryConfig,
+    mut send: F,
+) -> Result<Response, ProviderError>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<Response, ProviderError>>,
+{
+    let mut attempt = 1;
+    loop {
+        let result = send().await;
+        let wait = match &result {
+            Ok(response) if is_retryable_status(response.status()) => {
+                retry_after(response.headers()).unwrap_or_else(|| retry.backoff(attempt))
+            }
+            Err(error) if is_retryable_error(error) => retry.backoff(attempt),
+            _ => return result,
+        };
+        if attempt >= retry.max_attempts {
+            return result;
+        }
+        let wait = wait.min(retry.max_backoff);
+        match &result {
+            Ok(response) => tracing::warn!(
+                attempt,
+                "Request failed with status {}, retrying in {:?}",
+                response.status(),
+                wait
+            ),
+            Err(error) => {
+                tracing::warn!(attempt, "Request failed: {}, retrying in {:?}", error, wait)
+            }
+        }
+        tokio::time::sleep(wait).await;
+        attempt += 1;
+    }
+}
+
+/// The ID the provider gave the request, to quote when reporting a failure
+fn request_id(headers: &HeaderMap) -> Option<String> {
+    ["x-request-id", "request-id"]
+        .iter()
+        .find_map(|name| headers.get(*name)?.to_str().ok())
+        .map(str::to_string)
+}
+
+/// The provider's code for an error, falling back to its type where no code is set
+fn error_code(payload: Option<&Value>) -> Option<String> {
+    let error = payload?.get("error")?;
+    ["code", "type"]
+        .iter()
+        .find_map(|key| error.get(*key)?.as_str())
+        .map(str::to_string)
+}
+
+/// The usage an error body still reports, e.g. the input tokens of a request rejected late
+pub(crate) fn error_usage(payload: Option<&Value>) -> Option<Usage> {
+    payload
+        .filter(|p| p.get("usage").is_some_and(|u| !u.is_null()))
+        .and_then(|p| get_usage(p).ok())
+}
+
+/// Whether the `error` object of a 400 response says the prompt does not fit the context window
+///
+/// OpenAI sets a code, gateways and compatible servers often pass on only the message.
+fn is_context_length_error(error: &Value) -> bool {
+    let code = error.get("code").and_then(|c| c.as_str());
+    if matches!(
+        code,
+        Some("context_length_exceeded" | "string_above_max_length")
+    ) {
+        return true;
+    }
+    let Some(message) = error.get("message").and_then(|m| m.as_str()) else {
+        return false;
+    };
+    let message = message.to_lowercase();
+    [
+        "maximum context length",
+        "context window",
+        "context length",
+        "prompt is too long",
+        "too many tokens",
+    ]
+    .iter()
+    .any(|phrase| message.contains(phrase))
+}
+
+/// Whether an error payload reports that the requested model does not exist
+pub fn is_model_not_found(payload: Option<&Value>) -> bool {
+    let Some(error) = payload.and_then(|p| p.get("error")) else {
+        return false;
+    };
+    if error.get("code").and_then(|c| c.as_str()) == Some("model_not_found") {
+        return true;
+    }
+    error
+        .get("message")
+        .and_then(|m| m.as_str())
+        .is_some_and(|m| m.to_lowercase().contains("model"))
+}
+
+/// Build a `ModelNotFound` error, suggesting the known models closest to `requested`
+pub fn model_not_found(requested: &str, known_models: &[String]) -> ProviderError {
+    let requested_lower = requested.to_lowercase();
+    let max_distance = (requested.len() / 3).max(2);
+    let mut candidates: Vec<(usize, &String)> = known_models
+        .iter()
+        .map(|model| {
+            (
+                edit_distance(&requested_lower, &model.to_lowercase()),
+                model,
+            )
+        })
+        .filter(|(distance, _)| *distance <= max_distance)
+        .collect();
+    candidates.sort();
+
+    ProviderError::ModelNotFound {
+        requested: requested.to_string(),
+        suggestions: candidates
+            .into_iter()
+            .take(3)
+            .map(|(_, model)| model.clone())
+            .collect(),
+    }
+}
+
+/// Levenshtein distance between two strings, counted in characters
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut previous: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.chars().enumerate() {
+        let mut current = vec![i + 1];
+        for (j, cb) in b.iter().enumerate() {
+            let substitution = previous[j] + usize::from(ca != *cb);
+            current.push(substitution.min(previous[j + 1] + 1).min(current[j] + 1));
+        }
+        previous = current;
+    }
+    previous[b.len()]
+}
+
+/// Convert an audio content into an OpenAI `input_audio` content part
+pub fn convert_audio(audio: &AudioContent) -> Value {
+    json!({
+        "type": "input_audio",
+        "input_audio": {
+            "data": audio.data,
+            "format": audio.format,
+        }
+    })
+}
+
+/// Turn a binary tool result into content a model can take without corrupting it
+///
+/// Binary data arrives as a base64 blob resource. Images become image content,
+/// blobs that are actually UTF-8 become their text, and anything else is kept
+/// base64 encoded behind a marker naming its type, instead of being dropped or
+/// lossily converted to text. Other content is borrowed unchanged.
+pub fn convert_binary_content(content: &Content) -> Cow<'_, Content> {
+    let Content::Resource(resource) = content else {
+        return Cow::Borrowed(content);
+    };
+    let ResourceContents::BlobResourceContents {
+        uri,
+        mime_type,
+        blob,
+    } = &resource.resource
+    else {
+        return Cow::Borrowed(content);
+    };
+
+    let mime_type = mime_type.as_deref().unwrap_or("application/octet-stream");
+    if mime_type.starts_with("image/") {
+        return Cow::Owned(Content::image(blob, mime_type));
+    }
+    let decoded = base64::engine::general_purpose::STANDARD.decode(blob);
+    Cow::Owned(match decoded.map(String::from_utf8) {
+        Ok(Ok(text)) => Content::text(text),
+        _ => Content::text(format!(
+            "[Binary tool output {} ({}), base64 encoded]\n{}",
+            uri, mime_type, blob
+        )),
+    })
+}
+
+/// Whether a tool result carries an image, directly or as a binary resource
+fn is_image_output(content: &Content) -> bool {
+    matches!(*convert_binary_content(content), Content::Image(_))
+}
+
+/// The images in a conversation, in messages and in tool results
+pub fn count_images(messages: &[Message]) -> usize {
+    messages
+        .iter()
+        .flat_map(|message| message.content.iter())
+        .map(|content| match content {
+            MessageContent::Image(_) => 1,
+            MessageContent::ToolResponse(response) => match &response.tool_result {
+                Ok(result) => result.iter().filter(|item| is_image_output(item)).count(),
+                Err(_) => 0,
+            },
+            _ => 0,
+        })
+        .sum()
+}
+
+/// Stands in for an image sent to a model without image input
+pub const IMAGE_OMITTED: &str = "[image omitted]";
+/// Stands in for audio sent to a model without audio input
+pub const AUDIO_OMITTED: &str = "[audio omitted]";
+
+/// Reject content or built-in tools the model can not accept before sending the request
+///
+/// With `downconvert_unsupported` such content is replaced by
+/// `downconvert_content` first, so only what it can not convert is rejected.
+pub fn check_content_support(
+    model: &ModelConfig,
+    messages: &[Message],
+    tools: &[Tool],
+) -> Result<(), ProviderError> {
+    let capabilities = model.capabilities();
+    let has_audio = messages
+        .iter()
+        .flat_map(|message| message.content.iter())
+        .any(|content| matches!(content, MessageContent::Audio(_)));
+
+    if has_audio && !capabilities.audio_input {
+        return Err(ProviderError::NotSupported(format!(
+            "Model {} does not accept audio input",
+            model.model_name
+        )));
+    }
+    let has_image_output = messages
+        .iter()
+        .flat_map(|message| message.content.iter())
+        .filter_map(|content| content.as_tool_response())
+        .filter_map(|response| response.tool_result.as_ref().ok())
+        .flatten()
+        .any(is_image_output);
+    if has_image_output && !capabilities.image_input {
+        return Err(ProviderError::NotSupported(format!(
+            "Model {} can not accept binary tool results such as images, use a model with image input",
+            model.model_name
+        )));
+    }
+    let has_image = messages
+        .iter()
+        .flat_map(|message| message.content.iter())
+        .any(|content| matches!(content, MessageContent::Image(_)));
+    if has_image && !capabilities.image_input {
+        return Err(ProviderError::NotSupported(format!(
+            "Model {} does not accept images, use a model with image input",
+            model.model_name
+        )));
+    }
+    if let Some(limit) = model.max_images() {
+        let provided = count_images(messages);
+        if provided > limit {
+            return Err(ProviderError::TooManyImages { limit, provided });
+        }
+    }
+    let has_tool_content = messages
+        .iter()
+        .flat_map(|message| message.content.iter())
+        .any(|content| content.as_tool_request().is_some() || content.as_tool_response().is_some());
+    if (has_tool_content || !tools.is_empty()) && !capabilities.tools {
+        return Err(ProviderError::NotSupported(format!(
+            "Model {} does not support tools",
+            model.model_name
+        )));
+    }
+    if model.audio_output.is_some() && !capabilities.audio_output {
+        return Err(ProviderError::NotSupported(format!(
+            "Model {} can not respond with audio",
+            model.model_name
+        )));
+    }
+    if tools.iter().any(|tool| tool.name == WEB_SEARCH_TOOL_NAME) && !capabilities.web_search {
+        return Err(ProviderError::NotSupported(format!(
+            "Model {} does not have built-in web search",
+            model.model_name
+        )));
+    }
+    Ok(())
+}
+
+/// Replace content the model can not accept with text, so limited models still get a request
+///
+/// Images and audio become placeholders. For models without tools the tool
+/// definitions are dropped and tool calls and results become text summaries,
+/// so the model still sees what was done. Anything the model supports is kept.
+pub fn downconvert_content<'a>(
+    model: &ModelConfig,
+    messages: &[Message],
+    tools: &'a [Tool],
+) -> (Vec<Message>, &'a [Tool]) {
+    let capabilities = model.capabilities();
+    let text = |content: &[Content]| {
+        content
+            .iter()
+            .map(|item| match item {
+                Content::Image(_) => IMAGE_OMITTED.to_string(),
+                item => item.as_text().map(String::from).unwrap_or_default(),
+            })
+            .collect::<Vec<_>>()
+            .join("\n")
+    };
+    let messages = messages
+        .iter()
+        .cloned()
+        .map(|mut message| {
+            for content in message.content.iter_mut() {
+                match content {
+                    MessageContent::Image(_) if !capabilities.image_input => {
+                        *content = MessageContent::text(IMAGE_OMITTED);
+                    }
+                    MessageContent::Audio(_) if !capabilities.audio_input => {
+                        *content = MessageContent::text(AUDIO_OMITTED);
+                    }
+                    MessageContent::ToolRequest(request) if !capabilities.tools => {
+                        *content = MessageContent::text(match &request.tool_call {
+                            Ok(call) => {
+                                format!("[Called tool {} with {}]", call.name, call.arguments)
+                            }
+                            Err(e) => format!("[Tried to call a tool: {}]", e),
+                        });
+                    }
+                    MessageContent::ToolResponse(response) if !capabilities.tools => {
+                        *content = MessageContent::text(match &response.tool_result {
+                            Ok(result) => format!("[Tool result]\n{}", text(result)),
+                            Err(e) => format!("[Tool error]\n{}", e),
+                        });
+                    }
+                    MessageContent::ToolResponse(response) if !capabilities.image_input => {
+                        if let Ok(result) = response.tool_result.as_mut() {
+                            for item in result.iter_mut() {
+                                if is_image_output(item) {
+                                    *item = Content::text(IMAGE_OMITTED);
+                                }
+                            }
+                        }
+                    }
+                    _ => {}
+                }
+            }
+            message
+        })
+        .collect();
+    let tools = if capabilities.tools { tools } else { &[] };
+    (messages, tools)
+}
+
+/// Check every tool response answers exactly one earlier tool request
+///
+/// The APIs reject duplicate ids and orphaned responses with an error that
+/// does not say where they are, this names the message instead. Messages are
+/// numbered from 1.
+pub fn validate_tool_pairing(messages: &[Message]) -> Result<(), ProviderError> {
+    // The message each id was first requested and answered in
+    let mut requested: HashMap<&str, usize> = HashMap::new();
+    let mut answered: HashMap<&str, usize> = HashMap::new();
+    for (i, message) in messages.iter().enumerate() {
+        let number = i + 1;
+        for content in &message.content {
+            let invalid = |problem: String| Err(ProviderError::InvalidConversation(problem));
+            match content {
+                MessageContent::ToolRequest(request) => {
+                    if let Some(first) = requested.insert(&request.id, number) {
+                        return invalid(format!(
+                            "tool call id '{}' in message {} was already used in message {}",
+                            request.id, number, first
+                        ));
+                    }
+                }
+                MessageContent::ToolResponse(response) => {
+                    if !requested.contains_key(response.id.as_str()) {
+                        return invalid(format!(
+                            "the tool response in message {} answers tool call id '{}', which no earlier message requested",
+                            number, response.id
+                        ));
+                    }
+                    if let Some(first) = answered.insert(&response.id, number) {
+                        return invalid(format!(
+                            "tool call id '{}' is answered twice, in message {} and message {}",
+                            response.id, first, number
+                        ));
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Reject parameter combinations the model is known to refuse, before sending the request
+///
+/// The backends answer these with a bare 400, this names the conflicting
+/// parameters instead. The rules follow the model family, see `ModelCapabilities`.
+pub fn validate_params(model: &ModelConfig) -> Result<(), ProviderError> {
+    let capabilities = model.capabilities();
+    let extra = |key: &str| {
+        model
+            .extra_body
+            .as_ref()
+            .and_then(|body| body.get(key))
+            .filter(|value| !value.is_null())
+    };
+    let invalid = |problem: &str| {
+        Err(ProviderError::InvalidRequest(format!(
+            "{} for model {}",
+            problem, model.model_name
+        )))
+    };
+    let has_stop = !model.stop_token_ids.is_empty()
+        || !model.stop_sequences.is_empty()
+        || extra("stop").is_some();
+    let json_mode = model.response_format.is_some()
+        || extra("response_format")
+            .and_then(|format| format["type"].as_str())
+            .is_some_and(|kind| kind == "json_object" || kind == "json_schema");
+
+    if !capabilities.sampling {
+        if model.temperature.is_some() {
+            return invalid("temperature is not supported, remove it");
+        }
+        if model.top_p.is_some() {
+            return invalid("top_p is not supported, remove it");
+        }
+        if model.frequency_penalty.is_some() || model.presence_penalty.is_some() {
+            return invalid("frequency and presence penalties are not supported, remove them");
+        }
+        if has_stop {
+            return invalid("stop sequences are not supported, remove them");
+        }
+    }
+    if !capabilities.temperature_with_top_p && model.temperature.is_some() && model.top_p.is_some()
+    {
+        return invalid("temperature and top_p can not both be set, keep only one");
+    }
+    if has_stop && json_mode {
+        return invalid("stop sequences conflict with a JSON response_format, remove one");
+    }
+    if model.reasoning_effort.is_some() && !capabilities.reasoning_effort {
+        return invalid("reasoning_effort is only supported by reasoning models, remove it");
+    }
+    if model.reasoning_summary && !capabilities.reasoning_summary {
+        return invalid("reasoning summaries are only returned by reasoning models, turn them off");
+    }
+    Ok(())
+}
+
+const JSON_SCHEMA_TYPES: &[&str] = &[
+    "string", "number", "integer", "boolean", "object", "array", "null",
+];
+
+/// Check that every tool's input schema is an object schema the API will accept
+///
+/// Catches malformed agent definitions locally instead of as an opaque 400 from
+/// the provider. The error names the tool and the path of the offending field.
+pub fn validate_tools(tools: &[Tool]) -> Result<(), ProviderError> {
+    for tool in tools {
+        let invalid = |path: &str, problem: &str| {
+            ProviderError::request_failed(format!(
+                "Invalid input schema for tool '{}': {} {}",
+                tool.name, path, problem
+            ))
+        };
+        let schema = &tool.input_schema;
+        if !schema.is_object() {
+            return Err(invalid("input_schema", "must be a JSON object"));
+        }
+        if schema.get("type") != Some(&json!("object")) {
+            return Err(invalid("type", "must be \"object\""));
+        }
+        validate_schema(schema, "input_schema")
+            .map_err(|(path, problem)| invalid(&path, problem))?;
+    }
+    Ok(())
+}
+
+/// Validate one (sub)schema, returning the path and problem of the first malformed field
+fn validate_schema(schema: &Value, path: &str) -> Result<(), (String, &'static str)> {
+    let Some(schema) = schema.as_object() else {
+        // `true` and `false` are valid schemas that accept everything or nothing
+        return match schema {
+            Value::Bool(_) => Ok(()),
+            _ => Err((path.to_string(), "must be a schema object")),
+        };
+    };
+
+    if let Some(kind) = schema.get("type") {
+        let names: Vec<&Value> = match kind {
+            Value::Array(names) => names.iter().collect(),
+            name => vec![name],
+        };
+        if !names.iter().all(|name| {
+            name.as_str()
+                .is_some_and(|n| JSON_SCHEMA_TYPES.contains(&n))
+        }) {
+            return Err((format!("{}.type", path), "is not a JSON Schema type"));
+        }
+    }
+
+    let properties = match schema.get("properties") {
+        Some(properties) => Some(
+            properties
+                .as_object()
+                .ok_or_else(|| (format!("{}.properties", path), "must be an object"))?,
+        ),
+        None => None,
+    };
+    for (name, property) in properties.into_iter().flatten() {
+        validate_schema(property, &format!("{}.properties.{}", path, name))?;
+    }
+    if let Some(required) = schema.get("required") {
+        let required = required
+            .as_array()
+            .ok_or_else(|| (format!("{}.required", path), "must be an array"))?;
+        for name in required {
+            let name = name
+                .as_str()
+                .ok_or_else(|| (format!("{}.required", path), "must only contain strings"))?;
+            if !properties.is_some_and(|p| p.contains_key(name)) {
+                return Err((
+                    format!("{}.required", path),
+                    "lists a property that is not defined",
+                ));
+            }
+        }
+    }
+
+    // A list of schemas is the tuple form, one schema per position
+    match schema.get("items") {
+        Some(Value::Array(items)) => {
+            for (i, item) in items.iter().enumerate() {
+                validate_schema(item, &format!("{}.items[{}]", path, i))?;
+            }
+        }
+        Some(items) => validate_schema(items, &format!("{}.items", path))?,
+        None => {}
+    }
+    Ok(())
+}
+
+static HEADING: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"^#{1,6}\s+").unwrap());
+static BULLET: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"^(\s*)[-*+]\s+").unwrap());
+static RULE: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"^\s*([-*_])(\s*[-*_]){2,}\s*$").unwrap());
+static IMAGE: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"!\[([^\]]*)\]\([^)]*\)").unwrap());
+static LINK: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"\[([^\]]+)\]\(([^)]+)\)").unwrap());
+static STRONG: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"\*\*([^*]+)\*\*|__([^_]+)__").unwrap());
+static EMPHASIS: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"\*([^*\s][^*]*)\*").unwrap());
+
+/// Convert markdown to plain text for UIs that can not render it
+///
+/// Headings, emphasis, blockquotes and rules lose their markup, bullets become
+/// `•`, links keep their target in parentheses. Only `*` marks emphasis, so
+/// snake_case identifiers survive. Code fences are removed while
+/// the code inside them and in inline code spans is kept exactly as written.
+pub fn strip_markdown(text: &str) -> String {
+    let mut lines = Vec::new();
+    let mut in_code_block = false;
+    for line in text.lines() {
+        if line.trim_start().starts_with("```") {
+            in_code_block = !in_code_block;
+            continue;
+        }
+        if in_code_block {
+            lines.push(line.to_string());
+            continue;
+        }
+        if RULE.is_match(line) {
+            continue;
+        }
+
+        let line = match line.trim_start().strip_prefix('>') {
+            Some(quoted) => quoted.trim_start(),
+            None => line,
+        };
+        let line = HEADING.replace(line, "");
+        let line = BULLET.replace(&line, "${1}• ");
+        lines.push(strip_inline_markdown(&line));
+    }
+    lines.join("\n")
+}
+
+/// Apply `strip_markdown` to the text of a message, tool requests are left untouched
+pub fn strip_message_markdown(mut message: Message) -> Message {
+    for content in message.content.iter_mut() {
+        if let MessageContent::Text(text) = content {
+            text.text = strip_markdown(&text.text);
         }
     }
+    message
+}
+
+/// Trim whitespace around the text of a message, other content is left untouched
+///
+/// Leading whitespace is removed from the first text content and trailing
+/// whitespace from the last one, unless `keep_trailing` is set.
+pub fn trim_message_text(mut message: Message, keep_trailing: bool) -> Message {
+    let mut texts = message
+        .content
+        .iter_mut()
+        .filter_map(|content| match content {
+            MessageContent::Text(text) => Some(text),
+            _ => None,
+        });
+    if let Some(first) = texts.next() {
+        first.text = first.text.trim_start().to_string();
+        let last = texts.last().unwrap_or(first);
+        if !keep_trailing {
+            last.text = last.text.trim_end().to_string();
+        }
+    }
+    message
+}
+
+/// Split text into the reasoning inside `<tag>...</tag>` blocks and the answer around them
+///
+/// Nested blocks of the same tag belong to the outer one. A block that is never
+/// closed runs to the end of the text, and a closing tag without an opening one
+/// marks everything before it as reasoning, as some models leave the opening tag
+/// to their chat template. The answer is trimmed when any block was found.
+pub fn extract_thinking(text: &str, tags: &[String]) -> (Vec<String>, String) {
+    let mut thinking = Vec::new();
+    let mut answer = String::new();
+    let mut rest = text;
+
+    for tag in tags {
+        let (open, close) = (format!("<{}>", tag), format!("</{}>", tag));
+        if let Some(end) = rest.find(&close) {
+            if !rest[..end].contains(&open) {
+                thinking.push(rest[..end].trim().to_string());
+                rest = &rest[end + close.len()..];
+                break;
+            }
+        }
+    }
+
+    while let Some((start, tag)) = tags
+        .iter()
+        .filter_map(|tag| rest.find(&format!("<{}>", tag)).map(|start| (start, tag)))
+        .min_by_key(|(start, _)| *start)
+    {
+        let (open, close) = (format!("<{}>", tag), format!("</{}>", tag));
+        answer.push_str(&rest[..start]);
+        rest = &rest[start + open.len()..];
+
+        let mut block = String::new();
+        let mut depth = 1;
+        while depth > 0 {
+            match (rest.find(&open), rest.find(&close)) {
+                (Some(nested), Some(end)) if nested < end => {
+                    block.push_str(&rest[..nested]);
+                    rest = &rest[nested + open.len()..];
+                    depth += 1;
+                }
+                (_, Some(end)) => {
+                    block.push_str(&rest[..end]);
+                    rest = &rest[end + close.len()..];
+                    depth -= 1;
+                }
+                (_, None) => {
+                    block.push_str(rest);
+                    rest = "";
+                    break;
+                }
+            }
+        }
+        thinking.push(block.trim().to_string());
+    }
+    answer.push_str(rest);
+
+    thinking.retain(|block| !block.is_empty());
+    if thinking.is_empty() && answer == text {
+        return (thinking, answer);
+    }
+    (thinking, answer.trim().to_string())
+}
+
+/// Apply `extract_thinking` to the text of a message, placing the reasoning before the answer
+pub fn extract_message_thinking(mut message: Message, tags: &[String]) -> Message {
+    if tags.is_empty() {
+        return message;
+    }
+    let mut content = Vec::with_capacity(message.content.len());
+    for item in message.content.drain(..) {
+        match item {
+            MessageContent::Text(text) => {
+                let (thinking, answer) = extract_thinking(&text.text, tags);
+                content.extend(thinking.into_iter().map(MessageContent::thinking));
+                if !answer.is_empty() {
+                    content.push(MessageContent::text(answer));
+                }
+            }
+            other => content.push(other),
+        }
+    }
+    message.content = content;
+    message
+}
+
+/// Apply the clean up `model` asks for to a response message
+pub fn finish_message(mut message: Message, model: &ModelConfig) -> Message {
+    message = extract_message_thinking(message, &model.thinking_tags);
+    if model.strip_markdown {
+        message = strip_message_markdown(message);
+    }
+    if model.trim_response {
+        message = trim_message_text(message, model.preserve_trailing_whitespace);
+    }
+    message
+}
+
+/// Whether `finish_message` changes anything for `model`
+pub fn needs_finishing(model: &ModelConfig) -> bool {
+    !model.thinking_tags.is_empty() || model.strip_markdown || model.trim_response
+}
+
+/// The prefill to send, after applying the prefill policy for models that can not continue one
+pub fn effective_prefill(model: &ModelConfig) -> Result<Option<&str>, ProviderError> {
+    let Some(prefill) = model.prefill.as_deref() else {
+        return Ok(None);
+    };
+    if model.capabilities().prefill {
+        return Ok(Some(prefill));
+    }
+    match model.prefill_policy {
+        PrefillPolicy::Reject => Err(ProviderError::InvalidRequest(format!(
+            "prefill is only supported by Claude models, remove it for model {}",
+            model.model_name
+        ))),
+        PrefillPolicy::Ignore => {
+            tracing::warn!(
+                "Model {} can not continue a prefill, sending the request without it",
+                model.model_name
+            );
+            Ok(None)
+        }
+    }
+}
+
+/// Put the prefill in front of the model's continuation, so the message holds the whole answer
+pub fn prepend_prefill(mut message: Message, prefill: &str) -> Message {
+    match message.content.first_mut() {
+        Some(MessageContent::Text(text)) => text.text.insert_str(0, prefill),
+        _ => message.content.insert(0, MessageContent::text(prefill)),
+    }
+    message
+}
+
+/// Strip inline markup outside of code spans, which only lose their backticks
+fn strip_inline_markdown(line: &str) -> String {
+    line.split('`')
+        .enumerate()
+        .map(|(i, segment)| {
+            if i % 2 == 1 {
+                return segment.to_string();
+            }
+            let segment = IMAGE.replace_all(segment, "$1");
+            let segment = LINK.replace_all(&segment, "$1 ($2)");
+            let segment = STRONG.replace_all(&segment, "$1$2");
+            EMPHASIS.replace_all(&segment, "$1").to_string()
+        })
+        .collect()
+}
+
+/// Append an instruction to respond in `locale`, unless the prompt already has it
+pub fn apply_response_locale(system: &str, locale: Option<&str>) -> String {
+    let Some(locale) = locale.filter(|l| !l.trim().is_empty()) else {
+        return system.to_string();
+    };
+    let instruction = format!("Respond in {}.", locale.trim());
+    if system.to_lowercase().contains(&instruction.to_lowercase()) {
+        return system.to_string();
+    }
+    if system.is_empty() {
+        instruction
+    } else {
+        format!("{}\n\n{}", system.trim_end(), instruction)
+    }
 }
 
 pub fn sanitize_function_name(name: &str) -> String {
@@ -95,6 +1067,26 @@ pub fn is_valid_function_name(name: &str) -> bool {
     re.is_match(name)
 }
 
+/// The `system_fingerprint` of an OpenAI compatible response, not every backend sends one
+pub fn get_system_fingerprint(data: &Value) -> Option<String> {
+    data.get("system_fingerprint")
+        .and_then(|f| f.as_str())
+        .map(String::from)
+}
+
+/// The perplexity of a completion from the log probabilities of its tokens
+///
+/// That is `exp` of the negative mean log probability, 1.0 for a completion the
+/// model was certain of and higher the less likely it found it. Returns `None`
+/// for an empty completion, which has no perplexity.
+pub fn perplexity(logprobs: &[f32]) -> Option<f64> {
+    if logprobs.is_empty() {
+        return None;
+    }
+    let total: f64 = logprobs.iter().map(|&logprob| f64::from(logprob)).sum();
+    Some((-total / logprobs.len() as f64).exp())
+}
+
 /// Extract the model name from a JSON object. Common with most providers to have this top level attribute.
 pub fn get_model(data: &Value) -> String {
     if let Some(model) = data.get("model") {
@@ -137,22 +1129,60 @@ pub fn unescape_json_values(value: &Value) -> Value {
     }
 }
 
+/// Every text marked sensitive in `messages`
+pub fn sensitive_texts(messages: &[Message]) -> Vec<String> {
+    messages
+        .iter()
+        .flat_map(|message| message.sensitive.iter())
+        .filter(|text| !text.is_empty())
+        .cloned()
+        .collect()
+}
+
+/// Mask every occurrence of the sensitive texts in the strings of a JSON value
+///
+/// Matches within longer strings too, since request formats may join the
+/// text of several contents into one string.
+pub fn mask_sensitive(value: &mut Value, sensitive: &[String]) {
+    if sensitive.is_empty() {
+        return;
+    }
+    match value {
+        Value::String(text) => {
+            for secret in sensitive {
+                if text.contains(secret.as_str()) {
+                    *text = text.replace(secret.as_str(), "[SENSITIVE]");
+                }
+            }
+        }
+        Value::Array(items) => items
+            .iter_mut()
+            .for_each(|item| mask_sensitive(item, sensitive)),
+        Value::Object(map) => map
+            .values_mut()
+            .for_each(|item| mask_sensitive(item, sensitive)),
+        _ => {}
+    }
+}
+
+/// Log a request and its response at debug level, with sensitive message text masked
 pub fn emit_debug_trace<T: serde::Serialize>(
     model_config: &T,
+    messages: &[Message],
     payload: &impl serde::Serialize,
     response: &Value,
     usage: &Usage,
 ) {
-    // Handle both Map<String, Value> and Value payload types
-    let payload_str = match serde_json::to_value(payload) {
-        Ok(value) => serde_json::to_string_pretty(&value).unwrap_or_default(),
-        Err(_) => serde_json::to_string_pretty(&payload).unwrap_or_default(),
-    };
+    let sensitive = sensitive_texts(messages);
+    let mut payload = serde_json::to_value(payload).unwrap_or_default();
+    mask_sensitive(&mut payload, &sensitive);
+    let mut response = response.clone();
+    mask_sensitive(&mut response, &sensitive);
 
     tracing::debug!(
         model_config = %serde_json::to_string_pretty(model_config).unwrap_or_default(),
-        input = %payload_str,
-        output = %serde_json::to_string_pretty(response).unwrap_or_default(),
+        input = %serde_json::to_string_pretty(&payload).unwrap_or_default(),
+        output = %serde_json::to_string_pretty(&response).unwrap_or_default(),
         input_tokens = ?usage.input_tokens.unwrap_or_default(),
         output_tokens = ?usage.output_tokens.unwrap_or_default(),
         total_tokens = ?usage.total_tokens.unwrap_or_default(),
@@ -162,8 +1192,647 @@ pub fn emit_debug_trace<T: serde::Serialize>(
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::model::{AudioOutput, ReasoningEffort, ResponseFormat};
+    use mcp_core::tool::ToolCall;
     use serde_json::json;
 
+    #[test]
+    fn test_retry_after_and_backoff() {
+        let mut headers = HeaderMap::new();
+        assert_eq!(retry_after(&headers), None);
+        headers.insert(header::RETRY_AFTER, "7".parse().unwrap());
+        assert_eq!(retry_after(&headers), Some(Duration::from_secs(7)));
+        headers.insert(
+            header::RETRY_AFTER,
+            "Wed, 21 Oct 2015 07:28:00 GMT".parse().unwrap(),
+        );
+        assert_eq!(retry_after(&headers), Some(Duration::ZERO));
+
+        let mut headers = HeaderMap::new();
+        headers.insert("x-ratelimit-reset-requests", "1m30s".parse().unwrap());
+        headers.insert("x-ratelimit-reset-tokens", "250ms".parse().unwrap());
+        assert_eq!(rate_limit_reset(&headers), Some(Duration::from_secs(90)));
+        let mut headers = HeaderMap::new();
+        headers.insert("x-ratelimit-reset", "12".parse().unwrap());
+        assert_eq!(rate_limit_reset(&headers), Some(Duration::from_secs(12)));
+        headers.insert(header::RETRY_AFTER, "3".parse().unwrap());
+        assert_eq!(rate_limit_reset(&headers), Some(Duration::from_secs(3)));
+        assert_eq!(parse_reset_duration("6m0s"), Some(Duration::from_secs(360)));
+        assert_eq!(parse_reset_duration("soon"), None);
+
+        let retry = RetryConfig::default();
+        assert_eq!(retry.backoff(1), Duration::from_secs(1));
+        assert_eq!(retry.backoff(3), Duration::from_secs(4));
+        assert_eq!(retry.backoff(10), Duration::from_secs(30));
+
+        assert!(is_retryable_status(StatusCode::TOO_MANY_REQUESTS));
+        assert!(is_retryable_status(StatusCode::BAD_GATEWAY));
+        assert!(!is_retryable_status(StatusCode::BAD_REQUEST));
+        assert!(!is_retryable_status(StatusCode::NOT_IMPLEMENTED));
+    }
+
+    #[tokio::test]
+    async fn test_send_with_retry() {
+        let server = wiremock::MockServer::start().await;
+        let client = reqwest::Client::new();
+        let retry =
+            RetryConfig::new(3).with_backoff(Duration::from_millis(1), Duration::from_millis(10));
+        let send = |path: &'static str| {
+            let url = format!("{}/{}", server.uri(), path);
+            let client = client.clone();
+            move || {
+                let request = client.get(&url);
+                async move { Ok(request.send().await?) }
+            }
+        };
+
+        // A rate limit that clears up after the wait it asks for
+        wiremock::Mock::given(wiremock::matchers::path("/limited"))
+            .respond_with(wiremock::ResponseTemplate::new(429).insert_header("Retry-After", "0"))
+            .up_to_n_times(1)
+            .mount(&server)
+            .await;
+        wiremock::Mock::given(wiremock::matchers::path("/limited"))
+            .respond_with(wiremock::ResponseTemplate::new(200))
+            .mount(&server)
+            .await;
+        let response = send_with_retry(&retry, send("limited")).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        // A bad request fails the same way every time and is not retried
+        wiremock::Mock::given(wiremock::matchers::path("/invalid"))
+            .respond_with(wiremock::ResponseTemplate::new(400))
+            .expect(1)
+            .mount(&server)
+            .await;
+        let response = send_with_retry(&retry, send("invalid")).await.unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+
+        // Once the attempts are used up the last response is mapped as usual
+        wiremock::Mock::given(wiremock::matchers::path("/down"))
+            .respond_with(wiremock::ResponseTemplate::new(503))
+            .expect(3)
+            .mount(&server)
+            .await;
+        let response = send_with_retry(&retry, send("down")).await.unwrap();
+        assert!(matches!(
+            handle_response_openai_compat(response).await,
+            Err(ProviderError::ServerError(_))
+        ));
+        server.verify().await;
+    }
+
+    #[tokio::test]
+    async fn test_context_length_errors() {
+        let server = wiremock::MockServer::start().await;
+        let respond = |path: &str, template: wiremock::ResponseTemplate| {
+            wiremock::Mock::given(wiremock::matchers::path(path.to_string())).respond_with(template)
+        };
+        respond(
+            "/coded",
+            wiremock::ResponseTemplate::new(400).set_body_json(json!({
+                "error": {"message": "Too long", "code": "context_length_exceeded"}
+            })),
+        )
+        .mount(&server)
+        .await;
+        respond(
+            "/message",
+            wiremock::ResponseTemplate::new(400).set_body_json(json!({
+                "error": {
+                    "message": "This model's maximum context length is 128000 tokens. However, your messages resulted in 130512 tokens.",
+                    "type": "invalid_request_error",
+                    "code": null
+                }
+            })),
+        )
+        .mount(&server)
+        .await;
+        respond("/oversized", wiremock::ResponseTemplate::new(413))
+            .mount(&server)
+            .await;
+        respond(
+            "/other",
+            wiremock::ResponseTemplate::new(400).set_body_json(json!({
+                "error": {"message": "Invalid value for temperature"}
+            })),
+        )
+        .mount(&server)
+        .await;
+
+        let client = reqwest::Client::new();
+        let fetch = |path: &str| {
+            let request = client.get(format!("{}/{}", server.uri(), path));
+            async move { handle_response_openai_compat(request.send().await.unwrap()).await }
+        };
+        for path in ["coded", "message", "oversized"] {
+            assert!(
+                matches!(
+                    fetch(path).await,
+                    Err(ProviderError::ContextLengthExceeded(_))
+                ),
+                "{}",
+                path
+            );
+        }
+        assert!(matches!(
+            fetch("other").await,
+            Err(ProviderError::RequestFailed { .. })
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_rejected_request_keeps_status_code_and_id() {
+        let server = wiremock::MockServer::start().await;
+        wiremock::Mock::given(wiremock::matchers::method("GET"))
+            .respond_with(
+                wiremock::ResponseTemplate::new(422)
+                    .insert_header("x-request-id", "req_abc")
+                    .set_body_json(json!({
+                        "error": {"message": "Unsupported value", "type": "invalid_request_error", "code": null}
+                    })),
+            )
+            .mount(&server)
+            .await;
+
+        let response = reqwest::get(server.uri()).await.unwrap();
+        let error = handle_response_openai_compat(response).await.unwrap_err();
+        assert_eq!(error.status(), Some(422));
+        assert_eq!(error.code(), Some("invalid_request_error"));
+        assert_eq!(error.request_id(), Some("req_abc"));
+        assert!(!error.is_retryable());
+    }
+
+    #[test]
+    fn test_validate_tools() {
+        let tool = |schema: Value| Tool::new("get_weather", "Get the weather", schema);
+        let valid = tool(json!({
+            "type": "object",
+            "properties": {
+                "city": {"type": "string"},
+                "days": {"type": ["integer", "null"]},
+                "units": {"type": "array", "items": {"type": "string"}},
+                "point": {"type": "array", "items": [{"type": "number"}, {"type": "number"}]}
+            },
+            "required": ["city"]
+        }));
+        assert!(validate_tools(&[valid]).is_ok());
+
+        let cases = [
+            (json!("object"), "input_schema must be a JSON object"),
+            (json!({"type": "array"}), "type must be \"object\""),
+            (
+                json!({"type": "object", "properties": ["city"]}),
+                "input_schema.properties must be an object",
+            ),
+            (
+                json!({"type": "object", "properties": {"city": {"type": "text"}}}),
+                "input_schema.properties.city.type is not a JSON Schema type",
+            ),
+            (
+                json!({"type": "object", "properties": {"city": {"type": "string"}}, "required": ["town"]}),
+                "input_schema.required lists a property that is not defined",
+            ),
+            (
+                json!({"type": "object", "properties": {"tags": {"type": "array", "items": 3}}}),
+                "input_schema.properties.tags.items must be a schema object",
+            ),
+            (
+                json!({"type": "object", "properties": {"point": {"type": "array", "items": [{"type": "number"}, 3]}}}),
+                "input_schema.properties.point.items[1] must be a schema object",
+            ),
+        ];
+        for (schema, expected) in cases {
+            let error = validate_tools(&[tool(schema)]).unwrap_err().to_string();
+            assert!(error.contains("'get_weather'"), "{}", error);
+            assert!(error.ends_with(expected), "{}", error);
+        }
+    }
+
+    #[test]
+    fn test_strip_markdown() {
+        let markdown = "# Title\n\n## Steps\n- **first** step\n  * nested _item_\n1. one\n\n---\n> quoted *text*\nSee [the docs](https://example.com) and ![logo](logo.png).";
+        assert_eq!(
+            strip_markdown(markdown),
+            "Title\n\nSteps\n• first step\n  • nested _item_\n1. one\n\nquoted text\nSee the docs (https://example.com) and logo."
+        );
+    }
+
+    #[test]
+    fn test_strip_markdown_keeps_code() {
+        let markdown =
+            "Run `cargo **test**`:\n```bash\n# not a heading\n- not a bullet **x**\n```\ndone";
+        assert_eq!(
+            strip_markdown(markdown),
+            "Run cargo **test**:\n# not a heading\n- not a bullet **x**\ndone"
+        );
+    }
+
+    #[test]
+    fn test_trim_message_text() {
+        let message = Message::assistant()
+            .with_text("\n  Sure, checking ")
+            .with_tool_request("call_1", Ok(ToolCall::new("search", json!({"q": " x "}))))
+            .with_text(" Done.\n\n");
+
+        let trimmed = trim_message_text(message.clone(), false);
+        assert_eq!(trimmed.content[0].as_text(), Some("Sure, checking "));
+        assert_eq!(trimmed.content[1], message.content[1]);
+        assert_eq!(trimmed.content[2].as_text(), Some(" Done."));
+
+        let continued = trim_message_text(message.clone(), true);
+        assert_eq!(continued.content[2].as_text(), Some(" Done.\n\n"));
+
+        let single = trim_message_text(Message::assistant().with_text("  Once upon "), true);
+        assert_eq!(single.as_concat_text(), "Once upon ");
+    }
+
+    #[test]
+    fn test_extract_thinking() {
+        let tags = vec!["think".to_string()];
+        let message = Message::assistant().with_text(
+            "<think>The user wants a sum. <think>2 + 2</think> is 4.</think>\n\nThe answer is 4.",
+        );
+        let message = extract_message_thinking(message, &tags);
+        assert_eq!(message.content.len(), 2);
+        assert_eq!(
+            message.content[0].as_thinking(),
+            Some("The user wants a sum. 2 + 2 is 4.")
+        );
+        assert_eq!(message.content[1].as_text(), Some("The answer is 4."));
+
+        // Text without the tags is left exactly as it was
+        let (thinking, answer) = extract_thinking("  <b>bold</b> ", &tags);
+        assert!(thinking.is_empty());
+        assert_eq!(answer, "  <b>bold</b> ");
+
+        // Only the opening tag came from the chat template
+        let (thinking, answer) = extract_thinking("Easy one.</think>Yes.", &tags);
+        assert_eq!(thinking, vec!["Easy one."]);
+        assert_eq!(answer, "Yes.");
+    }
+
+    #[test]
+    fn test_extract_unclosed_thinking() {
+        let tags = vec!["reasoning".to_string(), "think".to_string()];
+        let (thinking, answer) =
+            extract_thinking("Let me see. <think>First, list the files, then", &tags);
+        assert_eq!(thinking, vec!["First, list the files, then"]);
+        assert_eq!(answer, "Let me see.");
+
+        let message = extract_message_thinking(
+            Message::assistant().with_text("<reasoning>Still going"),
+            &tags,
+        );
+        assert_eq!(message.content.len(), 1);
+        assert!(message.content[0].as_thinking().is_some());
+    }
+
+    #[test]
+    fn test_perplexity() {
+        // Every token at probability 1/4 gives a perplexity of 4
+        let quarter = (0.25f32).ln();
+        assert!((perplexity(&[quarter, quarter, quarter]).unwrap() - 4.0).abs() < 1e-5);
+        // exp(-mean(-0.5, -1.5)) = exp(1)
+        let mixed = perplexity(&[-0.5, -1.5]).unwrap();
+        assert!((mixed - std::f64::consts::E).abs() < 1e-9);
+        assert_eq!(perplexity(&[0.0]), Some(1.0));
+        assert_eq!(perplexity(&[]), None);
+    }
+
+    #[test]
+    fn test_get_system_fingerprint() {
+        let response = json!({"model": "gpt-4o", "system_fingerprint": "fp_44709d6fcb"});
+        assert_eq!(
+            get_system_fingerprint(&response).as_deref(),
+            Some("fp_44709d6fcb")
+        );
+        assert_eq!(get_system_fingerprint(&json!({"model": "gpt-4o"})), None);
+        assert_eq!(
+            get_system_fingerprint(&json!({"system_fingerprint": null})),
+            None
+        );
+    }
+
+    #[test]
+    fn test_web_search_requires_capability() {
+        let tools = vec![crate::providers::formats::openai::web_search_tool()];
+        let model = ModelConfig::new("gpt-4o".to_string());
+        assert!(matches!(
+            check_content_support(&model, &[], &tools),
+            Err(ProviderError::NotSupported(_))
+        ));
+
+        let model = ModelConfig::new("gpt-4o-search-preview".to_string());
+        assert!(check_content_support(&model, &[], &tools).is_ok());
+
+        // A tool of its own called web_search needs no built-in search
+        let tools = vec![Tool::new("web_search", "Search the web", json!({}))];
+        let model = ModelConfig::new("gpt-4o".to_string());
+        assert!(check_content_support(&model, &[], &tools).is_ok());
+    }
+
+    fn blob(uri: &str, mime_type: &str, bytes: &[u8]) -> Content {
+        Content::resource(ResourceContents::BlobResourceContents {
+            uri: uri.to_string(),
+            mime_type: Some(mime_type.to_string()),
+            blob: base64::engine::general_purpose::STANDARD.encode(bytes),
+        })
+    }
+
+    #[test]
+    fn test_convert_binary_content() {
+        // Not valid UTF-8, as in a PDF's binary comment line
+        let pdf_bytes = b"%PDF-1.7\n%\xe2\xe3\xcf\xd3\n";
+        let pdf = convert_binary_content(&blob("file:///report.pdf", "application/pdf", pdf_bytes))
+            .into_owned();
+        let text = pdf.as_text().unwrap();
+        let (marker, encoded) = text.split_once('\n').unwrap();
+        assert!(marker.contains("file:///report.pdf") && marker.contains("application/pdf"));
+        assert_eq!(
+            base64::engine::general_purpose::STANDARD
+                .decode(encoded)
+                .unwrap(),
+            pdf_bytes
+        );
+
+        let screenshot =
+            convert_binary_content(&blob("file:///shot.png", "image/png", b"\x89PNG")).into_owned();
+        assert_eq!(
+            screenshot.as_image().map(|(_, mime)| mime),
+            Some("image/png")
+        );
+
+        let csv =
+            convert_binary_content(&blob("file:///data.csv", "text/csv", b"a,b\n1,2")).into_owned();
+        assert_eq!(csv.as_text(), Some("a,b\n1,2"));
+    }
+
+    #[test]
+    fn test_binary_tool_images_require_capability() {
+        let messages = vec![Message::user().with_tool_response(
+            "call_1",
+            Ok(vec![blob("file:///shot.png", "image/png", b"\x89PNG")]),
+        )];
+        let model = ModelConfig::new("o1-mini".to_string());
+        assert!(matches!(
+            check_content_support(&model, &messages, &[]),
+            Err(ProviderError::NotSupported(_))
+        ));
+        let model = ModelConfig::new("gpt-4o".to_string());
+        assert!(check_content_support(&model, &messages, &[]).is_ok());
+    }
+
+    #[test]
+    fn test_too_many_images() {
+        let mut message = Message::user().with_text("Compare these screenshots");
+        for _ in 0..3 {
+            message = message.with_image("aGVsbG8=", "image/png");
+        }
+        let messages = vec![
+            message,
+            Message::user()
+                .with_tool_response("call_1", Ok(vec![Content::image("aGVsbG8=", "image/png")])),
+        ];
+        assert_eq!(count_images(&messages), 4);
+
+        // Within the limit known for the model
+        let model = ModelConfig::new("gpt-4o".to_string());
+        assert_eq!(model.max_images(), Some(500));
+        assert!(check_content_support(&model, &messages, &[]).is_ok());
+
+        let model = model.with_max_images(Some(3));
+        match check_content_support(&model, &messages, &[]) {
+            Err(ProviderError::TooManyImages { limit, provided }) => {
+                assert_eq!((limit, provided), (3, 4));
+            }
+            other => panic!("Expected too many images, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_downconvert_content() {
+        let messages = vec![
+            Message::user()
+                .with_text("What is in this picture?")
+                .with_image("aGVsbG8=", "image/png"),
+            Message::assistant().with_tool_request(
+                "call_1",
+                Ok(ToolCall::new("read_file", json!({"path": "notes.txt"}))),
+            ),
+            Message::user().with_tool_response(
+                "call_1",
+                Ok(vec![
+                    Content::text("buy milk"),
+                    Content::image("aGVsbG8=", "image/png"),
+                ]),
+            ),
+        ];
+        let tools = vec![Tool::new(
+            "read_file",
+            "Read a file",
+            json!({"type": "object"}),
+        )];
+
+        // Strict by default, the request is rejected
+        let model = ModelConfig::new("o1-mini".to_string());
+        assert!(matches!(
+            check_content_support(&model, &messages[..1], &[]),
+            Err(ProviderError::NotSupported(_))
+        ));
+        assert!(matches!(
+            check_content_support(&model, &messages[1..], &[]),
+            Err(ProviderError::NotSupported(_))
+        ));
+
+        let (converted, kept_tools) = downconvert_content(&model, &messages, &tools);
+        assert!(kept_tools.is_empty());
+        assert!(check_content_support(&model, &converted, kept_tools).is_ok());
+        assert_eq!(converted[0].content[1].as_text(), Some(IMAGE_OMITTED));
+        assert_eq!(
+            converted[1].as_concat_text(),
+            r#"[Called tool read_file with {"path":"notes.txt"}]"#
+        );
+        assert_eq!(
+            converted[2].as_concat_text(),
+            "[Tool result]\nbuy milk\n[image omitted]"
+        );
+
+        // Supported content is left alone
+        let model = ModelConfig::new("gpt-4o".to_string());
+        let (converted, kept_tools) = downconvert_content(&model, &messages, &tools);
+        assert_eq!(converted, messages);
+        assert_eq!(kept_tools.len(), 1);
+    }
+
+    #[test]
+    fn test_audio_output_requires_capability() {
+        let audio = Some(AudioOutput {
+            voice: "alloy".to_string(),
+            format: "wav".to_string(),
+        });
+        let model = ModelConfig::new("gpt-4o".to_string()).with_audio_output(audio.clone());
+        assert!(matches!(
+            check_content_support(&model, &[], &[]),
+            Err(ProviderError::NotSupported(_))
+        ));
+
+        let model = ModelConfig::new("gpt-4o-audio-preview".to_string()).with_audio_output(audio);
+        assert!(check_content_support(&model, &[], &[]).is_ok());
+    }
+
+    #[test]
+    fn test_validate_tool_pairing() {
+        let request = |id: &str| {
+            Message::assistant().with_tool_request(id, Ok(ToolCall::new("search", json!({}))))
+        };
+        let response = |id: &str| Message::user().with_tool_response(id, Ok(vec![]));
+        let problem = |messages: &[Message]| match validate_tool_pairing(messages) {
+            Err(ProviderError::InvalidConversation(problem)) => problem,
+            other => panic!("expected an invalid conversation, got {:?}", other),
+        };
+
+        let messages = vec![
+            Message::user().with_text("Search twice"),
+            request("call_1"),
+            response("call_1"),
+            request("call_2"),
+            response("call_2"),
+        ];
+        assert!(validate_tool_pairing(&messages).is_ok());
+
+        let duplicate = [messages.clone(), vec![response("call_1")]].concat();
+        assert_eq!(
+            problem(&duplicate),
+            "tool call id 'call_1' is answered twice, in message 3 and message 6"
+        );
+
+        let orphan = [messages.clone(), vec![response("call_9")]].concat();
+        assert_eq!(
+            problem(&orphan),
+            "the tool response in message 6 answers tool call id 'call_9', which no earlier message requested"
+        );
+
+        let reused = [messages, vec![request("call_2")]].concat();
+        assert!(problem(&reused).contains("already used in message 4"));
+    }
+
+    #[test]
+    fn test_validate_params() {
+        let invalid = |model: ModelConfig| {
+            matches!(
+                validate_params(&model),
+                Err(ProviderError::InvalidRequest(_))
+            )
+        };
+
+        // Reasoning models take no sampling parameters
+        let o1 = || ModelConfig::new("o1".to_string());
+        assert!(invalid(o1().with_temperature(Some(0.2))));
+        assert!(invalid(o1().with_top_p(Some(0.9))));
+        assert!(invalid(o1().with_frequency_penalty(Some(0.5))));
+        assert!(invalid(o1().with_stop_sequences(vec!["\n\n".to_string()])));
+        assert!(invalid(
+            o1().with_extra_body(Some(json!({"stop": ["\n\n"]})))
+        ));
+        assert!(validate_params(&o1().with_max_tokens(Some(1000))).is_ok());
+
+        // Claude takes either temperature or top_p
+        let claude =
+            || ModelConfig::new("claude-3-5-sonnet-latest".to_string()).with_temperature(Some(0.2));
+        assert!(invalid(claude().with_top_p(Some(0.9))));
+        assert!(validate_params(&claude()).is_ok());
+        let gpt = ModelConfig::new("gpt-4o".to_string())
+            .with_temperature(Some(0.2))
+            .with_top_p(Some(0.9));
+        assert!(validate_params(&gpt).is_ok());
+
+        // Stop sequences can cut a JSON document short
+        let json_mode = ModelConfig::new("gpt-4o".to_string())
+            .with_extra_body(Some(json!({"response_format": {"type": "json_object"}})));
+        assert!(validate_params(&json_mode).is_ok());
+        assert!(invalid(json_mode.clone().with_stop_token_ids(vec![13])));
+        assert!(invalid(json_mode.with_extra_body(Some(json!({
+            "response_format": {"type": "json_schema"},
+            "stop": "END"
+        })))));
+        let typed = ModelConfig::new("gpt-4o".to_string())
+            .with_response_format(Some(ResponseFormat::JsonObject));
+        assert!(invalid(typed.with_stop_token_ids(vec![13])));
+
+        // Only reasoning models think for longer or shorter
+        let effort = Some(ReasoningEffort::Low);
+        assert!(validate_params(&o1().with_reasoning_effort(effort)).is_ok());
+        let o3 = ModelConfig::new("openai/o3-mini".to_string()).with_reasoning_effort(effort);
+        assert!(validate_params(&o3).is_ok());
+        match validate_params(&ModelConfig::new("gpt-4o".to_string()).with_reasoning_effort(effort))
+        {
+            Err(ProviderError::InvalidRequest(message)) => {
+                assert!(message.contains("reasoning_effort"), "{}", message);
+                assert!(message.ends_with("for model gpt-4o"), "{}", message);
+            }
+            other => panic!("unexpected result: {:?}", other),
+        }
+        assert!(invalid(
+            ModelConfig::new("o1-mini".to_string()).with_reasoning_effort(effort)
+        ));
+        assert!(validate_params(&o3.with_reasoning_summary(true)).is_ok());
+        assert!(invalid(
+            ModelConfig::new("gpt-4o".to_string()).with_reasoning_summary(true)
+        ));
+    }
+
+    #[test]
+    fn test_model_not_found_suggestions() {
+        let known: Vec<String> = ["gpt-4o", "gpt-4o-mini", "claude-3-5-sonnet"]
+            .iter()
+            .map(|s| s.to_string())
+            .collect();
+
+        match model_not_found("gpt-4-o", &known) {
+            ProviderError::ModelNotFound {
+                requested,
+                suggestions,
+            } => {
+                assert_eq!(requested, "gpt-4-o");
+                assert_eq!(suggestions[0], "gpt-4o");
+                assert!(!suggestions.contains(&"claude-3-5-sonnet".to_string()));
+            }
+            other => panic!("unexpected error: {:?}", other),
+        }
+
+        let ProviderError::ModelNotFound { suggestions, .. } = model_not_found("llama3", &known)
+        else {
+            panic!("expected ModelNotFound");
+        };
+        assert!(suggestions.is_empty());
+
+        assert!(is_model_not_found(Some(
+            &json!({"error": {"code": "model_not_found", "message": "no such model"}})
+        )));
+        assert!(!is_model_not_found(Some(
+            &json!({"error": {"message": "not found"}})
+        )));
+    }
+
+    #[test]
+    fn test_apply_response_locale() {
+        assert_eq!(
+            apply_response_locale("Be brief.", Some("French")),
+            "Be brief.\n\nRespond in French."
+        );
+        assert_eq!(
+            apply_response_locale("", Some("French")),
+            "Respond in French."
+        );
+        assert_eq!(apply_response_locale("Be brief.", None), "Be brief.");
+        // An instruction already in the prompt is not repeated
+        assert_eq!(
+            apply_response_locale("Be brief. respond in french.", Some("French")),
+            "Be brief. respond in french."
+        );
+    }
+
     #[test]
     fn test_sanitize_function_name() {
         assert_eq!(sanitize_function_name("hello-world"), "hello-world");
@@ -0,0 +1,328 @@
+use crate::agents::ExtensionConfig;
+use crate::model::ModelConfig;
+use crate::prompt_template::load_prompt;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use thiserror::Error;
+
+#[derive(Error, Debug)]
+pub enum RecipeError {
+    #[error("Failed to read recipe {0}: {1}")]
+    Read(PathBuf, std::io::Error),
+    #[error("Failed to parse recipe {0}: {1}")]
+    Parse(PathBuf, String),
+    #[error("Recipe {0} is missing values for: {1}")]
+    MissingParameters(PathBuf, String),
+    #[error("Recipe {0} has no parameter '{1}'")]
+    UnknownParameter(PathBuf, String),
+    #[error("Failed to fill in recipe {0}: {1}")]
+    Template(PathBuf, String),
+    #[error("Recipe {0} includes itself")]
+    Cycle(PathBuf),
+}
+
+/// Model settings a recipe runs with, anything left out comes from the config
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct RecipeSettings {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub provider: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub model: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub temperature: Option<f32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_tokens: Option<i32>,
+}
+
+impl RecipeSettings {
+    /// Apply the settings to the model config of the run
+    pub fn apply(&self, model_config: ModelConfig) -> ModelConfig {
+        let model_config = match self.temperature {
+            Some(temperature) => model_config.with_temperature(Some(temperature)),
+            None => model_config,
+        };
+        match self.max_tokens {
+            Some(max_tokens) => model_config.with_max_tokens(Some(max_tokens)),
+            None => model_config,
+        }
+    }
+}
+
+/// A value the recipe needs, used as `{{ key }}` in its instructions and prompt
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RecipeParameter {
+    pub key: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    /// The value when none is given, a parameter without one is required
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub default: Option<String>,
+}
+
+/// Another recipe this one is made of
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SubRecipe {
+    /// Path to the recipe, relative to the one that includes it
+    pub path: String,
+    /// Values for its parameters, these can use the parameters of this recipe
+    #[serde(default)]
+    pub values: HashMap<String, String>,
+}
+
+/// A shareable workflow: what goose is told, the extensions and model it uses
+/// and what it is asked to do
+///
+/// Recipes are YAML, or JSON when the file ends in `.json`:
+///
+/// ```yaml
+/// title: Triage issues
+/// instructions: You triage the issues of {{ repo }}.
+/// prompt: Label the issues opened since {{ since }}.
+/// parameters:
+///   - key: repo
+///   - key: since
+///     default: yesterday
+/// extensions:
+///   - type: builtin
+///     name: developer
+/// sub_recipes:
+///   - path: style.yaml
+///     values:
+///       tone: friendly
+/// ```
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Recipe {
+    pub title: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    /// Added to the system prompt
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub instructions: Option<String>,
+    /// The first message of the session
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub prompt: Option<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub extensions: Vec<ExtensionConfig>,
+    #[serde(default)]
+    pub settings: RecipeSettings,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub parameters: Vec<RecipeParameter>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub sub_recipes: Vec<SubRecipe>,
+}
+
+impl Recipe {
+    /// Load the recipe at `path` with `values` for its parameters
+    ///
+    /// The instructions and prompt are filled in, and the sub-recipes are
+    /// loaded and merged in: their instructions and prompts follow the ones
+    /// of this recipe and their extensions are added unless one of that name
+    /// is there already. The settings of this recipe are kept. The result has
+    /// no sub-recipes left.
+    pub fn load(
+        path: impl AsRef<Path>,
+        values: &HashMap<String, String>,
+    ) -> Result<Self, RecipeError> {
+        Self::load_included(path.as_ref(), values, &mut Vec::new())
+    }
+
+    fn load_included(
+        path: &Path,
+        values: &HashMap<String, String>,
+        including: &mut Vec<PathBuf>,
+    ) -> Result<Self, RecipeError> {
+        let canonical = path
+            .canonicalize()
+            .map_err(|e| RecipeError::Read(path.to_path_buf(), e))?;
+        if including.contains(&canonical) {
+            return Err(RecipeError::Cycle(path.to_path_buf()));
+        }
+        let text =
+            std::fs::read_to_string(path).map_err(|e| RecipeError::Read(path.to_path_buf(), e))?;
+        let mut recipe = Self::parse(path, &text)?;
+        let context = recipe.context(path, values)?;
+        let render = |template: &str| {
+            load_prompt(template, &context)
+                .map_err(|e| RecipeError::Template(path.to_path_buf(), template_error(&e)))
+        };
+        recipe.instructions = recipe.instructions.as_deref().map(render).transpose()?;
+        recipe.prompt = recipe.prompt.as_deref().map(render).transpose()?;
+
+        including.push(canonical);
+        let base = path.parent().unwrap_or(Path::new("."));
+        for sub_recipe in std::mem::take(&mut recipe.sub_recipes) {
+            let values = sub_recipe
+                .values
+                .iter()
+                .map(|(key, value)| Ok((key.clone(), render(value)?)))
+                .collect::<Result<HashMap<_, _>, RecipeError>>()?;
+            let included = Self::load_included(&base.join(&sub_recipe.path), &values, including)?;
+            recipe.merge(included);
+        }
+        including.pop();
+        Ok(recipe)
+    }
+
+    fn parse(path: &Path, text: &str) -> Result<Self, RecipeError> {
+        let parse_error = |e: String| RecipeError::Parse(path.to_path_buf(), e);
+        if path.extension().is_some_and(|ext| ext == "json") {
+            serde_json::from_str(text).map_err(|e| parse_error(e.to_string()))
+        } else {
+            serde_yaml::from_str(text).map_err(|e| parse_error(e.to_string()))
+        }
+    }
+
+    /// The value of every parameter, from `values` or the defaults
+    fn context(
+        &self,
+        path: &Path,
+        values: &HashMap<String, String>,
+    ) -> Result<HashMap<String, String>, RecipeError> {
+        if let Some(unknown) = values
+            .keys()
+            .find(|key| !self.parameters.iter().any(|p| &p.key == *key))
+        {
+            return Err(RecipeError::UnknownParameter(
+                path.to_path_buf(),
+                unknown.clone(),
+            ));
+        }
+        let mut context = HashMap::new();
+        let mut missing = Vec::new();
+        for parameter in &self.parameters {
+            match values.get(&parameter.key).or(parameter.default.as_ref()) {
+                Some(value) => {
+                    context.insert(parameter.key.clone(), value.clone());
+                }
+                None => missing.push(parameter.key.as_str()),
+            }
+        }
+        if !missing.is_empty() {
+            return Err(RecipeError::MissingParameters(
+                path.to_path_buf(),
+                missing.join(", "),
+            ));
+        }
+        Ok(context)
+    }
+
+    fn merge(&mut self, included: Recipe) {
+        let join = |own: Option<String>, other: Option<String>| match (own, other) {
+            (Some(own), Some(other)) => Some(format!("{}\n\n{}", own, other)),
+            (own, other) => own.or(other),
+        };
+        self.instructions = join(self.instructions.take(), included.instructions);
+        self.prompt = join(self.prompt.take(), included.prompt);
+        for extension in included.extensions {
+            if !self.extensions.iter().any(|e| e.name() == extension.name()) {
+                self.extensions.push(extension);
+            }
+        }
+    }
+}
+
+/// The cause of a tera error, its own message only says rendering failed
+fn template_error(error: &tera::Error) -> String {
+    let mut message = error.to_string();
+    let mut source = std::error::Error::source(error);
+    while let Some(cause) = source {
+        message = format!("{}: {}", message, cause);
+        source = cause.source();
+    }
+    message
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    fn write(dir: &TempDir, name: &str, text: &str) -> PathBuf {
+        let path = dir.path().join(name);
+        std::fs::write(&path, text).unwrap();
+        path
+    }
+
+    #[test]
+    fn test_load_fills_in_parameters() {
+        let dir = TempDir::new().unwrap();
+        let path = write(
+            &dir,
+            "triage.yaml",
+            "title: Triage\n\
+             instructions: You triage the issues of {{ repo }}.\n\
+             prompt: Label the issues opened since {{ since }}.\n\
+             parameters:\n  - key: repo\n  - key: since\n    default: yesterday\n\
+             settings:\n  temperature: 0.2\n",
+        );
+
+        let values = HashMap::from([("repo".to_string(), "block/goose".to_string())]);
+        let recipe = Recipe::load(&path, &values).unwrap();
+        assert_eq!(
+            recipe.instructions.as_deref(),
+            Some("You triage the issues of block/goose.")
+        );
+        assert_eq!(
+            recipe.prompt.as_deref(),
+            Some("Label the issues opened since yesterday.")
+        );
+        assert_eq!(recipe.settings.temperature, Some(0.2));
+
+        assert!(matches!(
+            Recipe::load(&path, &HashMap::new()),
+            Err(RecipeError::MissingParameters(_, missing)) if missing == "repo"
+        ));
+        let typo = HashMap::from([("rpeo".to_string(), "block/goose".to_string())]);
+        assert!(matches!(
+            Recipe::load(&path, &typo),
+            Err(RecipeError::UnknownParameter(_, key)) if key == "rpeo"
+        ));
+    }
+
+    #[test]
+    fn test_sub_recipes_are_merged() {
+        let dir = TempDir::new().unwrap();
+        write(
+            &dir,
+            "style.json",
+            r#"{"title": "Style", "instructions": "Write in a {{ tone }} tone.",
+                "parameters": [{"key": "tone"}],
+                "extensions": [{"type": "builtin", "name": "developer"},
+                               {"type": "builtin", "name": "memory"}]}"#,
+        );
+        let path = write(
+            &dir,
+            "reply.yaml",
+            "title: Reply\n\
+             instructions: You draft replies.\n\
+             parameters:\n  - key: tone\n\
+             extensions:\n  - type: builtin\n    name: developer\n\
+             sub_recipes:\n  - path: style.json\n    values:\n      tone: \"{{ tone }}\"\n",
+        );
+
+        let values = HashMap::from([("tone".to_string(), "friendly".to_string())]);
+        let recipe = Recipe::load(&path, &values).unwrap();
+        assert_eq!(
+            recipe.instructions.as_deref(),
+            Some("You draft replies.\n\nWrite in a friendly tone.")
+        );
+        let names: Vec<_> = recipe.extensions.iter().map(|e| e.name()).collect();
+        assert_eq!(names, ["developer", "memory"]);
+        assert!(recipe.sub_recipes.is_empty());
+    }
+
+    #[test]
+    fn test_recipe_cannot_include_itself() {
+        let dir = TempDir::new().unwrap();
+        let path = write(
+            &dir,
+            "loop.yaml",
+            "title: Loop\nsub_recipes:\n  - path: loop.yaml\n",
+        );
+        assert!(matches!(
+            Recipe::load(&path, &HashMap::new()),
+            Err(RecipeError::Cycle(_))
+        ));
+    }
+}
@@ -1,8 +1,11 @@
 use include_dir::{include_dir, Dir};
 use mcp_core::Tool;
+use std::collections::HashMap;
 use std::error::Error;
 use std::fs;
 use std::path::Path;
+use std::sync::{Arc, LazyLock, Mutex, OnceLock};
+use thiserror::Error;
 use tokenizers::tokenizer::Tokenizer;
 
 use crate::message::Message;
@@ -11,9 +14,53 @@ use crate::message::Message;
 // If one of them doesn’t exist, we’ll download it at startup.
 static TOKENIZER_FILES: Dir = include_dir!("$CARGO_MANIFEST_DIR/../../tokenizer_files");
 
-/// The `TokenCounter` now stores exactly one `Tokenizer`.
+/// The outcome of loading a tokenizer, failures are kept so loading is not retried
+type LoadedTokenizer = Result<Arc<Tokenizer>, TokenizerError>;
+
+/// Tokenizers loaded so far, shared by every `TokenCounter` in the process
+static TOKENIZERS: LazyLock<Mutex<HashMap<String, Arc<OnceLock<LoadedTokenizer>>>>> =
+    LazyLock::new(Default::default);
+
+/// Roughly how many characters make up a token, used when no tokenizer could be loaded
+const CHARS_PER_TOKEN_ESTIMATE: usize = 4;
+
+#[derive(Error, Debug, Clone)]
+#[error("Failed to load tokenizer '{name}': {reason}")]
+pub struct TokenizerError {
+    pub name: String,
+    pub reason: String,
+}
+
+/// Get a tokenizer from the shared cache, calling `load` if it is the first use of `name`
+///
+/// Concurrent first calls for the same name wait for a single load, other names
+/// are not held up while it runs.
+fn shared_tokenizer<F>(name: &str, load: F) -> LoadedTokenizer
+where
+    F: FnOnce() -> Result<Tokenizer, Box<dyn Error>>,
+{
+    let slot = TOKENIZERS
+        .lock()
+        .unwrap()
+        .entry(name.to_string())
+        .or_default()
+        .clone();
+    slot.get_or_init(|| {
+        load().map(Arc::new).map_err(|e| TokenizerError {
+            name: name.to_string(),
+            reason: e.to_string(),
+        })
+    })
+    .clone()
+}
+
+/// Counts tokens with one HuggingFace tokenizer, loaded on first use
+///
+/// Creating a counter is cheap: the tokenizer is loaded by the first count and
+/// then shared with every other counter for the same tokenizer name.
 pub struct TokenCounter {
-    tokenizer: Tokenizer,
+    tokenizer_name: String,
+    tokenizer: OnceLock<LoadedTokenizer>,
 }
 
 impl TokenCounter {
@@ -22,8 +69,21 @@ impl TokenCounter {
     /// * `tokenizer_name` might look like "Xenova--gpt-4o"
     ///   or "Qwen--Qwen2.5-Coder-32B-Instruct", etc.
     pub fn new(tokenizer_name: &str) -> Self {
+        Self {
+            tokenizer_name: tokenizer_name.to_string(),
+            tokenizer: OnceLock::new(),
+        }
+    }
+
+    fn tokenizer(&self) -> &LoadedTokenizer {
+        self.tokenizer.get_or_init(|| {
+            shared_tokenizer(&self.tokenizer_name, || Self::load(&self.tokenizer_name))
+        })
+    }
+
+    fn load(tokenizer_name: &str) -> Result<Tokenizer, Box<dyn Error>> {
         match Self::load_from_embedded(tokenizer_name) {
-            Ok(tokenizer) => Self { tokenizer },
+            Ok(tokenizer) => Ok(tokenizer),
             Err(e) => {
                 println!(
                     "Tokenizer '{}' not found in embedded dir: {}",
@@ -31,10 +91,7 @@ impl TokenCounter {
                 );
                 println!("Attempting to download tokenizer and load...");
                 // Fallback to download tokenizer and load from disk
-                match Self::download_and_load(tokenizer_name) {
-                    Ok(counter) => counter,
-                    Err(e) => panic!("Failed to initialize tokenizer: {}", e),
-                }
+                Self::download_and_load(tokenizer_name)
             }
         }
     }
@@ -58,7 +115,7 @@ impl TokenCounter {
 
     /// Fallback: If not found in embedded, we look in `base_dir` on disk.
     /// If not on disk, we download from Hugging Face, then load from disk.
-    fn download_and_load(tokenizer_name: &str) -> Result<Self, Box<dyn Error>> {
+    fn download_and_load(tokenizer_name: &str) -> Result<Tokenizer, Box<dyn Error>> {
         let local_dir = std::env::temp_dir().join(tokenizer_name);
         let local_json_path = local_dir.join("tokenizer.json");
 
@@ -75,7 +132,7 @@ impl TokenCounter {
         let tokenizer = Tokenizer::from_bytes(&file_content)
             .map_err(|e| format!("Failed to parse tokenizer after download: {}", e))?;
 
-        Ok(Self { tokenizer })
+        Ok(tokenizer)
     }
 
     /// Download from Hugging Face into the local directory if not already present.
@@ -107,18 +164,29 @@ impl TokenCounter {
     }
 
     /// Count tokens for a piece of text using our single tokenizer.
+    ///
+    /// If the tokenizer can not be loaded the count is estimated from the text
+    /// length, use `try_count_tokens` to handle the failure instead.
     pub fn count_tokens(&self, text: &str) -> usize {
-        let encoding = self.tokenizer.encode(text, false).unwrap();
-        encoding.len()
+        self.try_count_tokens(text).unwrap_or_else(|e| {
+            tracing::warn!("{}, estimating the token count", e);
+            text.len().div_ceil(CHARS_PER_TOKEN_ESTIMATE)
+        })
+    }
+
+    /// Count tokens for a piece of text, reporting a tokenizer that failed to load
+    pub fn try_count_tokens(&self, text: &str) -> Result<usize, TokenizerError> {
+        let tokenizer = self.tokenizer().as_ref().map_err(Clone::clone)?;
+        let encoding = tokenizer.encode(text, false).map_err(|e| TokenizerError {
+            name: self.tokenizer_name.clone(),
+            reason: e.to_string(),
+        })?;
+        Ok(encoding.len())
     }
 
     fn count_tokens_for_tools(&self, tools: &[Tool]) -> usize {
         // Token counts for different function components
         let func_init = 7; // Tokens for function initialization
-        let prop_init = 3; // Tokens for properties initialization
-        let prop_key = 3; // Tokens for each property key
-        let enum_init: isize = -3; // Tokens adjustment for enum list start
-        let enum_item = 3; // Tokens for each enum item
         let func_end = 12; // Tokens for function ending
 
         let mut func_token_count = 0;
@@ -129,38 +197,51 @@ impl TokenCounter {
                 let description = &tool.description.trim_end_matches('.');
                 let line = format!("{}:{}", name, description);
                 func_token_count += self.count_tokens(&line); // Add tokens for name and description
+                func_token_count += self.count_tokens_for_properties(&tool.input_schema);
+            }
+            func_token_count += func_end;
+        }
 
-                if let serde_json::Value::Object(properties) = &tool.input_schema["properties"] {
-                    if !properties.is_empty() {
-                        func_token_count += prop_init; // Add tokens for start of properties
-                        for (key, value) in properties {
-                            func_token_count += prop_key; // Add tokens for each property
-                            let p_name = key;
-                            let p_type = value["type"].as_str().unwrap_or("");
-                            let p_desc = value["description"]
-                                .as_str()
-                                .unwrap_or("")
-                                .trim_end_matches('.');
-                            let line = format!("{}:{}:{}", p_name, p_type, p_desc);
-                            func_token_count += self.count_tokens(&line);
-                            if let Some(enum_values) = value["enum"].as_array() {
-                                func_token_count =
-                                    func_token_count.saturating_add_signed(enum_init); // Add tokens if property has enum list
-                                for item in enum_values {
-                                    if let Some(item_str) = item.as_str() {
-                                        func_token_count += enum_item;
-                                        func_token_count += self.count_tokens(item_str);
-                                    }
-                                }
+        func_token_count
+    }
+
+    /// Count the properties of an object schema, including those of nested objects and arrays
+    fn count_tokens_for_properties(&self, schema: &serde_json::Value) -> usize {
+        let prop_init = 3; // Tokens for properties initialization
+        let prop_key = 3; // Tokens for each property key
+        let enum_init: isize = -3; // Tokens adjustment for enum list start
+        let enum_item = 3; // Tokens for each enum item
+
+        let mut token_count = 0;
+        if let serde_json::Value::Object(properties) = &schema["properties"] {
+            if !properties.is_empty() {
+                token_count += prop_init; // Add tokens for start of properties
+                for (key, value) in properties {
+                    token_count += prop_key; // Add tokens for each property
+                    let p_name = key;
+                    let p_type = value["type"].as_str().unwrap_or("");
+                    let p_desc = value["description"]
+                        .as_str()
+                        .unwrap_or("")
+                        .trim_end_matches('.');
+                    let line = format!("{}:{}:{}", p_name, p_type, p_desc);
+                    token_count += self.count_tokens(&line);
+                    if let Some(enum_values) = value["enum"].as_array() {
+                        token_count = token_count.saturating_add_signed(enum_init); // Add tokens if property has enum list
+                        for item in enum_values {
+                            if let Some(item_str) = item.as_str() {
+                                token_count += enum_item;
+                                token_count += self.count_tokens(item_str);
                             }
                         }
                     }
+                    // Nested objects are spelled out inline, as are the items of arrays
+                    token_count += self.count_tokens_for_properties(value);
+                    token_count += self.count_tokens_for_properties(&value["items"]);
                 }
             }
-            func_token_count += func_end;
         }
-
-        func_token_count
+        token_count
     }
 
     pub fn count_chat_tokens(
@@ -278,6 +359,9 @@ mod tests {
                 content: vec![MessageContent::text(
                     "What's the weather like in San Francisco?",
                 )],
+                citations: Vec::new(),
+                cache_control: None,
+                sensitive: Vec::new(),
             },
             Message {
                 role: Role::Assistant,
@@ -285,11 +369,17 @@ mod tests {
                 content: vec![MessageContent::text(
                     "Looks like it's 60 degrees Fahrenheit in San Francisco.",
                 )],
+                citations: Vec::new(),
+                cache_control: None,
+                sensitive: Vec::new(),
             },
             Message {
                 role: Role::User,
                 created: 2,
                 content: vec![MessageContent::text("How about New York?")],
+                citations: Vec::new(),
+                cache_control: None,
+                sensitive: Vec::new(),
             },
         ];
 
@@ -324,12 +414,67 @@ mod tests {
     }
 
     #[test]
-    #[should_panic]
-    fn test_panic_if_provided_tokenizer_doesnt_exist() {
-        // This should panic because the tokenizer doesn't exist
-        // in the embedded directory and the download fails
+    fn test_nested_tool_properties_are_counted() {
+        let counter = TokenCounter::new(GPT_4O_TOKENIZER);
+        let tool = |schema| Tool {
+            name: "create_event".to_string(),
+            description: "Create a calendar event".to_string(),
+            input_schema: schema,
+        };
+        let flat = tool(json!({
+            "properties": {
+                "attendees": {"type": "array", "description": "Who to invite"}
+            }
+        }));
+        let nested = tool(json!({
+            "properties": {
+                "attendees": {
+                    "type": "array",
+                    "description": "Who to invite",
+                    "items": {
+                        "type": "object",
+                        "properties": {
+                            "email": {"type": "string", "description": "Their address"}
+                        }
+                    }
+                }
+            }
+        }));
+
+        let item = counter.count_tokens("email:string:Their address");
+        assert_eq!(
+            counter.count_chat_tokens("", &[], &[nested]),
+            counter.count_chat_tokens("", &[], &[flat]) + 3 + 3 + item
+        );
+    }
+
+    #[test]
+    fn test_error_if_provided_tokenizer_doesnt_exist() {
+        // The tokenizer doesn't exist in the embedded directory and the download fails
+        let counter = TokenCounter::new("nonexistent-tokenizer");
+        assert!(counter.try_count_tokens("Hello").is_err());
+        // The plain count falls back to an estimate instead of panicking
+        assert_eq!(counter.count_tokens("Hello there!"), 3);
+    }
+
+    #[test]
+    fn test_tokenizer_loaded_once() {
+        let loads = Arc::new(std::sync::atomic::AtomicUsize::new(0));
+        let load = || {
+            loads.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+            TokenCounter::load_from_embedded(GPT_4O_TOKENIZER)
+        };
+
+        std::thread::scope(|scope| {
+            for _ in 0..8 {
+                scope.spawn(|| shared_tokenizer("test--load-once", load).unwrap());
+            }
+        });
+        let first = shared_tokenizer("test--load-once", load).unwrap();
+        let second = shared_tokenizer("test--load-once", load).unwrap();
 
-        TokenCounter::new("nonexistent-tokenizer");
+        assert_eq!(loads.load(std::sync::atomic::Ordering::SeqCst), 1);
+        assert!(Arc::ptr_eq(&first, &second));
     }
 
     // Optional test to confirm that fallback download works if not found in embedded:
@@ -0,0 +1,291 @@
+//! Usage metrics of the process, rendered in the Prometheus text format
+//!
+//! Completions are counted by model and outcome, with their latency, tokens
+//! and cost, and tool calls by tool and outcome, with their latency. The
+//! outcome is "ok" or the error class, e.g. "rate_limit_exceeded".
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::sync::{LazyLock, Mutex};
+use std::time::Duration;
+
+use crate::providers::base::Usage;
+
+/// Upper bounds in seconds of the latency histogram buckets
+const BUCKETS: &[f64] = &[
+    0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0, 30.0, 60.0, 120.0, 300.0,
+];
+
+#[derive(Debug, Clone, Default)]
+struct Histogram {
+    /// Observations per bucket, not cumulative, the last one is over every bound
+    counts: [u64; BUCKETS.len() + 1],
+    sum: f64,
+}
+
+impl Histogram {
+    fn observe(&mut self, value: f64) {
+        let bucket = BUCKETS
+            .iter()
+            .position(|&bound| value <= bound)
+            .unwrap_or(BUCKETS.len());
+        self.counts[bucket] += 1;
+        self.sum += value;
+    }
+
+    fn count(&self) -> u64 {
+        self.counts.iter().sum()
+    }
+}
+
+#[derive(Debug, Default)]
+struct Metrics {
+    /// By model and outcome
+    completions: BTreeMap<(String, String), u64>,
+    completion_seconds: BTreeMap<String, Histogram>,
+    /// By model and "input" or "output"
+    tokens: BTreeMap<(String, &'static str), u64>,
+    cost_dollars: BTreeMap<String, f64>,
+    /// By tool and outcome
+    tool_calls: BTreeMap<(String, String), u64>,
+    tool_call_seconds: BTreeMap<String, Histogram>,
+}
+
+static METRICS: LazyLock<Mutex<Metrics>> = LazyLock::new(|| Mutex::new(Metrics::default()));
+
+/// Count a completion of `model`, `error` is the class of the error it failed with
+pub fn record_completion(
+    model: &str,
+    duration: Duration,
+    usage: Option<&Usage>,
+    cost: Option<f64>,
+    error: Option<&str>,
+) {
+    let mut metrics = METRICS.lock().unwrap();
+    let outcome = error.unwrap_or("ok").to_string();
+    *metrics
+        .completions
+        .entry((model.to_string(), outcome))
+        .or_default() += 1;
+    metrics
+        .completion_seconds
+        .entry(model.to_string())
+        .or_default()
+        .observe(duration.as_secs_f64());
+    if let Some(usage) = usage {
+        for (kind, tokens) in [
+            ("input", usage.input_tokens),
+            ("output", usage.output_tokens),
+        ] {
+            *metrics.tokens.entry((model.to_string(), kind)).or_default() +=
+                tokens.unwrap_or(0).max(0) as u64;
+        }
+    }
+    if let Some(cost) = cost {
+        *metrics.cost_dollars.entry(model.to_string()).or_default() += cost;
+    }
+}
+
+/// Count a call of `tool`, `error` is the class of the error it failed with
+pub fn record_tool_call(tool: &str, duration: Duration, error: Option<&str>) {
+    let mut metrics = METRICS.lock().unwrap();
+    let outcome = error.unwrap_or("ok").to_string();
+    *metrics
+        .tool_calls
+        .entry((tool.to_string(), outcome))
+        .or_default() += 1;
+    metrics
+        .tool_call_seconds
+        .entry(tool.to_string())
+        .or_default()
+        .observe(duration.as_secs_f64());
+}
+
+/// Quote a label value, escaping as the text format requires
+fn label(value: &str) -> String {
+    let escaped = value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n");
+    format!("\"{}\"", escaped)
+}
+
+fn header(out: &mut String, name: &str, kind: &str, help: &str) {
+    let _ = writeln!(out, "# HELP {} {}", name, help);
+    let _ = writeln!(out, "# TYPE {} {}", name, kind);
+}
+
+fn histogram(out: &mut String, name: &str, key: &str, values: &BTreeMap<String, Histogram>) {
+    for (value, histogram) in values {
+        let mut cumulative = 0;
+        for (bound, count) in BUCKETS.iter().zip(histogram.counts) {
+            cumulative += count;
+            let _ = writeln!(
+                out,
+                "{}_bucket{{{}={},le=\"{}\"}} {}",
+                name,
+                key,
+                label(value),
+                bound,
+                cumulative
+            );
+        }
+        let _ = writeln!(
+            out,
+            "{}_bucket{{{}={},le=\"+Inf\"}} {}",
+            name,
+            key,
+            label(value),
+            histogram.count()
+        );
+        let _ = writeln!(
+            out,
+            "{}_sum{{{}={}}} {}",
+            name,
+            key,
+            label(value),
+            histogram.sum
+        );
+        let _ = writeln!(
+            out,
+            "{}_count{{{}={}}} {}",
+            name,
+            key,
+            label(value),
+            histogram.count()
+        );
+    }
+}
+
+/// The metrics in the Prometheus text exposition format
+pub fn render() -> String {
+    let metrics = METRICS.lock().unwrap();
+    let mut out = String::new();
+
+    header(
+        &mut out,
+        "goose_completions_total",
+        "counter",
+        "Completions requested from providers, by model and outcome",
+    );
+    for ((model, outcome), count) in &metrics.completions {
+        let _ = writeln!(
+            out,
+            "goose_completions_total{{model={},outcome={}}} {}",
+            label(model),
+            label(outcome),
+            count
+        );
+    }
+    header(
+        &mut out,
+        "goose_completion_duration_seconds",
+        "histogram",
+        "How long completions took, by model",
+    );
+    histogram(
+        &mut out,
+        "goose_completion_duration_seconds",
+        "model",
+        &metrics.completion_seconds,
+    );
+    header(
+        &mut out,
+        "goose_tokens_total",
+        "counter",
+        "Tokens used by completions, by model and kind",
+    );
+    for ((model, kind), count) in &metrics.tokens {
+        let _ = writeln!(
+            out,
+            "goose_tokens_total{{model={},kind={}}} {}",
+            label(model),
+            label(kind),
+            count
+        );
+    }
+    header(
+        &mut out,
+        "goose_cost_dollars_total",
+        "counter",
+        "Cost of completions in US dollars at list prices, by model",
+    );
+    for (model, cost) in &metrics.cost_dollars {
+        let _ = writeln!(
+            out,
+            "goose_cost_dollars_total{{model={}}} {}",
+            label(model),
+            cost
+        );
+    }
+    header(
+        &mut out,
+        "goose_tool_calls_total",
+        "counter",
+        "Tool calls, by tool and outcome",
+    );
+    for ((tool, outcome), count) in &metrics.tool_calls {
+        let _ = writeln!(
+            out,
+            "goose_tool_calls_total{{tool={},outcome={}}} {}",
+            label(tool),
+            label(outcome),
+            count
+        );
+    }
+    header(
+        &mut out,
+        "goose_tool_call_duration_seconds",
+        "histogram",
+        "How long tool calls took, by tool",
+    );
+    histogram(
+        &mut out,
+        "goose_tool_call_duration_seconds",
+        "tool",
+        &metrics.tool_call_seconds,
+    );
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render() {
+        // The metrics are global, so the names are unique to this test
+        let usage = Usage::new(Some(100), Some(20), Some(120));
+        record_completion(
+            "metrics-test-model",
+            Duration::from_millis(300),
+            Some(&usage),
+            Some(0.25),
+            None,
+        );
+        record_completion(
+            "metrics-test-model",
+            Duration::from_secs(3),
+            None,
+            None,
+            Some("rate_limit_exceeded"),
+        );
+        record_tool_call("metrics_test__say \"hi\"", Duration::from_millis(50), None);
+
+        let text = render();
+        for line in [
+            "goose_completions_total{model=\"metrics-test-model\",outcome=\"ok\"} 1",
+            "goose_completions_total{model=\"metrics-test-model\",outcome=\"rate_limit_exceeded\"} 1",
+            "goose_completion_duration_seconds_bucket{model=\"metrics-test-model\",le=\"0.25\"} 0",
+            "goose_completion_duration_seconds_bucket{model=\"metrics-test-model\",le=\"0.5\"} 1",
+            "goose_completion_duration_seconds_bucket{model=\"metrics-test-model\",le=\"+Inf\"} 2",
+            "goose_completion_duration_seconds_sum{model=\"metrics-test-model\"} 3.3",
+            "goose_tokens_total{model=\"metrics-test-model\",kind=\"input\"} 100",
+            "goose_tokens_total{model=\"metrics-test-model\",kind=\"output\"} 20",
+            "goose_cost_dollars_total{model=\"metrics-test-model\"} 0.25",
+            "goose_tool_calls_total{tool=\"metrics_test__say \\\"hi\\\"\",outcome=\"ok\"} 1",
+        ] {
+            assert!(text.lines().any(|l| l == line), "missing {}", line);
+        }
+        assert!(text.contains("# TYPE goose_tool_call_duration_seconds histogram"));
+    }
+}
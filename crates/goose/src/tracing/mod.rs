@@ -1,5 +1,9 @@
 pub mod langfuse_layer;
+#[cfg(feature = "otel")]
+pub mod metrics;
 mod observation_layer;
+#[cfg(feature = "otel")]
+pub mod otlp;
 
 pub use langfuse_layer::{create_langfuse_observer, LangfuseBatchManager};
 pub use observation_layer::{
@@ -0,0 +1,74 @@
+//! Export of goose's spans to an OpenTelemetry collector over OTLP/HTTP
+//!
+//! The exporter is configured with the standard environment variables:
+//! `OTEL_EXPORTER_OTLP_ENDPOINT` or `OTEL_EXPORTER_OTLP_TRACES_ENDPOINT` turn
+//! it on, `OTEL_EXPORTER_OTLP_HEADERS` adds headers such as an API key and
+//! `OTEL_SERVICE_NAME` overrides the service name, "goose" by default.
+use opentelemetry::trace::TracerProvider as _;
+use opentelemetry_otlp::{
+    SpanExporter, OTEL_EXPORTER_OTLP_ENDPOINT, OTEL_EXPORTER_OTLP_TRACES_ENDPOINT,
+};
+use opentelemetry_sdk::trace::{SdkTracerProvider, Tracer};
+use opentelemetry_sdk::Resource;
+use std::env;
+use std::sync::OnceLock;
+use tracing::Subscriber;
+use tracing_opentelemetry::OpenTelemetryLayer;
+use tracing_subscriber::registry::LookupSpan;
+
+const DEFAULT_SERVICE_NAME: &str = "goose";
+
+static TRACER_PROVIDER: OnceLock<SdkTracerProvider> = OnceLock::new();
+
+/// Whether an OTLP endpoint is configured
+pub fn is_configured() -> bool {
+    [
+        OTEL_EXPORTER_OTLP_ENDPOINT,
+        OTEL_EXPORTER_OTLP_TRACES_ENDPOINT,
+    ]
+    .iter()
+    .any(|var| env::var(var).is_ok_and(|value| !value.is_empty()))
+}
+
+/// A layer that exports spans to the configured OTLP endpoint, None when there is none
+///
+/// Spans are sent in batches from a background thread. Call `shutdown` before
+/// the process exits to send the ones still waiting.
+pub fn create_otlp_layer<S>() -> Option<OpenTelemetryLayer<S, Tracer>>
+where
+    S: Subscriber + for<'span> LookupSpan<'span>,
+{
+    if !is_configured() {
+        return None;
+    }
+    let exporter = match SpanExporter::builder().with_http().build() {
+        Ok(exporter) => exporter,
+        Err(e) => {
+            eprintln!(
+                "Could not create the OTLP exporter, spans are not exported: {}",
+                e
+            );
+            return None;
+        }
+    };
+    let mut resource = Resource::builder();
+    if env::var("OTEL_SERVICE_NAME").is_err() {
+        resource = resource.with_service_name(DEFAULT_SERVICE_NAME);
+    }
+    let provider = SdkTracerProvider::builder()
+        .with_batch_exporter(exporter)
+        .with_resource(resource.build())
+        .build();
+    let tracer = provider.tracer("goose");
+    let _ = TRACER_PROVIDER.set(provider);
+    Some(tracing_opentelemetry::layer().with_tracer(tracer))
+}
+
+/// Send the spans that are still waiting and stop exporting
+pub fn shutdown() {
+    if let Some(provider) = TRACER_PROVIDER.get() {
+        if let Err(e) = provider.shutdown() {
+            eprintln!("Could not send the remaining spans: {}", e);
+        }
+    }
+}
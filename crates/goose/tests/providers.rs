@@ -3,7 +3,8 @@ use dotenv::dotenv;
 use goose::message::{Message, MessageContent};
 use goose::providers::base::Provider;
 use goose::providers::errors::ProviderError;
-use goose::providers::{anthropic, databricks, google, groq, ollama, openai, openrouter, omg};
+use goose::providers::streaming::collect_message;
+use goose::providers::{anthropic, databricks, google, groq, ollama, omg, openai, openrouter};
 use mcp_core::content::Content;
 use mcp_core::tool::Tool;
 use std::collections::HashMap;
@@ -111,6 +112,28 @@ impl ProviderTester {
         Ok(())
     }
 
+    async fn test_streaming_response(&self) -> Result<()> {
+        let message = Message::user().with_text("Count from one to five.");
+
+        let stream = self
+            .provider
+            .stream("You are a helpful assistant.", &[message], &[])
+            .await?;
+        let (response, usage) = collect_message(stream).await?;
+
+        // The deltas add up to a text response, followed by the usage of the whole completion
+        assert!(
+            !response.as_concat_text().is_empty(),
+            "Expected text in the streamed response"
+        );
+        assert!(
+            usage.usage.output_tokens.is_some(),
+            "Expected the stream to report usage"
+        );
+
+        Ok(())
+    }
+
     async fn test_tool_usage(&self) -> Result<()> {
         let weather_tool = Tool::new(
             "get_weather",
@@ -245,6 +268,7 @@ impl ProviderTester {
     /// Run all provider tests
     async fn run_test_suite(&self) -> Result<()> {
         self.test_basic_response().await?;
+        self.test_streaming_response().await?;
         self.test_tool_usage().await?;
         self.test_context_length_exceeded_error().await?;
         Ok(())
@@ -415,13 +439,7 @@ async fn test_openrouter_provider() -> Result<()> {
 
 #[tokio::test]
 async fn test_omg_provider() -> Result<()> {
-    test_provider(
-        "Omg",
-        &["OMG_API_KEY"],
-        None,
-        omg::OmgProvider::default,
-    )
-    .await
+    test_provider("Omg", &["OMG_API_KEY"], None, omg::OmgProvider::default).await
 }
 
 #[tokio::test]
@@ -0,0 +1,369 @@
+//! OAuth 2.0 device authorization (RFC 8628) for MCP servers reached over HTTP
+//!
+//! The user is shown a code to enter on a page of the server's identity
+//! provider, meanwhile the token endpoint is polled until they have done so.
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::sync::Mutex;
+
+use crate::transport::{Error, HttpAuth};
+
+const DEVICE_CODE_GRANT: &str = "urn:ietf:params:oauth:grant-type:device_code";
+
+/// Poll interval when the server does not give one, as the RFC says
+const DEFAULT_INTERVAL_SECS: u64 = 5;
+
+/// A token that expires within this is refreshed before it is used
+const EXPIRY_MARGIN_SECS: u64 = 30;
+
+/// Where to run the device flow for a server
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct OAuthConfig {
+    pub client_id: String,
+    pub device_authorization_url: String,
+    pub token_url: String,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub scopes: Vec<String>,
+}
+
+/// The token the device flow got, to be stored between sessions
+#[derive(Clone, Serialize, Deserialize)]
+pub struct OAuthToken {
+    pub access_token: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub refresh_token: Option<String>,
+    /// When the access token expires, in seconds since the epoch
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub expires_at: Option<u64>,
+}
+
+impl OAuthToken {
+    fn is_expired(&self) -> bool {
+        self.expires_at
+            .is_some_and(|expires_at| expires_at <= now() + EXPIRY_MARGIN_SECS)
+    }
+}
+
+/// What the user needs to approve the device
+#[derive(Clone, Deserialize)]
+pub struct DeviceAuthorization {
+    device_code: String,
+    pub user_code: String,
+    pub verification_uri: String,
+    /// The page with the code filled in, when the server offers it
+    #[serde(default)]
+    pub verification_uri_complete: Option<String>,
+    pub expires_in: u64,
+    #[serde(default = "default_interval")]
+    interval: u64,
+}
+
+fn default_interval() -> u64 {
+    DEFAULT_INTERVAL_SECS
+}
+
+#[derive(Deserialize)]
+struct TokenResponse {
+    access_token: String,
+    #[serde(default)]
+    refresh_token: Option<String>,
+    #[serde(default)]
+    expires_in: Option<u64>,
+}
+
+#[derive(Deserialize)]
+struct TokenError {
+    error: String,
+    #[serde(default)]
+    error_description: Option<String>,
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |time| time.as_secs())
+}
+
+fn auth_error(message: impl std::fmt::Display) -> Error {
+    Error::Authorization(message.to_string())
+}
+
+type Prompt = Arc<dyn Fn(&DeviceAuthorization) + Send + Sync>;
+type TokenCallback = Arc<dyn Fn(&OAuthToken) + Send + Sync>;
+
+/// Authorizes requests with a token from the device flow
+///
+/// The flow runs on the first request without a token and again when the
+/// token can neither be used nor refreshed. `prompt` shows the user where to
+/// enter the code.
+pub struct DeviceFlowAuth {
+    config: OAuthConfig,
+    client: reqwest::Client,
+    token: Mutex<Option<OAuthToken>>,
+    prompt: Prompt,
+    on_token: Option<TokenCallback>,
+}
+
+impl DeviceFlowAuth {
+    pub fn new<F>(config: OAuthConfig, prompt: F) -> Self
+    where
+        F: Fn(&DeviceAuthorization) + Send + Sync + 'static,
+    {
+        Self {
+            config,
+            client: reqwest::Client::new(),
+            token: Mutex::new(None),
+            prompt: Arc::new(prompt),
+            on_token: None,
+        }
+    }
+
+    /// Start from a token stored earlier
+    pub fn with_token(mut self, token: Option<OAuthToken>) -> Self {
+        self.token = Mutex::new(token);
+        self
+    }
+
+    /// Call `callback` with every new token, to store it
+    pub fn on_token<F>(mut self, callback: F) -> Self
+    where
+        F: Fn(&OAuthToken) + Send + Sync + 'static,
+    {
+        self.on_token = Some(Arc::new(callback));
+        self
+    }
+
+    async fn request_token(
+        &self,
+        form: &[(&str, &str)],
+    ) -> Result<Result<OAuthToken, TokenError>, Error> {
+        let response = self
+            .client
+            .post(&self.config.token_url)
+            .header("Accept", "application/json")
+            .form(form)
+            .send()
+            .await
+            .map_err(auth_error)?;
+        if !response.status().is_success() {
+            let error = response.json::<TokenError>().await.map_err(auth_error)?;
+            return Ok(Err(error));
+        }
+        let token = response.json::<TokenResponse>().await.map_err(auth_error)?;
+        Ok(Ok(OAuthToken {
+            access_token: token.access_token,
+            refresh_token: token.refresh_token,
+            expires_at: token.expires_in.map(|expires_in| now() + expires_in),
+        }))
+    }
+
+    /// Run the device flow until the user approved or denied it, or the code expired
+    async fn authorize(&self) -> Result<OAuthToken, Error> {
+        let scope = self.config.scopes.join(" ");
+        let mut form = vec![("client_id", self.config.client_id.as_str())];
+        if !scope.is_empty() {
+            form.push(("scope", &scope));
+        }
+        let response = self
+            .client
+            .post(&self.config.device_authorization_url)
+            .header("Accept", "application/json")
+            .form(&form)
+            .send()
+            .await
+            .map_err(auth_error)?;
+        if !response.status().is_success() {
+            return Err(auth_error(format!(
+                "the device authorization endpoint returned {}",
+                response.status()
+            )));
+        }
+        let authorization: DeviceAuthorization = response.json().await.map_err(auth_error)?;
+        (self.prompt)(&authorization);
+
+        let deadline = now() + authorization.expires_in;
+        let mut interval = authorization.interval;
+        loop {
+            tokio::time::sleep(Duration::from_secs(interval)).await;
+            if now() > deadline {
+                return Err(auth_error("the code expired before it was entered"));
+            }
+            let result = self
+                .request_token(&[
+                    ("grant_type", DEVICE_CODE_GRANT),
+                    ("device_code", &authorization.device_code),
+                    ("client_id", &self.config.client_id),
+                ])
+                .await?;
+            match result {
+                Ok(token) => return Ok(token),
+                Err(error) if error.error == "authorization_pending" => {}
+                Err(error) if error.error == "slow_down" => interval += DEFAULT_INTERVAL_SECS,
+                Err(error) => {
+                    return Err(auth_error(error.error_description.unwrap_or(error.error)))
+                }
+            }
+        }
+    }
+
+    /// Trade the refresh token for a new token, None when that is not possible
+    async fn refresh(&self, token: &OAuthToken) -> Result<Option<OAuthToken>, Error> {
+        let Some(refresh_token) = &token.refresh_token else {
+            return Ok(None);
+        };
+        let result = self
+            .request_token(&[
+                ("grant_type", "refresh_token"),
+                ("refresh_token", refresh_token),
+                ("client_id", &self.config.client_id),
+            ])
+            .await?;
+        Ok(result.ok().map(|mut new_token| {
+            // Servers may leave the refresh token out when it stays the same
+            new_token.refresh_token = new_token.refresh_token.or(Some(refresh_token.clone()));
+            new_token
+        }))
+    }
+
+    /// A refreshed token, or else one from a new device flow
+    async fn renew(&self, token: Option<&OAuthToken>) -> Result<OAuthToken, Error> {
+        let refreshed = match token {
+            Some(token) => self.refresh(token).await?,
+            None => None,
+        };
+        let token = match refreshed {
+            Some(token) => token,
+            None => self.authorize().await?,
+        };
+        if let Some(on_token) = &self.on_token {
+            on_token(&token);
+        }
+        Ok(token)
+    }
+}
+
+#[async_trait]
+impl HttpAuth for DeviceFlowAuth {
+    async fn header(&self) -> Result<Option<String>, Error> {
+        let mut token = self.token.lock().await;
+        let usable = token.as_ref().filter(|token| !token.is_expired());
+        if usable.is_none() {
+            *token = Some(self.renew(token.as_ref()).await?);
+        }
+        Ok(token
+            .as_ref()
+            .map(|token| format!("Bearer {}", token.access_token)))
+    }
+
+    async fn reauthorize(&self) -> Result<bool, Error> {
+        let mut token = self.token.lock().await;
+        *token = Some(self.renew(token.as_ref()).await?);
+        Ok(true)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use wiremock::matchers::{body_string_contains, method, path};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    fn config(server: &MockServer) -> OAuthConfig {
+        OAuthConfig {
+            client_id: "goose".to_string(),
+            device_authorization_url: format!("{}/device", server.uri()),
+            token_url: format!("{}/token", server.uri()),
+            scopes: vec!["mcp".to_string()],
+        }
+    }
+
+    #[tokio::test]
+    async fn test_device_flow() {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/device"))
+            .and(body_string_contains("scope=mcp"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+                "device_code": "device-1",
+                "user_code": "ABCD-EFGH",
+                "verification_uri": "https://example.com/activate",
+                "expires_in": 600,
+                "interval": 0
+            })))
+            .mount(&server)
+            .await;
+        Mock::given(method("POST"))
+            .and(path("/token"))
+            .respond_with(
+                ResponseTemplate::new(400).set_body_json(json!({"error": "authorization_pending"})),
+            )
+            .up_to_n_times(1)
+            .with_priority(1)
+            .mount(&server)
+            .await;
+        Mock::given(method("POST"))
+            .and(path("/token"))
+            .and(body_string_contains("device_code=device-1"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+                "access_token": "access-1",
+                "refresh_token": "refresh-1",
+                "expires_in": 3600
+            })))
+            .mount(&server)
+            .await;
+
+        let prompts = Arc::new(AtomicUsize::new(0));
+        let saved = Arc::new(std::sync::Mutex::new(None));
+        let auth = DeviceFlowAuth::new(config(&server), {
+            let prompts = prompts.clone();
+            move |authorization| {
+                assert_eq!(authorization.user_code, "ABCD-EFGH");
+                prompts.fetch_add(1, Ordering::SeqCst);
+            }
+        })
+        .on_token({
+            let saved = saved.clone();
+            move |token| *saved.lock().unwrap() = Some(token.access_token.clone())
+        });
+
+        assert_eq!(
+            auth.header().await.unwrap().as_deref(),
+            Some("Bearer access-1")
+        );
+        // The token is kept, the user is asked once
+        auth.header().await.unwrap();
+        assert_eq!(prompts.load(Ordering::SeqCst), 1);
+        assert_eq!(saved.lock().unwrap().as_deref(), Some("access-1"));
+    }
+
+    #[tokio::test]
+    async fn test_expired_token_is_refreshed() {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/token"))
+            .and(body_string_contains("grant_type=refresh_token"))
+            .respond_with(
+                ResponseTemplate::new(200)
+                    .set_body_json(json!({"access_token": "access-2", "expires_in": 3600})),
+            )
+            .mount(&server)
+            .await;
+
+        let auth = DeviceFlowAuth::new(config(&server), |_| panic!("no device flow expected"))
+            .with_token(Some(OAuthToken {
+                access_token: "access-1".to_string(),
+                refresh_token: Some("refresh-1".to_string()),
+                expires_at: Some(now()),
+            }));
+        assert_eq!(
+            auth.header().await.unwrap().as_deref(),
+            Some("Bearer access-2")
+        );
+        let token = auth.token.lock().await.clone().unwrap();
+        assert_eq!(token.refresh_token.as_deref(), Some("refresh-1"));
+    }
+}
@@ -0,0 +1,734 @@
+use async_trait::async_trait;
+use futures::StreamExt;
+use mcp_core::protocol::{
+    ErrorData, JsonRpcError, JsonRpcMessage, JsonRpcNotification, JsonRpcRequest, JsonRpcResponse,
+    METHOD_NOT_FOUND,
+};
+use reqwest::{Client as HttpClient, Method, RequestBuilder, Response, StatusCode};
+use std::collections::HashMap;
+use std::sync::Arc;
+use tokio::sync::RwLock;
+use tokio::time::Duration;
+use tracing::{debug, info, warn};
+
+use super::{Error, Transport, TransportHandle};
+
+const SESSION_HEADER: &str = "Mcp-Session-Id";
+
+/// How often a request is retried when the server can't be reached, and
+/// how often a broken event stream is resumed
+const MAX_RETRIES: u32 = 3;
+
+/// The wait before the first retry, doubled for each one after it
+const RETRY_DELAY: Duration = Duration::from_millis(250);
+
+/// Authorizes the requests to an MCP server
+#[async_trait]
+pub trait HttpAuth: Send + Sync {
+    /// The value of the `Authorization` header, None to send none
+    async fn header(&self) -> Result<Option<String>, Error>;
+
+    /// The server rejected the header, get a new one
+    ///
+    /// Returns false when there is no other header to try.
+    async fn reauthorize(&self) -> Result<bool, Error>;
+}
+
+/// Authorizes requests with a fixed bearer token
+pub struct BearerToken(String);
+
+impl BearerToken {
+    pub fn new<S: Into<String>>(token: S) -> Self {
+        Self(token.into())
+    }
+}
+
+#[async_trait]
+impl HttpAuth for BearerToken {
+    async fn header(&self) -> Result<Option<String>, Error> {
+        Ok(Some(format!("Bearer {}", self.0)))
+    }
+
+    async fn reauthorize(&self) -> Result<bool, Error> {
+        Ok(false)
+    }
+}
+
+/// One event of a `text/event-stream` body
+#[derive(Debug, Default, PartialEq)]
+struct SseEvent {
+    id: Option<String>,
+    event: Option<String>,
+    data: String,
+}
+
+/// Splits a `text/event-stream` body into events as its chunks arrive
+#[derive(Default)]
+struct SseParser {
+    buffer: Vec<u8>,
+    event: SseEvent,
+    has_data: bool,
+}
+
+impl SseParser {
+    fn push(&mut self, chunk: &[u8]) -> Vec<SseEvent> {
+        self.buffer.extend_from_slice(chunk);
+        let mut events = Vec::new();
+        while let Some(end) = self.buffer.iter().position(|&b| b == b'\n') {
+            let line: Vec<u8> = self.buffer.drain(..=end).collect();
+            let line = String::from_utf8_lossy(&line);
+            let line = line.trim_end_matches(['\n', '\r']);
+            if line.is_empty() {
+                if self.has_data || self.event.id.is_some() {
+                    events.push(std::mem::take(&mut self.event));
+                }
+                self.has_data = false;
+                continue;
+            }
+            if line.starts_with(':') {
+                continue;
+            }
+            let (field, value) = line.split_once(':').unwrap_or((line, ""));
+            let value = value.strip_prefix(' ').unwrap_or(value);
+            match field {
+                "id" => self.event.id = Some(value.to_string()),
+                "event" => self.event.event = Some(value.to_string()),
+                "data" => {
+                    if self.has_data {
+                        self.event.data.push('\n');
+                    }
+                    self.event.data.push_str(value);
+                    self.has_data = true;
+                }
+                _ => {}
+            }
+        }
+        events
+    }
+}
+
+/// The id a response answers, for responses and errors
+fn response_id(message: &JsonRpcMessage) -> Option<u64> {
+    match message {
+        JsonRpcMessage::Response(response) => response.id,
+        JsonRpcMessage::Error(error) => error.id,
+        _ => None,
+    }
+}
+
+fn is_initialize(message: &JsonRpcMessage) -> bool {
+    matches!(message, JsonRpcMessage::Request(request) if request.method == "initialize")
+}
+
+/// Log a notification the server sent on an event stream
+///
+/// Log messages are passed on to the log, the rest only matter to the
+/// server's own progress.
+fn log_notification(notification: &JsonRpcNotification) {
+    match (notification.method.as_str(), &notification.params) {
+        ("notifications/message", Some(params)) => info!(
+            logger = params.get("logger").and_then(|logger| logger.as_str()),
+            level = params.get("level").and_then(|level| level.as_str()),
+            "MCP server: {}",
+            params.get("data").unwrap_or(&serde_json::Value::Null)
+        ),
+        _ => debug!(?notification, "Notification from the server"),
+    }
+}
+
+fn connection_error(e: reqwest::Error) -> Error {
+    Error::StreamableHttp(e.to_string())
+}
+
+/// What came of posting a message
+enum Exchange {
+    Reply(JsonRpcMessage),
+    /// The server no longer knows the session
+    SessionExpired,
+}
+
+#[derive(Default)]
+struct Session {
+    id: Option<String>,
+    /// The initialize request and notification, replayed to start a new
+    /// session when the server forgot the old one
+    handshake: Vec<JsonRpcMessage>,
+}
+
+struct HttpConnection {
+    client: HttpClient,
+    url: String,
+    headers: HashMap<String, String>,
+    auth: Option<Arc<dyn HttpAuth>>,
+    session: RwLock<Session>,
+}
+
+impl HttpConnection {
+    async fn request(&self, method: Method) -> Result<RequestBuilder, Error> {
+        let mut request = self.client.request(method, &self.url);
+        for (name, value) in &self.headers {
+            request = request.header(name, value);
+        }
+        if let Some(auth) = &self.auth {
+            if let Some(header) = auth.header().await? {
+                request = request.header("Authorization", header);
+            }
+        }
+        if let Some(id) = &self.session.read().await.id {
+            request = request.header(SESSION_HEADER, id);
+        }
+        Ok(request)
+    }
+
+    /// Send a request, retrying when the server can't be reached and once
+    /// with new credentials when it rejects the ones sent
+    async fn send_with_retries(
+        &self,
+        method: Method,
+        build: impl Fn(RequestBuilder) -> RequestBuilder,
+    ) -> Result<Response, Error> {
+        let mut attempt = 0;
+        let mut reauthorized = false;
+        loop {
+            let request = build(self.request(method.clone()).await?);
+            let response = match request.send().await {
+                // The request never reached the server, so it is safe to send again
+                Err(e) if e.is_connect() && attempt < MAX_RETRIES => {
+                    tokio::time::sleep(RETRY_DELAY * 2u32.pow(attempt)).await;
+                    attempt += 1;
+                    continue;
+                }
+                Err(e) => return Err(connection_error(e)),
+                Ok(response) => response,
+            };
+            if response.status() == StatusCode::UNAUTHORIZED && !reauthorized {
+                if let Some(auth) = &self.auth {
+                    reauthorized = true;
+                    if auth.reauthorize().await? {
+                        continue;
+                    }
+                }
+            }
+            return Ok(response);
+        }
+    }
+
+    async fn post(&self, message: &JsonRpcMessage) -> Result<Exchange, Error> {
+        let body = serde_json::to_string(message)?;
+        let response = self
+            .send_with_retries(Method::POST, |request| {
+                request
+                    .header("Content-Type", "application/json")
+                    .header("Accept", "application/json, text/event-stream")
+                    .body(body.clone())
+            })
+            .await?;
+
+        let status = response.status();
+        if status == StatusCode::NOT_FOUND
+            && !is_initialize(message)
+            && self.session.read().await.id.is_some()
+        {
+            return Ok(Exchange::SessionExpired);
+        }
+        if !status.is_success() {
+            return Err(Error::HttpError {
+                status: status.as_u16(),
+                message: response.text().await.unwrap_or_default(),
+            });
+        }
+        if let Some(id) = response
+            .headers()
+            .get(SESSION_HEADER)
+            .and_then(|id| id.to_str().ok())
+        {
+            self.session.write().await.id = Some(id.to_string());
+        }
+
+        let JsonRpcMessage::Request(request) = message else {
+            // The server accepts notifications without a body
+            return Ok(Exchange::Reply(JsonRpcMessage::Nil));
+        };
+        let id = request.id;
+        let is_event_stream = response
+            .headers()
+            .get("Content-Type")
+            .and_then(|value| value.to_str().ok())
+            .is_some_and(|value| value.starts_with("text/event-stream"));
+        let reply = if is_event_stream {
+            self.read_event_stream(response, id).await?
+        } else {
+            let body = response.bytes().await.map_err(connection_error)?;
+            let messages: Vec<JsonRpcMessage> = match serde_json::from_slice(&body) {
+                Ok(JsonRpcMessage::Nil) | Err(_) => serde_json::from_slice(&body)?,
+                Ok(message) => vec![message],
+            };
+            messages
+                .into_iter()
+                .find(|message| response_id(message) == id)
+                .ok_or_else(|| Error::StreamableHttp("No response to the request".to_string()))?
+        };
+        Ok(Exchange::Reply(reply))
+    }
+
+    /// Read events until the response to `id`, resuming the stream from
+    /// the last event seen when it breaks off
+    async fn read_event_stream(
+        &self,
+        mut response: Response,
+        id: Option<u64>,
+    ) -> Result<JsonRpcMessage, Error> {
+        let mut last_event_id: Option<String> = None;
+        let mut resumes = 0;
+        loop {
+            let mut parser = SseParser::default();
+            let mut stream = response.bytes_stream();
+            while let Some(chunk) = stream.next().await {
+                let chunk = match chunk {
+                    Ok(chunk) => chunk,
+                    Err(e) => {
+                        warn!("The event stream broke off: {}", e);
+                        break;
+                    }
+                };
+                for event in parser.push(&chunk) {
+                    if event.id.is_some() {
+                        last_event_id = event.id.clone();
+                    }
+                    if event.data.is_empty()
+                        || event.event.as_deref().unwrap_or("message") != "message"
+                    {
+                        continue;
+                    }
+                    match serde_json::from_str::<JsonRpcMessage>(&event.data) {
+                        Ok(message) if response_id(&message) == id => return Ok(message),
+                        Ok(JsonRpcMessage::Request(request)) => self.answer(request).await,
+                        Ok(JsonRpcMessage::Notification(notification)) => {
+                            log_notification(&notification)
+                        }
+                        Ok(message) => debug!(?message, "Ignoring message from the server"),
+                        Err(e) => warn!("Failed to parse a message from the server: {}", e),
+                    }
+                }
+            }
+
+            let Some(event_id) = last_event_id.clone().filter(|_| resumes < MAX_RETRIES) else {
+                return Err(Error::StreamableHttp(
+                    "The event stream ended before the response".to_string(),
+                ));
+            };
+            tokio::time::sleep(RETRY_DELAY * 2u32.pow(resumes)).await;
+            resumes += 1;
+            response = self
+                .send_with_retries(Method::GET, |request| {
+                    request
+                        .header("Accept", "text/event-stream")
+                        .header("Last-Event-ID", &event_id)
+                })
+                .await?;
+            if !response.status().is_success() {
+                return Err(Error::HttpError {
+                    status: response.status().as_u16(),
+                    message: "Could not resume the event stream".to_string(),
+                });
+            }
+        }
+    }
+
+    /// Answer a request the server sent on an event stream
+    ///
+    /// The client declares no capabilities, so pings are the only requests
+    /// it answers with a result, every other one gets "method not found".
+    async fn answer(&self, request: JsonRpcRequest) {
+        let reply = if request.method == "ping" {
+            JsonRpcMessage::Response(JsonRpcResponse {
+                jsonrpc: "2.0".to_string(),
+                id: request.id,
+                result: Some(serde_json::json!({})),
+                error: None,
+            })
+        } else {
+            JsonRpcMessage::Error(JsonRpcError {
+                jsonrpc: "2.0".to_string(),
+                id: request.id,
+                error: ErrorData {
+                    code: METHOD_NOT_FOUND,
+                    message: format!("The client does not support {}", request.method),
+                    data: None,
+                },
+            })
+        };
+        let body = match serde_json::to_string(&reply) {
+            Ok(body) => body,
+            Err(e) => {
+                warn!("Failed to answer {}: {}", request.method, e);
+                return;
+            }
+        };
+        let response = self
+            .send_with_retries(Method::POST, |builder| {
+                builder
+                    .header("Content-Type", "application/json")
+                    .header("Accept", "application/json, text/event-stream")
+                    .body(body.clone())
+            })
+            .await;
+        match response {
+            Ok(response) if response.status().is_success() => {}
+            Ok(response) => warn!(
+                "The server rejected the answer to {}: {}",
+                request.method,
+                response.status()
+            ),
+            Err(e) => warn!("Failed to answer {}: {}", request.method, e),
+        }
+    }
+
+    /// Start a new session by replaying the handshake of the old one
+    async fn restart_session(&self) -> Result<(), Error> {
+        let handshake = {
+            let mut session = self.session.write().await;
+            session.id = None;
+            session.handshake.clone()
+        };
+        if handshake.is_empty() {
+            return Err(Error::StreamableHttp(
+                "The session ended before it was initialized".to_string(),
+            ));
+        }
+        for message in &handshake {
+            if let Exchange::SessionExpired = self.post(message).await? {
+                return Err(Error::StreamableHttp(
+                    "The server did not start a new session".to_string(),
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    async fn send(&self, message: JsonRpcMessage) -> Result<JsonRpcMessage, Error> {
+        if !matches!(
+            message,
+            JsonRpcMessage::Request(_) | JsonRpcMessage::Notification(_)
+        ) {
+            return Err(Error::UnsupportedMessage);
+        }
+        let reply = match self.post(&message).await? {
+            Exchange::Reply(reply) => reply,
+            Exchange::SessionExpired => {
+                debug!("The session expired, starting a new one");
+                self.restart_session().await?;
+                match self.post(&message).await? {
+                    Exchange::Reply(reply) => reply,
+                    Exchange::SessionExpired => {
+                        return Err(Error::StreamableHttp("The session expired".to_string()))
+                    }
+                }
+            }
+        };
+
+        let mut session = self.session.write().await;
+        if is_initialize(&message) {
+            session.handshake = vec![message];
+        } else if matches!(&message, JsonRpcMessage::Notification(n) if n.method == "notifications/initialized")
+        {
+            session.handshake.push(message);
+        }
+        Ok(reply)
+    }
+}
+
+#[derive(Clone)]
+pub struct StreamableHttpTransportHandle {
+    connection: Arc<HttpConnection>,
+}
+
+#[async_trait]
+impl TransportHandle for StreamableHttpTransportHandle {
+    async fn send(&self, message: JsonRpcMessage) -> Result<JsonRpcMessage, Error> {
+        self.connection.send(message).await
+    }
+}
+
+/// A transport for MCP servers served over HTTP, following the Streamable
+/// HTTP transport of the MCP spec
+///
+/// Every message is posted to the server URL, which replies with JSON or
+/// with an event stream that ends in the response. The session the server
+/// assigns is sent along with every message. A stream that breaks off is
+/// resumed from its last event, and when the server forgets the session a
+/// new one is started with the same handshake.
+pub struct StreamableHttpTransport {
+    connection: Arc<HttpConnection>,
+}
+
+impl StreamableHttpTransport {
+    /// Connect to `url`, sending `headers` with every request
+    pub fn new<S: Into<String>>(url: S, headers: HashMap<String, String>) -> Self {
+        Self::with_auth(url, headers, None)
+    }
+
+    /// Connect to `url`, sending `headers` with every request and authorizing it with `auth`
+    pub fn with_auth<S: Into<String>>(
+        url: S,
+        headers: HashMap<String, String>,
+        auth: Option<Arc<dyn HttpAuth>>,
+    ) -> Self {
+        Self {
+            connection: Arc::new(HttpConnection {
+                client: HttpClient::new(),
+                url: url.into(),
+                headers,
+                auth,
+                session: RwLock::new(Session::default()),
+            }),
+        }
+    }
+}
+
+#[async_trait]
+impl Transport for StreamableHttpTransport {
+    type Handle = StreamableHttpTransportHandle;
+
+    async fn start(&self) -> Result<Self::Handle, Error> {
+        Ok(StreamableHttpTransportHandle {
+            connection: Arc::clone(&self.connection),
+        })
+    }
+
+    /// End the session on the server
+    async fn close(&self) -> Result<(), Error> {
+        if self.connection.session.read().await.id.is_none() {
+            return Ok(());
+        }
+        let response = self
+            .connection
+            .request(Method::DELETE)
+            .await?
+            .send()
+            .await
+            .map_err(connection_error)?;
+        // Servers that don't let clients end sessions answer 405
+        if !response.status().is_success() && response.status() != StatusCode::METHOD_NOT_ALLOWED {
+            warn!("Failed to end the session: {}", response.status());
+        }
+        self.connection.session.write().await.id = None;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+    use wiremock::matchers::{body_string_contains, header, method};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    fn request(id: u64, method: &str) -> JsonRpcMessage {
+        JsonRpcMessage::Request(JsonRpcRequest {
+            jsonrpc: "2.0".to_string(),
+            id: Some(id),
+            method: method.to_string(),
+            params: Some(json!({})),
+        })
+    }
+
+    fn initialized() -> JsonRpcMessage {
+        JsonRpcMessage::Notification(JsonRpcNotification {
+            jsonrpc: "2.0".to_string(),
+            method: "notifications/initialized".to_string(),
+            params: None,
+        })
+    }
+
+    fn result(id: u64, value: serde_json::Value) -> serde_json::Value {
+        json!({"jsonrpc": "2.0", "id": id, "result": value})
+    }
+
+    fn event_stream(body: &str) -> ResponseTemplate {
+        ResponseTemplate::new(200).set_body_raw(body.to_string(), "text/event-stream")
+    }
+
+    #[test]
+    fn test_sse_parser() {
+        let mut parser = SseParser::default();
+        assert!(parser.push(b"id: 1\ndata: {\"a\":").is_empty());
+        let events = parser.push(b"1}\n\n: comment\nevent: ping\r\ndata: x\ndata: y\n\n");
+        assert_eq!(
+            events,
+            vec![
+                SseEvent {
+                    id: Some("1".to_string()),
+                    event: None,
+                    data: "{\"a\":1}".to_string()
+                },
+                SseEvent {
+                    id: None,
+                    event: Some("ping".to_string()),
+                    data: "x\ny".to_string()
+                },
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_session_and_event_stream() {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(body_string_contains("\"initialize\""))
+            .respond_with(
+                ResponseTemplate::new(200)
+                    .insert_header(SESSION_HEADER, "session-1")
+                    .set_body_json(result(1, json!({"protocolVersion": "2025-03-26"}))),
+            )
+            .mount(&server)
+            .await;
+        Mock::given(method("POST"))
+            .and(header(SESSION_HEADER, "session-1"))
+            .and(body_string_contains("notifications/initialized"))
+            .respond_with(ResponseTemplate::new(202))
+            .mount(&server)
+            .await;
+        let stream = format!(
+            "event: message\ndata: {}\n\nevent: message\ndata: {}\n\n",
+            json!({"jsonrpc": "2.0", "method": "notifications/progress", "params": {}}),
+            result(2, json!({"tools": []}))
+        );
+        Mock::given(method("POST"))
+            .and(header(SESSION_HEADER, "session-1"))
+            .and(header("Authorization", "Bearer secret"))
+            .and(body_string_contains("tools/list"))
+            .respond_with(event_stream(&stream))
+            .mount(&server)
+            .await;
+
+        let transport = StreamableHttpTransport::with_auth(
+            server.uri(),
+            HashMap::new(),
+            Some(Arc::new(BearerToken::new("secret"))),
+        );
+        let handle = transport.start().await.unwrap();
+        handle.send(request(1, "initialize")).await.unwrap();
+        assert_eq!(
+            handle.send(initialized()).await.unwrap(),
+            JsonRpcMessage::Nil
+        );
+        let reply = handle.send(request(2, "tools/list")).await.unwrap();
+        assert_eq!(response_id(&reply), Some(2));
+    }
+
+    #[tokio::test]
+    async fn test_server_requests_are_answered() {
+        let server = MockServer::start().await;
+        let stream = format!(
+            "data: {}\n\ndata: {}\n\ndata: {}\n\n",
+            json!({"jsonrpc": "2.0", "id": 7, "method": "ping"}),
+            json!({"jsonrpc": "2.0", "id": 8, "method": "sampling/createMessage", "params": {}}),
+            result(1, json!({"tools": []}))
+        );
+        Mock::given(method("POST"))
+            .and(body_string_contains("tools/list"))
+            .respond_with(event_stream(&stream))
+            .mount(&server)
+            .await;
+        Mock::given(method("POST"))
+            .and(body_string_contains(r#""id":7,"result":{}"#))
+            .respond_with(ResponseTemplate::new(202))
+            .expect(1)
+            .mount(&server)
+            .await;
+        Mock::given(method("POST"))
+            .and(body_string_contains(r#""id":8"#))
+            .and(body_string_contains(METHOD_NOT_FOUND.to_string()))
+            .respond_with(ResponseTemplate::new(202))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let handle = StreamableHttpTransport::new(server.uri(), HashMap::new())
+            .start()
+            .await
+            .unwrap();
+        let reply = handle.send(request(1, "tools/list")).await.unwrap();
+        assert_eq!(response_id(&reply), Some(1));
+        server.verify().await;
+    }
+
+    #[tokio::test]
+    async fn test_expired_session_is_restarted() {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(body_string_contains("\"initialize\""))
+            .respond_with(
+                ResponseTemplate::new(200)
+                    .insert_header(SESSION_HEADER, "session-1")
+                    .set_body_json(result(1, json!({}))),
+            )
+            .up_to_n_times(1)
+            .mount(&server)
+            .await;
+        Mock::given(method("POST"))
+            .and(body_string_contains("\"initialize\""))
+            .respond_with(
+                ResponseTemplate::new(200)
+                    .insert_header(SESSION_HEADER, "session-2")
+                    .set_body_json(result(1, json!({}))),
+            )
+            .mount(&server)
+            .await;
+        Mock::given(method("POST"))
+            .and(body_string_contains("notifications/initialized"))
+            .respond_with(ResponseTemplate::new(202))
+            .mount(&server)
+            .await;
+        Mock::given(method("POST"))
+            .and(header(SESSION_HEADER, "session-1"))
+            .and(body_string_contains("tools/list"))
+            .respond_with(ResponseTemplate::new(404))
+            .mount(&server)
+            .await;
+        Mock::given(method("POST"))
+            .and(header(SESSION_HEADER, "session-2"))
+            .and(body_string_contains("tools/list"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(result(2, json!({}))))
+            .mount(&server)
+            .await;
+
+        let handle = StreamableHttpTransport::new(server.uri(), HashMap::new())
+            .start()
+            .await
+            .unwrap();
+        handle.send(request(1, "initialize")).await.unwrap();
+        handle.send(initialized()).await.unwrap();
+        let reply = handle.send(request(2, "tools/list")).await.unwrap();
+        assert_eq!(response_id(&reply), Some(2));
+    }
+
+    #[tokio::test]
+    async fn test_broken_stream_is_resumed() {
+        let server = MockServer::start().await;
+        let stream = format!(
+            "id: 7\ndata: {}\n\n",
+            json!({"jsonrpc": "2.0", "method": "notifications/progress", "params": {}})
+        );
+        Mock::given(method("POST"))
+            .respond_with(event_stream(&stream))
+            .mount(&server)
+            .await;
+        Mock::given(method("GET"))
+            .and(header("Last-Event-ID", "7"))
+            .respond_with(event_stream(&format!(
+                "id: 8\ndata: {}\n\n",
+                result(3, json!({"content": []}))
+            )))
+            .mount(&server)
+            .await;
+
+        let handle = StreamableHttpTransport::new(server.uri(), HashMap::new())
+            .start()
+            .await
+            .unwrap();
+        let reply = handle.send(request(3, "tools/call")).await.unwrap();
+        assert_eq!(response_id(&reply), Some(3));
+    }
+}
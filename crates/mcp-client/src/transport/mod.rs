@@ -31,6 +31,12 @@ pub enum Error {
 
     #[error("HTTP error: {status} - {message}")]
     HttpError { status: u16, message: String },
+
+    #[error("Streamable HTTP error: {0}")]
+    StreamableHttp(String),
+
+    #[error("Authorization failed: {0}")]
+    Authorization(String),
 }
 
 /// A message that can be sent through the transport
@@ -125,3 +131,6 @@ pub use stdio::StdioTransport;
 
 pub mod sse;
 pub use sse::SseTransport;
+
+pub mod http;
+pub use http::{BearerToken, HttpAuth, StreamableHttpTransport};
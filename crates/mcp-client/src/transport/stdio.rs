@@ -1,11 +1,12 @@
 use std::collections::HashMap;
+use std::path::PathBuf;
 use std::sync::Arc;
 use tokio::process::{Child, ChildStderr, ChildStdin, ChildStdout, Command};
 
 use async_trait::async_trait;
 use mcp_core::protocol::JsonRpcMessage;
 use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
-use tokio::sync::{mpsc, Mutex};
+use tokio::sync::{mpsc, Mutex, OnceCell};
 
 use super::{send_message, Error, PendingRequests, Transport, TransportHandle, TransportMessage};
 
@@ -176,10 +177,36 @@ impl StdioTransportHandle {
     }
 }
 
+/// Whether `unshare` can give a process its own network namespace, checked once
+static NETWORK_SANDBOX: OnceCell<Result<(), String>> = OnceCell::const_new();
+
+/// Check that `unshare` can create the namespaces a server without network runs in
+///
+/// Without unprivileged user namespaces, disabled on some distributions and in
+/// many containers, it fails before starting the server.
+async fn probe_network_sandbox(unshare: &str) -> Result<(), String> {
+    let output = Command::new(unshare)
+        .args(["--user", "--map-root-user", "--net", "--", "true"])
+        .output()
+        .await;
+    let reason = match output {
+        Ok(output) if output.status.success() => return Ok(()),
+        Ok(output) => String::from_utf8_lossy(&output.stderr).trim().to_string(),
+        Err(e) => format!("{} could not be run: {}", unshare, e),
+    };
+    Err(format!(
+        "Network sandboxing is unavailable, it needs unprivileged user namespaces: {}",
+        reason
+    ))
+}
+
 pub struct StdioTransport {
     command: String,
     args: Vec<String>,
     env: HashMap<String, String>,
+    working_dir: Option<PathBuf>,
+    inherited_env: Option<Vec<String>>,
+    network: bool,
 }
 
 impl StdioTransport {
@@ -192,13 +219,81 @@ impl StdioTransport {
             command: command.into(),
             args,
             env,
+            working_dir: None,
+            inherited_env: None,
+            network: true,
+        }
+    }
+
+    /// Run the process in `dir` instead of the current directory
+    pub fn with_working_dir<P: Into<PathBuf>>(mut self, dir: P) -> Self {
+        self.working_dir = Some(dir.into());
+        self
+    }
+
+    /// Pass only the named variables of this process's environment on, the
+    /// variables given to `new` are always set
+    pub fn with_inherited_env(mut self, names: Vec<String>) -> Self {
+        self.inherited_env = Some(names);
+        self
+    }
+
+    /// Run the process without network access, not even to localhost
+    ///
+    /// This runs it under `unshare` in a new network namespace on Linux and
+    /// under `sandbox-exec` on macOS. Starting fails on other platforms.
+    pub fn without_network(mut self) -> Self {
+        self.network = false;
+        self
+    }
+
+    fn command(&self) -> Result<Command, Error> {
+        let mut command = if self.network {
+            Command::new(&self.command)
+        } else if cfg!(target_os = "linux") {
+            let mut command = Command::new("unshare");
+            command.args(["--user", "--map-root-user", "--net", "--", &self.command]);
+            command
+        } else if cfg!(target_os = "macos") {
+            let mut command = Command::new("sandbox-exec");
+            command.args([
+                "-p",
+                "(version 1)(allow default)(deny network*)",
+                &self.command,
+            ]);
+            command
+        } else {
+            return Err(Error::StdioProcessError(
+                "Running without network access is not supported on this platform".into(),
+            ));
+        };
+        command.args(&self.args);
+        if let Some(names) = &self.inherited_env {
+            command.env_clear();
+            command.envs(
+                names
+                    .iter()
+                    .filter_map(|name| std::env::var_os(name).map(|value| (name, value))),
+            );
         }
+        command.envs(&self.env);
+        if let Some(dir) = &self.working_dir {
+            command.current_dir(dir);
+        }
+        Ok(command)
     }
 
     async fn spawn_process(&self) -> Result<(Child, ChildStdin, ChildStdout, ChildStderr), Error> {
-        let mut process = Command::new(&self.command)
-            .envs(&self.env)
-            .args(&self.args)
+        if !self.network && cfg!(target_os = "linux") {
+            // Otherwise unshare exits right away and the server looks like it hung up
+            NETWORK_SANDBOX
+                .get_or_init(|| probe_network_sandbox("unshare"))
+                .await
+                .clone()
+                .map_err(Error::StdioProcessError)?;
+        }
+        let mut process = self
+            .command()?
             .stdin(std::process::Stdio::piped())
             .stdout(std::process::Stdio::piped())
             .stderr(std::process::Stdio::piped())
@@ -259,3 +354,95 @@ impl Transport for StdioTransport {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::ffi::OsStr;
+    use std::path::Path;
+
+    fn envs(command: &Command) -> HashMap<String, String> {
+        command
+            .as_std()
+            .get_envs()
+            .filter_map(|(name, value)| {
+                Some((name.to_str()?.to_string(), value?.to_str()?.to_string()))
+            })
+            .collect()
+    }
+
+    fn args(command: &Command) -> Vec<&OsStr> {
+        command.as_std().get_args().collect()
+    }
+
+    #[test]
+    fn test_command() {
+        let transport = StdioTransport::new(
+            "server",
+            vec!["--port".to_string(), "0".to_string()],
+            HashMap::from([("TOKEN".to_string(), "abc".to_string())]),
+        );
+        let command = transport.command().unwrap();
+        assert_eq!(command.as_std().get_program(), "server");
+        assert_eq!(args(&command), ["--port", "0"]);
+        assert_eq!(envs(&command)["TOKEN"], "abc");
+        assert_eq!(command.as_std().get_current_dir(), None);
+
+        std::env::set_var("STDIO_TEST_ALLOWED", "yes");
+        std::env::set_var("STDIO_TEST_HIDDEN", "no");
+        let command = transport
+            .with_working_dir("/srv/project")
+            .with_inherited_env(vec![
+                "STDIO_TEST_ALLOWED".to_string(),
+                "STDIO_TEST_UNSET".to_string(),
+            ])
+            .command()
+            .unwrap();
+        // Only the allowed variables that are set, and the server's own
+        assert_eq!(
+            envs(&command),
+            HashMap::from([
+                ("STDIO_TEST_ALLOWED".to_string(), "yes".to_string()),
+                ("TOKEN".to_string(), "abc".to_string()),
+            ])
+        );
+        assert_eq!(
+            command.as_std().get_current_dir(),
+            Some(Path::new("/srv/project"))
+        );
+    }
+
+    #[test]
+    fn test_command_without_network() {
+        let transport = StdioTransport::new("server", vec!["--stdio".to_string()], HashMap::new())
+            .without_network();
+        let command = transport.command();
+        if cfg!(target_os = "linux") {
+            let command = command.unwrap();
+            assert_eq!(command.as_std().get_program(), "unshare");
+            assert_eq!(
+                args(&command),
+                [
+                    "--user",
+                    "--map-root-user",
+                    "--net",
+                    "--",
+                    "server",
+                    "--stdio"
+                ]
+            );
+        } else if cfg!(target_os = "macos") {
+            let command = command.unwrap();
+            assert_eq!(command.as_std().get_program(), "sandbox-exec");
+            assert_eq!(args(&command)[2..], ["server", "--stdio"]);
+        } else {
+            assert!(command.is_err());
+        }
+    }
+
+    #[tokio::test]
+    async fn test_unavailable_network_sandbox() {
+        let error = probe_network_sandbox("no-such-unshare").await.unwrap_err();
+        assert!(error.contains("Network sandboxing is unavailable"));
+    }
+}